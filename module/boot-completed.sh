@@ -0,0 +1,9 @@
+MODDIR="${0%/*}"
+BASE_DIR="/data/adb/meta-hybrid"
+LOG_FILE="$BASE_DIR/daemon.log"
+BINARY="$MODDIR/meta-hybrid"
+if [ ! -f "$BASE_DIR/run/deferred.json" ] || [ ! -f "$BINARY" ]; then
+    exit 0
+fi
+echo "[Wrapper] Mounting deferred modules..." >> "$LOG_FILE"
+"$BINARY" mount-deferred >> "$LOG_FILE" 2>&1
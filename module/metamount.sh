@@ -2,9 +2,6 @@ MODDIR="${0%/*}"
 BASE_DIR="/data/adb/meta-hybrid"
 LOG_FILE="$BASE_DIR/daemon.log"
 mkdir -p "$BASE_DIR"
-if [ -f "$LOG_FILE" ]; then
-    rm "$LOG_FILE"
-fi
 log() {
     echo "[Wrapper] $1" >> "$LOG_FILE"
 }
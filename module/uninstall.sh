@@ -4,6 +4,21 @@
 # Cleanup script for metamodule removal
 ############################################
 
-rm -rf "/data/adb/meta-hybrid"
+MODDIR="${0%/*}"
+BASE_DIR="/data/adb/meta-hybrid"
+BINARY="$MODDIR/meta-hybrid"
+
+if [ -f "$BINARY" ]; then
+    chmod 755 "$BINARY"
+    # Prints the JSON report of what was removed.
+    "$BINARY" self-uninstall
+    exit 0
+fi
+
+# Without the binary nothing can be unmounted first; never follow a
+# symlinked base directory.
+if [ ! -L "$BASE_DIR" ]; then
+    rm -rf "$BASE_DIR"
+fi
 
 exit 0
@@ -0,0 +1,352 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Versioned JSON entry point for the WebUI.
+//!
+//! A request is `{"v":1,"op":"modules.list","params":{...}}` and the answer
+//! is always `{"v":1,"ok":bool,"data":...,"error":{"code","message"}}`, even
+//! for malformed requests, so the WebUI never has to read an exit status.
+//! The read-only subcommands print the `data` of the same ops, so both paths
+//! share one implementation.
+
+use std::path::PathBuf;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use super::{cli::Cli, cli_handlers};
+use crate::{
+    core::{granary, state::RuntimeState, storage, summary::BootSummary},
+    defs::Paths,
+};
+
+pub const API_VERSION: u32 = 1;
+
+/// Every operation the API serves, named as in the `op` field. `dispatch`
+/// matches on this exhaustively, so an op cannot be added without wiring it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Op {
+    #[serde(rename = "config.show")]
+    ConfigShow,
+    #[serde(rename = "config.check")]
+    ConfigCheck,
+    #[serde(rename = "storage.status")]
+    StorageStatus,
+    #[serde(rename = "modules.list")]
+    ModulesList,
+    #[serde(rename = "modules.exclude")]
+    ModulesExclude,
+    #[serde(rename = "modules.include")]
+    ModulesInclude,
+    #[serde(rename = "conflicts.list")]
+    ConflictsList,
+    #[serde(rename = "diagnostics.list")]
+    DiagnosticsList,
+    #[serde(rename = "plan.show")]
+    PlanShow,
+    #[serde(rename = "plan.diff")]
+    PlanDiff,
+    #[serde(rename = "status.get")]
+    StatusGet,
+    #[serde(rename = "snapshots.list")]
+    SnapshotsList,
+    #[serde(rename = "winnow.list")]
+    WinnowList,
+    #[serde(rename = "winnow.set")]
+    WinnowSet,
+    #[serde(rename = "winnow.unset")]
+    WinnowUnset,
+    #[serde(rename = "winnow.suggest")]
+    WinnowSuggest,
+    #[serde(rename = "summary.get")]
+    SummaryGet,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request is not a JSON envelope.
+    InvalidRequest,
+    UnsupportedVersion,
+    UnknownOp,
+    /// `params` does not fit the op.
+    InvalidParams,
+    /// The op ran and failed.
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::new(ErrorCode::Failed, format!("{:#}", e))
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::new(
+            ErrorCode::Failed,
+            format!("Failed to serialize result: {}", e),
+        )
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    op: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Response {
+    pub v: u32,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ApiError>,
+}
+
+impl Response {
+    pub fn success(data: Value) -> Self {
+        Self {
+            v: API_VERSION,
+            ok: true,
+            data: Some(data),
+            error: None,
+        }
+    }
+
+    pub fn error(error: ApiError) -> Self {
+        Self {
+            v: API_VERSION,
+            ok: false,
+            data: None,
+            error: Some(error),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ShowConfigParams {
+    /// Return `{profile, config}` with the selected profile merged in.
+    #[serde(default)]
+    resolved: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConflictsParams {
+    /// Only analyze this absolute subtree, e.g. `/system/framework`.
+    #[serde(default)]
+    path: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DiagnosticsParams {
+    /// Add how the pending module updates change the mount plan.
+    #[serde(default)]
+    preview_updates: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlanDiffParams {
+    /// A plan saved from `plan.show`.
+    baseline: PathBuf,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CheckParams {
+    #[serde(default)]
+    config: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WinnowSetParams {
+    path: String,
+    module: String,
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WinnowUnsetParams {
+    path: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WinnowSuggestParams {
+    #[serde(default)]
+    apply: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModuleParams {
+    id: String,
+}
+
+#[derive(Serialize)]
+struct ExclusionChange {
+    id: String,
+    excluded: bool,
+    /// False when the module was already in the requested state.
+    changed: bool,
+}
+
+#[derive(Serialize)]
+struct WinnowRuleChange {
+    path: String,
+    module: String,
+}
+
+/// Decodes `params`; a missing `params` counts as `{}`.
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, ApiError> {
+    let params = if params.is_null() {
+        Value::Object(Default::default())
+    } else {
+        params
+    };
+    serde_json::from_value(params)
+        .map_err(|e| ApiError::new(ErrorCode::InvalidParams, e.to_string()))
+}
+
+/// Runs `op` and returns its result as JSON.
+pub fn dispatch(cli: &Cli, op: Op, raw: Value) -> Result<Value, ApiError> {
+    let data = match op {
+        Op::ConfigShow => {
+            let p: ShowConfigParams = params(raw)?;
+            if p.resolved {
+                serde_json::to_value(cli_handlers::resolved_config(cli)?)?
+            } else {
+                serde_json::to_value(cli_handlers::show_config(cli)?)?
+            }
+        }
+        Op::ConfigCheck => {
+            let p: CheckParams = params(raw)?;
+            serde_json::to_value(cli_handlers::check(cli, p.config.as_deref()))?
+        }
+        Op::StorageStatus => serde_json::to_value(storage::status())?,
+        Op::ModulesList => serde_json::to_value(cli_handlers::list_modules(cli)?)?,
+        Op::ModulesExclude => {
+            let p: ModuleParams = params(raw)?;
+            let changed = cli_handlers::exclude_module(cli, &p.id)?;
+            serde_json::to_value(ExclusionChange {
+                id: p.id,
+                excluded: true,
+                changed,
+            })?
+        }
+        Op::ModulesInclude => {
+            let p: ModuleParams = params(raw)?;
+            let changed = cli_handlers::include_module(cli, &p.id)?;
+            serde_json::to_value(ExclusionChange {
+                id: p.id,
+                excluded: false,
+                changed,
+            })?
+        }
+        Op::ConflictsList => {
+            let p: ConflictsParams = params(raw)?;
+            serde_json::to_value(cli_handlers::list_conflicts(cli, p.path.as_deref())?)?
+        }
+        Op::DiagnosticsList => {
+            let p: DiagnosticsParams = params(raw)?;
+            serde_json::to_value(cli_handlers::diagnose(cli, p.preview_updates)?)?
+        }
+        Op::PlanShow => serde_json::to_value(cli_handlers::export_plan(cli)?)?,
+        Op::PlanDiff => {
+            let p: PlanDiffParams = params(raw)?;
+            serde_json::to_value(cli_handlers::diff_plan(cli, &p.baseline)?)?
+        }
+        Op::StatusGet => serde_json::to_value(RuntimeState::check_health())?,
+        Op::SnapshotsList => {
+            serde_json::to_value(granary::list_snapshots().context("Failed to list snapshots")?)?
+        }
+        Op::WinnowList => serde_json::to_value(cli_handlers::list_winnow_rules(cli)?)?,
+        Op::WinnowSet => {
+            let p: WinnowSetParams = params(raw)?;
+            let path = cli_handlers::winnow_set(cli, &p.path, &p.module, p.force)?;
+            serde_json::to_value(WinnowRuleChange {
+                path,
+                module: p.module,
+            })?
+        }
+        Op::WinnowUnset => {
+            let p: WinnowUnsetParams = params(raw)?;
+            let (path, module) = cli_handlers::winnow_unset(cli, &p.path)?;
+            serde_json::to_value(WinnowRuleChange { path, module })?
+        }
+        Op::WinnowSuggest => {
+            let p: WinnowSuggestParams = params(raw)?;
+            serde_json::to_value(cli_handlers::suggest_winnow_rules(cli, p.apply)?)?
+        }
+        Op::SummaryGet => serde_json::to_value(BootSummary::load(&Paths::default().summary_file)?)?,
+    };
+    Ok(data)
+}
+
+/// Parses an envelope, runs it and wraps the outcome. Never fails: every
+/// problem, including a malformed envelope, becomes an error response.
+pub fn handle(cli: &Cli, request: &[u8]) -> Response {
+    match parse(request).and_then(|(op, raw)| dispatch(cli, op, raw)) {
+        Ok(data) => Response::success(data),
+        Err(error) => Response::error(error),
+    }
+}
+
+fn parse(request: &[u8]) -> Result<(Op, Value), ApiError> {
+    let envelope: Value = serde_json::from_slice(request)
+        .map_err(|e| ApiError::new(ErrorCode::InvalidRequest, e.to_string()))?;
+
+    let version = envelope
+        .get("v")
+        .ok_or_else(|| ApiError::new(ErrorCode::InvalidRequest, "missing field `v`"))?;
+    if version.as_u64() != Some(API_VERSION.into()) {
+        return Err(ApiError::new(
+            ErrorCode::UnsupportedVersion,
+            format!(
+                "unsupported API version {}, expected {}",
+                version, API_VERSION
+            ),
+        ));
+    }
+
+    let request: Request = serde_json::from_value(envelope)
+        .map_err(|e| ApiError::new(ErrorCode::InvalidRequest, e.to_string()))?;
+    let op = serde_json::from_value(Value::String(request.op.clone()))
+        .map_err(|_| ApiError::new(ErrorCode::UnknownOp, format!("unknown op `{}`", request.op)))?;
+
+    Ok((op, request.params))
+}
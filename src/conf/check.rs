@@ -0,0 +1,97 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::path::Path;
+
+use rustix::fs::{Access, access};
+
+use crate::{
+    conf::config::{Config, OverlayMode},
+    core::{
+        inventory::Module,
+        ops::planner::{DiagnosticIssue, DiagnosticLevel},
+    },
+    utils,
+};
+
+fn issue(level: DiagnosticLevel, context: &str, message: String) -> DiagnosticIssue {
+    DiagnosticIssue {
+        level,
+        context: context.to_string(),
+        message,
+    }
+}
+
+/// Static checks of a config that is about to be used on the next boot.
+/// These complement the planner and storage diagnostics with problems that
+/// only show up once the device has rebooted.
+pub fn check_config(config: &Config, modules: &[Module]) -> Vec<DiagnosticIssue> {
+    let mut issues = Vec::new();
+
+    for partition in &config.partitions {
+        let path = Path::new("/").join(partition);
+        let exists = path
+            .symlink_metadata()
+            .is_ok_and(|m| m.is_dir() || m.file_type().is_symlink());
+        if !exists {
+            issues.push(issue(
+                DiagnosticLevel::Critical,
+                "Config",
+                format!(
+                    "partition '{}' is not a directory or symlink at {}",
+                    partition,
+                    path.display()
+                ),
+            ));
+        }
+    }
+
+    if matches!(config.effective_storage_mode(), OverlayMode::Erofs)
+        && !utils::kernel_features().erofs
+    {
+        issues.push(issue(
+            DiagnosticLevel::Critical,
+            "Config",
+            "erofs storage requested but the kernel does not support erofs".to_string(),
+        ));
+    }
+
+    if config.mountsource.trim().is_empty() {
+        issues.push(issue(
+            DiagnosticLevel::Critical,
+            "Config",
+            "mountsource is empty".to_string(),
+        ));
+    }
+
+    for (path, module_id) in &config.winnowing.rules {
+        if modules.iter().any(|m| &m.id == module_id) {
+            continue;
+        }
+        let (level, state) = if config.moduledir.join(module_id).is_dir() {
+            (DiagnosticLevel::Warning, "is not active")
+        } else {
+            (DiagnosticLevel::Critical, "does not exist")
+        };
+        issues.push(issue(
+            level,
+            "Winnowing",
+            format!("rule for {} prefers '{}', which {}", path, module_id, state),
+        ));
+    }
+
+    let mnt_dir = Path::new(&config.hybrid_mnt_dir);
+    let writable = mnt_dir
+        .ancestors()
+        .find(|p| p.exists())
+        .is_some_and(|p| access(p, Access::WRITE_OK).is_ok());
+    if !writable {
+        issues.push(issue(
+            DiagnosticLevel::Critical,
+            "Config",
+            format!("hybrid_mnt_dir {} is not writable", mnt_dir.display()),
+        ));
+    }
+
+    issues
+}
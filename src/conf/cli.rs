@@ -5,10 +5,15 @@ use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
 
-use crate::defs;
+use crate::{core::failure, defs};
 
 #[derive(Parser, Debug)]
-#[command(name = "meta-hybrid", version, about = "Hybrid Mount Metamodule")]
+#[command(
+    name = "meta-hybrid",
+    version,
+    about = "Hybrid Mount Metamodule",
+    after_help = failure::EXIT_CODES_HELP
+)]
 pub struct Cli {
     #[arg(short = 'c', long = "config")]
     pub config: Option<PathBuf>,
@@ -16,10 +21,36 @@ pub struct Cli {
     pub moduledir: Option<PathBuf>,
     #[arg(short = 's', long = "mountsource")]
     pub mountsource: Option<String>,
+    /// Deprecated: log at debug level, as `log_level = "debug"`.
     #[arg(short = 'v', long = "verbose")]
     pub verbose: bool,
+    /// Log the per-file lines of this module at debug level; may be
+    /// repeated. Those of other modules are left out.
+    #[arg(long = "debug-module", value_name = "ID")]
+    pub debug_modules: Vec<String>,
     #[arg(short = 'p', long = "partitions", value_delimiter = ',')]
     pub partitions: Vec<String>,
+    #[arg(long = "force-rebuild-image")]
+    pub force_rebuild_image: bool,
+    #[arg(long = "daemon")]
+    pub daemon: bool,
+    #[arg(long = "progress-socket")]
+    pub progress_socket: Option<PathBuf>,
+    /// Mount only what the last completed run mounted, with its config.
+    #[arg(long = "use-last-good")]
+    pub use_last_good: bool,
+    /// Config profile from `profiles/<name>.toml` to merge over the config.
+    #[arg(long = "profile")]
+    pub profile: Option<String>,
+    /// Plan against a dumped device tree instead of the live root. Only
+    /// `plan`, `plan-diff`, `conflicts`, `diagnostics` and `--dry-run` run;
+    /// nothing is mounted.
+    #[arg(long = "sysroot")]
+    pub sysroot: Option<PathBuf>,
+    /// Print the mount plan the boot run would make, as `plan` does, and
+    /// exit without mounting.
+    #[arg(long = "dry-run")]
+    pub dry_run: bool,
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -30,7 +61,11 @@ pub enum Commands {
         #[arg(short = 'o', long = "output", default_value = defs::CONFIG_FILE)]
         output: PathBuf,
     },
-    ShowConfig,
+    ShowConfig {
+        /// Print the config with the selected profile merged in.
+        #[arg(long = "resolved")]
+        resolved: bool,
+    },
     #[command(name = "save-config")]
     SaveConfig {
         #[arg(long)]
@@ -43,10 +78,137 @@ pub enum Commands {
         #[arg(long)]
         payload: String,
     },
+    /// Answers a versioned JSON request for the WebUI, given inline or on
+    /// stdin (`-` or no argument).
+    Api {
+        request: Option<String>,
+    },
     Storage,
     Modules,
-    Conflicts,
-    Diagnostics,
+    Conflicts {
+        /// Only analyze this subtree, e.g. `/system/framework`.
+        #[arg(long = "path")]
+        path: Option<String>,
+    },
+    Diagnostics {
+        /// Also report how the pending module updates change the mount plan.
+        #[arg(long)]
+        preview_updates: bool,
+    },
+    /// Prints the mount plan of the enabled modules as JSON without mounting.
+    Plan,
+    /// Compares the current plan against one saved from `plan`.
+    #[command(name = "plan-diff")]
+    PlanDiff {
+        baseline: PathBuf,
+    },
+    /// Verifies a config (default or the given file) before rebooting with it.
+    Check {
+        config: Option<PathBuf>,
+    },
+    Status,
+    Snapshot {
+        #[arg(short, long, default_value = "Manual")]
+        label: String,
+    },
+    Snapshots,
+    Restore {
+        id: String,
+    },
+    /// Forces `module` to win conflicts on `path`.
+    WinnowSet {
+        path: String,
+        module: String,
+        /// Save the rule even if the module is not in the inventory.
+        #[arg(long)]
+        force: bool,
+    },
+    WinnowUnset {
+        path: String,
+    },
+    /// Prints the winnowing rules with the conflicts they currently match.
+    WinnowList,
+    /// Proposes a winnowing rule, with its rationale, for each conflict no
+    /// rule decides yet.
+    WinnowSuggest {
+        /// Save every suggested rule into the config.
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Skips module `id` as if it had `skip_mount`, leaving it enabled for
+    /// KernelSU.
+    Exclude {
+        id: String,
+    },
+    /// Undoes `exclude`.
+    Include {
+        id: String,
+    },
+    /// Extracts a module zip into the staging area; it replaces the installed
+    /// module with the same id from the next mount on.
+    Stage {
+        zip: PathBuf,
+    },
+    Unstage {
+        id: String,
+    },
+    /// Repairs a device that no longer boots without reading the config or
+    /// state files and without mounting anything. Each action asks for
+    /// confirmation unless `--yes` is given.
+    Rescue {
+        /// Create `disable` in every module directory.
+        #[arg(long = "disable-all")]
+        disable_all: bool,
+        /// Create `disable` in this module's directory; may be repeated.
+        #[arg(long = "disable")]
+        disable: Vec<String>,
+        /// Delete modules.img and modules.erofs.
+        #[arg(long = "remove-images")]
+        remove_images: bool,
+        /// Delete everything in the run directory.
+        #[arg(long = "clear-state")]
+        clear_state: bool,
+        /// Do not ask before each action.
+        #[arg(short = 'y', long = "yes")]
+        yes: bool,
+    },
+    /// Unmounts the storage and deletes everything under
+    /// /data/adb/meta-hybrid; run by the module's uninstall script. Prints
+    /// what was removed as JSON.
+    #[command(name = "self-uninstall")]
+    SelfUninstall {
+        /// Keep config.toml, its profiles, the module rules and the granary
+        /// snapshots for a reinstall.
+        #[arg(long = "keep-config")]
+        keep_config: bool,
+    },
+    /// Deletes the persistent file hash cache.
+    #[command(name = "cache-clear")]
+    CacheClear,
+    /// Decodes the mount trace written with `trace_mounts` to JSON.
+    Trace {
+        /// Decode the trace of the run before the last one.
+        #[arg(long)]
+        previous: bool,
+    },
+    /// Prints the summary of the last boot, as text when `summary_text`
+    /// rendered it and as JSON otherwise.
+    Summary,
+    /// Times each pipeline stage and prints per-stage statistics as JSON.
+    Bench {
+        #[arg(short, long, default_value_t = 5)]
+        iterations: usize,
+        /// Also time the overlay and bind mounts, made on a scratch tree in
+        /// a private mount namespace.
+        #[arg(long)]
+        mount: bool,
+    },
+    /// Magic mounts the modules `defer_ce_modules` held back at boot; meant
+    /// for a `boot-completed` script. Does nothing when none were.
+    #[command(name = "mount-deferred")]
+    MountDeferred,
+    #[command(hide = true)]
+    Daemon,
     Poaceae {
         #[arg(short, long, default_value = defs::POACEAE_MOUNT_POINT)]
         target: String,
@@ -56,6 +218,17 @@ pub enum Commands {
     },
 }
 
+impl Commands {
+    /// Commands that only read the modules and the stock partitions, and
+    /// so can run against a `--sysroot`.
+    pub fn allows_sysroot(&self) -> bool {
+        matches!(
+            self,
+            Self::Plan | Self::PlanDiff { .. } | Self::Conflicts { .. } | Self::Diagnostics { .. }
+        )
+    }
+}
+
 #[derive(Subcommand, Debug)]
 pub enum PoaceaeAction {
     Hide {
@@ -1,30 +1,147 @@
 // Copyright 2026 Hybrid Mount Developers
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::{fs::File, path::Path};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
 
 use anyhow::{Context, Result};
 use serde::Serialize;
+use serde_json::Value;
 
 use crate::{
     conf::{
+        api::{self, Op},
+        check,
         cli::{Cli, PoaceaeAction},
-        config::{self, Config},
+        config::{self, Config, WinnowingTable},
+        profile,
+    },
+    core::{
+        bench, bundles, daemon, deferred,
+        failure::FailureClass,
+        granary, hashcache, integrity, inventory,
+        inventory::model as modules,
+        ops::{
+            blacklist::{self, Blacklist},
+            conflict::ConflictSeverity,
+            foreign, identical, partition_map,
+            plan_export::{self, PlanDiff, PlanExport},
+            planner,
+            probe::{LiveSystem, RootedSystem, SystemProbe},
+            replace,
+            simulate::{self, MountPrediction, PredictedOutcome},
+            sync,
+            update_preview::{self, ModulePlanDiff},
+            verity::{self, VerityCheck},
+        },
+        rescue::{self, RescueAction, RescueTargets},
+        staging,
+        state::RuntimeState,
+        storage,
+        uninstall::{self, UninstallTargets},
+        winnow,
     },
-    core::{inventory, inventory::model as modules, ops::planner, storage},
     defs,
-    sys::poaceae,
-    utils,
+    sys::{denylist, poaceae, root_backend},
+    utils::{self, timing, trace},
 };
 
+const MAX_PAYLOAD_SIZE: u64 = 4 * 1024 * 1024;
+
 #[derive(Serialize)]
-struct DiagnosticIssueJson {
+pub(crate) struct DiagnosticIssueJson {
     level: String,
     context: String,
     message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prediction: Option<MountPrediction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    verity: Option<VerityCheck>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct CheckVerdict {
+    ok: bool,
+    issues: Vec<DiagnosticIssueJson>,
+    kernel_features: utils::KernelFeatures,
 }
 
+fn level_name(level: &planner::DiagnosticLevel) -> String {
+    match level {
+        planner::DiagnosticLevel::Info => "Info".to_string(),
+        planner::DiagnosticLevel::Warning => "Warning".to_string(),
+        planner::DiagnosticLevel::Critical => "Critical".to_string(),
+    }
+}
+
+impl From<planner::DiagnosticIssue> for DiagnosticIssueJson {
+    fn from(issue: planner::DiagnosticIssue) -> Self {
+        Self {
+            level: level_name(&issue.level),
+            context: issue.context,
+            message: issue.message,
+            prediction: None,
+            verity: None,
+        }
+    }
+}
+
+impl From<MountPrediction> for DiagnosticIssueJson {
+    fn from(prediction: MountPrediction) -> Self {
+        let (level, outcome) = match prediction.outcome {
+            PredictedOutcome::Overlay => ("Info", "overlay"),
+            PredictedOutcome::FallbackMagic => ("Warning", "fallback to magic mount"),
+            PredictedOutcome::Skip => ("Warning", "skipped"),
+        };
+        let mut message = format!("{}: {}", prediction.target, outcome);
+        if !prediction.reasons.is_empty() {
+            message.push_str(&format!(" ({})", prediction.reasons.join("; ")));
+        }
+        Self {
+            level: level.to_string(),
+            context: "Predicted Mounts".to_string(),
+            message,
+            prediction: Some(prediction),
+            verity: None,
+        }
+    }
+}
+
+impl From<VerityCheck> for DiagnosticIssueJson {
+    fn from(check: VerityCheck) -> Self {
+        Self {
+            level: level_name(&check.level()),
+            context: "Verity".to_string(),
+            message: check.message(),
+            prediction: None,
+            verity: Some(check),
+        }
+    }
+}
+
+/// The config, with the module directory looked up inside `--sysroot`
+/// unless `--moduledir` names one.
 fn load_config(cli: &Cli) -> Result<Config> {
+    let mut config = read_config(cli)?;
+    if let Some(root) = &cli.sysroot {
+        config.moduledir = match &cli.moduledir {
+            Some(dir) => dir.clone(),
+            None => root.join(
+                config
+                    .moduledir
+                    .strip_prefix("/")
+                    .unwrap_or(&config.moduledir),
+            ),
+        };
+    }
+    Ok(config)
+}
+
+fn read_config(cli: &Cli) -> Result<Config> {
     if let Some(config_path) = &cli.config {
         return Config::from_file(config_path).with_context(|| {
             format!(
@@ -55,28 +172,141 @@ fn load_config(cli: &Cli) -> Result<Config> {
     }
 }
 
+/// The dumped device tree given with `--sysroot`, if any.
+fn sysroot(cli: &Cli) -> Option<RootedSystem> {
+    cli.sysroot.as_ref().map(RootedSystem::sysroot)
+}
+
+/// Where stock paths are looked up: in `sysroot`, or on the live device.
+fn system_probe(sysroot: Option<&RootedSystem>) -> &dyn SystemProbe {
+    match sysroot {
+        Some(sysroot) => sysroot,
+        None => &LiveSystem,
+    }
+}
+
 pub fn handle_gen_config(output: &Path) -> Result<()> {
     Config::default()
         .save_to_file(output)
         .with_context(|| format!("Failed to save generated config to {}", output.display()))
 }
 
-pub fn handle_show_config(cli: &Cli) -> Result<()> {
-    let config = load_config(cli)?;
+/// Runs a read-only API op and prints its data, as `api` would return it.
+fn print_op(cli: &Cli, op: Op, params: Value) -> Result<Value> {
+    let data = api::dispatch(cli, op, params)?;
 
-    let json = serde_json::to_string(&config).context("Failed to serialize config to JSON")?;
+    println!("{}", data);
+
+    Ok(data)
+}
 
-    println!("{}", json);
+pub(crate) fn show_config(cli: &Cli) -> Result<Config> {
+    load_config(cli)
+}
+
+#[derive(Serialize)]
+pub(crate) struct ResolvedConfig {
+    profile: Option<profile::AppliedProfile>,
+    config: Config,
+}
+
+/// The config with the selected profile merged in, as a mount run would
+/// use it before CLI overrides.
+pub(crate) fn resolved_config(cli: &Cli) -> Result<ResolvedConfig> {
+    let mut config = load_config(cli)?;
+    let profile = profile::resolve(&mut config, config_target(cli), cli.profile.as_deref())?;
+    Ok(ResolvedConfig { profile, config })
+}
+
+pub fn handle_show_config(cli: &Cli, resolved: bool) -> Result<()> {
+    let params = serde_json::json!({ "resolved": resolved });
+    print_op(cli, Op::ConfigShow, params).map(drop)
+}
+
+/// Answers one API request (inline JSON, or stdin for `-` or none) with a
+/// response envelope. Request errors are reported in the envelope, not as a
+/// failed exit.
+pub fn handle_api(cli: &Cli, request: Option<&str>) -> Result<()> {
+    let bytes = match request {
+        None | Some("-") => {
+            let mut buf = Vec::new();
+            io::stdin()
+                .take(MAX_PAYLOAD_SIZE + 1)
+                .read_to_end(&mut buf)
+                .context("Failed to read API request from stdin")?;
+            buf
+        }
+        Some(inline) => inline.as_bytes().to_vec(),
+    };
+
+    let response = if bytes.len() as u64 > MAX_PAYLOAD_SIZE {
+        api::Response::error(api::ApiError::new(
+            api::ErrorCode::InvalidRequest,
+            format!("request exceeds {} bytes", MAX_PAYLOAD_SIZE),
+        ))
+    } else {
+        api::handle(cli, &bytes)
+    };
+
+    println!(
+        "{}",
+        serde_json::to_string(&response).context("Failed to serialize API response")?
+    );
 
     Ok(())
 }
 
-pub fn handle_save_config(payload: &str) -> Result<()> {
-    let json_bytes = (0..payload.len())
+/// Decodes a `--payload` argument: `-` reads raw JSON from stdin, `@path`
+/// reads raw JSON from a file, anything else is hex-encoded JSON.
+fn read_payload(payload: &str) -> Result<Vec<u8>> {
+    let too_large = || {
+        anyhow::anyhow!(
+            "Payload exceeds the {} MiB limit",
+            MAX_PAYLOAD_SIZE / (1024 * 1024)
+        )
+    };
+
+    if payload == "-" {
+        let mut buf = Vec::new();
+        std::io::stdin()
+            .lock()
+            .take(MAX_PAYLOAD_SIZE + 1)
+            .read_to_end(&mut buf)
+            .context("Failed to read payload from stdin")?;
+        if buf.len() as u64 > MAX_PAYLOAD_SIZE {
+            return Err(too_large());
+        }
+        return Ok(buf);
+    }
+
+    if let Some(path) = payload.strip_prefix('@') {
+        let len = std::fs::metadata(path)
+            .with_context(|| format!("Failed to stat payload file {}", path))?
+            .len();
+        if len > MAX_PAYLOAD_SIZE {
+            return Err(too_large());
+        }
+        return std::fs::read(path)
+            .with_context(|| format!("Failed to read payload file {}", path));
+    }
+
+    if payload.len() as u64 / 2 > MAX_PAYLOAD_SIZE {
+        return Err(too_large());
+    }
+
+    if !payload.len().is_multiple_of(2) || !payload.is_ascii() {
+        anyhow::bail!("Failed to decode hex payload: malformed input");
+    }
+
+    (0..payload.len())
         .step_by(2)
         .map(|i| u8::from_str_radix(&payload[i..i + 2], 16))
         .collect::<Result<Vec<u8>, _>>()
-        .context("Failed to decode hex payload")?;
+        .context("Failed to decode hex payload")
+}
+
+pub fn handle_save_config(payload: &str) -> Result<()> {
+    let json_bytes = read_payload(payload)?;
 
     let config: Config =
         serde_json::from_slice(&json_bytes).context("Failed to parse config JSON payload")?;
@@ -92,11 +322,7 @@ pub fn handle_save_config(payload: &str) -> Result<()> {
 
 pub fn handle_save_module_rules(module_id: &str, payload: &str) -> Result<()> {
     utils::validate_module_id(module_id)?;
-    let json_bytes = (0..payload.len())
-        .step_by(2)
-        .map(|i| u8::from_str_radix(&payload[i..i + 2], 16))
-        .collect::<Result<Vec<u8>, _>>()
-        .context("Failed to decode hex payload")?;
+    let json_bytes = read_payload(payload)?;
 
     let new_rules: config::ModuleRules =
         serde_json::from_slice(&json_bytes).context("Failed to parse module rules JSON")?;
@@ -113,68 +339,789 @@ pub fn handle_save_module_rules(module_id: &str, payload: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn handle_storage() -> Result<()> {
-    storage::print_status().context("Failed to retrieve storage status")
+pub fn handle_storage(cli: &Cli) -> Result<()> {
+    print_op(cli, Op::StorageStatus, Value::Null).map(drop)
 }
 
-pub fn handle_modules(cli: &Cli) -> Result<()> {
+pub(crate) fn list_modules(cli: &Cli) -> Result<Vec<modules::ModuleInfo>> {
     let config = load_config(cli)?;
 
-    modules::print_list(&config).context("Failed to list modules")
+    modules::list(&config).context("Failed to list modules")
 }
 
-pub fn handle_conflicts(cli: &Cli) -> Result<()> {
+pub fn handle_modules(cli: &Cli) -> Result<()> {
+    print_op(cli, Op::ModulesList, Value::Null).map(drop)
+}
+
+/// Conflicts between enabled modules, sorted by path. `prefix` limits the
+/// analysis to one absolute subtree.
+pub(crate) fn list_conflicts(
+    cli: &Cli,
+    prefix: Option<&str>,
+) -> Result<Vec<winnow::ChaffConflict>> {
     let config = load_config(cli)?;
 
+    let sysroot = sysroot(cli);
+    let probe = system_probe(sysroot.as_ref());
+
     let module_list = inventory::scan(&config.moduledir, &config)
         .context("Failed to scan modules for conflict analysis")?;
 
-    let plan = planner::generate(&config, &module_list, &config.moduledir)
+    let plan = planner::generate_with_probe(&config, &module_list, &config.moduledir, probe)
         .context("Failed to generate plan for conflict analysis")?;
 
-    let report = plan.analyze();
+    let report = match prefix {
+        Some(prefix) => {
+            plan.analyze_under_with_probe(probe, Path::new(&WinnowingTable::normalize_path(prefix)))
+        }
+        None => plan.analyze_with_probe(probe),
+    };
 
-    let json =
-        serde_json::to_string(&report.conflicts).context("Failed to serialize conflict report")?;
+    let resolved = winnow::sift_conflicts(&report.conflicts, &config.winnowing);
+    hashcache::persist();
 
-    println!("{}", json);
+    Ok(resolved)
+}
 
-    Ok(())
+pub fn handle_conflicts(cli: &Cli, prefix: Option<&str>) -> Result<()> {
+    let params = serde_json::json!({ "path": prefix });
+    print_op(cli, Op::ConflictsList, params).map(drop)
 }
 
-pub fn handle_diagnostics(cli: &Cli) -> Result<()> {
+pub(crate) fn diagnose(cli: &Cli, preview_updates: bool) -> Result<Vec<DiagnosticIssueJson>> {
     let config = load_config(cli)?;
+    let sysroot = sysroot(cli);
+    let probe = system_probe(sysroot.as_ref());
 
     let module_list = inventory::scan(&config.moduledir, &config)
         .context("Failed to scan modules for diagnostics")?;
 
-    let plan = planner::generate(&config, &module_list, &config.moduledir)
+    let plan = planner::generate_with_probe(&config, &module_list, &config.moduledir, probe)
         .context("Failed to generate plan for diagnostics")?;
 
-    let report = plan.analyze();
+    let predictions = simulate::simulate_with_probe(&plan, &config.mountsource, probe);
+    let mut issues = collect_diagnostics(
+        &config,
+        &module_list,
+        &plan,
+        plan.analyze_with_probe(probe),
+        sysroot.as_ref(),
+    );
+    if preview_updates {
+        let diffs = update_preview::preview_with_probe(&config, &defs::Paths::default(), probe)
+            .context("Failed to preview pending module updates")?;
+        issues.extend(diffs.iter().map(update_issue));
+    }
+    let verity = match sysroot {
+        Some(_) => Vec::new(),
+        None => verity::scan(&plan),
+    };
+    let json_issues: Vec<DiagnosticIssueJson> = issues
+        .into_iter()
+        .map(DiagnosticIssueJson::from)
+        .chain(predictions.into_iter().map(DiagnosticIssueJson::from))
+        .chain(verity.into_iter().map(DiagnosticIssueJson::from))
+        .collect();
+    hashcache::persist();
+
+    Ok(json_issues)
+}
+
+pub fn handle_diagnostics(cli: &Cli, preview_updates: bool) -> Result<()> {
+    let params = serde_json::json!({ "preview_updates": preview_updates });
+    print_op(cli, Op::DiagnosticsList, params).map(drop)
+}
+
+/// The plan of the enabled modules as computed now.
+pub(crate) fn export_plan(cli: &Cli) -> Result<PlanExport> {
+    let config = load_config(cli)?;
+
+    let sysroot = sysroot(cli);
+
+    let module_list = inventory::scan(&config.moduledir, &config)
+        .context("Failed to scan modules for the plan export")?;
+
+    let plan = planner::generate_with_probe(
+        &config,
+        &module_list,
+        &config.moduledir,
+        system_probe(sysroot.as_ref()),
+    )
+    .context("Failed to generate plan for the plan export")?;
+
+    Ok(plan.to_json())
+}
+
+pub fn handle_plan(cli: &Cli) -> Result<()> {
+    print_op(cli, Op::PlanShow, Value::Null).map(drop)
+}
 
-    let json_issues: Vec<DiagnosticIssueJson> = report
+/// How the current plan differs from the one saved at `baseline`.
+pub(crate) fn diff_plan(cli: &Cli, baseline: &Path) -> Result<PlanDiff> {
+    let baseline = plan_export::load(baseline)?;
+    Ok(plan_export::diff(&baseline, &export_plan(cli)?))
+}
+
+pub fn handle_plan_diff(cli: &Cli, baseline: &Path) -> Result<()> {
+    let params = serde_json::json!({ "baseline": baseline });
+    print_op(cli, Op::PlanDiff, params).map(drop)
+}
+
+/// One line per module: its version change, then the mounts the update
+/// adds, removes and moves to another method.
+fn update_issue(diff: &ModulePlanDiff) -> planner::DiagnosticIssue {
+    let mut parts = Vec::new();
+    if !diff.added.is_empty() {
+        let added: Vec<_> = diff
+            .added
+            .iter()
+            .map(|m| format!("{} ({})", m.target, m.method))
+            .collect();
+        parts.push(format!("adds {}", added.join(", ")));
+    }
+    if !diff.removed.is_empty() {
+        let removed: Vec<_> = diff
+            .removed
+            .iter()
+            .map(|m| format!("{} ({})", m.target, m.method))
+            .collect();
+        parts.push(format!("removes {}", removed.join(", ")));
+    }
+    parts.extend(
+        diff.changed
+            .iter()
+            .map(|c| format!("{}: {} -> {}", c.target, c.before, c.after)),
+    );
+    if parts.is_empty() {
+        parts.push("mounts unchanged".to_string());
+    }
+
+    let version = match &diff.update {
+        Some(update) => format!(
+            "{} -> {}",
+            update.current_version.as_deref().unwrap_or("not installed"),
+            update.new_version
+        ),
+        None => "not updated".to_string(),
+    };
+    planner::DiagnosticIssue {
+        level: planner::DiagnosticLevel::Info,
+        context: format!("Update {}", diff.id),
+        message: format!("{}: {}", version, parts.join("; ")),
+    }
+}
+
+/// Diagnostics of `plan` on the live device, or on the dumped tree of
+/// `sysroot`. A sysroot leaves out everything only the device can tell:
+/// its kernel, denylist, boot state, foreign mounts and verity.
+fn collect_diagnostics(
+    config: &Config,
+    module_list: &[inventory::Module],
+    plan: &planner::MountPlan,
+    mut report: planner::AnalysisReport,
+    sysroot: Option<&RootedSystem>,
+) -> Vec<planner::DiagnosticIssue> {
+    let mut span = timing::span("diagnostics");
+    let probe = system_probe(sysroot);
+    if sysroot.is_none() {
+        let features = utils::kernel_features();
+        report.diagnostics.push(planner::DiagnosticIssue {
+            level: planner::DiagnosticLevel::Info,
+            context: "Kernel".to_string(),
+            message: format!(
+                "root={:?} overlayfs={} fsopen={} tmpfs_xattr={} erofs={} max_layers={} max_lowerdir_len={}",
+                features.root_impl,
+                features.overlayfs,
+                features.overlay_fsopen,
+                features.tmpfs_xattr,
+                features.erofs,
+                features.max_lowerdir_count,
+                features.max_lowerdir_len
+            ),
+        });
+    }
+    report.diagnostics.extend(storage::diagnose(config));
+    report
+        .diagnostics
+        .extend(blacklist::diagnose(&Blacklist::new(config, module_list)));
+    if sysroot.is_none() {
+        report
+            .diagnostics
+            .extend(denylist::diagnose(config.denylist_provider));
+        report
+            .diagnostics
+            .extend(bundles::diagnose(&config.image_modules));
+    }
+    if sysroot.is_none()
+        && let Ok(state) = RuntimeState::load()
+    {
+        report.diagnostics.extend(
+            state
+                .busy_skipped
+                .iter()
+                .map(|path| planner::DiagnosticIssue {
+                    level: planner::DiagnosticLevel::Warning,
+                    context: "Magic Mount".to_string(),
+                    message: format!(
+                        "{} was not replaced at boot because the stock file was in use",
+                        path.display()
+                    ),
+                }),
+        );
+        report
+            .diagnostics
+            .extend(sync::diagnose(&state.sync_summary));
+        if let Some(integrity) = &state.integrity {
+            report.diagnostics.extend(integrity::diagnose(integrity));
+        }
+    }
+    report
+        .diagnostics
+        .extend(inventory::validate::diagnose(module_list));
+    report.diagnostics.extend(inventory::encryption::diagnose(
+        module_list,
+        config.defer_ce_modules,
+    ));
+    report
         .diagnostics
+        .extend(partition_map::diagnose(module_list, probe));
+    report
+        .diagnostics
+        .extend(replace::diagnose(module_list, probe));
+    if config.skip_identical_files {
+        let stock_root = sysroot.map_or(Path::new("/"), |s| &s.root);
+        report
+            .diagnostics
+            .extend(identical::diagnose(module_list, stock_root));
+    }
+    if sysroot.is_none() {
+        report.diagnostics.extend(foreign::diagnose(
+            &foreign::scan(plan, config, &defs::Paths::default()),
+            config.foreign_mount_policy,
+        ));
+    }
+    span.items(report.diagnostics.len());
+    report.diagnostics
+}
+
+/// Runs the dry-run pipeline plus static config checks on the default
+/// config or `config_path`. `ok` is false when any critical issue is found.
+pub(crate) fn check(cli: &Cli, config_path: Option<&Path>) -> CheckVerdict {
+    let loaded = match config_path {
+        Some(path) => Config::from_file(path)
+            .with_context(|| format!("Failed to load config from {}", path.display())),
+        None => load_config(cli),
+    };
+
+    let issues = match loaded {
+        Ok(config) => check_pipeline(&config),
+        Err(e) => vec![DiagnosticIssueJson::from(planner::DiagnosticIssue {
+            level: planner::DiagnosticLevel::Critical,
+            context: "Config".to_string(),
+            message: format!("{:#}", e),
+        })],
+    };
+
+    let ok = !issues.iter().any(|i| i.level == "Critical");
+
+    CheckVerdict {
+        ok,
+        issues,
+        kernel_features: utils::kernel_features().clone(),
+    }
+}
+
+/// Prints the `check` verdict. Exits with the diagnostics abort code when
+/// it is not ok.
+pub fn handle_check(cli: &Cli, config_path: Option<&Path>) -> Result<()> {
+    let params = serde_json::json!({ "config": config_path });
+    let verdict = print_op(cli, Op::ConfigCheck, params)?;
+
+    if verdict["ok"] != Value::Bool(true) {
+        std::process::exit(FailureClass::DiagnosticsAbort.exit_code());
+    }
+
+    Ok(())
+}
+
+fn check_pipeline(config: &Config) -> Vec<DiagnosticIssueJson> {
+    let critical = |context: &str, e: anyhow::Error| planner::DiagnosticIssue {
+        level: planner::DiagnosticLevel::Critical,
+        context: context.to_string(),
+        message: format!("{:#}", e),
+    };
+
+    let module_list = match inventory::scan(&config.moduledir, config) {
+        Ok(modules) => modules,
+        Err(e) => return vec![critical("Inventory", e).into()],
+    };
+
+    let mut issues = check::check_config(config, &module_list);
+
+    let plan = match planner::generate(config, &module_list, &config.moduledir) {
+        Ok(plan) => plan,
+        Err(e) => {
+            issues.push(critical("Planner", e));
+            return issues.into_iter().map(DiagnosticIssueJson::from).collect();
+        }
+    };
+
+    let report = plan.analyze();
+    let resolved = winnow::sift_conflicts(&report.conflicts, &config.winnowing);
+    if !resolved.is_empty() {
+        let forced = resolved.iter().filter(|c| c.is_forced).count();
+        let count = |severity| resolved.iter().filter(|c| c.severity == severity).count();
+        issues.push(planner::DiagnosticIssue {
+            level: planner::DiagnosticLevel::Info,
+            context: "Conflicts".to_string(),
+            message: format!(
+                "{} contested paths ({} severe, {} notice, {} benign), {} settled by winnowing rules",
+                resolved.len(),
+                count(ConflictSeverity::Severe),
+                count(ConflictSeverity::Notice),
+                count(ConflictSeverity::Benign),
+                forced
+            ),
+        });
+    }
+
+    issues.extend(collect_diagnostics(
+        config,
+        &module_list,
+        &plan,
+        report,
+        None,
+    ));
+    hashcache::persist();
+    issues
         .into_iter()
-        .map(|i| DiagnosticIssueJson {
-            level: match i.level {
-                planner::DiagnosticLevel::Info => "Info".to_string(),
-                planner::DiagnosticLevel::Warning => "Warning".to_string(),
-                planner::DiagnosticLevel::Critical => "Critical".to_string(),
-            },
-            context: i.context,
-            message: i.message,
+        .map(DiagnosticIssueJson::from)
+        .chain(
+            verity::scan(&plan)
+                .into_iter()
+                .map(DiagnosticIssueJson::from),
+        )
+        .collect()
+}
+
+/// Prints the health report. Exits with status 1 when unhealthy.
+pub fn handle_status(cli: &Cli) -> Result<()> {
+    let report = print_op(cli, Op::StatusGet, Value::Null)?;
+
+    if report["healthy"] != Value::Bool(true) {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+pub fn handle_snapshot(cli: &Cli, label: &str) -> Result<()> {
+    let config = load_config(cli)?;
+
+    let info = granary::create_snapshot(&config, label).context("Failed to create snapshot")?;
+
+    println!("{}", serde_json::to_string(&info)?);
+
+    Ok(())
+}
+
+pub fn handle_snapshots(cli: &Cli) -> Result<()> {
+    print_op(cli, Op::SnapshotsList, Value::Null).map(drop)
+}
+
+pub fn handle_restore(cli: &Cli, id: &str) -> Result<()> {
+    let config = load_config(cli)?;
+
+    let summary = granary::restore_snapshot(&config, id)
+        .with_context(|| format!("Failed to restore snapshot {}", id))?;
+
+    println!("{}", serde_json::to_string(&summary)?);
+
+    Ok(())
+}
+
+/// Where config edits from the CLI are written: `--config` if given,
+/// otherwise the default config file.
+fn config_target(cli: &Cli) -> &Path {
+    cli.config
+        .as_deref()
+        .unwrap_or(Path::new(defs::CONFIG_FILE))
+}
+
+/// Saves a winnowing rule and returns the normalized path it was saved
+/// under.
+pub(crate) fn winnow_set(cli: &Cli, path: &str, module: &str, force: bool) -> Result<String> {
+    utils::validate_module_id(module)?;
+    let mut config = load_config(cli)?;
+
+    let known = inventory::scan(&config.moduledir, &config)
+        .map(|modules| modules.iter().any(|m| m.id == module))
+        .unwrap_or(false);
+
+    if !known {
+        if !force {
+            anyhow::bail!(
+                "Module '{}' is not in the current inventory (use --force to save anyway)",
+                module
+            );
+        }
+        log::warn!("Module '{}' is not in the current inventory", module);
+    }
+
+    config.winnowing.set_rule(path, module);
+    config
+        .save_to_file(config_target(cli))
+        .context("Failed to save config file")?;
+
+    Ok(WinnowingTable::normalize_path(path))
+}
+
+pub fn handle_winnow_set(cli: &Cli, path: &str, module: &str, force: bool) -> Result<()> {
+    let path = winnow_set(cli, path, module, force)?;
+
+    println!("Winnowing rule saved: {} -> {}", path, module);
+
+    Ok(())
+}
+
+/// Removes a winnowing rule and returns its normalized path and the module
+/// it named.
+pub(crate) fn winnow_unset(cli: &Cli, path: &str) -> Result<(String, String)> {
+    let mut config = load_config(cli)?;
+
+    let Some(module) = config.winnowing.remove_rule(path) else {
+        anyhow::bail!(
+            "No winnowing rule for {}",
+            WinnowingTable::normalize_path(path)
+        );
+    };
+
+    config
+        .save_to_file(config_target(cli))
+        .context("Failed to save config file")?;
+
+    Ok((WinnowingTable::normalize_path(path), module))
+}
+
+pub fn handle_winnow_unset(cli: &Cli, path: &str) -> Result<()> {
+    let (path, module) = winnow_unset(cli, path)?;
+
+    println!("Winnowing rule removed: {} -> {}", path, module);
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub(crate) struct WinnowRuleJson {
+    path: String,
+    module: String,
+    /// Modules currently providing the path; empty when it is not contested.
+    contenders: Vec<String>,
+    /// The rule does not decide any current conflict.
+    stale: bool,
+}
+
+pub(crate) fn list_winnow_rules(cli: &Cli) -> Result<Vec<WinnowRuleJson>> {
+    let config = load_config(cli)?;
+
+    let module_list = inventory::scan(&config.moduledir, &config)
+        .context("Failed to scan modules for winnowing rules")?;
+
+    let plan = planner::generate(&config, &module_list, &config.moduledir)
+        .context("Failed to generate plan for winnowing rules")?;
+
+    let report = plan.analyze();
+    let contested: HashMap<String, Vec<String>> = report
+        .conflicts
+        .iter()
+        .map(|c| (winnow::conflict_path(c), c.contending_modules.clone()))
+        .collect();
+
+    let rules: Vec<WinnowRuleJson> = config
+        .winnowing
+        .rules
+        .iter()
+        .map(|(path, module)| {
+            let contenders = contested.get(path).cloned().unwrap_or_default();
+            WinnowRuleJson {
+                stale: !contenders.contains(module),
+                path: path.clone(),
+                module: module.clone(),
+                contenders,
+            }
         })
         .collect();
 
-    let json =
-        serde_json::to_string(&json_issues).context("Failed to serialize diagnostics report")?;
+    Ok(rules)
+}
+
+/// Rules proposed for the conflicts no rule decides yet. With `apply` they
+/// are all saved into the winnowing table.
+pub(crate) fn suggest_winnow_rules(
+    cli: &Cli,
+    apply: bool,
+) -> Result<Vec<winnow::WinnowSuggestion>> {
+    let mut config = load_config(cli)?;
+
+    let module_list = inventory::scan(&config.moduledir, &config)
+        .context("Failed to scan modules for winnowing suggestions")?;
+
+    let plan = planner::generate(&config, &module_list, &config.moduledir)
+        .context("Failed to generate plan for winnowing suggestions")?;
+
+    let report = plan.analyze();
+    let suggestions =
+        winnow::suggest_rules(&plan, &report.conflicts, &module_list, &config.winnowing);
+    hashcache::persist();
+
+    if apply && !suggestions.is_empty() {
+        for suggestion in &suggestions {
+            config
+                .winnowing
+                .set_rule(&suggestion.path, &suggestion.module);
+        }
+        config
+            .save_to_file(config_target(cli))
+            .context("Failed to save config file")?;
+        log::info!("Saved {} suggested winnowing rule(s)", suggestions.len());
+    }
+
+    Ok(suggestions)
+}
+
+pub fn handle_winnow_suggest(cli: &Cli, apply: bool) -> Result<()> {
+    let params = serde_json::json!({ "apply": apply });
+    print_op(cli, Op::WinnowSuggest, params).map(drop)
+}
+
+pub fn handle_winnow_list(cli: &Cli) -> Result<()> {
+    print_op(cli, Op::WinnowList, Value::Null).map(drop)
+}
+
+/// Adds `id` to the config's `exclusions`. Returns whether it was not
+/// already there.
+pub(crate) fn exclude_module(cli: &Cli, id: &str) -> Result<bool> {
+    utils::validate_module_id(id)?;
+    let mut config = load_config(cli)?;
+
+    if config.exclusions.iter().any(|e| e == id) {
+        return Ok(false);
+    }
+    config.exclusions.push(id.to_string());
+    config
+        .save_to_file(config_target(cli))
+        .context("Failed to save config file")?;
+
+    Ok(true)
+}
 
-    println!("{}", json);
+pub fn handle_exclude(cli: &Cli, id: &str) -> Result<()> {
+    if exclude_module(cli, id)? {
+        println!("Module excluded: {}", id);
+    } else {
+        println!("Module already excluded: {}", id);
+    }
 
     Ok(())
 }
 
+/// Removes `id` from the config's `exclusions`. Returns whether it was
+/// there.
+pub(crate) fn include_module(cli: &Cli, id: &str) -> Result<bool> {
+    let mut config = load_config(cli)?;
+
+    let before = config.exclusions.len();
+    config.exclusions.retain(|e| e != id);
+    if config.exclusions.len() == before {
+        return Ok(false);
+    }
+    config
+        .save_to_file(config_target(cli))
+        .context("Failed to save config file")?;
+
+    Ok(true)
+}
+
+pub fn handle_include(cli: &Cli, id: &str) -> Result<()> {
+    if include_module(cli, id)? {
+        println!("Module included: {}", id);
+    } else {
+        println!("Module was not excluded: {}", id);
+    }
+
+    Ok(())
+}
+
+pub fn handle_stage(zip: &Path) -> Result<()> {
+    let staged = staging::stage(zip, Path::new(defs::STAGING_DIR))
+        .with_context(|| format!("Failed to stage {}", zip.display()))?;
+
+    println!("{}", serde_json::to_string(&staged)?);
+
+    Ok(())
+}
+
+pub fn handle_unstage(id: &str) -> Result<()> {
+    staging::unstage(id, Path::new(defs::STAGING_DIR))
+        .with_context(|| format!("Failed to unstage {}", id))?;
+
+    println!(
+        "{}",
+        serde_json::to_string(&staging::list(Path::new(defs::STAGING_DIR)))?
+    );
+
+    Ok(())
+}
+
+/// Runs the selected rescue actions, asking on stderr before each unless
+/// `yes`, and prints what was done.
+pub fn handle_rescue(
+    disable_all: bool,
+    disable: &[String],
+    remove_images: bool,
+    clear_state: bool,
+    yes: bool,
+) -> Result<()> {
+    let mut actions = Vec::new();
+    if disable_all {
+        actions.push(RescueAction::DisableAll);
+    }
+    actions.extend(disable.iter().cloned().map(RescueAction::Disable));
+    if remove_images {
+        actions.push(RescueAction::RemoveImages);
+    }
+    if clear_state {
+        actions.push(RescueAction::ClearState);
+    }
+    if actions.is_empty() {
+        anyhow::bail!(
+            "Nothing to do; pass --disable-all, --disable <id>, --remove-images or --clear-state"
+        );
+    }
+
+    let mut confirm = |prompt: &str| {
+        if yes {
+            return true;
+        }
+        eprint!("{} [y/N] ", prompt);
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer).is_ok()
+            && matches!(answer.trim(), "y" | "Y" | "yes" | "YES")
+    };
+
+    for line in rescue::run(&actions, &RescueTargets::default(), &mut confirm) {
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
+/// Cleans up after the module and prints the report; leftovers make it
+/// fail after printing.
+pub fn handle_self_uninstall(keep_config: bool) -> Result<()> {
+    let report = uninstall::run(&UninstallTargets::default(), keep_config)?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if !report.errors.is_empty() {
+        anyhow::bail!("Cleanup left {} item(s) behind", report.errors.len());
+    }
+    Ok(())
+}
+
+pub fn handle_cache_clear() -> Result<()> {
+    hashcache::clear(Path::new(defs::HASH_CACHE_FILE)).context("Failed to clear hash cache")
+}
+
+pub fn handle_trace(previous: bool) -> Result<()> {
+    let path = Path::new(defs::TRACE_FILE);
+    let path = if previous {
+        trace::previous_path(path)
+    } else {
+        path.to_path_buf()
+    };
+    let dump = trace::decode(&path)?;
+    println!("{}", serde_json::to_string(&dump)?);
+    Ok(())
+}
+
+pub fn handle_summary(cli: &Cli) -> Result<()> {
+    let text_file = defs::Paths::default().summary_file.with_extension("txt");
+    if let Ok(text) = std::fs::read_to_string(&text_file) {
+        print!("{}", text);
+        return Ok(());
+    }
+    print_op(cli, Op::SummaryGet, Value::Null).map(drop)
+}
+
+/// Runs the pipeline `iterations` times and prints per-stage statistics.
+/// With `mount`, `main` has already moved the process into a private mount
+/// namespace.
+pub fn handle_bench(cli: &Cli, iterations: usize, mount: bool) -> Result<()> {
+    let config = load_config(cli)?;
+    let iterations = iterations.max(1);
+    let scratch = defs::Paths::default().run_dir.join(bench::SCRATCH_DIR_NAME);
+
+    timing::start_recording();
+    let mut modules = 0;
+    let mut sandbox = None;
+    for _ in 0..iterations {
+        let module_list = inventory::scan(&config.moduledir, &config)
+            .context("Failed to scan modules for the benchmark")?;
+        let plan = planner::generate(&config, &module_list, &config.moduledir)
+            .context("Failed to generate plan for the benchmark")?;
+        collect_diagnostics(&config, &module_list, &plan, plan.analyze(), None);
+        if mount {
+            sandbox = Some(bench::mount_sandboxed(
+                &plan,
+                &scratch,
+                &config.mountsource,
+            )?);
+        }
+        modules = module_list.len();
+    }
+
+    let report = bench::BenchReport {
+        iterations,
+        modules,
+        stages: timing::summarize(&timing::take_samples()),
+        sandbox,
+    };
+    println!("{}", serde_json::to_string(&report)?);
+    Ok(())
+}
+
+/// Mounts the modules held back at boot and prints their ids as JSON.
+pub fn handle_mount_deferred(config: Config) -> Result<()> {
+    let _log_guard = utils::init_logging(
+        config.effective_log_level().filter(),
+        &config.debug_modules,
+        config.log_format == config::LogFormat::Json,
+        config.log_buffer_kb,
+    )
+    .context("Failed to initialize logging")?;
+    root_backend::install(root_backend::detect());
+
+    let mounted = deferred::mount(&config, &defs::Paths::default())
+        .context("Failed to mount deferred modules")?;
+    println!("{}", serde_json::to_string(&mounted)?);
+    Ok(())
+}
+
+pub fn handle_daemon(config: Config) -> Result<()> {
+    let _log_guard = utils::init_logging(
+        config.effective_log_level().filter(),
+        &config.debug_modules,
+        config.log_format == config::LogFormat::Json,
+        config.log_buffer_kb,
+    )
+    .context("Failed to initialize logging")?;
+
+    if let Err(e) = utils::camouflage_process(&utils::random_kworker_name()) {
+        log::warn!("Failed to camouflage process: {:#}", e);
+    }
+
+    daemon::run(&config)
+}
+
 pub fn handle_poaceae(target_path: &str, action: &PoaceaeAction) -> Result<()> {
     let file = File::open(target_path)
         .with_context(|| format!("Failed to open PoaceaeFS root at {}", target_path))?;
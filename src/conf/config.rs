@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fs,
     path::{Path, PathBuf},
 };
@@ -10,7 +10,8 @@ use std::{
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
-use crate::defs;
+use super::migrate::{self, CURRENT_SCHEMA_VERSION, LoadReport};
+use crate::{defs, sys::denylist::DenylistProvider, utils};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BackupConfig {
@@ -37,6 +38,60 @@ impl Default for BackupConfig {
     }
 }
 
+/// Sizing of the ext4 `modules.img`, see `core::storage::image_size`, and
+/// the loop device it is attached to.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StorageConfig {
+    /// Free space, in MiB, every image gets at least.
+    #[serde(default = "default_image_overhead_mb")]
+    pub image_overhead_mb: u64,
+    /// Free space in percent of the module content, when that is more than
+    /// the overhead.
+    #[serde(default = "default_image_headroom_percent")]
+    pub image_headroom_percent: u64,
+    /// Let the kernel detach the loop device once the image is unmounted.
+    #[serde(default = "default_loop_autoclear")]
+    pub loop_autoclear: bool,
+    /// Open the image with direct I/O, bypassing the page cache.
+    #[serde(default)]
+    pub loop_direct_io: bool,
+}
+
+fn default_image_overhead_mb() -> u64 {
+    64
+}
+
+fn default_image_headroom_percent() -> u64 {
+    20
+}
+
+fn default_loop_autoclear() -> bool {
+    true
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            image_overhead_mb: default_image_overhead_mb(),
+            image_headroom_percent: default_image_headroom_percent(),
+            loop_autoclear: default_loop_autoclear(),
+            loop_direct_io: false,
+        }
+    }
+}
+
+/// Per-boot randomization of the identifiers detection apps look for.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct StealthConfig {
+    /// Replace `mountsource` with a random `/dev/block/dm-N` source, and the
+    /// file name of the image's loop device with a random one.
+    #[serde(default)]
+    pub randomize_mountsource: bool,
+    /// Replace `hybrid_mnt_dir` with a random directory under `/mnt`.
+    #[serde(default)]
+    pub randomize_tempdir: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum OverlayMode {
@@ -44,6 +99,179 @@ pub enum OverlayMode {
     Tmpfs,
     Ext4,
     Erofs,
+    Zram,
+}
+
+/// Value of the overlayfs `xino` option.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum XinoMode {
+    Auto,
+    On,
+    Off,
+}
+
+impl XinoMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::On => "on",
+            Self::Off => "off",
+        }
+    }
+}
+
+/// overlayfs mount options for one partition. Unset options are left to
+/// the kernel default.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OverlayOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xino: Option<XinoMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metacopy: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect_dir: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub userxattr: Option<bool>,
+}
+
+impl OverlayOptions {
+    /// Options set here win over those of `base`.
+    pub fn merged_over(self, base: Self) -> Self {
+        Self {
+            xino: self.xino.or(base.xino),
+            metacopy: self.metacopy.or(base.metacopy),
+            redirect_dir: self.redirect_dir.or(base.redirect_dir),
+            userxattr: self.userxattr.or(base.userxattr),
+        }
+    }
+
+    /// The options as `(key, value)` mount parameters; `userxattr` is a
+    /// flag without a value.
+    pub fn mount_params(&self) -> Vec<(&'static str, Option<&'static str>)> {
+        let on_off = |on: bool| if on { "on" } else { "off" };
+        let mut params = Vec::new();
+        if let Some(xino) = self.xino {
+            params.push(("xino", Some(xino.as_str())));
+        }
+        if let Some(metacopy) = self.metacopy {
+            params.push(("metacopy", Some(on_off(metacopy))));
+        }
+        if let Some(redirect_dir) = self.redirect_dir {
+            params.push(("redirect_dir", Some(on_off(redirect_dir))));
+        }
+        if self.userxattr == Some(true) {
+            params.push(("userxattr", None));
+        }
+        params
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub fn filter(self) -> log::LevelFilter {
+        match self {
+            Self::Error => log::LevelFilter::Error,
+            Self::Warn => log::LevelFilter::Warn,
+            Self::Info => log::LevelFilter::Info,
+            Self::Debug => log::LevelFilter::Debug,
+            Self::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Plain,
+    Json,
+}
+
+/// What magic mount does with a module file whose stock counterpart is
+/// being executed or mapped by a running process.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BusyFilePolicy {
+    /// Leave the stock file in place and record the skip.
+    Skip,
+    /// Replace it and log a warning.
+    Warn,
+    /// Replace it without checking.
+    #[default]
+    Force,
+}
+
+/// What a boot does when the modules exceed `tmpfs_max_mb` on tmpfs storage.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TmpfsOverflow {
+    /// Use an ext4 image instead of tmpfs.
+    #[default]
+    Ext4,
+    /// Leave out the largest modules until the rest fit.
+    Trim,
+    /// Abort the mount run.
+    Fail,
+}
+
+/// What to do when another root solution already mounted over a partition
+/// we are about to mount.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ForeignMountPolicy {
+    /// Stop before mounting anything.
+    Abort,
+    /// Mount on top of the foreign mounts anyway.
+    #[default]
+    Stack,
+    /// Leave the affected partitions alone and mount the rest.
+    SkipPartition,
+}
+
+/// When a mount run replays the last good plan instead of scanning the
+/// module directory afresh.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum LastGoodPolicy {
+    #[default]
+    Never,
+    /// Only when a boot loop is detected; replaces safe mode if a plan was
+    /// recorded.
+    OnBootLoop,
+    Always,
+}
+
+/// Mount namespace a run mounts in, see `sys::namespace`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NamespaceMode {
+    /// The init namespace, seen by every process.
+    #[default]
+    Global,
+    /// A pinned slave copy of the init namespace; the global tree is left
+    /// untouched.
+    Clone,
+}
+
+/// Scheduling of the storage and sync phases, see `sys::priority`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BootPriority {
+    /// Idle I/O, niced threads and a low `io.weight` until mounting starts.
+    Low,
+    #[default]
+    Normal,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
@@ -61,6 +289,8 @@ pub enum MountMode {
     Overlay,
     Magic,
     Ignore,
+    #[serde(alias = "hymofs")]
+    Hymo,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -69,6 +299,21 @@ pub struct ModuleRules {
     pub default_mode: MountMode,
     #[serde(default)]
     pub paths: HashMap<String, MountMode>,
+    /// Layering weight; higher values sit above lower ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order: Option<i32>,
+    /// Modules this one must be layered above.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub after: Vec<String>,
+    /// Partition directories of the module that are never mounted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_partitions: Vec<String>,
+    /// Keep every file of the module writable instead of remounting it read-only.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_rw_files: bool,
+    /// Globs of target paths this module's files are not mounted at.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blacklist: Vec<String>,
 }
 
 impl ModuleRules {
@@ -78,23 +323,100 @@ impl ModuleRules {
         }
         self.default_mode.clone()
     }
+
+    pub fn excludes_partition(&self, partition: &str) -> bool {
+        self.exclude_partitions.iter().any(|p| p == partition)
+    }
+}
+
+/// Maps an absolute target path to the module that must win conflicts on it.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct WinnowingTable {
+    #[serde(default, deserialize_with = "deserialize_winnowing_rules")]
+    pub rules: BTreeMap<String, String>,
+}
+
+impl WinnowingTable {
+    /// Canonical form of a rule path: one leading `/`, no empty segments and
+    /// no trailing `/`, matching the paths conflict analysis produces.
+    pub fn normalize_path(path: &str) -> String {
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        format!("/{}", segments.join("/"))
+    }
+
+    pub fn set_rule(&mut self, path: &str, module_id: &str) {
+        self.rules
+            .insert(Self::normalize_path(path), module_id.to_string());
+    }
+
+    pub fn remove_rule(&mut self, path: &str) -> Option<String> {
+        self.rules.remove(&Self::normalize_path(path))
+    }
+
+    pub fn get_preferred_module(&self, path: &str) -> Option<&str> {
+        self.rules
+            .get(&Self::normalize_path(path))
+            .map(|s| s.as_str())
+    }
+}
+
+fn deserialize_winnowing_rules<'de, D>(
+    deserializer: D,
+) -> Result<BTreeMap<String, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let raw = BTreeMap::<String, String>::deserialize(deserializer)?;
+    Ok(raw
+        .into_iter()
+        .map(|(path, module)| (WinnowingTable::normalize_path(&path), module))
+        .collect())
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
     #[serde(default = "default_moduledir")]
     pub moduledir: PathBuf,
+    /// squashfs or erofs images, each holding module directories at its
+    /// top level, mounted read-only next to `moduledir`.
+    #[serde(default)]
+    pub image_modules: Vec<PathBuf>,
     #[serde(default = "default_mountsource")]
     pub mountsource: String,
+    /// Deprecated: the same as `log_level = "debug"`.
     pub verbose: bool,
     #[serde(default, deserialize_with = "deserialize_partitions_flexible")]
     pub partitions: Vec<String>,
     #[serde(default)]
+    pub auto_partitions: bool,
+    #[serde(default)]
+    pub busy_file_policy: BusyFilePolicy,
+    #[serde(default)]
+    pub foreign_mount_policy: ForeignMountPolicy,
+    #[serde(default)]
+    pub prefer_bind_for_small_modules: bool,
+    /// overlayfs mount options by partition name, `"*"` for every
+    /// partition; see [`Config::overlay_options_for`].
+    #[serde(default)]
+    pub overlay_options: BTreeMap<String, OverlayOptions>,
+    /// On a failed overlay mount, bisect the layers and send only the
+    /// modules that break it to magic mount.
+    #[serde(default = "default_granular_fallback")]
+    pub granular_fallback: bool,
+    #[serde(default)]
     pub overlay_mode: OverlayMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_mode: Option<OverlayMode>,
     #[serde(default)]
     pub disable_umount: bool,
     #[serde(default)]
     pub allow_umount_coexistence: bool,
+    #[serde(default)]
+    pub susfs_hide_paths: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub denylist_provider: Option<DenylistProvider>,
     #[serde(default, alias = "granary")]
     pub backup: BackupConfig,
     #[serde(default = "default_hybrid_mnt_dir")]
@@ -103,6 +425,161 @@ pub struct Config {
     pub default_mode: DefaultMode,
     #[serde(default)]
     pub rules: HashMap<String, ModuleRules>,
+    #[serde(default)]
+    pub strict_atomic: bool,
+    #[serde(default)]
+    pub force_rebuild_image: bool,
+    #[serde(default)]
+    pub winnowing: WinnowingTable,
+    #[serde(default)]
+    pub stealth: StealthConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub daemon: bool,
+    #[serde(default)]
+    pub ext4_reserved_blocks_percent: Option<u8>,
+    /// Most module content tmpfs storage may hold, in MiB; 0 for no cap.
+    #[serde(default)]
+    pub tmpfs_max_mb: u64,
+    #[serde(default)]
+    pub tmpfs_overflow: TmpfsOverflow,
+    #[serde(default)]
+    pub selinux_audit: bool,
+    #[serde(default = "default_dedup_min_size")]
+    pub dedup_min_size: u64,
+    /// Check the storage copies against the sha256 manifest of the last
+    /// sync before mounting them.
+    #[serde(default)]
+    pub verify_storage: bool,
+    /// Time the check may take, in seconds; 0 for no limit.
+    #[serde(default = "default_verify_max_seconds")]
+    pub verify_max_seconds: u64,
+    /// Share of the files below `verify_full_above_kb` hashed per check.
+    #[serde(default = "default_verify_sample_percent")]
+    pub verify_sample_percent: u8,
+    /// Files of at least this many KiB are hashed on every check.
+    #[serde(default = "default_verify_full_above_kb")]
+    pub verify_full_above_kb: u64,
+    /// Render the boot summary to `last_summary.txt` besides the JSON.
+    #[serde(default)]
+    pub summary_text: bool,
+    /// Leave module files identical to the stock file out of the mount.
+    #[serde(default)]
+    pub skip_identical_files: bool,
+    #[serde(default)]
+    pub auto_shrink: bool,
+    #[serde(default, deserialize_with = "deserialize_partitions_flexible")]
+    pub rw_partitions: Vec<String>,
+    #[serde(default = "default_safe_mode_threshold")]
+    pub safe_mode_threshold: u32,
+    #[serde(default)]
+    pub safe_modules: Vec<String>,
+    #[serde(default)]
+    pub exclusions: Vec<String>,
+    /// Paths appended to `DENIED_MOUNT_TARGETS`.
+    #[serde(default)]
+    pub user_deny_paths: Vec<String>,
+    /// Globs of target paths no module file is mounted at; see
+    /// `core::ops::blacklist`.
+    #[serde(default)]
+    pub mount_blacklist: Vec<String>,
+    #[serde(default)]
+    pub use_last_good: LastGoodPolicy,
+    /// Profile merged over this config; see `conf::profile`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub boot_priority: BootPriority,
+    /// Bind module `apex/<name>/` files into active APEX mounts.
+    #[serde(default)]
+    pub allow_apex: bool,
+    /// Hold modules linking into credential-encrypted storage back until
+    /// `mount-deferred`; see `core::deferred`.
+    #[serde(default)]
+    pub defer_ce_modules: bool,
+    /// Worker pool size; unset picks the core count, capped at 4.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threads: Option<usize>,
+    #[serde(default)]
+    pub namespace_mode: NamespaceMode,
+    /// Record each mount syscall in `run/trace.bin`; see `utils::trace`.
+    #[serde(default)]
+    pub trace_mounts: bool,
+    #[serde(default = "default_mount_timeout_secs")]
+    pub mount_timeout_secs: u64,
+    #[serde(default = "default_mount_deadline_secs")]
+    pub mount_deadline_secs: u64,
+    /// How long storage setup waits for a read-only `/data` to become
+    /// writable before falling back to tmpfs.
+    #[serde(default = "default_data_rw_wait_secs")]
+    pub data_rw_wait_secs: u64,
+    #[serde(default)]
+    pub log_level: LogLevel,
+    /// Modules whose per-file lines are logged at debug level; those of
+    /// every other module are left out whatever `log_level` is.
+    #[serde(default)]
+    pub debug_modules: Vec<String>,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    #[serde(default = "default_log_max_size")]
+    pub log_max_size: u64,
+    #[serde(default = "default_log_max_files")]
+    pub log_max_files: usize,
+    #[serde(default = "default_log_buffer_kb")]
+    pub log_buffer_kb: usize,
+}
+
+fn default_schema_version() -> u32 {
+    CURRENT_SCHEMA_VERSION
+}
+
+fn default_granular_fallback() -> bool {
+    true
+}
+
+fn default_mount_timeout_secs() -> u64 {
+    20
+}
+
+fn default_mount_deadline_secs() -> u64 {
+    90
+}
+
+fn default_data_rw_wait_secs() -> u64 {
+    10
+}
+
+fn default_safe_mode_threshold() -> u32 {
+    3
+}
+
+fn default_dedup_min_size() -> u64 {
+    1024 * 1024
+}
+
+fn default_verify_max_seconds() -> u64 {
+    10
+}
+
+fn default_verify_sample_percent() -> u8 {
+    10
+}
+
+fn default_verify_full_above_kb() -> u64 {
+    1024
+}
+
+fn default_log_max_size() -> u64 {
+    1024 * 1024
+}
+
+fn default_log_max_files() -> usize {
+    3
+}
+
+fn default_log_buffer_kb() -> usize {
+    64
 }
 
 fn default_hybrid_mnt_dir() -> String {
@@ -117,7 +594,9 @@ fn default_mountsource() -> String {
     crate::sys::mount::detect_mount_source()
 }
 
-fn deserialize_partitions_flexible<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+pub(super) fn deserialize_partitions_flexible<'de, D>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
@@ -141,17 +620,69 @@ where
 impl Default for Config {
     fn default() -> Self {
         Self {
+            schema_version: default_schema_version(),
             moduledir: default_moduledir(),
+            image_modules: Vec::new(),
             mountsource: default_mountsource(),
             verbose: false,
             partitions: Vec::new(),
+            auto_partitions: false,
+            busy_file_policy: BusyFilePolicy::default(),
+            foreign_mount_policy: ForeignMountPolicy::default(),
+            prefer_bind_for_small_modules: false,
+            overlay_options: BTreeMap::new(),
+            granular_fallback: default_granular_fallback(),
             overlay_mode: OverlayMode::default(),
+            storage_mode: None,
             disable_umount: false,
             allow_umount_coexistence: false,
+            susfs_hide_paths: false,
+            denylist_provider: None,
             backup: BackupConfig::default(),
             hybrid_mnt_dir: default_hybrid_mnt_dir(),
             default_mode: DefaultMode::default(),
             rules: HashMap::new(),
+            strict_atomic: false,
+            force_rebuild_image: false,
+            winnowing: WinnowingTable::default(),
+            stealth: StealthConfig::default(),
+            storage: StorageConfig::default(),
+            daemon: false,
+            ext4_reserved_blocks_percent: None,
+            tmpfs_max_mb: 0,
+            tmpfs_overflow: TmpfsOverflow::default(),
+            selinux_audit: false,
+            dedup_min_size: default_dedup_min_size(),
+            verify_storage: false,
+            verify_max_seconds: default_verify_max_seconds(),
+            verify_sample_percent: default_verify_sample_percent(),
+            verify_full_above_kb: default_verify_full_above_kb(),
+            summary_text: false,
+            skip_identical_files: false,
+            auto_shrink: false,
+            rw_partitions: Vec::new(),
+            safe_mode_threshold: default_safe_mode_threshold(),
+            mount_timeout_secs: default_mount_timeout_secs(),
+            mount_deadline_secs: default_mount_deadline_secs(),
+            data_rw_wait_secs: default_data_rw_wait_secs(),
+            safe_modules: Vec::new(),
+            exclusions: Vec::new(),
+            user_deny_paths: Vec::new(),
+            mount_blacklist: Vec::new(),
+            use_last_good: LastGoodPolicy::default(),
+            profile: None,
+            boot_priority: BootPriority::default(),
+            allow_apex: false,
+            defer_ce_modules: false,
+            threads: None,
+            namespace_mode: NamespaceMode::default(),
+            trace_mounts: false,
+            log_level: LogLevel::default(),
+            debug_modules: Vec::new(),
+            log_format: LogFormat::default(),
+            log_max_size: default_log_max_size(),
+            log_max_files: default_log_max_files(),
+            log_buffer_kb: default_log_buffer_kb(),
         }
     }
 }
@@ -160,33 +691,114 @@ impl Config {
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
         let content = fs::read_to_string(path.as_ref()).context("failed to read config file")?;
 
-        let config: Config = toml::from_str(&content).context("failed to parse config file")?;
+        let (config, report) = Self::parse(&content)?;
+
+        if report.from_version < CURRENT_SCHEMA_VERSION {
+            log::info!(
+                ">> Migrated config from schema v{} to v{}",
+                report.from_version,
+                CURRENT_SCHEMA_VERSION
+            );
+        }
+        if !report.unknown_keys.is_empty() {
+            log::warn!(
+                "Ignoring unknown config keys: {}",
+                report.unknown_keys.join(", ")
+            );
+        }
 
         Ok(config)
     }
 
+    /// Parses `config.toml` contents, upgrading older schemas first.
+    pub fn parse(content: &str) -> Result<(Self, LoadReport)> {
+        let mut table: toml::Table =
+            toml::from_str(content).context("failed to parse config file")?;
+        let from_version = migrate::upgrade(&mut table);
+
+        let config: Config = toml::Value::Table(table.clone())
+            .try_into()
+            .context("failed to parse config file")?;
+        let unknown_keys = migrate::unknown_keys(&table, &config);
+
+        Ok((
+            config,
+            LoadReport {
+                from_version,
+                unknown_keys,
+            },
+        ))
+    }
+
+    /// overlayfs options for `partition`: its own entry merged over `"*"`.
+    pub fn overlay_options_for(&self, partition: &str) -> OverlayOptions {
+        let all = self.overlay_options.get("*").copied().unwrap_or_default();
+        match self.overlay_options.get(partition) {
+            Some(options) => options.merged_over(all),
+            None => all,
+        }
+    }
+
+    /// Storage backend to set up; `storage_mode` wins over `overlay_mode`.
+    pub fn effective_storage_mode(&self) -> OverlayMode {
+        self.storage_mode
+            .clone()
+            .unwrap_or_else(|| self.overlay_mode.clone())
+    }
+
+    /// Swaps `mountsource` and `hybrid_mnt_dir` for values generated for
+    /// this boot, as selected by `stealth`. The chosen values end up in the
+    /// runtime state so later commands can find the mounts again.
+    pub fn apply_stealth(&mut self) {
+        if self.stealth.randomize_mountsource {
+            self.mountsource = utils::random_mount_source();
+        }
+
+        if self.stealth.randomize_tempdir {
+            self.hybrid_mnt_dir = utils::random_mount_dir().to_string_lossy().to_string();
+        }
+    }
+
     pub fn load_default() -> Result<Self> {
         Self::from_file(defs::CONFIG_FILE)
     }
 
     pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        let content = toml::to_string_pretty(self).context("failed to serialize config")?;
+        let latest = Self {
+            schema_version: CURRENT_SCHEMA_VERSION,
+            ..self.clone()
+        };
+        let content = toml::to_string_pretty(&latest).context("failed to serialize config")?;
 
         if let Some(parent) = path.as_ref().parent() {
             fs::create_dir_all(parent).context("failed to create config directory")?;
         }
 
-        fs::write(path.as_ref(), content).context("failed to write config file")?;
+        utils::atomic_write(path.as_ref(), content).context("failed to write config file")?;
 
         Ok(())
     }
 
+    /// `log_level`, raised to debug by the deprecated `verbose`.
+    pub fn effective_log_level(&self) -> LogLevel {
+        if self.verbose {
+            self.log_level.max(LogLevel::Debug)
+        } else {
+            self.log_level
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn merge_with_cli(
         &mut self,
         moduledir: Option<PathBuf>,
         mountsource: Option<String>,
         verbose: bool,
+        debug_modules: Vec<String>,
         partitions: Vec<String>,
+        force_rebuild_image: bool,
+        daemon: bool,
+        use_last_good: bool,
     ) {
         if let Some(dir) = moduledir {
             self.moduledir = dir;
@@ -200,8 +812,26 @@ impl Config {
             self.verbose = true;
         }
 
+        for id in debug_modules {
+            if !self.debug_modules.contains(&id) {
+                self.debug_modules.push(id);
+            }
+        }
+
         if !partitions.is_empty() {
             self.partitions = partitions;
         }
+
+        if force_rebuild_image {
+            self.force_rebuild_image = true;
+        }
+
+        if daemon {
+            self.daemon = true;
+        }
+
+        if use_last_good {
+            self.use_last_good = LastGoodPolicy::Always;
+        }
     }
 }
@@ -0,0 +1,137 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Upgrades `config.toml` files written by older releases.
+//!
+//! A file without `schema_version` is version 1. Each migration rewrites the
+//! raw TOML table from version `n` to `n + 1`, so a file from any older
+//! release is brought up to [`CURRENT_SCHEMA_VERSION`] before it is
+//! deserialized into [`Config`].
+
+use toml::{Table, Value};
+
+use super::config::Config;
+
+pub const CURRENT_SCHEMA_VERSION: u32 = 2;
+
+/// Tables whose keys are fixed fields, checked one level down for unknown
+/// keys. Map-like tables such as `rules` accept any key.
+const STRUCT_TABLES: &[&str] = &["backup", "stealth", "storage", "winnowing"];
+
+/// What loading a config had to do to it.
+#[derive(Debug, Clone, Default)]
+pub struct LoadReport {
+    /// Schema version the file declared (1 when it had none).
+    pub from_version: u32,
+    /// Keys the current schema does not know, as dotted paths.
+    pub unknown_keys: Vec<String>,
+}
+
+type Migration = fn(&mut Table);
+
+/// `MIGRATIONS[n - 1]` upgrades version `n` to `n + 1`.
+const MIGRATIONS: &[Migration] = &[v1_to_v2];
+
+/// Version 1 spelled the storage backend as the `force_ext4` and `use_erofs`
+/// flags, kept module backups under `granary`, and stored winnowing rules
+/// directly in `[winnowing]` rather than in `[winnowing.rules]`.
+fn v1_to_v2(table: &mut Table) {
+    let force_ext4 = table.remove("force_ext4").and_then(|v| v.as_bool());
+    let use_erofs = table.remove("use_erofs").and_then(|v| v.as_bool());
+    if !table.contains_key("storage_mode") {
+        let mode = match (use_erofs, force_ext4) {
+            (Some(true), _) => Some("erofs"),
+            (_, Some(true)) => Some("ext4"),
+            _ => None,
+        };
+        if let Some(mode) = mode {
+            table.insert("storage_mode".into(), Value::String(mode.into()));
+        }
+    }
+
+    if let Some(granary) = table.remove("granary")
+        && !table.contains_key("backup")
+    {
+        table.insert("backup".into(), granary);
+    }
+
+    if let Some(Value::Table(winnowing)) = table.get_mut("winnowing") {
+        let flat: Vec<String> = winnowing
+            .iter()
+            .filter(|(key, value)| key.as_str() != "rules" && value.is_str())
+            .map(|(key, _)| key.clone())
+            .collect();
+        if !flat.is_empty() {
+            let mut rules = match winnowing.remove("rules") {
+                Some(Value::Table(rules)) => rules,
+                _ => Table::new(),
+            };
+            for key in flat {
+                if let Some(module) = winnowing.remove(&key) {
+                    rules.entry(key).or_insert(module);
+                }
+            }
+            winnowing.insert("rules".into(), Value::Table(rules));
+        }
+    }
+}
+
+/// Declared schema version of a raw config; 1 when the key is absent.
+pub fn schema_version(table: &Table) -> u32 {
+    table
+        .get("schema_version")
+        .and_then(Value::as_integer)
+        .and_then(|v| u32::try_from(v).ok())
+        .unwrap_or(1)
+}
+
+/// Applies every migration from the table's version up to the current one
+/// and stamps the result. Returns the version the table started at.
+pub fn upgrade(table: &mut Table) -> u32 {
+    let from = schema_version(table);
+    if from > CURRENT_SCHEMA_VERSION {
+        log::warn!(
+            "config.toml has schema version {}, newer than the supported {}; loading it as is",
+            from,
+            CURRENT_SCHEMA_VERSION
+        );
+        return from;
+    }
+
+    for migration in MIGRATIONS.iter().skip(from.saturating_sub(1) as usize) {
+        migration(table);
+    }
+    table.insert(
+        "schema_version".into(),
+        Value::Integer(CURRENT_SCHEMA_VERSION.into()),
+    );
+    from
+}
+
+/// Keys in `raw` that `config` did not take, as dotted paths. A key is
+/// unknown when serializing the parsed config does not produce it again.
+pub fn unknown_keys(raw: &Table, config: &Config) -> Vec<String> {
+    let Ok(parsed) = Table::try_from(config) else {
+        return Vec::new();
+    };
+
+    let mut unknown = Vec::new();
+    for (key, value) in raw {
+        let Some(known) = parsed.get(key) else {
+            unknown.push(key.clone());
+            continue;
+        };
+        if !STRUCT_TABLES.contains(&key.as_str()) {
+            continue;
+        }
+        if let (Value::Table(inner), Value::Table(known)) = (value, known) {
+            unknown.extend(
+                inner
+                    .keys()
+                    .filter(|k| !known.contains_key(*k))
+                    .map(|k| format!("{key}.{k}")),
+            );
+        }
+    }
+    unknown
+}
@@ -1,6 +1,10 @@
 // Copyright 2025 Meta-Hybrid Mount Authors
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+pub mod api;
+pub mod check;
 pub mod cli;
 pub mod cli_handlers;
 pub mod config;
+pub mod migrate;
+pub mod profile;
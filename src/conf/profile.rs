@@ -0,0 +1,428 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Named config profiles.
+//!
+//! `profiles/<name>.toml` next to `config.toml` holds only the keys a
+//! profile changes. The profile is picked by `--profile`, then the
+//! `persist.meta_hybrid.profile` property, then the `profile` key of the
+//! base config, and merged over the base field by field: scalars and lists
+//! present in the profile replace the base value, while `rules`,
+//! `overlay_options`, `winnowing.rules` and the `backup`, `stealth` and
+//! `storage` tables are merged per key.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use super::config::{
+    self, BootPriority, BusyFilePolicy, Config, DefaultMode, ForeignMountPolicy, LastGoodPolicy,
+    LogFormat, LogLevel, ModuleRules, NamespaceMode, OverlayMode, OverlayOptions, TmpfsOverflow,
+    WinnowingTable,
+};
+use crate::{sys::denylist::DenylistProvider, utils};
+
+/// Property that selects a profile when `--profile` is not given.
+pub const PROFILE_PROP: &str = "persist.meta_hybrid.profile";
+
+/// Directory next to the config file holding the profiles.
+pub const PROFILES_DIR: &str = "profiles";
+
+/// Where the applied profile name came from.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileSource {
+    Cli,
+    Property,
+    Config,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AppliedProfile {
+    pub name: String,
+    pub source: ProfileSource,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PartialBackupConfig {
+    pub max_backups: Option<usize>,
+    pub retention_days: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PartialStorageConfig {
+    pub image_overhead_mb: Option<u64>,
+    pub image_headroom_percent: Option<u64>,
+    pub loop_autoclear: Option<bool>,
+    pub loop_direct_io: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PartialStealthConfig {
+    pub randomize_mountsource: Option<bool>,
+    pub randomize_tempdir: Option<bool>,
+}
+
+/// A profile file: every `Config` key, all optional. Unknown keys are an
+/// error so a typo does not silently leave the base value in place.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PartialConfig {
+    pub moduledir: Option<PathBuf>,
+    pub image_modules: Option<Vec<PathBuf>>,
+    pub mountsource: Option<String>,
+    pub verbose: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_partitions_opt")]
+    pub partitions: Option<Vec<String>>,
+    pub auto_partitions: Option<bool>,
+    pub busy_file_policy: Option<BusyFilePolicy>,
+    pub foreign_mount_policy: Option<ForeignMountPolicy>,
+    pub prefer_bind_for_small_modules: Option<bool>,
+    pub overlay_options: Option<BTreeMap<String, OverlayOptions>>,
+    pub granular_fallback: Option<bool>,
+    pub overlay_mode: Option<OverlayMode>,
+    pub storage_mode: Option<OverlayMode>,
+    pub disable_umount: Option<bool>,
+    pub allow_umount_coexistence: Option<bool>,
+    pub susfs_hide_paths: Option<bool>,
+    pub denylist_provider: Option<DenylistProvider>,
+    pub backup: Option<PartialBackupConfig>,
+    pub hybrid_mnt_dir: Option<String>,
+    pub default_mode: Option<DefaultMode>,
+    pub rules: Option<HashMap<String, ModuleRules>>,
+    pub strict_atomic: Option<bool>,
+    pub force_rebuild_image: Option<bool>,
+    pub winnowing: Option<WinnowingTable>,
+    pub stealth: Option<PartialStealthConfig>,
+    pub storage: Option<PartialStorageConfig>,
+    pub daemon: Option<bool>,
+    pub ext4_reserved_blocks_percent: Option<u8>,
+    pub tmpfs_max_mb: Option<u64>,
+    pub tmpfs_overflow: Option<TmpfsOverflow>,
+    pub selinux_audit: Option<bool>,
+    pub dedup_min_size: Option<u64>,
+    pub verify_storage: Option<bool>,
+    pub verify_max_seconds: Option<u64>,
+    pub verify_sample_percent: Option<u8>,
+    pub verify_full_above_kb: Option<u64>,
+    pub summary_text: Option<bool>,
+    pub skip_identical_files: Option<bool>,
+    pub auto_shrink: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_partitions_opt")]
+    pub rw_partitions: Option<Vec<String>>,
+    pub safe_mode_threshold: Option<u32>,
+    pub safe_modules: Option<Vec<String>>,
+    pub exclusions: Option<Vec<String>>,
+    pub user_deny_paths: Option<Vec<String>>,
+    pub mount_blacklist: Option<Vec<String>>,
+    pub use_last_good: Option<LastGoodPolicy>,
+    pub boot_priority: Option<BootPriority>,
+    pub allow_apex: Option<bool>,
+    pub defer_ce_modules: Option<bool>,
+    pub threads: Option<usize>,
+    pub namespace_mode: Option<NamespaceMode>,
+    pub trace_mounts: Option<bool>,
+    pub mount_timeout_secs: Option<u64>,
+    pub mount_deadline_secs: Option<u64>,
+    pub data_rw_wait_secs: Option<u64>,
+    pub log_level: Option<LogLevel>,
+    pub debug_modules: Option<Vec<String>>,
+    pub log_format: Option<LogFormat>,
+    pub log_max_size: Option<u64>,
+    pub log_max_files: Option<usize>,
+    pub log_buffer_kb: Option<usize>,
+}
+
+fn deserialize_partitions_opt<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    config::deserialize_partitions_flexible(deserializer).map(Some)
+}
+
+impl PartialConfig {
+    pub fn parse(content: &str) -> Result<Self> {
+        toml::from_str(content).context("failed to parse profile")
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read profile {}", path.display()))?;
+        Self::parse(&content).with_context(|| format!("invalid profile {}", path.display()))
+    }
+
+    /// Writes every value present in the profile over `config`.
+    pub fn merge_into(self, config: &mut Config) {
+        if let Some(v) = self.moduledir {
+            config.moduledir = v;
+        }
+        if let Some(v) = self.image_modules {
+            config.image_modules = v;
+        }
+        if let Some(v) = self.mountsource {
+            config.mountsource = v;
+        }
+        if let Some(v) = self.verbose {
+            config.verbose = v;
+        }
+        if let Some(v) = self.partitions {
+            config.partitions = v;
+        }
+        if let Some(v) = self.auto_partitions {
+            config.auto_partitions = v;
+        }
+        if let Some(v) = self.busy_file_policy {
+            config.busy_file_policy = v;
+        }
+        if let Some(v) = self.foreign_mount_policy {
+            config.foreign_mount_policy = v;
+        }
+        if let Some(v) = self.prefer_bind_for_small_modules {
+            config.prefer_bind_for_small_modules = v;
+        }
+        if let Some(options) = self.overlay_options {
+            config.overlay_options.extend(options);
+        }
+        if let Some(v) = self.granular_fallback {
+            config.granular_fallback = v;
+        }
+        if let Some(v) = self.overlay_mode {
+            config.overlay_mode = v;
+        }
+        if let Some(v) = self.storage_mode {
+            config.storage_mode = Some(v);
+        }
+        if let Some(v) = self.disable_umount {
+            config.disable_umount = v;
+        }
+        if let Some(v) = self.allow_umount_coexistence {
+            config.allow_umount_coexistence = v;
+        }
+        if let Some(v) = self.susfs_hide_paths {
+            config.susfs_hide_paths = v;
+        }
+        if let Some(v) = self.denylist_provider {
+            config.denylist_provider = Some(v);
+        }
+        if let Some(backup) = self.backup {
+            if let Some(v) = backup.max_backups {
+                config.backup.max_backups = v;
+            }
+            if let Some(v) = backup.retention_days {
+                config.backup.retention_days = v;
+            }
+        }
+        if let Some(v) = self.hybrid_mnt_dir {
+            config.hybrid_mnt_dir = v;
+        }
+        if let Some(v) = self.default_mode {
+            config.default_mode = v;
+        }
+        if let Some(rules) = self.rules {
+            config.rules.extend(rules);
+        }
+        if let Some(v) = self.strict_atomic {
+            config.strict_atomic = v;
+        }
+        if let Some(v) = self.force_rebuild_image {
+            config.force_rebuild_image = v;
+        }
+        if let Some(winnowing) = self.winnowing {
+            config.winnowing.rules.extend(winnowing.rules);
+        }
+        if let Some(stealth) = self.stealth {
+            if let Some(v) = stealth.randomize_mountsource {
+                config.stealth.randomize_mountsource = v;
+            }
+            if let Some(v) = stealth.randomize_tempdir {
+                config.stealth.randomize_tempdir = v;
+            }
+        }
+        if let Some(storage) = self.storage {
+            if let Some(v) = storage.image_overhead_mb {
+                config.storage.image_overhead_mb = v;
+            }
+            if let Some(v) = storage.image_headroom_percent {
+                config.storage.image_headroom_percent = v;
+            }
+            if let Some(v) = storage.loop_autoclear {
+                config.storage.loop_autoclear = v;
+            }
+            if let Some(v) = storage.loop_direct_io {
+                config.storage.loop_direct_io = v;
+            }
+        }
+        if let Some(v) = self.daemon {
+            config.daemon = v;
+        }
+        if let Some(v) = self.ext4_reserved_blocks_percent {
+            config.ext4_reserved_blocks_percent = Some(v);
+        }
+        if let Some(v) = self.tmpfs_max_mb {
+            config.tmpfs_max_mb = v;
+        }
+        if let Some(v) = self.tmpfs_overflow {
+            config.tmpfs_overflow = v;
+        }
+        if let Some(v) = self.selinux_audit {
+            config.selinux_audit = v;
+        }
+        if let Some(v) = self.dedup_min_size {
+            config.dedup_min_size = v;
+        }
+        if let Some(v) = self.verify_storage {
+            config.verify_storage = v;
+        }
+        if let Some(v) = self.verify_max_seconds {
+            config.verify_max_seconds = v;
+        }
+        if let Some(v) = self.verify_sample_percent {
+            config.verify_sample_percent = v;
+        }
+        if let Some(v) = self.verify_full_above_kb {
+            config.verify_full_above_kb = v;
+        }
+        if let Some(v) = self.summary_text {
+            config.summary_text = v;
+        }
+        if let Some(v) = self.skip_identical_files {
+            config.skip_identical_files = v;
+        }
+        if let Some(v) = self.auto_shrink {
+            config.auto_shrink = v;
+        }
+        if let Some(v) = self.rw_partitions {
+            config.rw_partitions = v;
+        }
+        if let Some(v) = self.safe_mode_threshold {
+            config.safe_mode_threshold = v;
+        }
+        if let Some(v) = self.safe_modules {
+            config.safe_modules = v;
+        }
+        if let Some(v) = self.exclusions {
+            config.exclusions = v;
+        }
+        if let Some(v) = self.user_deny_paths {
+            config.user_deny_paths = v;
+        }
+        if let Some(v) = self.mount_blacklist {
+            config.mount_blacklist = v;
+        }
+        if let Some(v) = self.use_last_good {
+            config.use_last_good = v;
+        }
+        if let Some(v) = self.boot_priority {
+            config.boot_priority = v;
+        }
+        if let Some(v) = self.allow_apex {
+            config.allow_apex = v;
+        }
+        if let Some(v) = self.defer_ce_modules {
+            config.defer_ce_modules = v;
+        }
+        if let Some(v) = self.threads {
+            config.threads = Some(v);
+        }
+        if let Some(v) = self.namespace_mode {
+            config.namespace_mode = v;
+        }
+        if let Some(v) = self.trace_mounts {
+            config.trace_mounts = v;
+        }
+        if let Some(v) = self.mount_timeout_secs {
+            config.mount_timeout_secs = v;
+        }
+        if let Some(v) = self.mount_deadline_secs {
+            config.mount_deadline_secs = v;
+        }
+        if let Some(v) = self.data_rw_wait_secs {
+            config.data_rw_wait_secs = v;
+        }
+        if let Some(v) = self.log_level {
+            config.log_level = v;
+        }
+        if let Some(v) = self.debug_modules {
+            config.debug_modules = v;
+        }
+        if let Some(v) = self.log_format {
+            config.log_format = v;
+        }
+        if let Some(v) = self.log_max_size {
+            config.log_max_size = v;
+        }
+        if let Some(v) = self.log_max_files {
+            config.log_max_files = v;
+        }
+        if let Some(v) = self.log_buffer_kb {
+            config.log_buffer_kb = v;
+        }
+    }
+}
+
+/// The profiles directory for the config file at `config_path`.
+pub fn profiles_dir(config_path: &Path) -> PathBuf {
+    config_path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join(PROFILES_DIR)
+}
+
+/// Picks the profile by precedence: `cli`, then `property`, then the
+/// `profile` key of `config`. Empty values count as unset.
+pub fn select(
+    cli: Option<&str>,
+    property: Option<&str>,
+    config: &Config,
+) -> Option<(String, ProfileSource)> {
+    [
+        (cli, ProfileSource::Cli),
+        (property, ProfileSource::Property),
+        (config.profile.as_deref(), ProfileSource::Config),
+    ]
+    .into_iter()
+    .find_map(|(name, source)| {
+        name.filter(|n| !n.is_empty())
+            .map(|n| (n.to_string(), source))
+    })
+}
+
+/// Merges the profile `name` from `dir` over `config` and records it in
+/// `config.profile`.
+pub fn apply(config: &mut Config, dir: &Path, name: &str) -> Result<PathBuf> {
+    if name.contains('/') || name.starts_with('.') {
+        bail!("invalid profile name '{}'", name);
+    }
+
+    let path = dir.join(format!("{}.toml", name));
+    PartialConfig::from_file(&path)?.merge_into(config);
+    config.profile = Some(name.to_string());
+    Ok(path)
+}
+
+/// Selects and applies the profile for the config loaded from
+/// `config_path`. Afterwards `config.profile` names the applied profile, or
+/// is `None` when none was selected. On error `config` is left unmerged.
+pub fn resolve(
+    config: &mut Config,
+    config_path: &Path,
+    cli: Option<&str>,
+) -> Result<Option<AppliedProfile>> {
+    let property = utils::getprop(PROFILE_PROP);
+    let Some((name, source)) = select(cli, property.as_deref(), config) else {
+        config.profile = None;
+        return Ok(None);
+    };
+
+    let path = apply(config, &profiles_dir(config_path), &name)?;
+    Ok(Some(AppliedProfile { name, source, path }))
+}
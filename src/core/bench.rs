@@ -0,0 +1,145 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! `meta-hybrid bench`: how long each pipeline stage takes on this device.
+//!
+//! The dry stages run exactly as for `diagnostics`. With `--mount` the
+//! overlay and bind operations of the plan are mounted as well, but onto a
+//! scratch tree on a tmpfs, inside a private mount namespace `main` enters
+//! before anything else; an empty directory stands in for each stock
+//! partition. Magic mount and HymoFS work on the live tree and are left out.
+
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use rustix::mount::{MountFlags, UnmountFlags, mount, unmount};
+use serde::Serialize;
+
+use crate::{
+    conf::config::OverlayOptions,
+    core::ops::planner::MountPlan,
+    mount::{bind, overlayfs::overlayfs},
+    utils::{self, timing},
+};
+
+/// Scratch directory of the sandboxed mounts inside the run directory.
+pub const SCRATCH_DIR_NAME: &str = "bench";
+/// Empty directory standing in for the stock partitions.
+const STOCK_DIR_NAME: &str = "stock";
+const TARGETS_DIR_NAME: &str = "targets";
+
+#[derive(Debug, Serialize)]
+pub struct BenchReport {
+    pub iterations: usize,
+    /// Modules the inventory returned on the last run.
+    pub modules: usize,
+    pub stages: Vec<timing::StageStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<SandboxReport>,
+}
+
+/// Outcome of the last sandboxed mount run.
+#[derive(Debug, Default, Serialize)]
+pub struct SandboxReport {
+    pub mounted: usize,
+    /// Targets that failed to mount, with the error.
+    pub failed: Vec<String>,
+    /// Magic mount and HymoFS modules, which are not benchmarked.
+    pub skipped_modules: usize,
+}
+
+/// Where `target` lands inside `targets`, or `None` if it would leave it.
+fn sandboxed(targets: &Path, target: &Path) -> Option<PathBuf> {
+    let relative = target.strip_prefix("/").unwrap_or(target);
+    if relative
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return None;
+    }
+    Some(targets.join(relative))
+}
+
+/// Mounts the overlay and bind operations of `plan` under `scratch` and
+/// takes them down again. Only call it from a private mount namespace, see
+/// [`crate::sys::namespace::enter_private`].
+pub fn mount_sandboxed(
+    plan: &MountPlan,
+    scratch: &Path,
+    mount_source: &str,
+) -> Result<SandboxReport> {
+    utils::ensure_dir_exists(scratch)?;
+    mount(mount_source, scratch, "tmpfs", MountFlags::empty(), None).with_context(|| {
+        format!(
+            "Failed to mount the bench scratch tmpfs on {}",
+            scratch.display()
+        )
+    })?;
+
+    let result = mount_all(plan, scratch, mount_source);
+    let _ = unmount(scratch, UnmountFlags::DETACH);
+    result
+}
+
+fn mount_all(plan: &MountPlan, scratch: &Path, mount_source: &str) -> Result<SandboxReport> {
+    let mut span = timing::span("mount");
+    let stock = scratch.join(STOCK_DIR_NAME);
+    let targets = scratch.join(TARGETS_DIR_NAME);
+    fs::create_dir_all(&stock)?;
+    fs::create_dir_all(&targets)?;
+    let stock = stock.to_string_lossy().to_string();
+
+    let mut report = SandboxReport {
+        skipped_modules: plan.magic_module_ids.len() + plan.hymo_module_ids.len(),
+        ..Default::default()
+    };
+
+    for op in &plan.overlay_ops {
+        let Some(dest) = sandboxed(&targets, Path::new(&op.target)) else {
+            bail!("Overlay target {} leaves the bench scratch tree", op.target);
+        };
+        fs::create_dir_all(&dest)?;
+        let lowerdirs: Vec<String> = op
+            .lowerdirs
+            .iter()
+            .map(|p| p.to_string_lossy().to_string())
+            .collect();
+        match overlayfs::mount_overlayfs(
+            &lowerdirs,
+            &stock,
+            None,
+            None,
+            &dest,
+            mount_source,
+            &OverlayOptions::default(),
+        ) {
+            Ok(_) => report.mounted += 1,
+            Err(e) => report.failed.push(format!("{}: {:#}", op.target, e)),
+        }
+    }
+
+    for op in &plan.bind_ops {
+        let Some(dest) = sandboxed(&targets, &op.target) else {
+            bail!(
+                "Bind target {} leaves the bench scratch tree",
+                op.target.display()
+            );
+        };
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&dest, b"")?;
+        match bind::bind_file(&op.source, &dest, false) {
+            Ok(()) => report.mounted += 1,
+            Err(e) => report
+                .failed
+                .push(format!("{}: {:#}", op.target.display(), e)),
+        }
+    }
+
+    span.items(report.mounted);
+    Ok(report)
+}
@@ -0,0 +1,37 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{fs, path::Path};
+
+use anyhow::Result;
+
+use crate::utils;
+
+const ATTEMPTS_FILE_NAME: &str = "mount_attempts";
+
+/// Records that a mount sequence is starting and returns how many earlier
+/// attempts never reached finalize.
+pub fn begin_attempt(run_dir: &Path) -> Result<u32> {
+    let path = run_dir.join(ATTEMPTS_FILE_NAME);
+
+    let unfinished = fs::read_to_string(&path)
+        .ok()
+        .and_then(|content| content.trim().parse::<u32>().ok())
+        .unwrap_or(0);
+
+    utils::ensure_dir_exists(run_dir)?;
+    utils::atomic_write(&path, (unfinished + 1).to_string())?;
+
+    Ok(unfinished)
+}
+
+/// Marks the current attempt as completed.
+pub fn finish_attempt(run_dir: &Path) {
+    let path = run_dir.join(ATTEMPTS_FILE_NAME);
+
+    if let Err(e) = fs::remove_file(&path)
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        log::warn!("Failed to clear mount attempt marker: {}", e);
+    }
+}
@@ -0,0 +1,311 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Module bundles: squashfs or erofs images listed in `image_modules`, each
+//! holding module directories at its top level.
+//!
+//! While storage is set up every image is loop-mounted read-only at
+//! `<bundles_dir>/<name>`, `name` being its file name without the extension,
+//! and stays mounted until the storage is torn down. The scanner reads the
+//! directories below as modules with id `<name>.<dir>`, so they are synced,
+//! planned and reported like any other and a conflict names the bundle. An
+//! image that is missing, not recognised or fails to mount is skipped with a
+//! warning and never fails the boot.
+
+use std::{
+    collections::HashSet,
+    ffi::CStr,
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use rustix::mount::{
+    MountFlags, MountPropagationFlags, UnmountFlags, mount, mount_change, unmount,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    core::ops::planner::{DiagnosticIssue, DiagnosticLevel},
+    mount::umount_mgr,
+    sys::{
+        loopdev::{self, LoopOptions},
+        mount::is_mounted,
+    },
+    utils::{self, ensure_dir_exists},
+};
+
+const SQUASHFS_MAGIC: [u8; 4] = *b"hsqs";
+/// `EROFS_SUPER_MAGIC_V1`, little-endian, at the start of the superblock.
+const EROFS_MAGIC: [u8; 4] = 0xE0F5_E1E2_u32.to_le_bytes();
+const EROFS_SUPER_OFFSET: u64 = 1024;
+
+/// Filesystem of a bundle image, told apart by its superblock magic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageKind {
+    Squashfs,
+    Erofs,
+}
+
+impl ImageKind {
+    /// Reads the magic of `image`; `None` when it is neither filesystem.
+    pub fn detect(image: &Path) -> Result<Option<Self>> {
+        let mut file =
+            File::open(image).with_context(|| format!("Failed to open {}", image.display()))?;
+        let mut magic = [0; 4];
+
+        if file.read_exact(&mut magic).is_ok() && magic == SQUASHFS_MAGIC {
+            return Ok(Some(Self::Squashfs));
+        }
+        if file.seek(SeekFrom::Start(EROFS_SUPER_OFFSET)).is_ok()
+            && file.read_exact(&mut magic).is_ok()
+            && magic == EROFS_MAGIC
+        {
+            return Ok(Some(Self::Erofs));
+        }
+        Ok(None)
+    }
+
+    fn fstype(self) -> &'static CStr {
+        match self {
+            Self::Squashfs => c"squashfs",
+            Self::Erofs => c"erofs",
+        }
+    }
+}
+
+/// A mounted bundle image.
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    pub name: String,
+    pub image: PathBuf,
+    pub mount_point: PathBuf,
+    pub loop_device: u32,
+    /// The kernel frees the loop device once the bundle is unmounted.
+    pub autoclear: bool,
+}
+
+/// The bundle name of `image`: its file name without the extension, if
+/// that is a valid module id.
+pub fn name(image: &Path) -> Option<String> {
+    let stem = image.file_stem()?.to_str()?;
+    utils::validate_module_id(stem).ok()?;
+    Some(stem.to_string())
+}
+
+/// Id of the module in directory `dir` of bundle `bundle`.
+pub fn module_id(bundle: &str, dir: &str) -> String {
+    format!("{}.{}", bundle, dir)
+}
+
+/// Bundle names in `images`, in order, with the image each is read from.
+/// Images with no valid name, or the name of an earlier one, are dropped.
+fn named(images: &[PathBuf]) -> Vec<(String, &Path)> {
+    let mut seen = HashSet::new();
+    images
+        .iter()
+        .filter_map(|image| Some((name(image)?, image.as_path())))
+        .filter(|(name, _)| seen.insert(name.clone()))
+        .collect()
+}
+
+/// Checks that `image` can be mounted as a bundle.
+fn check(image: &Path) -> Result<ImageKind> {
+    if name(image).is_none() {
+        bail!(
+            "{} has no usable bundle name; the file name without its extension must be a valid module id",
+            image.display()
+        );
+    }
+    if !image.is_file() {
+        bail!("{} does not exist", image.display());
+    }
+    match ImageKind::detect(image)? {
+        Some(kind) => Ok(kind),
+        None => bail!(
+            "{} is neither a squashfs nor an erofs image",
+            image.display()
+        ),
+    }
+}
+
+fn mount_one(
+    name: &str,
+    image: &Path,
+    mount_point: &Path,
+    kind: ImageKind,
+    options: &LoopOptions,
+) -> Result<Bundle> {
+    if is_mounted(mount_point) {
+        unmount(mount_point, UnmountFlags::DETACH)
+            .with_context(|| format!("Failed to unmount stale {}", mount_point.display()))?;
+    }
+    ensure_dir_exists(mount_point)?;
+
+    let options = LoopOptions {
+        read_only: true,
+        ..options.clone()
+    };
+    let device = loopdev::loop_attach(image, &options)?;
+    let mounted = device
+        .node()
+        .with_context(|| format!("No device node for {}", device.name()))
+        .and_then(|node| {
+            mount(
+                &node,
+                mount_point,
+                kind.fstype(),
+                MountFlags::RDONLY | MountFlags::NODEV | MountFlags::NOSUID | MountFlags::NOATIME,
+                None,
+            )
+            .with_context(|| {
+                format!(
+                    "Failed to mount {} ({}) on {}",
+                    node.display(),
+                    image.display(),
+                    mount_point.display()
+                )
+            })
+        });
+    if let Err(e) = mounted {
+        if let Err(detach) = loopdev::detach_number(device.number) {
+            log::warn!("{:#}", detach);
+        }
+        return Err(e);
+    }
+
+    Ok(Bundle {
+        name: name.to_string(),
+        image: image.to_path_buf(),
+        mount_point: mount_point.to_path_buf(),
+        loop_device: device.number,
+        autoclear: options.autoclear,
+    })
+}
+
+/// Mounts every image in `images` under `bundles_dir` and returns those
+/// that mounted. Mounts are private and, with `hide`, queued for
+/// try_umount.
+pub fn mount_all(
+    images: &[PathBuf],
+    bundles_dir: &Path,
+    options: &LoopOptions,
+    hide: bool,
+) -> Vec<Bundle> {
+    let mut seen = HashSet::new();
+    let mut mounted = Vec::new();
+    for image in images {
+        let kind = match check(image) {
+            Ok(kind) => kind,
+            Err(e) => {
+                log::warn!("Skipping module image: {:#}", e);
+                continue;
+            }
+        };
+        let Some(name) = name(image).filter(|name| seen.insert(name.clone())) else {
+            log::warn!(
+                "Skipping module image {}: an earlier image has the same name",
+                image.display()
+            );
+            continue;
+        };
+        let mount_point = bundles_dir.join(&name);
+        match mount_one(&name, image, &mount_point, kind, options) {
+            Ok(bundle) => {
+                if let Err(e) = mount_change(&mount_point, MountPropagationFlags::PRIVATE) {
+                    log::warn!("Failed to make bundle {} private: {}", name, e);
+                }
+                if hide {
+                    let _ = umount_mgr::send_umountable(&mount_point);
+                }
+                log::info!(
+                    "Bundle {} mounted from {} ({:?})",
+                    name,
+                    image.display(),
+                    kind
+                );
+                mounted.push(bundle);
+            }
+            Err(e) => log::warn!("Skipping module image {}: {:#}", image.display(), e),
+        }
+    }
+    mounted
+}
+
+/// Unmounts `bundles` and detaches their loop devices. An autoclear device
+/// is freed by the unmount and may already be bound again by someone else,
+/// so it is left alone.
+pub fn unmount_all(bundles: &mut Vec<Bundle>) {
+    for bundle in bundles.drain(..) {
+        if is_mounted(&bundle.mount_point)
+            && let Err(e) = unmount(&bundle.mount_point, UnmountFlags::DETACH)
+        {
+            log::warn!(
+                "Failed to unmount bundle {}: {}",
+                bundle.mount_point.display(),
+                e
+            );
+        }
+        if !bundle.autoclear
+            && let Err(e) = loopdev::detach_number(bundle.loop_device)
+        {
+            log::warn!("{:#}", e);
+        }
+    }
+}
+
+/// Where the scanner looks for the modules of each bundle in `images`, in
+/// order. A bundle whose image did not mount has an empty directory.
+pub fn sources(images: &[PathBuf], bundles_dir: &Path) -> Vec<(String, PathBuf)> {
+    named(images)
+        .into_iter()
+        .map(|(name, _)| {
+            let dir = bundles_dir.join(&name);
+            (name, dir)
+        })
+        .collect()
+}
+
+/// Digest of the mounted images, so an image built from the storage goes
+/// stale when a bundle changes.
+pub fn fingerprint(bundles: &[Bundle]) -> String {
+    let mut hasher = Sha256::new();
+    for bundle in bundles {
+        hasher.update(bundle.name.as_bytes());
+        if let Ok(meta) = fs::metadata(&bundle.image) {
+            hasher.update(meta.len().to_le_bytes());
+            hasher.update(meta.mtime().to_le_bytes());
+            hasher.update(meta.ino().to_le_bytes());
+        }
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Warns about each image in `images` that cannot be mounted as a bundle.
+pub fn diagnose(images: &[PathBuf]) -> Vec<DiagnosticIssue> {
+    let mut seen = HashSet::new();
+    images
+        .iter()
+        .filter_map(|image| {
+            let message = match check(image) {
+                Err(e) => format!("{:#}; it is skipped", e),
+                Ok(_) if !seen.insert(name(image)?) => format!(
+                    "{} has the same bundle name as an earlier image; it is skipped",
+                    image.display()
+                ),
+                Ok(_) => return None,
+            };
+            Some(DiagnosticIssue {
+                level: DiagnosticLevel::Warning,
+                context: "Module Images".to_string(),
+                message,
+            })
+        })
+        .collect()
+}
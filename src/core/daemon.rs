@@ -0,0 +1,335 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    ffi::OsString,
+    fs,
+    mem::MaybeUninit,
+    os::{fd::OwnedFd, unix::process::CommandExt},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use rustix::{
+    event::{PollFd, PollFlags, Timespec, poll},
+    fs::inotify::{self, CreateFlags, ReadFlags, WatchFlags},
+    io::Errno,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    conf::config::Config,
+    core::{inventory, ops::planner, state::RuntimeState},
+    defs, utils,
+};
+
+const DEBOUNCE: Duration = Duration::from_millis(1500);
+
+/// Files inside a module directory whose changes affect the mount plan.
+const WATCHED_FLAGS: &[&str] = &[
+    defs::DISABLE_FILE_NAME,
+    defs::REMOVE_FILE_NAME,
+    defs::SKIP_MOUNT_FILE_NAME,
+    defs::MOUNT_MODE_FILE_NAME,
+    "module.prop",
+];
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PendingChanges {
+    pub generated_at: u64,
+    pub changed: usize,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub mode_changed: Vec<String>,
+}
+
+/// Re-launches the binary detached from the boot script so the mount
+/// sequence can report completion while the watcher keeps running.
+pub fn spawn() -> Result<()> {
+    let exe = std::env::current_exe().context("Failed to resolve current executable")?;
+
+    let mut args: Vec<OsString> = std::env::args_os().skip(1).collect();
+    args.push("daemon".into());
+
+    let child = Command::new(exe)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()
+        .context("Failed to spawn watcher daemon")?;
+
+    log::info!(">> Daemon mode: watcher started (pid {})", child.id());
+
+    Ok(())
+}
+
+struct Watcher {
+    fd: OwnedFd,
+    moduledir: PathBuf,
+    root_wd: i32,
+    module_wds: HashMap<i32, String>,
+}
+
+impl Watcher {
+    fn new(moduledir: &Path) -> Result<Self> {
+        let fd = inotify::init(CreateFlags::CLOEXEC | CreateFlags::NONBLOCK)
+            .context("Failed to initialize inotify")?;
+
+        let root_wd = inotify::add_watch(
+            &fd,
+            moduledir,
+            WatchFlags::CREATE | WatchFlags::DELETE | WatchFlags::MOVED_FROM | WatchFlags::MOVED_TO,
+        )
+        .with_context(|| format!("Failed to watch {}", moduledir.display()))?;
+
+        let mut watcher = Self {
+            fd,
+            moduledir: moduledir.to_path_buf(),
+            root_wd,
+            module_wds: HashMap::new(),
+        };
+
+        for entry in fs::read_dir(moduledir)?.flatten() {
+            if entry.path().is_dir() {
+                watcher.watch_module(&entry.file_name().to_string_lossy());
+            }
+        }
+
+        Ok(watcher)
+    }
+
+    fn watch_module(&mut self, id: &str) {
+        let path = self.moduledir.join(id);
+
+        match inotify::add_watch(
+            &self.fd,
+            &path,
+            WatchFlags::CREATE
+                | WatchFlags::DELETE
+                | WatchFlags::MOVED_FROM
+                | WatchFlags::MOVED_TO
+                | WatchFlags::CLOSE_WRITE
+                | WatchFlags::DELETE_SELF,
+        ) {
+            Ok(wd) => {
+                self.module_wds.insert(wd, id.to_string());
+            }
+            Err(e) => log::debug!("Failed to watch {}: {}", path.display(), e),
+        }
+    }
+
+    fn wait(&self, timeout: Option<Duration>) -> Result<bool> {
+        let timespec = timeout.map(|t| Timespec {
+            tv_sec: t.as_secs() as _,
+            tv_nsec: t.subsec_nanos() as _,
+        });
+
+        let mut fds = [PollFd::new(&self.fd, PollFlags::IN)];
+
+        loop {
+            match poll(&mut fds, timespec.as_ref()) {
+                Ok(n) => return Ok(n > 0),
+                Err(Errno::INTR) => continue,
+                Err(e) => return Err(e).context("Failed to poll inotify descriptor"),
+            }
+        }
+    }
+
+    /// Consumes all queued events and reports whether any of them is relevant.
+    /// When the queue overflowed, events were lost: that counts as a change,
+    /// and every module directory is watched again.
+    fn drain(&mut self) -> Result<bool> {
+        let mut buf = [MaybeUninit::<u8>::uninit(); 4096];
+        let mut relevant = false;
+        let mut overflowed = false;
+        let mut new_modules = Vec::new();
+
+        {
+            let mut reader = inotify::Reader::new(&self.fd, &mut buf);
+
+            loop {
+                let event = match reader.next() {
+                    Ok(event) => event,
+                    Err(Errno::AGAIN) => break,
+                    Err(Errno::INTR) => continue,
+                    Err(e) => return Err(e).context("Failed to read inotify events"),
+                };
+
+                if event.events().contains(ReadFlags::Q_OVERFLOW) {
+                    log::warn!("inotify queue overflowed, rescanning all modules");
+                    overflowed = true;
+                    relevant = true;
+                    continue;
+                }
+                // The directory is gone and the kernel drops its watch.
+                if event
+                    .events()
+                    .intersects(ReadFlags::IGNORED | ReadFlags::DELETE_SELF)
+                {
+                    if let Some(id) = self.module_wds.remove(&event.wd()) {
+                        log::debug!("Module {} is no longer watched", id);
+                    }
+                    continue;
+                }
+
+                let name = event
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default();
+
+                if event.wd() == self.root_wd {
+                    if !event.events().contains(ReadFlags::ISDIR) {
+                        continue;
+                    }
+                    if event
+                        .events()
+                        .intersects(ReadFlags::CREATE | ReadFlags::MOVED_TO)
+                    {
+                        new_modules.push(name.clone());
+                    }
+                    log::debug!("Module directory changed: {}", name);
+                    relevant = true;
+                } else if let Some(id) = self.module_wds.get(&event.wd())
+                    && WATCHED_FLAGS.contains(&name.as_str())
+                {
+                    log::debug!("Module {} flag changed: {}", id, name);
+                    relevant = true;
+                }
+            }
+        }
+
+        if overflowed {
+            match fs::read_dir(&self.moduledir) {
+                Ok(entries) => {
+                    new_modules = entries
+                        .flatten()
+                        .filter(|entry| entry.path().is_dir())
+                        .map(|entry| entry.file_name().to_string_lossy().to_string())
+                        .collect()
+                }
+                Err(e) => log::warn!("Failed to read {}: {}", self.moduledir.display(), e),
+            }
+        }
+        for id in new_modules {
+            self.watch_module(&id);
+        }
+
+        Ok(relevant)
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Plans against the live module directory without touching any mounts and
+/// compares the result with what was mounted at boot.
+fn compute_pending(config: &Config, state: &RuntimeState) -> Result<PendingChanges> {
+    let modules = inventory::scan(&config.moduledir, config)?;
+    let plan = planner::generate(config, &modules, &config.moduledir)?;
+
+    let old_overlay: BTreeSet<&String> = state.overlay_modules.iter().collect();
+    let old_magic: BTreeSet<&String> = state.magic_modules.iter().collect();
+    let old_hymo: BTreeSet<&String> = state.hymo_modules.iter().collect();
+    let old_bind: BTreeSet<&String> = state.bind_modules.iter().collect();
+    let new_overlay: BTreeSet<&String> = plan.overlay_module_ids.iter().collect();
+    let new_magic: BTreeSet<&String> = plan.magic_module_ids.iter().collect();
+    let new_hymo: BTreeSet<&String> = plan.hymo_module_ids.iter().collect();
+    let new_bind: BTreeSet<&String> = plan.bind_module_ids.iter().collect();
+
+    let old_all: BTreeSet<&String> = old_overlay
+        .iter()
+        .chain(&old_magic)
+        .chain(&old_hymo)
+        .chain(&old_bind)
+        .cloned()
+        .collect();
+    let new_all: BTreeSet<&String> = new_overlay
+        .iter()
+        .chain(&new_magic)
+        .chain(&new_hymo)
+        .chain(&new_bind)
+        .cloned()
+        .collect();
+
+    let added: Vec<String> = new_all
+        .difference(&old_all)
+        .map(|s| s.to_string())
+        .collect();
+    let removed: Vec<String> = old_all
+        .difference(&new_all)
+        .map(|s| s.to_string())
+        .collect();
+    let mode_changed: Vec<String> = old_all
+        .intersection(&new_all)
+        .filter(|id| {
+            old_overlay.contains(*id) != new_overlay.contains(*id)
+                || old_magic.contains(*id) != new_magic.contains(*id)
+                || old_hymo.contains(*id) != new_hymo.contains(*id)
+                || old_bind.contains(*id) != new_bind.contains(*id)
+        })
+        .map(|s| s.to_string())
+        .collect();
+
+    Ok(PendingChanges {
+        generated_at: now_secs(),
+        changed: added.len() + removed.len() + mode_changed.len(),
+        added,
+        removed,
+        mode_changed,
+    })
+}
+
+fn refresh(config: &Config) -> Result<()> {
+    let mut state = RuntimeState::load().context("Failed to load runtime state")?;
+    let pending = compute_pending(config, &state)?;
+
+    utils::atomic_write(defs::PENDING_FILE, serde_json::to_string_pretty(&pending)?)
+        .context("Failed to write pending changes report")?;
+
+    if state.pending_changes != pending.changed {
+        state.pending_changes = pending.changed;
+        state.save()?;
+    }
+
+    if pending.changed > 0 {
+        log::info!(">> {} module(s) changed, reboot to apply.", pending.changed);
+    }
+
+    Ok(())
+}
+
+pub fn run(config: &Config) -> Result<()> {
+    let mut watcher = Watcher::new(&config.moduledir)?;
+
+    log::info!(
+        ">> Watching {} for module changes...",
+        config.moduledir.display()
+    );
+
+    if let Err(e) = refresh(config) {
+        log::warn!("Failed to compute pending changes: {:#}", e);
+    }
+
+    loop {
+        watcher.wait(None)?;
+        let mut dirty = watcher.drain()?;
+
+        // Module installs touch many files; wait until the directory settles.
+        while watcher.wait(Some(DEBOUNCE))? {
+            dirty |= watcher.drain()?;
+        }
+
+        if dirty && let Err(e) = refresh(config) {
+            log::warn!("Failed to compute pending changes: {:#}", e);
+        }
+    }
+}
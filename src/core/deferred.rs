@@ -0,0 +1,200 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Modules held back at boot by `defer_ce_modules`.
+//!
+//! The boot run leaves modules that link into credential-encrypted storage
+//! out of the plan and lists them in `run/deferred.json`. `mount-deferred`,
+//! called from a `boot-completed` script, magic mounts them straight from
+//! their module directory, which is on DE storage, and clears the list.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    conf::config::Config,
+    core::{
+        inventory::{self, Module},
+        ops::planner,
+        state::{MountPhase, RuntimeState},
+    },
+    defs::Paths,
+    mount::magic_mount,
+    utils,
+};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeferredList {
+    pub timestamp: u64,
+    /// Ids of the held-back modules, in layering order.
+    pub modules: Vec<String>,
+}
+
+impl DeferredList {
+    pub fn new(modules: Vec<String>) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            modules,
+        }
+    }
+
+    /// Writes the list, or removes the file when nothing is held back.
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        if self.modules.is_empty() {
+            return clear(path);
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        if let Some(parent) = path.parent() {
+            utils::ensure_dir_exists(parent)?;
+        }
+        utils::atomic_write(path, json)
+    }
+
+    /// The list at `path`; empty when there is none.
+    pub fn load_from(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+}
+
+pub fn clear(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("Failed to remove {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Removes the modules that link into CE storage from `modules` and returns
+/// their ids.
+pub fn hold_back(modules: &mut Vec<Module>) -> Vec<String> {
+    let mut held = Vec::new();
+    modules.retain(|m| {
+        if m.ce_refs.is_empty() {
+            return true;
+        }
+        log::info!(
+            ">> Deferring {}: links into CE storage ({})",
+            m.id,
+            m.ce_refs[0].display()
+        );
+        held.push(m.id.clone());
+        false
+    });
+    held
+}
+
+/// Magic mounts the modules listed at `paths.deferred_file` and records
+/// them as mounted deferred in the state file. Returns the ids mounted; a
+/// missing or empty list mounts nothing.
+pub fn mount(config: &Config, paths: &Paths) -> Result<Vec<String>> {
+    let list = DeferredList::load_from(&paths.deferred_file)?;
+    if list.modules.is_empty() {
+        log::info!(">> No deferred modules to mount.");
+        return Ok(Vec::new());
+    }
+
+    let mut modules = inventory::scan_with_paths(&config.moduledir, config, paths)?;
+    modules.retain(|m| list.modules.contains(&m.id));
+    modules.sort_by_key(|m| list.modules.iter().position(|id| id == &m.id));
+    for id in &list.modules {
+        if !modules.iter().any(|m| &m.id == id) {
+            log::warn!("Deferred module {} is no longer enabled, skipping it", id);
+        }
+    }
+
+    let plan = planner::generate(config, &modules, &config.moduledir)?;
+    let blacklisted = plan.blacklisted_paths();
+    let writable: HashSet<PathBuf> = plan
+        .writable_files
+        .iter()
+        .filter_map(|file| {
+            let module = modules.iter().find(|m| m.id == file.module_id)?;
+            Some(module.source_path.join(&file.relative))
+        })
+        .collect();
+
+    // Staged modules live outside the module directory; magic mount reads
+    // one directory of modules at a time.
+    let mut by_dir: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+    for module in &modules {
+        if let Some(dir) = module.source_path.parent() {
+            by_dir
+                .entry(dir.to_path_buf())
+                .or_default()
+                .push(module.id.clone());
+        }
+    }
+
+    let mut state = RuntimeState::load_from(&paths.state_file).unwrap_or_default();
+    let mount_source = if state.mount_source.is_empty() {
+        config.mountsource.clone()
+    } else {
+        state.mount_source.clone()
+    };
+    let workspace = paths.run_dir.join("deferred_workspace");
+
+    let mut mounted = Vec::new();
+    for (module_dir, ids) in by_dir {
+        log::info!(
+            ">> Mounting {} deferred module(s) from {} [MAGIC]",
+            ids.len(),
+            module_dir.display()
+        );
+        magic_mount::magic_mount(
+            &workspace,
+            &module_dir,
+            &mount_source,
+            &plan.extra_partitions,
+            &ids,
+            &plan.exclusions,
+            &blacklisted,
+            &config.user_deny_paths,
+            writable.clone(),
+            config.busy_file_policy,
+            !config.disable_umount,
+        )
+        .with_context(|| {
+            format!(
+                "Failed to mount deferred modules from {}",
+                module_dir.display()
+            )
+        })?;
+        mounted.extend(ids);
+    }
+    fs::remove_dir(&workspace).ok();
+
+    for id in &list.modules {
+        if mounted.contains(id) {
+            state.mount_phase.insert(id.clone(), MountPhase::Deferred);
+            if !state.magic_modules.contains(id) {
+                state.magic_modules.push(id.clone());
+            }
+        } else {
+            state.mount_phase.remove(id);
+        }
+    }
+    state.magic_modules.sort();
+    if let Err(e) = state.save_to(&paths.state_file) {
+        log::error!("Failed to save runtime state: {:#}", e);
+    }
+
+    clear(&paths.deferred_file)?;
+    Ok(mounted)
+}
@@ -0,0 +1,174 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Exit codes of a mount run.
+//!
+//! Each failure is classed by the phase it happened in, so boot scripts can
+//! tell a storage failure (worth retrying with another backend) from a run
+//! that diagnostics stopped on purpose (not worth retrying). The class and
+//! code are also written to the state file.
+
+use std::{fmt, path::Path};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{core::state::RuntimeState, utils::cancel::Cancelled};
+
+/// The exit code table, shown by `--help`.
+pub const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0    success
+  2    config error
+  3    storage backend failure
+  4    inventory or sync failure
+  5    plan generation failure
+  6    execution failure, some mounts may have been made
+  7    aborted by diagnostics (also `check` finding a critical issue)
+  130  cancelled by SIGINT
+  143  cancelled by SIGTERM";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureClass {
+    Config,
+    Storage,
+    Inventory,
+    Plan,
+    Execution,
+    DiagnosticsAbort,
+}
+
+impl FailureClass {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Config => 2,
+            Self::Storage => 3,
+            Self::Inventory => 4,
+            Self::Plan => 5,
+            Self::Execution => 6,
+            Self::DiagnosticsAbort => 7,
+        }
+    }
+}
+
+/// Stops a run because a diagnostic said it must not go on, e.g.
+/// `foreign_mount_policy = "abort"`. Any phase returning it fails with
+/// [`FailureClass::DiagnosticsAbort`].
+#[derive(Debug, Clone)]
+pub struct DiagnosticsAbort(pub String);
+
+impl fmt::Display for DiagnosticsAbort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for DiagnosticsAbort {}
+
+/// Error of a mount run, by the phase that failed.
+#[derive(Debug)]
+pub enum RunError {
+    Config(anyhow::Error),
+    Storage(anyhow::Error),
+    Inventory(anyhow::Error),
+    Plan(anyhow::Error),
+    Execution(anyhow::Error),
+    DiagnosticsAbort(anyhow::Error),
+}
+
+impl RunError {
+    /// Classes `error` as `class`, unless it carries a [`DiagnosticsAbort`].
+    pub fn new(class: FailureClass, error: anyhow::Error) -> Self {
+        if error.downcast_ref::<DiagnosticsAbort>().is_some() {
+            return Self::DiagnosticsAbort(error);
+        }
+        match class {
+            FailureClass::Config => Self::Config(error),
+            FailureClass::Storage => Self::Storage(error),
+            FailureClass::Inventory => Self::Inventory(error),
+            FailureClass::Plan => Self::Plan(error),
+            FailureClass::Execution => Self::Execution(error),
+            FailureClass::DiagnosticsAbort => Self::DiagnosticsAbort(error),
+        }
+    }
+
+    pub fn class(&self) -> FailureClass {
+        match self {
+            Self::Config(_) => FailureClass::Config,
+            Self::Storage(_) => FailureClass::Storage,
+            Self::Inventory(_) => FailureClass::Inventory,
+            Self::Plan(_) => FailureClass::Plan,
+            Self::Execution(_) => FailureClass::Execution,
+            Self::DiagnosticsAbort(_) => FailureClass::DiagnosticsAbort,
+        }
+    }
+
+    pub fn error(&self) -> &anyhow::Error {
+        match self {
+            Self::Config(e)
+            | Self::Storage(e)
+            | Self::Inventory(e)
+            | Self::Plan(e)
+            | Self::Execution(e)
+            | Self::DiagnosticsAbort(e) => e,
+        }
+    }
+
+    /// The signal that stopped the run, if it was cancelled rather than
+    /// failed.
+    pub fn cancelled(&self) -> Option<Cancelled> {
+        self.error().downcast_ref::<Cancelled>().copied()
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self.cancelled() {
+            Some(cancelled) => cancelled.exit_code(),
+            None => self.class().exit_code(),
+        }
+    }
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#}", self.error())
+    }
+}
+
+impl std::error::Error for RunError {}
+
+/// Adds context to a phase result and classes its error.
+pub trait Classify<T> {
+    fn classify(self, class: FailureClass, context: &'static str) -> Result<T, RunError>;
+}
+
+impl<T> Classify<T> for Result<T> {
+    fn classify(self, class: FailureClass, context: &'static str) -> Result<T, RunError> {
+        self.map_err(|e| RunError::new(class, e.context(context)))
+    }
+}
+
+/// The failure written to the state file.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FailureRecord {
+    pub code: i32,
+    pub phase: FailureClass,
+    pub message: String,
+}
+
+impl From<&RunError> for FailureRecord {
+    fn from(error: &RunError) -> Self {
+        Self {
+            code: error.exit_code(),
+            phase: error.class(),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Replaces the state at `state_file` with a record of the failed run. Only
+/// the boot count is kept from the previous state, which may be unreadable.
+pub fn record(state_file: &Path, error: &RunError) -> Result<()> {
+    let previous = RuntimeState::load_from(state_file).unwrap_or_default();
+    RuntimeState::failed(previous.boot_count + 1, error.into()).save_to(state_file)
+}
@@ -0,0 +1,467 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    conf::config::{BackupConfig, Config},
+    core::inventory::model::ModuleProp,
+    defs, utils,
+};
+
+const ARCHIVE_SUFFIX: &str = ".tar.gz";
+const MANIFEST_ENTRY: &str = "manifest.json";
+const CONFIG_ENTRY: &str = "config.toml";
+const RULES_PREFIX: &str = "rules/";
+const FORMAT_VERSION: u32 = 1;
+/// Snapshots only hold metadata; anything larger is not one of ours.
+const MAX_UNPACKED_SIZE: u64 = 16 * 1024 * 1024;
+const BLOCK: usize = 512;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModuleSnapshot {
+    pub id: String,
+    pub version: String,
+    pub disabled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub format: u32,
+    pub id: String,
+    pub label: String,
+    pub timestamp: u64,
+    pub modules: Vec<ModuleSnapshot>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SnapshotInfo {
+    pub id: String,
+    pub label: String,
+    pub timestamp: u64,
+    pub size: u64,
+    pub module_count: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RestoreSummary {
+    pub id: String,
+    pub enabled: Vec<String>,
+    pub disabled: Vec<String>,
+    pub missing: Vec<String>,
+    pub config_restored: bool,
+    pub rules_restored: Vec<String>,
+    pub rules_removed: Vec<String>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn archive_path(id: &str) -> PathBuf {
+    Path::new(defs::GRANARY_DIR).join(format!("{}{}", id, ARCHIVE_SUFFIX))
+}
+
+fn write_octal(field: &mut [u8], value: u64) {
+    let text = format!("{:0width$o}\0", value, width = field.len() - 1);
+    field.copy_from_slice(text.as_bytes());
+}
+
+fn parse_octal(field: &[u8]) -> Result<u64> {
+    let text: String = field
+        .iter()
+        .take_while(|&&b| b != 0)
+        .map(|&b| b as char)
+        .collect();
+    u64::from_str_radix(text.trim(), 8)
+        .with_context(|| format!("Invalid numeric field in archive header: {:?}", text))
+}
+
+fn header_checksum(header: &[u8]) -> u64 {
+    header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            if (148..156).contains(&i) {
+                b' ' as u64
+            } else {
+                b as u64
+            }
+        })
+        .sum()
+}
+
+/// Appends one regular file to a ustar stream.
+fn tar_append(out: &mut Vec<u8>, name: &str, data: &[u8], mtime: u64) -> Result<()> {
+    ensure!(name.len() < 100, "Archive entry name too long: {}", name);
+
+    let mut header = [0u8; BLOCK];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    write_octal(&mut header[100..108], 0o644);
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    write_octal(&mut header[124..136], data.len() as u64);
+    write_octal(&mut header[136..148], mtime);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    let checksum = header_checksum(&header);
+    write_octal(&mut header[148..155], checksum);
+    header[155] = b' ';
+
+    out.extend_from_slice(&header);
+    out.extend_from_slice(data);
+    out.resize(out.len().div_ceil(BLOCK) * BLOCK, 0);
+
+    Ok(())
+}
+
+/// Parses a ustar stream into its regular files, rejecting bad checksums
+/// and entries that run past the end of the data.
+fn tar_entries(data: &[u8]) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut entries = BTreeMap::new();
+    let mut offset = 0;
+
+    loop {
+        let header = data
+            .get(offset..offset + BLOCK)
+            .context("Archive is truncated (no end-of-archive marker)")?;
+
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+
+        let stored = parse_octal(&header[148..156])?;
+        ensure!(
+            stored == header_checksum(header),
+            "Archive header checksum mismatch at offset {}",
+            offset
+        );
+
+        let name: String = String::from_utf8_lossy(&header[..100])
+            .trim_end_matches('\0')
+            .to_string();
+        let size = parse_octal(&header[124..136])? as usize;
+
+        offset += BLOCK;
+        let body = data
+            .get(offset..offset + size)
+            .with_context(|| format!("Archive is truncated inside {}", name))?;
+
+        if matches!(header[156], b'0' | 0) {
+            entries.insert(name, body.to_vec());
+        }
+
+        offset += size.div_ceil(BLOCK) * BLOCK;
+    }
+
+    Ok(entries)
+}
+
+fn read_archive(path: &Path) -> Result<BTreeMap<String, Vec<u8>>> {
+    let file =
+        fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+
+    let mut data = Vec::new();
+    GzDecoder::new(file)
+        .take(MAX_UNPACKED_SIZE + 1)
+        .read_to_end(&mut data)
+        .with_context(|| format!("{} is corrupt or truncated", path.display()))?;
+
+    ensure!(
+        data.len() as u64 <= MAX_UNPACKED_SIZE,
+        "{} unpacks to more than {} bytes",
+        path.display(),
+        MAX_UNPACKED_SIZE
+    );
+
+    tar_entries(&data).with_context(|| format!("{} is not a valid snapshot", path.display()))
+}
+
+fn parse_manifest(entries: &BTreeMap<String, Vec<u8>>) -> Result<SnapshotManifest> {
+    let raw = entries
+        .get(MANIFEST_ENTRY)
+        .context("Snapshot has no manifest")?;
+    let manifest: SnapshotManifest =
+        serde_json::from_slice(raw).context("Snapshot manifest is malformed")?;
+
+    ensure!(
+        manifest.format == FORMAT_VERSION,
+        "Unsupported snapshot format {}",
+        manifest.format
+    );
+    // Restoring joins each id onto the module directory.
+    for module in &manifest.modules {
+        utils::validate_module_id(&module.id)
+            .with_context(|| format!("Snapshot lists an unsafe module id: {}", module.id))?;
+    }
+
+    Ok(manifest)
+}
+
+fn collect_modules(moduledir: &Path) -> Vec<ModuleSnapshot> {
+    let Ok(entries) = fs::read_dir(moduledir) else {
+        return Vec::new();
+    };
+
+    let mut modules: Vec<ModuleSnapshot> = entries
+        .flatten()
+        .filter(|e| e.path().is_dir())
+        .map(|e| {
+            let path = e.path();
+            ModuleSnapshot {
+                id: e.file_name().to_string_lossy().to_string(),
+                version: ModuleProp::from(path.join("module.prop").as_path()).version,
+                disabled: path.join(defs::DISABLE_FILE_NAME).exists(),
+            }
+        })
+        .collect();
+
+    modules.sort_by(|a, b| a.id.cmp(&b.id));
+    modules
+}
+
+fn rule_files() -> Vec<(String, PathBuf)> {
+    let Ok(entries) = fs::read_dir(defs::RULES_DIR) else {
+        return Vec::new();
+    };
+
+    let mut files: Vec<(String, PathBuf)> = entries
+        .flatten()
+        .filter(|e| e.path().is_file() && e.path().extension().is_some_and(|ext| ext == "json"))
+        .map(|e| (e.file_name().to_string_lossy().to_string(), e.path()))
+        .collect();
+
+    files.sort();
+    files
+}
+
+pub fn list_snapshots() -> Result<Vec<SnapshotInfo>> {
+    let Ok(entries) = fs::read_dir(defs::GRANARY_DIR) else {
+        return Ok(Vec::new());
+    };
+
+    let mut snapshots = Vec::new();
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(id) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_suffix(ARCHIVE_SUFFIX))
+            .map(str::to_string)
+        else {
+            continue;
+        };
+
+        match read_archive(&path).and_then(|entries| parse_manifest(&entries)) {
+            Ok(manifest) => snapshots.push(SnapshotInfo {
+                id,
+                label: manifest.label,
+                timestamp: manifest.timestamp,
+                size: entry.metadata().map(|m| m.len()).unwrap_or(0),
+                module_count: manifest.modules.len(),
+            }),
+            Err(e) => log::warn!("Skipping unreadable snapshot {}: {:#}", id, e),
+        }
+    }
+
+    snapshots.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then(b.id.cmp(&a.id)));
+
+    Ok(snapshots)
+}
+
+/// Removes snapshots beyond `max_backups` or older than `retention_days`.
+/// Zero disables the respective limit; the newest snapshot is always kept.
+fn prune(backup: &BackupConfig) -> Result<()> {
+    let now = now_secs();
+
+    for (index, snapshot) in list_snapshots()?.iter().enumerate().skip(1) {
+        let over_count = backup.max_backups > 0 && index >= backup.max_backups;
+        let expired = backup.retention_days > 0
+            && now.saturating_sub(snapshot.timestamp) > backup.retention_days * 86400;
+
+        if over_count || expired {
+            log::info!("Pruning snapshot {}", snapshot.id);
+            fs::remove_file(archive_path(&snapshot.id))
+                .with_context(|| format!("Failed to remove snapshot {}", snapshot.id))?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn create_snapshot(config: &Config, label: &str) -> Result<SnapshotInfo> {
+    utils::ensure_dir_exists(defs::GRANARY_DIR)?;
+
+    let timestamp = now_secs();
+    let base_id = chrono::Local::now().format("%Y%m%d-%H%M%S").to_string();
+    let mut id = base_id.clone();
+    let mut suffix = 1;
+    while archive_path(&id).exists() {
+        id = format!("{}-{}", base_id, suffix);
+        suffix += 1;
+    }
+
+    let manifest = SnapshotManifest {
+        format: FORMAT_VERSION,
+        id: id.clone(),
+        label: label.to_string(),
+        timestamp,
+        modules: collect_modules(&config.moduledir),
+    };
+
+    let mut tar = Vec::new();
+    tar_append(
+        &mut tar,
+        MANIFEST_ENTRY,
+        &serde_json::to_vec_pretty(&manifest)?,
+        timestamp,
+    )?;
+
+    if let Ok(content) = fs::read(defs::CONFIG_FILE) {
+        tar_append(&mut tar, CONFIG_ENTRY, &content, timestamp)?;
+    }
+
+    for (name, path) in rule_files() {
+        let content =
+            fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        tar_append(
+            &mut tar,
+            &format!("{}{}", RULES_PREFIX, name),
+            &content,
+            timestamp,
+        )?;
+    }
+
+    tar.resize(tar.len() + 2 * BLOCK, 0);
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&tar)?;
+    let compressed = encoder.finish()?;
+
+    let path = archive_path(&id);
+    utils::atomic_write(&path, &compressed)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    if let Err(e) = prune(&config.backup) {
+        log::warn!("Failed to prune old snapshots: {:#}", e);
+    }
+
+    Ok(SnapshotInfo {
+        id,
+        label: manifest.label,
+        timestamp,
+        size: compressed.len() as u64,
+        module_count: manifest.modules.len(),
+    })
+}
+
+fn set_flag(path: &Path, present: bool) -> Result<bool> {
+    match (present, path.exists()) {
+        (true, false) => fs::write(path, "")
+            .with_context(|| format!("Failed to create {}", path.display()))
+            .map(|_| true),
+        (false, true) => fs::remove_file(path)
+            .with_context(|| format!("Failed to remove {}", path.display()))
+            .map(|_| true),
+        _ => Ok(false),
+    }
+}
+
+/// Validates the whole archive first, then re-applies module disable flags,
+/// `config.toml` and the rules directory.
+pub fn restore_snapshot(config: &Config, id: &str) -> Result<RestoreSummary> {
+    if id.is_empty() || id.contains('/') || id.starts_with('.') {
+        bail!("Invalid snapshot id: {}", id);
+    }
+
+    let path = archive_path(id);
+    ensure!(path.exists(), "Snapshot {} not found", id);
+
+    let entries = read_archive(&path)?;
+    let manifest = parse_manifest(&entries)?;
+
+    let restored_config = entries.get(CONFIG_ENTRY);
+    if let Some(content) = restored_config {
+        let text = std::str::from_utf8(content).context("Snapshot config is not UTF-8")?;
+        Config::parse(text).context("Snapshot config.toml is invalid")?;
+    }
+
+    let mut rules = BTreeMap::new();
+    for (name, content) in &entries {
+        let Some(file) = name.strip_prefix(RULES_PREFIX) else {
+            continue;
+        };
+        ensure!(
+            !file.is_empty() && !file.contains('/') && !file.starts_with('.'),
+            "Snapshot contains an unsafe rules entry: {}",
+            name
+        );
+        rules.insert(file.to_string(), content);
+    }
+
+    let mut summary = RestoreSummary {
+        id: id.to_string(),
+        ..Default::default()
+    };
+
+    for module in &manifest.modules {
+        let module_dir = config.moduledir.join(&module.id);
+        if !module_dir.is_dir() {
+            summary.missing.push(module.id.clone());
+            continue;
+        }
+
+        if set_flag(&module_dir.join(defs::DISABLE_FILE_NAME), module.disabled)? {
+            if module.disabled {
+                summary.disabled.push(module.id.clone());
+            } else {
+                summary.enabled.push(module.id.clone());
+            }
+        }
+    }
+
+    if let Some(content) = restored_config
+        && fs::read(defs::CONFIG_FILE).ok().as_ref() != Some(content)
+    {
+        utils::atomic_write(defs::CONFIG_FILE, content).context("Failed to restore config")?;
+        summary.config_restored = true;
+    }
+
+    for (name, path) in rule_files() {
+        if !rules.contains_key(&name) {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+            summary.rules_removed.push(name);
+        }
+    }
+
+    if !rules.is_empty() {
+        utils::ensure_dir_exists(defs::RULES_DIR)?;
+    }
+    for (name, content) in rules {
+        let path = Path::new(defs::RULES_DIR).join(&name);
+        if fs::read(&path).ok().as_ref() != Some(content) {
+            utils::atomic_write(&path, content)
+                .with_context(|| format!("Failed to restore {}", path.display()))?;
+            summary.rules_restored.push(name);
+        }
+    }
+
+    Ok(summary)
+}
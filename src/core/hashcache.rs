@@ -0,0 +1,259 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Persistent sha256 cache for module files.
+//!
+//! Conflict analysis, dedup and the EROFS manifest all hash module contents.
+//! Entries are keyed by path and reused while the file keeps its inode, size
+//! and mtime, so an unchanged module tree is read once and then only
+//! `stat`ed. The cache lives in `RUN_DIR`, holds at most a fixed number of
+//! entries (least recently used go first) and is thrown away when it cannot
+//! be parsed.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    hash::{DefaultHasher, Hash, Hasher},
+    io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{defs, utils};
+
+/// Entries kept on disk and in memory.
+pub const DEFAULT_CAPACITY: usize = 65536;
+/// Independent locks, so rayon workers hashing different files rarely wait
+/// on each other.
+const SHARDS: usize = 16;
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    path: PathBuf,
+    ino: u64,
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+    sha256: String,
+    /// Value of the access clock when the entry was last used.
+    used: u64,
+}
+
+impl Entry {
+    fn matches(&self, meta: &fs::Metadata) -> bool {
+        self.ino == meta.ino()
+            && self.size == meta.len()
+            && self.mtime == meta.mtime()
+            && self.mtime_nsec == meta.mtime_nsec()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    entries: Vec<Entry>,
+}
+
+pub struct HashCache {
+    file: PathBuf,
+    capacity: usize,
+    shards: Vec<Mutex<HashMap<PathBuf, Entry>>>,
+    clock: AtomicU64,
+    dirty: AtomicBool,
+}
+
+fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut out = [0u8; 32];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(out)
+}
+
+fn hash_file(path: &Path) -> io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+/// Oldest entry of `shard`, by access clock.
+fn least_recent(shard: &HashMap<PathBuf, Entry>) -> Option<PathBuf> {
+    shard
+        .values()
+        .min_by_key(|e| e.used)
+        .map(|e| e.path.clone())
+}
+
+impl HashCache {
+    /// Loads the cache stored in `file`. A missing, unreadable or corrupt
+    /// file gives an empty cache.
+    pub fn load(file: &Path, capacity: usize) -> Self {
+        let cache = Self {
+            file: file.to_path_buf(),
+            capacity: capacity.max(1),
+            shards: (0..SHARDS).map(|_| Mutex::default()).collect(),
+            clock: AtomicU64::new(0),
+            dirty: AtomicBool::new(false),
+        };
+
+        let parsed = fs::read(file).ok().and_then(|bytes| {
+            match serde_json::from_slice::<CacheFile>(&bytes) {
+                Ok(parsed) if parsed.version == FORMAT_VERSION => Some(parsed),
+                Ok(_) | Err(_) => {
+                    log::debug!("Discarding unusable hash cache {}", file.display());
+                    None
+                }
+            }
+        });
+
+        if let Some(mut parsed) = parsed {
+            parsed.entries.sort_by_key(|e| e.used);
+            let skip = parsed.entries.len().saturating_sub(cache.capacity);
+            cache.dirty.store(skip > 0, Ordering::Relaxed);
+            for entry in parsed.entries.into_iter().skip(skip) {
+                if from_hex(&entry.sha256).is_none() {
+                    continue;
+                }
+                cache.clock.fetch_max(entry.used + 1, Ordering::Relaxed);
+                if let Ok(mut shard) = cache.shard(&entry.path).lock() {
+                    shard.insert(entry.path.clone(), entry);
+                }
+            }
+        }
+
+        cache
+    }
+
+    fn shard(&self, path: &Path) -> &Mutex<HashMap<PathBuf, Entry>> {
+        let mut hasher = DefaultHasher::new();
+        path.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % SHARDS]
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Sha256 of the file at `path`, from the cache while its inode, size
+    /// and mtime are unchanged.
+    pub fn hash(&self, path: &Path) -> io::Result<[u8; 32]> {
+        let meta = fs::metadata(path)?;
+
+        if let Ok(mut shard) = self.shard(path).lock()
+            && let Some(entry) = shard.get_mut(path)
+            && entry.matches(&meta)
+            && let Some(digest) = from_hex(&entry.sha256)
+        {
+            entry.used = self.tick();
+            self.dirty.store(true, Ordering::Relaxed);
+            return Ok(digest);
+        }
+
+        let digest = hash_file(path)?;
+        let entry = Entry {
+            path: path.to_path_buf(),
+            ino: meta.ino(),
+            size: meta.len(),
+            mtime: meta.mtime(),
+            mtime_nsec: meta.mtime_nsec(),
+            sha256: to_hex(&digest),
+            used: self.tick(),
+        };
+
+        if let Ok(mut shard) = self.shard(path).lock() {
+            let limit = self.capacity.div_ceil(SHARDS);
+            if !shard.contains_key(path)
+                && shard.len() >= limit
+                && let Some(oldest) = least_recent(&shard)
+            {
+                shard.remove(&oldest);
+            }
+            shard.insert(entry.path.clone(), entry);
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+
+        Ok(digest)
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .filter_map(|s| s.lock().ok().map(|s| s.len()))
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes the cache back if anything changed since it was loaded, keeping
+    /// the `capacity` most recently used entries.
+    pub fn save(&self) -> Result<()> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let mut entries: Vec<Entry> = self
+            .shards
+            .iter()
+            .filter_map(|s| {
+                s.lock()
+                    .ok()
+                    .map(|s| s.values().cloned().collect::<Vec<_>>())
+            })
+            .flatten()
+            .collect();
+        entries.sort_by_key(|e| std::cmp::Reverse(e.used));
+        entries.truncate(self.capacity);
+
+        let content = serde_json::to_vec(&CacheFile {
+            version: FORMAT_VERSION,
+            entries,
+        })?;
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        utils::atomic_write(&self.file, content)
+    }
+}
+
+fn global() -> &'static HashCache {
+    static CACHE: OnceLock<HashCache> = OnceLock::new();
+    CACHE.get_or_init(|| HashCache::load(Path::new(defs::HASH_CACHE_FILE), DEFAULT_CAPACITY))
+}
+
+/// Sha256 of `path` through the process-wide cache stored in `RUN_DIR`.
+pub fn hash_file_cached(path: &Path) -> Result<[u8; 32]> {
+    Ok(global().hash(path)?)
+}
+
+/// Saves the process-wide cache. Failing to save only costs a rehash later.
+pub fn persist() {
+    if let Err(e) = global().save() {
+        log::debug!("Failed to save hash cache: {:#}", e);
+    }
+}
+
+/// Removes the cache file.
+pub fn clear(file: &Path) -> Result<()> {
+    match fs::remove_file(file) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
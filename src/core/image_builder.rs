@@ -0,0 +1,192 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{Context, Result, bail};
+
+use crate::{defs, utils};
+
+const MKFS_EROFS: &str = "mkfs.erofs";
+/// Where an `mkfs.erofs` shipped with the ROM or root solution may live.
+const MKFS_EROFS_SEARCH_DIRS: &[&str] = &[
+    defs::BIN_DIR,
+    "/system/bin",
+    "/vendor/bin",
+    "/data/adb/ksu/bin",
+    "/data/adb/ap/bin",
+];
+
+/// Packs a directory tree into a read-only image.
+pub trait ImageBuilder {
+    /// Short name recorded in the log and the state file.
+    fn name(&self) -> &'static str;
+    fn build(&self, src: &Path, out: &Path) -> Result<()>;
+    /// Whether the images it builds keep extended attributes, SELinux
+    /// labels among them. `None` when the binary could not be asked.
+    fn supports_xattrs(&self) -> Option<bool>;
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+/// `mkfs.erofs` built without xattr support leaves the xattr options out of
+/// its `--help`.
+fn mkfs_supports_xattrs(bin: &Path) -> Option<bool> {
+    let output = Command::new(bin)
+        .arg("--help")
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    let help = [output.stdout, output.stderr].concat();
+    if help.is_empty() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&help).contains("xattr"))
+}
+
+fn run_mkfs(bin: &Path, src: &Path, out: &Path) -> Result<()> {
+    if out.exists() {
+        let _ = fs::remove_file(out);
+    }
+
+    let output = Command::new(bin)
+        .arg("-z")
+        .arg("lz4hc")
+        // Xattr tolerance: any value from 0 up keeps xattrs, SELinux
+        // labels included; a negative one would drop them.
+        .arg("-x")
+        .arg("256")
+        .arg(out)
+        .arg(src)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .with_context(|| format!("Failed to execute {}", bin.display()))?;
+
+    if !output.status.success() {
+        bail!(
+            "{} exited with {}: {}",
+            bin.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
+
+/// The static `mkfs.erofs` packed into the module zip. It is copied to
+/// `BIN_DIR` before use so it runs from a stable, executable location even
+/// when the module directory is not reachable through the metamodule link.
+pub struct BundledMkfs {
+    payload: PathBuf,
+    installed: PathBuf,
+}
+
+impl BundledMkfs {
+    pub fn locate() -> Option<Self> {
+        let payload = PathBuf::from(defs::MKFS_EROFS_PATH);
+        payload.is_file().then(|| Self {
+            payload,
+            installed: Path::new(defs::BIN_DIR).join(MKFS_EROFS),
+        })
+    }
+
+    /// Copies the payload to `BIN_DIR` unless an identical copy is there.
+    fn install(&self) -> Result<&Path> {
+        let current = fs::metadata(&self.installed).ok();
+        let payload_meta = fs::metadata(&self.payload)
+            .with_context(|| format!("Bundled {} is missing", self.payload.display()))?;
+
+        let fresh = current
+            .is_some_and(|m| m.len() == payload_meta.len() && m.mtime() >= payload_meta.mtime());
+
+        if !fresh {
+            utils::ensure_dir_exists(defs::BIN_DIR)?;
+            fs::copy(&self.payload, &self.installed).with_context(|| {
+                format!("Failed to extract bundled {}", self.installed.display())
+            })?;
+            log::debug!(
+                "Extracted bundled mkfs.erofs to {}",
+                self.installed.display()
+            );
+        }
+
+        fs::set_permissions(&self.installed, fs::Permissions::from_mode(0o755))?;
+        Ok(&self.installed)
+    }
+}
+
+impl ImageBuilder for BundledMkfs {
+    fn name(&self) -> &'static str {
+        "bundled"
+    }
+
+    fn build(&self, src: &Path, out: &Path) -> Result<()> {
+        run_mkfs(self.install()?, src, out)
+    }
+
+    fn supports_xattrs(&self) -> Option<bool> {
+        mkfs_supports_xattrs(self.install().ok()?)
+    }
+}
+
+/// An `mkfs.erofs` already present on the device.
+pub struct ExternalMkfs {
+    bin: PathBuf,
+}
+
+impl ExternalMkfs {
+    /// Searches `MKFS_EROFS_SEARCH_DIRS`, then `PATH`.
+    pub fn locate() -> Option<Self> {
+        let path_dirs = std::env::var_os("PATH")
+            .map(|p| std::env::split_paths(&p).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        MKFS_EROFS_SEARCH_DIRS
+            .iter()
+            .map(PathBuf::from)
+            .chain(path_dirs)
+            .map(|dir| dir.join(MKFS_EROFS))
+            .find(|candidate| is_executable(candidate))
+            .map(|bin| Self { bin })
+    }
+}
+
+impl ImageBuilder for ExternalMkfs {
+    fn name(&self) -> &'static str {
+        "external"
+    }
+
+    fn build(&self, src: &Path, out: &Path) -> Result<()> {
+        run_mkfs(&self.bin, src, out)
+    }
+
+    fn supports_xattrs(&self) -> Option<bool> {
+        mkfs_supports_xattrs(&self.bin)
+    }
+}
+
+/// The EROFS builder to use: the bundled binary when the module ships one,
+/// otherwise one found on the device. `None` when neither exists.
+pub fn select() -> Option<Box<dyn ImageBuilder>> {
+    if let Some(bundled) = BundledMkfs::locate() {
+        return Some(Box::new(bundled));
+    }
+    ExternalMkfs::locate().map(|b| Box::new(b) as Box<dyn ImageBuilder>)
+}
+
+/// Places searched by [`select`], for error messages.
+pub fn search_locations() -> String {
+    std::iter::once(defs::MKFS_EROFS_PATH)
+        .chain(MKFS_EROFS_SEARCH_DIRS.iter().copied())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,322 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Sha256 manifest of the module copies in storage, checked at boot.
+//!
+//! With `verify_storage`, every sync that changes a module's copy rehashes
+//! it into `INTEGRITY_FILE`. The next boot checks the copies against it
+//! before sync touches them: sizes and unexpected files always, contents of
+//! files of at least `verify_full_above_kb` always and of a random
+//! `verify_sample_percent` of the rest, within `verify_max_seconds`. A
+//! module whose copy does not match is not mounted that boot. Only the ext4
+//! image keeps its content between boots, so other storage modes skip the
+//! check. The hashes are read fresh from storage, never from the hash cache,
+//! whose entries tampering could leave looking valid.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    hash::{BuildHasher, RandomState},
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::{
+    conf::config::Config,
+    core::{
+        inventory::Module,
+        ops::planner::{DiagnosticIssue, DiagnosticLevel},
+    },
+    utils::{self, pool},
+};
+
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileDigest {
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Digest of every regular file of each module copy, by path relative to
+/// the copy.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    version: u32,
+    pub modules: BTreeMap<String, BTreeMap<PathBuf, FileDigest>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IntegrityStatus {
+    /// Every file in the manifest was hashed and matched.
+    Verified,
+    /// The files hashed matched, but the sample or time limit left some out.
+    Sampled,
+    /// A module copy did not match its manifest.
+    Failed,
+    /// Nothing was checked; `reason` says why.
+    #[default]
+    Skipped,
+}
+
+/// How the boot-time check of the storage went.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IntegrityReport {
+    pub status: IntegrityStatus,
+    /// Files whose content was hashed.
+    pub hashed: usize,
+    /// Files in the manifest of the modules checked.
+    pub total: usize,
+    pub elapsed_ms: u64,
+    /// `verify_max_seconds` ran out before every chosen file was hashed.
+    #[serde(default)]
+    pub timed_out: bool,
+    /// Mismatching paths, relative to the copy, by module id. These modules
+    /// were not mounted.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub failed: BTreeMap<String, Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl IntegrityReport {
+    pub fn skipped(reason: &str) -> Self {
+        Self {
+            reason: Some(reason.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+/// Limits of the boot-time check, from the config.
+#[derive(Debug, Clone, Copy)]
+pub struct VerifyLimits {
+    /// 0 for no limit.
+    pub max_seconds: u64,
+    pub sample_percent: u8,
+    pub full_above_bytes: u64,
+}
+
+impl VerifyLimits {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_seconds: config.verify_max_seconds,
+            sample_percent: config.verify_sample_percent.min(100),
+            full_above_bytes: config.verify_full_above_kb * 1024,
+        }
+    }
+}
+
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Regular files below `copy`, relative to it. Whiteouts, symlinks and
+/// directories carry no content to hash.
+fn regular_files(copy: &Path) -> Vec<PathBuf> {
+    WalkDir::new(copy)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .flatten()
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.path().strip_prefix(copy).ok().map(Path::to_path_buf))
+        .collect()
+}
+
+/// Hashes every regular file of the module copy at `copy`.
+pub fn hash_copy(copy: &Path) -> BTreeMap<PathBuf, FileDigest> {
+    let files = regular_files(copy);
+    pool::install(|| {
+        files
+            .into_par_iter()
+            .filter_map(|relative| {
+                let path = copy.join(&relative);
+                let digest = FileDigest {
+                    size: fs::metadata(&path).ok()?.len(),
+                    sha256: hash_file(&path)
+                        .inspect_err(|e| log::warn!("Cannot hash {}: {}", path.display(), e))
+                        .ok()?,
+                };
+                Some((relative, digest))
+            })
+            .collect()
+    })
+}
+
+impl Manifest {
+    /// The manifest stored in `file`; `None` when there is none yet or it
+    /// cannot be used.
+    pub fn load(file: &Path) -> Option<Self> {
+        let bytes = fs::read(file).ok()?;
+        match serde_json::from_slice::<Self>(&bytes) {
+            Ok(manifest) if manifest.version == FORMAT_VERSION => Some(manifest),
+            Ok(_) | Err(_) => {
+                log::warn!("Ignoring unusable integrity manifest {}", file.display());
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, file: &Path) -> Result<()> {
+        let json = serde_json::to_string(&Self {
+            version: FORMAT_VERSION,
+            modules: self.modules.clone(),
+        })?;
+        if let Some(parent) = file.parent() {
+            utils::ensure_dir_exists(parent)?;
+        }
+        utils::atomic_write(file, json)
+    }
+
+    /// Rehashes the copies in `storage` of the modules in `changed` and of
+    /// those without an entry yet, and drops the entries of modules that
+    /// are not in `modules` or have no copy. Returns the ids rehashed.
+    pub fn update(
+        &mut self,
+        storage: &Path,
+        modules: &[Module],
+        changed: &[String],
+    ) -> Vec<String> {
+        let mut rehashed = Vec::new();
+        let mut kept = BTreeMap::new();
+        for module in modules {
+            let copy = storage.join(&module.id);
+            if !copy.is_dir() {
+                continue;
+            }
+            let entry = match self.modules.remove(&module.id) {
+                Some(entry) if !changed.contains(&module.id) => entry,
+                _ => {
+                    rehashed.push(module.id.clone());
+                    hash_copy(&copy)
+                }
+            };
+            kept.insert(module.id.clone(), entry);
+        }
+        self.modules = kept;
+        rehashed
+    }
+}
+
+/// Checks the copies in `storage` of `modules` against `manifest`. Modules
+/// without an entry, such as ones not synced yet, are not checked.
+pub fn verify(
+    manifest: &Manifest,
+    storage: &Path,
+    modules: &[Module],
+    limits: VerifyLimits,
+) -> IntegrityReport {
+    let started = Instant::now();
+    let deadline = (limits.max_seconds > 0).then(|| Duration::from_secs(limits.max_seconds));
+    let sampler = RandomState::new();
+    let mut report = IntegrityReport::default();
+    let mut left_out = false;
+
+    for module in modules {
+        let Some(expected) = manifest.modules.get(&module.id) else {
+            continue;
+        };
+        let copy = storage.join(&module.id);
+        report.total += expected.len();
+        let mut mismatches = BTreeSet::new();
+
+        for relative in regular_files(&copy) {
+            if !expected.contains_key(&relative) {
+                mismatches.insert(relative);
+            }
+        }
+
+        for (relative, digest) in expected {
+            let path = copy.join(relative);
+            match fs::metadata(&path) {
+                Ok(meta) if meta.is_file() && meta.len() == digest.size => {}
+                _ => {
+                    mismatches.insert(relative.clone());
+                    continue;
+                }
+            }
+
+            let chosen = digest.size >= limits.full_above_bytes
+                || sampler.hash_one(&path) % 100 < limits.sample_percent as u64;
+            if !chosen {
+                left_out = true;
+                continue;
+            }
+            if deadline.is_some_and(|limit| started.elapsed() >= limit) {
+                report.timed_out = true;
+                continue;
+            }
+
+            report.hashed += 1;
+            if hash_file(&path).ok().as_ref() != Some(&digest.sha256) {
+                mismatches.insert(relative.clone());
+            }
+        }
+
+        if !mismatches.is_empty() {
+            log::error!(
+                "!! Storage copy of {} does not match its manifest: {} file(s)",
+                module.id,
+                mismatches.len()
+            );
+            report.failed.insert(
+                module.id.clone(),
+                mismatches.iter().map(|p| p.display().to_string()).collect(),
+            );
+        }
+    }
+
+    report.elapsed_ms = started.elapsed().as_millis() as u64;
+    report.status = if !report.failed.is_empty() {
+        IntegrityStatus::Failed
+    } else if left_out || report.timed_out {
+        IntegrityStatus::Sampled
+    } else {
+        IntegrityStatus::Verified
+    };
+    report
+}
+
+/// A Critical issue per module whose copy failed the check, and a Warning
+/// when the time limit cut it short.
+pub fn diagnose(report: &IntegrityReport) -> Vec<DiagnosticIssue> {
+    let mut issues: Vec<DiagnosticIssue> = report
+        .failed
+        .iter()
+        .map(|(id, paths)| DiagnosticIssue {
+            level: DiagnosticLevel::Critical,
+            context: id.clone(),
+            message: format!(
+                "Not mounted: {} file(s) in storage differ from the integrity manifest, e.g. {}",
+                paths.len(),
+                paths.first().map(String::as_str).unwrap_or_default()
+            ),
+        })
+        .collect();
+    if report.timed_out {
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Warning,
+            context: "Integrity".to_string(),
+            message: format!(
+                "verify_max_seconds ran out after hashing {} of {} files",
+                report.hashed, report.total
+            ),
+        });
+    }
+    issues
+}
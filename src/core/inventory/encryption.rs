@@ -0,0 +1,114 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Module content that only resolves once credential-encrypted storage is
+//! unlocked.
+//!
+//! `/data/adb` is device-encrypted (DE) and readable at boot, but app data
+//! and media are credential-encrypted (CE) until the user unlocks. A module
+//! symlinking into CE storage mounts links that dangle until then; with
+//! `defer_ce_modules` such modules are held back and mounted later by
+//! `mount-deferred`.
+
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use walkdir::WalkDir;
+
+use crate::core::{
+    inventory::Module,
+    ops::planner::{DiagnosticIssue, DiagnosticLevel},
+};
+
+/// Roots of the CE storage. `/data/user_de` is DE and deliberately absent.
+pub const CE_PATH_PREFIXES: &[&str] = &[
+    "/data/data",
+    "/data/user",
+    "/data/media",
+    "/data/misc_ce",
+    "/data/system_ce",
+    "/data/vendor_ce",
+];
+
+pub fn is_ce_path(path: &Path) -> bool {
+    CE_PATH_PREFIXES
+        .iter()
+        .any(|prefix| path.starts_with(prefix))
+}
+
+/// Lexically resolves `target`, the content of the symlink at the mounted
+/// path `link`, to an absolute path.
+fn resolve(link: &Path, target: &Path) -> PathBuf {
+    let base = if target.is_absolute() {
+        PathBuf::from("/")
+    } else {
+        link.parent()
+            .map_or_else(|| PathBuf::from("/"), Path::to_path_buf)
+    };
+
+    let mut resolved = base;
+    for component in target.components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => resolved.push(name),
+            _ => {}
+        }
+    }
+    resolved
+}
+
+/// Symlinks in the partition directories of the module at `module_dir`
+/// whose target is on CE storage, as paths inside the module.
+pub fn ce_references(module_dir: &Path) -> Vec<PathBuf> {
+    WalkDir::new(module_dir)
+        .min_depth(2)
+        .sort_by_file_name()
+        .into_iter()
+        .flatten()
+        .filter(|entry| entry.path_is_symlink())
+        .filter_map(|entry| {
+            let relative = entry.path().strip_prefix(module_dir).ok()?;
+            let target = fs::read_link(entry.path()).ok()?;
+            is_ce_path(&resolve(&Path::new("/").join(relative), &target))
+                .then(|| relative.to_path_buf())
+        })
+        .collect()
+}
+
+/// One issue per module linking into CE storage: a Warning when it is
+/// mounted at boot anyway, an Info when `defer_ce_modules` holds it back.
+pub fn diagnose(modules: &[Module], defer: bool) -> Vec<DiagnosticIssue> {
+    modules
+        .iter()
+        .filter(|module| !module.ce_refs.is_empty())
+        .map(|module| {
+            let first = module.ce_refs[0].display();
+            let count = module.ce_refs.len();
+            if defer {
+                DiagnosticIssue {
+                    level: DiagnosticLevel::Info,
+                    context: module.id.clone(),
+                    message: format!(
+                        "{} symlink(s) into CE storage, e.g. {}; mounted by mount-deferred \
+                         after unlock",
+                        count, first
+                    ),
+                }
+            } else {
+                DiagnosticIssue {
+                    level: DiagnosticLevel::Warning,
+                    context: module.id.clone(),
+                    message: format!(
+                        "{} symlink(s) into CE storage, e.g. {}, dangle until the device is \
+                         unlocked; enable defer_ce_modules to mount this module after unlock",
+                        count, first
+                    ),
+                }
+            }
+        })
+        .collect()
+}
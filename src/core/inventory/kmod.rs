@@ -0,0 +1,150 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Kernel modules shipped by a module, checked against the running kernel.
+//!
+//! The `vermagic=` entry of a .ko's `.modinfo` section starts with the
+//! kernel release it was built for. When it differs from `uname -r` the
+//! kernel refuses to load the file, whatever the overlay does.
+
+use std::{ffi::CStr, fs, path::Path};
+
+use walkdir::WalkDir;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const MODINFO_SECTION: &[u8] = b".modinfo";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VermagicMismatch {
+    /// Path of the .ko below the module root.
+    pub relative: String,
+    pub vermagic: String,
+}
+
+/// Reads fixed-width integers of one ELF file in its byte order.
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+        self.data
+            .get(offset..offset.checked_add(N)?)?
+            .try_into()
+            .ok()
+    }
+
+    fn u16(&self, offset: usize) -> Option<usize> {
+        let b = self.bytes(offset)?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        } as usize)
+    }
+
+    fn u32(&self, offset: usize) -> Option<usize> {
+        let b = self.bytes(offset)?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        } as usize)
+    }
+
+    fn u64(&self, offset: usize) -> Option<usize> {
+        let b = self.bytes(offset)?;
+        usize::try_from(if self.big_endian {
+            u64::from_be_bytes(b)
+        } else {
+            u64::from_le_bytes(b)
+        })
+        .ok()
+    }
+}
+
+/// The `.modinfo` section of an ELF relocatable, 32 or 64 bit, either byte
+/// order. `None` when `data` is not ELF or has no such section.
+fn modinfo_section(data: &[u8]) -> Option<&[u8]> {
+    if !data.starts_with(ELF_MAGIC) {
+        return None;
+    }
+    let elf64 = match data.get(4)? {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+    let r = Reader {
+        data,
+        big_endian: *data.get(5)? == 2,
+    };
+
+    let (shoff, shentsize, shnum, shstrndx) = if elf64 {
+        (r.u64(0x28)?, r.u16(0x3A)?, r.u16(0x3C)?, r.u16(0x3E)?)
+    } else {
+        (r.u32(0x20)?, r.u16(0x2E)?, r.u16(0x30)?, r.u16(0x32)?)
+    };
+    // sh_name, then sh_offset and sh_size.
+    let section = |index: usize| -> Option<(usize, &[u8])> {
+        let header = shoff.checked_add(index.checked_mul(shentsize)?)?;
+        let name = r.u32(header)?;
+        let (offset, size) = if elf64 {
+            (r.u64(header + 0x18)?, r.u64(header + 0x20)?)
+        } else {
+            (r.u32(header + 0x10)?, r.u32(header + 0x14)?)
+        };
+        Some((name, data.get(offset..offset.checked_add(size)?)?))
+    };
+
+    let (_, names) = section(shstrndx)?;
+    (0..shnum).find_map(|index| {
+        let (name, contents) = section(index)?;
+        let name = CStr::from_bytes_until_nul(names.get(name..)?).ok()?;
+        (name.to_bytes() == MODINFO_SECTION).then_some(contents)
+    })
+}
+
+/// The `vermagic=` value of the kernel module in `data`.
+pub fn vermagic(data: &[u8]) -> Option<String> {
+    modinfo_section(data)?
+        .split(|&b| b == 0)
+        .find_map(|entry| entry.strip_prefix(b"vermagic="))
+        .map(|value| String::from_utf8_lossy(value).trim().to_string())
+}
+
+/// Release of the running kernel, as `uname -r` prints it.
+pub fn kernel_release() -> Option<String> {
+    // SAFETY: utsname is plain old data and uname only fills it in.
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return None;
+    }
+    // SAFETY: uname NUL-terminates every field.
+    let release = unsafe { CStr::from_ptr(uts.release.as_ptr()) };
+    Some(release.to_string_lossy().to_string())
+}
+
+/// Every `.ko` under `module_dir` whose vermagic names a release other than
+/// `release`. Files without a readable vermagic are skipped.
+pub fn check(module_dir: &Path, release: &str) -> Vec<VermagicMismatch> {
+    WalkDir::new(module_dir)
+        .min_depth(1)
+        .into_iter()
+        .flatten()
+        .filter(|e| e.file_type().is_file() && e.path().extension().is_some_and(|x| x == "ko"))
+        .filter_map(|entry| {
+            let vermagic = vermagic(&fs::read(entry.path()).ok()?)?;
+            let built_for = vermagic.split_whitespace().next()?;
+            (built_for != release).then(|| VermagicMismatch {
+                relative: entry
+                    .path()
+                    .strip_prefix(module_dir)
+                    .unwrap_or(entry.path())
+                    .display()
+                    .to_string(),
+                vermagic,
+            })
+        })
+        .collect()
+}
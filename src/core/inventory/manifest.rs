@@ -0,0 +1,178 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Optional `meta_hybrid_manifest.toml` in a module root listing the device
+//! paths the module means to provide:
+//!
+//! ```toml
+//! expects = ["/system/app/Foo/Foo.apk", "/vendor/lib64/libbar.so"]
+//! ```
+//!
+//! Each path is checked against the module tree with a stat, the same way
+//! magic mount folds `vendor/` and `system/vendor/` together, and partition
+//! dirs nested in themselves or not covered by the manifest are reported.
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::defs;
+
+/// Partitions magic mount accepts both at the module root and under
+/// `system/`.
+const SYSTEM_LINKED: &[&str] = &["vendor", "system_ext", "product", "odm"];
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    expects: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ManifestStatus {
+    Ok,
+    Mismatch,
+    #[default]
+    Absent,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ManifestCheck {
+    pub status: ManifestStatus,
+    /// What does not match, each with a suggested fix.
+    pub issues: Vec<String>,
+}
+
+fn components(target: &str) -> Vec<String> {
+    Path::new(target)
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Module-relative paths that put a file at `target`, e.g. `vendor/lib/x`
+/// and `system/vendor/lib/x` for `/vendor/lib/x`.
+pub fn candidates(target: &str) -> Vec<PathBuf> {
+    let parts = components(target);
+    let mut found = vec![parts.iter().collect::<PathBuf>()];
+    match parts.as_slice() {
+        [system, linked, ..] if system == "system" && SYSTEM_LINKED.contains(&linked.as_str()) => {
+            found.push(parts[1..].iter().collect());
+        }
+        [linked, ..] if SYSTEM_LINKED.contains(&linked.as_str()) => {
+            found.push(Path::new("system").join(parts.iter().collect::<PathBuf>()));
+        }
+        _ => {}
+    }
+    found
+}
+
+fn exists(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok()
+}
+
+/// Checks the manifest of the module at `module_dir`, if it has one.
+pub fn check(module_dir: &Path) -> ManifestCheck {
+    let content = match fs::read_to_string(module_dir.join(defs::MANIFEST_FILE_NAME)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return ManifestCheck::default(),
+        Err(e) => {
+            return ManifestCheck {
+                status: ManifestStatus::Mismatch,
+                issues: vec![format!("{} is unreadable: {}", defs::MANIFEST_FILE_NAME, e)],
+            };
+        }
+    };
+    let manifest: Manifest = match toml::from_str(&content) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            return ManifestCheck {
+                status: ManifestStatus::Mismatch,
+                issues: vec![format!(
+                    "{} is invalid: {}",
+                    defs::MANIFEST_FILE_NAME,
+                    e.message()
+                )],
+            };
+        }
+    };
+
+    let mut issues = Vec::new();
+    let mut covered = BTreeSet::new();
+
+    for target in &manifest.expects {
+        let paths = candidates(target);
+        covered.extend(
+            paths
+                .iter()
+                .filter_map(|p| p.components().next())
+                .map(|c| c.as_os_str().to_string_lossy().to_string()),
+        );
+
+        if paths.iter().any(|p| exists(&module_dir.join(p))) {
+            continue;
+        }
+
+        let doubled = paths.iter().find_map(|p| {
+            let top = p.components().next()?;
+            let nested = Path::new(top.as_os_str()).join(p);
+            exists(&module_dir.join(&nested)).then_some((nested, top))
+        });
+        issues.push(match doubled {
+            Some((nested, top)) => format!(
+                "expected {} is at {}; move the contents of {}/{}/ up into {}/",
+                target,
+                nested.display(),
+                top.as_os_str().to_string_lossy(),
+                top.as_os_str().to_string_lossy(),
+                top.as_os_str().to_string_lossy()
+            ),
+            None => format!(
+                "expected {} is missing (looked for {}); ship the file or drop it from expects",
+                target,
+                paths
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        });
+    }
+
+    for partition in defs::BUILTIN_PARTITIONS {
+        let dir = module_dir.join(partition);
+        if !dir.is_dir() {
+            continue;
+        }
+        if dir.join(partition).is_dir() {
+            issues.push(format!(
+                "{p}/{p}/ is nested twice; move its contents up into {p}/",
+                p = partition
+            ));
+        }
+        if !covered.contains(*partition) {
+            issues.push(format!(
+                "ships {p}/ but the manifest expects nothing under /{p}; remove it or list its files in expects",
+                p = partition
+            ));
+        }
+    }
+
+    ManifestCheck {
+        status: if issues.is_empty() {
+            ManifestStatus::Ok
+        } else {
+            ManifestStatus::Mismatch
+        },
+        issues,
+    }
+}
@@ -1,8 +1,13 @@
 // Copyright 2026 Hybrid Mount Developers
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+pub mod encryption;
+pub mod kmod;
+pub mod manifest;
 pub mod model;
 pub mod scanner;
+pub mod updates;
+pub mod validate;
 
 pub use scanner::*;
 
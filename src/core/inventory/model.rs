@@ -2,7 +2,6 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::{
-    collections::HashSet,
     fs::{self},
     io::{BufRead, BufReader},
     os::unix::fs::{FileTypeExt, MetadataExt},
@@ -16,21 +15,28 @@ use extattr::lgetxattr;
 use regex_lite::Regex;
 use serde::Serialize;
 
-use super::scanner as inventory;
+use super::{
+    manifest::ManifestStatus,
+    scanner as inventory,
+    updates::{self, PendingUpdate},
+    validate::{ModuleIssue, validate_prop},
+};
 use crate::{
     conf::config::{self, MountMode},
-    core::state::RuntimeState,
+    core::state::{MountPhase, RuntimeState},
     defs, utils,
 };
 
 static MODULE_PROP_REGEX: OnceLock<Regex> = OnceLock::new();
 
 #[derive(Default)]
-struct ModuleProp {
-    name: String,
-    version: String,
-    author: String,
-    description: String,
+pub struct ModuleProp {
+    pub name: String,
+    pub version: String,
+    pub author: String,
+    pub description: String,
+    /// `versionCode`, when it is a non-negative integer.
+    pub version_code: Option<u64>,
 }
 
 impl From<&Path> for ModuleProp {
@@ -51,6 +57,7 @@ impl From<&Path> for ModuleProp {
                         "version" => prop.version = v.to_string(),
                         "author" => prop.author = v.to_string(),
                         "description" => prop.description = v.to_string(),
+                        "versionCode" => prop.version_code = v.trim().parse().ok(),
                         _ => {}
                     }
                 }
@@ -60,36 +67,125 @@ impl From<&Path> for ModuleProp {
     }
 }
 
-#[derive(Serialize)]
-struct ModuleInfo {
-    id: String,
-    name: String,
-    version: String,
-    author: String,
-    description: String,
-    mode: String,
-    is_mounted: bool,
-    rules: config::ModuleRules,
+#[derive(Debug, Serialize)]
+pub struct ModuleInfo {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub author: String,
+    pub description: String,
+    pub mode: String,
+    pub resolved_mode: String,
+    pub mode_source: String,
+    /// Partitions the module ships files for; empty when it only carries
+    /// scripts.
+    pub partitions: Vec<String>,
+    pub is_mounted: bool,
+    /// Mode the module was actually mounted with during this boot.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effective_mode: Option<String>,
+    /// Set when `effective_mode` differs from the plan, e.g. a magic mount
+    /// after OverlayFS failed. A planned magic mount has no reason.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback_reason: Option<String>,
+    /// `boot`, or `pending`/`deferred` for a module `defer_ce_modules` held
+    /// back until `mount-deferred`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mount_phase: Option<MountPhase>,
+    /// Space the module took at the last sync; see [`ModuleUsage::size_bytes`].
+    ///
+    /// [`ModuleUsage::size_bytes`]: crate::core::ops::sync::ModuleUsage::size_bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size_bytes: Option<u64>,
+    /// `false` when the module has no copy in the storage backend.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub in_storage: Option<bool>,
+    pub staged: bool,
+    /// `image_modules` bundle the module is read from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bundle: Option<String>,
+    /// Update or fresh install waiting in `modules_update` for the reboot.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update: Option<PendingUpdate>,
+    /// Why the module is skipped; absent when it is mounted normally.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub excluded_by: Option<inventory::Exclusion>,
+    /// `ok`, `mismatch` or `absent` for `meta_hybrid_manifest.toml`.
+    pub manifest: ManifestStatus,
+    pub rules: config::ModuleRules,
+    /// module.prop problems. File names are only checked by `diagnose`,
+    /// which walks the whole tree.
+    pub issues: Vec<ModuleIssue>,
+}
+
+fn mode_name(mode: &MountMode) -> &'static str {
+    match mode {
+        MountMode::Overlay => "overlay",
+        MountMode::Magic => "magic",
+        MountMode::Ignore => "ignore",
+        MountMode::Hymo => "hymo",
+    }
 }
 
 impl ModuleInfo {
-    fn new(m: inventory::Module, mounted_set: &HashSet<&str>) -> Self {
+    fn new(
+        m: inventory::Module,
+        excluded_by: Option<inventory::Exclusion>,
+        state: &RuntimeState,
+        update: Option<PendingUpdate>,
+        extra_partitions: &[String],
+    ) -> Self {
         let prop = ModuleProp::from(m.source_path.join("module.prop").as_path());
 
         let mode_str = match m.rules.default_mode {
             MountMode::Overlay => "auto",
             MountMode::Magic => "magic",
             MountMode::Ignore => "ignore",
+            MountMode::Hymo => "hymofs",
+        };
+
+        let (resolved_mode, mode_source) = match &m.mode {
+            Some(mode) => (mode_name(mode), "mount_mode"),
+            None => (mode_name(&m.rules.default_mode), "rules"),
         };
 
+        let effective_mode = if state.overlay_modules.contains(&m.id) {
+            Some("overlay")
+        } else if state.magic_modules.contains(&m.id) {
+            Some("magic")
+        } else if state.hymo_modules.contains(&m.id) {
+            Some("hymo")
+        } else if state.bind_modules.contains(&m.id) {
+            Some("bind")
+        } else {
+            None
+        };
+
+        let usage = state.sync_summary.module_usage.get(&m.id);
+        let partitions = m.content_partitions(extra_partitions);
+
         Self {
-            is_mounted: mounted_set.contains(m.id.as_str()),
+            issues: validate_prop(&m.source_path),
+            size_bytes: usage.map(|u| u.size_bytes()),
+            in_storage: usage.map(|u| u.in_storage),
+            is_mounted: effective_mode.is_some(),
+            effective_mode: effective_mode.map(str::to_string),
+            fallback_reason: state.fallback_reasons.get(&m.id).cloned(),
+            mount_phase: state.mount_phase.get(&m.id).copied(),
+            staged: m.staged,
+            bundle: m.bundle,
+            update,
+            excluded_by,
+            manifest: m.manifest.status,
             id: m.id,
             name: prop.name,
             version: prop.version,
             author: prop.author,
             description: prop.description,
             mode: mode_str.to_string(),
+            resolved_mode: resolved_mode.to_string(),
+            mode_source: mode_source.to_string(),
+            partitions,
             rules: m.rules,
         }
     }
@@ -148,69 +244,188 @@ impl ModuleFile {
     }
 }
 
-pub fn print_list(config: &config::Config) -> Result<()> {
-    let modules = inventory::scan(&config.moduledir, config)?;
+/// Installed modules with their metadata, resolved mode and mount status.
+/// Skipped modules are included with `excluded_by` set, and modules only
+/// waiting in `modules_update` with an `install` update.
+pub fn list(config: &config::Config) -> Result<Vec<ModuleInfo>> {
+    list_with_paths(config, &defs::Paths::default())
+}
 
-    let state = RuntimeState::load().unwrap_or_default();
+/// Same as [`list`] with rules, staged modules and the runtime state read
+/// from `paths`.
+pub fn list_with_paths(config: &config::Config, paths: &defs::Paths) -> Result<Vec<ModuleInfo>> {
+    let modules = inventory::scan_all_with_paths(&config.moduledir, config, paths)?;
+    let mut pending = updates::pending(&config.moduledir);
 
-    let mounted_ids: HashSet<&str> = state
-        .overlay_modules
-        .iter()
-        .chain(state.magic_modules.iter())
-        .map(|s| s.as_str())
-        .collect();
+    let state = RuntimeState::load_from(&paths.state_file).unwrap_or_default();
 
-    let infos: Vec<ModuleInfo> = modules
+    let mut infos: Vec<ModuleInfo> = modules
         .into_iter()
-        .map(|m| ModuleInfo::new(m, &mounted_ids))
+        .map(|(m, excluded_by)| {
+            let update = pending.remove(&m.id);
+            ModuleInfo::new(m, excluded_by, &state, update, &config.partitions)
+        })
         .collect();
 
-    println!("{}", serde_json::to_string(&infos)?);
+    for (id, update) in pending {
+        if let Some((m, excluded_by)) =
+            inventory::load_module(update.path.clone(), id, config, &paths.rules_dir, false)
+        {
+            infos.push(ModuleInfo::new(
+                m,
+                excluded_by,
+                &state,
+                Some(update),
+                &config.partitions,
+            ));
+        }
+    }
+
+    Ok(infos)
+}
 
-    Ok(())
+/// What the module.prop status reflects after a mount run.
+#[derive(Debug, Clone, Default)]
+pub struct PropStatus {
+    pub storage_mode: String,
+    pub overlay_count: usize,
+    pub magic_count: usize,
+    pub bind_count: usize,
+    pub usage_percent: u8,
+    pub degraded: bool,
+    pub safe_mode: bool,
+    /// Headline of the boot summary, e.g. `7 mounted, 1 fallback, 2 warnings`.
+    pub headline: Option<String>,
 }
 
-pub fn update_description(storage_mode: &str, overlay_count: usize, magic_count: usize) {
-    let prop_path = Path::new(defs::MODULE_PROP_FILE);
+impl PropStatus {
+    fn description(&self) -> String {
+        let mode_str = match self.storage_mode.as_str() {
+            "tmpfs" => "Tmpfs",
+            "erofs" => "EROFS",
+            "zram" => "Zram",
+            _ => "Ext4",
+        };
+
+        let status_emoji = match self.storage_mode.as_str() {
+            "tmpfs" => "🐾",
+            "erofs" => "🚀",
+            "zram" => "🗜️",
+            _ => "💿",
+        };
+
+        let mut desc_text = format!(
+            "😋 运行中喵～ ({}) {} | Overlay: {} | Magic: {}",
+            mode_str, status_emoji, self.overlay_count, self.magic_count
+        );
+        if self.bind_count > 0 {
+            desc_text.push_str(&format!(" | Bind: {}", self.bind_count));
+        }
+        desc_text.push_str(&format!(" | Storage: {}%", self.usage_percent));
+        if self.safe_mode {
+            desc_text.push_str(" | 🛟 Safe Mode");
+        }
+        if self.degraded {
+            desc_text.push_str(" | ⚠️ Degraded");
+        }
+        if let Some(headline) = &self.headline {
+            desc_text.push_str(&format!(" | {}", headline));
+        }
+        desc_text
+    }
 
-    if !prop_path.exists() {
-        return;
+    /// `status=` value: `ok`, `safe_mode` or `degraded`, then the counts,
+    /// storage backend and usage, comma separated.
+    pub fn status(&self) -> String {
+        let state = if self.safe_mode {
+            "safe_mode"
+        } else if self.degraded {
+            "degraded"
+        } else {
+            "ok"
+        };
+        format!(
+            "{},overlay={},magic={},bind={},storage={},usage={}%",
+            state,
+            self.overlay_count,
+            self.magic_count,
+            self.bind_count,
+            self.storage_mode,
+            self.usage_percent
+        )
     }
+}
 
-    let mode_str = match storage_mode {
-        "tmpfs" => "Tmpfs",
-        "erofs" => "EROFS",
-        _ => "Ext4",
-    };
+/// Sets `key=value` pairs in module.prop contents. Existing lines keep their
+/// position and line ending; every other line, comments and bytes that are
+/// not UTF-8 included, is copied unchanged. Keys not present are appended.
+pub fn set_prop_values(content: &[u8], values: &[(&str, &str)]) -> Vec<u8> {
+    let crlf = content.windows(2).any(|w| w == b"\r\n");
+    let eol: &[u8] = if crlf { b"\r\n" } else { b"\n" };
+
+    let mut out = Vec::with_capacity(content.len() + 128);
+    let mut seen = vec![false; values.len()];
+
+    for line in content.split_inclusive(|&b| b == b'\n') {
+        let body_len = line
+            .strip_suffix(b"\r\n")
+            .or_else(|| line.strip_suffix(b"\n"))
+            .map_or(line.len(), <[u8]>::len);
+        let (body, ending) = line.split_at(body_len);
+
+        let matched = values.iter().position(|(key, _)| {
+            body.strip_prefix(key.as_bytes())
+                .is_some_and(|rest| rest.starts_with(b"="))
+        });
+        match matched {
+            Some(i) => {
+                seen[i] = true;
+                let (key, value) = values[i];
+                out.extend_from_slice(format!("{}={}", key, value).as_bytes());
+                out.extend_from_slice(ending);
+            }
+            None => out.extend_from_slice(line),
+        }
+    }
 
-    let status_emoji = match storage_mode {
-        "tmpfs" => "🐾",
-        "erofs" => "🚀",
-        _ => "💿",
-    };
+    for (i, (key, value)) in values.iter().enumerate() {
+        if seen[i] {
+            continue;
+        }
+        if !out.is_empty() && !out.ends_with(b"\n") {
+            out.extend_from_slice(eol);
+        }
+        out.extend_from_slice(format!("{}={}", key, value).as_bytes());
+        out.extend_from_slice(eol);
+    }
 
-    let desc_text = format!(
-        "description=😋 运行中喵～ ({}) {} | Overlay: {} | Magic: {}",
-        mode_str, status_emoji, overlay_count, magic_count
-    );
+    out
+}
 
-    let lines: Vec<String> = match fs::File::open(prop_path) {
-        Ok(file) => BufReader::new(file)
-            .lines()
-            .map_while(Result::ok)
-            .map(|line| {
-                if line.starts_with("description=") {
-                    desc_text.clone()
-                } else {
-                    line
-                }
-            })
-            .collect(),
+fn write_prop_values(prop_path: &Path, values: &[(&str, &str)]) {
+    let content = match fs::read(prop_path) {
+        Ok(content) => content,
         Err(_) => return,
     };
 
-    let content = lines.join("\n");
-    if let Err(e) = utils::atomic_write(prop_path, format!("{}\n", content)) {
+    if let Err(e) = utils::atomic_write(prop_path, set_prop_values(&content, values)) {
         log::warn!("Failed to update module description: {}", e);
     }
 }
+
+/// Rewrites the `description=` line of our own module.prop.
+pub fn update_description(prop_path: &Path, status: &PropStatus) {
+    write_prop_values(prop_path, &[("description", &status.description())]);
+}
+
+/// Like [`update_description`], and also sets a machine-readable `status=`
+/// key, e.g. `status=ok,overlay=5,magic=2,bind=0,storage=tmpfs,usage=43%`.
+pub fn update_status_prop(prop_path: &Path, status: &PropStatus) {
+    write_prop_values(
+        prop_path,
+        &[
+            ("description", &status.description()),
+            ("status", &status.status()),
+        ],
+    );
+}
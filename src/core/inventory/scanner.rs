@@ -9,14 +9,74 @@ use std::{
 
 use anyhow::Result;
 use rayon::prelude::*;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
+use super::{
+    encryption,
+    manifest::{self, ManifestCheck},
+};
 use crate::{
     conf::config::{self, ModuleRules, MountMode},
+    core::{bundles, staging},
     defs,
+    utils::{pool, timing},
 };
 
-fn load_module_rules(module_dir: &Path, module_id: &str, cfg: &config::Config) -> ModuleRules {
+#[derive(Deserialize)]
+struct PartialRules {
+    default_mode: Option<MountMode>,
+    paths: Option<HashMap<String, MountMode>>,
+    order: Option<i32>,
+    after: Option<Vec<String>>,
+    exclude_partitions: Option<Vec<String>>,
+    allow_rw_files: Option<bool>,
+    blacklist: Option<Vec<String>>,
+}
+
+fn apply_rules_file(rules: &mut ModuleRules, path: &Path, module_id: &str) {
+    if !path.exists() {
+        return;
+    }
+
+    match fs::read_to_string(path) {
+        Ok(content) => match serde_json::from_str::<PartialRules>(&content) {
+            Ok(partial) => {
+                if let Some(mode) = partial.default_mode {
+                    rules.default_mode = mode;
+                }
+                if let Some(paths) = partial.paths {
+                    rules.paths = paths;
+                }
+                if partial.order.is_some() {
+                    rules.order = partial.order;
+                }
+                if let Some(after) = partial.after {
+                    rules.after = after;
+                }
+                if let Some(excluded) = partial.exclude_partitions {
+                    rules.exclude_partitions = excluded;
+                }
+                if let Some(allow) = partial.allow_rw_files {
+                    rules.allow_rw_files = allow;
+                }
+                if let Some(blacklist) = partial.blacklist {
+                    rules.blacklist = blacklist;
+                }
+            }
+            Err(e) => {
+                log::warn!("Failed to parse rules for module '{}': {}", module_id, e)
+            }
+        },
+        Err(e) => log::warn!("Failed to read rule file for '{}': {}", module_id, e),
+    }
+}
+
+fn load_module_rules(
+    module_dir: &Path,
+    module_id: &str,
+    cfg: &config::Config,
+    rules_dir: &Path,
+) -> ModuleRules {
     let mut rules = ModuleRules {
         default_mode: match cfg.default_mode {
             config::DefaultMode::Overlay => MountMode::Overlay,
@@ -25,91 +85,301 @@ fn load_module_rules(module_dir: &Path, module_id: &str, cfg: &config::Config) -
         ..Default::default()
     };
 
-    #[derive(Deserialize)]
-    struct PartialRules {
-        default_mode: Option<MountMode>,
-        paths: Option<HashMap<String, MountMode>>,
-    }
-
-    let internal_config = module_dir.join("hybrid_rules.json");
-
-    if internal_config.exists() {
-        match fs::read_to_string(&internal_config) {
-            Ok(content) => match serde_json::from_str::<PartialRules>(&content) {
-                Ok(partial) => {
-                    if let Some(mode) = partial.default_mode {
-                        rules.default_mode = mode;
-                    }
-                    if let Some(paths) = partial.paths {
-                        rules.paths = paths;
-                    }
-                }
-                Err(e) => {
-                    log::warn!("Failed to parse rules for module '{}': {}", module_id, e)
-                }
-            },
-            Err(e) => log::warn!("Failed to read rule file for '{}': {}", module_id, e),
-        }
-    }
+    apply_rules_file(&mut rules, &module_dir.join("hybrid_rules.json"), module_id);
+    apply_rules_file(
+        &mut rules,
+        &rules_dir.join(format!("{}.json", module_id)),
+        module_id,
+    );
 
     if let Some(global_rules) = cfg.rules.get(module_id) {
         rules.default_mode = global_rules.default_mode.clone();
         rules.paths.extend(global_rules.paths.clone());
+        if global_rules.order.is_some() {
+            rules.order = global_rules.order;
+        }
+        for id in &global_rules.after {
+            if !rules.after.contains(id) {
+                rules.after.push(id.clone());
+            }
+        }
+        rules.allow_rw_files |= global_rules.allow_rw_files;
+        for partition in &global_rules.exclude_partitions {
+            if !rules.excludes_partition(partition) {
+                rules.exclude_partitions.push(partition.clone());
+            }
+        }
+        for pattern in &global_rules.blacklist {
+            if !rules.blacklist.contains(pattern) {
+                rules.blacklist.push(pattern.clone());
+            }
+        }
     }
 
     rules
 }
 
+fn load_mode_override(module_dir: &Path, module_id: &str) -> Option<MountMode> {
+    let mode_file = module_dir.join(defs::MOUNT_MODE_FILE_NAME);
+
+    let content = match fs::read_to_string(&mode_file) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            log::warn!("Failed to read mount_mode for '{}': {}", module_id, e);
+            return None;
+        }
+    };
+
+    match content.trim().to_ascii_lowercase().as_str() {
+        "magic" => Some(MountMode::Magic),
+        "overlay" => Some(MountMode::Overlay),
+        "hymo" | "hymofs" => Some(MountMode::Hymo),
+        "auto" | "" => None,
+        other => {
+            log::warn!(
+                "Invalid mount_mode '{}' for module '{}', treating as auto",
+                other,
+                module_id
+            );
+            None
+        }
+    }
+}
+
+/// Why a module on disk is left out of the scan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Exclusion {
+    /// Listed in the config's `exclusions`; KernelSU still sees it enabled.
+    #[serde(rename = "meta-hybrid")]
+    Config,
+    #[serde(rename = "disable file")]
+    DisableFile,
+    #[serde(rename = "skip_mount")]
+    SkipMount,
+}
+
 #[derive(Debug, Clone)]
 pub struct Module {
     pub id: String,
     pub source_path: PathBuf,
     pub rules: ModuleRules,
+    /// Mode forced by the module's `mount_mode` file; `None` means auto.
+    pub mode: Option<MountMode>,
+    /// Loaded from the staging directory instead of the module directory.
+    pub staged: bool,
+    /// `image_modules` bundle the module comes from; its id carries the
+    /// bundle name as a prefix.
+    pub bundle: Option<String>,
+    /// Result of checking `meta_hybrid_manifest.toml`.
+    pub manifest: ManifestCheck,
+    /// Symlinks whose target is on credential-encrypted storage; see
+    /// [`encryption`].
+    pub ce_refs: Vec<PathBuf>,
 }
 
-pub fn scan(source_dir: &Path, cfg: &config::Config) -> Result<Vec<Module>> {
-    if !source_dir.exists() {
-        return Ok(Vec::new());
+impl Module {
+    pub fn get_mode(&self, relative_path: &str) -> MountMode {
+        match &self.mode {
+            Some(mode) => mode.clone(),
+            None => self.rules.get_mode(relative_path),
+        }
     }
 
-    let dir_entries = fs::read_dir(source_dir)?.collect::<std::io::Result<Vec<_>>>()?;
-
-    let mut modules: Vec<Module> = dir_entries
-        .into_par_iter()
-        .filter_map(|entry| {
-            let path = entry.path();
+    /// Partitions the module ships anything for at its root: the built-in
+    /// and kernel module partitions plus `extra`, sorted. Empty for a module
+    /// that only carries scripts.
+    pub fn content_partitions(&self, extra: &[String]) -> Vec<String> {
+        let mut partitions: Vec<String> = defs::BUILTIN_PARTITIONS
+            .iter()
+            .chain(defs::DLKM_PARTITIONS)
+            .map(|p| p.to_string())
+            .chain(extra.iter().cloned())
+            .filter(|p| {
+                fs::read_dir(self.source_path.join(p))
+                    .is_ok_and(|mut entries| entries.next().is_some())
+            })
+            .collect();
+        partitions.sort();
+        partitions.dedup();
+        partitions
+    }
 
-            if !path.is_dir() {
-                return None;
+    pub fn needs_magic(&self) -> bool {
+        match &self.mode {
+            Some(mode) => *mode == MountMode::Magic,
+            None => {
+                self.rules.default_mode == MountMode::Magic
+                    || self.rules.paths.values().any(|v| *v == MountMode::Magic)
             }
+        }
+    }
+}
+
+fn exclusion(path: &Path, id: &str, cfg: &config::Config) -> Option<Exclusion> {
+    if cfg.exclusions.iter().any(|e| e == id) {
+        Some(Exclusion::Config)
+    } else if path.join(defs::DISABLE_FILE_NAME).exists() {
+        Some(Exclusion::DisableFile)
+    } else if path.join(defs::SKIP_MOUNT_FILE_NAME).exists() {
+        Some(Exclusion::SkipMount)
+    } else {
+        None
+    }
+}
 
-            let id = entry.file_name().to_string_lossy().to_string();
+/// Directories in a module directory that are never modules.
+pub(super) const IGNORED_DIR_NAMES: &[&str] =
+    &["meta-hybrid", "lost+found", ".git", ".idea", ".vscode"];
 
-            if matches!(
-                id.as_str(),
-                "meta-hybrid" | "lost+found" | ".git" | ".idea" | ".vscode"
-            ) {
-                return None;
-            }
+/// Loads a module and says whether it is excluded. Modules pending removal
+/// are gone as far as the scan is concerned.
+pub(super) fn load_module(
+    path: PathBuf,
+    id: String,
+    cfg: &config::Config,
+    rules_dir: &Path,
+    staged: bool,
+) -> Option<(Module, Option<Exclusion>)> {
+    if path.join(defs::REMOVE_FILE_NAME).exists() {
+        return None;
+    }
 
-            if path.join(defs::DISABLE_FILE_NAME).exists()
-                || path.join(defs::REMOVE_FILE_NAME).exists()
-                || path.join(defs::SKIP_MOUNT_FILE_NAME).exists()
-            {
-                return None;
-            }
+    let excluded = exclusion(&path, &id, cfg);
+    let rules = load_module_rules(&path, &id, cfg, rules_dir);
+    let mode = load_mode_override(&path, &id);
+    let manifest = manifest::check(&path);
+    let ce_refs = encryption::ce_references(&path);
 
-            let rules = load_module_rules(&path, &id, cfg);
+    Some((
+        Module {
+            id,
+            source_path: path,
+            rules,
+            mode,
+            staged,
+            bundle: None,
+            manifest,
+            ce_refs,
+        },
+        excluded,
+    ))
+}
+
+/// Enabled modules under `source_dir`, with rules merged from the module,
+/// the rules directory and `cfg`, in layering order. Staged modules replace
+/// installed ones with the same id.
+pub fn scan(source_dir: &Path, cfg: &config::Config) -> Result<Vec<Module>> {
+    scan_with_paths(source_dir, cfg, &defs::Paths::default())
+}
+
+/// Same as [`scan`] with rule files and staged modules read from `paths`.
+pub fn scan_with_paths(
+    source_dir: &Path,
+    cfg: &config::Config,
+    paths: &defs::Paths,
+) -> Result<Vec<Module>> {
+    Ok(scan_all_with_paths(source_dir, cfg, paths)?
+        .into_iter()
+        .filter(|(_, excluded)| excluded.is_none())
+        .map(|(module, _)| module)
+        .collect())
+}
+
+/// Every module [`scan`] considers, including those it skips, paired with
+/// the reason it skips them.
+pub fn scan_all(
+    source_dir: &Path,
+    cfg: &config::Config,
+) -> Result<Vec<(Module, Option<Exclusion>)>> {
+    scan_all_with_paths(source_dir, cfg, &defs::Paths::default())
+}
+
+pub fn scan_all_with_paths(
+    source_dir: &Path,
+    cfg: &config::Config,
+    paths: &defs::Paths,
+) -> Result<Vec<(Module, Option<Exclusion>)>> {
+    let mut span = timing::span("inventory");
+    let rules_dir = paths.rules_dir.as_path();
+
+    let dir_entries = if source_dir.exists() {
+        fs::read_dir(source_dir)?.collect::<std::io::Result<Vec<_>>>()?
+    } else {
+        Vec::new()
+    };
 
-            Some(Module {
-                id,
-                source_path: path,
-                rules,
+    let mut staged = staging::staged_dirs(&paths.staging_dir);
+
+    let mut modules: Vec<(Module, Option<Exclusion>)> = pool::install(|| {
+        dir_entries
+            .into_par_iter()
+            .filter_map(|entry| {
+                let path = entry.path();
+
+                if !path.is_dir() {
+                    return None;
+                }
+
+                let id = entry.file_name().to_string_lossy().to_string();
+
+                if IGNORED_DIR_NAMES.contains(&id.as_str()) {
+                    return None;
+                }
+
+                if staged.contains_key(&id) {
+                    return None;
+                }
+
+                load_module(path, id, cfg, rules_dir, false)
             })
-        })
-        .collect();
+            .collect()
+    });
+
+    modules.extend(
+        staged
+            .drain()
+            .filter_map(|(id, path)| load_module(path, id, cfg, rules_dir, true)),
+    );
 
-    modules.sort_by(|a, b| b.id.cmp(&a.id));
+    for (bundle, dir) in bundles::sources(&cfg.image_modules, &paths.bundles_dir) {
+        modules.extend(bundle_modules(&bundle, &dir, cfg, rules_dir));
+    }
+
+    sort_modules(&mut modules);
+    span.items(modules.len());
 
     Ok(modules)
 }
+
+/// Modules in the mounted bundle `bundle` at `dir`, with their ids prefixed
+/// by the bundle name.
+fn bundle_modules(
+    bundle: &str,
+    dir: &Path,
+    cfg: &config::Config,
+    rules_dir: &Path,
+) -> Vec<(Module, Option<Exclusion>)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    entries
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if IGNORED_DIR_NAMES.contains(&name.as_str()) {
+                return None;
+            }
+            let id = bundles::module_id(bundle, &name);
+            let (mut module, excluded) = load_module(entry.path(), id, cfg, rules_dir, false)?;
+            module.bundle = Some(bundle.to_string());
+            Some((module, excluded))
+        })
+        .collect()
+}
+
+pub(super) fn sort_modules(modules: &mut [(Module, Option<Exclusion>)]) {
+    modules.sort_by(|(a, _), (b, _)| b.id.cmp(&a.id));
+}
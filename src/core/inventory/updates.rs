@@ -0,0 +1,130 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Module updates KernelSU stages in `modules_update/<id>` until the next
+//! reboot, when they replace `modules/<id>`.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use super::{
+    model::ModuleProp,
+    scanner::{self, Exclusion, IGNORED_DIR_NAMES, Module},
+};
+use crate::{conf::config, defs};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateKind {
+    /// A newer copy of an installed module.
+    Update,
+    /// A module installed since boot that has no live copy yet.
+    Install,
+}
+
+/// An update waiting for the reboot.
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingUpdate {
+    pub kind: UpdateKind,
+    /// `version` of the installed module.prop; absent for an install.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_version: Option<String>,
+    /// `version` of the staged module.prop.
+    pub new_version: String,
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+/// The update directory next to `moduledir`.
+pub fn update_dir(moduledir: &Path) -> PathBuf {
+    moduledir.with_file_name(defs::MODULES_UPDATE_DIR_NAME)
+}
+
+/// Updates staged for modules under `moduledir`, keyed by module id.
+pub fn pending(moduledir: &Path) -> BTreeMap<String, PendingUpdate> {
+    let Ok(entries) = fs::read_dir(update_dir(moduledir)) else {
+        return BTreeMap::new();
+    };
+
+    entries
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| {
+            let id = entry.file_name().to_string_lossy().to_string();
+            if IGNORED_DIR_NAMES.contains(&id.as_str()) {
+                return None;
+            }
+            let path = entry.path();
+            let new_prop = path.join("module.prop");
+            if !new_prop.is_file() {
+                return None;
+            }
+
+            let current_prop = moduledir.join(&id).join("module.prop");
+            let current_version = current_prop
+                .is_file()
+                .then(|| ModuleProp::from(current_prop.as_path()).version);
+            let update = PendingUpdate {
+                kind: if current_version.is_some() {
+                    UpdateKind::Update
+                } else {
+                    UpdateKind::Install
+                },
+                current_version,
+                new_version: ModuleProp::from(new_prop.as_path()).version,
+                path,
+            };
+            Some((id, update))
+        })
+        .collect()
+}
+
+/// Every module as the next boot will see it: [`scanner::scan_all`] with
+/// each pending update in place of the installed copy and pending installs
+/// added. Staged modules still win over both.
+pub fn scan_all_updated_with_paths(
+    source_dir: &Path,
+    cfg: &config::Config,
+    paths: &defs::Paths,
+) -> Result<Vec<(Module, Option<Exclusion>)>> {
+    let mut modules = scanner::scan_all_with_paths(source_dir, cfg, paths)?;
+
+    for (id, update) in pending(source_dir) {
+        let existing = modules.iter().position(|(m, _)| m.id == id);
+        if existing.is_some_and(|i| modules[i].0.staged) {
+            continue;
+        }
+        let updated = scanner::load_module(update.path, id, cfg, &paths.rules_dir, false);
+        match (existing, updated) {
+            (Some(i), Some(updated)) => modules[i] = updated,
+            // The update marks the module for removal.
+            (Some(i), None) => {
+                modules.remove(i);
+            }
+            (None, Some(updated)) => modules.push(updated),
+            (None, None) => {}
+        }
+    }
+
+    scanner::sort_modules(&mut modules);
+    Ok(modules)
+}
+
+/// Enabled modules as the next boot will see them.
+pub fn scan_updated_with_paths(
+    source_dir: &Path,
+    cfg: &config::Config,
+    paths: &defs::Paths,
+) -> Result<Vec<Module>> {
+    Ok(scan_all_updated_with_paths(source_dir, cfg, paths)?
+        .into_iter()
+        .filter(|(_, excluded)| excluded.is_none())
+        .map(|(module, _)| module)
+        .collect())
+}
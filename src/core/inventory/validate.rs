@@ -0,0 +1,195 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{fs, path::Path};
+
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use super::{Module, kmod};
+use crate::{
+    core::ops::planner::{DiagnosticIssue, DiagnosticLevel},
+    utils,
+};
+
+const NAME_MAX: usize = 255;
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IssueSeverity {
+    Warning,
+    Critical,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModuleIssue {
+    pub severity: IssueSeverity,
+    pub message: String,
+}
+
+impl ModuleIssue {
+    fn warning(message: impl Into<String>) -> Self {
+        Self {
+            severity: IssueSeverity::Warning,
+            message: message.into(),
+        }
+    }
+
+    fn critical(message: impl Into<String>) -> Self {
+        Self {
+            severity: IssueSeverity::Critical,
+            message: message.into(),
+        }
+    }
+}
+
+fn check_prop(path: &Path, issues: &mut Vec<ModuleIssue>) {
+    let prop_path = path.join("module.prop");
+    let raw = match fs::read(&prop_path) {
+        Ok(raw) => raw,
+        Err(e) => {
+            issues.push(ModuleIssue::critical(format!(
+                "module.prop is unreadable ({}); magic mount will skip this module",
+                e
+            )));
+            return;
+        }
+    };
+
+    if raw.starts_with(UTF8_BOM) {
+        issues.push(ModuleIssue::warning("module.prop starts with a UTF-8 BOM"));
+    }
+    if raw.windows(2).any(|w| w == b"\r\n") {
+        issues.push(ModuleIssue::warning("module.prop uses CRLF line endings"));
+    }
+
+    let content = String::from_utf8_lossy(raw.strip_prefix(UTF8_BOM).unwrap_or(&raw));
+    let value = |key: &str| {
+        content.lines().find_map(|line| {
+            line.trim_end_matches('\r')
+                .split_once('=')
+                .filter(|(k, _)| k.trim() == key)
+                .map(|(_, v)| v.trim().to_string())
+        })
+    };
+
+    let dir_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    match value("id") {
+        None => issues.push(ModuleIssue::critical("module.prop has no id= entry")),
+        Some(id) => {
+            if id != dir_name {
+                issues.push(ModuleIssue::critical(format!(
+                    "id={} does not match directory name {}",
+                    id, dir_name
+                )));
+            }
+            if let Err(e) = utils::validate_module_id(&id) {
+                issues.push(ModuleIssue::critical(e.to_string()));
+            }
+        }
+    }
+
+    match value("version") {
+        Some(v) if !v.is_empty() => {}
+        _ => issues.push(ModuleIssue::warning("module.prop has no version")),
+    }
+
+    match value("versionCode") {
+        None => issues.push(ModuleIssue::warning("module.prop has no versionCode")),
+        Some(code) if code.parse::<u64>().is_err() => issues.push(ModuleIssue::warning(format!(
+            "versionCode '{}' is not a non-negative integer",
+            code
+        ))),
+        Some(_) => {}
+    }
+}
+
+fn check_names(path: &Path, issues: &mut Vec<ModuleIssue>) {
+    for entry in WalkDir::new(path).min_depth(1).into_iter().flatten() {
+        let name = entry.file_name().as_encoded_bytes();
+        let relative = entry.path().strip_prefix(path).unwrap_or(entry.path());
+
+        if name.contains(&b'\n') {
+            issues.push(ModuleIssue::warning(format!(
+                "File name contains a newline: {:?}",
+                relative
+            )));
+        }
+        if name.len() > NAME_MAX {
+            issues.push(ModuleIssue::warning(format!(
+                "File name exceeds {} bytes: {}",
+                NAME_MAX,
+                relative.display()
+            )));
+        }
+    }
+}
+
+/// Checks the module.prop of the module at `path`. Cheap enough for every
+/// module listing; the file tree is only walked by [`validate_module`].
+pub fn validate_prop(path: &Path) -> Vec<ModuleIssue> {
+    let mut issues = Vec::new();
+    check_prop(path, &mut issues);
+    issues
+}
+
+/// Checks module.prop and the file tree of the module at `path` for problems
+/// that would otherwise only surface as confusing mount behaviour.
+pub fn validate_module(path: &Path) -> Vec<ModuleIssue> {
+    let mut issues = validate_prop(path);
+    check_names(path, &mut issues);
+    issues
+}
+
+pub fn diagnose(modules: &[Module]) -> Vec<DiagnosticIssue> {
+    let release = kmod::kernel_release();
+    modules
+        .iter()
+        .flat_map(|module| {
+            let vermagic = release
+                .as_deref()
+                .map(|release| {
+                    kmod::check(&module.source_path, release)
+                        .into_iter()
+                        .map(|m| DiagnosticIssue {
+                            level: DiagnosticLevel::Warning,
+                            context: module.id.clone(),
+                            message: format!(
+                                "Kernel module {} was built for '{}' but the running kernel is {}; it will not load",
+                                m.relative, m.vermagic, release
+                            ),
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+
+            validate_module(&module.source_path)
+                .into_iter()
+                .map(|issue| DiagnosticIssue {
+                    level: match issue.severity {
+                        IssueSeverity::Warning => DiagnosticLevel::Warning,
+                        IssueSeverity::Critical => DiagnosticLevel::Critical,
+                    },
+                    context: module.id.clone(),
+                    message: issue.message,
+                })
+                .chain(
+                    module
+                        .manifest
+                        .issues
+                        .iter()
+                        .map(|message| DiagnosticIssue {
+                            level: DiagnosticLevel::Warning,
+                            context: module.id.clone(),
+                            message: format!("Manifest: {}", message),
+                        }),
+                )
+                .chain(vermagic)
+        })
+        .collect()
+}
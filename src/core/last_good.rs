@@ -0,0 +1,154 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! The plan of the last mount run that reached finalize, replayed to recover
+//! from a bad module update.
+//!
+//! Every completed run records which modules it mounted and how, the overlay
+//! targets, a fingerprint of each module's `module.prop` and the config it
+//! ran with. A replay uses that config and only the recorded modules whose
+//! fingerprint still matches, so modules installed or updated since are left
+//! out.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    conf::config::Config,
+    core::{inventory::Module, ops::planner::MountPlan},
+    utils,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RecordedModule {
+    /// `overlay`, `magic`, `hymo` or `bind`.
+    pub mode: String,
+    /// Sha256 of the module's `module.prop`; empty when it had none.
+    pub prop_sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LastGoodPlan {
+    pub timestamp: u64,
+    pub modules: BTreeMap<String, RecordedModule>,
+    /// Overlay mount targets of the run.
+    pub targets: Vec<String>,
+    pub config: Config,
+}
+
+fn prop_fingerprint(module: &Module) -> String {
+    match fs::read(module.source_path.join("module.prop")) {
+        Ok(content) => Sha256::digest(content)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+        Err(_) => String::new(),
+    }
+}
+
+impl LastGoodPlan {
+    /// Records a completed run. `mounted` gives the module ids per mode, as
+    /// `(mode, ids)`; modules not among `modules` are ignored.
+    pub fn record<'a>(
+        modules: &[Module],
+        mounted: impl IntoIterator<Item = (&'a str, &'a [String])>,
+        plan: &MountPlan,
+        config: &Config,
+    ) -> Self {
+        let mut recorded = BTreeMap::new();
+        for (mode, ids) in mounted {
+            for id in ids {
+                if let Some(module) = modules.iter().find(|m| &m.id == id) {
+                    recorded.insert(
+                        id.clone(),
+                        RecordedModule {
+                            mode: mode.to_string(),
+                            prop_sha256: prop_fingerprint(module),
+                        },
+                    );
+                }
+            }
+        }
+
+        let targets: BTreeSet<String> = plan
+            .overlay_ops
+            .iter()
+            .map(|op| op.target.clone())
+            .collect();
+
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            modules: recorded,
+            targets: targets.into_iter().collect(),
+            config: config.clone(),
+        }
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        if let Some(parent) = path.parent() {
+            utils::ensure_dir_exists(parent)?;
+        }
+        utils::atomic_write(path, json)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("No last good plan at {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse last good plan {}", path.display()))
+    }
+
+    /// Keeps the recorded modules that are unchanged since the record.
+    /// Recorded modules that are gone are only warned about.
+    pub fn restrict(&self, modules: Vec<Module>) -> Vec<Module> {
+        for id in self.modules.keys() {
+            if !modules.iter().any(|m| &m.id == id) {
+                log::warn!("Last good plan: {} is no longer installed, dropping it", id);
+            }
+        }
+
+        modules
+            .into_iter()
+            .filter(|m| match self.modules.get(&m.id) {
+                None => {
+                    log::info!("Last good plan: skipping {}, not in the record", m.id);
+                    false
+                }
+                Some(recorded) if recorded.prop_sha256 != prop_fingerprint(m) => {
+                    log::warn!(
+                        "Last good plan: skipping {}, changed since the record",
+                        m.id
+                    );
+                    false
+                }
+                Some(_) => true,
+            })
+            .collect()
+    }
+
+    /// Drops overlay operations on targets the recorded run did not mount.
+    pub fn restrict_plan(&self, plan: &mut MountPlan) {
+        plan.overlay_ops.retain(|op| {
+            let known = self.targets.contains(&op.target);
+            if !known {
+                log::warn!(
+                    "Last good plan: not mounting {}, it was not a target of the record",
+                    op.target
+                );
+            }
+            known
+        });
+    }
+}
@@ -1,19 +1,38 @@
 // Copyright 2026 Hybrid Mount Developers
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::path::Path;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
+use rustix::mount::{UnmountFlags, unmount};
 
 use crate::{
-    conf::config::Config,
+    conf::config::{BootPriority, Config, LastGoodPolicy, TmpfsOverflow},
     core::{
+        bootloop, bundles, deferred,
+        integrity::{self, IntegrityReport, Manifest, VerifyLimits},
         inventory,
         inventory::model as modules,
-        ops::{executor, planner, sync},
+        last_good::LastGoodPlan,
+        ops::{audit, blacklist::Blacklist, executor, planner, sync},
         state, storage,
         storage::{StorageHandle, get_usage},
+        summary, winnow,
+    },
+    defs::{self, Paths},
+    sys::{
+        denylist,
+        loopdev::{self, LoopOptions},
+        mount::is_mounted,
+        namespace::NamespaceReport,
+        priority::Throttle,
+        root_backend, susfs,
     },
+    utils::{self, cancel, progress, timing, trace},
 };
 
 pub struct Init;
@@ -25,60 +44,233 @@ pub struct StorageReady {
 pub struct ModulesReady {
     pub handle: StorageHandle,
     pub modules: Vec<inventory::Module>,
+    /// Modules held back for `mount-deferred`.
+    pub deferred: Vec<String>,
+    pub sync_summary: sync::SyncSummary,
+    pub context_audit: Option<audit::ContextAudit>,
 }
 
 pub struct Planned {
     pub handle: StorageHandle,
     pub modules: Vec<inventory::Module>,
+    pub deferred: Vec<String>,
     pub plan: planner::MountPlan,
+    pub sync_summary: sync::SyncSummary,
+    pub context_audit: Option<audit::ContextAudit>,
 }
 
 pub struct Executed {
     pub handle: StorageHandle,
-    #[allow(dead_code)]
     pub modules: Vec<inventory::Module>,
+    pub deferred: Vec<String>,
     pub plan: planner::MountPlan,
     pub result: executor::ExecutionResult,
+    pub sync_summary: sync::SyncSummary,
+    pub context_audit: Option<audit::ContextAudit>,
 }
 
+/// Drives one boot-time mount sequence. Each step consumes the controller
+/// and returns it in the next state, so steps can only run in order.
 pub struct MountController<S> {
     config: Config,
+    paths: Paths,
+    safe_mode: bool,
+    /// The recorded plan this run replays instead of a fresh scan.
+    last_good: Option<LastGoodPlan>,
+    /// Lowered priority of the storage and sync phases, restored before
+    /// mounting.
+    throttle: Throttle,
+    /// Start of the run; `mount_deadline_secs` is measured from here.
+    started: Instant,
+    /// Loop devices that were attached before storage setup, which a
+    /// cancelled run must not detach.
+    loops_before: HashSet<String>,
+    /// Mount namespace the process was moved into before the run.
+    namespace: NamespaceReport,
     state: S,
 }
 
+impl<S> MountController<S> {
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn paths(&self) -> &Paths {
+        &self.paths
+    }
+
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    /// Whether this run only mounts `safe_modules` after a boot loop.
+    pub fn safe_mode(&self) -> bool {
+        self.safe_mode
+    }
+
+    /// Whether this run replays the last good plan.
+    pub fn replaying_last_good(&self) -> bool {
+        self.last_good.is_some()
+    }
+}
+
 impl MountController<Init> {
     pub fn new(config: Config) -> Self {
+        Self::with_paths(config, Paths::default())
+    }
+
+    /// Creates a controller that keeps its state, journal and reports under
+    /// `paths` instead of the device defaults.
+    pub fn with_paths(config: Config, paths: Paths) -> Self {
         Self {
             config,
+            paths,
+            safe_mode: false,
+            last_good: None,
+            throttle: Throttle::apply(BootPriority::Normal),
+            started: Instant::now(),
+            loops_before: HashSet::new(),
+            namespace: NamespaceReport::default(),
             state: Init,
         }
     }
 
+    /// Records that the process already mounts in `namespace`, see
+    /// `sys::namespace::enter_clone`.
+    pub fn in_namespace(mut self, namespace: NamespaceReport) -> Self {
+        self.namespace = namespace;
+        self
+    }
+
     pub fn init_storage(
-        self,
+        mut self,
         mnt_base: &Path,
         img_path: &Path,
     ) -> Result<MountController<StorageReady>> {
-        let handle = storage::setup(
+        let _phase = utils::enter_phase("storage");
+        let _span = timing::span("storage");
+
+        let mut boot_loop = false;
+        match bootloop::begin_attempt(&self.paths.run_dir) {
+            Ok(unfinished) if unfinished > 0 => {
+                log::warn!(
+                    "!! {} previous mount attempt(s) did not complete.",
+                    unfinished
+                );
+                let threshold = self.config.safe_mode_threshold;
+                boot_loop = threshold > 0 && unfinished >= threshold;
+            }
+            Ok(_) => {}
+            Err(e) => log::warn!("Failed to record mount attempt: {:#}", e),
+        }
+
+        let replay = match self.config.use_last_good {
+            LastGoodPolicy::Never => false,
+            LastGoodPolicy::OnBootLoop => boot_loop,
+            LastGoodPolicy::Always => true,
+        };
+        if replay {
+            match LastGoodPlan::load_from(&self.paths.last_good_plan_file) {
+                Ok(record) => {
+                    log::warn!(
+                        "!! Replaying the last good plan ({} modules) with its config",
+                        record.modules.len()
+                    );
+                    let policy = self.config.use_last_good;
+                    self.config = record.config.clone();
+                    self.config.use_last_good = policy;
+                    self.last_good = Some(record);
+                    boot_loop = false;
+                }
+                Err(e) => log::warn!("Cannot replay the last good plan: {:#}", e),
+            }
+        }
+
+        if boot_loop {
+            log::warn!(
+                "!! BOOT LOOP DETECTED: entering safe mode, mounting only {:?}",
+                self.config.safe_modules
+            );
+            self.safe_mode = true;
+        }
+
+        self.throttle = Throttle::apply(self.config.boot_priority);
+
+        let storage_mode = self.config.effective_storage_mode();
+        progress::emit("storage", None, 0, 1);
+
+        self.loops_before = loopdev::owned_names(Path::new(defs::BASE_DIR));
+
+        let tmpfs_cap = match storage_mode {
+            crate::conf::config::OverlayMode::Tmpfs => Some(storage::check_tmpfs_cap(
+                &self.config.moduledir,
+                self.config.tmpfs_max_mb,
+                self.config.tmpfs_overflow,
+            )?),
+            _ => None,
+        };
+        let overflow = tmpfs_cap.as_ref().and_then(|cap| cap.overflow);
+
+        let mut handle = storage::setup(
             mnt_base,
             img_path,
+            &self.paths.run_dir,
             &self.config.moduledir,
-            matches!(
-                self.config.overlay_mode,
-                crate::conf::config::OverlayMode::Ext4
-            ),
-            matches!(
-                self.config.overlay_mode,
-                crate::conf::config::OverlayMode::Erofs
-            ),
+            &self.paths.staging_dir,
+            matches!(storage_mode, crate::conf::config::OverlayMode::Ext4)
+                || overflow == Some(TmpfsOverflow::Ext4),
+            matches!(storage_mode, crate::conf::config::OverlayMode::Erofs),
+            matches!(storage_mode, crate::conf::config::OverlayMode::Zram),
             &self.config.mountsource,
             self.config.disable_umount,
+            self.config.force_rebuild_image,
+            self.config.ext4_reserved_blocks_percent,
+            &self.config.storage,
+            &LoopOptions::from_config(&self.config),
+            Duration::from_secs(self.config.data_rw_wait_secs),
         )?;
 
+        if handle.mode == "tmpfs" {
+            let mut report = tmpfs_cap.unwrap_or_default();
+            // A read-only `/data` keeps tmpfs even when an image was asked for.
+            if report.overflow == Some(TmpfsOverflow::Ext4) {
+                log::warn!("!! Cannot leave tmpfs for an ext4 image, trimming modules instead");
+                report.overflow = Some(TmpfsOverflow::Trim);
+            }
+            handle.tmpfs = Some(report);
+        } else {
+            // Kept so `storage` can say why an image was used.
+            handle.tmpfs = tmpfs_cap.filter(|report| report.overflow.is_some());
+        }
+
+        handle.bundles = bundles::mount_all(
+            &self.config.image_modules,
+            &self.paths.bundles_dir,
+            &LoopOptions::from_config(&self.config),
+            !self.config.disable_umount,
+        );
+        if !handle.bundles.is_empty()
+            && let Some(manifest) = handle.manifest.take()
+        {
+            handle.manifest = Some(format!(
+                "{}+{}",
+                manifest,
+                bundles::fingerprint(&handle.bundles)
+            ));
+        }
+
         log::info!(">> Storage Backend: [{}]", handle.mode.to_uppercase());
+        progress::emit("storage", None, 1, 1);
 
         Ok(MountController {
             config: self.config,
+            paths: self.paths,
+            safe_mode: self.safe_mode,
+            last_good: self.last_good,
+            throttle: self.throttle,
+            started: self.started,
+            loops_before: self.loops_before,
+            namespace: self.namespace,
             state: StorageReady { handle },
         })
     }
@@ -86,23 +278,129 @@ impl MountController<Init> {
 
 impl MountController<StorageReady> {
     pub fn scan_and_sync(mut self) -> Result<MountController<ModulesReady>> {
-        let modules = inventory::scan(&self.config.moduledir, &self.config)?;
+        if let Some(signal) = cancel::requested() {
+            return Err(cancel_run(
+                &self.config,
+                &self.paths,
+                &self.loops_before,
+                &self.namespace,
+                self.state.handle,
+                None,
+                None,
+                signal,
+            ));
+        }
+
+        let phase = utils::enter_phase("inventory");
+
+        let mut modules =
+            inventory::scan_with_paths(&self.config.moduledir, &self.config, &self.paths)
+                .map_err(|e| abandon(&mut self.state.handle, e))?;
+
+        if let Some(record) = &self.last_good {
+            modules = record.restrict(modules);
+        } else if self.safe_mode {
+            modules.retain(|m| self.config.safe_modules.contains(&m.id));
+        }
+
+        // A list left by an earlier run must not be mounted after this one.
+        if let Err(e) = deferred::clear(&self.paths.deferred_file) {
+            log::warn!("Failed to clear the deferred module list: {:#}", e);
+        }
+        let deferred = if self.config.defer_ce_modules {
+            deferred::hold_back(&mut modules)
+        } else {
+            Vec::new()
+        };
 
         log::info!(
             ">> Inventory Scan: Found {} enabled modules.",
             modules.len()
         );
 
-        sync::perform_sync(&modules, &self.state.handle.mount_point)?;
+        drop(phase);
+        let _phase = utils::enter_phase("sync");
+
+        if let Some(report) = &mut self.state.handle.tmpfs
+            && report.overflow == Some(TmpfsOverflow::Trim)
+        {
+            let sizes = storage::module_sizes(&modules);
+            report.trimmed = storage::trim_to_cap(&sizes, report.cap_bytes);
+            for (id, size) in sizes.iter().filter(|(id, _)| report.trimmed.contains(id)) {
+                log::warn!(
+                    "!! Leaving out {} ({} MiB) to stay under tmpfs_max_mb",
+                    id,
+                    size.div_ceil(1024 * 1024)
+                );
+            }
+            modules.retain(|m| !report.trimmed.contains(&m.id));
+        }
+
+        if self.config.verify_storage {
+            let report = self.verify_storage(&modules);
+            modules.retain(|m| !report.failed.contains_key(&m.id));
+            self.state.handle.integrity = Some(report);
+        }
+
+        if let Err(e) = storage::ensure_capacity(
+            &mut self.state.handle,
+            storage::required_bytes(&modules),
+            self.config.auto_shrink,
+            &self.config.storage,
+            &LoopOptions::from_config(&self.config),
+        ) {
+            log::warn!("Storage capacity check failed: {:#}", e);
+        }
+
+        let stock_root = self.config.skip_identical_files.then_some(Path::new("/"));
+        let sync_summary = sync::perform_sync_with_stock(
+            &modules,
+            &self.state.handle.mount_point,
+            self.config.dedup_min_size,
+            stock_root,
+            &Blacklist::new(&self.config, &modules),
+        )
+        .map_err(|e| abandon(&mut self.state.handle, e))?;
+        // A half-synced copy can miss files the rest of the module needs.
+        modules.retain(|m| !sync_summary.dirty.contains(&m.id));
+        if let Some(manifest) = self.state.handle.manifest.take() {
+            self.state.handle.manifest = Some(format!(
+                "{}+{}",
+                manifest,
+                storage::sync_fingerprint(&self.config, &modules, stock_root)
+            ));
+        }
+        self.update_integrity_manifest(&modules, &sync_summary.changed);
+
+        if let Some(report) = &mut self.state.handle.tmpfs
+            && self.state.handle.mode == "tmpfs"
+        {
+            report.used_bytes = get_usage(&self.state.handle.mount_point).1;
+            log::info!(
+                ">> tmpfs storage holds {} MiB{}",
+                report.used_bytes.div_ceil(1024 * 1024),
+                match report.cap_bytes {
+                    0 => String::new(),
+                    cap => format!(" of a {} MiB cap", cap / (1024 * 1024)),
+                }
+            );
+        }
+
+        if let Some(signal) = cancel::requested() {
+            return Err(cancel_run(
+                &self.config,
+                &self.paths,
+                &self.loops_before,
+                &self.namespace,
+                self.state.handle,
+                Some(sync_summary),
+                None,
+                signal,
+            ));
+        }
 
         if self.state.handle.mode == "erofs_staging" {
-            let needs_magic = modules.iter().any(|m| {
-                m.rules.default_mode == inventory::MountMode::Magic
-                    || m.rules
-                        .paths
-                        .values()
-                        .any(|v| *v == inventory::MountMode::Magic)
-            });
+            let needs_magic = modules.iter().any(|m| m.needs_magic());
 
             if needs_magic {
                 let magic_ws = self.state.handle.mount_point.join("magic_workspace");
@@ -112,50 +410,201 @@ impl MountController<StorageReady> {
             }
         }
 
-        self.state.handle.commit(self.config.disable_umount)?;
+        self.state
+            .handle
+            .commit(self.config.disable_umount)
+            .map_err(|e| abandon(&mut self.state.handle, e))?;
+
+        let context_audit = self.config.selinux_audit.then(|| {
+            audit::check_contexts(&self.state.handle.mount_point, &self.config.partitions)
+        });
 
         Ok(MountController {
             config: self.config,
+            paths: self.paths,
+            safe_mode: self.safe_mode,
+            last_good: self.last_good,
+            throttle: self.throttle,
+            started: self.started,
+            loops_before: self.loops_before,
+            namespace: self.namespace,
             state: ModulesReady {
                 handle: self.state.handle,
                 modules,
+                deferred,
+                sync_summary,
+                context_audit,
             },
         })
     }
+
+    /// Checks the module copies against the manifest the last sync left.
+    /// Only the ext4 image keeps them between boots.
+    fn verify_storage(&self, modules: &[inventory::Module]) -> IntegrityReport {
+        let _span = timing::span("integrity");
+        if self.state.handle.mode != "ext4" {
+            return IntegrityReport::skipped("storage is rebuilt every boot");
+        }
+        let Some(manifest) = Manifest::load(&self.paths.integrity_file) else {
+            return IntegrityReport::skipped("no manifest yet");
+        };
+
+        let report = integrity::verify(
+            &manifest,
+            &self.state.handle.mount_point,
+            modules,
+            VerifyLimits::from_config(&self.config),
+        );
+        log::info!(
+            ">> Storage integrity: {:?}, {} of {} files hashed in {} ms",
+            report.status,
+            report.hashed,
+            report.total,
+            report.elapsed_ms
+        );
+        report
+    }
+
+    /// Rehashes the copies sync changed. Without `verify_storage` any
+    /// manifest is removed, as the copies may change unrecorded until it is
+    /// enabled again.
+    fn update_integrity_manifest(&self, modules: &[inventory::Module], changed: &[String]) {
+        let file = &self.paths.integrity_file;
+        if !self.config.verify_storage || self.state.handle.mode != "ext4" {
+            if file.exists()
+                && let Err(e) = std::fs::remove_file(file)
+            {
+                log::warn!("Failed to remove the integrity manifest: {}", e);
+            }
+            return;
+        }
+
+        let mut manifest = Manifest::load(file).unwrap_or_default();
+        let rehashed = manifest.update(&self.state.handle.mount_point, modules, changed);
+        if !rehashed.is_empty() {
+            log::info!(">> Integrity manifest updated for: {}", rehashed.join(", "));
+        }
+        if let Err(e) = manifest.save(file) {
+            log::warn!("Failed to save the integrity manifest: {:#}", e);
+        }
+    }
 }
 
 impl MountController<ModulesReady> {
-    pub fn generate_plan(self) -> Result<MountController<Planned>> {
-        let plan = planner::generate(
+    pub fn generate_plan(mut self) -> Result<MountController<Planned>> {
+        let _phase = utils::enter_phase("plan");
+
+        if let Some(signal) = cancel::requested() {
+            return Err(cancel_run(
+                &self.config,
+                &self.paths,
+                &self.loops_before,
+                &self.namespace,
+                self.state.handle,
+                Some(self.state.sync_summary),
+                None,
+                signal,
+            ));
+        }
+
+        let mut plan = planner::generate(
             &self.config,
             &self.state.modules,
             &self.state.handle.mount_point,
-        )?;
+        )
+        .map_err(|e| abandon(&mut self.state.handle, e))?;
+
+        if let Some(record) = &self.last_good {
+            record.restrict_plan(&mut plan);
+        }
 
         Ok(MountController {
             config: self.config,
+            paths: self.paths,
+            safe_mode: self.safe_mode,
+            last_good: self.last_good,
+            throttle: self.throttle,
+            started: self.started,
+            loops_before: self.loops_before,
+            namespace: self.namespace,
             state: Planned {
                 handle: self.state.handle,
                 modules: self.state.modules,
+                deferred: self.state.deferred,
                 plan,
+                sync_summary: self.state.sync_summary,
+                context_audit: self.state.context_audit,
             },
         })
     }
 }
 
 impl MountController<Planned> {
-    pub fn execute(self) -> Result<MountController<Executed>> {
+    pub fn execute(mut self) -> Result<MountController<Executed>> {
+        let _phase = utils::enter_phase("execute");
+
+        // Mount syscalls are latency-sensitive; never run them throttled.
+        self.throttle.restore();
+
+        if let Some(signal) = cancel::requested() {
+            return Err(cancel_run(
+                &self.config,
+                &self.paths,
+                &self.loops_before,
+                &self.namespace,
+                self.state.handle,
+                Some(self.state.sync_summary),
+                None,
+                signal,
+            ));
+        }
+
         log::info!(">> Link Start! Executing mount plan...");
 
-        let result = executor::execute(&self.state.plan, &self.config)?;
+        if self.config.trace_mounts
+            && let Err(e) = trace::start(&self.paths.trace_file)
+        {
+            log::warn!("Mount tracing disabled: {:#}", e);
+        }
+
+        let deadline = (self.config.mount_deadline_secs > 0)
+            .then(|| self.started + Duration::from_secs(self.config.mount_deadline_secs));
+
+        let result =
+            executor::execute_with_paths(&self.state.plan, &self.config, &self.paths, deadline);
+        trace::stop();
+        let result = result?;
+
+        if let Some(signal) = result.cancelled {
+            return Err(cancel_run(
+                &self.config,
+                &self.paths,
+                &self.loops_before,
+                &self.namespace,
+                self.state.handle,
+                Some(self.state.sync_summary),
+                Some(result),
+                signal,
+            ));
+        }
 
         Ok(MountController {
             config: self.config,
+            paths: self.paths,
+            safe_mode: self.safe_mode,
+            last_good: self.last_good,
+            throttle: self.throttle,
+            started: self.started,
+            loops_before: self.loops_before,
+            namespace: self.namespace,
             state: Executed {
                 handle: self.state.handle,
                 modules: self.state.modules,
+                deferred: self.state.deferred,
                 plan: self.state.plan,
                 result,
+                sync_summary: self.state.sync_summary,
+                context_audit: self.state.context_audit,
             },
         })
     }
@@ -163,13 +612,54 @@ impl MountController<Planned> {
 
 impl MountController<Executed> {
     pub fn finalize(self) -> Result<()> {
-        modules::update_description(
-            &self.state.handle.mode,
-            self.state.result.overlay_module_ids.len(),
-            self.state.result.magic_module_ids.len(),
-        );
+        let _phase = utils::enter_phase("finalize");
+        let span = timing::span("finalize");
 
         let storage_stats = get_usage(&self.state.handle.mount_point);
+        let conflicts_resolved =
+            winnow::resolved_conflicts(&self.state.plan, &self.config.winnowing);
+
+        executor::log_partition_stats(&self.state.result.per_partition);
+
+        if let Some(contexts) = self.state.context_audit {
+            let mut mount_points: Vec<String> = self
+                .state
+                .plan
+                .overlay_ops
+                .iter()
+                .map(|op| op.target.clone())
+                .collect();
+            mount_points.extend(
+                self.state
+                    .result
+                    .per_partition
+                    .keys()
+                    .map(|p| format!("/{}", p)),
+            );
+            mount_points.push(self.config.hybrid_mnt_dir.clone());
+
+            if let Err(e) = audit::finish(contexts, &mount_points, &self.paths.selinux_report_file)
+            {
+                log::warn!("Failed to write SELinux audit report: {:#}", e);
+            }
+        }
+
+        let (susfs_active, susfs_hidden) = if self.config.susfs_hide_paths {
+            let mut paths = vec![self.state.handle.mount_point.clone()];
+            paths.extend(self.state.handle.backing_image.clone());
+            paths.push(PathBuf::from(defs::BASE_DIR.trim_end_matches('/')));
+            susfs::hide_paths(&paths)
+        } else {
+            (false, Vec::new())
+        };
+
+        // Hides the loop-mounted image from /sys/fs/ext4, as KernelSU does
+        // for its own modules.img.
+        if self.state.handle.mode == "ext4"
+            && let Err(e) = root_backend::active().nuke_sysfs(&self.state.handle.mount_point)
+        {
+            log::warn!("Failed to nuke ext4 sysfs: {:#}", e);
+        }
 
         let mut active_mounts: Vec<String> = self
             .state
@@ -182,21 +672,254 @@ impl MountController<Executed> {
         active_mounts.sort();
         active_mounts.dedup();
 
-        let state = state::RuntimeState::new(
+        let image_builder = self
+            .state
+            .handle
+            .image_builder
+            .as_ref()
+            .map(|b| b.name().to_string());
+
+        // Only a full run is worth replaying: safe mode and degraded runs
+        // mounted less than asked, and a replay is already the record.
+        if !self.safe_mode && self.last_good.is_none() && !self.state.result.degraded {
+            let result = &self.state.result;
+            let record = LastGoodPlan::record(
+                &self.state.modules,
+                [
+                    ("overlay", &result.overlay_module_ids[..]),
+                    ("magic", &result.magic_module_ids[..]),
+                    ("hymo", &result.hymo_module_ids[..]),
+                    ("bind", &result.bind_module_ids[..]),
+                ],
+                &self.state.plan,
+                &self.config,
+            );
+            if let Err(e) = record.save_to(&self.paths.last_good_plan_file) {
+                log::warn!("Failed to record the last good plan: {:#}", e);
+            }
+        }
+
+        let previous = state::RuntimeState::load_from(&self.paths.state_file).unwrap_or_default();
+        let image = storage::image_record(&self.state.handle, previous.image.as_ref());
+        let data_wait = self.state.handle.data_wait;
+        let tmpfs = self.state.handle.tmpfs.clone();
+        let integrity = self.state.handle.integrity.clone();
+        let loop_device = self.state.handle.loop_device;
+
+        if let Err(e) = deferred::DeferredList::new(self.state.deferred.clone())
+            .save_to(&self.paths.deferred_file)
+        {
+            log::warn!("Failed to write the deferred module list: {:#}", e);
+        } else if !self.state.deferred.is_empty() {
+            log::info!(
+                ">> {} module(s) deferred until mount-deferred runs",
+                self.state.deferred.len()
+            );
+        }
+
+        let mut state = state::RuntimeState::new(
             self.state.handle.mode,
             self.state.handle.mount_point,
             self.state.result.overlay_module_ids,
             self.state.result.magic_module_ids,
+            self.state.result.hymo_module_ids,
             active_mounts,
             storage_stats,
+            self.state.sync_summary,
+            self.state.result.per_partition,
         );
 
-        if let Err(e) = state.save() {
+        state.rw_partitions = state
+            .per_partition
+            .iter()
+            .filter(|(_, stats)| stats.writable)
+            .map(|(name, _)| name.clone())
+            .collect();
+        state.rw_partitions.sort();
+        state.boot_count = previous.boot_count + 1;
+        state.image = image;
+        state.loop_device = loop_device;
+        state.safe_mode = self.safe_mode;
+        state.plan_source = if self.last_good.is_some() {
+            state::PlanSource::LastGood
+        } else {
+            state::PlanSource::Scan
+        };
+        state.profile = self.config.profile.clone();
+        state.boot_priority = self.throttle.report().clone();
+        state.namespace = self.namespace;
+        state.degraded = self.state.result.degraded;
+        state.record_data_wait(data_wait);
+        state.tmpfs = tmpfs;
+        state.integrity = integrity;
+        state.fallback_reasons = self.state.result.fallback_reasons;
+        state.busy_skipped = self.state.result.busy_skipped;
+        state.foreign_mounts = self.state.result.foreign_mounts;
+        state.susfs_active = susfs_active;
+        state.susfs_hidden = susfs_hidden;
+        state.bind_modules = self.state.result.bind_module_ids;
+        state.apex = self.state.result.apex_names;
+        state.mount_phase = [
+            &state.overlay_modules,
+            &state.magic_modules,
+            &state.hymo_modules,
+            &state.bind_modules,
+        ]
+        .into_iter()
+        .flatten()
+        .map(|id| (id.clone(), state::MountPhase::Boot))
+        .chain(
+            self.state
+                .deferred
+                .into_iter()
+                .map(|id| (id, state::MountPhase::Pending)),
+        )
+        .collect();
+        state.image_builder = image_builder;
+        state.mount_source = self.config.mountsource.clone();
+        let denylist = denylist::status(self.config.denylist_provider);
+        state.denylist_provider = denylist.provider;
+        state.denylist_enforce = denylist.enforcing;
+        state.hybrid_mnt_dir = PathBuf::from(&self.config.hybrid_mnt_dir);
+        state.last_result = if self.safe_mode {
+            state::BootResult::SafeMode
+        } else {
+            state::BootResult::Mounted
+        };
+
+        if let Err(e) = state.save_to(&self.paths.state_file) {
             log::error!("Failed to save runtime state: {:#}", e);
         }
 
+        // The finalize span has to end to be in the summary.
+        drop(span);
+        let summary = summary::record(
+            &state,
+            conflicts_resolved,
+            &self.paths.summary_file,
+            self.config.summary_text,
+        );
+        modules::update_status_prop(
+            &self.paths.module_prop_file,
+            &modules::PropStatus {
+                storage_mode: state.storage_mode.clone(),
+                overlay_count: state.overlay_modules.len(),
+                magic_count: state.magic_modules.len(),
+                bind_count: state.bind_modules.len(),
+                usage_percent: state.storage_percent,
+                degraded: state.degraded,
+                safe_mode: self.safe_mode,
+                headline: Some(summary.headline),
+            },
+        );
+
+        bootloop::finish_attempt(&self.paths.run_dir);
+
         log::info!(">> System operational. Mount sequence complete.");
+        progress::emit("finalize", None, 1, 1);
 
         Ok(())
     }
 }
+
+/// Tears down `handle` for a run that failed before anything was mounted
+/// from it, so its zram or loop device is not left behind, and passes `e`
+/// on.
+fn abandon(handle: &mut StorageHandle, e: anyhow::Error) -> anyhow::Error {
+    if let Err(teardown) = handle.teardown() {
+        log::warn!("Failed to tear down storage: {:#}", teardown);
+    }
+    e
+}
+
+/// Cleans up after a run stopped by `signal` and returns the error that ends
+/// it. In order: the magic mount workspace tmpfs is detached; the storage is
+/// torn down unless completed mounts still use it; loop devices this run
+/// attached and no longer needs are detached; and the state file records
+/// the mounts that were made, with `last_result` set to `cancelled`.
+#[allow(clippy::too_many_arguments)]
+fn cancel_run(
+    config: &Config,
+    paths: &Paths,
+    loops_before: &HashSet<String>,
+    namespace: &NamespaceReport,
+    mut handle: StorageHandle,
+    sync_summary: Option<sync::SyncSummary>,
+    result: Option<executor::ExecutionResult>,
+    signal: i32,
+) -> anyhow::Error {
+    let cancelled = cancel::Cancelled(signal);
+    log::warn!("!! Mount sequence {}, cleaning up", cancelled);
+
+    let magic_ws = PathBuf::from(&config.hybrid_mnt_dir).join("magic_workspace");
+    if is_mounted(&magic_ws)
+        && let Err(e) = unmount(&magic_ws, UnmountFlags::DETACH)
+    {
+        log::warn!("Failed to unmount {}: {}", magic_ws.display(), e);
+    }
+
+    let in_use = result.as_ref().is_some_and(|r| {
+        !r.overlay_module_ids.is_empty()
+            || !r.magic_module_ids.is_empty()
+            || !r.hymo_module_ids.is_empty()
+            || !r.bind_module_ids.is_empty()
+    });
+    if !in_use && let Err(e) = handle.teardown() {
+        log::warn!("Failed to tear down storage: {:#}", e);
+    }
+
+    let detached = loopdev::detach_idle(Path::new(defs::BASE_DIR), loops_before);
+    if detached > 0 {
+        log::info!("Detached {} loop device(s) attached by this run", detached);
+    }
+
+    let storage_stats = if in_use {
+        get_usage(&handle.mount_point)
+    } else {
+        (0, 0, 0)
+    };
+    let (overlay, magic, hymo, bind, per_partition) = match result {
+        Some(r) => (
+            r.overlay_module_ids,
+            r.magic_module_ids,
+            r.hymo_module_ids,
+            r.bind_module_ids,
+            r.per_partition,
+        ),
+        None => Default::default(),
+    };
+    let mut active_mounts: Vec<String> = per_partition.keys().cloned().collect();
+    active_mounts.sort();
+    let data_wait = handle.data_wait;
+    let tmpfs = handle.tmpfs.take();
+    let integrity = handle.integrity.take();
+    let loop_device = handle.loop_device;
+
+    let mut state = state::RuntimeState::new(
+        handle.mode,
+        handle.mount_point,
+        overlay,
+        magic,
+        hymo,
+        active_mounts,
+        storage_stats,
+        sync_summary.unwrap_or_default(),
+        per_partition,
+    );
+    state.mount_source = config.mountsource.clone();
+    state.hybrid_mnt_dir = PathBuf::from(&config.hybrid_mnt_dir);
+    state.bind_modules = bind;
+    state.namespace = namespace.clone();
+    state.record_data_wait(data_wait);
+    state.tmpfs = tmpfs;
+    state.integrity = integrity;
+    state.loop_device = loop_device;
+    state.last_result = state::BootResult::Cancelled;
+
+    if let Err(e) = state.save_to(&paths.state_file) {
+        log::error!("Failed to save runtime state: {:#}", e);
+    }
+    summary::record(&state, 0, &paths.summary_file, config.summary_text);
+
+    anyhow::Error::new(cancelled)
+}
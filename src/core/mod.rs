@@ -1,10 +1,26 @@
 // Copyright 2026 Hybrid Mount Developers
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+pub mod bench;
+pub mod bootloop;
+pub mod bundles;
+pub mod daemon;
+pub mod deferred;
+pub mod failure;
+pub mod granary;
+pub mod hashcache;
+pub mod image_builder;
+pub mod integrity;
 pub mod inventory;
+pub mod last_good;
 pub mod manager;
 pub mod ops;
+pub mod rescue;
+pub mod staging;
 pub mod state;
 pub mod storage;
+pub mod summary;
+pub mod uninstall;
+pub mod winnow;
 
 pub use manager::MountController;
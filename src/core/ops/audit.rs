@@ -0,0 +1,165 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::{defs, utils};
+
+/// Upper bound for walking the synced tree; the kernel log scan is cheap.
+const CONTEXT_BUDGET: Duration = Duration::from_millis(800);
+const DMESG_TAIL_LINES: usize = 512;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ContextMismatch {
+    pub path: PathBuf,
+    pub actual: String,
+    pub expected: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ContextAudit {
+    pub checked: usize,
+    pub mismatches: Vec<ContextMismatch>,
+    pub truncated: bool,
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SelinuxReport {
+    pub generated_at: u64,
+    #[serde(flatten)]
+    pub contexts: ContextAudit,
+    pub denials: Vec<String>,
+}
+
+/// Compares the labels of synced partition files against the stock paths
+/// they will shadow. Must run before mounting, while stock paths are still
+/// visible.
+pub fn check_contexts(storage_root: &Path, partitions: &[String]) -> ContextAudit {
+    let start = Instant::now();
+    let mut audit = ContextAudit::default();
+
+    let Ok(modules) = std::fs::read_dir(storage_root) else {
+        return audit;
+    };
+
+    'modules: for module in modules.flatten() {
+        let module_root = module.path();
+
+        for partition in defs::BUILTIN_PARTITIONS
+            .iter()
+            .copied()
+            .chain(partitions.iter().map(|p| p.as_str()))
+        {
+            let part_root = module_root.join(partition);
+            if !part_root.is_dir() {
+                continue;
+            }
+
+            for entry in WalkDir::new(&part_root).into_iter().flatten() {
+                if start.elapsed() > CONTEXT_BUDGET {
+                    audit.truncated = true;
+                    break 'modules;
+                }
+
+                let Ok(relative) = entry.path().strip_prefix(&module_root) else {
+                    continue;
+                };
+                let Ok(actual) = utils::lgetfilecon(entry.path()) else {
+                    continue;
+                };
+
+                audit.checked += 1;
+
+                let expected = utils::expected_system_context(relative);
+                if actual != expected {
+                    audit.mismatches.push(ContextMismatch {
+                        path: Path::new("/").join(relative),
+                        actual,
+                        expected,
+                    });
+                }
+            }
+        }
+    }
+
+    audit.elapsed_ms = start.elapsed().as_millis() as u64;
+    audit
+}
+
+fn scan_denials(mount_points: &[String]) -> Vec<String> {
+    let Ok(output) = Command::new("dmesg").output() else {
+        return Vec::new();
+    };
+
+    let log = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = log.lines().collect();
+    let tail = &lines[lines.len().saturating_sub(DMESG_TAIL_LINES)..];
+
+    tail.iter()
+        .filter(|line| line.contains("avc:") && line.contains("denied"))
+        .filter(|line| {
+            mount_points
+                .iter()
+                .any(|mp| line.contains(&format!("\"{}", mp.trim_end_matches('/'))))
+        })
+        .map(|line| line.trim().to_string())
+        .collect()
+}
+
+/// Collects avc denials touching our mount points and writes the combined
+/// report to `RUN_DIR`.
+pub fn finish(
+    contexts: ContextAudit,
+    mount_points: &[String],
+    report_path: &Path,
+) -> Result<SelinuxReport> {
+    for mismatch in &contexts.mismatches {
+        log::warn!(
+            "SELinux: {} is {} but stock expects {}",
+            mismatch.path.display(),
+            mismatch.actual,
+            mismatch.expected
+        );
+    }
+
+    if contexts.truncated {
+        log::warn!(
+            "SELinux audit stopped after {} entries (time budget exceeded)",
+            contexts.checked
+        );
+    }
+
+    let denials = scan_denials(mount_points);
+    for denial in &denials {
+        log::warn!("SELinux denial: {}", denial);
+    }
+
+    let report = SelinuxReport {
+        generated_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        contexts,
+        denials,
+    };
+
+    utils::atomic_write(report_path, serde_json::to_string_pretty(&report)?)?;
+
+    log::info!(
+        ">> SELinux audit: {} checked, {} mismatches, {} denials",
+        report.contexts.checked,
+        report.contexts.mismatches.len(),
+        report.denials.len()
+    );
+
+    Ok(report)
+}
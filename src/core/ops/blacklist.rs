@@ -0,0 +1,188 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Module files kept out of the mount by glob.
+//!
+//! `mount_blacklist` applies to every module, the `blacklist` of a module's
+//! rules only to that module. Patterns match the path a file is mounted at,
+//! e.g. `/system/etc/hosts`; a file shipped as `system/vendor/...` is also
+//! tried as `/vendor/...`. Sync leaves matching files out of the storage
+//! copy, so they never reach an overlay lowerdir, and magic mount skips them
+//! while collecting its tree. A blacklisted file falls through to lower
+//! modules or stock as if the module did not ship it.
+
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
+
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::{
+    conf::config::{Config, WinnowingTable},
+    core::{
+        inventory::Module,
+        ops::planner::{DiagnosticIssue, DiagnosticLevel, LayerDemotion},
+    },
+    utils::{self, glob::Glob},
+};
+
+/// Partitions a module may ship under `system/` that are mounted at `/`.
+const SYSTEM_ALIASED: &[&str] = &["vendor", "system_ext", "product", "odm"];
+
+#[derive(Debug, Clone, Default)]
+pub struct Blacklist {
+    global: Vec<Glob>,
+    modules: HashMap<String, Vec<Glob>>,
+    /// Patterns that failed to compile: owner (`mount_blacklist` or the
+    /// module id), pattern and error.
+    invalid: Vec<(String, String, String)>,
+}
+
+impl Blacklist {
+    pub fn new(config: &Config, modules: &[Module]) -> Self {
+        let mut blacklist = Self::default();
+        blacklist.global = blacklist.compile("mount_blacklist", &config.mount_blacklist);
+        for module in modules {
+            let globs = blacklist.compile(&module.id, &module.rules.blacklist);
+            if !globs.is_empty() {
+                blacklist.modules.insert(module.id.clone(), globs);
+            }
+        }
+        blacklist
+    }
+
+    fn compile(&mut self, owner: &str, patterns: &[String]) -> Vec<Glob> {
+        patterns
+            .iter()
+            .filter_map(|pattern| match Glob::new(pattern) {
+                Ok(glob) => Some(glob),
+                Err(e) => {
+                    log::warn!(
+                        "Ignoring blacklist pattern '{}' of {}: {:#}",
+                        pattern,
+                        owner,
+                        e
+                    );
+                    self.invalid
+                        .push((owner.to_string(), pattern.clone(), format!("{:#}", e)));
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.global.is_empty() && self.modules.is_empty()
+    }
+
+    /// The pattern that keeps `target` of `module_id` out of the mount.
+    pub fn matching_target(&self, module_id: &str, target: &str) -> Option<&str> {
+        self.global
+            .iter()
+            .chain(self.modules.get(module_id).into_iter().flatten())
+            .find(|glob| glob.is_match(target))
+            .map(Glob::pattern)
+    }
+
+    /// The pattern that keeps the module file `relative` of `module_id`
+    /// out of the mount. Files in the module root are never mounted and
+    /// never match.
+    pub fn matching(&self, module_id: &str, relative: &Path) -> Option<&str> {
+        if self.is_empty() || relative.components().nth(1).is_none() {
+            return None;
+        }
+        target_paths(relative)
+            .iter()
+            .find_map(|target| self.matching_target(module_id, target))
+    }
+}
+
+/// Paths the module file `relative` can be mounted at: `/` joined with it,
+/// plus `/<partition>/...` for a `system/<partition>/...` file.
+pub fn target_paths(relative: &Path) -> Vec<String> {
+    let segments: Vec<_> = relative
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+
+    let mut targets = vec![format!("/{}", segments.join("/"))];
+    if segments.len() > 1
+        && segments[0] == "system"
+        && SYSTEM_ALIASED.contains(&segments[1].as_ref())
+    {
+        targets.push(format!("/{}", segments[1..].join("/")));
+    }
+    targets
+}
+
+/// A module file the blacklist keeps out of the mount.
+#[derive(Debug, Clone, Serialize)]
+pub struct BlacklistedFile {
+    pub module_id: String,
+    /// Path inside the module directory.
+    #[serde(skip)]
+    pub relative: PathBuf,
+    /// Path the file would have been mounted at.
+    pub target: String,
+    pub pattern: String,
+}
+
+/// Files of `module` that `blacklist` keeps out, in path order.
+pub fn filtered(module: &Module, blacklist: &Blacklist) -> Vec<BlacklistedFile> {
+    if blacklist.is_empty() {
+        return Vec::new();
+    }
+
+    WalkDir::new(&module.source_path)
+        .min_depth(2)
+        .sort_by_file_name()
+        .into_iter()
+        .flatten()
+        .filter(|entry| !entry.file_type().is_dir() && !utils::is_rw_marker(entry.path()))
+        .filter_map(|entry| {
+            let relative = entry.path().strip_prefix(&module.source_path).ok()?;
+            let pattern = blacklist.matching(&module.id, relative)?;
+            Some(BlacklistedFile {
+                module_id: module.id.clone(),
+                relative: relative.to_path_buf(),
+                target: target_paths(relative).swap_remove(0),
+                pattern: pattern.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Winnowing rules that force a module for a path it blacklists. The
+/// blacklist wins: the file is not mounted from that module.
+pub fn winnowing_conflicts(table: &WinnowingTable, blacklist: &Blacklist) -> Vec<LayerDemotion> {
+    table
+        .rules
+        .iter()
+        .filter_map(|(path, module_id)| {
+            let pattern = blacklist.matching_target(module_id, path)?;
+            Some(LayerDemotion {
+                module_id: module_id.clone(),
+                target: path.clone(),
+                reason: format!("blacklisted by '{}'", pattern),
+            })
+        })
+        .collect()
+}
+
+/// One Warning per blacklist pattern that is not a valid glob.
+pub fn diagnose(blacklist: &Blacklist) -> Vec<DiagnosticIssue> {
+    blacklist
+        .invalid
+        .iter()
+        .map(|(owner, pattern, error)| DiagnosticIssue {
+            level: DiagnosticLevel::Warning,
+            context: "Blacklist".to_string(),
+            message: format!("Pattern '{}' of {} is ignored: {}", pattern, owner, error),
+        })
+        .collect()
+}
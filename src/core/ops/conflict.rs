@@ -0,0 +1,181 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Component, Path},
+    sync::{Mutex, OnceLock},
+};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::core::hashcache;
+
+/// Files up to this size are hashed in full; larger ones are sampled.
+const FULL_HASH_LIMIT: u64 = 16 * 1024 * 1024;
+/// Bytes read from each end of a file that is too large to hash in full.
+const SAMPLE_SIZE: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum ConflictSeverity {
+    /// Every contender ships the same content, so the winner does not matter.
+    Benign,
+    /// Contents differ on a path that does not hold code (resources, configs).
+    Notice,
+    /// Contents differ on a library or binary path, where the losing module
+    /// is likely to break.
+    Severe,
+}
+
+/// One module's version of a contested path.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConflictContender {
+    pub module_id: String,
+    pub size: u64,
+    pub file_type: String,
+    /// Hex sha256 of the contents (or of the link target for symlinks).
+    /// `None` when the file could not be read.
+    pub sha256: Option<String>,
+    /// The digest covers only the head, tail and size of the file.
+    pub sampled: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct CacheKey {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+fn digest_cache() -> &'static Mutex<HashMap<CacheKey, String>> {
+    static CACHE: OnceLock<Mutex<HashMap<CacheKey, String>>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hash_sampled(path: &Path, size: u64) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut file = File::open(path)?;
+
+    io::copy(&mut (&mut file).take(SAMPLE_SIZE), &mut hasher)?;
+    file.seek(SeekFrom::End(-(SAMPLE_SIZE as i64)))?;
+    io::copy(&mut file.take(SAMPLE_SIZE), &mut hasher)?;
+    hasher.update(size.to_le_bytes());
+
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Collects size, type and digest of `path` as provided by `module_id`.
+/// Full digests come from the persistent [`hashcache`]; sampled digests of
+/// large files are cached per inode and mtime for the life of the process.
+pub fn describe(module_id: &str, path: &Path) -> ConflictContender {
+    let mut contender = ConflictContender {
+        module_id: module_id.to_string(),
+        size: 0,
+        file_type: "unknown".to_string(),
+        sha256: None,
+        sampled: false,
+    };
+
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return contender;
+    };
+
+    contender.size = meta.len();
+
+    if meta.file_type().is_symlink() {
+        contender.file_type = "symlink".to_string();
+        contender.sha256 = fs::read_link(path)
+            .ok()
+            .map(|target| to_hex(&Sha256::digest(target.as_os_str().as_bytes())));
+        return contender;
+    }
+
+    if !meta.is_file() {
+        return contender;
+    }
+
+    contender.file_type = "file".to_string();
+    contender.sampled = meta.len() > FULL_HASH_LIMIT;
+
+    let key = CacheKey {
+        dev: meta.dev(),
+        ino: meta.ino(),
+        size: meta.len(),
+        mtime: meta.mtime(),
+        mtime_nsec: meta.mtime_nsec(),
+    };
+
+    if let Ok(cache) = digest_cache().lock()
+        && let Some(hit) = cache.get(&key)
+    {
+        contender.sha256 = Some(hit.clone());
+        return contender;
+    }
+
+    if !contender.sampled {
+        match hashcache::hash_file_cached(path) {
+            Ok(digest) => contender.sha256 = Some(to_hex(&digest)),
+            Err(e) => log::debug!("Failed to hash {}: {:#}", path.display(), e),
+        }
+        return contender;
+    }
+
+    match hash_sampled(path, meta.len()) {
+        Ok(digest) => {
+            if let Ok(mut cache) = digest_cache().lock() {
+                cache.insert(key, digest.clone());
+            }
+            contender.sha256 = Some(digest);
+        }
+        Err(e) => log::debug!("Failed to hash {}: {}", path.display(), e),
+    }
+
+    contender
+}
+
+/// True when every contender has the same type, size and digest.
+pub fn all_identical(contenders: &[ConflictContender]) -> bool {
+    let Some(first) = contenders.first() else {
+        return true;
+    };
+
+    first.sha256.is_some()
+        && contenders.iter().all(|c| {
+            c.file_type == first.file_type && c.size == first.size && c.sha256 == first.sha256
+        })
+}
+
+/// Whether `path` (absolute, as seen on the device) holds libraries or
+/// binaries: `/system/lib*`, `/system/bin` or `/vendor/lib*`.
+fn is_code_path(path: &Path) -> bool {
+    let mut parts = path.components().filter_map(|c| match c {
+        Component::Normal(s) => s.to_str(),
+        _ => None,
+    });
+
+    match (parts.next(), parts.next()) {
+        (Some("system"), Some(dir)) => dir == "bin" || dir.starts_with("lib"),
+        (Some("vendor"), Some(dir)) => dir.starts_with("lib"),
+        _ => false,
+    }
+}
+
+pub fn classify(path: &Path, identical: bool) -> ConflictSeverity {
+    if identical {
+        ConflictSeverity::Benign
+    } else if is_code_path(path) {
+        ConflictSeverity::Severe
+    } else {
+        ConflictSeverity::Notice
+    }
+}
@@ -2,54 +2,421 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs::File,
     path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
-use anyhow::Result;
+use anyhow::{Result, anyhow, bail};
+use rustix::mount::{UnmountFlags, unmount};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
 
 use crate::{
     conf::config,
-    core::ops::planner::MountPlan,
-    defs,
+    core::{
+        failure::DiagnosticsAbort,
+        ops::{
+            foreign::{self, ForeignMount},
+            journal::UndoJournal,
+            planner::{MountPlan, OverlayOperation},
+        },
+    },
+    defs::{self, Paths},
     mount::{
-        magic_mount,
-        overlayfs::{self, utils::umount_dir},
+        bind, magic_mount,
+        overlayfs::{self, overlayfs::MountMethod, utils::umount_dir},
         umount_mgr,
     },
-    utils,
+    sys::poaceae,
+    utils::{
+        self, cancel, progress, timing,
+        trace::{self, TraceOp},
+    },
 };
 
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PartitionStats {
+    pub layer_count: usize,
+    pub file_count: usize,
+    pub symlink_count: usize,
+    pub tmpfs_dirs_created: usize,
+    #[serde(default)]
+    pub mirrored_count: usize,
+    pub fallback: bool,
+    /// Mounted with a persistent upperdir, so writes survive reboots.
+    #[serde(default)]
+    pub writable: bool,
+    /// `overlay_options` the kernel kept, read back from the mount table.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub overlay_options: BTreeMap<String, String>,
+}
+
 pub struct ExecutionResult {
     pub overlay_module_ids: Vec<String>,
     pub magic_module_ids: Vec<String>,
+    pub hymo_module_ids: Vec<String>,
+    /// Modules mounted file by file through `plan.bind_ops`.
+    pub bind_module_ids: Vec<String>,
+    /// APEXes at least one of `plan.apex_ops` was bound into.
+    pub apex_names: Vec<String>,
+    /// Every mount the run established, in order, including those kept
+    /// when a later one failed.
+    pub journal: UndoJournal,
+    pub per_partition: HashMap<String, PartitionStats>,
+    /// A mount timed out or the deadline cut the run short.
+    pub degraded: bool,
+    /// Why a module was mounted differently than planned, keyed by module id.
+    pub fallback_reasons: HashMap<String, String>,
+    /// Module files magic mount left out because the stock file was in use.
+    pub busy_skipped: Vec<PathBuf>,
+    /// Signal that stopped the run before every operation was attempted.
+    pub cancelled: Option<i32>,
+    /// Mounts other managers had already made on our partitions.
+    pub foreign_mounts: Vec<ForeignMount>,
+}
+
+/// Bounds how long mount work may block. Each unit of work gets at most
+/// `per_op`, and nothing new starts once `deadline` has passed.
+struct Watchdog {
+    per_op: Option<Duration>,
+    deadline: Option<Instant>,
+    degraded: bool,
 }
 
+impl Watchdog {
+    fn new(config: &config::Config, deadline: Option<Instant>) -> Self {
+        Self {
+            per_op: (config.mount_timeout_secs > 0)
+                .then(|| Duration::from_secs(config.mount_timeout_secs)),
+            deadline,
+            degraded: false,
+        }
+    }
+
+    /// Runs `work` within the time budget. `None` means it was skipped
+    /// because the deadline passed, or abandoned after timing out; see
+    /// [`utils::run_with_timeout`] for what happens to abandoned work.
+    fn run<T, F>(&mut self, what: &str, work: F) -> Option<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let remaining = match self.deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(left) if !left.is_zero() => Some(left),
+                _ => {
+                    log::error!("!! CRITICAL: mount deadline passed, skipping {}", what);
+                    self.degraded = true;
+                    return None;
+                }
+            },
+            None => None,
+        };
+
+        let budget = match (self.per_op, remaining) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(t), None) | (None, Some(t)) => t,
+            (None, None) => return Some(work()),
+        };
+
+        let result = utils::run_with_timeout("mh-mount", budget, work);
+        if result.is_none() {
+            log::error!(
+                "!! CRITICAL: {} did not finish within {}s, abandoning it",
+                what,
+                budget.as_secs()
+            );
+            self.degraded = true;
+        }
+        result
+    }
+}
+
+fn count_layer_entries(stats: &mut PartitionStats, lowerdirs: &[PathBuf]) {
+    for entry in lowerdirs
+        .iter()
+        .flat_map(|dir| WalkDir::new(dir).min_depth(1).into_iter().flatten())
+    {
+        let ft = entry.file_type();
+        if ft.is_symlink() {
+            stats.symlink_count += 1;
+        } else if !ft.is_dir() {
+            stats.file_count += 1;
+        }
+    }
+}
+
+pub fn log_partition_stats(per_partition: &HashMap<String, PartitionStats>) {
+    if per_partition.is_empty() {
+        return;
+    }
+
+    let mut partitions: Vec<_> = per_partition.iter().collect();
+    partitions.sort_by(|a, b| a.0.cmp(b.0));
+
+    log::info!(">> Partition Summary:");
+    log::info!(
+        "{:<16} {:>6} {:>8} {:>8} {:>6} {:>8} {:>8}",
+        "PARTITION",
+        "LAYERS",
+        "FILES",
+        "SYMLINKS",
+        "TMPFS",
+        "MIRRORED",
+        "FALLBACK"
+    );
+
+    for (name, stats) in partitions {
+        log::info!(
+            "{:<16} {:>6} {:>8} {:>8} {:>6} {:>8} {:>8}",
+            name,
+            stats.layer_count,
+            stats.file_count,
+            stats.symlink_count,
+            stats.tmpfs_dirs_created,
+            stats.mirrored_count,
+            if stats.fallback { "yes" } else { "no" }
+        );
+    }
+}
+
+/// Creates the upperdir and workdir of a read-write partition and checks that
+/// their filesystem can hold the overlay xattrs the kernel writes there.
+fn prepare_rw_dirs(upper: &Path, work: &Path) -> Result<()> {
+    utils::ensure_dir_exists(upper)?;
+    utils::ensure_dir_exists(work)?;
+
+    if !utils::fs_supports_overlay_xattrs(upper) {
+        bail!(
+            "{} is on a filesystem that does not support trusted.* xattrs and cannot hold an upperdir",
+            upper.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Seeds the partition upperdir with the writable files below `target`, so
+/// writes land in the system rw dir instead of failing on the read-only lower
+/// layers. Files already in the upperdir are left alone.
+fn stage_writable_files(plan: &MountPlan, target: &str, upper: &Path, work: &Path) {
+    for file in plan.writable_files.iter().filter(|f| f.overlay) {
+        let Ok(inside) = file.path.strip_prefix(target) else {
+            continue;
+        };
+
+        let dst = upper.join(inside);
+        if dst.symlink_metadata().is_ok() {
+            continue;
+        }
+
+        let result = utils::ensure_dir_exists(work)
+            .and_then(|_| utils::ensure_dir_exists(dst.parent().unwrap_or(upper)))
+            .and_then(|_| utils::copy_entry(&file.source, &dst, &file.relative, true));
+
+        match result {
+            Ok(()) => log::info!(
+                "Staged writable file {} ({})",
+                file.path.display(),
+                file.module_id
+            ),
+            Err(e) => log::warn!(
+                "Failed to stage writable file {}: {:#}",
+                file.path.display(),
+                e
+            ),
+        }
+    }
+}
+
+/// Mounts the overlay of `op` from the lowerdirs at `layers`, within the
+/// watchdog's budget. `None` when the watchdog skipped or abandoned it.
+fn mount_overlay_layers(
+    watchdog: &mut Watchdog,
+    op: &OverlayOperation,
+    layers: &[usize],
+    upper: Option<PathBuf>,
+    work: Option<PathBuf>,
+    config: &config::Config,
+) -> Option<Result<MountMethod>> {
+    let lowerdirs: Vec<String> = layers
+        .iter()
+        .map(|&i| op.lowerdirs[i].display().to_string())
+        .collect();
+    let target = op.target.clone();
+    let mount_source = config.mountsource.clone();
+    let options = config.overlay_options_for(&op.partition_name);
+    watchdog.run(&format!("overlay mount of {}", op.target), move || {
+        overlayfs::overlayfs::mount_overlay(
+            &target,
+            &lowerdirs,
+            work,
+            upper,
+            &mount_source,
+            &options,
+        )
+    })
+}
+
+/// Narrows down the layers that make an overlay of `count` layers fail.
+/// `mounts` tries the overlay with only the given layer indices and reports
+/// whether it mounted, leaving nothing mounted. A binary search halving the
+/// range each step finds how many of the top layers still mount; the layer
+/// right below them is the culprit, confirmed by mounting everything else.
+/// When that fails too, every layer below the top ones that mounted is bad.
+/// Returns the bad layers, or `None` when not even the top layer mounts
+/// alone. Calls `mounts` at most `ceil(log2(count)) + 1` times.
+pub fn bisect_layers(count: usize, mounts: &mut dyn FnMut(&[usize]) -> bool) -> Option<Vec<usize>> {
+    if count < 2 {
+        return None;
+    }
+
+    let all: Vec<usize> = (0..count).collect();
+    // The top `good` layers mount, the top `bad` do not.
+    let (mut good, mut bad) = (0, count);
+    while bad - good > 1 {
+        let mid = (good + bad) / 2;
+        if mounts(&all[..mid]) {
+            good = mid;
+        } else {
+            bad = mid;
+        }
+    }
+
+    let rest: Vec<usize> = all.iter().copied().filter(|&i| i != good).collect();
+    if mounts(&rest) {
+        return Some(vec![good]);
+    }
+    (good > 0).then(|| all[good..].to_vec())
+}
+
+/// Performs the mounts of `plan`. Mount work that outlives
+/// `mount_timeout_secs` is abandoned and handled like a failed mount; once
+/// `mount_deadline_secs` (counted from now) has passed, remaining work is
+/// skipped and the result is marked degraded. A cancellation request stops
+/// the run after the operation in progress; see [`ExecutionResult::cancelled`].
 pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionResult> {
+    let deadline = (config.mount_deadline_secs > 0)
+        .then(|| Instant::now() + Duration::from_secs(config.mount_deadline_secs));
+    execute_with_paths(plan, config, &Paths::default(), deadline)
+}
+
+/// Same as [`execute`] with journal and writable-upperdir locations taken
+/// from `paths` and an explicit global `deadline`.
+pub fn execute_with_paths(
+    plan: &MountPlan,
+    config: &config::Config,
+    paths: &Paths,
+    deadline: Option<Instant>,
+) -> Result<ExecutionResult> {
+    let mut span = timing::span("execute");
+    span.items(plan.overlay_ops.len() + plan.magic_module_ids.len() + plan.apex_ops.len());
+    let foreign_mounts = foreign::scan(plan, config, paths);
+    let mut foreign_reasons: HashMap<String, String> = HashMap::new();
+    let trimmed;
+    let plan = if foreign_mounts.is_empty() {
+        plan
+    } else {
+        let partitions = foreign::affected_partitions(&foreign_mounts);
+        for issue in foreign::diagnose(&foreign_mounts, config.foreign_mount_policy) {
+            log::warn!("{}", issue.message);
+        }
+        match config.foreign_mount_policy {
+            config::ForeignMountPolicy::Abort => {
+                return Err(DiagnosticsAbort(format!(
+                    "Foreign mounts found on {}; aborting as foreign_mount_policy requires",
+                    partitions.into_iter().collect::<Vec<_>>().join(", ")
+                ))
+                .into());
+            }
+            config::ForeignMountPolicy::Stack => plan,
+            config::ForeignMountPolicy::SkipPartition => {
+                let mut skipped = plan.clone();
+                let reason = format!(
+                    "partitions {} skipped because of foreign mounts",
+                    partitions.iter().cloned().collect::<Vec<_>>().join(", ")
+                );
+                for id in skipped.drop_partitions(&partitions) {
+                    foreign_reasons.insert(id, reason.clone());
+                }
+                trimmed = skipped;
+                &trimmed
+            }
+        }
+    };
+
     let mut final_magic_ids: HashSet<String> = plan.magic_module_ids.iter().cloned().collect();
     let mut final_overlay_ids: HashSet<String> = HashSet::new();
+    let mut final_hymo_ids: HashSet<String> = HashSet::new();
+    let mut final_bind_ids: HashSet<String> = HashSet::new();
+
+    UndoJournal::recover_stale(&paths.journal_file);
+    let mut journal = UndoJournal::new(config.strict_atomic, &paths.journal_file);
+    let mut per_partition: HashMap<String, PartitionStats> = HashMap::new();
+    let mut watchdog = Watchdog::new(config, deadline);
+
+    let mut fallback_reasons: HashMap<String, String> = foreign_reasons;
+    let mut busy_skipped: Vec<PathBuf> = Vec::new();
+    for id in &plan.hymo_fallback {
+        fallback_reasons.insert(id.clone(), "HymoFS is not active".to_string());
+    }
+    for demotion in &plan.demoted {
+        fallback_reasons.insert(
+            demotion.module_id.clone(),
+            format!("overlay on {} {}", demotion.target, demotion.reason),
+        );
+    }
 
     log::info!(">> Phase 1: OverlayFS Execution...");
 
+    let total_ops = plan.overlay_ops.len()
+        + plan.hymo_ops.len()
+        + plan.bind_ops.len()
+        + plan.apex_ops.len()
+        + 1;
+    let mut done_ops = 0;
+
     for op in &plan.overlay_ops {
+        if cancel::requested().is_some() {
+            break;
+        }
+        done_ops += 1;
+        progress::emit_target("execute", &op.target, done_ops, total_ops);
+
         let involved_modules: Vec<String> = op
             .lowerdirs
             .iter()
             .filter_map(|p| utils::extract_module_id(p))
             .collect();
 
-        let lowerdir_strings: Vec<String> = op
-            .lowerdirs
-            .iter()
-            .map(|p| p.display().to_string())
-            .collect();
-
-        let rw_root = Path::new(defs::SYSTEM_RW_DIR);
+        let rw_root = paths.system_rw_dir.as_path();
         let part_rw = rw_root.join(&op.partition_name);
         let upper = part_rw.join("upperdir");
         let work = part_rw.join("workdir");
 
-        let (upper_opt, work_opt) = if upper.exists() && work.exists() {
+        if rw_root.is_dir() {
+            stage_writable_files(plan, &op.target, &upper, &work);
+        }
+
+        let rw_ready = if config.rw_partitions.contains(&op.partition_name) {
+            match prepare_rw_dirs(&upper, &work) {
+                Ok(()) => true,
+                Err(e) => {
+                    log::error!(
+                        "Read-write overlay for {} disabled: {:#}",
+                        op.partition_name,
+                        e
+                    );
+                    false
+                }
+            }
+        } else {
+            upper.exists() && work.exists()
+        };
+
+        let (upper_opt, work_opt) = if rw_ready {
             (Some(upper), Some(work))
         } else {
             (None, None)
@@ -58,19 +425,134 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
         log::info!(
             "Mounting {} [OVERLAY] (Layers: {})",
             op.target,
-            lowerdir_strings.len()
+            op.lowerdirs.len()
         );
 
-        match overlayfs::overlayfs::mount_overlay(
-            &op.target,
-            &lowerdir_strings,
-            work_opt,
-            upper_opt,
-            &config.mountsource,
-        ) {
-            Ok(_) => {
-                for id in involved_modules {
-                    final_overlay_ids.insert(id);
+        let mut layers: Vec<usize> = (0..op.lowerdirs.len()).collect();
+        let first = mount_overlay_layers(
+            &mut watchdog,
+            op,
+            &layers,
+            upper_opt.clone(),
+            work_opt.clone(),
+            config,
+        );
+        let timed_out = first.is_none();
+        let mut mounted = first.unwrap_or_else(|| Err(anyhow!("timed out")));
+        let mut bad_layers: Vec<usize> = Vec::new();
+        let mut retries = 0;
+
+        if let Err(e) = &mounted
+            && config.granular_fallback
+            && !timed_out
+            && layers.len() > 1
+        {
+            log::warn!(
+                "OverlayFS failed for {}: {}. Bisecting its {} layers.",
+                op.target,
+                e,
+                layers.len()
+            );
+            let bad = bisect_layers(layers.len(), &mut |subset| {
+                retries += 1;
+                let trial = mount_overlay_layers(
+                    &mut watchdog,
+                    op,
+                    subset,
+                    upper_opt.clone(),
+                    work_opt.clone(),
+                    config,
+                );
+                if !matches!(trial, Some(Ok(_))) {
+                    return false;
+                }
+                if let Err(e) = unmount(op.target.as_str(), UnmountFlags::DETACH) {
+                    log::warn!("Failed to unmount trial overlay on {}: {}", op.target, e);
+                }
+                true
+            });
+
+            if let Some(bad) = bad {
+                // A module's other layers on this target go with it.
+                let bad_ids: HashSet<String> = bad
+                    .iter()
+                    .filter_map(|&i| utils::extract_module_id(&op.lowerdirs[i]))
+                    .collect();
+                layers.retain(|&i| {
+                    !bad.contains(&i)
+                        && utils::extract_module_id(&op.lowerdirs[i])
+                            .is_none_or(|id| !bad_ids.contains(&id))
+                });
+
+                if !layers.is_empty() {
+                    retries += 1;
+                    let retry = mount_overlay_layers(
+                        &mut watchdog,
+                        op,
+                        &layers,
+                        upper_opt.clone(),
+                        work_opt.clone(),
+                        config,
+                    );
+                    if let Some(Ok(method)) = retry {
+                        mounted = Ok(method);
+                        bad_layers = (0..op.lowerdirs.len())
+                            .filter(|i| !layers.contains(i))
+                            .collect();
+                    }
+                }
+            }
+            log::info!(
+                "Bisection of {} took {} retry mount(s), bad layers: [{}]",
+                op.target,
+                retries,
+                bad_layers
+                    .iter()
+                    .map(|&i| op.lowerdirs[i].display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        match mounted {
+            Ok(method) => {
+                journal.record(&op.target, method);
+
+                let lowerdirs: Vec<PathBuf> =
+                    layers.iter().map(|&i| op.lowerdirs[i].clone()).collect();
+                let effective = overlayfs::overlayfs::effective_options(Path::new(&op.target));
+                let stats = per_partition.entry(op.partition_name.clone()).or_default();
+                stats.layer_count += lowerdirs.len();
+                stats.writable |= rw_ready;
+                stats.overlay_options.extend(effective);
+                count_layer_entries(stats, &lowerdirs);
+
+                for dir in &lowerdirs {
+                    if let Some(id) = utils::extract_module_id(dir) {
+                        final_overlay_ids.insert(id);
+                    }
+                }
+
+                for &i in &bad_layers {
+                    let dir = &op.lowerdirs[i];
+                    let Some(id) = utils::extract_module_id(dir) else {
+                        continue;
+                    };
+                    log::warn!(
+                        "Layer {} breaks the overlay on {}, moving {} to Magic Mount.",
+                        dir.display(),
+                        op.target,
+                        id
+                    );
+                    fallback_reasons.entry(id.clone()).or_insert_with(|| {
+                        format!(
+                            "layer {} broke the overlayfs mount for {} (found in {} retry mounts)",
+                            dir.display(),
+                            op.target,
+                            retries
+                        )
+                    });
+                    final_magic_ids.insert(id);
                 }
 
                 #[cfg(any(target_os = "linux", target_os = "android"))]
@@ -90,19 +572,223 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
                     op.target,
                     e
                 );
+                per_partition
+                    .entry(op.partition_name.clone())
+                    .or_default()
+                    .fallback = true;
+                let mut reason = format!("overlayfs mount for {} failed: {}", op.target, e);
+                if retries > 0 {
+                    reason.push_str(&format!(
+                        " (no culprit layer found in {} retry mounts)",
+                        retries
+                    ));
+                }
                 for id in involved_modules {
+                    fallback_reasons
+                        .entry(id.clone())
+                        .or_insert_with(|| reason.clone());
                     final_magic_ids.insert(id);
                 }
             }
         }
     }
 
+    if !plan.hymo_ops.is_empty() && cancel::requested().is_none() {
+        log::info!(">> Phase 1b: HymoFS Merge...");
+
+        match File::open(defs::POACEAE_MOUNT_POINT) {
+            Ok(control) => {
+                for op in &plan.hymo_ops {
+                    if cancel::requested().is_some() {
+                        break;
+                    }
+                    done_ops += 1;
+                    progress::emit("execute", Some(&op.module_id), done_ops, total_ops);
+
+                    let source = op.source.to_string_lossy();
+                    let target = op.target.to_string_lossy();
+
+                    match poaceae::merge(&control, &source, &target) {
+                        Ok(()) => {
+                            log::info!("Merging {} [HYMOFS] ({})", target, op.module_id);
+                            let stats = per_partition.entry(op.partition_name.clone()).or_default();
+                            stats.layer_count += 1;
+                            count_layer_entries(stats, std::slice::from_ref(&op.source));
+                            final_hymo_ids.insert(op.module_id.clone());
+                        }
+                        Err(e) => {
+                            log::warn!(
+                                "HymoFS merge failed for {} ({}): {:#}. Fallback to Magic Mount.",
+                                target,
+                                op.module_id,
+                                e
+                            );
+                            fallback_reasons.insert(
+                                op.module_id.clone(),
+                                format!("hymofs merge for {} failed: {:#}", target, e),
+                            );
+                            final_magic_ids.insert(op.module_id.clone());
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                log::warn!(
+                    "HymoFS control {} unavailable: {}. Fallback to Magic Mount.",
+                    defs::POACEAE_MOUNT_POINT,
+                    e
+                );
+                for op in &plan.hymo_ops {
+                    fallback_reasons.insert(
+                        op.module_id.clone(),
+                        format!("hymofs control unavailable: {}", e),
+                    );
+                    final_magic_ids.insert(op.module_id.clone());
+                }
+            }
+        }
+    }
+
+    if !plan.bind_ops.is_empty() && cancel::requested().is_none() {
+        log::info!(">> Phase 1c: Direct Bind Mounts...");
+
+        let umount = !config.disable_umount;
+        let mut bound: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        let mut failed: HashMap<String, String> = HashMap::new();
+
+        for op in &plan.bind_ops {
+            if cancel::requested().is_some() {
+                break;
+            }
+            done_ops += 1;
+            progress::emit("execute", Some(&op.module_id), done_ops, total_ops);
+
+            if failed.contains_key(&op.module_id) {
+                continue;
+            }
+
+            let source = op.source.clone();
+            let target = op.target.clone();
+            let mounted = watchdog
+                .run(
+                    &format!("bind mount of {}", op.target.display()),
+                    move || bind::bind_file(&source, &target, umount),
+                )
+                .unwrap_or_else(|| Err(anyhow!("timed out")));
+
+            match mounted {
+                Ok(()) => {
+                    log::info!("Mounting {} [BIND] ({})", op.target.display(), op.module_id);
+                    journal.record(&op.target, MountMethod::Bind);
+                    per_partition
+                        .entry(op.partition_name.clone())
+                        .or_default()
+                        .file_count += 1;
+                    bound
+                        .entry(op.module_id.clone())
+                        .or_default()
+                        .push(op.target.clone());
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Bind mount failed for {} ({}): {:#}. Fallback to Magic Mount.",
+                        op.target.display(),
+                        op.module_id,
+                        e
+                    );
+                    failed.insert(
+                        op.module_id.clone(),
+                        format!("bind mount of {} failed: {:#}", op.target.display(), e),
+                    );
+                }
+            }
+        }
+
+        for (module_id, reason) in failed {
+            for target in bound.remove(&module_id).unwrap_or_default() {
+                let event = trace::begin(TraceOp::Umount, &target);
+                let unmounted = unmount(&target, UnmountFlags::DETACH);
+                event.finish(&unmounted);
+                if let Err(e) = unmounted {
+                    log::warn!("Failed to undo bind mount {}: {}", target.display(), e);
+                }
+            }
+            fallback_reasons.insert(module_id.clone(), reason);
+            final_magic_ids.insert(module_id);
+        }
+        final_bind_ids.extend(bound.into_keys());
+    }
+
+    let mut apex_names: BTreeSet<String> = BTreeSet::new();
+    if !plan.apex_ops.is_empty() && cancel::requested().is_none() {
+        log::warn!(">> Phase 1d: APEX Bind Mounts (allow_apex)...");
+
+        let umount = !config.disable_umount;
+        for op in &plan.apex_ops {
+            if cancel::requested().is_some() {
+                break;
+            }
+            done_ops += 1;
+            progress::emit("execute", Some(&op.module_id), done_ops, total_ops);
+
+            let source = op.source.clone();
+            let target = op.target.clone();
+            let mounted = watchdog
+                .run(
+                    &format!("bind mount of {}", op.target.display()),
+                    move || bind::bind_file(&source, &target, umount),
+                )
+                .unwrap_or_else(|| Err(anyhow!("timed out")));
+
+            match mounted {
+                Ok(()) => {
+                    log::warn!(
+                        "!! Mounting {} [APEX] ({})",
+                        op.target.display(),
+                        op.module_id
+                    );
+                    journal.record(&op.target, MountMethod::Bind);
+                    per_partition
+                        .entry(op.partition_name.clone())
+                        .or_default()
+                        .file_count += 1;
+                    if let Some(name) = op.target.iter().nth(2) {
+                        apex_names.insert(name.to_string_lossy().to_string());
+                    }
+                }
+                Err(e) => log::warn!(
+                    "APEX bind mount failed for {} ({}), skipping it: {:#}",
+                    op.target.display(),
+                    op.module_id,
+                    e
+                ),
+            }
+        }
+    }
+
     final_overlay_ids.retain(|id| !final_magic_ids.contains(id));
+    final_hymo_ids.retain(|id| !final_magic_ids.contains(id));
 
     let mut magic_queue: Vec<String> = final_magic_ids.iter().cloned().collect();
-    magic_queue.sort();
+    magic_queue.sort_by_key(|id| {
+        plan.module_order
+            .iter()
+            .position(|m| m == id)
+            .unwrap_or(usize::MAX)
+    });
+
+    done_ops = total_ops - 1;
+
+    let cancelled = cancel::requested();
+    if let Some(signal) = cancelled {
+        log::warn!(
+            "!! Mount run {}, skipping remaining mounts",
+            cancel::Cancelled(signal)
+        );
+        final_magic_ids.clear();
+    } else if !magic_queue.is_empty() {
+        progress::emit_target("execute", "magic", done_ops, total_ops);
 
-    if !magic_queue.is_empty() {
         let tempdir = PathBuf::from(&config.hybrid_mnt_dir).join("magic_workspace");
         let _ = umount_mgr::TMPFS.set(tempdir.to_string_lossy().to_string());
 
@@ -111,9 +797,9 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
             tempdir.display()
         );
 
-        if matches!(config.overlay_mode, config::OverlayMode::Erofs) {
+        if matches!(config.effective_storage_mode(), config::OverlayMode::Erofs) {
             if tempdir.exists() {
-                crate::sys::mount::mount_tmpfs(&tempdir, "magic_ws")?;
+                crate::sys::mount::mount_tmpfs(&tempdir, &config.mountsource)?;
                 #[cfg(any(target_os = "linux", target_os = "android"))]
                 if let Err(e) = umount_mgr::send_umountable(&tempdir) {
                     log::warn!("Failed to schedule unmount for magic_ws: {}", e);
@@ -125,19 +811,62 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
             std::fs::create_dir_all(&tempdir)?;
         }
 
-        let module_dir = Path::new(&config.hybrid_mnt_dir);
-        let magic_need_ids: HashSet<String> = magic_queue.iter().cloned().collect();
+        let module_dir = PathBuf::from(&config.hybrid_mnt_dir);
+        let writable: HashSet<PathBuf> = plan
+            .writable_files
+            .iter()
+            .filter(|file| !file.overlay)
+            .map(|file| module_dir.join(&file.module_id).join(&file.relative))
+            .collect();
+
+        let mount_source = config.mountsource.clone();
+        let partitions = plan.extra_partitions.clone();
+        let queue = magic_queue.clone();
+        let exclusions = plan.exclusions.clone();
+        let blacklisted = plan.blacklisted_paths();
+        let deny_paths = config.user_deny_paths.clone();
+        let umount = !config.disable_umount;
+        let busy_policy = config.busy_file_policy;
+        let mounted = watchdog
+            .run("magic mount phase", move || {
+                magic_mount::magic_mount(
+                    &tempdir,
+                    &module_dir,
+                    &mount_source,
+                    &partitions,
+                    &queue,
+                    &exclusions,
+                    &blacklisted,
+                    &deny_paths,
+                    writable,
+                    busy_policy,
+                    umount,
+                )
+            })
+            .unwrap_or_else(|| Err(anyhow!("timed out")));
+
+        match mounted {
+            Ok(counters) => {
+                for (partition, counter) in counters {
+                    let stats = per_partition.entry(partition).or_default();
+                    stats.file_count += counter.files as usize;
+                    stats.symlink_count += counter.symlinks as usize;
+                    stats.tmpfs_dirs_created += counter.tmpfs_dirs as usize;
+                    stats.mirrored_count += counter.mirrored as usize;
+                    busy_skipped.extend(counter.busy_skipped);
+                }
+                busy_skipped.sort();
+            }
+            Err(e) => {
+                log::error!("Magic Mount critical failure: {:#}", e);
 
-        if let Err(e) = magic_mount::magic_mount(
-            &tempdir,
-            module_dir,
-            &config.mountsource,
-            &config.partitions,
-            magic_need_ids,
-            !config.disable_umount,
-        ) {
-            log::error!("Magic Mount critical failure: {:#}", e);
-            final_magic_ids.clear();
+                if config.strict_atomic {
+                    journal.rollback();
+                    bail!("Magic Mount failed in strict atomic mode: {:#}", e);
+                }
+
+                final_magic_ids.clear();
+            }
         }
     }
 
@@ -159,14 +888,38 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
         }
     }
 
+    UndoJournal::discard(&paths.journal_file);
+    progress::emit("execute", None, total_ops, total_ops);
+
     let mut result_overlay: Vec<String> = final_overlay_ids.into_iter().collect();
     let mut result_magic: Vec<String> = final_magic_ids.into_iter().collect();
+    let mut result_hymo: Vec<String> = final_hymo_ids.into_iter().collect();
+    let mut result_bind: Vec<String> = final_bind_ids.into_iter().collect();
 
     result_overlay.sort();
     result_magic.sort();
+    result_hymo.sort();
+    result_bind.sort();
+
+    fallback_reasons.retain(|id, _| {
+        result_overlay.contains(id)
+            || result_magic.contains(id)
+            || result_hymo.contains(id)
+            || result_bind.contains(id)
+    });
 
     Ok(ExecutionResult {
         overlay_module_ids: result_overlay,
         magic_module_ids: result_magic,
+        hymo_module_ids: result_hymo,
+        bind_module_ids: result_bind,
+        apex_names: apex_names.into_iter().collect(),
+        journal,
+        per_partition,
+        degraded: watchdog.degraded,
+        fallback_reasons,
+        busy_skipped,
+        cancelled,
+        foreign_mounts,
     })
 }
@@ -0,0 +1,176 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Mounts another root solution already placed on the partitions we are
+//! about to mount over.
+//!
+//! Magisk's magic mount or a second module manager running before us leaves
+//! overlayfs or tmpfs mounts on `/system` and friends. Stacking our own
+//! mounts on top of those gives results neither side expects, so they are
+//! found up front and handled according to `foreign_mount_policy`.
+
+use std::{
+    collections::BTreeSet,
+    path::{Component, Path, PathBuf},
+};
+
+use procfs::process::Process;
+use serde::{Deserialize, Serialize};
+
+use super::planner::{DiagnosticIssue, DiagnosticLevel, MountPlan};
+use crate::{
+    conf::config::{Config, ForeignMountPolicy},
+    core::state::RuntimeState,
+    defs::{self, Paths},
+};
+
+/// Known producers of foreign mounts, told apart by mount source.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ForeignKind {
+    Magisk,
+    /// Magisk's older `worker` tmpfs.
+    Worker,
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ForeignMount {
+    pub partition: String,
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+    pub source: String,
+    pub kind: ForeignKind,
+}
+
+pub fn classify(source: &str) -> ForeignKind {
+    let source = source.to_ascii_lowercase();
+    if source.contains("magisk") {
+        ForeignKind::Magisk
+    } else if source.contains("worker") {
+        ForeignKind::Worker
+    } else {
+        ForeignKind::Other
+    }
+}
+
+/// Partitions the plan mounts something on. Magic mount may touch any
+/// builtin partition, so those count too whenever it has work.
+pub fn plan_partitions(plan: &MountPlan) -> BTreeSet<String> {
+    let mut partitions: BTreeSet<String> = plan
+        .overlay_ops
+        .iter()
+        .map(|op| op.partition_name.clone())
+        .chain(plan.hymo_ops.iter().map(|op| op.partition_name.clone()))
+        .chain(plan.bind_ops.iter().map(|op| op.partition_name.clone()))
+        .collect();
+
+    if !plan.magic_module_ids.is_empty() {
+        partitions.extend(defs::BUILTIN_PARTITIONS.iter().map(|p| p.to_string()));
+        partitions.extend(plan.extra_partitions.iter().cloned());
+    }
+    partitions
+}
+
+fn partition_of(mount_point: &Path) -> Option<String> {
+    mount_point.components().find_map(|c| match c {
+        Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+        _ => None,
+    })
+}
+
+/// Picks the overlayfs and tmpfs mounts on `partitions` whose source is not
+/// one of `own_sources`. `mounts` yields `(mount point, fs type, source)`.
+pub fn detect(
+    mounts: impl IntoIterator<Item = (PathBuf, String, String)>,
+    partitions: &BTreeSet<String>,
+    own_sources: &[&str],
+) -> Vec<ForeignMount> {
+    mounts
+        .into_iter()
+        .filter(|(_, fs_type, _)| fs_type == "overlay" || fs_type == "tmpfs")
+        .filter(|(_, _, source)| !own_sources.contains(&source.as_str()))
+        .filter_map(|(mount_point, fs_type, source)| {
+            let partition = partition_of(&mount_point).filter(|p| partitions.contains(p))?;
+            Some(ForeignMount {
+                partition,
+                kind: classify(&source),
+                mount_point,
+                fs_type,
+                source,
+            })
+        })
+        .collect()
+}
+
+/// Scans the live mount table for foreign mounts on the plan's partitions.
+/// Mounts from our current source, or the one recorded by the previous run,
+/// are ours.
+pub fn scan(plan: &MountPlan, config: &Config, paths: &Paths) -> Vec<ForeignMount> {
+    let mounts = match Process::myself().and_then(|p| p.mountinfo()) {
+        Ok(mounts) => mounts,
+        Err(e) => {
+            log::warn!("Cannot read mountinfo to look for foreign mounts: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let previous = RuntimeState::load_from(&paths.state_file)
+        .map(|state| state.mount_source)
+        .unwrap_or_default();
+    let mut own = vec![config.mountsource.as_str()];
+    if !previous.is_empty() {
+        own.push(previous.as_str());
+    }
+
+    detect(
+        mounts
+            .into_iter()
+            .map(|m| (m.mount_point, m.fs_type, m.mount_source.unwrap_or_default())),
+        &plan_partitions(plan),
+        &own,
+    )
+}
+
+/// The distinct partitions affected by `found`.
+pub fn affected_partitions(found: &[ForeignMount]) -> BTreeSet<String> {
+    found.iter().map(|m| m.partition.clone()).collect()
+}
+
+/// One issue per affected partition, Critical when the policy aborts.
+pub fn diagnose(found: &[ForeignMount], policy: ForeignMountPolicy) -> Vec<DiagnosticIssue> {
+    let level = match policy {
+        ForeignMountPolicy::Abort => DiagnosticLevel::Critical,
+        ForeignMountPolicy::Stack | ForeignMountPolicy::SkipPartition => DiagnosticLevel::Warning,
+    };
+    let outcome = match policy {
+        ForeignMountPolicy::Abort => "the mount run will abort",
+        ForeignMountPolicy::Stack => "our mounts will be stacked on top",
+        ForeignMountPolicy::SkipPartition => "the partition will be skipped",
+    };
+
+    affected_partitions(found)
+        .into_iter()
+        .map(|partition| {
+            let mut sources: Vec<&str> = found
+                .iter()
+                .filter(|m| m.partition == partition)
+                .map(|m| m.source.as_str())
+                .collect();
+            sources.sort();
+            sources.dedup();
+            let count = found.iter().filter(|m| m.partition == partition).count();
+            DiagnosticIssue {
+                level: level.clone(),
+                context: "Foreign Mounts".to_string(),
+                message: format!(
+                    "/{} has {} mount(s) from another manager (source: {}); {}",
+                    partition,
+                    count,
+                    sources.join(", "),
+                    outcome
+                ),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,182 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Module files that are byte-identical to the stock file they replace.
+//!
+//! With `skip_identical_files` such files are left out of the storage copy,
+//! so neither overlayfs nor magic mount puts anything over the stock file:
+//! lower layers fall through and no bind mount is made. A symlinked stock
+//! path is compared through its resolved target. Files inside a `.replace`
+//! or opaque directory are always kept, since nothing falls through there,
+//! and a stock path with anything mounted over it is never trusted as stock.
+
+use std::{
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Component, Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::{
+    core::{
+        hashcache,
+        inventory::Module,
+        ops::planner::{DiagnosticIssue, DiagnosticLevel},
+    },
+    defs, utils,
+};
+
+const OVERLAYFS_SUPER_MAGIC: i64 = 0x794c_7630;
+
+/// Whether the first component of `relative` is a partition we mount on.
+fn in_partition(relative: &Path) -> bool {
+    match relative.components().next() {
+        Some(Component::Normal(name)) => name.to_str().is_some_and(|name| {
+            defs::BUILTIN_PARTITIONS.contains(&name) || defs::DLKM_PARTITIONS.contains(&name)
+        }),
+        _ => false,
+    }
+}
+
+/// Whether a directory of `relative` inside `module_dir` replaces the stock
+/// directory wholesale.
+fn under_replaced_dir(module_dir: &Path, relative: &Path) -> bool {
+    relative
+        .ancestors()
+        .skip(1)
+        .any(|dir| utils::is_replace_dir(&module_dir.join(dir)))
+}
+
+#[allow(clippy::unnecessary_cast)]
+fn on_overlayfs(path: &Path) -> bool {
+    rustix::fs::statfs(path).is_ok_and(|st| st.f_type as i64 == OVERLAYFS_SUPER_MAGIC)
+}
+
+/// The stock file `relative` replaces under `stock_root`, resolved through
+/// symlinks. `None` when there is no such regular file, when it is on an
+/// overlay, or when it is not on the filesystem of its partition root, as
+/// with magic mount's tmpfs mirrors and bind mounts: something, most likely
+/// an earlier run of ours, is mounted over it.
+fn stock_file(stock_root: &Path, relative: &Path) -> Option<(PathBuf, u64)> {
+    let root = stock_root.canonicalize().ok()?;
+    let stock = stock_root.join(relative).canonicalize().ok()?;
+    let meta = fs::metadata(&stock).ok()?;
+    if !meta.is_file() || on_overlayfs(&stock) {
+        return None;
+    }
+
+    let partition = stock
+        .ancestors()
+        .find(|dir| dir.parent() == Some(root.as_path()))?;
+    if fs::metadata(partition).ok()?.dev() != meta.dev() {
+        return None;
+    }
+    Some((stock, meta.len()))
+}
+
+/// Whether `module_file`, at `relative` inside `module_dir`, may be left
+/// out because the stock file under `stock_root` has the same contents.
+/// Sizes are compared first, then hashes through the hash cache.
+pub fn matches_stock(
+    module_dir: &Path,
+    module_file: &Path,
+    relative: &Path,
+    stock_root: &Path,
+) -> bool {
+    let Ok(meta) = fs::symlink_metadata(module_file) else {
+        return false;
+    };
+    if !meta.is_file() || !in_partition(relative) || utils::is_rw_marker(module_file) {
+        return false;
+    }
+    let Some((stock, stock_len)) = stock_file(stock_root, relative) else {
+        return false;
+    };
+    if stock_len != meta.len() || under_replaced_dir(module_dir, relative) {
+        return false;
+    }
+
+    match (
+        hashcache::hash_file_cached(module_file),
+        hashcache::hash_file_cached(&stock),
+    ) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Files of the module at `module_dir` that `skip_identical_files` would
+/// leave unmounted, for the dry run.
+pub fn count(module_dir: &Path, stock_root: &Path) -> usize {
+    WalkDir::new(module_dir)
+        .min_depth(2)
+        .into_iter()
+        .flatten()
+        .filter(|entry| entry.file_type().is_file())
+        .filter(|entry| {
+            entry
+                .path()
+                .strip_prefix(module_dir)
+                .is_ok_and(|relative| matches_stock(module_dir, entry.path(), relative, stock_root))
+        })
+        .count()
+}
+
+/// One Info issue per module with files identical to stock, saying how many
+/// mounts `skip_identical_files` saves.
+pub fn diagnose(modules: &[Module], stock_root: &Path) -> Vec<DiagnosticIssue> {
+    modules
+        .iter()
+        .filter_map(|module| {
+            let identical = count(&module.source_path, stock_root);
+            (identical > 0).then(|| DiagnosticIssue {
+                level: DiagnosticLevel::Info,
+                context: module.id.clone(),
+                message: format!(
+                    "{} file(s) identical to stock are skipped, saving {} mount(s)",
+                    identical, identical
+                ),
+            })
+        })
+        .collect()
+}
+
+/// Digest of the stock files the files of `modules` would be compared
+/// against: path, size, mtime and inode of each. A stock file that changes
+/// changes which module files [`matches_stock`] leaves out.
+pub fn fingerprint(modules: &[Module], stock_root: &Path) -> String {
+    let mut hasher = Sha256::new();
+    for module in modules {
+        hasher.update(module.id.as_bytes());
+        for entry in WalkDir::new(&module.source_path)
+            .min_depth(2)
+            .sort_by_file_name()
+            .into_iter()
+            .flatten()
+            .filter(|entry| entry.file_type().is_file())
+        {
+            let Ok(relative) = entry.path().strip_prefix(&module.source_path) else {
+                continue;
+            };
+            if !in_partition(relative) {
+                continue;
+            }
+            let Some(meta) =
+                stock_file(stock_root, relative).and_then(|(stock, _)| fs::metadata(stock).ok())
+            else {
+                continue;
+            };
+            hasher.update(relative.as_os_str().as_encoded_bytes());
+            hasher.update(meta.len().to_le_bytes());
+            hasher.update(meta.mtime().to_le_bytes());
+            hasher.update(meta.ino().to_le_bytes());
+        }
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
@@ -0,0 +1,110 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use rustix::mount::{UnmountFlags, unmount};
+use serde::{Deserialize, Serialize};
+
+use crate::{mount::overlayfs::overlayfs::MountMethod, sys::mount::is_mounted, utils};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub target: PathBuf,
+    pub method: MountMethod,
+}
+
+/// Records every overlay mount established during execution so that a
+/// failed run can be unwound in reverse order.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UndoJournal {
+    pub entries: Vec<JournalEntry>,
+    #[serde(skip)]
+    persist: bool,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl UndoJournal {
+    pub fn new(persist: bool, path: &Path) -> Self {
+        Self {
+            entries: Vec::new(),
+            persist,
+            path: path.to_path_buf(),
+        }
+    }
+
+    pub fn record<P: AsRef<Path>>(&mut self, target: P, method: MountMethod) {
+        self.entries.push(JournalEntry {
+            target: target.as_ref().to_path_buf(),
+            method,
+        });
+
+        if self.persist
+            && let Err(e) = self.save()
+        {
+            log::warn!("Failed to persist mount journal: {:#}", e);
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let json = serde_json::to_string(self).context("Failed to serialize mount journal")?;
+        utils::atomic_write(&self.path, json)
+    }
+
+    pub fn rollback(&mut self) {
+        log::warn!(">> Rolling back {} overlay mount(s)...", self.entries.len());
+
+        while let Some(entry) = self.entries.pop() {
+            if !is_mounted(&entry.target) {
+                continue;
+            }
+
+            match unmount(&entry.target, UnmountFlags::DETACH) {
+                Ok(_) => log::info!(
+                    "Rolled back {} ({:?})",
+                    entry.target.display(),
+                    entry.method
+                ),
+                Err(e) => log::error!("Failed to roll back {}: {}", entry.target.display(), e),
+            }
+        }
+
+        Self::discard(&self.path);
+    }
+
+    pub fn discard(path: &Path) {
+        if path.exists()
+            && let Err(e) = fs::remove_file(path)
+        {
+            log::warn!("Failed to remove mount journal: {}", e);
+        }
+    }
+
+    /// Unwinds mounts left behind by a run that crashed before completing.
+    pub fn recover_stale(path: &Path) {
+        if !path.exists() {
+            return;
+        }
+
+        let journal = fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<UndoJournal>(&content).ok());
+
+        match journal {
+            Some(mut journal) => {
+                log::warn!("Found mount journal from an interrupted run, cleaning up.");
+                journal.path = path.to_path_buf();
+                journal.rollback();
+            }
+            None => {
+                log::warn!("Discarding unreadable mount journal.");
+                Self::discard(path);
+            }
+        }
+    }
+}
@@ -1,6 +1,21 @@
 // Copyright 2026 Hybrid Mount Developers
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+pub mod audit;
+pub mod blacklist;
+pub mod conflict;
 pub mod executor;
+pub mod foreign;
+pub mod identical;
+pub mod journal;
+pub mod mount_alias;
+pub mod partition_map;
+pub mod plan_export;
 pub mod planner;
+pub mod probe;
+pub mod replace;
+pub mod simulate;
 pub mod sync;
+pub mod sync_journal;
+pub mod update_preview;
+pub mod verity;
@@ -0,0 +1,175 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Overlay targets that are bind mounts of each other.
+//!
+//! Some ROMs mount `/odm` as a bind mount of `/vendor/odm`. Both spellings
+//! canonicalize to themselves, so the planner would give each its own
+//! overlay: the one mounted second hides the first, and their conflicts
+//! never meet in one stack. Each target is resolved through mountinfo to
+//! the device and the path inside it that it shows; targets showing the
+//! same subtree are collapsed into one op on the canonical spelling.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsString,
+    os::unix::ffi::OsStringExt,
+    path::{Path, PathBuf},
+};
+
+/// One line of `/proc/self/mountinfo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountEntry {
+    /// `major:minor` of the filesystem.
+    pub device: String,
+    /// Directory of the filesystem shown at `mount_point`; `/` unless it is
+    /// a bind mount of a subdirectory.
+    pub root: PathBuf,
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+}
+
+/// The subtree a path shows: a directory inside a filesystem.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MountIdentity {
+    pub device: String,
+    pub path: PathBuf,
+}
+
+/// An overlay target merged into another spelling of the same directory.
+#[derive(Debug, Clone)]
+pub struct TargetAlias {
+    pub alias: String,
+    pub canonical: String,
+    /// Modules whose layers moved from `alias` to `canonical`.
+    pub modules: Vec<String>,
+}
+
+/// Undoes the octal escapes mountinfo writes for spaces, tabs, newlines
+/// and backslashes.
+fn unescape(field: &str) -> PathBuf {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && let Some(code) = field
+                .get(i + 1..i + 4)
+                .and_then(|digits| u8::from_str_radix(digits, 8).ok())
+        {
+            out.push(code);
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    PathBuf::from(OsString::from_vec(out))
+}
+
+fn parse_line(line: &str) -> Option<MountEntry> {
+    let mut fields = line.split_whitespace();
+    let device = fields.nth(2)?.to_string();
+    let root = unescape(fields.next()?);
+    let mount_point = unescape(fields.next()?);
+    // Mount options, then optional fields up to the `-` separator.
+    let fs_type = fields.skip_while(|field| *field != "-").nth(1)?.to_string();
+    Some(MountEntry {
+        device,
+        root,
+        mount_point,
+        fs_type,
+    })
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MountTable {
+    entries: Vec<MountEntry>,
+}
+
+impl MountTable {
+    /// Parses the text of a mountinfo file, skipping malformed lines.
+    pub fn parse(mountinfo: &str) -> Self {
+        Self {
+            entries: mountinfo.lines().filter_map(parse_line).collect(),
+        }
+    }
+
+    pub fn entries(&self) -> &[MountEntry] {
+        &self.entries
+    }
+
+    /// The mount `path` is on. Overlays are skipped, as they are ours or
+    /// stacked on the stock mount below, and of several mounts at the same
+    /// point the first, the stock one, counts.
+    fn mount_of(&self, path: &Path) -> Option<&MountEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.fs_type != "overlay" && path.starts_with(&entry.mount_point))
+            .max_by_key(|entry| entry.mount_point.components().count())
+    }
+
+    /// The subtree the absolute device path `path` shows.
+    pub fn identity(&self, path: &Path) -> Option<MountIdentity> {
+        let entry = self.mount_of(path)?;
+        let inside = path.strip_prefix(&entry.mount_point).ok()?;
+        Some(MountIdentity {
+            device: entry.device.clone(),
+            path: entry.root.join(inside),
+        })
+    }
+}
+
+/// Merges the overlay groups whose targets show the same subtree into the
+/// group of the canonical target: the one reached without a bind mount of
+/// a subdirectory, else the shortest path. Layers keep `module_order`; a
+/// module with layers under both spellings has the canonical one on top.
+pub fn collapse(
+    groups: &mut HashMap<PathBuf, Vec<(String, PathBuf)>>,
+    table: &MountTable,
+    module_order: &[String],
+) -> Vec<TargetAlias> {
+    let mut by_identity: BTreeMap<MountIdentity, Vec<(usize, PathBuf)>> = BTreeMap::new();
+    for target in groups.keys() {
+        let (Some(identity), Some(mount)) = (table.identity(target), table.mount_of(target)) else {
+            continue;
+        };
+        by_identity
+            .entry(identity)
+            .or_default()
+            .push((mount.root.components().count(), target.clone()));
+    }
+
+    let rank = |id: &str| module_order.iter().position(|m| m == id);
+    let mut aliases = Vec::new();
+    for mut targets in by_identity.into_values().filter(|t| t.len() > 1) {
+        targets.sort_by(|a, b| {
+            (a.0, a.1.components().count(), &a.1).cmp(&(b.0, b.1.components().count(), &b.1))
+        });
+        let canonical = targets[0].1.clone();
+        let mut merged = groups.remove(&canonical).unwrap_or_default();
+
+        for (_, alias) in &targets[1..] {
+            let layers = groups.remove(alias).unwrap_or_default();
+            let mut modules: Vec<String> = layers.iter().map(|(id, _)| id.clone()).collect();
+            modules.dedup();
+            log::warn!(
+                "{} is a bind mount of {}; mounting the layers of {} there",
+                alias.display(),
+                canonical.display(),
+                modules.join(", ")
+            );
+            aliases.push(TargetAlias {
+                alias: alias.to_string_lossy().to_string(),
+                canonical: canonical.to_string_lossy().to_string(),
+                modules,
+            });
+            merged.extend(layers);
+        }
+
+        merged.sort_by_key(|(id, _)| rank(id).unwrap_or(usize::MAX));
+        groups.insert(canonical, merged);
+    }
+    aliases
+}
@@ -0,0 +1,209 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Where each partition a module can ship lives on the device.
+//!
+//! `vendor`, `product`, `system_ext` and `odm` may be shipped at the module
+//! root or below `system/`. On system-as-root devices `/vendor` is the mount
+//! and `/system/vendor` links to it; on older A-only devices `/vendor` links
+//! to `/system/vendor`. Either way both spellings name one directory. The
+//! planner and magic mount resolve partitions through [`PartitionMap`] so
+//! they agree on that directory, and take module content from
+//! [`module_sources`] so both spellings merge in one order: `<mod>/vendor`
+//! above `<mod>/system/vendor`.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use walkdir::WalkDir;
+
+use crate::core::{
+    inventory::Module,
+    ops::{
+        planner::{DiagnosticIssue, DiagnosticLevel},
+        probe::SystemProbe,
+    },
+};
+
+/// Partitions that can also be spelled `system/<name>` in a module.
+pub const ALIASED_PARTITIONS: &[&str] = &["vendor", "product", "system_ext", "odm"];
+
+#[derive(Debug, Clone, Default)]
+pub struct PartitionMap {
+    /// Canonical device directory of each partition that exists.
+    targets: BTreeMap<String, PathBuf>,
+    /// Aliased partitions whose `system/<name>` spelling is the same
+    /// directory as `/<name>`.
+    merged: Vec<String>,
+}
+
+impl PartitionMap {
+    /// Resolves `system`, the aliased partitions and `extra` on the device
+    /// seen through `probe`.
+    pub fn resolve(extra: &[String], probe: &dyn SystemProbe) -> Self {
+        let mut map = Self::default();
+
+        let names = ["system"]
+            .into_iter()
+            .chain(ALIASED_PARTITIONS.iter().copied())
+            .chain(extra.iter().map(String::as_str));
+        for name in names {
+            if map.targets.contains_key(name) || map.is_merged(name) {
+                continue;
+            }
+            let at_root = canonical_dir(&Path::new("/").join(name), probe);
+
+            if ALIASED_PARTITIONS.contains(&name) {
+                let nested = canonical_dir(&Path::new("/system").join(name), probe);
+                // A nested directory of its own is part of /system, not an
+                // alias of /<name>.
+                if at_root.is_none() || nested.is_none() || at_root == nested {
+                    map.merged.push(name.to_string());
+                }
+                if let Some(target) = at_root.or(nested) {
+                    map.targets.insert(name.to_string(), target);
+                }
+            } else if let Some(target) = at_root {
+                map.targets.insert(name.to_string(), target);
+            }
+        }
+        map
+    }
+
+    /// The canonical device directory of `partition`, if it exists.
+    pub fn target(&self, partition: &str) -> Option<&Path> {
+        self.targets.get(partition).map(PathBuf::as_path)
+    }
+
+    /// Whether `system/<partition>` in a module is another spelling of
+    /// `<partition>`.
+    pub fn is_merged(&self, partition: &str) -> bool {
+        self.merged.iter().any(|p| p == partition)
+    }
+
+    /// Whether `partition` is mounted on its own rather than as part of
+    /// `/system`.
+    pub fn is_standalone(&self, partition: &str) -> bool {
+        partition != "system"
+            && self
+                .target(partition)
+                .is_some_and(|target| !target.starts_with("/system"))
+    }
+
+    /// The `system/<partition>` directories of the module at `module_root`
+    /// that belong to another partition and must be left out when walking
+    /// its `system/`.
+    pub fn nested_sources(&self, module_root: &Path) -> Vec<PathBuf> {
+        self.merged
+            .iter()
+            .map(|p| module_root.join("system").join(p))
+            .filter(|path| path.is_dir())
+            .collect()
+    }
+}
+
+fn canonical_dir(path: &Path, probe: &dyn SystemProbe) -> Option<PathBuf> {
+    probe
+        .canonicalize(path)
+        .filter(|target| probe.is_dir(target))
+}
+
+/// The directories of the module at `module_root` that feed a partition
+/// `wanted` accepts, as `(partition, directory)`. Sorted by partition, and
+/// within one partition by precedence: `<mod>/<name>` before
+/// `<mod>/system/<name>`.
+pub fn module_sources(
+    module_root: &Path,
+    map: &PartitionMap,
+    wanted: &dyn Fn(&str) -> bool,
+) -> Vec<(String, PathBuf)> {
+    let mut sources = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(module_root) {
+        for entry in entries.flatten() {
+            if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            if wanted(&name) {
+                sources.push((name, entry.path(), 0));
+            }
+        }
+    }
+
+    for path in map.nested_sources(module_root) {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if wanted(&name) {
+            sources.push((name, path, 1));
+        }
+    }
+
+    sources.sort_by(|a, b| (&a.0, a.2).cmp(&(&b.0, b.2)));
+    sources
+        .into_iter()
+        .map(|(name, path, _)| (name, path))
+        .collect()
+}
+
+/// Files the module at `module_root` ships under both spellings of a merged
+/// partition, relative to the partition, e.g. `vendor/etc/a.conf`. Only the
+/// `<mod>/<name>` copy is mounted.
+pub fn spelling_conflicts(module_root: &Path, map: &PartitionMap) -> Vec<PathBuf> {
+    let mut conflicts = Vec::new();
+
+    for nested in map.nested_sources(module_root) {
+        let Some(name) = nested.file_name() else {
+            continue;
+        };
+        let top = module_root.join(name);
+        if !top.is_dir() {
+            continue;
+        }
+
+        for entry in WalkDir::new(&top).min_depth(1).sort_by_file_name() {
+            let Ok(entry) = entry else {
+                continue;
+            };
+            if entry.file_type().is_dir() {
+                continue;
+            }
+            let Ok(relative) = entry.path().strip_prefix(&top) else {
+                continue;
+            };
+            if fs::symlink_metadata(nested.join(relative)).is_ok_and(|m| !m.is_dir()) {
+                conflicts.push(Path::new(name).join(relative));
+            }
+        }
+    }
+    conflicts
+}
+
+/// One warning per module shipping the same file as both `<name>/` and
+/// `system/<name>/`.
+pub fn diagnose(modules: &[Module], probe: &dyn SystemProbe) -> Vec<DiagnosticIssue> {
+    let map = PartitionMap::resolve(&[], probe);
+
+    modules
+        .iter()
+        .filter_map(|module| {
+            let conflicts = spelling_conflicts(&module.source_path, &map);
+            let first = conflicts.first()?;
+            Some(DiagnosticIssue {
+                level: DiagnosticLevel::Warning,
+                context: module.id.clone(),
+                message: format!(
+                    "{} file(s) are shipped both as {} and as system/{}; the first copy is used",
+                    conflicts.len(),
+                    first.display(),
+                    first.display()
+                ),
+            })
+        })
+        .collect()
+}
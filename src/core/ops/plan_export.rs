@@ -0,0 +1,277 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! The mount plan as data, and what changed between two plans.
+//!
+//! `meta-hybrid plan` prints a [`PlanExport`]; `plan-diff` compares the
+//! current plan against one saved earlier. Everything in the export is
+//! sorted or kept in layer order, so an unchanged plan exports the same
+//! bytes every time.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::{core::ops::planner::MountPlan, utils};
+
+pub const PLAN_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlanExport {
+    pub schema_version: u32,
+    /// Module ids from topmost to lowest layer.
+    pub module_order: Vec<String>,
+    /// Overlay targets by partition, sorted by target.
+    pub partitions: BTreeMap<String, Vec<OverlayTarget>>,
+    /// Magic mounted modules with their file count per partition.
+    pub magic: BTreeMap<String, BTreeMap<String, usize>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hymo: Vec<HymoMerge>,
+    /// Files bound directly, by module.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub bind: BTreeMap<String, Vec<String>>,
+    /// How each module is mounted: `overlay`, `magic`, `hymo` or `bind`,
+    /// several when its partitions differ.
+    pub modes: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OverlayTarget {
+    pub target: String,
+    /// Top layer first; it wins conflicts.
+    pub layers: Vec<Layer>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Layer {
+    pub module: String,
+    pub source: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HymoMerge {
+    pub module: String,
+    pub partition: String,
+    pub source: String,
+    pub target: String,
+}
+
+impl MountPlan {
+    /// The plan in the [`PlanExport`] schema, ready to serialize.
+    pub fn to_json(&self) -> PlanExport {
+        let mut partitions: BTreeMap<String, Vec<OverlayTarget>> = BTreeMap::new();
+        for op in &self.overlay_ops {
+            let layers = op
+                .lowerdirs
+                .iter()
+                .map(|dir| Layer {
+                    module: utils::extract_module_id(dir).unwrap_or_default(),
+                    source: dir.display().to_string(),
+                })
+                .collect();
+            partitions
+                .entry(op.partition_name.clone())
+                .or_default()
+                .push(OverlayTarget {
+                    target: op.target.clone(),
+                    layers,
+                });
+        }
+        for targets in partitions.values_mut() {
+            targets.sort_by(|a, b| a.target.cmp(&b.target));
+        }
+
+        let magic = self
+            .magic_module_ids
+            .iter()
+            .map(|id| {
+                let files = self.magic_files.get(id).cloned().unwrap_or_default();
+                (id.clone(), files)
+            })
+            .collect();
+
+        let mut hymo: Vec<HymoMerge> = self
+            .hymo_ops
+            .iter()
+            .map(|op| HymoMerge {
+                module: op.module_id.clone(),
+                partition: op.partition_name.clone(),
+                source: op.source.display().to_string(),
+                target: op.target.display().to_string(),
+            })
+            .collect();
+        hymo.sort_by(|a, b| (&a.target, &a.module).cmp(&(&b.target, &b.module)));
+
+        let mut bind: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for op in &self.bind_ops {
+            bind.entry(op.module_id.clone())
+                .or_default()
+                .push(op.target.display().to_string());
+        }
+        for targets in bind.values_mut() {
+            targets.sort();
+        }
+
+        let mut modes: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (mode, ids) in [
+            ("bind", &self.bind_module_ids),
+            ("hymo", &self.hymo_module_ids),
+            ("magic", &self.magic_module_ids),
+            ("overlay", &self.overlay_module_ids),
+        ] {
+            for id in ids {
+                modes.entry(id.clone()).or_default().push(mode.to_string());
+            }
+        }
+
+        PlanExport {
+            schema_version: PLAN_SCHEMA_VERSION,
+            module_order: self.module_order.clone(),
+            partitions,
+            magic,
+            hymo,
+            bind,
+            modes,
+        }
+    }
+}
+
+/// Reads a plan saved from `meta-hybrid plan`.
+pub fn load(path: &Path) -> Result<PlanExport> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let export: PlanExport = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    if export.schema_version != PLAN_SCHEMA_VERSION {
+        bail!(
+            "{} has plan schema {}, expected {}",
+            path.display(),
+            export.schema_version,
+            PLAN_SCHEMA_VERSION
+        );
+    }
+    Ok(export)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PlanDiff {
+    pub added_layers: Vec<LayerChange>,
+    pub removed_layers: Vec<LayerChange>,
+    /// Targets whose remaining layers are stacked in another order, which
+    /// changes the module that wins a conflict.
+    pub reordered: Vec<Reorder>,
+    /// Modules mounted another way; an empty list means not mounted.
+    pub mode_changes: Vec<ModeChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LayerChange {
+    pub target: String,
+    pub module: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Reorder {
+    pub target: String,
+    /// Module ids top first.
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModeChange {
+    pub module: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+impl PlanDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_layers.is_empty()
+            && self.removed_layers.is_empty()
+            && self.reordered.is_empty()
+            && self.mode_changes.is_empty()
+    }
+}
+
+/// Layer module ids of every overlay target, top first.
+fn stacks(export: &PlanExport) -> BTreeMap<&str, Vec<&str>> {
+    export
+        .partitions
+        .values()
+        .flatten()
+        .map(|t| {
+            let modules = t.layers.iter().map(|l| l.module.as_str()).collect();
+            (t.target.as_str(), modules)
+        })
+        .collect()
+}
+
+/// Removes one occurrence of each of `other` from `modules`.
+fn without<'a>(modules: &[&'a str], other: &[&str]) -> Vec<&'a str> {
+    let mut left = other.to_vec();
+    modules
+        .iter()
+        .copied()
+        .filter(|m| match left.iter().position(|o| o == m) {
+            Some(i) => {
+                left.remove(i);
+                false
+            }
+            None => true,
+        })
+        .collect()
+}
+
+/// What `current` changes compared to `baseline`, sorted by target and
+/// module.
+pub fn diff(baseline: &PlanExport, current: &PlanExport) -> PlanDiff {
+    let mut result = PlanDiff::default();
+    let before = stacks(baseline);
+    let after = stacks(current);
+
+    let targets: BTreeSet<&str> = before.keys().chain(after.keys()).copied().collect();
+    for target in targets {
+        let old = before.get(target).cloned().unwrap_or_default();
+        let new = after.get(target).cloned().unwrap_or_default();
+
+        let removed = without(&old, &new);
+        let added = without(&new, &old);
+        let change = |module: &str| LayerChange {
+            target: target.to_string(),
+            module: module.to_string(),
+        };
+        result
+            .removed_layers
+            .extend(removed.iter().map(|m| change(m)));
+        result.added_layers.extend(added.iter().map(|m| change(m)));
+
+        if without(&old, &removed) != without(&new, &added) {
+            result.reordered.push(Reorder {
+                target: target.to_string(),
+                before: old.iter().map(|m| m.to_string()).collect(),
+                after: new.iter().map(|m| m.to_string()).collect(),
+            });
+        }
+    }
+
+    let modules: BTreeSet<&String> = baseline.modes.keys().chain(current.modes.keys()).collect();
+    for module in modules {
+        let old = baseline.modes.get(module).cloned().unwrap_or_default();
+        let new = current.modes.get(module).cloned().unwrap_or_default();
+        if old != new {
+            result.mode_changes.push(ModeChange {
+                module: module.clone(),
+                before: old,
+                after: new,
+            });
+        }
+    }
+
+    result
+}
@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
     fs,
     path::{Path, PathBuf},
 };
@@ -14,8 +14,20 @@ use walkdir::WalkDir;
 
 use crate::{
     conf::config,
-    core::inventory::{Module, MountMode},
-    defs, utils,
+    core::{
+        inventory::{Module, MountMode},
+        ops::{
+            blacklist::{self, Blacklist, BlacklistedFile},
+            conflict::{self, ConflictContender, ConflictSeverity},
+            mount_alias::{self, MountTable, TargetAlias},
+            partition_map::{self, PartitionMap},
+            probe::{LiveSystem, SystemProbe},
+        },
+        winnow,
+    },
+    defs,
+    mount::overlayfs::overlayfs::{MAX_LOWERDIR_COUNT, max_lowerdir_len},
+    utils::{self, pool, timing},
 };
 
 #[derive(Debug, Clone)]
@@ -25,23 +37,121 @@ pub struct OverlayOperation {
     pub lowerdirs: Vec<PathBuf>,
 }
 
-#[derive(Debug, Default)]
+/// Merges one module partition directory onto its live counterpart through
+/// HymoFS instead of mounting over it.
+#[derive(Debug, Clone)]
+pub struct HymoOperation {
+    pub module_id: String,
+    pub partition_name: String,
+    pub source: PathBuf,
+    pub target: PathBuf,
+}
+
+/// Bind-mounts one module file straight over the stock file it replaces.
+#[derive(Debug, Clone)]
+pub struct BindOperation {
+    pub module_id: String,
+    pub partition_name: String,
+    pub source: PathBuf,
+    pub target: PathBuf,
+}
+
+/// Where APEX modules are mounted and where a module ships files for them.
+pub const APEX_DIR_NAME: &str = "apex";
+
+/// Most files a module may replace and still be mounted through
+/// `BindOperation`s under `prefer_bind_for_small_modules`.
+pub const BIND_MAX_FILES: usize = 5;
+
+/// A module file that stays writable after mounting.
+#[derive(Debug, Clone)]
+pub struct WritableFile {
+    pub module_id: String,
+    /// Live path the file appears at, e.g. `/system/etc/hosts`.
+    pub path: PathBuf,
+    /// Path relative to the module root, e.g. `system/etc/hosts`.
+    pub relative: PathBuf,
+    /// Copy inside the storage the mount is served from.
+    pub source: PathBuf,
+    pub overlay: bool,
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct MountPlan {
     pub overlay_ops: Vec<OverlayOperation>,
+    pub hymo_ops: Vec<HymoOperation>,
+    pub bind_ops: Vec<BindOperation>,
+    /// Module files bound over files of active APEX mounts under
+    /// `allow_apex`. Kept apart from `bind_ops`: a failure skips the file
+    /// instead of moving the module to magic mount.
+    pub apex_ops: Vec<BindOperation>,
+    /// APEX names `apex_ops` bind into, sorted.
+    pub apex_names: Vec<String>,
+    /// Module `apex/` entries left out, with the reason.
+    pub apex_skipped: Vec<LayerDemotion>,
     pub overlay_module_ids: Vec<String>,
     pub magic_module_ids: Vec<String>,
+    pub hymo_module_ids: Vec<String>,
+    pub bind_module_ids: Vec<String>,
+    /// Modules that asked for HymoFS while it is unavailable.
+    pub hymo_fallback: Vec<String>,
+    /// Module ids from topmost to lowest layer.
+    pub module_order: Vec<String>,
+    /// Modules whose `after` declarations form a cycle.
+    pub order_cycle: Vec<String>,
+    /// Partition directories each module excluded through its rules.
+    pub exclusions: HashMap<String, HashSet<String>>,
+    pub writable_files: Vec<WritableFile>,
+    /// Modules moved to magic mount because an overlay stack hit a kernel limit.
+    pub demoted: Vec<LayerDemotion>,
+    /// Small modules kept on overlayfs because a file they replace is also
+    /// shipped by an overlay module.
+    pub bind_rejected: Vec<LayerDemotion>,
+    /// Partitions mounted besides `BUILTIN_PARTITIONS`: the configured
+    /// `partitions` plus any found by `auto_partitions`.
+    pub extra_partitions: Vec<String>,
+    /// Module directories left out because they resolve to a path in
+    /// `DENIED_MOUNT_TARGETS` or `user_deny_paths`.
+    pub denied: Vec<LayerDemotion>,
+    /// Module files kept out of the mount by the blacklist, per module in
+    /// path order.
+    pub blacklisted: Vec<BlacklistedFile>,
+    /// Winnowing rules forcing a module for a path it blacklists.
+    pub blacklist_conflicts: Vec<LayerDemotion>,
+    /// Files each module magic mounts, by partition.
+    pub magic_files: BTreeMap<String, BTreeMap<String, usize>>,
+    /// Storage layers left out because their module is not enabled.
+    pub stale_layers: Vec<LayerDemotion>,
+    /// Overlay targets that are bind mounts of another target, whose
+    /// layers were moved onto it.
+    pub target_aliases: Vec<TargetAlias>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LayerDemotion {
+    pub module_id: String,
+    pub target: String,
+    pub reason: String,
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct ConflictEntry {
     pub partition: String,
-    pub relative_path: String,
+    pub target: String,
+    /// Path below `target`, byte for byte as on disk.
+    #[serde(skip)]
+    pub relative_path: PathBuf,
+    /// `relative_path` for reports; invalid UTF-8 is replaced.
+    #[serde(rename = "relative_path")]
+    pub relative_display: String,
     pub contending_modules: Vec<String>,
+    pub contenders: Vec<ConflictContender>,
+    pub identical: bool,
+    pub severity: ConflictSeverity,
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub enum DiagnosticLevel {
-    #[allow(dead_code)]
     Info,
     Warning,
     Critical,
@@ -62,87 +172,968 @@ pub struct AnalysisReport {
 
 #[allow(clippy::collapsible_if)]
 impl MountPlan {
+    /// Drops every operation on `partitions` and excludes those partition
+    /// directories from magic mounted modules. Returns the modules that lost
+    /// at least one operation.
+    pub fn drop_partitions(&mut self, partitions: &BTreeSet<String>) -> BTreeSet<String> {
+        let mut affected = BTreeSet::new();
+
+        self.overlay_ops.retain(|op| {
+            let keep = !partitions.contains(&op.partition_name);
+            if !keep {
+                affected.extend(
+                    op.lowerdirs
+                        .iter()
+                        .filter_map(|p| utils::extract_module_id(p)),
+                );
+            }
+            keep
+        });
+        self.hymo_ops.retain(|op| {
+            let keep = !partitions.contains(&op.partition_name);
+            if !keep {
+                affected.insert(op.module_id.clone());
+            }
+            keep
+        });
+        self.bind_ops.retain(|op| {
+            let keep = !partitions.contains(&op.partition_name);
+            if !keep {
+                affected.insert(op.module_id.clone());
+            }
+            keep
+        });
+        self.apex_ops.retain(|op| {
+            let keep = !partitions.contains(&op.partition_name);
+            if !keep {
+                affected.insert(op.module_id.clone());
+            }
+            keep
+        });
+
+        for files in self.magic_files.values_mut() {
+            files.retain(|partition, _| !partitions.contains(partition));
+        }
+        for id in &self.magic_module_ids {
+            self.exclusions
+                .entry(id.clone())
+                .or_default()
+                .extend(partitions.iter().cloned());
+        }
+
+        affected
+    }
+
+    /// Blacklisted module files by module id, as paths inside the module.
+    pub fn blacklisted_paths(&self) -> HashMap<String, HashSet<PathBuf>> {
+        let mut paths: HashMap<String, HashSet<PathBuf>> = HashMap::new();
+        for file in &self.blacklisted {
+            paths
+                .entry(file.module_id.clone())
+                .or_default()
+                .insert(file.relative.clone());
+        }
+        paths
+    }
+
     pub fn analyze(&self) -> AnalysisReport {
-        let results: Vec<(Vec<ConflictEntry>, Vec<DiagnosticIssue>)> = self
+        self.analyze_with_probe(&LiveSystem)
+    }
+
+    /// Same as [`MountPlan::analyze`] with mount targets checked through `probe`.
+    pub fn analyze_with_probe(&self, probe: &dyn SystemProbe) -> AnalysisReport {
+        self.analyze_scoped(probe, None)
+    }
+
+    /// Same as [`MountPlan::analyze`], looking for conflicts and dead
+    /// symlinks only at or below the absolute path `prefix`.
+    pub fn analyze_under(&self, prefix: &Path) -> AnalysisReport {
+        self.analyze_under_with_probe(&LiveSystem, prefix)
+    }
+
+    /// Same as [`MountPlan::analyze_under`] with mount targets checked
+    /// through `probe`.
+    pub fn analyze_under_with_probe(
+        &self,
+        probe: &dyn SystemProbe,
+        prefix: &Path,
+    ) -> AnalysisReport {
+        self.analyze_scoped(probe, Some(prefix))
+    }
+
+    fn analyze_scoped(&self, probe: &dyn SystemProbe, prefix: Option<&Path>) -> AnalysisReport {
+        let mut span = timing::span("analyze");
+        // Overlay operations inside the scope, with the subdirectory of the
+        // target to walk when the scope is narrower than the target.
+        let scoped: Vec<(usize, &OverlayOperation, PathBuf)> = self
             .overlay_ops
-            .par_iter()
-            .map(|op| {
-                let mut local_conflicts = Vec::new();
-                let mut local_diagnostics = Vec::new();
-                let mut file_map: HashMap<String, Vec<String>> = HashMap::new();
-
-                if !Path::new(&op.target).exists() {
-                    local_diagnostics.push(DiagnosticIssue {
-                        level: DiagnosticLevel::Critical,
-                        context: op.partition_name.clone(),
-                        message: format!("Target mount point does not exist: {}", op.target),
-                    });
+            .iter()
+            .enumerate()
+            .filter_map(|(i, op)| {
+                let target = Path::new(&op.target);
+                match prefix {
+                    None => Some((i, op, PathBuf::new())),
+                    Some(prefix) => match prefix.strip_prefix(target) {
+                        Ok(sub) => Some((i, op, sub.to_path_buf())),
+                        Err(_) if target.starts_with(prefix) => Some((i, op, PathBuf::new())),
+                        Err(_) => None,
+                    },
                 }
+            })
+            .collect();
 
-                for layer_path in &op.lowerdirs {
-                    if !layer_path.exists() {
-                        continue;
-                    }
+        let mut op_diagnostics: Vec<DiagnosticIssue> = scoped
+            .iter()
+            .filter(|(_, op, _)| !probe.exists(Path::new(&op.target)))
+            .map(|(_, op, _)| DiagnosticIssue {
+                level: DiagnosticLevel::Critical,
+                context: op.partition_name.clone(),
+                message: format!("Target mount point does not exist: {}", op.target),
+            })
+            .collect();
 
-                    let module_id =
-                        utils::extract_module_id(layer_path).unwrap_or_else(|| "UNKNOWN".into());
-
-                    for entry in WalkDir::new(layer_path).min_depth(1).into_iter().flatten() {
-                        if entry.path_is_symlink() {
-                            if let Ok(target) = std::fs::read_link(entry.path()) {
-                                if target.is_absolute() && !target.exists() {
-                                    local_diagnostics.push(DiagnosticIssue {
-                                        level: DiagnosticLevel::Warning,
-                                        context: module_id.clone(),
-                                        message: format!(
-                                            "Dead absolute symlink: {} -> {}",
-                                            entry.path().display(),
-                                            target.display()
-                                        ),
-                                    });
-                                }
-                            }
-                        }
+        // Every layer is walked on its own worker; the results come back in
+        // layer order, so merging them keeps the topmost module first.
+        let layers: Vec<(usize, &Path, &Path)> = scoped
+            .iter()
+            .flat_map(|(i, op, sub)| {
+                op.lowerdirs
+                    .iter()
+                    .map(move |layer| (*i, layer.as_path(), sub.as_path()))
+            })
+            .collect();
+        let scans: Vec<LayerScan> = pool::install(|| {
+            layers
+                .into_par_iter()
+                .map(|(op, layer, sub)| scan_layer(op, layer, sub))
+                .collect()
+        });
 
-                        if !entry.file_type().is_file() {
-                            continue;
-                        }
+        let mut file_map: HashMap<(usize, PathBuf), Vec<(String, PathBuf)>> = HashMap::new();
+        for scan in scans {
+            op_diagnostics.extend(scan.diagnostics);
+            for (rel, path) in scan.files {
+                file_map
+                    .entry((scan.op, rel))
+                    .or_default()
+                    .push((scan.module_id.clone(), path));
+            }
+        }
+        span.items(file_map.len());
 
-                        if let Ok(rel) = entry.path().strip_prefix(layer_path) {
-                            let rel_str = rel.to_string_lossy().to_string();
-                            file_map.entry(rel_str).or_default().push(module_id.clone());
-                        }
-                    }
-                }
+        let contested: Vec<_> = file_map
+            .into_iter()
+            .filter(|(_, sources)| sources.len() > 1)
+            .collect();
+        let conflicts: Vec<ConflictEntry> = pool::install(|| {
+            contested
+                .into_par_iter()
+                .map(|((op, rel_path), sources)| {
+                    let op = &self.overlay_ops[op];
+                    let contenders: Vec<ConflictContender> = sources
+                        .iter()
+                        .map(|(id, path)| conflict::describe(id, path))
+                        .collect();
+                    let identical = conflict::all_identical(&contenders);
+                    let severity =
+                        conflict::classify(&Path::new(&op.target).join(&rel_path), identical);
 
-                for (rel_path, modules) in file_map {
-                    if modules.len() > 1 {
-                        local_conflicts.push(ConflictEntry {
-                            partition: op.partition_name.clone(),
-                            relative_path: rel_path,
-                            contending_modules: modules,
-                        });
+                    ConflictEntry {
+                        partition: op.partition_name.clone(),
+                        target: op.target.clone(),
+                        relative_display: rel_path.to_string_lossy().to_string(),
+                        relative_path: rel_path,
+                        contending_modules: sources.into_iter().map(|(id, _)| id).collect(),
+                        contenders,
+                        identical,
+                        severity,
                     }
+                })
+                .collect()
+        });
+
+        let mut report = AnalysisReport::default();
+
+        if !self.order_cycle.is_empty() {
+            report.diagnostics.push(DiagnosticIssue {
+                level: DiagnosticLevel::Critical,
+                context: "Ordering".to_string(),
+                message: format!(
+                    "Module ordering cycle detected among: {}",
+                    self.order_cycle.join(", ")
+                ),
+            });
+        }
+
+        for denied in &self.denied {
+            report.diagnostics.push(DiagnosticIssue {
+                level: DiagnosticLevel::Critical,
+                context: denied.module_id.clone(),
+                message: format!(
+                    "Refusing to mount over {}: {}",
+                    denied.target, denied.reason
+                ),
+            });
+        }
+
+        for module_id in &self.hymo_fallback {
+            report.diagnostics.push(DiagnosticIssue {
+                level: DiagnosticLevel::Warning,
+                context: module_id.clone(),
+                message: "HymoFS requested but not detected; mounting via OverlayFS instead"
+                    .to_string(),
+            });
+        }
+
+        let rw_upper_available = Path::new(defs::SYSTEM_RW_DIR).is_dir();
+        for file in &self.writable_files {
+            let (level, message) = match (file.overlay, rw_upper_available) {
+                (false, _) => (
+                    DiagnosticLevel::Info,
+                    format!("Writable file (magic mount): {}", file.path.display()),
+                ),
+                (true, true) => (
+                    DiagnosticLevel::Info,
+                    format!(
+                        "Writable file (overlay upperdir in {}): {}",
+                        defs::SYSTEM_RW_DIR,
+                        file.path.display()
+                    ),
+                ),
+                (true, false) => (
+                    DiagnosticLevel::Warning,
+                    format!(
+                        "{} requests write access but {} is missing; it stays read-only",
+                        file.path.display(),
+                        defs::SYSTEM_RW_DIR
+                    ),
+                ),
+            };
+            report.diagnostics.push(DiagnosticIssue {
+                level,
+                context: file.module_id.clone(),
+                message,
+            });
+        }
+
+        let mut apex_binds: BTreeMap<(&str, PathBuf), usize> = BTreeMap::new();
+        for op in &self.apex_ops {
+            let root: PathBuf = op.target.iter().take(3).collect();
+            *apex_binds.entry((op.module_id.as_str(), root)).or_default() += 1;
+        }
+        for ((module_id, root), files) in apex_binds {
+            report.diagnostics.push(DiagnosticIssue {
+                level: DiagnosticLevel::Warning,
+                context: module_id.to_string(),
+                message: format!(
+                    "Replaces {} file(s) in {}. A broken library there can bootloop the device; \
+                     `meta-hybrid rescue --disable {}` undoes it from recovery",
+                    files,
+                    root.display(),
+                    module_id
+                ),
+            });
+        }
+        for skipped in &self.apex_skipped {
+            report.diagnostics.push(DiagnosticIssue {
+                level: DiagnosticLevel::Warning,
+                context: skipped.module_id.clone(),
+                message: format!("Skipping {}: {}", skipped.target, skipped.reason),
+            });
+        }
+
+        for rejected in &self.bind_rejected {
+            report.diagnostics.push(DiagnosticIssue {
+                level: DiagnosticLevel::Info,
+                context: rejected.module_id.clone(),
+                message: format!(
+                    "Bind mount optimization disabled: {} {}",
+                    rejected.target, rejected.reason
+                ),
+            });
+        }
+
+        for demotion in &self.demoted {
+            report.diagnostics.push(DiagnosticIssue {
+                level: DiagnosticLevel::Warning,
+                context: demotion.module_id.clone(),
+                message: format!(
+                    "Demoted to magic mount: overlay on {} {}",
+                    demotion.target, demotion.reason
+                ),
+            });
+        }
+
+        for files in self.blacklisted.chunk_by(|a, b| a.module_id == b.module_id) {
+            let listed: Vec<String> = files
+                .iter()
+                .map(|file| format!("{} ('{}')", file.target, file.pattern))
+                .collect();
+            report.diagnostics.push(DiagnosticIssue {
+                level: DiagnosticLevel::Info,
+                context: files[0].module_id.clone(),
+                message: format!("Blacklisted, not mounted: {}", listed.join(", ")),
+            });
+        }
+        for alias in &self.target_aliases {
+            report.diagnostics.push(DiagnosticIssue {
+                level: DiagnosticLevel::Warning,
+                context: alias.modules.join(", "),
+                message: format!(
+                    "{} is a bind mount of {}; both are layered there as one overlay",
+                    alias.alias, alias.canonical
+                ),
+            });
+        }
+        for stale in &self.stale_layers {
+            report.diagnostics.push(DiagnosticIssue {
+                level: DiagnosticLevel::Warning,
+                context: stale.module_id.clone(),
+                message: format!("Not layering {}: {}", stale.target, stale.reason),
+            });
+        }
+        for conflict in &self.blacklist_conflicts {
+            report.diagnostics.push(DiagnosticIssue {
+                level: DiagnosticLevel::Warning,
+                context: conflict.module_id.clone(),
+                message: format!(
+                    "Winnowing forces this module for {}, but the file is {}; the blacklist wins",
+                    conflict.target, conflict.reason
+                ),
+            });
+        }
+
+        report.diagnostics.extend(op_diagnostics);
+        report.conflicts = conflicts;
+        report
+            .conflicts
+            .sort_by_cached_key(|c| Path::new(&c.target).join(&c.relative_path));
+
+        for c in &report.conflicts {
+            if c.severity == ConflictSeverity::Severe {
+                report.diagnostics.push(DiagnosticIssue {
+                    level: DiagnosticLevel::Warning,
+                    context: c.contending_modules.join(", "),
+                    message: format!(
+                        "Modules ship different versions of {}; only {} takes effect",
+                        winnow::conflict_path(c),
+                        c.contending_modules[0]
+                    ),
+                });
+            }
+        }
+
+        report
+    }
+}
+
+/// Files of one overlay layer, relative to the layer root.
+struct LayerScan {
+    op: usize,
+    module_id: String,
+    files: Vec<(PathBuf, PathBuf)>,
+    diagnostics: Vec<DiagnosticIssue>,
+}
+
+/// Walks `layer` below `sub`, collecting files as `(relative, full)` and
+/// reporting absolute symlinks that point nowhere.
+fn scan_layer(op: usize, layer: &Path, sub: &Path) -> LayerScan {
+    let module_id = utils::extract_module_id(layer).unwrap_or_else(|| "UNKNOWN".into());
+    let mut files = Vec::new();
+    let mut diagnostics = Vec::new();
+
+    let start = layer.join(sub);
+    if start.exists() {
+        for entry in WalkDir::new(&start).min_depth(1).into_iter().flatten() {
+            if entry.path_is_symlink()
+                && let Ok(target) = fs::read_link(entry.path())
+                && target.is_absolute()
+                && !target.exists()
+            {
+                diagnostics.push(DiagnosticIssue {
+                    level: DiagnosticLevel::Warning,
+                    context: module_id.clone(),
+                    message: format!(
+                        "Dead absolute symlink: {} -> {}",
+                        entry.path().display(),
+                        target.display()
+                    ),
+                });
+            }
+
+            if entry.file_type().is_dir() {
+                continue;
+            }
+
+            if let Ok(rel) = entry.path().strip_prefix(layer) {
+                files.push((rel.to_path_buf(), entry.path().to_path_buf()));
+            }
+        }
+    }
+
+    LayerScan {
+        op,
+        module_id,
+        files,
+        diagnostics,
+    }
+}
+
+/// Sorts modules from topmost to lowest layer. Without declarations the
+/// existing order (id descending) is kept; `order` lifts a module above lower
+/// values and `after` places it above the listed modules. Modules caught in a
+/// cycle are returned separately and appended on top in default order.
+fn order_modules(modules: &[Module]) -> (Vec<&Module>, Vec<String>) {
+    let index: HashMap<&str, usize> = modules
+        .iter()
+        .enumerate()
+        .map(|(i, m)| (m.id.as_str(), i))
+        .collect();
+
+    let mut indegree = vec![0usize; modules.len()];
+    let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); modules.len()];
+
+    for (i, module) in modules.iter().enumerate() {
+        for dep in &module.rules.after {
+            match index.get(dep.as_str()) {
+                Some(&d) if d != i => {
+                    dependents[d].push(i);
+                    indegree[i] += 1;
                 }
+                Some(_) => {}
+                None => crate::module_debug!(
+                    Some(&module.id),
+                    "ordering target '{}' is not enabled",
+                    dep
+                ),
+            }
+        }
+    }
+
+    let rank = |i: usize| (modules[i].rules.order.unwrap_or(0), modules[i].id.as_str());
+
+    let mut ready: Vec<usize> = (0..modules.len()).filter(|&i| indegree[i] == 0).collect();
+    let mut bottom_up = Vec::with_capacity(modules.len());
 
-                (local_conflicts, local_diagnostics)
+    while !ready.is_empty() {
+        let pos = (0..ready.len())
+            .min_by_key(|&p| rank(ready[p]))
+            .unwrap_or(0);
+        let next = ready.swap_remove(pos);
+        bottom_up.push(next);
+
+        for &dependent in &dependents[next] {
+            indegree[dependent] -= 1;
+            if indegree[dependent] == 0 {
+                ready.push(dependent);
+            }
+        }
+    }
+
+    let mut cyclic: Vec<usize> = (0..modules.len())
+        .filter(|i| !bottom_up.contains(i))
+        .collect();
+    cyclic.sort_by_key(|&i| rank(i));
+
+    let cycle_ids: Vec<String> = cyclic.iter().map(|&i| modules[i].id.clone()).collect();
+    bottom_up.extend(cyclic);
+
+    (
+        bottom_up.into_iter().rev().map(|i| &modules[i]).collect(),
+        cycle_ids,
+    )
+}
+
+/// Whether the module has `.replace` directories, which overlayfs can only
+/// honour through the `trusted.overlay.opaque` xattr.
+pub(crate) fn uses_opaque_dirs(content_path: &Path) -> bool {
+    WalkDir::new(content_path)
+        .min_depth(1)
+        .into_iter()
+        .flatten()
+        .any(|entry| {
+            if entry.file_type().is_dir() {
+                utils::is_overlay_opaque(entry.path())
+            } else {
+                entry.file_name() == defs::REPLACE_DIR_FILE_NAME
+            }
+        })
+}
+
+/// Length of the `lowerdir` value for `layers` stacked over `target`, with
+/// the escaping `mount_overlayfs` applies on its legacy mount path.
+pub(crate) fn lowerdir_len<'a>(
+    layers: impl IntoIterator<Item = &'a Path>,
+    target: &'a Path,
+) -> usize {
+    layers
+        .into_iter()
+        .map(Path::as_os_str)
+        .chain(std::iter::once(target.as_os_str()))
+        .map(|p| {
+            let bytes = p.as_encoded_bytes();
+            let escaped = bytes
+                .iter()
+                .filter(|&&b| matches!(b, b'\\' | b':' | b','))
+                .count();
+            bytes.len() + escaped + 1
+        })
+        .sum::<usize>()
+        - 1
+}
+
+/// Modules whose overlay stack includes a path that is not valid UTF-8.
+/// Mount options are passed as strings, so such stacks cannot be expressed
+/// and their modules go to magic mount, which handles any name.
+fn find_unrepresentable(groups: &[(PathBuf, Vec<(String, PathBuf)>)]) -> Vec<LayerDemotion> {
+    let mut demoted: Vec<LayerDemotion> = Vec::new();
+
+    for (target, layers) in groups {
+        let bad = std::iter::once(target)
+            .chain(layers.iter().map(|(_, p)| p))
+            .find(|p| p.to_str().is_none());
+        let Some(bad) = bad else {
+            continue;
+        };
+
+        let reason = format!(
+            "cannot be expressed as an overlay option: {} is not valid UTF-8",
+            bad.display()
+        );
+        for (module_id, _) in layers {
+            if !demoted.iter().any(|d| &d.module_id == module_id) {
+                demoted.push(LayerDemotion {
+                    module_id: module_id.clone(),
+                    target: target.to_string_lossy().to_string(),
+                    reason: reason.clone(),
+                });
+            }
+        }
+    }
+
+    demoted
+}
+
+/// Finds the modules that overflow each overlay stack. Layers are ordered top
+/// first, so the lowest-priority modules are the ones cut. Each group is
+/// judged on its own, which keeps the result independent of map order.
+fn find_overflow(groups: &[(PathBuf, Vec<(String, PathBuf)>)]) -> Vec<LayerDemotion> {
+    let max_layers = MAX_LOWERDIR_COUNT - 1;
+    let max_len = max_lowerdir_len();
+    let mut demoted: Vec<LayerDemotion> = Vec::new();
+
+    for (target, layers) in groups {
+        let total_len = lowerdir_len(layers.iter().map(|(_, p)| p.as_path()), target);
+        if layers.len() <= max_layers && total_len <= max_len {
+            continue;
+        }
+
+        let mut keep = layers.len().min(max_layers);
+        while keep > 0
+            && lowerdir_len(layers[..keep].iter().map(|(_, p)| p.as_path()), target) > max_len
+        {
+            keep -= 1;
+        }
+
+        let reason = if layers.len() > max_layers {
+            format!(
+                "needs {} layers, exceeding the stack limit of {}",
+                layers.len() + 1,
+                MAX_LOWERDIR_COUNT
+            )
+        } else {
+            format!(
+                "needs a {}-byte lowerdir, exceeding the limit of {} bytes",
+                total_len, max_len
+            )
+        };
+
+        for (module_id, _) in &layers[keep..] {
+            if !demoted.iter().any(|d| &d.module_id == module_id) {
+                demoted.push(LayerDemotion {
+                    module_id: module_id.clone(),
+                    target: target.to_string_lossy().to_string(),
+                    reason: reason.clone(),
+                });
+            }
+        }
+    }
+
+    demoted
+}
+
+/// Regular files under `dir` of the module that must stay writable, either
+/// because the module allows it or a `.rw` marker asks for it. `dir` and
+/// `skipped` are relative to the module root; directories in `skipped`
+/// belong to another partition and are left out.
+fn collect_writable(
+    module: &Module,
+    content_path: &Path,
+    dir: &Path,
+    skipped: &[&Path],
+    overlay: bool,
+) -> Vec<WritableFile> {
+    let source_root = &module.source_path;
+
+    WalkDir::new(source_root.join(dir))
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| {
+            !entry
+                .path()
+                .strip_prefix(source_root)
+                .is_ok_and(|relative| skipped.contains(&relative))
+        })
+        .flatten()
+        .filter(|entry| entry.file_type().is_file())
+        .filter(|entry| !utils::is_rw_marker(entry.path()))
+        .filter(|entry| module.rules.allow_rw_files || utils::has_rw_marker(entry.path()))
+        .filter_map(|entry| {
+            let relative = entry.path().strip_prefix(source_root).ok()?.to_path_buf();
+            Some(WritableFile {
+                module_id: module.id.clone(),
+                path: Path::new("/").join(&relative),
+                source: content_path.join(&relative),
+                relative,
+                overlay,
             })
+        })
+        .collect()
+}
+
+/// Files, symlinks and whiteouts below `dir`, leaving out the directories
+/// in `skipped`.
+fn count_files(dir: &Path, skipped: &[PathBuf]) -> usize {
+    WalkDir::new(dir)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| !skipped.iter().any(|s| s == entry.path()))
+        .flatten()
+        .filter(|entry| !entry.file_type().is_dir())
+        .count()
+}
+
+/// Whether `/<name>` is a partition on the live system: a real directory,
+/// or a symlink that resolves to a mount point.
+fn is_live_partition(name: &str, probe: &dyn SystemProbe) -> bool {
+    let path = Path::new("/").join(name);
+    if probe.is_symlink(&path) {
+        probe
+            .canonicalize(&path)
+            .is_some_and(|target| probe.is_mount_point(&target))
+    } else {
+        probe.is_dir(&path)
+    }
+}
+
+/// Top-level directories of `modules` that are not builtin partitions but
+/// exist as partitions on the device, sorted by name.
+pub fn discover_partitions(modules: &[Module], probe: &dyn SystemProbe) -> Vec<String> {
+    let mut candidates: HashSet<String> = HashSet::new();
+
+    for module in modules {
+        let Ok(entries) = fs::read_dir(&module.source_path) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.')
+                || defs::BUILTIN_PARTITIONS.contains(&name.as_str())
+                || defs::DLKM_PARTITIONS.contains(&name.as_str())
+                || defs::NON_PARTITION_DIRS.contains(&name.as_str())
+            {
+                continue;
+            }
+            candidates.insert(name);
+        }
+    }
+
+    let mut found: Vec<String> = candidates
+        .into_iter()
+        .filter(|name| {
+            let live = is_live_partition(name, probe);
+            if !live {
+                log::debug!("Ignoring module directory {}: not a partition", name);
+            }
+            live
+        })
+        .collect();
+    found.sort();
+    found
+}
+
+/// The `DLKM_PARTITIONS` mounted on the device, directly or through a
+/// symlink to the mount.
+pub fn mounted_dlkm_partitions(probe: &dyn SystemProbe) -> Vec<String> {
+    defs::DLKM_PARTITIONS
+        .iter()
+        .filter(|name| {
+            let path = Path::new("/").join(name);
+            probe
+                .canonicalize(&path)
+                .is_some_and(|target| probe.is_mount_point(&target))
+        })
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// Configured partitions and the mounted dlkm partitions, merged with
+/// discovered ones when `auto_partitions` is on.
+fn resolve_partitions(
+    config: &config::Config,
+    modules: &[Module],
+    probe: &dyn SystemProbe,
+) -> Vec<String> {
+    let mut partitions = config.partitions.clone();
+
+    for dlkm in mounted_dlkm_partitions(probe) {
+        if !partitions.contains(&dlkm) {
+            partitions.push(dlkm);
+        }
+    }
+
+    if config.auto_partitions {
+        let discovered: Vec<String> = discover_partitions(modules, probe)
+            .into_iter()
+            .filter(|p| !partitions.contains(p))
             .collect();
 
-        let mut report = AnalysisReport::default();
-        for (c, d) in results {
-            report.conflicts.extend(c);
-            report.diagnostics.extend(d);
+        if !discovered.is_empty() {
+            log::info!(">> Auto-discovered partitions: {}", discovered.join(", "));
+        }
+        partitions.extend(discovered);
+    }
+
+    partitions
+}
+
+/// The bind mounts that would replace the overlay for `module`, or `None`
+/// when it does more than swap a few existing files: new files, symlinks,
+/// whiteouts, `.replace` dirs, writable files, a non-overlay mode for some
+/// partition, or more than `BIND_MAX_FILES` files.
+fn bind_candidate(
+    module: &Module,
+    content_path: &Path,
+    extra_partitions: &[String],
+    deny_paths: &[String],
+    probe: &dyn SystemProbe,
+) -> Option<Vec<BindOperation>> {
+    let mut ops = Vec::new();
+
+    for entry in fs::read_dir(content_path).ok()?.flatten() {
+        if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+            continue;
+        }
+        let dir_name = entry.file_name().to_string_lossy().to_string();
+        if !defs::BUILTIN_PARTITIONS.contains(&dir_name.as_str())
+            && !extra_partitions.contains(&dir_name)
+            || dir_name == APEX_DIR_NAME
+        {
+            continue;
+        }
+        if module.rules.excludes_partition(&dir_name) {
+            continue;
+        }
+        if module.get_mode(&dir_name) != MountMode::Overlay {
+            return None;
+        }
+
+        for file in WalkDir::new(entry.path()).min_depth(1) {
+            let file = file.ok()?;
+            let file_type = file.file_type();
+            if file_type.is_dir() {
+                if utils::is_overlay_opaque(file.path()) {
+                    return None;
+                }
+                continue;
+            }
+            if !file_type.is_file()
+                || file.file_name() == defs::REPLACE_DIR_FILE_NAME
+                || utils::is_rw_marker(file.path())
+                || utils::has_rw_marker(file.path())
+                || module.rules.allow_rw_files
+            {
+                return None;
+            }
+
+            // Resolve the directory only: a stock symlink must not send the
+            // bind to whatever it points at.
+            let relative = file.path().strip_prefix(content_path).ok()?;
+            let live = Path::new("/").join(relative);
+            let target = probe.canonicalize(live.parent()?)?.join(live.file_name()?);
+            if !probe.exists(&target)
+                || probe.is_symlink(&target)
+                || probe.is_dir(&target)
+                || utils::denied_target(&target, deny_paths).is_some()
+                // Shipped under both spellings of a partition; overlay
+                // decides which copy wins.
+                || ops.iter().any(|op: &BindOperation| op.target == target)
+            {
+                return None;
+            }
+
+            ops.push(BindOperation {
+                module_id: module.id.clone(),
+                partition_name: target
+                    .iter()
+                    .nth(1)
+                    .map(|s| s.to_string_lossy().to_string())
+                    .unwrap_or_else(|| dir_name.clone()),
+                source: file.into_path(),
+                target,
+            });
+            if ops.len() > BIND_MAX_FILES {
+                return None;
+            }
         }
+    }
 
-        report.conflicts.sort_by(|a, b| {
-            a.partition
-                .cmp(&b.partition)
-                .then_with(|| a.relative_path.cmp(&b.relative_path))
+    (!ops.is_empty()).then_some(ops)
+}
+
+/// Plans `module`'s `apex/<name>/...` files in `apex_dir` as bind mounts
+/// over the same files of `/apex/<name>`. overlayfs over APEX mounts does
+/// not work, so only files that replace an existing stock file are bound.
+/// APEXes that are not mounted, new files, symlinks and paths in
+/// `deny_paths` are skipped, never failing the plan.
+fn plan_apex(
+    plan: &mut MountPlan,
+    module: &Module,
+    apex_dir: &Path,
+    deny_paths: &[String],
+    probe: &dyn SystemProbe,
+) {
+    let mut skip = |target: &Path, reason: &str| {
+        log::warn!(
+            "!! Module {}: skipping {}: {}",
+            module.id,
+            target.display(),
+            reason
+        );
+        plan.apex_skipped.push(LayerDemotion {
+            module_id: module.id.clone(),
+            target: target.to_string_lossy().to_string(),
+            reason: reason.to_string(),
         });
+    };
+    // `allow_apex` lifts the built-in `/apex` entry and nothing else.
+    let apex_root_dir = format!("/{}", APEX_DIR_NAME);
 
-        report
+    let Ok(entries) = fs::read_dir(apex_dir) else {
+        return;
+    };
+    let mut ops = Vec::new();
+    let mut names = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let apex_root = Path::new("/").join(APEX_DIR_NAME).join(&name);
+        if !entry.file_type().is_ok_and(|t| t.is_dir()) || name.starts_with('.') {
+            continue;
+        }
+        if !probe.is_mount_point(&apex_root) {
+            skip(&apex_root, "not an active APEX mount");
+            continue;
+        }
+
+        for file in WalkDir::new(entry.path())
+            .min_depth(1)
+            .into_iter()
+            .flatten()
+        {
+            if file.file_type().is_dir() {
+                continue;
+            }
+            let Ok(relative) = file.path().strip_prefix(entry.path()) else {
+                continue;
+            };
+            let target = apex_root.join(relative);
+            let resolved = target
+                .parent()
+                .and_then(|parent| probe.canonicalize(parent))
+                .zip(target.file_name())
+                .map(|(parent, name)| parent.join(name));
+
+            let reason = if !file.file_type().is_file() {
+                Some("only regular files can be bound into an APEX")
+            } else if !resolved.as_ref().is_some_and(|r| r.starts_with(&apex_root)) {
+                Some("leaves the APEX mount")
+            } else if !probe.exists(&target) || probe.is_symlink(&target) || probe.is_dir(&target) {
+                Some("no stock file to bind over; APEXes cannot gain files")
+            } else if utils::denied_target_except(&target, deny_paths, &[apex_root_dir.as_str()])
+                .is_some()
+            {
+                Some("listed in user_deny_paths")
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                skip(&target, reason);
+                continue;
+            }
+
+            ops.push(BindOperation {
+                module_id: module.id.clone(),
+                partition_name: APEX_DIR_NAME.to_string(),
+                source: file.into_path(),
+                target,
+            });
+            if !names.contains(&name) {
+                names.push(name.clone());
+            }
+        }
     }
+
+    if !ops.is_empty() {
+        log::warn!(
+            "!! Module {} replaces {} file(s) inside APEX {}; a broken file there can bootloop",
+            module.id,
+            ops.len(),
+            names.join(", ")
+        );
+    }
+    plan.apex_ops.extend(ops);
+    for name in names {
+        if !plan.apex_names.contains(&name) {
+            plan.apex_names.push(name);
+        }
+    }
+    plan.apex_names.sort();
+}
+
+/// First module other than `module_id` whose overlay layer in `groups` also
+/// provides `target`.
+fn overlay_contender<'a>(
+    groups: &'a [(PathBuf, Vec<(String, PathBuf)>)],
+    module_id: &str,
+    target: &Path,
+) -> Option<&'a str> {
+    groups.iter().find_map(|(group_target, layers)| {
+        let inside = target.strip_prefix(group_target).ok()?;
+        layers
+            .iter()
+            .find(|(id, layer)| id != module_id && layer.join(inside).symlink_metadata().is_ok())
+            .map(|(id, _)| id.as_str())
+    })
+}
+
+/// Records that `module_id`'s `dir` resolves to `target` below the
+/// protected `denied`, and leaves it out of the plan.
+fn deny(plan: &mut MountPlan, module_id: &str, dir: &str, target: &Path, denied: &str) {
+    log::error!(
+        "!! Module {}: {} resolves to {}, which is never mounted over ({})",
+        module_id,
+        dir,
+        target.display(),
+        denied
+    );
+    plan.denied.push(LayerDemotion {
+        module_id: module_id.to_string(),
+        target: target.to_string_lossy().to_string(),
+        reason: format!("{} resolves to protected path {}", dir, denied),
+    });
 }
 
 struct ProcessingItem {
@@ -156,16 +1147,79 @@ pub fn generate(
     modules: &[Module],
     storage_root: &Path,
 ) -> Result<MountPlan> {
+    generate_with_probe(config, modules, storage_root, &LiveSystem)
+}
+
+/// Same as [`generate`] with the live filesystem seen through `probe`.
+pub fn generate_with_probe(
+    config: &config::Config,
+    modules: &[Module],
+    storage_root: &Path,
+    probe: &dyn SystemProbe,
+) -> Result<MountPlan> {
+    let mut span = timing::span("plan");
+    span.items(modules.len());
     let mut plan = MountPlan::default();
 
-    let mut overlay_groups: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    let blacklist = Blacklist::new(config, modules);
+    plan.blacklisted = modules
+        .iter()
+        .flat_map(|module| blacklist::filtered(module, &blacklist))
+        .collect();
+    plan.blacklist_conflicts = blacklist::winnowing_conflicts(&config.winnowing, &blacklist);
+
+    let mut overlay_groups: HashMap<PathBuf, Vec<(String, PathBuf)>> = HashMap::new();
 
     let mut overlay_ids = HashSet::new();
     let mut magic_ids = HashSet::new();
+    let mut hymo_ids = HashSet::new();
+    let mut bind_candidates: Vec<Vec<BindOperation>> = Vec::new();
 
     let sensitive_partitions: HashSet<&str> = defs::SENSITIVE_PARTITIONS.iter().cloned().collect();
 
-    for module in modules {
+    let xattr_supported = probe.supports_overlay_xattrs(storage_root);
+    if !xattr_supported {
+        log::warn!("Storage does not support overlay xattrs; opaque directories need magic mount.");
+    }
+
+    let hymofs_active = probe.hymofs_active();
+
+    plan.extra_partitions = resolve_partitions(config, modules, probe);
+    let partitions = PartitionMap::resolve(&plan.extra_partitions, probe);
+
+    let (ordered, order_cycle) = order_modules(modules);
+
+    if !order_cycle.is_empty() {
+        log::error!(
+            "Module ordering cycle among [{}], falling back to default order for them.",
+            order_cycle.join(", ")
+        );
+    }
+
+    plan.module_order = ordered.iter().map(|m| m.id.clone()).collect();
+    plan.exclusions = modules
+        .iter()
+        .filter(|m| !m.rules.exclude_partitions.is_empty())
+        .map(|m| {
+            (
+                m.id.clone(),
+                m.rules.exclude_partitions.iter().cloned().collect(),
+            )
+        })
+        .collect();
+    plan.order_cycle = order_cycle;
+
+    if modules
+        .iter()
+        .any(|m| m.rules.order.is_some() || !m.rules.after.is_empty())
+    {
+        log::info!(
+            ">> Module order (top first): {}",
+            plan.module_order.join(" > ")
+        );
+    }
+
+    for module in ordered {
         let mut content_path = storage_root.join(&module.id);
         if !content_path.exists() {
             content_path = module.source_path.clone();
@@ -174,132 +1228,369 @@ pub fn generate(
             continue;
         }
 
-        if let Ok(entries) = fs::read_dir(&content_path) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if !path.is_dir() {
-                    continue;
-                }
+        let force_magic = !xattr_supported && uses_opaque_dirs(&content_path);
+        if force_magic {
+            log::warn!(
+                "Module {} relies on .replace directories, forcing magic mount.",
+                module.id
+            );
+        }
 
-                let dir_name = entry.file_name().to_string_lossy().to_string();
+        if config.prefer_bind_for_small_modules
+            && !force_magic
+            && let Some(ops) = bind_candidate(
+                module,
+                &content_path,
+                &plan.extra_partitions,
+                &config.user_deny_paths,
+                probe,
+            )
+        {
+            bind_candidates.push(ops);
+        }
 
-                if !defs::BUILTIN_PARTITIONS.contains(&dir_name.as_str())
-                    && !config.partitions.contains(&dir_name)
-                {
-                    continue;
+        let nested = partitions.nested_sources(&content_path);
+        let sources = partition_map::module_sources(&content_path, &partitions, &|name| {
+            defs::BUILTIN_PARTITIONS.contains(&name)
+                || plan.extra_partitions.iter().any(|p| p == name)
+        });
+
+        let skipped: Vec<&Path> = nested
+            .iter()
+            .filter_map(|n| n.strip_prefix(&content_path).ok())
+            .collect();
+
+        for (dir_name, path) in sources {
+            let relative = path.strip_prefix(&content_path).unwrap_or(&path);
+
+            if module.rules.excludes_partition(&dir_name) {
+                crate::module_debug!(Some(&module.id), "excludes partition {}", dir_name);
+                continue;
+            }
+
+            if dir_name == APEX_DIR_NAME {
+                if config.allow_apex {
+                    plan_apex(&mut plan, module, &path, &config.user_deny_paths, probe);
+                } else {
+                    log::warn!(
+                        "Module {} ships apex/, which is only mounted with allow_apex",
+                        module.id
+                    );
+                    plan.apex_skipped.push(LayerDemotion {
+                        module_id: module.id.clone(),
+                        target: format!("/{}", APEX_DIR_NAME),
+                        reason: "allow_apex is off".to_string(),
+                    });
                 }
+                continue;
+            }
 
-                let mode = module.rules.get_mode(&dir_name);
-                if matches!(mode, MountMode::Magic) {
-                    magic_ids.insert(module.id.clone());
-                    continue;
+            let root_target = PathBuf::from("/").join(&dir_name);
+            let root_target = probe.canonicalize(&root_target).unwrap_or(root_target);
+            if let Some(denied) = utils::denied_target(&root_target, &config.user_deny_paths) {
+                deny(&mut plan, &module.id, &dir_name, &root_target, &denied);
+                continue;
+            }
+
+            let mut mode = module.get_mode(&dir_name);
+            if mode == MountMode::Hymo && !hymofs_active {
+                if !plan.hymo_fallback.contains(&module.id) {
+                    log::warn!(
+                        "Module {} requests HymoFS but it is not active, using OverlayFS.",
+                        module.id
+                    );
+                    plan.hymo_fallback.push(module.id.clone());
                 }
-                if matches!(mode, MountMode::Ignore) {
+                mode = MountMode::Overlay;
+            }
+            if mode == MountMode::Overlay && force_magic {
+                mode = MountMode::Magic;
+            }
+            if matches!(mode, MountMode::Hymo) {
+                hymo_ids.insert(module.id.clone());
+                plan.hymo_ops.push(HymoOperation {
+                    module_id: module.id.clone(),
+                    partition_name: dir_name.clone(),
+                    source: path.clone(),
+                    target: PathBuf::from("/").join(&dir_name),
+                });
+                continue;
+            }
+            if matches!(mode, MountMode::Magic) {
+                plan.writable_files.extend(collect_writable(
+                    module,
+                    &content_path,
+                    relative,
+                    &skipped,
+                    false,
+                ));
+                *plan
+                    .magic_files
+                    .entry(module.id.clone())
+                    .or_default()
+                    .entry(dir_name.clone())
+                    .or_default() += count_files(&path, &nested);
+                magic_ids.insert(module.id.clone());
+                continue;
+            }
+            if matches!(mode, MountMode::Ignore) {
+                continue;
+            }
+
+            plan.writable_files.extend(collect_writable(
+                module,
+                &content_path,
+                relative,
+                &skipped,
+                true,
+            ));
+
+            overlay_ids.insert(module.id.clone());
+
+            let mut queue = VecDeque::new();
+            queue.push_back(ProcessingItem {
+                module_source: path.clone(),
+                system_target: partitions
+                    .target(&dir_name)
+                    .map(Path::to_path_buf)
+                    .unwrap_or_else(|| PathBuf::from("/").join(&dir_name)),
+                partition_label: dir_name.clone(),
+            });
+
+            while let Some(item) = queue.pop_front() {
+                let ProcessingItem {
+                    module_source,
+                    system_target,
+                    partition_label,
+                } = item;
+
+                if !probe.exists(&system_target) {
                     continue;
                 }
 
-                overlay_ids.insert(module.id.clone());
+                let resolved_target = match probe.read_link(&system_target) {
+                    Some(target) => {
+                        if target.is_absolute() {
+                            target
+                        } else {
+                            system_target
+                                .parent()
+                                .unwrap_or(Path::new("/"))
+                                .join(target)
+                        }
+                    }
+                    None => system_target.clone(),
+                };
 
-                let mut queue = VecDeque::new();
-                queue.push_back(ProcessingItem {
-                    module_source: path.clone(),
-                    system_target: PathBuf::from("/").join(&dir_name),
-                    partition_label: dir_name.clone(),
-                });
+                let canonical_target = probe
+                    .canonicalize(&resolved_target)
+                    .unwrap_or(resolved_target);
 
-                while let Some(item) = queue.pop_front() {
-                    let ProcessingItem {
-                        module_source,
-                        system_target,
-                        partition_label,
-                    } = item;
+                if let Some(denied) =
+                    utils::denied_target(&canonical_target, &config.user_deny_paths)
+                {
+                    let relative = module_source
+                        .strip_prefix(&content_path)
+                        .unwrap_or(&module_source)
+                        .to_string_lossy()
+                        .to_string();
+                    deny(&mut plan, &module.id, &relative, &canonical_target, &denied);
+                    continue;
+                }
 
-                    if !system_target.exists() {
-                        continue;
-                    }
+                let target_name = canonical_target
+                    .file_name()
+                    .map(|s| s.to_string_lossy())
+                    .unwrap_or_default();
 
-                    let resolved_target = match fs::read_link(&system_target) {
-                        Ok(target) => {
-                            if target.is_absolute() {
-                                target
-                            } else {
-                                system_target
-                                    .parent()
-                                    .unwrap_or(Path::new("/"))
-                                    .join(target)
-                            }
-                        }
-                        Err(_) => system_target.clone(),
-                    };
+                let should_split =
+                    sensitive_partitions.contains(&*target_name) || target_name == "system";
 
-                    let canonical_target = if resolved_target.exists() {
-                        match resolved_target.canonicalize() {
-                            Ok(p) => p,
-                            Err(_) => resolved_target,
-                        }
-                    } else {
-                        resolved_target
-                    };
-
-                    let target_name = canonical_target
-                        .file_name()
-                        .map(|s| s.to_string_lossy())
-                        .unwrap_or_default();
-
-                    let should_split = sensitive_partitions.contains(target_name.as_ref())
-                        || target_name == "system";
-
-                    if should_split {
-                        if let Ok(sub_entries) = fs::read_dir(&module_source) {
-                            for sub_entry in sub_entries.flatten() {
-                                let sub_path = sub_entry.path();
-                                if !sub_path.is_dir() {
-                                    continue;
-                                }
-                                let sub_name = sub_entry.file_name();
-
-                                queue.push_back(ProcessingItem {
-                                    module_source: sub_path,
-                                    system_target: canonical_target.join(sub_name),
-                                    partition_label: partition_label.clone(),
-                                });
+                if should_split {
+                    if let Ok(sub_entries) = fs::read_dir(&module_source) {
+                        for sub_entry in sub_entries.flatten() {
+                            let sub_path = sub_entry.path();
+                            if !sub_path.is_dir() || nested.contains(&sub_path) {
+                                continue;
                             }
+                            let sub_name = sub_entry.file_name();
+
+                            queue.push_back(ProcessingItem {
+                                module_source: sub_path,
+                                system_target: canonical_target.join(sub_name),
+                                partition_label: partition_label.clone(),
+                            });
                         }
-                    } else {
-                        overlay_groups
-                            .entry(canonical_target)
-                            .or_default()
-                            .push(module_source);
                     }
+                } else {
+                    overlay_groups
+                        .entry(canonical_target)
+                        .or_default()
+                        .push((module.id.clone(), module_source));
                 }
             }
         }
     }
 
-    for (target_path, layers) in overlay_groups {
-        let target_str = target_path.to_string_lossy().to_string();
+    // Magic mount attaches `extra_partitions` on its own; keep denied ones
+    // away from it.
+    plan.extra_partitions.retain(|name| {
+        let target = PathBuf::from("/").join(name);
+        let target = probe.canonicalize(&target).unwrap_or(target);
+        utils::denied_target(&target, &config.user_deny_paths).is_none()
+    });
+
+    let mounts = MountTable::parse(&probe.mountinfo().unwrap_or_default());
+    plan.target_aliases = mount_alias::collapse(&mut overlay_groups, &mounts, &plan.module_order);
+
+    let mut groups: Vec<(PathBuf, Vec<(String, PathBuf)>)> = overlay_groups
+        .into_iter()
+        .map(|(target, mut layers)| {
+            winnow::promote_forced_layers(&target, &mut layers, &config.winnowing);
+            (target, layers)
+        })
+        .collect();
+    groups.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for ops in bind_candidates {
+        let module_id = ops[0].module_id.clone();
+        let contended = ops.iter().find_map(|op| {
+            overlay_contender(&groups, &module_id, &op.target).map(|other| (op, other))
+        });
 
-        if !target_path.is_dir() {
+        if let Some((op, other)) = contended {
+            log::info!(
+                "Module {} stays on overlayfs: {} is also shipped by {}",
+                module_id,
+                op.target.display(),
+                other
+            );
+            plan.bind_rejected.push(LayerDemotion {
+                module_id: module_id.clone(),
+                target: op.target.to_string_lossy().to_string(),
+                reason: format!("is also shipped by overlay module {}", other),
+            });
             continue;
         }
 
+        for (_, layers) in &mut groups {
+            layers.retain(|(id, _)| id != &module_id);
+        }
+        overlay_ids.remove(&module_id);
+        plan.bind_module_ids.push(module_id);
+        plan.bind_ops.extend(ops);
+    }
+    plan.bind_module_ids.sort();
+
+    plan.demoted = find_unrepresentable(&groups);
+    for demotion in find_overflow(&groups) {
+        if !plan
+            .demoted
+            .iter()
+            .any(|d| d.module_id == demotion.module_id)
+        {
+            plan.demoted.push(demotion);
+        }
+    }
+
+    for demotion in &plan.demoted {
+        log::warn!(
+            "Module {} demoted to magic mount: overlay on {} {}",
+            demotion.module_id,
+            demotion.target,
+            demotion.reason
+        );
+        overlay_ids.remove(&demotion.module_id);
+        magic_ids.insert(demotion.module_id.clone());
+    }
+
+    for file in &mut plan.writable_files {
+        if plan.demoted.iter().any(|d| d.module_id == file.module_id) {
+            file.overlay = false;
+        }
+    }
+
+    for (target_path, mut layers) in groups {
         let partition_name = target_path
             .iter()
             .nth(1)
             .map(|s| s.to_string_lossy().to_string())
             .unwrap_or_else(|| "unknown".to_string());
 
+        layers.retain(|(id, path)| {
+            if !plan.demoted.iter().any(|d| &d.module_id == id) {
+                return true;
+            }
+            *plan
+                .magic_files
+                .entry(id.clone())
+                .or_default()
+                .entry(partition_name.clone())
+                .or_default() += count_files(path, &[]);
+            false
+        });
+        if layers.is_empty() {
+            continue;
+        }
+
+        let target_str = target_path.to_string_lossy().to_string();
+
+        if !probe.is_dir(&target_path) {
+            continue;
+        }
+
         plan.overlay_ops.push(OverlayOperation {
             partition_name,
             target: target_str,
-            lowerdirs: layers,
+            lowerdirs: layers.into_iter().map(|(_, path)| path).collect(),
         });
     }
 
     plan.overlay_module_ids = overlay_ids.into_iter().collect();
     plan.magic_module_ids = magic_ids.into_iter().collect();
+    plan.hymo_module_ids = hymo_ids.into_iter().collect();
     plan.overlay_module_ids.sort();
     plan.magic_module_ids.sort();
+    plan.hymo_module_ids.sort();
+
+    drop_stale_layers(&mut plan, storage_root, modules);
 
     Ok(plan)
 }
+
+/// Removes overlay lowerdirs under `storage_root` whose module directory
+/// is not one of the enabled `modules`, such as the leftover copy of a
+/// module that was given `skip_mount` after its last sync. Each one dropped
+/// is recorded in `stale_layers`; targets left without layers are dropped.
+pub fn drop_stale_layers(plan: &mut MountPlan, storage_root: &Path, modules: &[Module]) {
+    let enabled: HashSet<&str> = modules.iter().map(|m| m.id.as_str()).collect();
+    let mut stale = Vec::new();
+
+    for op in &mut plan.overlay_ops {
+        op.lowerdirs.retain(|dir| {
+            let Ok(relative) = dir.strip_prefix(storage_root) else {
+                return true;
+            };
+            let Some(id) = relative.iter().next().and_then(|id| id.to_str()) else {
+                return true;
+            };
+            if enabled.contains(id) {
+                return true;
+            }
+            log::warn!(
+                "Not layering {} on {}: module {} is not enabled",
+                dir.display(),
+                op.target,
+                id
+            );
+            stale.push(LayerDemotion {
+                module_id: id.to_string(),
+                target: op.target.clone(),
+                reason: format!("{} belongs to a module that is not enabled", dir.display()),
+            });
+            false
+        });
+    }
+    plan.overlay_ops.retain(|op| !op.lowerdirs.is_empty());
+    plan.stale_layers.extend(stale);
+}
@@ -0,0 +1,227 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use procfs::process::Process;
+
+use crate::{core::storage, defs, sys::mount::is_mounted, utils};
+
+/// What the planner needs to know about the live system. Paths are absolute
+/// device paths such as `/system/etc`.
+pub trait SystemProbe: Sync {
+    fn exists(&self, path: &Path) -> bool;
+    fn is_dir(&self, path: &Path) -> bool;
+    fn is_symlink(&self, path: &Path) -> bool;
+    fn read_link(&self, path: &Path) -> Option<PathBuf>;
+    fn canonicalize(&self, path: &Path) -> Option<PathBuf>;
+    fn is_mount_point(&self, path: &Path) -> bool;
+    /// The module storage at `storage_root` keeps `trusted.overlay.*` xattrs.
+    fn supports_overlay_xattrs(&self, storage_root: &Path) -> bool;
+    fn hymofs_active(&self) -> bool;
+    /// Source of the overlayfs mounted exactly at `path`, if there is one.
+    fn overlay_source(&self, path: &Path) -> Option<String>;
+    /// Text of `/proc/self/mountinfo`, if it can be read.
+    fn mountinfo(&self) -> Option<String>;
+}
+
+/// The device the daemon runs on.
+pub struct LiveSystem;
+
+impl SystemProbe for LiveSystem {
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        path.is_symlink()
+    }
+
+    fn read_link(&self, path: &Path) -> Option<PathBuf> {
+        fs::read_link(path).ok()
+    }
+
+    fn canonicalize(&self, path: &Path) -> Option<PathBuf> {
+        path.canonicalize().ok()
+    }
+
+    fn is_mount_point(&self, path: &Path) -> bool {
+        is_mounted(path)
+    }
+
+    fn supports_overlay_xattrs(&self, storage_root: &Path) -> bool {
+        utils::fs_supports_overlay_xattrs(storage_root)
+    }
+
+    fn hymofs_active(&self) -> bool {
+        storage::is_hymofs_active()
+    }
+
+    fn overlay_source(&self, path: &Path) -> Option<String> {
+        Process::myself()
+            .and_then(|p| p.mountinfo())
+            .ok()?
+            .into_iter()
+            .rfind(|m| m.fs_type == "overlay" && m.mount_point == path)
+            .map(|m| m.mount_source.unwrap_or_default())
+    }
+
+    fn mountinfo(&self) -> Option<String> {
+        fs::read_to_string("/proc/self/mountinfo").ok()
+    }
+}
+
+/// A fake device root: `/system` is looked up as `<root>/system`. Symlinks
+/// inside the tree are followed within `root`, so an absolute target like
+/// `/vendor` resolves to `<root>/vendor`. Nothing is ever mounted there;
+/// mount points and feature support are declared up front.
+#[derive(Debug, Clone)]
+pub struct RootedSystem {
+    pub root: PathBuf,
+    pub mount_points: HashSet<PathBuf>,
+    /// Overlay mounts already present, by mount point, with their source.
+    pub overlays: HashMap<PathBuf, String>,
+    /// Mountinfo of the device, with device paths.
+    pub mountinfo: Option<String>,
+    pub overlay_xattrs: bool,
+    pub hymofs: bool,
+}
+
+/// Symlink hops followed before a path is treated as unresolvable.
+const MAX_SYMLINK_HOPS: usize = 40;
+
+impl RootedSystem {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            mount_points: HashSet::new(),
+            overlays: HashMap::new(),
+            mountinfo: None,
+            overlay_xattrs: true,
+            hymofs: false,
+        }
+    }
+
+    /// A device tree dumped to `root`, as given with `--sysroot`. Every
+    /// top-level partition directory other than `/system` counts as a mount
+    /// point, as it is on the device; one that is a symlink, such as
+    /// `/vendor` on A-only devices, does not. So does each directory in
+    /// `/apex`, standing for an active APEX. A dumped
+    /// `proc/self/mountinfo` is read as the device's.
+    pub fn sysroot(root: impl Into<PathBuf>) -> Self {
+        let mut system = Self::new(root);
+        system.mountinfo = fs::read_to_string(system.root.join("proc/self/mountinfo")).ok();
+        for name in defs::BUILTIN_PARTITIONS
+            .iter()
+            .chain(defs::DLKM_PARTITIONS)
+            .filter(|name| **name != "system")
+        {
+            let host = system.root.join(name);
+            if host.is_dir() && !host.is_symlink() {
+                system.mount_points.insert(Path::new("/").join(name));
+            }
+        }
+        let apexes = fs::read_dir(system.root.join("apex")).into_iter().flatten();
+        for entry in apexes.flatten() {
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                system
+                    .mount_points
+                    .insert(Path::new("/apex").join(entry.file_name()));
+            }
+        }
+        system
+    }
+
+    fn host_path(&self, path: &Path) -> PathBuf {
+        self.root.join(path.strip_prefix("/").unwrap_or(path))
+    }
+
+    /// Resolves `path` component by component, keeping every hop inside
+    /// `root`. Returns the device path, or `None` if some part is missing.
+    fn resolve(&self, path: &Path, hops: &mut usize) -> Option<PathBuf> {
+        let mut resolved = PathBuf::from("/");
+
+        for component in path.components() {
+            match component {
+                Component::RootDir | Component::Prefix(_) | Component::CurDir => {}
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                Component::Normal(name) => {
+                    let next = resolved.join(name);
+                    let host = self.host_path(&next);
+                    let meta = fs::symlink_metadata(&host).ok()?;
+
+                    if meta.file_type().is_symlink() {
+                        *hops += 1;
+                        if *hops > MAX_SYMLINK_HOPS {
+                            return None;
+                        }
+                        let target = fs::read_link(&host).ok()?;
+                        let joined = if target.is_absolute() {
+                            target
+                        } else {
+                            resolved.join(target)
+                        };
+                        resolved = self.resolve(&joined, hops)?;
+                    } else {
+                        resolved = next;
+                    }
+                }
+            }
+        }
+
+        Some(resolved)
+    }
+}
+
+impl SystemProbe for RootedSystem {
+    fn exists(&self, path: &Path) -> bool {
+        self.canonicalize(path).is_some()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.canonicalize(path)
+            .is_some_and(|p| self.host_path(&p).is_dir())
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        self.host_path(path).is_symlink()
+    }
+
+    fn read_link(&self, path: &Path) -> Option<PathBuf> {
+        fs::read_link(self.host_path(path)).ok()
+    }
+
+    fn canonicalize(&self, path: &Path) -> Option<PathBuf> {
+        self.resolve(path, &mut 0)
+    }
+
+    fn is_mount_point(&self, path: &Path) -> bool {
+        self.mount_points.contains(path)
+    }
+
+    fn supports_overlay_xattrs(&self, _storage_root: &Path) -> bool {
+        self.overlay_xattrs
+    }
+
+    fn hymofs_active(&self) -> bool {
+        self.hymofs
+    }
+
+    fn overlay_source(&self, path: &Path) -> Option<String> {
+        self.overlays.get(path).cloned()
+    }
+
+    fn mountinfo(&self) -> Option<String> {
+        self.mountinfo.clone()
+    }
+}
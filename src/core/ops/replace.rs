@@ -0,0 +1,91 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! `.replace` directories, which hide the stock directory they sit over.
+//!
+//! A directory is replaced when it holds a `.replace` marker file or carries
+//! the `trusted.overlay.opaque` xattr; both mount paths accept either form.
+//! Sync turns the marker into the xattr for overlayfs and magic mount reads
+//! the flag into `Node.replace`, so the marker itself is never mounted.
+
+use std::path::{Component, Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use crate::{
+    core::{
+        inventory::Module,
+        ops::{
+            planner::{DiagnosticIssue, DiagnosticLevel},
+            probe::SystemProbe,
+        },
+    },
+    defs, utils,
+};
+
+/// Whether the first component of `relative` is a partition we mount on.
+fn in_partition(relative: &Path) -> bool {
+    match relative.components().next() {
+        Some(Component::Normal(name)) => name.to_str().is_some_and(|name| {
+            defs::BUILTIN_PARTITIONS.contains(&name) || defs::DLKM_PARTITIONS.contains(&name)
+        }),
+        _ => false,
+    }
+}
+
+/// Replaced directories of the module at `module_root` without a stock
+/// directory to replace, relative to the module. Nothing below a replaced
+/// directory is looked at, as the outer one decides what is visible.
+pub fn missing_stock(module_root: &Path, probe: &dyn SystemProbe) -> Vec<PathBuf> {
+    let mut missing = Vec::new();
+    let mut walker = WalkDir::new(module_root)
+        .min_depth(2)
+        .sort_by_file_name()
+        .into_iter();
+
+    while let Some(entry) = walker.next() {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        if !entry.file_type().is_dir() {
+            continue;
+        }
+        let Ok(relative) = entry.path().strip_prefix(module_root) else {
+            continue;
+        };
+        if !in_partition(relative) {
+            walker.skip_current_dir();
+            continue;
+        }
+        if !utils::is_replace_dir(entry.path()) {
+            continue;
+        }
+
+        if !probe.is_dir(&Path::new("/").join(relative)) {
+            missing.push(relative.to_path_buf());
+        }
+        walker.skip_current_dir();
+    }
+    missing
+}
+
+/// One warning per module replacing a directory the device does not have,
+/// which mounts as a new directory instead.
+pub fn diagnose(modules: &[Module], probe: &dyn SystemProbe) -> Vec<DiagnosticIssue> {
+    modules
+        .iter()
+        .filter_map(|module| {
+            let missing = missing_stock(&module.source_path, probe);
+            let first = missing.first()?;
+            Some(DiagnosticIssue {
+                level: DiagnosticLevel::Warning,
+                context: module.id.clone(),
+                message: format!(
+                    "{} replaced dir(s) have no stock directory, e.g. /{}; they are added as new directories",
+                    missing.len(),
+                    first.display()
+                ),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,135 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Predicts what the executor will do with each overlay operation of a plan.
+//!
+//! The executor only finds out that an overlay mount fails when the kernel
+//! refuses it, and then hands the involved modules to magic mount. This runs
+//! the same preconditions against the probe beforehand so diagnostics can
+//! show the outcome without mounting anything.
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use super::{
+    planner::{self, MountPlan, OverlayOperation},
+    probe::{LiveSystem, SystemProbe},
+};
+use crate::{
+    mount::overlayfs::overlayfs::{MAX_LOWERDIR_COUNT, max_lowerdir_len},
+    utils,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PredictedOutcome {
+    /// The overlay mount is expected to succeed.
+    Overlay,
+    /// The overlay mount is expected to fail and its modules to be magic
+    /// mounted instead.
+    FallbackMagic,
+    /// Nothing can be mounted at the target.
+    Skip,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MountPrediction {
+    pub partition: String,
+    pub target: String,
+    pub modules: Vec<String>,
+    pub outcome: PredictedOutcome,
+    /// Why the outcome is not `overlay`, or what the overlay will lose.
+    pub reasons: Vec<String>,
+}
+
+pub fn simulate(plan: &MountPlan, mount_source: &str) -> Vec<MountPrediction> {
+    simulate_with_probe(plan, mount_source, &LiveSystem)
+}
+
+pub fn simulate_with_probe(
+    plan: &MountPlan,
+    mount_source: &str,
+    probe: &dyn SystemProbe,
+) -> Vec<MountPrediction> {
+    plan.overlay_ops
+        .iter()
+        .map(|op| predict(op, mount_source, probe))
+        .collect()
+}
+
+fn predict(op: &OverlayOperation, mount_source: &str, probe: &dyn SystemProbe) -> MountPrediction {
+    let mut prediction = MountPrediction {
+        partition: op.partition_name.clone(),
+        target: op.target.clone(),
+        modules: op
+            .lowerdirs
+            .iter()
+            .filter_map(|p| utils::extract_module_id(p))
+            .collect(),
+        outcome: PredictedOutcome::Overlay,
+        reasons: Vec::new(),
+    };
+
+    let target = Path::new(&op.target);
+    let Some(canonical) = probe.canonicalize(target) else {
+        prediction.outcome = PredictedOutcome::Skip;
+        prediction.reasons.push("target does not exist".to_string());
+        return prediction;
+    };
+    if !probe.is_dir(&canonical) {
+        prediction.outcome = PredictedOutcome::Skip;
+        prediction.reasons.push(format!(
+            "target resolves to {}, not a directory",
+            canonical.display()
+        ));
+        return prediction;
+    }
+
+    let mut fail = |reason: String| {
+        prediction.outcome = PredictedOutcome::FallbackMagic;
+        prediction.reasons.push(reason);
+    };
+
+    // Layers live in module storage, outside the device tree the probe
+    // models, so they are checked directly as the planner does.
+    for lower in &op.lowerdirs {
+        if !lower.is_dir() {
+            fail(format!("layer {} is missing", lower.display()));
+        } else if planner::uses_opaque_dirs(lower) && !probe.supports_overlay_xattrs(lower) {
+            fail(format!(
+                "layer {} needs opaque directories but its filesystem drops trusted.overlay xattrs",
+                lower.display()
+            ));
+        }
+    }
+
+    if let Some(source) = probe.overlay_source(&canonical)
+        && source != mount_source
+    {
+        fail(format!(
+            "target is already an overlay mounted by another manager ({})",
+            source
+        ));
+    }
+
+    // The executor trims these rather than failing, so the mount still
+    // happens with fewer layers.
+    let layers = op.lowerdirs.len() + 1;
+    if layers > MAX_LOWERDIR_COUNT {
+        prediction.reasons.push(format!(
+            "{} layers exceed the limit of {}; the lowest ones will be dropped",
+            layers, MAX_LOWERDIR_COUNT
+        ));
+    }
+    let len = planner::lowerdir_len(op.lowerdirs.iter().map(|p| p.as_path()), &canonical);
+    let max_len = max_lowerdir_len();
+    if len > max_len {
+        prediction.reasons.push(format!(
+            "lowerdir is {} bytes, over the {} byte limit; the lowest layers will be dropped",
+            len, max_len
+        ));
+    }
+
+    prediction
+}
@@ -1,162 +1,938 @@
 // Copyright 2026 Hybrid Mount Developers
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::{collections::HashSet, fs, path::Path};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File, Metadata},
+    io::{self, BufReader, Read},
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use anyhow::Result;
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use walkdir::WalkDir;
 
-use crate::{core::inventory::Module, defs, utils};
+use crate::{
+    core::{
+        hashcache,
+        inventory::Module,
+        ops::{
+            blacklist::Blacklist,
+            identical,
+            planner::{DiagnosticIssue, DiagnosticLevel},
+            sync_journal::{self, JournalOp},
+        },
+    },
+    defs,
+    sys::mount::is_mounted,
+    utils::{self, cancel, pool, progress, timing},
+};
+
+/// Entries at the top of the storage that are not module copies.
+const PRESERVED_STORAGE_ENTRIES: &[&str] = &["lost+found", "meta-hybrid", "magic_workspace"];
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct SyncSummary {
+    pub copied: usize,
+    pub skipped: usize,
+    pub deleted: usize,
+    pub failed: usize,
+    #[serde(default)]
+    pub failures: Vec<String>,
+    /// Files replaced by a hard link to an identical copy during this sync.
+    #[serde(default)]
+    pub dedup_linked: usize,
+    /// Bytes the storage currently saves by sharing identical files.
+    #[serde(default)]
+    pub dedup_saved_bytes: u64,
+    /// Copies of modules that are no longer enabled, removed from storage.
+    #[serde(default)]
+    pub pruned: usize,
+    /// Bytes freed by removing those copies.
+    #[serde(default)]
+    pub reclaimed_bytes: u64,
+    /// Disk usage of every synced module, keyed by module id.
+    #[serde(default)]
+    pub module_usage: BTreeMap<String, ModuleUsage>,
+    /// Files left out of storage because they are identical to stock.
+    #[serde(default)]
+    pub skipped_identical: usize,
+    /// Files left out of storage by `mount_blacklist` or a module's
+    /// `blacklist`.
+    #[serde(default)]
+    pub skipped_blacklisted: usize,
+    /// Entries in storage still without a usable SELinux context after
+    /// sync: missing, `rootfs` or `unlabeled`. Only counted with SELinux.
+    #[serde(default)]
+    pub unlabeled: usize,
+    /// Modules whose storage copy an earlier sync or prune left halfway;
+    /// their copies were rebuilt from scratch.
+    #[serde(default)]
+    pub recovered: Vec<String>,
+    /// Modules whose copy could not be synced completely. They keep their
+    /// journal marker and are not mounted until a later sync succeeds.
+    #[serde(default)]
+    pub dirty: Vec<String>,
+    /// Modules whose storage copy this sync copied into or deleted from.
+    #[serde(default)]
+    pub changed: Vec<String>,
+}
+
+/// How much one module occupies in its source directory and in storage.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ModuleUsage {
+    pub source_bytes: u64,
+    /// Bytes the source takes up on disk; less than `source_bytes` when
+    /// it has sparse files.
+    #[serde(default)]
+    pub source_allocated_bytes: u64,
+    pub source_files: usize,
+    /// Bytes of the storage copy. A file deduplicated with other modules is
+    /// split evenly between the modules sharing it.
+    pub synced_bytes: u64,
+    /// Bytes the storage copy takes up on disk, split the same way. Sync
+    /// keeps the holes of sparse files.
+    #[serde(default)]
+    pub synced_allocated_bytes: u64,
+    /// Entries in the storage copy; whiteouts count, `.replace` markers
+    /// translated to opaque xattrs do not.
+    pub synced_files: usize,
+    /// Whether the module has a copy in the storage backend. Modules with
+    /// nothing to sync are mounted from their source and take no space there.
+    pub in_storage: bool,
+    /// Files not mounted because they are identical to the stock file.
+    #[serde(default)]
+    pub skipped_identical: usize,
+}
+
+impl ModuleUsage {
+    /// What the module costs: its storage copy, or its source when it has
+    /// none.
+    pub fn size_bytes(&self) -> u64 {
+        if self.in_storage {
+            self.synced_bytes
+        } else {
+            self.source_bytes
+        }
+    }
+}
+
+impl SyncSummary {
+    fn merge(mut self, other: Self) -> Self {
+        self.copied += other.copied;
+        self.skipped += other.skipped;
+        self.deleted += other.deleted;
+        self.failed += other.failed;
+        self.skipped_identical += other.skipped_identical;
+        self.skipped_blacklisted += other.skipped_blacklisted;
+        self.failures.extend(other.failures);
+        self.module_usage.extend(other.module_usage);
+        self.dirty.extend(other.dirty);
+        self.changed.extend(other.changed);
+        self
+    }
+
+    fn fail(&mut self, module_id: &str, path: &Path, err: impl std::fmt::Display) {
+        self.failed += 1;
+        self.failures
+            .push(format!("{}: {}: {}", module_id, path.display(), err));
+    }
+}
+
+/// Warnings about the modules the journal caught with a half-written copy.
+pub fn diagnose(summary: &SyncSummary) -> Vec<DiagnosticIssue> {
+    let recovered = summary.recovered.iter().map(|id| DiagnosticIssue {
+        level: DiagnosticLevel::Warning,
+        context: id.clone(),
+        message: "An earlier sync or removal of this module was interrupted; \
+                  its storage copy was rebuilt"
+            .to_string(),
+    });
+    let dirty = summary.dirty.iter().map(|id| DiagnosticIssue {
+        level: DiagnosticLevel::Warning,
+        context: id.clone(),
+        message: "Not mounted: its storage copy could not be synced completely; \
+                  the next boot syncs it again"
+            .to_string(),
+    });
+    recovered.chain(dirty).collect()
+}
+
+pub fn perform_sync(
+    modules: &[Module],
+    target_base: &Path,
+    dedup_min_size: u64,
+) -> Result<SyncSummary> {
+    perform_sync_with_stock(
+        modules,
+        target_base,
+        dedup_min_size,
+        None,
+        &Blacklist::default(),
+    )
+}
 
-pub fn perform_sync(modules: &[Module], target_base: &Path) -> Result<()> {
+/// [`perform_sync`] that, given the root of the stock partitions, leaves out
+/// module files identical to the stock file they would replace, and always
+/// leaves out the files `blacklist` matches.
+pub fn perform_sync_with_stock(
+    modules: &[Module],
+    target_base: &Path,
+    dedup_min_size: u64,
+    identical_stock_root: Option<&Path>,
+    blacklist: &Blacklist,
+) -> Result<SyncSummary> {
     log::info!("Starting smart module sync to {}", target_base.display());
+    let mut span = timing::span("sync");
+    span.items(modules.len());
+
+    let (recovered, unrecoverable) = recover_interrupted(modules, target_base);
+    let (pruned, reclaimed_bytes) = prune_orphaned_modules(modules, target_base)?;
+
+    let done = AtomicUsize::new(0);
+    let report = |id: &str| {
+        let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+        progress::emit("sync", Some(id), done, modules.len());
+    };
+
+    let summary = pool::install(|| {
+        modules
+            .par_iter()
+            .map(|module| {
+                // Modules already being copied finish; the rest are left alone.
+                if cancel::requested().is_some() {
+                    report(&module.id);
+                    return SyncSummary::default();
+                }
+                if unrecoverable.contains(&module.id) {
+                    report(&module.id);
+                    return SyncSummary {
+                        dirty: vec![module.id.clone()],
+                        ..Default::default()
+                    };
+                }
 
-    prune_orphaned_modules(modules, target_base)?;
+                let has_content = !module.content_partitions(&[]).is_empty();
+
+                let source = tree_usage(&module.source_path, false);
+                let mut usage = ModuleUsage {
+                    source_bytes: source.bytes,
+                    source_allocated_bytes: source.allocated_bytes,
+                    source_files: source.files,
+                    ..Default::default()
+                };
+
+                if !has_content {
+                    crate::module_debug!(Some(&module.id), "Skipping module: no content");
+                    report(&module.id);
+                    return SyncSummary {
+                        module_usage: BTreeMap::from([(module.id.clone(), usage)]),
+                        ..Default::default()
+                    };
+                }
 
-    modules.par_iter().for_each(|module| {
-        let dst = target_base.join(&module.id);
-        let dst_backup = target_base.join(format!(".backup_{}", module.id));
+                let dst = target_base.join(&module.id);
+                let mut stats = match sync_journal::begin(
+                    target_base,
+                    JournalOp::Sync,
+                    &module.id,
+                    Some(&module.source_path),
+                ) {
+                    Ok(()) => sync_module(module, &dst, identical_stock_root, blacklist),
+                    Err(e) => {
+                        let mut stats = SyncSummary::default();
+                        stats.fail(&module.id, &dst, format!("{:#}", e));
+                        stats
+                    }
+                };
+                if stats.failed > 0 {
+                    stats.dirty.push(module.id.clone());
+                }
+                usage.skipped_identical = stats.skipped_identical;
+                stats.module_usage.insert(module.id.clone(), usage);
+
+                if stats.copied > 0 || stats.deleted > 0 {
+                    stats.changed.push(module.id.clone());
+                    log::info!(
+                        "Synced module: {} (copied: {}, deleted: {}, identical to stock: {})",
+                        module.id,
+                        stats.copied,
+                        stats.deleted,
+                        stats.skipped_identical
+                    );
+                } else {
+                    crate::module_debug!(Some(&module.id), "Module unchanged");
+                }
 
-        let has_content = defs::BUILTIN_PARTITIONS.iter().any(|p| {
-            let part_path = module.source_path.join(p);
+                report(&module.id);
+                stats
+            })
+            .reduce(SyncSummary::default, SyncSummary::merge)
+    });
 
-            part_path.exists() && has_files_recursive(&part_path)
-        });
+    let mut summary = summary;
+    summary.pruned = pruned;
+    summary.reclaimed_bytes = reclaimed_bytes;
+    summary.recovered = recovered;
+    summary.dirty.sort();
+    summary.changed.sort();
+    commit_synced(modules, target_base, &mut summary);
+    if dedup_min_size > 0 {
+        let (linked, saved) = dedup_storage(modules, target_base, dedup_min_size);
+        summary.dedup_linked = linked;
+        summary.dedup_saved_bytes = saved;
+    }
 
-        if has_content && should_sync(&module.source_path, &dst) {
-            log::info!("Syncing module: {} (Updated/New)", module.id);
+    let verify_labels = utils::selinux_enabled();
+    for (id, usage) in summary.module_usage.iter_mut() {
+        let copy = target_base.join(id);
+        if copy.is_dir() {
+            let synced = tree_usage(&copy, true);
+            usage.synced_bytes = synced.bytes;
+            usage.synced_allocated_bytes = synced.allocated_bytes;
+            usage.synced_files = synced.files;
+            usage.in_storage = true;
+            if verify_labels {
+                summary.unlabeled += count_unlabeled(&copy, id);
+            }
+        }
+    }
 
-            let tmp_dst = target_base.join(format!(".tmp_{}", module.id));
+    for failure in &summary.failures {
+        log::error!("Sync failure: {}", failure);
+    }
+    if !summary.dirty.is_empty() {
+        log::warn!(
+            "!! Incomplete storage copies, not mounting: {}",
+            summary.dirty.join(", ")
+        );
+    }
 
-            if tmp_dst.exists() {
-                let _ = fs::remove_dir_all(&tmp_dst);
+    log::info!(
+        "Sync complete: {} copied, {} skipped, {} deleted, {} failed, {} bytes deduplicated, \
+         {} orphans pruned ({} bytes), {} unlabeled",
+        summary.copied,
+        summary.skipped,
+        summary.deleted,
+        summary.failed,
+        summary.dedup_saved_bytes,
+        summary.pruned,
+        summary.reclaimed_bytes,
+        summary.unlabeled
+    );
+
+    Ok(summary)
+}
+
+/// Handles the journal markers a previous run left behind. The copy of an
+/// enabled module is deleted so this sync rebuilds it from scratch; the
+/// copy of a disabled one is left to the orphan pruning. Returns the
+/// modules recovered and the enabled ones whose copy could not be deleted.
+fn recover_interrupted(modules: &[Module], target_base: &Path) -> (Vec<String>, HashSet<String>) {
+    let mut recovered = Vec::new();
+    let mut unrecoverable = HashSet::new();
+
+    for marker in sync_journal::pending(target_base) {
+        let id = marker.module;
+        let copy = target_base.join(&id);
+        let enabled = modules.iter().any(|m| m.id == id);
+        log::warn!(
+            "!! {:?} of module {} was interrupted; {}",
+            marker.op,
+            id,
+            if enabled {
+                "rebuilding its storage copy"
+            } else {
+                "finishing its removal"
             }
+        );
+
+        if !enabled {
+            if copy.symlink_metadata().is_err()
+                && let Err(e) = sync_journal::commit(target_base, &id)
+            {
+                log::warn!("{:#}", e);
+            }
+        } else if let Err(e) = fs::remove_dir_all(&copy)
+            && e.kind() != io::ErrorKind::NotFound
+        {
+            log::error!("Failed to remove the incomplete copy of {}: {}", id, e);
+            unrecoverable.insert(id.clone());
+        }
+        recovered.push(id);
+    }
 
-            if let Err(e) = utils::sync_dir(&module.source_path, &tmp_dst, true) {
-                log::error!("Failed to sync module {}: {}", module.id, e);
-                let _ = fs::remove_dir_all(&tmp_dst);
-                return;
+    (recovered, unrecoverable)
+}
+
+/// Flushes the storage and clears the markers of modules synced without a
+/// failure. On a failed flush every marker stays and the modules count as
+/// dirty, since their copies may not have reached the disk.
+fn commit_synced(modules: &[Module], target_base: &Path, summary: &mut SyncSummary) {
+    if !sync_journal::journal_dir(target_base).is_dir() {
+        return;
+    }
+    if let Err(e) = sync_journal::flush(target_base) {
+        log::error!("Failed to flush synced modules: {:#}", e);
+        summary.dirty = sync_journal::pending(target_base)
+            .into_iter()
+            .map(|m| m.module)
+            .filter(|id| modules.iter().any(|m| &m.id == id))
+            .collect();
+        return;
+    }
+    for module in modules {
+        if summary.dirty.contains(&module.id) {
+            continue;
+        }
+        if let Err(e) = sync_journal::commit(target_base, &module.id) {
+            log::warn!("{:#}", e);
+        }
+    }
+}
+
+fn sync_module(
+    module: &Module,
+    dst: &Path,
+    identical_stock_root: Option<&Path>,
+    blacklist: &Blacklist,
+) -> SyncSummary {
+    let mut stats = SyncSummary::default();
+    let src = &module.source_path;
+    // first destination written for each hard-linked source inode
+    let mut linked: HashMap<(u64, u64), PathBuf> = HashMap::new();
+
+    if let Err(e) = utils::mirror_dir(src, dst, Path::new(""), true) {
+        stats.fail(&module.id, dst, e);
+        return stats;
+    }
+
+    for entry in WalkDir::new(src).min_depth(1) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                stats.fail(&module.id, src, e);
+                continue;
             }
+        };
+
+        let Ok(relative) = entry.path().strip_prefix(src) else {
+            continue;
+        };
+        let dst_path = dst.join(relative);
 
-            if let Err(e) = utils::prune_empty_dirs(&tmp_dst) {
-                log::warn!("Failed to prune empty dirs for {}: {}", module.id, e);
+        // Markers only matter at plan time and must not show up in the mount.
+        if utils::is_rw_marker(entry.path()) {
+            continue;
+        }
+        // Already carried by the opaque xattr of the synced directory.
+        if utils::is_replace_marker(entry.path())
+            && dst_path.parent().is_some_and(utils::is_overlay_opaque)
+        {
+            continue;
+        }
+
+        if entry.file_type().is_dir() {
+            match utils::mirror_dir(entry.path(), &dst_path, relative, true) {
+                // A directory kept from an earlier sync may predate its label.
+                Ok(false) => {
+                    let _ = utils::internal_apply_system_context(&dst_path, relative);
+                }
+                Ok(true) => {}
+                Err(e) => stats.fail(&module.id, &dst_path, e),
             }
+            continue;
+        }
 
-            if let Err(e) = apply_overlay_opaque_flags(&tmp_dst) {
-                log::warn!(
-                    "Failed to apply overlay opaque xattrs for {}: {}",
-                    module.id,
-                    e
-                );
+        if blacklist.matching(&module.id, relative).is_some() {
+            match fs::remove_file(&dst_path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    stats.fail(&module.id, &dst_path, e)
+                }
+                _ => stats.skipped_blacklisted += 1,
             }
+            continue;
+        }
 
-            let mut backup_created = false;
-            if dst.exists() {
-                if let Err(e) = fs::rename(&dst, &dst_backup) {
-                    log::error!("Failed to backup existing module {}: {}", module.id, e);
-                    let _ = fs::remove_dir_all(&tmp_dst);
-                    return;
+        if let Some(stock_root) = identical_stock_root
+            && identical::matches_stock(src, entry.path(), relative, stock_root)
+        {
+            match fs::remove_file(&dst_path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    stats.fail(&module.id, &dst_path, e)
                 }
-                backup_created = true;
+                _ => stats.skipped_identical += 1,
             }
+            continue;
+        }
 
-            if let Err(e) = fs::rename(&tmp_dst, &dst) {
-                log::error!("Failed to commit atomic sync for {}: {}", module.id, e);
-                if backup_created {
-                    let _ = fs::rename(&dst_backup, &dst);
+        if let Ok(meta) = entry.metadata()
+            && meta.is_file()
+            && meta.nlink() > 1
+        {
+            let key = (meta.dev(), meta.ino());
+            if let Some(first) = linked.get(&key) {
+                match link_entry(first, &dst_path) {
+                    Ok(true) => stats.copied += 1,
+                    Ok(false) => stats.skipped += 1,
+                    Err(e) => stats.fail(&module.id, &dst_path, e),
                 }
-                let _ = fs::remove_dir_all(&tmp_dst);
-                return;
+                continue;
             }
+            linked.insert(key, dst_path.clone());
+        }
 
-            if backup_created && let Err(e) = fs::remove_dir_all(&dst_backup) {
-                log::warn!("Failed to clean up backup for {}: {}", module.id, e);
+        match entry.metadata() {
+            Ok(meta) if is_unchanged(entry.path(), &meta, &dst_path) => {
+                let _ = utils::internal_apply_system_context(&dst_path, relative);
+                stats.skipped += 1
             }
+            Ok(_) => match utils::copy_entry(entry.path(), &dst_path, relative, true) {
+                Ok(_) => stats.copied += 1,
+                Err(e) => stats.fail(&module.id, &dst_path, e),
+            },
+            Err(e) => stats.fail(&module.id, entry.path(), e),
+        }
+    }
+
+    for entry in WalkDir::new(dst)
+        .min_depth(1)
+        .contents_first(true)
+        .into_iter()
+        .flatten()
+    {
+        let Ok(relative) = entry.path().strip_prefix(dst) else {
+            continue;
+        };
+
+        let src_path = src.join(relative);
+        if src_path.symlink_metadata().is_ok() && !utils::is_rw_marker(&src_path) {
+            continue;
+        }
+
+        let result = if entry.file_type().is_dir() {
+            fs::remove_dir_all(entry.path())
         } else {
-            log::debug!("Skipping module: {}", module.id);
+            fs::remove_file(entry.path())
+        };
+
+        match result {
+            Ok(_) => stats.deleted += 1,
+            Err(e) => stats.fail(&module.id, entry.path(), e),
         }
-    });
+    }
 
-    Ok(())
+    if let Err(e) = utils::prune_empty_dirs(dst) {
+        log::warn!("Failed to prune empty dirs for {}: {}", module.id, e);
+    }
+
+    if let Err(e) = apply_overlay_opaque_flags(&module.id, src, dst) {
+        log::warn!(
+            "Failed to apply overlay opaque xattrs for {}: {}",
+            module.id,
+            e
+        );
+    }
+
+    stats
 }
 
-fn apply_overlay_opaque_flags(root: &Path) -> Result<()> {
-    for entry in WalkDir::new(root).min_depth(1).into_iter().flatten() {
-        #[allow(clippy::collapsible_if)]
-        if entry.file_type().is_file() && entry.file_name() == defs::REPLACE_DIR_FILE_NAME {
-            if let Some(parent) = entry.path().parent() {
-                utils::set_overlay_opaque(parent)?;
-                log::debug!("Set overlay opaque xattr on: {}", parent.display());
+/// Entries under `copy` of module `id` whose SELinux context is missing,
+/// `rootfs` or `unlabeled`; each is logged at debug level.
+fn count_unlabeled(copy: &Path, id: &str) -> usize {
+    WalkDir::new(copy)
+        .min_depth(1)
+        .into_iter()
+        .flatten()
+        .filter(|entry| {
+            let labeled =
+                utils::lgetfilecon(entry.path()).is_ok_and(|c| utils::is_valid_context(&c));
+            if !labeled {
+                crate::module_debug!(
+                    Some(id),
+                    "No valid SELinux context after sync: {}",
+                    entry.path().display()
+                );
             }
+            !labeled
+        })
+        .count()
+}
+
+/// Makes `dst` another name for `first`. Returns `false` when it already was.
+fn link_entry(first: &Path, dst: &Path) -> Result<bool> {
+    let first_meta = fs::symlink_metadata(first)?;
+
+    if let Ok(dst_meta) = fs::symlink_metadata(dst) {
+        if dst_meta.dev() == first_meta.dev() && dst_meta.ino() == first_meta.ino() {
+            return Ok(false);
+        }
+        if dst_meta.is_dir() {
+            fs::remove_dir_all(dst)?;
+        } else {
+            fs::remove_file(dst)?;
         }
     }
-    Ok(())
+
+    fs::hard_link(first, dst)?;
+    Ok(true)
 }
 
-fn prune_orphaned_modules(modules: &[Module], target_base: &Path) -> Result<()> {
-    if !target_base.exists() {
-        return Ok(());
+fn is_unchanged(src: &Path, src_meta: &Metadata, dst: &Path) -> bool {
+    let Ok(dst_meta) = fs::symlink_metadata(dst) else {
+        return false;
+    };
+
+    let ft = src_meta.file_type();
+    if ft != dst_meta.file_type() {
+        return false;
     }
 
-    let active_ids: HashSet<&str> = modules.iter().map(|m| m.id.as_str()).collect();
+    if ft.is_symlink() {
+        return matches!(
+            (fs::read_link(src), fs::read_link(dst)),
+            (Ok(a), Ok(b)) if a == b
+        );
+    }
 
-    let entries: Vec<_> = fs::read_dir(target_base)?.filter_map(|e| e.ok()).collect();
+    if ft.is_char_device() || ft.is_block_device() {
+        return src_meta.rdev() == dst_meta.rdev() && src_meta.mode() == dst_meta.mode();
+    }
 
-    entries.par_iter().for_each(|entry| {
-        let path = entry.path();
+    if src_meta.len() != dst_meta.len()
+        || src_meta.mode() != dst_meta.mode()
+        || !utils::overlay_xattrs_equal(src, dst)
+    {
+        return false;
+    }
 
-        let name_os = entry.file_name();
+    // A deduplicated copy carries the mtime of whichever file it was linked
+    // to, so fall back to comparing contents instead of recopying it.
+    src_meta.mtime() == dst_meta.mtime()
+        || (dst_meta.nlink() > 1 && same_content(src, dst).unwrap_or(false))
+}
 
-        let name = name_os.to_string_lossy();
+fn same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    let mut a = BufReader::new(File::open(a)?);
+    let mut b = BufReader::new(File::open(b)?);
+    let mut buf_a = [0u8; 64 * 1024];
+    let mut buf_b = [0u8; 64 * 1024];
 
-        if name != "lost+found"
-            && name != "meta-hybrid"
-            && !name.starts_with('.')
-            && !active_ids.contains(name.as_ref())
-        {
-            log::info!("Pruning orphaned module storage: {}", name);
+    loop {
+        let n = a.read(&mut buf_a)?;
+        if n == 0 {
+            return Ok(b.read(&mut buf_b[..1])? == 0);
+        }
+        b.read_exact(&mut buf_b[..n])?;
+        if buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+    }
+}
 
-            if path.is_dir() {
-                if let Err(e) = fs::remove_dir_all(&path) {
-                    log::warn!("Failed to remove orphan dir {}: {}", name, e);
-                }
-            } else if let Err(e) = fs::remove_file(&path) {
-                log::warn!("Failed to remove orphan file {}: {}", name, e);
+/// Files that may share one inode: same size, permissions, owner and label.
+#[derive(PartialEq, Eq, Hash)]
+struct DedupKey {
+    dev: u64,
+    len: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    context: String,
+}
+
+struct DedupCandidate {
+    path: PathBuf,
+    ino: u64,
+}
+
+/// Hard-links synced files of at least `min_size` bytes that have identical
+/// contents. Only paths inside the storage are touched; module sources are
+/// never linked. Returns the number of files relinked and the bytes the
+/// storage saves through shared inodes.
+fn dedup_storage(modules: &[Module], target_base: &Path, min_size: u64) -> (usize, u64) {
+    let mut groups: HashMap<DedupKey, Vec<DedupCandidate>> = HashMap::new();
+
+    for module in modules {
+        for entry in WalkDir::new(target_base.join(&module.id))
+            .min_depth(1)
+            .into_iter()
+            .flatten()
+        {
+            if !entry.file_type().is_file() {
+                continue;
             }
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            if meta.len() < min_size {
+                continue;
+            }
+
+            let key = DedupKey {
+                dev: meta.dev(),
+                len: meta.len(),
+                mode: meta.mode(),
+                uid: meta.uid(),
+                gid: meta.gid(),
+                context: utils::lgetfilecon(entry.path()).unwrap_or_default(),
+            };
+            groups.entry(key).or_default().push(DedupCandidate {
+                path: entry.into_path(),
+                ino: meta.ino(),
+            });
         }
+    }
+
+    let linked: usize = pool::install(|| {
+        groups
+            .par_iter()
+            .filter(|(_, files)| files.iter().any(|f| f.ino != files[0].ino))
+            .map(|(_, files)| dedup_group(files))
+            .sum()
     });
 
-    Ok(())
+    let saved = groups
+        .iter()
+        .flat_map(|(key, files)| {
+            let mut inodes: HashMap<u64, u64> = HashMap::new();
+            for file in files {
+                if let Ok(meta) = fs::symlink_metadata(&file.path) {
+                    *inodes.entry(meta.ino()).or_default() += 1;
+                }
+            }
+            inodes.into_values().map(move |names| (names - 1) * key.len)
+        })
+        .sum();
+
+    (linked, saved)
 }
 
-fn should_sync(src: &Path, dst: &Path) -> bool {
-    if !dst.exists() {
-        return true;
+fn dedup_group(files: &[DedupCandidate]) -> usize {
+    // canonical path per content hash, plus the inode it lives on
+    let mut canonical: HashMap<[u8; 32], &DedupCandidate> = HashMap::new();
+    let mut hashed: HashMap<u64, [u8; 32]> = HashMap::new();
+    let mut linked = 0;
+
+    for file in files {
+        let hash = match hashed.get(&file.ino) {
+            Some(hash) => *hash,
+            None => match hashcache::hash_file_cached(&file.path) {
+                Ok(hash) => {
+                    hashed.insert(file.ino, hash);
+                    hash
+                }
+                Err(e) => {
+                    log::debug!("dedup: failed to hash {}: {}", file.path.display(), e);
+                    continue;
+                }
+            },
+        };
+
+        let Some(first) = canonical.get(&hash) else {
+            canonical.insert(hash, file);
+            continue;
+        };
+        if first.ino == file.ino || !utils::overlay_xattrs_equal(&first.path, &file.path) {
+            continue;
+        }
+
+        match replace_with_link(&first.path, &file.path) {
+            Ok(()) => {
+                log::debug!("dedup: {} -> {}", file.path.display(), first.path.display());
+                linked += 1;
+            }
+            Err(e) => log::warn!("dedup: failed to link {}: {}", file.path.display(), e),
+        }
     }
 
-    let src_prop = src.join("module.prop");
+    linked
+}
+
+/// Atomically replaces `dst` with a hard link to `first`.
+fn replace_with_link(first: &Path, dst: &Path) -> io::Result<()> {
+    let mut tmp = dst.as_os_str().to_owned();
+    tmp.push(".dedup");
+    let tmp = PathBuf::from(tmp);
+
+    let _ = fs::remove_file(&tmp);
+    fs::hard_link(first, &tmp)?;
+    fs::rename(&tmp, dst).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
 
-    let dst_prop = dst.join("module.prop");
+/// Translates Magisk-style `.replace` markers into overlayfs opaque
+/// directories and drops the copied marker once the xattr is set, so it
+/// does not show up in the mount. Where the storage refuses the xattr the
+/// marker stays for magic mount to honour. Char-device 0:0 whiteouts are
+/// already valid overlayfs whiteouts and are carried over as-is by
+/// `copy_entry`.
+fn apply_overlay_opaque_flags(module_id: &str, src: &Path, dst: &Path) -> Result<()> {
+    for entry in WalkDir::new(dst).into_iter().flatten() {
+        if !entry.file_type().is_dir() {
+            continue;
+        }
 
-    if !src_prop.exists() || !dst_prop.exists() {
-        return true;
+        let Ok(relative) = entry.path().strip_prefix(dst) else {
+            continue;
+        };
+        let src_dir = src.join(relative);
+
+        let wants_opaque = utils::is_replace_dir(&src_dir);
+        let is_opaque = utils::is_overlay_opaque(entry.path());
+
+        if wants_opaque {
+            if !is_opaque {
+                utils::set_overlay_opaque(entry.path())?;
+                crate::module_debug!(
+                    Some(module_id),
+                    "Set overlay opaque xattr on: {}",
+                    entry.path().display()
+                );
+            }
+            let marker = entry.path().join(defs::REPLACE_DIR_FILE_NAME);
+            if utils::is_replace_marker(&marker) {
+                fs::remove_file(&marker)?;
+            }
+        } else if is_opaque {
+            utils::clear_overlay_opaque(entry.path())?;
+            crate::module_debug!(
+                Some(module_id),
+                "Cleared overlay opaque xattr on: {}",
+                entry.path().display()
+            );
+        }
     }
+    Ok(())
+}
 
-    match (fs::read(&src_prop), fs::read(&dst_prop)) {
-        (Ok(s), Ok(d)) => s != d,
-        _ => true,
+/// Whether `path` is the root of its own mount rather than a plain
+/// directory on the filesystem below it.
+fn is_separate_filesystem(path: &Path) -> bool {
+    let Some(parent) = path.parent() else {
+        return false;
+    };
+
+    match (fs::metadata(path), fs::metadata(parent)) {
+        (Ok(own), Ok(below)) => own.dev() != below.dev() || is_mounted(path),
+        _ => false,
     }
 }
 
-fn has_files_recursive(path: &Path) -> bool {
-    if let Ok(entries) = fs::read_dir(path) {
-        for entry in entries.flatten() {
-            if entry.file_type().is_ok() {
-                return true;
-            }
-        }
+/// Bytes that removing `path` frees. Files still hard-linked elsewhere
+/// (e.g. shared by dedup) are not counted.
+fn reclaimable_bytes(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|meta| meta.is_file() && meta.nlink() == 1)
+        .map(|meta| meta.len())
+        .sum()
+}
+
+/// Removes top-level entries of `target_base` that belong to no enabled
+/// module, whether it was uninstalled or is still installed but disabled or
+/// marked `skip_mount` since it was synced. This runs before copying so the
+/// freed space is available to the sync. Nothing is removed unless
+/// `target_base` is its own mount; if the storage failed to mount we would
+/// otherwise delete from whatever lies underneath. Returns the number of
+/// entries pruned and the bytes freed.
+fn prune_orphaned_modules(modules: &[Module], target_base: &Path) -> Result<(usize, u64)> {
+    if !target_base.exists() {
+        return Ok((0, 0));
     }
 
-    false
+    if !is_separate_filesystem(target_base) {
+        log::warn!(
+            "{} is not a mounted storage backend, skipping orphan pruning",
+            target_base.display()
+        );
+        return Ok((0, 0));
+    }
+
+    let active_ids: HashSet<&str> = modules.iter().map(|m| m.id.as_str()).collect();
+
+    let entries: Vec<_> = fs::read_dir(target_base)?.filter_map(|e| e.ok()).collect();
+
+    let pruned: Vec<u64> = pool::install(|| {
+        entries
+            .par_iter()
+            .filter_map(|entry| {
+                let path = entry.path();
+                let name_os = entry.file_name();
+                let name = name_os.to_string_lossy();
+
+                if PRESERVED_STORAGE_ENTRIES.contains(&&*name)
+                    || name.starts_with('.')
+                    || active_ids.contains(&*name)
+                {
+                    return None;
+                }
+
+                let bytes = reclaimable_bytes(&path);
+                log::info!(
+                    "Pruning storage of removed or disabled module: {} ({} bytes)",
+                    name,
+                    bytes
+                );
+
+                if let Err(e) = sync_journal::begin(target_base, JournalOp::Prune, &name, None) {
+                    log::warn!("{:#}", e);
+                }
+                let removed = if path.is_dir() {
+                    fs::remove_dir_all(&path)
+                } else {
+                    fs::remove_file(&path)
+                };
+
+                match removed {
+                    Ok(()) => {
+                        if let Err(e) = sync_journal::commit(target_base, &name) {
+                            log::warn!("{:#}", e);
+                        }
+                        Some(bytes)
+                    }
+                    Err(e) => {
+                        log::warn!("Failed to remove orphan {}: {}", name, e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    });
+
+    Ok((pruned.len(), pruned.iter().sum()))
+}
+
+#[derive(Default)]
+struct TreeUsage {
+    bytes: u64,
+    allocated_bytes: u64,
+    files: usize,
+}
+
+/// Bytes, allocated bytes and non-directory entries under `root`. With
+/// `share_links`, a file with several hard links contributes `1 / nlink` of
+/// its size per name, so shared copies are not counted once per module.
+fn tree_usage(root: &Path, share_links: bool) -> TreeUsage {
+    WalkDir::new(root)
+        .min_depth(1)
+        .into_iter()
+        .flatten()
+        .filter(|entry| !entry.file_type().is_dir())
+        .filter_map(|entry| entry.metadata().ok())
+        .fold(TreeUsage::default(), |mut usage, meta| {
+            if meta.is_file() {
+                let names = if share_links { meta.nlink().max(1) } else { 1 };
+                usage.bytes += meta.len() / names;
+                usage.allocated_bytes += utils::allocated_size(&meta) / names;
+            }
+            usage.files += 1;
+            usage
+        })
 }
@@ -0,0 +1,127 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Write-ahead markers for changes sync makes inside the storage.
+//!
+//! Before a module's copy is changed, `.journal/<id>.pending` is written
+//! and made durable. It is removed once the change completed and the
+//! storage was flushed, so a marker found later means the copy was left
+//! halfway, e.g. by a power loss, and must not be mounted as it is. The
+//! journal lives in the storage itself and shares its lifetime; a module id
+//! always starts with a letter, so it cannot clash with a module copy.
+
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::utils;
+
+pub const JOURNAL_DIR_NAME: &str = ".journal";
+const MARKER_EXTENSION: &str = "pending";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalOp {
+    /// Copying the module source into its storage copy.
+    Sync,
+    /// Deleting the storage copy of a module that is no longer enabled.
+    Prune,
+}
+
+/// What a marker records about the change it guards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingMarker {
+    pub op: JournalOp,
+    pub module: String,
+    /// Module directory copied from; absent for a prune.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<PathBuf>,
+    /// Unix time the change started.
+    #[serde(default)]
+    pub started: u64,
+}
+
+pub fn journal_dir(storage: &Path) -> PathBuf {
+    storage.join(JOURNAL_DIR_NAME)
+}
+
+fn marker_path(storage: &Path, module: &str) -> PathBuf {
+    journal_dir(storage).join(format!("{}.{}", module, MARKER_EXTENSION))
+}
+
+/// Durably records that `module`'s copy in `storage` is about to change.
+pub fn begin(storage: &Path, op: JournalOp, module: &str, source: Option<&Path>) -> Result<()> {
+    let marker = PendingMarker {
+        op,
+        module: module.to_string(),
+        source: source.map(Path::to_path_buf),
+        started: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+    };
+    fs::create_dir_all(journal_dir(storage))?;
+    utils::atomic_write(marker_path(storage, module), serde_json::to_vec(&marker)?)
+        .with_context(|| format!("Failed to write the sync journal marker of {}", module))
+}
+
+/// Forgets the marker of `module`. Only call it once the change is on
+/// disk; see [`flush`].
+pub fn commit(storage: &Path, module: &str) -> Result<()> {
+    match fs::remove_file(marker_path(storage, module)) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to clear the sync journal marker of {}", module));
+        }
+    }
+    if let Ok(dir) = File::open(journal_dir(storage)) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// Writes everything changed in the filesystem holding `storage` to disk.
+pub fn flush(storage: &Path) -> Result<()> {
+    let dir = File::open(storage)?;
+    rustix::fs::syncfs(&dir).with_context(|| format!("syncfs {}", storage.display()))
+}
+
+/// Markers left behind by changes that never completed, by module id. A
+/// marker that cannot be read still names its module and counts as an
+/// interrupted sync.
+pub fn pending(storage: &Path) -> Vec<PendingMarker> {
+    let Ok(entries) = fs::read_dir(journal_dir(storage)) else {
+        return Vec::new();
+    };
+
+    let mut markers: Vec<PendingMarker> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != MARKER_EXTENSION) {
+                return None;
+            }
+            let module = path.file_stem()?.to_string_lossy().to_string();
+            let marker = fs::read(&path)
+                .ok()
+                .and_then(|raw| serde_json::from_slice::<PendingMarker>(&raw).ok())
+                .filter(|m| m.module == module)
+                .unwrap_or(PendingMarker {
+                    op: JournalOp::Sync,
+                    module,
+                    source: None,
+                    started: 0,
+                });
+            Some(marker)
+        })
+        .collect();
+    markers.sort_by(|a, b| a.module.cmp(&b.module));
+    markers
+}
@@ -0,0 +1,223 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! How the mount plan changes once the module updates waiting in
+//! `modules_update` are applied at the next reboot.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use super::{
+    planner::{self, MountPlan},
+    probe::{LiveSystem, SystemProbe},
+};
+use crate::{
+    conf::config::Config,
+    core::inventory::{
+        self, Module,
+        updates::{self, PendingUpdate},
+    },
+    defs,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlannedMount {
+    pub target: String,
+    /// `overlay`, `magic`, `hymo` or `bind`.
+    pub method: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MountChange {
+    pub target: String,
+    pub before: String,
+    pub after: String,
+}
+
+/// The mounts of one module before and after the updates.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModulePlanDiff {
+    pub id: String,
+    /// Absent for a module whose mounts change only because of another
+    /// module's update.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update: Option<PendingUpdate>,
+    pub added: Vec<PlannedMount>,
+    pub removed: Vec<PlannedMount>,
+    pub changed: Vec<MountChange>,
+}
+
+impl ModulePlanDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Where the planner reads a module's content from for `storage_root`.
+fn content_path(module: &Module, storage_root: &Path) -> PathBuf {
+    let stored = storage_root.join(&module.id);
+    if stored.exists() {
+        stored
+    } else {
+        module.source_path.clone()
+    }
+}
+
+/// Mount target to method for every module in `plan`. A magic mounted
+/// module is listed with the partitions it covers that no other method
+/// already mounts for it.
+pub fn module_mounts(
+    plan: &MountPlan,
+    modules: &[Module],
+    storage_root: &Path,
+) -> BTreeMap<String, BTreeMap<String, String>> {
+    let mut mounts: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+    let contents: Vec<(&str, PathBuf)> = modules
+        .iter()
+        .map(|m| (m.id.as_str(), content_path(m, storage_root)))
+        .collect();
+
+    for op in &plan.overlay_ops {
+        for lowerdir in &op.lowerdirs {
+            if let Some((id, _)) = contents.iter().find(|(_, c)| lowerdir.starts_with(c)) {
+                mounts
+                    .entry(id.to_string())
+                    .or_default()
+                    .insert(op.target.clone(), "overlay".to_string());
+            }
+        }
+    }
+    for op in &plan.hymo_ops {
+        mounts
+            .entry(op.module_id.clone())
+            .or_default()
+            .insert(op.target.to_string_lossy().to_string(), "hymo".to_string());
+    }
+    for op in plan.bind_ops.iter().chain(&plan.apex_ops) {
+        mounts
+            .entry(op.module_id.clone())
+            .or_default()
+            .insert(op.target.to_string_lossy().to_string(), "bind".to_string());
+    }
+
+    for (id, content) in &contents {
+        if !plan.magic_module_ids.iter().any(|m| m == id) {
+            continue;
+        }
+        let Ok(entries) = fs::read_dir(content) else {
+            continue;
+        };
+        let module_mounts = mounts.entry(id.to_string()).or_default();
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !entry.path().is_dir()
+                || !(defs::BUILTIN_PARTITIONS.contains(&name.as_str())
+                    || plan.extra_partitions.contains(&name))
+            {
+                continue;
+            }
+            let target = format!("/{}", name);
+            if !module_mounts
+                .keys()
+                .any(|t| Path::new(t).starts_with(&target))
+            {
+                module_mounts.insert(target, "magic".to_string());
+            }
+        }
+    }
+
+    mounts
+}
+
+fn diff_module(
+    id: &str,
+    update: Option<PendingUpdate>,
+    before: &BTreeMap<String, String>,
+    after: &BTreeMap<String, String>,
+) -> ModulePlanDiff {
+    let planned = |(target, method): (&String, &String)| PlannedMount {
+        target: target.clone(),
+        method: method.clone(),
+    };
+
+    ModulePlanDiff {
+        id: id.to_string(),
+        update,
+        added: after
+            .iter()
+            .filter(|(t, _)| !before.contains_key(*t))
+            .map(planned)
+            .collect(),
+        removed: before
+            .iter()
+            .filter(|(t, _)| !after.contains_key(*t))
+            .map(planned)
+            .collect(),
+        changed: after
+            .iter()
+            .filter_map(|(target, method)| {
+                let old = before.get(target)?;
+                (old != method).then(|| MountChange {
+                    target: target.clone(),
+                    before: old.clone(),
+                    after: method.clone(),
+                })
+            })
+            .collect(),
+    }
+}
+
+/// Plans the modules as they are and as the pending updates leave them,
+/// both against the module directories as a dry run does. Lists every
+/// module with a pending update, and any other module whose mounts change.
+pub fn preview_with_probe(
+    config: &Config,
+    paths: &defs::Paths,
+    probe: &dyn SystemProbe,
+) -> Result<Vec<ModulePlanDiff>> {
+    let moduledir = &config.moduledir;
+    let mut pending = updates::pending(moduledir);
+
+    let current = inventory::scan_with_paths(moduledir, config, paths)?;
+    let current_plan = planner::generate_with_probe(config, &current, moduledir, probe)?;
+    let before = module_mounts(&current_plan, &current, moduledir);
+
+    // Planning against the update dir picks updated content where it
+    // exists and falls back to each module's own directory elsewhere.
+    let update_dir = updates::update_dir(moduledir);
+    let updated = updates::scan_updated_with_paths(moduledir, config, paths)?;
+    let updated_plan = planner::generate_with_probe(config, &updated, &update_dir, probe)?;
+    let after = module_mounts(&updated_plan, &updated, &update_dir);
+
+    let empty = BTreeMap::new();
+    let ids: BTreeSet<String> = before
+        .keys()
+        .chain(after.keys())
+        .chain(pending.keys())
+        .cloned()
+        .collect();
+    let diffs = ids
+        .into_iter()
+        .filter_map(|id| {
+            let diff = diff_module(
+                &id,
+                pending.remove(&id),
+                before.get(&id).unwrap_or(&empty),
+                after.get(&id).unwrap_or(&empty),
+            );
+            (diff.update.is_some() || !diff.is_empty()).then_some(diff)
+        })
+        .collect();
+
+    Ok(diffs)
+}
+
+pub fn preview(config: &Config) -> Result<Vec<ModulePlanDiff>> {
+    preview_with_probe(config, &defs::Paths::default(), &LiveSystem)
+}
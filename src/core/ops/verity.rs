@@ -0,0 +1,271 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! dm-verity status of the partitions the plan mounts on.
+//!
+//! The block device behind each partition is found in the mount table and,
+//! when it is a device-mapper node, named through
+//! `/sys/block/dm-N/dm/{name,uuid}`. Together with `ro.boot.veritymode` and
+//! `partition.<name>.verified` that tells whether verity is enforcing there.
+//! Everything read is kept as evidence so a wrong verdict can be traced back.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use procfs::process::Process;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    foreign,
+    planner::{DiagnosticLevel, MountPlan},
+};
+
+/// Where the kernel lists block devices.
+pub const SYS_BLOCK: &str = "/sys/block";
+
+const VERITYMODE_PROP: &str = "ro.boot.veritymode";
+const VERIFIEDBOOTSTATE_PROP: &str = "ro.boot.verifiedbootstate";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VerityState {
+    Enforcing,
+    /// No verity target, or verity only logging or disabled.
+    NotEnforcing,
+    Unknown,
+}
+
+/// What was read for one partition. Missing values stay `None`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct VerityEvidence {
+    pub partition: String,
+    /// Mount point of the block mount holding the partition; `/` for
+    /// system-as-root.
+    pub mount_point: Option<PathBuf>,
+    /// Mount source, e.g. `/dev/block/dm-3`.
+    pub device: Option<String>,
+    pub fs_type: Option<String>,
+    /// `/sys/block/dm-N/dm/name`, e.g. `system-verity`.
+    pub dm_name: Option<String>,
+    pub dm_uuid: Option<String>,
+    /// The verity properties that were set, as read.
+    pub properties: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerityCheck {
+    pub state: VerityState,
+    /// The plan puts an overlay on this partition.
+    pub overlaid: bool,
+    pub evidence: VerityEvidence,
+}
+
+/// Parses `getprop` output, one `[key]: [value]` per line.
+pub fn parse_getprop(output: &str) -> BTreeMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once("]: [")?;
+            let key = key.strip_prefix('[')?;
+            let value = value.strip_suffix(']')?;
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Resolves the dm name and uuid of `device`, given as `/dev/block/dm-N`
+/// or `/dev/block/mapper/<name>`.
+fn dm_identity(device: &str, sys_block: &Path) -> (Option<String>, Option<String>) {
+    let Some(base) = Path::new(device).file_name().and_then(|n| n.to_str()) else {
+        return (None, None);
+    };
+
+    if base.starts_with("dm-") {
+        let dm = sys_block.join(base).join("dm");
+        return (
+            read_trimmed(&dm.join("name")),
+            read_trimmed(&dm.join("uuid")),
+        );
+    }
+
+    if device.contains("/mapper/") {
+        let uuid = fs::read_dir(sys_block).ok().and_then(|entries| {
+            entries.flatten().find_map(|entry| {
+                let dm = entry.path().join("dm");
+                (read_trimmed(&dm.join("name")).as_deref() == Some(base))
+                    .then(|| read_trimmed(&dm.join("uuid")))
+                    .flatten()
+            })
+        });
+        return (Some(base.to_string()), uuid);
+    }
+
+    (None, None)
+}
+
+/// Gathers the evidence for `partition`. `mounts` yields `(mount point, fs
+/// type, source)`; the deepest block mount at or above `/<partition>` wins,
+/// so overlays stacked on top are looked through.
+pub fn gather(
+    partition: &str,
+    mounts: &[(PathBuf, String, String)],
+    sys_block: &Path,
+    properties: &BTreeMap<String, String>,
+) -> VerityEvidence {
+    let target = Path::new("/").join(partition);
+    let block = mounts
+        .iter()
+        .filter(|(_, _, source)| source.starts_with("/dev/"))
+        .filter(|(mount_point, _, _)| target.starts_with(mount_point))
+        .fold(None::<&(PathBuf, String, String)>, |best, m| match best {
+            Some(b) if b.0.as_os_str().len() > m.0.as_os_str().len() => Some(b),
+            _ => Some(m),
+        });
+
+    let (dm_name, dm_uuid) = block
+        .map(|(_, _, source)| dm_identity(source, sys_block))
+        .unwrap_or_default();
+
+    let verified_prop = format!("partition.{}.verified", partition);
+    let properties = [VERITYMODE_PROP, VERIFIEDBOOTSTATE_PROP, &verified_prop]
+        .into_iter()
+        .filter_map(|key| Some((key.to_string(), properties.get(key)?.clone())))
+        .collect();
+
+    VerityEvidence {
+        partition: partition.to_string(),
+        mount_point: block.map(|m| m.0.clone()),
+        device: block.map(|m| m.2.clone()),
+        fs_type: block.map(|m| m.1.clone()),
+        dm_name,
+        dm_uuid,
+        properties,
+    }
+}
+
+/// Decides the verity state from `evidence`. A `-verity` dm target, a
+/// `VERITY` dm uuid or a set `partition.<name>.verified` mean verity is
+/// active; `ro.boot.veritymode` of `logging` or `disabled` turns it off.
+pub fn assess(evidence: &VerityEvidence) -> VerityState {
+    let verified = evidence
+        .properties
+        .get(&format!("partition.{}.verified", evidence.partition))
+        .is_some_and(|v| !v.is_empty() && v != "0");
+    let dm_verity = evidence
+        .dm_name
+        .as_deref()
+        .is_some_and(|n| n.ends_with("-verity"))
+        || evidence
+            .dm_uuid
+            .as_deref()
+            .is_some_and(|u| u.contains("VERITY"));
+    let mode = evidence.properties.get(VERITYMODE_PROP).map(String::as_str);
+
+    if verified || dm_verity {
+        return match mode {
+            Some("logging" | "disabled") => VerityState::NotEnforcing,
+            _ => VerityState::Enforcing,
+        };
+    }
+
+    let Some(device) = evidence.device.as_deref() else {
+        return VerityState::Unknown;
+    };
+    let is_dm = device.contains("/mapper/")
+        || Path::new(device)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.starts_with("dm-"));
+    if is_dm && evidence.dm_name.is_none() {
+        VerityState::Unknown
+    } else {
+        VerityState::NotEnforcing
+    }
+}
+
+impl VerityCheck {
+    /// Critical when an overlay goes on an enforcing partition that is not
+    /// ext4, Info otherwise, including when the state is unknown.
+    pub fn level(&self) -> DiagnosticLevel {
+        let ext4 = self.evidence.fs_type.as_deref() == Some("ext4");
+        match self.state {
+            VerityState::Enforcing if self.overlaid && !ext4 => DiagnosticLevel::Critical,
+            _ => DiagnosticLevel::Info,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        let e = &self.evidence;
+        let device = match (&e.device, &e.dm_name) {
+            (Some(device), Some(name)) => format!("{} '{}'", device, name),
+            (Some(device), None) => device.clone(),
+            _ => "no block device found".to_string(),
+        };
+        let fs_type = e.fs_type.as_deref().unwrap_or("unknown fs");
+        let head = format!("/{} ({} on {})", e.partition, fs_type, device);
+        match (self.state, self.level()) {
+            (_, DiagnosticLevel::Critical) => format!(
+                "{}: verity is enforcing and the plan overlays a non-ext4 partition, which is known to fail verification",
+                head
+            ),
+            (VerityState::Enforcing, _) => format!("{}: verity is enforcing", head),
+            (VerityState::NotEnforcing, _) => format!("{}: verity is not enforcing", head),
+            (VerityState::Unknown, _) => format!("{}: verity status could not be determined", head),
+        }
+    }
+}
+
+/// Reads every system property; empty when `getprop` is unavailable.
+fn read_properties() -> BTreeMap<String, String> {
+    match Command::new("getprop").output() {
+        Ok(output) if output.status.success() => {
+            parse_getprop(&String::from_utf8_lossy(&output.stdout))
+        }
+        Ok(_) | Err(_) => {
+            log::debug!("getprop unavailable, verity properties not read");
+            BTreeMap::new()
+        }
+    }
+}
+
+/// Checks every partition the plan mounts on against the live system.
+pub fn scan(plan: &MountPlan) -> Vec<VerityCheck> {
+    let mounts: Vec<(PathBuf, String, String)> = match Process::myself().and_then(|p| p.mountinfo())
+    {
+        Ok(mounts) => mounts
+            .into_iter()
+            .map(|m| (m.mount_point, m.fs_type, m.mount_source.unwrap_or_default()))
+            .collect(),
+        Err(e) => {
+            log::warn!("Cannot read mountinfo to check verity: {}", e);
+            Vec::new()
+        }
+    };
+    let properties = read_properties();
+
+    foreign::plan_partitions(plan)
+        .into_iter()
+        .map(|partition| {
+            let evidence = gather(&partition, &mounts, Path::new(SYS_BLOCK), &properties);
+            VerityCheck {
+                state: assess(&evidence),
+                overlaid: plan
+                    .overlay_ops
+                    .iter()
+                    .any(|op| op.partition_name == partition),
+                evidence,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,198 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Last-resort repairs for a device that no longer boots, run from recovery
+//! or `adb shell`.
+//!
+//! Nothing here reads `config.toml` or `daemon_state.json`, so either may be
+//! corrupt, and nothing mounts or unmounts: every action is a plain file
+//! operation on paths given up front.
+
+use std::{
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+
+use crate::{defs, utils};
+
+/// The files the rescue actions touch.
+#[derive(Debug, Clone)]
+pub struct RescueTargets {
+    pub modules_dir: PathBuf,
+    /// The ext4 image; its erofs sibling is derived from it.
+    pub image: PathBuf,
+    pub run_dir: PathBuf,
+}
+
+impl Default for RescueTargets {
+    fn default() -> Self {
+        Self {
+            modules_dir: PathBuf::from(defs::MODULES_DIR),
+            image: PathBuf::from(defs::MODULES_IMG_FILE),
+            run_dir: PathBuf::from(defs::RUN_DIR),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RescueAction {
+    /// Creates `disable` in every module directory.
+    DisableAll,
+    Disable(String),
+    /// Deletes `modules.img` and the `modules.erofs` built next to it.
+    RemoveImages,
+    /// Empties the run directory.
+    ClearState,
+}
+
+impl RescueAction {
+    /// The question asked before the action runs.
+    pub fn prompt(&self, targets: &RescueTargets) -> String {
+        match self {
+            Self::DisableAll => {
+                format!("Disable every module in {}?", targets.modules_dir.display())
+            }
+            Self::Disable(id) => format!("Disable module {}?", id),
+            Self::RemoveImages => format!(
+                "Delete {} and {}?",
+                targets.image.display(),
+                erofs_image(&targets.image).display()
+            ),
+            Self::ClearState => format!("Delete everything in {}?", targets.run_dir.display()),
+        }
+    }
+}
+
+fn erofs_image(image: &Path) -> PathBuf {
+    image.with_extension("erofs")
+}
+
+fn remove_entry(path: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+fn disable(module_dir: &Path) -> Result<bool> {
+    let flag = module_dir.join(defs::DISABLE_FILE_NAME);
+    if flag.exists() {
+        return Ok(false);
+    }
+    fs::write(&flag, b"").with_context(|| format!("Failed to create {}", flag.display()))?;
+    Ok(true)
+}
+
+/// Runs one action and describes what changed, one line per change.
+fn perform(action: &RescueAction, targets: &RescueTargets) -> Result<Vec<String>> {
+    let mut done = Vec::new();
+    match action {
+        RescueAction::DisableAll => {
+            let entries = fs::read_dir(&targets.modules_dir)
+                .with_context(|| format!("Failed to read {}", targets.modules_dir.display()))?;
+            let mut dirs: Vec<PathBuf> = entries
+                .flatten()
+                .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+                .map(|e| e.path())
+                .collect();
+            dirs.sort();
+            for dir in dirs {
+                let id = dir.file_name().unwrap_or_default().to_string_lossy();
+                match disable(&dir) {
+                    Ok(true) => done.push(format!("disabled {}", id)),
+                    Ok(false) => done.push(format!("{} was already disabled", id)),
+                    Err(e) => done.push(format!("FAILED to disable {}: {:#}", id, e)),
+                }
+            }
+        }
+        RescueAction::Disable(id) => {
+            utils::validate_module_id(id)?;
+            let dir = targets.modules_dir.join(id);
+            if !dir.is_dir() {
+                bail!(
+                    "module {} not found in {}",
+                    id,
+                    targets.modules_dir.display()
+                );
+            }
+            done.push(if disable(&dir)? {
+                format!("disabled {}", id)
+            } else {
+                format!("{} was already disabled", id)
+            });
+        }
+        RescueAction::RemoveImages => {
+            for image in [targets.image.clone(), erofs_image(&targets.image)] {
+                match fs::remove_file(&image) {
+                    Ok(()) => done.push(format!("deleted {}", image.display())),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        done.push(format!("{} did not exist", image.display()))
+                    }
+                    Err(e) => bail!("Failed to delete {}: {}", image.display(), e),
+                }
+            }
+        }
+        RescueAction::ClearState => {
+            let entries = match fs::read_dir(&targets.run_dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    done.push(format!("{} did not exist", targets.run_dir.display()));
+                    return Ok(done);
+                }
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("Failed to read {}", targets.run_dir.display()));
+                }
+            };
+            // The pinned mount namespace lives in the run directory; rescue
+            // does not unmount, so mount points are left for a reboot.
+            let run_dev = fs::metadata(&targets.run_dir).map(|m| m.dev()).ok();
+            let mut removed = 0;
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if fs::symlink_metadata(&path).is_ok_and(|m| Some(m.dev()) != run_dev) {
+                    done.push(format!("kept {}: it is a mount point", path.display()));
+                    continue;
+                }
+                match remove_entry(&path) {
+                    Ok(()) => removed += 1,
+                    Err(e) => done.push(format!("FAILED to delete {}: {}", path.display(), e)),
+                }
+            }
+            done.push(format!(
+                "deleted {} entr{} in {}",
+                removed,
+                if removed == 1 { "y" } else { "ies" },
+                targets.run_dir.display()
+            ));
+        }
+    }
+    Ok(done)
+}
+
+/// Runs `actions` in order, each only once `confirm` accepts its prompt, and
+/// returns the plain-text summary. A failed action is reported and does not
+/// stop the ones after it.
+pub fn run(
+    actions: &[RescueAction],
+    targets: &RescueTargets,
+    confirm: &mut dyn FnMut(&str) -> bool,
+) -> Vec<String> {
+    let mut summary = Vec::new();
+    for action in actions {
+        let prompt = action.prompt(targets);
+        if !confirm(&prompt) {
+            summary.push(format!("skipped: {}", prompt));
+            continue;
+        }
+        match perform(action, targets) {
+            Ok(done) => summary.extend(done),
+            Err(e) => summary.push(format!("FAILED: {} {:#}", prompt, e)),
+        }
+    }
+    summary
+}
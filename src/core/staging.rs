@@ -0,0 +1,221 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::Read,
+    os::unix::fs::{PermissionsExt, symlink},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use serde::{Deserialize, Serialize};
+use zip::ZipArchive;
+
+use crate::{core::inventory::model::ModuleProp, utils};
+
+const INDEX_FILE: &str = "staged.json";
+/// Symlink targets are short paths; anything larger is not a real link.
+const MAX_LINK_TARGET: u64 = 4096;
+
+/// A module zip extracted into the staging directory. Staged modules take
+/// the place of installed modules with the same id on the next mount.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StagedModule {
+    pub id: String,
+    pub version: String,
+    pub source: String,
+    pub staged_at: u64,
+}
+
+fn index_path(staging_dir: &Path) -> PathBuf {
+    staging_dir.join(INDEX_FILE)
+}
+
+/// Staged modules recorded in `staging_dir`, sorted by id. A missing or
+/// unreadable index yields an empty list.
+pub fn list(staging_dir: &Path) -> Vec<StagedModule> {
+    let Ok(raw) = fs::read_to_string(index_path(staging_dir)) else {
+        return Vec::new();
+    };
+
+    match serde_json::from_str::<Vec<StagedModule>>(&raw) {
+        Ok(mut staged) => {
+            staged.sort_by(|a, b| a.id.cmp(&b.id));
+            staged
+        }
+        Err(e) => {
+            log::warn!("Ignoring malformed staging index: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+fn save(staging_dir: &Path, staged: &[StagedModule]) -> Result<()> {
+    let json = serde_json::to_string_pretty(staged)?;
+    utils::atomic_write(index_path(staging_dir), json).context("Failed to write staging index")
+}
+
+/// Directories of staged modules that are still present, keyed by id.
+pub fn staged_dirs(staging_dir: &Path) -> HashMap<String, PathBuf> {
+    list(staging_dir)
+        .into_iter()
+        .filter_map(|m| {
+            let dir = staging_dir.join(&m.id);
+            dir.is_dir().then_some((m.id, dir))
+        })
+        .collect()
+}
+
+/// Extracts every entry of `archive` below `dest`, keeping unix modes and
+/// symlinks. Entries that would land outside `dest` abort the extraction.
+/// Symlinks are created last so no entry can be written through one.
+fn extract<R: Read + std::io::Seek>(archive: &mut ZipArchive<R>, dest: &Path) -> Result<()> {
+    let mut links = Vec::new();
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let Some(rel) = entry.enclosed_name() else {
+            bail!("Refusing unsafe path in module zip: {}", entry.name());
+        };
+        let out = dest.join(&rel);
+
+        if entry.is_symlink() {
+            ensure!(
+                entry.size() <= MAX_LINK_TARGET,
+                "Symlink target too long: {}",
+                entry.name()
+            );
+            let mut target = String::new();
+            entry
+                .read_to_string(&mut target)
+                .with_context(|| format!("Failed to read symlink {}", entry.name()))?;
+            links.push((out, PathBuf::from(target)));
+            continue;
+        }
+
+        if entry.is_dir() {
+            fs::create_dir_all(&out)?;
+        } else {
+            if let Some(parent) = out.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut file = fs::File::create(&out)
+                .with_context(|| format!("Failed to create {}", out.display()))?;
+            std::io::copy(&mut entry, &mut file)
+                .with_context(|| format!("Failed to extract {}", entry.name()))?;
+        }
+
+        if let Some(mode) = entry.unix_mode() {
+            fs::set_permissions(&out, fs::Permissions::from_mode(mode & 0o7777))?;
+        }
+    }
+
+    for (out, target) in links {
+        if let Some(parent) = out.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        symlink(&target, &out)
+            .with_context(|| format!("Failed to create symlink {}", out.display()))?;
+    }
+
+    Ok(())
+}
+
+fn read_module_id(prop_path: &Path) -> Result<String> {
+    let content = fs::read_to_string(prop_path).context("Module zip has no module.prop")?;
+
+    let id = content
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("id="))
+        .map(|id| id.trim().to_string())
+        .context("module.prop does not declare an id")?;
+
+    utils::validate_module_id(&id)?;
+    Ok(id)
+}
+
+/// Extracts the module zip at `zip_path` to `staging_dir/<id>` and records
+/// it in the staging index, replacing an earlier staged copy of the module.
+pub fn stage(zip_path: &Path, staging_dir: &Path) -> Result<StagedModule> {
+    let file = fs::File::open(zip_path)
+        .with_context(|| format!("Failed to open {}", zip_path.display()))?;
+    let mut archive = ZipArchive::new(file)
+        .with_context(|| format!("{} is not a valid zip archive", zip_path.display()))?;
+
+    utils::ensure_dir_exists(staging_dir)?;
+
+    let tmp_dir = staging_dir.join(".incoming");
+    if tmp_dir.exists() {
+        fs::remove_dir_all(&tmp_dir)?;
+    }
+    fs::create_dir_all(&tmp_dir)?;
+
+    let prepared = extract(&mut archive, &tmp_dir).and_then(|_| {
+        let prop_path = tmp_dir.join("module.prop");
+        let id = read_module_id(&prop_path)?;
+        Ok((id, ModuleProp::from(prop_path.as_path()).version))
+    });
+
+    let (id, version) = match prepared {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            let _ = fs::remove_dir_all(&tmp_dir);
+            return Err(e);
+        }
+    };
+
+    let module_dir = staging_dir.join(&id);
+    if module_dir.exists() {
+        fs::remove_dir_all(&module_dir)
+            .with_context(|| format!("Failed to replace staged module {}", id))?;
+    }
+    fs::rename(&tmp_dir, &module_dir)?;
+
+    let entry = StagedModule {
+        id: id.clone(),
+        version,
+        source: zip_path.to_string_lossy().to_string(),
+        staged_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    };
+
+    let mut staged = list(staging_dir);
+    staged.retain(|m| m.id != id);
+    staged.push(entry.clone());
+    save(staging_dir, &staged)?;
+
+    log::info!("Staged module {} for next boot", id);
+
+    Ok(entry)
+}
+
+/// Removes a staged module so the installed copy (if any) is used again.
+pub fn unstage(id: &str, staging_dir: &Path) -> Result<()> {
+    utils::validate_module_id(id)?;
+
+    let mut staged = list(staging_dir);
+    let before = staged.len();
+    staged.retain(|m| m.id != id);
+
+    let module_dir = staging_dir.join(id);
+    ensure!(
+        staged.len() != before || module_dir.exists(),
+        "Module {} is not staged",
+        id
+    );
+
+    if module_dir.exists() {
+        fs::remove_dir_all(&module_dir)
+            .with_context(|| format!("Failed to remove staged module {}", id))?;
+    }
+    save(staging_dir, &staged)?;
+
+    log::info!("Unstaged module {}", id);
+
+    Ok(())
+}
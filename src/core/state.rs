@@ -2,15 +2,69 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::{
+    collections::{BTreeMap, HashMap, HashSet},
     fs,
-    path::PathBuf,
+    path::{Path, PathBuf},
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::Result;
+use procfs::process::{MountInfo, Process};
 use serde::{Deserialize, Serialize};
 
-use crate::defs;
+use crate::{
+    conf::config::NamespaceMode,
+    core::{
+        failure::FailureRecord,
+        integrity::IntegrityReport,
+        ops::{executor::PartitionStats, foreign::ForeignMount, sync::SyncSummary},
+        storage::{self, DataWait, ImageRecord, TmpfsReport},
+    },
+    defs,
+    sys::{
+        denylist::DenylistProvider,
+        namespace::{self, NamespaceReport},
+        priority::PriorityReport,
+        root_backend::{self, Backend},
+    },
+    utils::KernelFeatures,
+};
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BootResult {
+    #[default]
+    Unknown,
+    Mounted,
+    SafeMode,
+    /// SIGTERM or SIGINT stopped the run; only the recorded mounts exist.
+    Cancelled,
+    /// The run failed; `failure` says in which phase.
+    Failed,
+}
+
+/// Where the modules mounted this boot were taken from.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanSource {
+    /// A fresh scan of the module directory.
+    #[default]
+    Scan,
+    /// The plan recorded by the last completed run.
+    LastGood,
+}
+
+/// When a module was mounted.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MountPhase {
+    /// By the boot-time run.
+    Boot,
+    /// Held back by `defer_ce_modules` and not mounted yet.
+    Pending,
+    /// By `mount-deferred`, after the device was unlocked.
+    Deferred,
+}
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct RuntimeState {
@@ -21,6 +75,11 @@ pub struct RuntimeState {
     pub overlay_modules: Vec<String>,
     pub magic_modules: Vec<String>,
     #[serde(default)]
+    pub hymo_modules: Vec<String>,
+    /// Modules mounted file by file with bind mounts.
+    #[serde(default)]
+    pub bind_modules: Vec<String>,
+    #[serde(default)]
     pub active_mounts: Vec<String>,
     #[serde(default)]
     pub storage_total: u64,
@@ -28,8 +87,179 @@ pub struct RuntimeState {
     pub storage_used: u64,
     #[serde(default)]
     pub storage_percent: u8,
+    /// The denylist provider unmounts module mounts on its own.
+    #[serde(default, alias = "zygisksu_enforce")]
+    pub denylist_enforce: bool,
+    #[serde(default)]
+    pub denylist_provider: DenylistProvider,
+    /// Builder that packed the EROFS image (`bundled` or `external`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_builder: Option<String>,
+    /// Why a module was mounted differently than planned, keyed by module id.
+    #[serde(default)]
+    pub fallback_reasons: HashMap<String, String>,
+    /// Module files not mounted this boot because the stock file was in use.
+    #[serde(default)]
+    pub busy_skipped: Vec<PathBuf>,
+    /// Mounts other managers had already made on our partitions this boot.
+    #[serde(default)]
+    pub foreign_mounts: Vec<ForeignMount>,
+    /// The kernel answered SUSFS `sus_path` requests this boot.
+    #[serde(default)]
+    pub susfs_active: bool,
+    /// Paths hidden through SUSFS `sus_path` this boot.
+    #[serde(default)]
+    pub susfs_hidden: Vec<PathBuf>,
+    #[serde(default)]
+    pub sync_summary: SyncSummary,
+    #[serde(default)]
+    pub pending_changes: usize,
+    #[serde(default)]
+    pub per_partition: HashMap<String, PartitionStats>,
+    #[serde(default)]
+    pub boot_count: u64,
     #[serde(default)]
-    pub zygisksu_enforce: bool,
+    pub last_result: BootResult,
+    /// Partitions mounted with a persistent upperdir.
+    #[serde(default)]
+    pub rw_partitions: Vec<String>,
+    /// Set when repeated unfinished mount attempts made this boot skip modules.
+    #[serde(default)]
+    pub safe_mode: bool,
+    #[serde(default)]
+    pub plan_source: PlanSource,
+    /// Config profile applied this boot.
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Priority the storage and sync phases ran at.
+    #[serde(default)]
+    pub boot_priority: PriorityReport,
+    /// APEXes module files were bound into under `allow_apex`.
+    #[serde(default)]
+    pub apex: Vec<String>,
+    /// When each mounted or held-back module was mounted.
+    #[serde(default)]
+    pub mount_phase: BTreeMap<String, MountPhase>,
+    /// Size of the worker pool this boot.
+    #[serde(default)]
+    pub threads: usize,
+    /// Mount namespace the mounts were made in.
+    #[serde(default)]
+    pub namespace: NamespaceReport,
+    /// A mount timed out or the mount deadline forced an early finalize.
+    #[serde(default)]
+    pub degraded: bool,
+    /// Why storage could not be set up as configured, e.g. `data_ro`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub degraded_reason: Option<String>,
+    /// Storage setup waited for a read-only `/data`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_wait: Option<DataWait>,
+    /// Root implementation try_umount and sysfs nuking went through.
+    #[serde(default)]
+    pub root_backend: Backend,
+    /// Kernel and root environment probed during this boot.
+    #[serde(default)]
+    pub kernel_features: KernelFeatures,
+    /// Source string of every mount made this boot.
+    #[serde(default)]
+    pub mount_source: String,
+    /// Mount base used this boot; differs from the config when randomized.
+    #[serde(default)]
+    pub hybrid_mnt_dir: PathBuf,
+    /// Cap, usage and overflow decision of tmpfs storage, when it is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tmpfs: Option<TmpfsReport>,
+    /// Outcome of the `verify_storage` check, when it is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<IntegrityReport>,
+    /// Size, usage and last resize of the ext4 image, when one is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<ImageRecord>,
+    /// Loop device `modules.img` is attached to, for teardown.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loop_device: Option<u32>,
+    /// Exit code, phase and error of a failed run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<FailureRecord>,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PartitionHealth {
+    Mounted,
+    Missing,
+    Stale,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PartitionStatus {
+    pub partition: String,
+    pub status: PartitionHealth,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatusReport {
+    pub daemon_ran: bool,
+    pub healthy: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    pub storage_mode: String,
+    pub uptime_secs: u64,
+    pub overlay_count: usize,
+    pub magic_count: usize,
+    pub hymo_count: usize,
+    pub safe_mode: bool,
+    pub degraded: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub degraded_reason: Option<String>,
+    /// Storage setup waited for a read-only `/data`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_wait: Option<DataWait>,
+    pub namespace_mode: NamespaceMode,
+    pub partitions: Vec<PartitionStatus>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn boot_time_secs() -> Option<u64> {
+    let content = fs::read_to_string("/proc/stat").ok()?;
+
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))
+        .and_then(|v| v.trim().parse().ok())
+}
+
+/// Overlay mount points, limited to mounts made with `source` when known.
+fn overlay_mount_points(mounts: Vec<MountInfo>, source: &str) -> HashSet<PathBuf> {
+    mounts
+        .into_iter()
+        .filter(|m| m.fs_type == "overlay")
+        .filter(|m| source.is_empty() || m.mount_source.as_deref() == Some(source))
+        .map(|m| m.mount_point)
+        .collect()
+}
+
+/// The mount table of the namespace `report` says the mounts were made in,
+/// or why it cannot be read.
+fn namespace_mounts(report: &NamespaceReport) -> Result<Vec<MountInfo>, String> {
+    match (report.mode, &report.ns_path) {
+        (NamespaceMode::Global, _) => Ok(Process::myself()
+            .and_then(|p| p.mountinfo())
+            .map(|m| m.0)
+            .unwrap_or_default()),
+        (NamespaceMode::Clone, Some(path)) => namespace::mountinfo_in(path)
+            .map_err(|e| format!("cannot enter the mount namespace: {:#}", e)),
+        (NamespaceMode::Clone, None) => {
+            Err("the cloned mount namespace was not pinned and is gone".to_string())
+        }
+    }
 }
 
 impl RuntimeState {
@@ -39,8 +269,11 @@ impl RuntimeState {
         mount_point: PathBuf,
         overlay_modules: Vec<String>,
         magic_modules: Vec<String>,
+        hymo_modules: Vec<String>,
         active_mounts: Vec<String>,
         storage_info: (u64, u64, u8),
+        sync_summary: SyncSummary,
+        per_partition: HashMap<String, PartitionStats>,
     ) -> Self {
         let start = SystemTime::now();
 
@@ -51,8 +284,6 @@ impl RuntimeState {
 
         let pid = std::process::id();
 
-        let zygisksu_enforce = crate::utils::check_zygisksu_enforce_status();
-
         Self {
             timestamp,
             pid,
@@ -60,31 +291,209 @@ impl RuntimeState {
             mount_point,
             overlay_modules,
             magic_modules,
+            hymo_modules,
+            bind_modules: Vec::new(),
             active_mounts,
             storage_total: storage_info.0,
             storage_used: storage_info.1,
             storage_percent: storage_info.2,
-            zygisksu_enforce,
+            denylist_enforce: false,
+            denylist_provider: DenylistProvider::default(),
+            image_builder: None,
+            fallback_reasons: HashMap::new(),
+            busy_skipped: Vec::new(),
+            foreign_mounts: Vec::new(),
+            susfs_active: false,
+            susfs_hidden: Vec::new(),
+            sync_summary,
+            pending_changes: 0,
+            per_partition,
+            rw_partitions: Vec::new(),
+            boot_count: 0,
+            last_result: BootResult::default(),
+            safe_mode: false,
+            plan_source: PlanSource::default(),
+            profile: None,
+            boot_priority: PriorityReport::default(),
+            apex: Vec::new(),
+            mount_phase: BTreeMap::new(),
+            threads: crate::utils::pool::size(),
+            namespace: NamespaceReport::default(),
+            degraded: false,
+            degraded_reason: None,
+            data_wait: None,
+            root_backend: root_backend::active().backend(),
+            kernel_features: crate::utils::kernel_features().clone(),
+            mount_source: String::new(),
+            hybrid_mnt_dir: PathBuf::new(),
+            tmpfs: None,
+            integrity: None,
+            image: None,
+            loop_device: None,
+            failure: None,
+        }
+    }
+
+    /// The state of a run that failed, possibly before anything it would
+    /// normally record was known.
+    pub fn failed(boot_count: u64, failure: FailureRecord) -> Self {
+        Self {
+            timestamp: now_secs(),
+            pid: std::process::id(),
+            boot_count,
+            last_result: BootResult::Failed,
+            failure: Some(failure),
+            ..Default::default()
+        }
+    }
+
+    /// Records how storage setup dealt with a read-only `/data`.
+    pub fn record_data_wait(&mut self, wait: Option<DataWait>) {
+        self.data_wait = wait;
+        if wait.is_some_and(|wait| !wait.writable) {
+            self.degraded_reason = Some(storage::DATA_RO_REASON.to_string());
         }
     }
 
     pub fn save(&self) -> Result<()> {
-        let json = serde_json::to_string_pretty(self)?;
+        self.save_to(Path::new(defs::STATE_FILE))
+    }
 
-        fs::write(defs::STATE_FILE, json)?;
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
 
-        Ok(())
+        crate::utils::atomic_write(path, json)
     }
 
     pub fn load() -> Result<Self> {
-        if !std::path::Path::new(defs::STATE_FILE).exists() {
+        Self::load_from(Path::new(defs::STATE_FILE))
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        if !path.exists() {
             return Ok(Self::default());
         }
 
-        let content = fs::read_to_string(defs::STATE_FILE)?;
+        let content = fs::read_to_string(path)?;
 
         let state = serde_json::from_str(&content)?;
 
         Ok(state)
     }
+
+    pub fn check_health() -> StatusReport {
+        if !std::path::Path::new(defs::STATE_FILE).exists() {
+            return StatusReport {
+                daemon_ran: false,
+                healthy: false,
+                message: Some("daemon has not run".to_string()),
+                storage_mode: "unknown".to_string(),
+                uptime_secs: 0,
+                overlay_count: 0,
+                magic_count: 0,
+                hymo_count: 0,
+                safe_mode: false,
+                degraded: false,
+                degraded_reason: None,
+                data_wait: None,
+                namespace_mode: NamespaceMode::default(),
+                partitions: Vec::new(),
+            };
+        }
+
+        let state = match Self::load() {
+            Ok(state) => state,
+            Err(e) => {
+                return StatusReport {
+                    daemon_ran: false,
+                    healthy: false,
+                    message: Some(format!("failed to load state file: {:#}", e)),
+                    storage_mode: "unknown".to_string(),
+                    uptime_secs: 0,
+                    overlay_count: 0,
+                    magic_count: 0,
+                    hymo_count: 0,
+                    safe_mode: false,
+                    degraded: false,
+                    degraded_reason: None,
+                    data_wait: None,
+                    namespace_mode: NamespaceMode::default(),
+                    partitions: Vec::new(),
+                };
+            }
+        };
+
+        let is_stale = boot_time_secs().is_some_and(|btime| state.timestamp < btime);
+        let (mounts, namespace_error) = match namespace_mounts(&state.namespace) {
+            Ok(mounts) => (mounts, None),
+            Err(e) => (Vec::new(), Some(e)),
+        };
+        let mounted = overlay_mount_points(mounts, &state.mount_source);
+
+        let partitions: Vec<PartitionStatus> = state
+            .active_mounts
+            .iter()
+            .map(|partition| {
+                let root = PathBuf::from("/").join(partition);
+                let status = if is_stale {
+                    PartitionHealth::Stale
+                } else if mounted.iter().any(|p| p.starts_with(&root)) {
+                    PartitionHealth::Mounted
+                } else {
+                    PartitionHealth::Missing
+                };
+
+                PartitionStatus {
+                    partition: partition.clone(),
+                    status,
+                }
+            })
+            .collect();
+
+        let healthy = !is_stale
+            && namespace_error.is_none()
+            && !state.safe_mode
+            && !state.degraded
+            && state.degraded_reason.is_none()
+            && partitions
+                .iter()
+                .all(|p| p.status == PartitionHealth::Mounted);
+
+        StatusReport {
+            daemon_ran: true,
+            healthy,
+            message: if is_stale {
+                Some("state file predates current boot".to_string())
+            } else if let Some(e) = namespace_error {
+                Some(e)
+            } else if state.safe_mode {
+                Some("safe mode: modules skipped after repeated unfinished mounts".to_string())
+            } else if state.degraded {
+                Some("degraded: mounts timed out or were cut off by the deadline".to_string())
+            } else if let Some(wait) = state.data_wait.filter(|wait| !wait.writable) {
+                Some(format!(
+                    "degraded: /data stayed read-only for {} ms, tmpfs storage was used",
+                    wait.waited_ms
+                ))
+            } else {
+                state.data_wait.map(|wait| {
+                    format!(
+                        "started {} ms late waiting for /data to become writable",
+                        wait.waited_ms
+                    )
+                })
+            },
+            storage_mode: state.storage_mode,
+            uptime_secs: now_secs().saturating_sub(state.timestamp),
+            overlay_count: state.overlay_modules.len(),
+            magic_count: state.magic_modules.len(),
+            hymo_count: state.hymo_modules.len(),
+            safe_mode: state.safe_mode,
+            degraded: state.degraded,
+            degraded_reason: state.degraded_reason,
+            data_wait: state.data_wait,
+            namespace_mode: state.namespace.mode,
+            partitions,
+        }
+    }
 }
@@ -3,33 +3,109 @@
 
 use std::{
     fs,
-    os::unix::fs::PermissionsExt,
+    os::unix::fs::{MetadataExt, PermissionsExt},
     path::{Path, PathBuf},
     process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{Context, Result, bail, ensure};
 use jwalk::WalkDir;
-use rustix::mount::{MountPropagationFlags, UnmountFlags, mount_change, unmount as umount};
-use serde::Serialize;
+use rustix::mount::{
+    MountFlags, MountPropagationFlags, UnmountFlags, mount, mount_change, unmount as umount,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 #[cfg(any(target_os = "linux", target_os = "android"))]
 use crate::mount::umount_mgr::send_umountable;
 use crate::{
-    core::state::RuntimeState,
+    conf::config::{Config, StorageConfig, TmpfsOverflow},
+    core::{
+        bundles::{self, Bundle},
+        hashcache,
+        image_builder::{self, ImageBuilder},
+        integrity::IntegrityReport,
+        inventory::Module,
+        ops::{
+            identical,
+            planner::{DiagnosticIssue, DiagnosticLevel},
+            sync::ModuleUsage,
+        },
+        state::RuntimeState,
+    },
     defs,
     mount::overlayfs::utils as overlay_utils,
-    sys::mount::is_mounted,
+    sys::{
+        loopdev::{self, LoopDevice, LoopOptions},
+        mount::is_mounted,
+    },
     utils::{self, ensure_dir_exists, lsetfilecon},
 };
 
 const DEFAULT_SELINUX_CONTEXT: &str = "u:object_r:system_file:s0";
+const MKFS_EXT4_SEARCH_DIRS: &[&str] = &["/system/bin", "/vendor/bin", "/data/adb/ksu/bin"];
+const MIB: u64 = 1024 * 1024;
+/// Smallest image ever created, whatever `storage.image_overhead_mb` says.
+const MIN_IMAGE_SIZE: u64 = 16 * MIB;
+/// Usage below which `auto_shrink` trims the image.
+const SHRINK_BELOW_PERCENT: u8 = 40;
+const ZRAM_CONTROL_DIR: &str = "/sys/class/zram-control";
+/// File created and removed to check that `/data` takes writes.
+const DATA_RW_PROBE_FILE: &str = ".rw_probe";
+const DATA_RW_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// `degraded_reason` of a boot that found `/data` read-only throughout.
+pub const DATA_RO_REASON: &str = "data_ro";
 
 pub struct StorageHandle {
     pub mount_point: PathBuf,
     pub mode: String,
     pub backing_image: Option<PathBuf>,
     pub final_target: Option<PathBuf>,
+    pub manifest: Option<String>,
+    pub force_rebuild: bool,
+    pub zram_device: Option<u32>,
+    /// Loop device `modules.img` is attached to.
+    pub loop_device: Option<u32>,
+    /// Packs the staged tree into the EROFS image on commit.
+    pub image_builder: Option<Box<dyn ImageBuilder>>,
+    /// The ext4 image was created or resized by this run.
+    pub image_sized: bool,
+    /// Set when `/data` was read-only as setup started.
+    pub data_wait: Option<DataWait>,
+    /// Cap, usage and overflow decision of tmpfs storage.
+    pub tmpfs: Option<TmpfsReport>,
+    /// Outcome of the `verify_storage` check of the module copies.
+    pub integrity: Option<IntegrityReport>,
+    /// `image_modules` mounted for this run.
+    pub bundles: Vec<Bundle>,
+}
+
+/// How tmpfs storage fared against `tmpfs_max_mb` this boot.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TmpfsReport {
+    /// 0 when no cap is set.
+    pub cap_bytes: u64,
+    /// Module content measured before storage setup.
+    pub estimated_bytes: u64,
+    /// Bytes in use on the tmpfs after sync.
+    pub used_bytes: u64,
+    /// The `tmpfs_overflow` policy applied because the modules exceeded
+    /// the cap.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overflow: Option<TmpfsOverflow>,
+    /// Modules the `trim` policy left out, largest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trimmed: Vec<String>,
+}
+
+/// How long [`setup`] waited for a read-only `/data`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DataWait {
+    pub waited_ms: u64,
+    /// `/data` became writable in time; otherwise tmpfs storage was used.
+    pub writable: bool,
 }
 
 impl StorageHandle {
@@ -45,8 +121,38 @@ impl StorageHandle {
                 .as_ref()
                 .context("EROFS final target missing")?;
 
-            create_erofs_image(&self.mount_point, image_path)
-                .context("Failed to pack EROFS image")?;
+            let manifest_path = erofs_manifest_path(image_path);
+            let staged_manifest = self.manifest.as_ref().map(|digest| {
+                format!(
+                    "{}:{}",
+                    digest,
+                    self.mount_point.join("magic_workspace").exists()
+                )
+            });
+
+            let up_to_date = !self.force_rebuild
+                && image_path.exists()
+                && staged_manifest.is_some()
+                && fs::read_to_string(&manifest_path).ok() == staged_manifest;
+
+            if up_to_date {
+                log::info!("EROFS image is up to date, skipping rebuild.");
+            } else {
+                let _ = fs::remove_file(&manifest_path);
+
+                let builder = self
+                    .image_builder
+                    .as_deref()
+                    .context("No EROFS image builder selected")?;
+                create_erofs_image(builder, &self.mount_point, image_path)
+                    .context("Failed to pack EROFS image")?;
+
+                if let Some(manifest) = &staged_manifest
+                    && let Err(e) = utils::atomic_write(&manifest_path, manifest)
+                {
+                    log::warn!("Failed to save EROFS manifest: {:#}", e);
+                }
+            }
 
             if let Err(e) = umount(&self.mount_point, UnmountFlags::DETACH) {
                 log::warn!("Failed to unmount staging tmpfs: {}", e);
@@ -77,17 +183,68 @@ impl StorageHandle {
 
         Ok(())
     }
+
+    /// Unmounts the storage and releases the zram or loop device backing it,
+    /// if any.
+    pub fn teardown(&mut self) -> Result<()> {
+        bundles::unmount_all(&mut self.bundles);
+
+        if is_mounted(&self.mount_point) {
+            umount(&self.mount_point, UnmountFlags::DETACH)
+                .with_context(|| format!("Failed to unmount {}", self.mount_point.display()))?;
+        }
+
+        if let Some(index) = self.zram_device.take() {
+            zram_hot_remove(index);
+        }
+
+        // An autoclear device is already gone once unmounted.
+        if let Some(number) = self.loop_device.take()
+            && let Err(e) = loopdev::detach_number(number)
+        {
+            log::debug!("{:#}", e);
+        }
+
+        Ok(())
+    }
 }
 
-#[derive(Serialize)]
-struct StorageStatus {
+#[derive(Debug, Serialize)]
+pub struct StorageStatus {
     #[serde(rename = "type")]
-    mode: String,
-    mount_point: String,
-    usage_percent: u8,
-    total_size: u64,
-    used_size: u64,
-    supported_modes: Vec<String>,
+    pub mode: String,
+    pub mount_point: String,
+    pub usage_percent: u8,
+    pub total_size: u64,
+    pub used_size: u64,
+    pub supported_modes: Vec<String>,
+    pub hymofs_available: bool,
+    pub dedup_saved_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zram: Option<ZramStatus>,
+    /// The tmpfs cap and what it made this boot do.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tmpfs: Option<TmpfsReport>,
+    /// Loop devices attached to images under the base directory.
+    pub loop_devices: Vec<LoopDevice>,
+    /// Per-module usage from the last sync, largest first.
+    pub modules: Vec<ModuleUsageEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModuleUsageEntry {
+    pub id: String,
+    pub size_bytes: u64,
+    #[serde(flatten)]
+    pub usage: ModuleUsage,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ZramStatus {
+    pub device: u32,
+    pub uncompressed_size: u64,
+    pub compressed_size: u64,
+    pub mem_used: u64,
 }
 
 pub fn get_usage(path: &Path) -> (u64, u64, u8) {
@@ -106,6 +263,8 @@ pub fn get_usage(path: &Path) -> (u64, u64, u8) {
     }
 }
 
+/// Bytes the files under `path` take up on disk. Holes in sparse files
+/// are not counted, as sync keeps them.
 fn calculate_total_size(path: &Path) -> Result<u64> {
     let mut total_size = 0;
     if path.is_dir() {
@@ -113,7 +272,7 @@ fn calculate_total_size(path: &Path) -> Result<u64> {
             let entry = entry?;
             let file_type = entry.file_type()?;
             if file_type.is_file() {
-                total_size += entry.metadata()?.len();
+                total_size += utils::allocated_size(&entry.metadata()?);
             } else if file_type.is_dir() {
                 total_size += calculate_total_size(&entry.path())?;
             }
@@ -122,6 +281,406 @@ fn calculate_total_size(path: &Path) -> Result<u64> {
     Ok(total_size)
 }
 
+fn compute_image_size(moduledir: &Path, sizing: &StorageConfig) -> Result<u64> {
+    let total_size = calculate_total_size(moduledir)?;
+    Ok(image_size(total_size, sizing))
+}
+
+/// Image size for `content` bytes of modules: the content plus
+/// `image_headroom_percent` of it or `image_overhead_mb`, whichever is more,
+/// in whole MiB.
+pub fn image_size(content: u64, sizing: &StorageConfig) -> u64 {
+    let headroom = content.saturating_mul(sizing.image_headroom_percent) / 100;
+    let free = headroom.max(sizing.image_overhead_mb.saturating_mul(MIB));
+    content
+        .saturating_add(free)
+        .max(MIN_IMAGE_SIZE)
+        .next_multiple_of(MIB)
+}
+
+/// Whether a filesystem of `total` bytes is too small for `required` bytes:
+/// less than half the free space [`image_size`] would give is left.
+pub fn needs_grow(total: u64, required: u64, sizing: &StorageConfig) -> bool {
+    let slack = image_size(required, sizing) - required;
+    total.saturating_sub(required) < slack / 2
+}
+
+/// What the runtime state records about the ext4 image.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageRecord {
+    pub size_bytes: u64,
+    pub used_bytes: u64,
+    /// When the image was last created or resized, in seconds since the
+    /// epoch; carried over from `previous` when this run left it alone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_resize: Option<u64>,
+}
+
+impl ImageRecord {
+    pub fn new(size_bytes: u64, used_bytes: u64, sized_now: bool, previous: Option<&Self>) -> Self {
+        let last_resize = if sized_now {
+            Some(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+            )
+        } else {
+            previous.and_then(|p| p.last_resize)
+        };
+        Self {
+            size_bytes,
+            used_bytes,
+            last_resize,
+        }
+    }
+}
+
+/// The record for the ext4 image behind `handle`; `None` for other backends.
+pub fn image_record(handle: &StorageHandle, previous: Option<&ImageRecord>) -> Option<ImageRecord> {
+    if handle.mode != "ext4" {
+        return None;
+    }
+    let size = fs::metadata(handle.backing_image.as_deref()?).ok()?.len();
+    let (_, used, _) = get_usage(&handle.mount_point);
+    Some(ImageRecord::new(size, used, handle.image_sized, previous))
+}
+
+/// Bytes the given modules occupy once synced into storage.
+pub fn required_bytes(modules: &[Module]) -> u64 {
+    modules
+        .iter()
+        .map(|m| calculate_total_size(&m.source_path).unwrap_or(0))
+        .sum()
+}
+
+/// Measures the module directory against `max_mb` for tmpfs storage. Over
+/// the cap, `fail` is an error and the other policies are recorded as the
+/// report's `overflow`; with `ext4` the caller sets up an image instead.
+pub fn check_tmpfs_cap(
+    moduledir: &Path,
+    max_mb: u64,
+    policy: TmpfsOverflow,
+) -> Result<TmpfsReport> {
+    let mut report = TmpfsReport {
+        cap_bytes: max_mb.saturating_mul(MIB),
+        ..Default::default()
+    };
+    if report.cap_bytes == 0 {
+        return Ok(report);
+    }
+
+    report.estimated_bytes = calculate_total_size(moduledir).unwrap_or(0);
+    if report.estimated_bytes <= report.cap_bytes {
+        return Ok(report);
+    }
+
+    ensure!(
+        policy != TmpfsOverflow::Fail,
+        "Modules need {} MiB, over tmpfs_max_mb={}",
+        report.estimated_bytes.div_ceil(MIB),
+        max_mb
+    );
+    log::warn!(
+        "!! Modules need {} MiB, over tmpfs_max_mb={}; applying tmpfs_overflow={:?}",
+        report.estimated_bytes.div_ceil(MIB),
+        max_mb,
+        policy
+    );
+    report.overflow = Some(policy);
+    Ok(report)
+}
+
+/// Ids of the largest modules to leave out so the rest of `sizes` fit in
+/// `cap` bytes, largest first. Ties go to the later id.
+pub fn trim_to_cap(sizes: &[(String, u64)], cap: u64) -> Vec<String> {
+    let mut by_size: Vec<&(String, u64)> = sizes.iter().collect();
+    by_size.sort_by(|a, b| b.1.cmp(&a.1).then(b.0.cmp(&a.0)));
+
+    let mut total: u64 = sizes.iter().map(|(_, size)| size).sum();
+    by_size
+        .into_iter()
+        .take_while(|(_, size)| {
+            let over = total > cap;
+            total -= size;
+            over
+        })
+        .map(|(id, _)| id.clone())
+        .collect()
+}
+
+/// Content bytes of each module, as [`required_bytes`] counts them.
+pub fn module_sizes(modules: &[Module]) -> Vec<(String, u64)> {
+    modules
+        .iter()
+        .map(|m| {
+            (
+                m.id.clone(),
+                calculate_total_size(&m.source_path).unwrap_or(0),
+            )
+        })
+        .collect()
+}
+
+/// Grows the mounted ext4 image when too little space would be left once
+/// `required_bytes` are in it (see [`needs_grow`]), and shrinks it when
+/// `auto_shrink` is set and usage is below 40%. Other backends are left
+/// alone. A failed resize keeps the original image mounted.
+pub fn ensure_capacity(
+    handle: &mut StorageHandle,
+    required_bytes: u64,
+    auto_shrink: bool,
+    sizing: &StorageConfig,
+    loop_options: &LoopOptions,
+) -> Result<()> {
+    let Some(img_path) = handle.backing_image.clone() else {
+        return Ok(());
+    };
+    if handle.mode != "ext4" {
+        return Ok(());
+    }
+
+    let (total, used, percent) = get_usage(&handle.mount_point);
+    if total == 0 {
+        return Ok(());
+    }
+
+    let image_len = fs::metadata(&img_path)
+        .with_context(|| format!("Failed to stat {}", img_path.display()))?
+        .len();
+    let needed = required_bytes.max(used);
+    let wanted = image_size(needed, sizing);
+
+    // Shrink only when it frees at least 16 MiB.
+    let new_len = if needs_grow(total, needed, sizing) {
+        image_len + wanted.saturating_sub(total)
+    } else if auto_shrink && percent < SHRINK_BELOW_PERCENT && total > wanted + 16 * MIB {
+        image_len.saturating_sub(total - wanted).max(MIN_IMAGE_SIZE)
+    } else {
+        return Ok(());
+    };
+    let new_len = new_len.next_multiple_of(MIB);
+    if new_len == image_len {
+        return Ok(());
+    }
+
+    log::info!(
+        ">> Resizing {}: {} -> {} bytes (fs total {}, used {}, required {})",
+        img_path.display(),
+        image_len,
+        new_len,
+        total,
+        used,
+        required_bytes
+    );
+
+    resize_image(handle, &img_path, image_len, new_len, loop_options)?;
+    handle.image_sized = true;
+
+    let (total_after, used_after, _) = get_usage(&handle.mount_point);
+    log::info!(
+        ">> Resized {}: fs total {} -> {} bytes, used {}",
+        img_path.display(),
+        total,
+        total_after,
+        used_after
+    );
+
+    Ok(())
+}
+
+/// Unmounts the storage, resizes the image behind it and mounts it again on
+/// a fresh loop device. The image is remounted even when resizing fails.
+fn resize_image(
+    handle: &mut StorageHandle,
+    img_path: &Path,
+    old_len: u64,
+    new_len: u64,
+    loop_options: &LoopOptions,
+) -> Result<()> {
+    let target = handle.mount_point.clone();
+    let target = target.as_path();
+    let resize2fs = find_e2fs_tool("resize2fs").with_context(|| {
+        format!(
+            "resize2fs not found (searched {} and PATH); cannot resize storage",
+            MKFS_EXT4_SEARCH_DIRS.join(", ")
+        )
+    })?;
+
+    umount(target, UnmountFlags::empty())
+        .with_context(|| format!("Failed to unmount {} for resize", target.display()))?;
+    if !loop_options.autoclear
+        && let Some(number) = handle.loop_device.take()
+        && let Err(e) = loopdev::detach_number(number)
+    {
+        log::warn!("{:#}", e);
+    }
+
+    let result = resize_unmounted(img_path, &resize2fs, old_len, new_len);
+    if let Err(e) = &result {
+        log::error!(
+            "Resize of {} failed, keeping {} bytes: {:#}",
+            img_path.display(),
+            old_len,
+            e
+        );
+    }
+
+    handle.loop_device = Some(
+        overlay_utils::mount_ext4(img_path, target, loop_options)
+            .with_context(|| format!("Failed to remount {} after resize", img_path.display()))?,
+    );
+    if let Err(e) = mount_change(target, MountPropagationFlags::PRIVATE) {
+        log::warn!("Failed to make storage private: {}", e);
+    }
+
+    result
+}
+
+fn resize_unmounted(img_path: &Path, resize2fs: &Path, old_len: u64, new_len: u64) -> Result<()> {
+    let file = fs::OpenOptions::new().write(true).open(img_path)?;
+
+    // Growing extends the file first; the filesystem still fits the old size,
+    // so the image stays mountable if resize2fs then fails.
+    if new_len > old_len {
+        file.set_len(new_len)
+            .with_context(|| format!("Failed to extend image to {} bytes", new_len))?;
+    }
+
+    check_image(img_path)?;
+
+    let output = Command::new(resize2fs)
+        .arg(img_path)
+        .arg(format!("{}K", new_len / 1024))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .with_context(|| format!("Failed to execute {}", resize2fs.display()))?;
+    if !output.status.success() {
+        bail!(
+            "resize2fs failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    // Shrinking truncates only after the filesystem has moved out of the tail.
+    if new_len < old_len {
+        file.set_len(new_len)
+            .with_context(|| format!("Failed to truncate image to {} bytes", new_len))?;
+    }
+
+    Ok(())
+}
+
+fn erofs_manifest_path(image_path: &Path) -> PathBuf {
+    let mut name = image_path.as_os_str().to_os_string();
+    name.push(".manifest");
+    PathBuf::from(name)
+}
+
+/// Digest of every enabled module tree (paths, sizes, modes and file
+/// contents) plus the module flags, used to decide whether the EROFS image
+/// must be rebuilt. Contents come from the hash cache, so an untouched tree
+/// is only `stat`ed. Staged modules are included so staging a new version
+/// forces a rebuild.
+fn compute_modules_manifest(moduledir: &Path, staging_dir: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+
+    let mut entries: Vec<_> = fs::read_dir(moduledir)?.flatten().collect();
+    entries.sort_by_key(|e| e.file_name());
+
+    if let Ok(staged) = fs::read_dir(staging_dir) {
+        let mut staged: Vec<_> = staged.flatten().collect();
+        staged.sort_by_key(|e| e.file_name());
+        entries.extend(staged);
+    }
+
+    for entry in entries {
+        let path = entry.path();
+        if !path.is_dir() {
+            continue;
+        }
+
+        let disabled = [
+            defs::DISABLE_FILE_NAME,
+            defs::REMOVE_FILE_NAME,
+            defs::SKIP_MOUNT_FILE_NAME,
+        ]
+        .iter()
+        .any(|flag| path.join(flag).exists());
+
+        hasher.update(entry.file_name().as_encoded_bytes());
+        hasher.update([disabled as u8]);
+
+        if disabled {
+            continue;
+        }
+
+        for file in walkdir::WalkDir::new(&path)
+            .min_depth(1)
+            .sort_by_file_name()
+            .into_iter()
+            .flatten()
+        {
+            let Ok(meta) = file.metadata() else {
+                continue;
+            };
+
+            if let Ok(rel) = file.path().strip_prefix(&path) {
+                hasher.update(rel.as_os_str().as_encoded_bytes());
+            }
+            hasher.update(meta.len().to_le_bytes());
+            hasher.update(meta.mode().to_le_bytes());
+            if file.file_type().is_file() {
+                hasher.update(hashcache::hash_file_cached(file.path())?);
+            } else if file.file_type().is_symlink() {
+                hasher.update(fs::read_link(file.path())?.as_os_str().as_encoded_bytes());
+            }
+        }
+    }
+    hashcache::persist();
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Digest of what shapes the synced tree besides the module sources: the
+/// modules synced, the blacklists in effect, `mount_blacklist` and each
+/// module's `blacklist`, and whether `skip_identical_files` compared them
+/// against the stock files under `stock_root`, along with those files.
+/// Folded into the EROFS manifest, so changing any of them rebuilds the
+/// image.
+pub fn sync_fingerprint(config: &Config, modules: &[Module], stock_root: Option<&Path>) -> String {
+    let mut hasher = Sha256::new();
+    for pattern in &config.mount_blacklist {
+        hasher.update(pattern.as_bytes());
+        hasher.update([0]);
+    }
+    for module in modules {
+        hasher.update([1]);
+        hasher.update(module.id.as_bytes());
+        for pattern in &module.rules.blacklist {
+            hasher.update([0]);
+            hasher.update(pattern.as_bytes());
+        }
+    }
+    match stock_root {
+        Some(root) => {
+            hasher.update([1]);
+            hasher.update(identical::fingerprint(modules, root).as_bytes());
+        }
+        None => hasher.update([0]),
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
 fn check_image<P>(img: P) -> Result<()>
 where
     P: AsRef<Path>,
@@ -138,19 +697,144 @@ where
     Ok(())
 }
 
+/// Whether files can be written under `base`: its filesystem is not mounted
+/// read-only and a probe file can be created in it. A `base` that cannot be
+/// inspected counts as writable, so the real error surfaces later.
+pub fn data_writable(base: &Path) -> bool {
+    match rustix::fs::statvfs(base) {
+        Ok(stat) if stat.f_flag.contains(rustix::fs::StatVfsMountFlags::RDONLY) => return false,
+        Ok(_) => {}
+        Err(_) => return true,
+    }
+
+    let probe = base.join(DATA_RW_PROBE_FILE);
+    let writable = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&probe)
+        .is_ok();
+    let _ = fs::remove_file(&probe);
+    writable
+}
+
+/// Polls until `base` is writable or `timeout` passed. `None` when it was
+/// writable right away.
+pub fn wait_for_writable(base: &Path, timeout: Duration) -> Option<DataWait> {
+    if data_writable(base) {
+        return None;
+    }
+
+    log::warn!(
+        "!! {} is read-only (userdata checkpoint?), waiting up to {}s",
+        base.display(),
+        timeout.as_secs()
+    );
+    let started = Instant::now();
+    let writable = loop {
+        let elapsed = started.elapsed();
+        if elapsed >= timeout {
+            break false;
+        }
+        thread::sleep(DATA_RW_POLL_INTERVAL.min(timeout - elapsed));
+        if data_writable(base) {
+            break true;
+        }
+    };
+    let waited_ms = started.elapsed().as_millis() as u64;
+
+    if writable {
+        log::warn!(
+            ">> {} became writable after {} ms, storage setup starts late",
+            base.display(),
+            waited_ms
+        );
+    } else {
+        log::error!(
+            "!! {} is still read-only after {} ms, falling back to tmpfs storage",
+            base.display(),
+            waited_ms
+        );
+    }
+    Some(DataWait {
+        waited_ms,
+        writable,
+    })
+}
+
+/// Sets up the storage backend. When `/data` is read-only it first waits up
+/// to `data_rw_wait` for it; if it stays read-only only tmpfs and zram,
+/// which write nothing there, are tried.
+#[allow(clippy::too_many_arguments)]
 pub fn setup(
     mnt_base: &Path,
     img_path: &Path,
+    run_dir: &Path,
     moduledir: &Path,
+    staged_modules_dir: &Path,
     force_ext4: bool,
     use_erofs: bool,
+    use_zram: bool,
     mount_source: &str,
     disable_umount: bool,
+    force_rebuild: bool,
+    ext4_reserved_percent: Option<u8>,
+    sizing: &StorageConfig,
+    loop_options: &LoopOptions,
+    data_rw_wait: Duration,
+) -> Result<StorageHandle> {
+    let data_wait = wait_for_writable(Path::new(defs::BASE_DIR), data_rw_wait);
+    let data_ro = data_wait.is_some_and(|wait| !wait.writable);
+
+    let mut handle = setup_backend(
+        mnt_base,
+        img_path,
+        run_dir,
+        moduledir,
+        staged_modules_dir,
+        force_ext4,
+        use_erofs,
+        use_zram,
+        mount_source,
+        disable_umount,
+        force_rebuild,
+        ext4_reserved_percent,
+        sizing,
+        loop_options,
+        data_ro,
+    )?;
+    handle.data_wait = data_wait;
+    Ok(handle)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn setup_backend(
+    mnt_base: &Path,
+    img_path: &Path,
+    run_dir: &Path,
+    moduledir: &Path,
+    staged_modules_dir: &Path,
+    force_ext4: bool,
+    use_erofs: bool,
+    use_zram: bool,
+    mount_source: &str,
+    disable_umount: bool,
+    force_rebuild: bool,
+    ext4_reserved_percent: Option<u8>,
+    sizing: &StorageConfig,
+    loop_options: &LoopOptions,
+    data_ro: bool,
 ) -> Result<StorageHandle> {
     if is_mounted(mnt_base) {
         let _ = umount(mnt_base, UnmountFlags::DETACH);
     }
 
+    let stale = loopdev::cleanup_stale(Path::new(defs::BASE_DIR));
+    if stale > 0 {
+        log::warn!("Detached {} stale loop device(s) from earlier runs", stale);
+    }
+
     let try_hide = |path: &Path| {
         #[cfg(any(target_os = "linux", target_os = "android"))]
         if !disable_umount {
@@ -167,9 +851,24 @@ pub fn setup(
         }
     };
 
-    if use_erofs && is_erofs_supported() {
+    let image_builder = if use_erofs && !data_ro && utils::kernel_features().erofs {
+        let builder = image_builder::select();
+        if builder.is_none() {
+            log::error!(
+                "No mkfs.erofs found in {} or PATH, not using EROFS storage",
+                image_builder::search_locations()
+            );
+        }
+        builder
+    } else {
+        None
+    };
+
+    if let Some(builder) = image_builder {
+        log::info!("EROFS image builder: {}", builder.name());
+
         let erofs_path = img_path.with_extension("erofs");
-        let staging_dir = Path::new(defs::RUN_DIR).join("erofs_staging");
+        let staging_dir = run_dir.join("erofs_staging");
 
         if is_mounted(&staging_dir) {
             let _ = umount(&staging_dir, UnmountFlags::DETACH);
@@ -184,23 +883,57 @@ pub fn setup(
         make_private(&staging_dir);
         try_hide(&staging_dir);
 
+        let manifest = match compute_modules_manifest(moduledir, staged_modules_dir) {
+            Ok(digest) => Some(digest),
+            Err(e) => {
+                log::warn!("Failed to compute module manifest, forcing rebuild: {}", e);
+                None
+            }
+        };
+
         return Ok(StorageHandle {
             mount_point: staging_dir,
             mode: "erofs_staging".to_string(),
             backing_image: Some(erofs_path),
             final_target: Some(mnt_base.to_path_buf()),
+            manifest,
+            force_rebuild,
+            zram_device: None,
+            loop_device: None,
+            image_builder: Some(builder),
+            image_sized: false,
+            data_wait: None,
+            tmpfs: None,
+            integrity: None,
+            bundles: Vec::new(),
         });
     }
 
-    if !force_ext4 && try_setup_tmpfs(mnt_base, mount_source)? {
+    if use_zram {
+        match setup_zram(mnt_base, moduledir, ext4_reserved_percent, sizing) {
+            Ok(handle) => {
+                make_private(mnt_base);
+
+                try_hide(mnt_base);
+
+                return Ok(handle);
+            }
+            Err(e) => log::warn!("zram storage unavailable, falling back to tmpfs: {:#}", e),
+        }
+    }
+
+    if (!force_ext4 || data_ro) && try_setup_tmpfs(mnt_base, mount_source)? {
         make_private(mnt_base);
 
         try_hide(mnt_base);
 
+        // The image is only stale when tmpfs was chosen, not forced.
         let erofs_path = img_path.with_extension("erofs");
-
-        if erofs_path.exists() {
-            let _ = fs::remove_file(erofs_path);
+        if !data_ro {
+            if erofs_path.exists() {
+                let _ = fs::remove_file(&erofs_path);
+            }
+            let _ = fs::remove_file(erofs_manifest_path(&erofs_path));
         }
 
         return Ok(StorageHandle {
@@ -208,10 +941,34 @@ pub fn setup(
             mode: "tmpfs".to_string(),
             backing_image: None,
             final_target: None,
+            manifest: None,
+            force_rebuild: false,
+            zram_device: None,
+            loop_device: None,
+            image_builder: None,
+            image_sized: false,
+            data_wait: None,
+            tmpfs: None,
+            integrity: None,
+            bundles: Vec::new(),
         });
     }
 
-    let handle = setup_ext4_image(mnt_base, img_path, moduledir)?;
+    if data_ro {
+        bail!(
+            "{} is read-only and tmpfs storage is unavailable without CONFIG_TMPFS_XATTR",
+            defs::BASE_DIR
+        );
+    }
+
+    let handle = setup_ext4_image(
+        mnt_base,
+        img_path,
+        moduledir,
+        ext4_reserved_percent,
+        sizing,
+        loop_options,
+    )?;
 
     make_private(mnt_base);
 
@@ -221,77 +978,471 @@ pub fn setup(
 }
 
 fn try_setup_tmpfs(target: &Path, mount_source: &str) -> Result<bool> {
+    if !utils::kernel_features().tmpfs_xattr {
+        return Ok(false);
+    }
+
     if crate::sys::mount::mount_tmpfs(target, mount_source).is_ok() {
-        if utils::is_overlay_xattr_supported().unwrap_or(false) {
-            log::info!("Tmpfs mounted and supports xattrs (CONFIG_TMPFS_XATTR=y).");
-            return Ok(true);
-        } else {
-            let _ = umount(target, UnmountFlags::DETACH);
-        }
+        log::info!("Tmpfs mounted and supports xattrs (CONFIG_TMPFS_XATTR=y).");
+        return Ok(true);
     }
 
     Ok(false)
 }
 
-fn setup_ext4_image(target: &Path, img_path: &Path, moduledir: &Path) -> Result<StorageHandle> {
-    if img_path.exists()
-        && let Err(e) = fs::remove_file(img_path)
+/// Locates `mkfs.ext4` in the usual Android locations, then in `PATH`.
+pub fn find_mkfs_ext4() -> Option<PathBuf> {
+    find_e2fs_tool("mkfs.ext4")
+}
+
+/// Locates an e2fsprogs binary the same way as [`find_mkfs_ext4`].
+fn find_e2fs_tool(name: &str) -> Option<PathBuf> {
+    let path_dirs = std::env::var_os("PATH")
+        .map(|p| std::env::split_paths(&p).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    MKFS_EXT4_SEARCH_DIRS
+        .iter()
+        .map(PathBuf::from)
+        .chain(path_dirs)
+        .map(|dir| dir.join(name))
+        .find(|candidate| {
+            fs::metadata(candidate)
+                .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        })
+}
+
+/// Reports storage problems that would only surface once the backend is set up.
+pub fn diagnose(config: &crate::conf::config::Config) -> Vec<DiagnosticIssue> {
+    let mut issues = Vec::new();
+
+    let storage_mode = config.effective_storage_mode();
+    let ext4_needed = matches!(storage_mode, crate::conf::config::OverlayMode::Ext4)
+        || (matches!(storage_mode, crate::conf::config::OverlayMode::Tmpfs)
+            && !utils::kernel_features().tmpfs_xattr);
+
+    if matches!(storage_mode, crate::conf::config::OverlayMode::Zram)
+        && !Path::new(ZRAM_CONTROL_DIR).exists()
     {
-        log::warn!("Failed to remove old image: {}", e);
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Warning,
+            context: "Storage".to_string(),
+            message: format!(
+                "zram storage requested but {} is missing; tmpfs will be used",
+                ZRAM_CONTROL_DIR
+            ),
+        });
     }
 
-    let total_size = calculate_total_size(moduledir)?;
-    let min_size = 64 * 1024 * 1024;
-    let grow_size = std::cmp::max((total_size as f64 * 1.2) as u64, min_size);
+    if matches!(storage_mode, crate::conf::config::OverlayMode::Erofs)
+        && utils::kernel_features().erofs
+    {
+        match image_builder::select() {
+            None => issues.push(DiagnosticIssue {
+                level: DiagnosticLevel::Critical,
+                context: "Storage".to_string(),
+                message: format!(
+                    "EROFS storage is requested but mkfs.erofs was not found in {} or PATH; \
+                     tmpfs or ext4 will be used",
+                    image_builder::search_locations()
+                ),
+            }),
+            Some(builder) if builder.supports_xattrs() == Some(false) => {
+                issues.push(DiagnosticIssue {
+                    level: DiagnosticLevel::Critical,
+                    context: "Storage".to_string(),
+                    message: format!(
+                        "The {} mkfs.erofs has no xattr support; module files would lose \
+                         their SELinux labels in modules.erofs",
+                        builder.name()
+                    ),
+                })
+            }
+            Some(_) => {}
+        }
+    }
+
+    if matches!(storage_mode, crate::conf::config::OverlayMode::Tmpfs) && config.tmpfs_max_mb > 0 {
+        let needed = calculate_total_size(&config.moduledir).unwrap_or(0);
+        if needed > config.tmpfs_max_mb.saturating_mul(MIB) {
+            let (level, outcome) = match config.tmpfs_overflow {
+                TmpfsOverflow::Ext4 => (DiagnosticLevel::Warning, "an ext4 image is used instead"),
+                TmpfsOverflow::Trim => {
+                    (DiagnosticLevel::Warning, "the largest modules are left out")
+                }
+                TmpfsOverflow::Fail => (DiagnosticLevel::Critical, "the mount run aborts"),
+            };
+            issues.push(DiagnosticIssue {
+                level,
+                context: "Storage".to_string(),
+                message: format!(
+                    "Modules need {} MiB, over tmpfs_max_mb={}; {}",
+                    needed.div_ceil(MIB),
+                    config.tmpfs_max_mb,
+                    outcome
+                ),
+            });
+        }
+    }
+
+    if ext4_needed && find_mkfs_ext4().is_none() {
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Critical,
+            context: "Storage".to_string(),
+            message: format!(
+                "ext4 storage is required but mkfs.ext4 was not found in {} or PATH",
+                MKFS_EXT4_SEARCH_DIRS.join(", ")
+            ),
+        });
+    }
+
+    issues.extend(diagnose_rw_partitions(config));
+
+    if let Some(percent) = config.ext4_reserved_blocks_percent
+        && percent > 50
+    {
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Warning,
+            context: "Storage".to_string(),
+            message: format!(
+                "ext4_reserved_blocks_percent={} exceeds the mkfs limit of 50",
+                percent
+            ),
+        });
+    }
+
+    issues
+}
+
+/// Problems with the upperdirs requested through `rw_partitions` that the
+/// kernel would reject at mount time.
+#[allow(clippy::unnecessary_cast)]
+fn diagnose_rw_partitions(config: &crate::conf::config::Config) -> Vec<DiagnosticIssue> {
+    const OVERLAYFS_SUPER_MAGIC: i64 = 0x794c_7630;
+
+    let mut issues = Vec::new();
+    if config.rw_partitions.is_empty() {
+        return issues;
+    }
+
+    let rw_dir = Path::new(defs::SYSTEM_RW_DIR);
+    let mut critical = |message: String| {
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Critical,
+            context: "RW Overlay".to_string(),
+            message,
+        })
+    };
+
+    if let Some(existing) = rw_dir.ancestors().find(|p| p.exists()) {
+        if !utils::fs_supports_overlay_xattrs(existing) {
+            critical(format!(
+                "{} is on a filesystem that does not support trusted.* xattrs; upperdirs cannot be created",
+                defs::SYSTEM_RW_DIR
+            ));
+        }
+        if rustix::fs::statfs(existing).is_ok_and(|st| st.f_type as i64 == OVERLAYFS_SUPER_MAGIC) {
+            critical(format!(
+                "{} is on overlayfs, which the kernel rejects as an upper filesystem",
+                defs::SYSTEM_RW_DIR
+            ));
+        }
+    }
+
+    let storage = Path::new(&config.hybrid_mnt_dir);
+    if rw_dir.starts_with(storage) || storage.starts_with(rw_dir) {
+        critical(format!(
+            "{} overlaps the module storage {}; overlayfs rejects overlapping layers",
+            defs::SYSTEM_RW_DIR,
+            storage.display()
+        ));
+    }
+
+    for partition in &config.rw_partitions {
+        let lower = Path::new("/").join(partition);
+        if rw_dir.starts_with(&lower) {
+            critical(format!(
+                "{} lives inside its own lower layer {}",
+                defs::SYSTEM_RW_DIR,
+                lower.display()
+            ));
+        }
+    }
+
+    issues
+}
+
+/// Mounts an existing image on `target`, running `e2fsck -p` on it once
+/// when the first mount fails. Returns the loop device number.
+fn mount_existing_image(img_path: &Path, target: &Path, loop_options: &LoopOptions) -> Result<u32> {
+    match overlay_utils::mount_ext4(img_path, target, loop_options) {
+        Ok(loop_device) => return Ok(loop_device),
+        Err(e) => log::warn!("{:#}, checking {}", e, img_path.display()),
+    }
+    crate::sys::mount::repair_image(img_path)?;
+    overlay_utils::mount_ext4(img_path, target, loop_options)
+        .with_context(|| format!("Failed to mount {} after repair", img_path.display()))
+}
+
+/// Where an image that cannot be repaired is kept before it is recreated.
+pub fn image_backup_path(img_path: &Path) -> PathBuf {
+    let mut name = img_path.as_os_str().to_owned();
+    name.push(".bak");
+    PathBuf::from(name)
+}
+
+/// Mounts `modules.img` on `target`, reusing the existing image when it
+/// mounts, if need be after a repair. An image that cannot be repaired is
+/// moved to [`image_backup_path`], replacing an older backup, and a new one
+/// sized for the module set is created.
+fn setup_ext4_image(
+    target: &Path,
+    img_path: &Path,
+    moduledir: &Path,
+    reserved_percent: Option<u8>,
+    sizing: &StorageConfig,
+    loop_options: &LoopOptions,
+) -> Result<StorageHandle> {
+    ensure_dir_exists(target)?;
+
+    let handle = |image_sized, loop_device| StorageHandle {
+        mount_point: target.to_path_buf(),
+        mode: "ext4".to_string(),
+        backing_image: Some(img_path.to_path_buf()),
+        final_target: None,
+        manifest: None,
+        force_rebuild: false,
+        zram_device: None,
+        loop_device: Some(loop_device),
+        image_builder: None,
+        image_sized,
+        data_wait: None,
+        tmpfs: None,
+        integrity: None,
+        bundles: Vec::new(),
+    };
+
+    if img_path.exists() {
+        match mount_existing_image(img_path, target, loop_options) {
+            Ok(loop_device) => {
+                log::info!("Reusing {}", img_path.display());
+                relabel_storage(target);
+                return Ok(handle(false, loop_device));
+            }
+            Err(e) => {
+                let backup = image_backup_path(img_path);
+                log::error!(
+                    "!! {} is beyond repair ({:#}), keeping it as {} and creating a new one",
+                    img_path.display(),
+                    e,
+                    backup.display()
+                );
+                fs::rename(img_path, &backup).with_context(|| {
+                    format!(
+                        "Failed to move {} to {}",
+                        img_path.display(),
+                        backup.display()
+                    )
+                })?;
+            }
+        }
+    }
+
+    let mkfs = find_mkfs_ext4().with_context(|| {
+        format!(
+            "mkfs.ext4 not found (searched {} and PATH); cannot create ext4 storage",
+            MKFS_EXT4_SEARCH_DIRS.join(", ")
+        )
+    })?;
+
+    let grow_size = compute_image_size(moduledir, sizing)?;
 
     fs::File::create(img_path)
-        .context("Failed to create ext4 image file")?
+        .with_context(|| format!("Failed to create ext4 image file {}", img_path.display()))?
         .set_len(grow_size)
-        .context("Failed to extend ext4 image")?;
-
-    let result = Command::new("mkfs.ext4")
-        .arg("-b")
-        .arg("1024")
-        .arg(img_path)
-        .stdout(std::process::Stdio::piped())
-        .output()?;
+        .with_context(|| format!("Failed to allocate {} bytes for ext4 image", grow_size))?;
 
-    ensure!(
-        result.status.success(),
-        "Failed to format ext4 image: {}",
-        String::from_utf8(result.stderr)?
-    );
+    format_ext4(&mkfs, img_path, reserved_percent)?;
 
     check_image(img_path)?;
 
     utils::lsetfilecon(img_path, "u:object_r:ksu_file:s0").ok();
 
-    ensure_dir_exists(target)?;
-    if overlay_utils::AutoMountExt4::try_new(img_path, target, false).is_err() {
-        if crate::sys::mount::repair_image(img_path).is_ok() {
-            overlay_utils::AutoMountExt4::try_new(img_path, target, false)
-                .context("Failed to mount modules.img after repair")
-                .map(|_| ())?;
-        } else {
-            bail!("Failed to repair modules.img");
-        }
+    let loop_device = overlay_utils::mount_ext4(img_path, target, loop_options)
+        .with_context(|| format!("Failed to mount new {}", img_path.display()))?;
+
+    relabel_storage(target);
+
+    Ok(handle(true, loop_device))
+}
+
+fn format_ext4(mkfs: &Path, device: &Path, reserved_percent: Option<u8>) -> Result<()> {
+    let mut cmd = Command::new(mkfs);
+    cmd.arg("-b").arg("1024");
+    if let Some(percent) = reserved_percent {
+        cmd.arg("-m").arg(percent.min(50).to_string());
     }
 
+    let result = cmd
+        .arg(device)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .with_context(|| format!("Failed to execute {}", mkfs.display()))?;
+
+    ensure!(
+        result.status.success(),
+        "Failed to format {} with {} ({}): {}",
+        device.display(),
+        mkfs.display(),
+        result.status,
+        String::from_utf8_lossy(&result.stderr).trim()
+    );
+
+    Ok(())
+}
+
+fn relabel_storage(target: &Path) {
     for dir_entry in WalkDir::new(target).parallelism(jwalk::Parallelism::Serial) {
         if let Some(path) = dir_entry.ok().map(|dir_entry| dir_entry.path()) {
             let _ = utils::lsetfilecon(&path, DEFAULT_SELINUX_CONTEXT);
         }
     }
+}
+
+fn zram_block_device(index: u32) -> PathBuf {
+    let android = PathBuf::from(format!("/dev/block/zram{}", index));
+    if android.exists() {
+        android
+    } else {
+        PathBuf::from(format!("/dev/zram{}", index))
+    }
+}
+
+fn zram_hot_remove(index: u32) {
+    let control = Path::new(ZRAM_CONTROL_DIR).join("hot_remove");
+    if let Err(e) = fs::write(&control, index.to_string()) {
+        log::warn!("Failed to release zram{}: {}", index, e);
+    }
+}
+
+/// Allocates a fresh zram device sized for the module set and mounts an ext4
+/// filesystem from it. The device is released again if any step fails.
+fn setup_zram(
+    target: &Path,
+    moduledir: &Path,
+    reserved_percent: Option<u8>,
+    sizing: &StorageConfig,
+) -> Result<StorageHandle> {
+    let control = Path::new(ZRAM_CONTROL_DIR);
+    ensure!(
+        control.join("hot_add").exists(),
+        "{} not present (kernel lacks zram hot-add support)",
+        ZRAM_CONTROL_DIR
+    );
+
+    let mkfs = find_mkfs_ext4().with_context(|| {
+        format!(
+            "mkfs.ext4 not found (searched {} and PATH)",
+            MKFS_EXT4_SEARCH_DIRS.join(", ")
+        )
+    })?;
+
+    let disksize = compute_image_size(moduledir, sizing)?;
+
+    let index: u32 = fs::read_to_string(control.join("hot_add"))
+        .context("Failed to allocate zram device")?
+        .trim()
+        .parse()
+        .context("Unexpected zram hot_add response")?;
+
+    let result = (|| -> Result<()> {
+        fs::write(
+            format!("/sys/block/zram{}/disksize", index),
+            disksize.to_string(),
+        )
+        .with_context(|| format!("Failed to set zram{} disksize", index))?;
+
+        let device = zram_block_device(index);
+        format_ext4(&mkfs, &device, reserved_percent)?;
+
+        ensure_dir_exists(target)?;
+        mount(&device, target, c"ext4", MountFlags::NOATIME, None)
+            .with_context(|| format!("Failed to mount {}", device.display()))?;
+
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        zram_hot_remove(index);
+        return Err(e);
+    }
+
+    relabel_storage(target);
+
+    log::info!(
+        "zram{} mounted with {} bytes of uncompressed capacity.",
+        index,
+        disksize
+    );
 
     Ok(StorageHandle {
         mount_point: target.to_path_buf(),
-        mode: "ext4".to_string(),
-        backing_image: Some(img_path.to_path_buf()),
+        mode: "zram".to_string(),
+        backing_image: None,
         final_target: None,
+        manifest: None,
+        force_rebuild: false,
+        zram_device: Some(index),
+        loop_device: None,
+        image_builder: None,
+        image_sized: false,
+        data_wait: None,
+        tmpfs: None,
+        integrity: None,
+        bundles: Vec::new(),
+    })
+}
+
+/// Finds the zram device mounted at `mnt_base` through its mount source.
+fn mounted_zram_index(mnt_base: &Path) -> Option<u32> {
+    let mounts = procfs::process::Process::myself().ok()?.mountinfo().ok()?;
+
+    mounts
+        .into_iter()
+        .find(|m| m.mount_point == mnt_base)
+        .and_then(|m| m.mount_source)
+        .and_then(|source| {
+            source
+                .rsplit('/')
+                .next()
+                .and_then(|name| name.strip_prefix("zram"))
+                .and_then(|n| n.parse().ok())
+        })
+}
+
+fn read_zram_status(index: u32) -> Option<ZramStatus> {
+    let stat = fs::read_to_string(format!("/sys/block/zram{}/mm_stat", index)).ok()?;
+    let fields: Vec<u64> = stat
+        .split_whitespace()
+        .take(3)
+        .filter_map(|v| v.parse().ok())
+        .collect();
+
+    let [uncompressed_size, compressed_size, mem_used] = fields[..] else {
+        return None;
+    };
+
+    Some(ZramStatus {
+        device: index,
+        uncompressed_size,
+        compressed_size,
+        mem_used,
     })
 }
 
-pub fn print_status() -> Result<()> {
+/// Current state of the storage backend, as reported by the `storage` command.
+pub fn status() -> StorageStatus {
     let state = RuntimeState::load().ok();
     let fallback_mnt = crate::conf::config::Config::load_default()
         .map(|c| c.hybrid_mnt_dir)
@@ -329,62 +1480,66 @@ pub fn print_status() -> Result<()> {
     }
 
     let mut supported_modes = vec!["ext4".to_string(), "erofs".to_string()];
-    let check_dir = Path::new(defs::XATTR_CHECK_DIR);
-    if crate::sys::mount::mount_tmpfs(check_dir, "mh_check").is_ok() {
-        if utils::is_overlay_xattr_supported().unwrap_or(false) {
-            supported_modes.insert(0, "tmpfs".to_string());
-        }
-        let _ = umount(check_dir, UnmountFlags::DETACH);
-        let _ = fs::remove_dir(check_dir);
+    if utils::kernel_features().tmpfs_xattr {
+        supported_modes.insert(0, "tmpfs".to_string());
+    }
+    if Path::new(ZRAM_CONTROL_DIR).exists() {
+        supported_modes.push("zram".to_string());
     }
 
-    let status = StorageStatus {
+    let zram = mounted_zram_index(&mnt_base).and_then(read_zram_status);
+
+    let mut modules: Vec<ModuleUsageEntry> = state
+        .as_ref()
+        .map(|s| {
+            s.sync_summary
+                .module_usage
+                .iter()
+                .map(|(id, usage)| ModuleUsageEntry {
+                    id: id.clone(),
+                    size_bytes: usage.size_bytes(),
+                    usage: usage.clone(),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    modules.sort_by(|a, b| b.size_bytes.cmp(&a.size_bytes).then(a.id.cmp(&b.id)));
+
+    StorageStatus {
         mode,
         mount_point: mnt_base.to_string_lossy().to_string(),
         usage_percent: percent,
         total_size: total,
         used_size: used,
         supported_modes,
-    };
-
-    println!("{}", serde_json::to_string(&status)?);
-
-    Ok(())
+        hymofs_available: is_hymofs_active(),
+        dedup_saved_bytes: state
+            .as_ref()
+            .map_or(0, |s| s.sync_summary.dedup_saved_bytes),
+        zram,
+        tmpfs: state.as_ref().and_then(|s| s.tmpfs.clone()),
+        loop_devices: loopdev::list_owned(Path::new(defs::BASE_DIR)),
+        modules,
+    }
 }
 
-fn is_erofs_supported() -> bool {
-    fs::read_to_string("/proc/filesystems")
-        .map(|content| content.contains("erofs"))
-        .unwrap_or(false)
+/// Whether the HymoFS redirection layer is registered with the kernel and its
+/// control root is mounted, i.e. merge requests can actually be issued.
+pub fn is_hymofs_active() -> bool {
+    let registered = fs::read_to_string("/proc/filesystems").is_ok_and(|content| {
+        content.lines().any(|line| {
+            matches!(
+                line.split_whitespace().last(),
+                Some("hymofs") | Some("poaceaefs")
+            )
+        })
+    });
+
+    registered && is_mounted(defs::POACEAE_MOUNT_POINT)
 }
 
-fn create_erofs_image(src_dir: &Path, image_path: &Path) -> Result<()> {
-    let mkfs_bin = Path::new(defs::MKFS_EROFS_PATH);
-    let cmd_name = if mkfs_bin.exists() {
-        mkfs_bin.as_os_str()
-    } else {
-        std::ffi::OsStr::new("mkfs.erofs")
-    };
-
-    if image_path.exists() {
-        let _ = fs::remove_file(image_path);
-    }
-
-    let output = Command::new(cmd_name)
-        .arg("-z")
-        .arg("lz4hc")
-        .arg("-x")
-        .arg("256")
-        .arg(image_path)
-        .arg(src_dir)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-        .context("Failed to execute mkfs.erofs")?;
-
-    if !output.status.success() {
-        bail!("Failed to create EROFS image");
-    }
+fn create_erofs_image(builder: &dyn ImageBuilder, src_dir: &Path, image_path: &Path) -> Result<()> {
+    builder.build(src_dir, image_path)?;
 
     let _ = fs::set_permissions(image_path, fs::Permissions::from_mode(0o644));
     lsetfilecon(image_path, "u:object_r:ksu_file:s0")?;
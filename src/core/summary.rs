@@ -0,0 +1,311 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! One-glance report of the last boot, for the manager's WebUI banner.
+//!
+//! Every run that gets as far as writing its state, whether it mounted,
+//! was cancelled or failed, also writes `run/last_summary.json`, and with
+//! `summary_text` a rendered `last_summary.txt` beside it. Phase times come
+//! from the timing spans recorded since the run started, so a failed run
+//! still shows the phases it got through.
+
+use std::{collections::BTreeMap, fmt::Write as _, fs, path::Path};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::{
+        failure::FailureRecord,
+        state::{BootResult, MountPhase, RuntimeState},
+    },
+    utils::{
+        self,
+        timing::{self, Sample},
+    },
+};
+
+pub const SUMMARY_SCHEMA_VERSION: u32 = 1;
+/// Warnings kept in the summary; `counts.warnings` has the full number.
+const TOP_WARNINGS: usize = 5;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SummaryCounts {
+    /// Modules mounted by any method.
+    pub mounted: usize,
+    pub overlay: usize,
+    pub magic: usize,
+    pub hymo: usize,
+    pub bind: usize,
+    /// Modules held back until `mount-deferred`.
+    pub deferred: usize,
+    /// Modules mounted differently than planned.
+    pub fallbacks: usize,
+    /// Conflicts a winnowing rule decided.
+    pub conflicts_resolved: usize,
+    pub warnings: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SummaryStorage {
+    pub mode: String,
+    pub used_bytes: u64,
+    pub total_bytes: u64,
+    pub usage_percent: u8,
+}
+
+/// Wall-clock time of one stage, summed over its spans.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PhaseTime {
+    pub stage: String,
+    pub ms: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BootSummary {
+    pub schema_version: u32,
+    pub timestamp: u64,
+    pub result: BootResult,
+    /// e.g. `7 mounted, 1 fallback, 2 warnings`.
+    pub headline: String,
+    pub counts: SummaryCounts,
+    pub storage: SummaryStorage,
+    pub degraded: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub degraded_reason: Option<String>,
+    /// The most important warnings, most severe first.
+    pub warnings: Vec<String>,
+    /// Stages in the order they finished.
+    pub phases: Vec<PhaseTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<FailureRecord>,
+}
+
+fn plural(count: usize, word: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, word)
+    } else {
+        format!("{} {}s", count, word)
+    }
+}
+
+/// Every warning the state carries, most severe first.
+fn warnings(state: &RuntimeState) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if let Some(failure) = &state.failure {
+        warnings.push(format!(
+            "Failed in {:?} (exit {}): {}",
+            failure.phase, failure.code, failure.message
+        ));
+    }
+    if state.safe_mode {
+        warnings.push("Safe mode: modules skipped after unfinished boots".to_string());
+    }
+    if let Some(integrity) = &state.integrity {
+        for id in integrity.failed.keys() {
+            warnings.push(format!("{}: storage copy failed the integrity check", id));
+        }
+    }
+    if state.degraded {
+        warnings.push("Degraded: a mount timed out or the deadline cut the run short".to_string());
+    }
+    if let Some(reason) = &state.degraded_reason {
+        warnings.push(format!("Degraded storage: {}", reason));
+    }
+    let fallbacks: BTreeMap<_, _> = state.fallback_reasons.iter().collect();
+    for (id, reason) in fallbacks {
+        warnings.push(format!("{}: {}", id, reason));
+    }
+    if let Some(tmpfs) = &state.tmpfs
+        && !tmpfs.trimmed.is_empty()
+    {
+        warnings.push(format!(
+            "Left out to stay under tmpfs_max_mb: {}",
+            tmpfs.trimmed.join(", ")
+        ));
+    }
+    if !state.foreign_mounts.is_empty() {
+        warnings.push(format!(
+            "{} on our partitions",
+            plural(state.foreign_mounts.len(), "foreign mount")
+        ));
+    }
+    if !state.busy_skipped.is_empty() {
+        warnings.push(format!(
+            "{} skipped because the stock file was busy",
+            plural(state.busy_skipped.len(), "file")
+        ));
+    }
+    warnings
+}
+
+/// Time per stage, in the order each stage first finished.
+fn phases(samples: &[Sample]) -> Vec<PhaseTime> {
+    let mut phases: Vec<PhaseTime> = Vec::new();
+    for sample in samples {
+        let ms = sample.elapsed.as_millis() as u64;
+        match phases.iter_mut().find(|p| p.stage == sample.stage) {
+            Some(phase) => phase.ms += ms,
+            None => phases.push(PhaseTime {
+                stage: sample.stage.to_string(),
+                ms,
+            }),
+        }
+    }
+    phases
+}
+
+impl BootSummary {
+    pub fn new(state: &RuntimeState, samples: &[Sample]) -> Self {
+        let mut warnings = warnings(state);
+        let counts = SummaryCounts {
+            mounted: state.overlay_modules.len()
+                + state.magic_modules.len()
+                + state.hymo_modules.len()
+                + state.bind_modules.len(),
+            overlay: state.overlay_modules.len(),
+            magic: state.magic_modules.len(),
+            hymo: state.hymo_modules.len(),
+            bind: state.bind_modules.len(),
+            deferred: state
+                .mount_phase
+                .values()
+                .filter(|phase| **phase == MountPhase::Pending)
+                .count(),
+            fallbacks: state.fallback_reasons.len(),
+            conflicts_resolved: 0,
+            warnings: warnings.len(),
+        };
+        warnings.truncate(TOP_WARNINGS);
+
+        let mut summary = Self {
+            schema_version: SUMMARY_SCHEMA_VERSION,
+            timestamp: state.timestamp,
+            result: state.last_result,
+            headline: String::new(),
+            counts,
+            storage: SummaryStorage {
+                mode: state.storage_mode.clone(),
+                used_bytes: state.storage_used,
+                total_bytes: state.storage_total,
+                usage_percent: state.storage_percent,
+            },
+            degraded: state.degraded || state.degraded_reason.is_some(),
+            degraded_reason: state.degraded_reason.clone(),
+            warnings,
+            phases: phases(samples),
+            failure: state.failure.clone(),
+        };
+        summary.headline = summary.headline();
+        summary
+    }
+
+    /// `7 mounted, 1 fallback, 2 warnings`, led by the outcome when the
+    /// run did not finish normally.
+    fn headline(&self) -> String {
+        let counts = format!(
+            "{} mounted, {}, {}",
+            self.counts.mounted,
+            plural(self.counts.fallbacks, "fallback"),
+            plural(self.counts.warnings, "warning")
+        );
+        match (self.result, &self.failure) {
+            (BootResult::Failed, Some(failure)) => {
+                format!("Failed in {:?} (exit {})", failure.phase, failure.code)
+            }
+            (BootResult::Cancelled, _) => format!("Cancelled, {}", counts),
+            (BootResult::SafeMode, _) => format!("Safe mode, {}", counts),
+            _ => counts,
+        }
+    }
+
+    /// The summary as a few lines of plain text.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "{}", self.headline);
+        let _ = writeln!(
+            out,
+            "Modules: {} overlay, {} magic, {} hymo, {} bind, {} deferred",
+            self.counts.overlay,
+            self.counts.magic,
+            self.counts.hymo,
+            self.counts.bind,
+            self.counts.deferred
+        );
+        if self.counts.conflicts_resolved > 0 {
+            let _ = writeln!(
+                out,
+                "Winnowing decided {}",
+                plural(self.counts.conflicts_resolved, "conflict")
+            );
+        }
+        let _ = writeln!(
+            out,
+            "Storage: {}, {}% of {} MiB used",
+            self.storage.mode,
+            self.storage.usage_percent,
+            self.storage.total_bytes / (1024 * 1024)
+        );
+        if !self.phases.is_empty() {
+            let phases: Vec<String> = self
+                .phases
+                .iter()
+                .map(|p| format!("{} {} ms", p.stage, p.ms))
+                .collect();
+            let _ = writeln!(out, "Phases: {}", phases.join(", "));
+        }
+        for warning in &self.warnings {
+            let _ = writeln!(out, "! {}", warning);
+        }
+        if self.counts.warnings > self.warnings.len() {
+            let _ = writeln!(
+                out,
+                "! ... and {} more",
+                self.counts.warnings - self.warnings.len()
+            );
+        }
+        out
+    }
+
+    /// Writes the summary to `file`, and its rendering next to it as
+    /// `.txt` when `text` is set. A stale rendering is removed otherwise.
+    pub fn save(&self, file: &Path, text: bool) -> Result<()> {
+        if let Some(parent) = file.parent() {
+            utils::ensure_dir_exists(parent)?;
+        }
+        utils::atomic_write(file, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", file.display()))?;
+
+        let text_file = file.with_extension("txt");
+        if text {
+            utils::atomic_write(&text_file, self.render())
+                .with_context(|| format!("Failed to write {}", text_file.display()))?;
+        } else if text_file.exists() {
+            let _ = fs::remove_file(&text_file);
+        }
+        Ok(())
+    }
+
+    pub fn load(file: &Path) -> Result<Self> {
+        let content =
+            fs::read(file).with_context(|| format!("No boot summary at {}", file.display()))?;
+        serde_json::from_slice(&content)
+            .with_context(|| format!("Failed to parse {}", file.display()))
+    }
+}
+
+/// Builds the summary of the run recorded in `state` from the spans
+/// recorded so far and saves it. Failures are only logged.
+pub fn record(
+    state: &RuntimeState,
+    conflicts_resolved: usize,
+    file: &Path,
+    text: bool,
+) -> BootSummary {
+    let mut summary = BootSummary::new(state, &timing::recorded());
+    summary.counts.conflicts_resolved = conflicts_resolved;
+    if let Err(e) = summary.save(file, text) {
+        log::warn!("Failed to write the boot summary: {:#}", e);
+    }
+    summary
+}
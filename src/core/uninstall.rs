@@ -0,0 +1,251 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Cleanup run by the module's `uninstall.sh` once the module is removed.
+//!
+//! Unmounts what the last run left mounted, detaches the loop devices
+//! backing images under the base directory and deletes its contents, so
+//! nothing is orphaned on `/data`. Deletion never leaves the base
+//! directory: symlinks are removed rather than followed, a subtree still
+//! mounted after the unmounts is left alone, and a base directory that is
+//! itself a symlink is refused outright.
+
+use std::{
+    collections::HashSet,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use rustix::mount::{UnmountFlags, unmount};
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::{
+    conf::profile,
+    core::{
+        ops::{journal::UndoJournal, mount_alias::MountTable},
+        state::RuntimeState,
+    },
+    defs,
+    sys::{loopdev, mount::is_mounted},
+    utils,
+};
+
+/// The files the cleanup reads and the directory it empties.
+#[derive(Debug, Clone)]
+pub struct UninstallTargets {
+    pub base_dir: PathBuf,
+    pub state_file: PathBuf,
+    pub journal_file: PathBuf,
+}
+
+impl Default for UninstallTargets {
+    fn default() -> Self {
+        Self {
+            base_dir: PathBuf::from(defs::BASE_DIR),
+            state_file: PathBuf::from(defs::STATE_FILE),
+            journal_file: PathBuf::from(defs::MOUNT_JOURNAL_FILE),
+        }
+    }
+}
+
+/// What the cleanup did, printed as JSON for the uninstall script.
+#[derive(Debug, Default, Serialize)]
+pub struct UninstallReport {
+    pub unmounted: Vec<PathBuf>,
+    pub loops_detached: usize,
+    /// Entries of the base directory deleted with everything below them.
+    pub removed: Vec<PathBuf>,
+    /// Files deleted, counting each hard-linked file once.
+    pub removed_files: usize,
+    /// Disk space the deleted files took up.
+    pub reclaimed_bytes: u64,
+    /// Entries kept for a reinstall with `--keep-config`.
+    pub kept: Vec<PathBuf>,
+    /// What could not be unmounted or deleted.
+    pub errors: Vec<String>,
+}
+
+/// Names in the base directory a reinstall picks up again: the config, its
+/// profiles, the per-module rules and the granary snapshots.
+fn config_entries() -> Vec<&'static str> {
+    [defs::CONFIG_FILE, defs::RULES_DIR, defs::GRANARY_DIR]
+        .iter()
+        .filter_map(|path| Path::new(path).file_name()?.to_str())
+        .chain([profile::PROFILES_DIR])
+        .collect()
+}
+
+/// Whether `point`, read from the state file, can be the storage mount
+/// rather than `/` or a directory holding the base directory.
+fn is_storage_point(point: &Path, base_dir: &Path) -> bool {
+    point.is_absolute() && point.parent().is_some() && !base_dir.starts_with(point)
+}
+
+/// Unwinds the overlays of an interrupted run, then unmounts the storage
+/// the last run recorded and every mount below the base directory, deepest
+/// first.
+fn unmount_all(targets: &UninstallTargets, report: &mut UninstallReport) {
+    UndoJournal::recover_stale(&targets.journal_file);
+
+    let mut points = Vec::new();
+    if let Ok(state) = RuntimeState::load_from(&targets.state_file)
+        && is_storage_point(&state.mount_point, &targets.base_dir)
+    {
+        points.push(state.mount_point.join("magic_workspace"));
+        points.push(state.mount_point);
+    }
+
+    let table = MountTable::parse(&fs::read_to_string("/proc/self/mountinfo").unwrap_or_default());
+    let mut below: Vec<PathBuf> = table
+        .entries()
+        .iter()
+        .map(|entry| entry.mount_point.clone())
+        .filter(|point| point.starts_with(&targets.base_dir) && *point != targets.base_dir)
+        .collect();
+    below.sort_by_key(|point| std::cmp::Reverse(point.components().count()));
+    points.extend(below);
+
+    let mut seen = HashSet::new();
+    for point in points {
+        if !seen.insert(point.clone()) || !is_mounted(&point) {
+            continue;
+        }
+        match unmount(&point, UnmountFlags::DETACH) {
+            Ok(()) => {
+                log::info!("Unmounted {}", point.display());
+                report.unmounted.push(point);
+            }
+            Err(e) => report
+                .errors
+                .push(format!("Failed to unmount {}: {}", point.display(), e)),
+        }
+    }
+}
+
+/// Deletes `path` and everything below it on the base directory's
+/// filesystem `device`, without following symlinks.
+fn remove_tree(
+    path: &Path,
+    device: u64,
+    inodes: &mut HashSet<(u64, u64)>,
+    report: &mut UninstallReport,
+) -> Result<()> {
+    let meta = fs::symlink_metadata(path)?;
+    if meta.dev() != device {
+        bail!("{} is still mounted", path.display());
+    }
+    if !meta.is_dir() {
+        if inodes.insert((meta.dev(), meta.ino())) {
+            report.removed_files += 1;
+            report.reclaimed_bytes += utils::allocated_size(&meta);
+        }
+        return fs::remove_file(path)
+            .with_context(|| format!("Failed to delete {}", path.display()));
+    }
+
+    let mut failed = None;
+    for entry in WalkDir::new(path)
+        .same_file_system(true)
+        .contents_first(true)
+    {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                failed.get_or_insert_with(|| e.to_string());
+                continue;
+            }
+        };
+        let removed = if entry.file_type().is_dir() {
+            fs::remove_dir(entry.path())
+        } else {
+            if let Ok(meta) = entry.metadata()
+                && inodes.insert((meta.dev(), meta.ino()))
+            {
+                report.removed_files += 1;
+                report.reclaimed_bytes += utils::allocated_size(&meta);
+            }
+            fs::remove_file(entry.path())
+        };
+        if let Err(e) = removed {
+            failed.get_or_insert_with(|| format!("{}: {}", entry.path().display(), e));
+        }
+    }
+    match failed {
+        Some(e) => bail!("Failed to delete {}", e),
+        None => Ok(()),
+    }
+}
+
+/// Runs the cleanup. Failing to unmount or delete something is recorded in
+/// the report and does not stop the rest; only a base directory that is a
+/// symlink is an error. A missing base directory is nothing to do.
+pub fn run(targets: &UninstallTargets, keep_config: bool) -> Result<UninstallReport> {
+    let base_dir = &targets.base_dir;
+    let base_meta = match fs::symlink_metadata(base_dir) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(UninstallReport::default());
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to stat {}", base_dir.display())),
+    };
+    if base_meta.is_symlink() {
+        bail!(
+            "Refusing to clean up {}: it is a symlink",
+            base_dir.display()
+        );
+    }
+    if !base_meta.is_dir() {
+        bail!("{} is not a directory", base_dir.display());
+    }
+
+    let mut report = UninstallReport::default();
+    unmount_all(targets, &mut report);
+    report.loops_detached = loopdev::cleanup_stale(base_dir);
+
+    let kept_names = if keep_config {
+        config_entries()
+    } else {
+        Vec::new()
+    };
+    let mut entries: Vec<PathBuf> = fs::read_dir(base_dir)
+        .with_context(|| format!("Failed to read {}", base_dir.display()))?
+        .flatten()
+        .map(|entry| entry.path())
+        .collect();
+    entries.sort();
+
+    let mut inodes = HashSet::new();
+    for entry in entries {
+        let keep = entry
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| kept_names.contains(&name));
+        if keep {
+            report.kept.push(entry);
+            continue;
+        }
+        match remove_tree(&entry, base_meta.dev(), &mut inodes, &mut report) {
+            Ok(()) => report.removed.push(entry),
+            Err(e) => report.errors.push(format!("{:#}", e)),
+        }
+    }
+
+    if report.kept.is_empty() && report.errors.is_empty() {
+        match fs::remove_dir(base_dir) {
+            Ok(()) => report.removed.push(base_dir.clone()),
+            Err(e) => report
+                .errors
+                .push(format!("Failed to delete {}: {}", base_dir.display(), e)),
+        }
+    }
+
+    log::info!(
+        "Uninstall cleanup removed {} file(s), reclaiming {} bytes",
+        report.removed_files,
+        report.reclaimed_bytes
+    );
+    Ok(report)
+}
@@ -0,0 +1,320 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::HashMap,
+    fs,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{
+    conf::config::WinnowingTable,
+    core::{
+        inventory::{Module, model::ModuleProp},
+        ops::{
+            conflict::ConflictSeverity,
+            planner::{ConflictEntry, MountPlan},
+        },
+    },
+    utils,
+};
+
+/// Path components holding one app per directory.
+const APP_DIRS: &[&str] = &["app", "priv-app"];
+
+/// A conflict together with the module that will actually provide the file.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChaffConflict {
+    pub path: String,
+    pub contenders: Vec<String>,
+    pub selected: String,
+    pub is_forced: bool,
+    /// Module a rule prefers for this path when another rule under the same
+    /// overlay target put a different module on top.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unmet_rule: Option<String>,
+    pub identical: bool,
+    pub severity: ConflictSeverity,
+}
+
+pub fn conflict_path(conflict: &ConflictEntry) -> String {
+    Path::new(&conflict.target)
+        .join(&conflict.relative_path)
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Reports which module serves each contested path. Contenders come in the
+/// plan's layer order, after [`promote_forced_layers`], so the first one is
+/// what overlayfs shows. A rule only forces the path when its module ended
+/// up on top; promotion is per target, so another rule can outrank it.
+pub fn sift_conflicts(conflicts: &[ConflictEntry], table: &WinnowingTable) -> Vec<ChaffConflict> {
+    conflicts
+        .iter()
+        .filter(|c| !c.contending_modules.is_empty())
+        .map(|c| {
+            let path = conflict_path(c);
+            let selected = c.contending_modules[0].clone();
+            let rule = table
+                .get_preferred_module(&path)
+                .filter(|id| c.contending_modules.iter().any(|m| m == id));
+
+            ChaffConflict {
+                is_forced: rule == Some(selected.as_str()),
+                unmet_rule: rule.filter(|id| *id != selected).map(str::to_string),
+                path,
+                contenders: c.contending_modules.clone(),
+                selected,
+                identical: c.identical,
+                severity: c.severity,
+            }
+        })
+        .collect()
+}
+
+/// Moves the layers of modules forced by the winnowing table to the top of
+/// the lowerdir stack for `target`. Rules are applied in path order, so when
+/// several rules under one target disagree the last path wins.
+pub fn promote_forced_layers(
+    target: &Path,
+    layers: &mut Vec<(String, PathBuf)>,
+    table: &WinnowingTable,
+) {
+    for (rule_path, module_id) in &table.rules {
+        if !Path::new(rule_path).starts_with(target) {
+            continue;
+        }
+
+        let (forced, rest): (Vec<_>, Vec<_>) =
+            layers.drain(..).partition(|(id, _)| id == module_id);
+
+        if !forced.is_empty() {
+            log::debug!(
+                "Winnowing: {} forced on top of {} for {}",
+                module_id,
+                target.display(),
+                rule_path
+            );
+        }
+
+        layers.extend(forced);
+        layers.extend(rest);
+    }
+}
+
+/// Number of rules that decide a conflict in `plan`: the path is in the
+/// layers of more than one module of its overlay stack, one of them the
+/// rule's.
+pub fn resolved_conflicts(plan: &MountPlan, table: &WinnowingTable) -> usize {
+    table
+        .rules
+        .iter()
+        .filter(|(rule_path, module_id)| {
+            plan.overlay_ops.iter().any(|op| {
+                let Ok(relative) = Path::new(rule_path).strip_prefix(&op.target) else {
+                    return false;
+                };
+                let holders: Vec<String> = op
+                    .lowerdirs
+                    .iter()
+                    .filter(|dir| fs::symlink_metadata(dir.join(relative)).is_ok())
+                    .filter_map(|dir| utils::extract_module_id(dir))
+                    .collect();
+                holders.len() > 1 && holders.contains(module_id)
+            })
+        })
+        .count()
+}
+
+/// Which heuristic chose the module of a [`WinnowSuggestion`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuggestionBasis {
+    /// The contenders ship the same app; the newest module wins.
+    VersionCode,
+    /// The module id names the file or a directory above it.
+    PackageName,
+    /// Nothing else decides, so the current winner is made explicit.
+    CurrentOrder,
+}
+
+/// A winnowing rule proposed for a contested path.
+#[derive(Debug, Clone, Serialize)]
+pub struct WinnowSuggestion {
+    pub path: String,
+    pub module: String,
+    /// Modules providing the path, topmost layer first.
+    pub contenders: Vec<String>,
+    pub basis: SuggestionBasis,
+    pub rationale: String,
+}
+
+/// Whether a layer of `module_id` in `plan` holds a file, not a whiteout,
+/// at the conflict's path.
+fn ships_file(plan: &MountPlan, conflict: &ConflictEntry, module_id: &str) -> bool {
+    plan.overlay_ops
+        .iter()
+        .filter(|op| op.target == conflict.target)
+        .flat_map(|op| &op.lowerdirs)
+        .filter(|dir| utils::extract_module_id(dir).is_some_and(|id| id == module_id))
+        .any(|dir| {
+            fs::symlink_metadata(dir.join(&conflict.relative_path))
+                .is_ok_and(|meta| !meta.file_type().is_char_device())
+        })
+}
+
+/// Lowercase letters and digits of `name`, so `Google_Camera` and
+/// `googlecamera` compare equal.
+fn squash(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// The component of `path` that names `module_id`: a directory, the file
+/// name without extension, or one dot-separated part of either, as in
+/// `com.example.camera`.
+fn package_component<'a>(path: &'a str, module_id: &str) -> Option<&'a str> {
+    let id = squash(module_id);
+    if id.is_empty() {
+        return None;
+    }
+    path.split('/').skip(2).find(|component| {
+        let stem = component
+            .rsplit_once('.')
+            .map_or(*component, |(stem, _)| stem);
+        [*component, stem]
+            .into_iter()
+            .chain(component.split('.'))
+            .any(|name| squash(name) == id)
+    })
+}
+
+/// The app directory `path` is in, such as `/system/app/Camera`.
+fn app_dir(path: &str) -> Option<String> {
+    let components: Vec<&str> = path.split('/').collect();
+    let at = components.iter().position(|c| APP_DIRS.contains(c))?;
+    (components.len() > at + 2).then(|| components[..at + 2].join("/"))
+}
+
+fn suggest_one(
+    conflict: &ConflictEntry,
+    path: String,
+    candidates: &[&String],
+    version_codes: &HashMap<&str, Option<u64>>,
+) -> WinnowSuggestion {
+    let suggestion = |module: &str, basis, rationale| WinnowSuggestion {
+        path: path.clone(),
+        module: module.to_string(),
+        contenders: conflict.contending_modules.clone(),
+        basis,
+        rationale,
+    };
+
+    let codes: Vec<(&String, u64)> = candidates
+        .iter()
+        .filter_map(|id| Some((*id, version_codes.get(id.as_str()).copied().flatten()?)))
+        .collect();
+    if let Some(app) = app_dir(&path)
+        && codes.len() == candidates.len()
+        && let Some(&(id, newest)) = codes.iter().max_by_key(|(_, code)| *code)
+        && codes.iter().filter(|(_, code)| *code == newest).count() == 1
+    {
+        return suggestion(
+            id,
+            SuggestionBasis::VersionCode,
+            format!(
+                "{} has the highest versionCode ({}) of the modules shipping {}",
+                id, newest, app
+            ),
+        );
+    }
+
+    let named: Vec<(&String, &str)> = candidates
+        .iter()
+        .filter_map(|id| Some((*id, package_component(&path, id)?)))
+        .collect();
+    if let [(id, component)] = named[..] {
+        return suggestion(
+            id,
+            SuggestionBasis::PackageName,
+            format!(
+                "module id {} matches the path component '{}'",
+                id, component
+            ),
+        );
+    }
+
+    let winner = &conflict.contending_modules[0];
+    let (module, rationale) = if candidates.contains(&winner) {
+        (
+            winner,
+            format!(
+                "{} already wins as the topmost layer; the rule keeps it winning if the module order changes",
+                winner
+            ),
+        )
+    } else {
+        (
+            candidates[0],
+            format!(
+                "{} is the topmost layer shipping the file; {} only hides it",
+                candidates[0], winner
+            ),
+        )
+    };
+    suggestion(module, SuggestionBasis::CurrentOrder, rationale)
+}
+
+/// Proposes a rule for each contested path that no rule decides yet and
+/// whose contenders differ. Only modules whose layer really holds the file
+/// are proposed. Suggestions are grouped by contending modules, then sorted
+/// by path.
+pub fn suggest_rules(
+    plan: &MountPlan,
+    conflicts: &[ConflictEntry],
+    modules: &[Module],
+    table: &WinnowingTable,
+) -> Vec<WinnowSuggestion> {
+    let version_codes: HashMap<&str, Option<u64>> = modules
+        .iter()
+        .map(|m| {
+            let prop = ModuleProp::from(m.source_path.join("module.prop").as_path());
+            (m.id.as_str(), prop.version_code)
+        })
+        .collect();
+
+    let mut suggestions: Vec<WinnowSuggestion> = conflicts
+        .iter()
+        .filter(|c| c.contending_modules.len() > 1 && !c.identical)
+        .filter_map(|c| {
+            let path = conflict_path(c);
+            let covered = table
+                .get_preferred_module(&path)
+                .is_some_and(|id| c.contending_modules.iter().any(|m| m == id));
+            if covered {
+                return None;
+            }
+            let candidates: Vec<&String> = c
+                .contending_modules
+                .iter()
+                .filter(|id| ships_file(plan, c, id))
+                .collect();
+            if candidates.is_empty() {
+                return None;
+            }
+            Some(suggest_one(c, path, &candidates, &version_codes))
+        })
+        .collect();
+
+    suggestions.sort_by_cached_key(|s| {
+        let mut group = s.contenders.clone();
+        group.sort();
+        (group, s.path.clone())
+    });
+    suggestions
+}
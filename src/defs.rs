@@ -1,21 +1,45 @@
 // Copyright 2026 Hybrid Mount Developers
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use std::path::{Path, PathBuf};
+
 pub const DEFAULT_HYBRID_MNT_DIR: &str = "/debug_ramdisk";
+pub const BASE_DIR: &str = "/data/adb/meta-hybrid/";
 pub const MODULES_IMG_FILE: &str = "/data/adb/meta-hybrid/modules.img";
 pub const RUN_DIR: &str = "/data/adb/meta-hybrid/run/";
 pub const STATE_FILE: &str = "/data/adb/meta-hybrid/run/daemon_state.json";
+pub const PENDING_FILE: &str = "/data/adb/meta-hybrid/run/pending.json";
+pub const SELINUX_REPORT_FILE: &str = "/data/adb/meta-hybrid/run/selinux_report.json";
+pub const MOUNT_JOURNAL_FILE: &str = "/data/adb/meta-hybrid/run/mount_journal.json";
+pub const HASH_CACHE_FILE: &str = "/data/adb/meta-hybrid/run/hash_cache.json";
+pub const MOUNT_NS_DIR: &str = "/data/adb/meta-hybrid/run/ns/";
+pub const TRACE_FILE: &str = "/data/adb/meta-hybrid/run/trace.bin";
+pub const DEFERRED_FILE: &str = "/data/adb/meta-hybrid/run/deferred.json";
+pub const SUMMARY_FILE: &str = "/data/adb/meta-hybrid/run/last_summary.json";
+pub const LAST_GOOD_PLAN_FILE: &str = "/data/adb/meta-hybrid/last_good_plan.json";
+pub const INTEGRITY_FILE: &str = "/data/adb/meta-hybrid/integrity.json";
 pub const DISABLE_FILE_NAME: &str = "disable";
 pub const REMOVE_FILE_NAME: &str = "remove";
 pub const SKIP_MOUNT_FILE_NAME: &str = "skip_mount";
+pub const MOUNT_MODE_FILE_NAME: &str = "mount_mode";
+pub const MANIFEST_FILE_NAME: &str = "meta_hybrid_manifest.toml";
+pub const RULES_DIR: &str = "/data/adb/meta-hybrid/rules/";
+pub const GRANARY_DIR: &str = "/data/adb/meta-hybrid/granary/";
+pub const BIN_DIR: &str = "/data/adb/meta-hybrid/bin";
+pub const STAGING_DIR: &str = "/data/adb/meta-hybrid/staging/";
 pub const SYSTEM_RW_DIR: &str = "/data/adb/meta-hybrid/rw";
+pub const BUNDLES_DIR: &str = "/data/adb/meta-hybrid/bundles/";
 pub const MODULE_PROP_FILE: &str = "/data/adb/modules/meta-hybrid/module.prop";
 pub const MODULES_DIR: &str = "/data/adb/modules";
+/// Sibling of the module directory where KernelSU stages updates until reboot.
+pub const MODULES_UPDATE_DIR_NAME: &str = "modules_update";
+pub const DAEMON_LOG_FILE: &str = "/data/adb/meta-hybrid/daemon.log";
 pub const CONFIG_FILE: &str = "/data/adb/meta-hybrid/config.toml";
 pub const MKFS_EROFS_PATH: &str = "/data/adb/metamodule/tools/mkfs.erofs";
 pub const XATTR_CHECK_DIR: &str = "/data/local/tmp/.mh_xattr_chk";
 pub const POACEAE_MOUNT_POINT: &str = "/data/adb/poaceaefs_mount";
 pub const ZYGISKSU_DENYLIST_FILE: &str = "/data/adb/zygisksu/denylist_enforce";
+pub const KSU_ALLOWLIST_FILE: &str = "/data/adb/ksu/.allowlist";
 
 pub const BUILTIN_PARTITIONS: &[&str] = &[
     "system",
@@ -41,6 +65,42 @@ pub const BUILTIN_PARTITIONS: &[&str] = &[
     "prism",
 ];
 
+/// Kernel module partitions. Unlike `BUILTIN_PARTITIONS` they are only
+/// mounted on when `/<name>` is a real mount, since many devices ship an
+/// empty stub directory for them.
+pub const DLKM_PARTITIONS: &[&str] = &["system_dlkm", "vendor_dlkm"];
+
+/// Top-level module directories that are never partitions, even when a
+/// directory of the same name exists on the device.
+pub const NON_PARTITION_DIRS: &[&str] = &[
+    "META-INF",
+    "common",
+    "webroot",
+    "zygisk",
+    "bin",
+    "lib",
+    "tools",
+    "cache",
+    "data",
+    "dev",
+    "proc",
+    "sys",
+    "mnt",
+    "storage",
+    "sdcard",
+    "metadata",
+    "debug_ramdisk",
+    "linkerconfig",
+    "postinstall",
+];
+
+/// Paths nothing is mounted on or below, whatever a module says. `/` only
+/// matches itself. The one exception is `/apex`: with `allow_apex`, module
+/// files are bound over files inside active APEX mounts.
+pub const DENIED_MOUNT_TARGETS: &[&str] = &[
+    "/", "/data", "/proc", "/sys", "/dev", "/apex", "/storage", "/mnt",
+];
+
 pub const SENSITIVE_PARTITIONS: &[&str] = &[
     "vendor",
     "product",
@@ -64,5 +124,78 @@ pub const SENSITIVE_PARTITIONS: &[&str] = &[
     "prism",
 ];
 
+/// On-disk locations the mount engine reads and writes. `Default` gives the
+/// device paths above; library users and tests can point them elsewhere and
+/// hand the result to [`crate::core::MountController::with_paths`].
+#[derive(Debug, Clone)]
+pub struct Paths {
+    pub run_dir: PathBuf,
+    pub state_file: PathBuf,
+    pub journal_file: PathBuf,
+    pub selinux_report_file: PathBuf,
+    pub rules_dir: PathBuf,
+    pub staging_dir: PathBuf,
+    pub system_rw_dir: PathBuf,
+    pub module_prop_file: PathBuf,
+    pub last_good_plan_file: PathBuf,
+    pub mount_ns_dir: PathBuf,
+    pub trace_file: PathBuf,
+    pub deferred_file: PathBuf,
+    pub integrity_file: PathBuf,
+    /// `last_summary.txt` is written next to it.
+    pub summary_file: PathBuf,
+    /// `image_modules` are mounted at `<bundles_dir>/<name>`.
+    pub bundles_dir: PathBuf,
+}
+
+impl Default for Paths {
+    fn default() -> Self {
+        Self {
+            run_dir: PathBuf::from(RUN_DIR),
+            state_file: PathBuf::from(STATE_FILE),
+            journal_file: PathBuf::from(MOUNT_JOURNAL_FILE),
+            selinux_report_file: PathBuf::from(SELINUX_REPORT_FILE),
+            rules_dir: PathBuf::from(RULES_DIR),
+            staging_dir: PathBuf::from(STAGING_DIR),
+            system_rw_dir: PathBuf::from(SYSTEM_RW_DIR),
+            module_prop_file: PathBuf::from(MODULE_PROP_FILE),
+            last_good_plan_file: PathBuf::from(LAST_GOOD_PLAN_FILE),
+            mount_ns_dir: PathBuf::from(MOUNT_NS_DIR),
+            trace_file: PathBuf::from(TRACE_FILE),
+            deferred_file: PathBuf::from(DEFERRED_FILE),
+            integrity_file: PathBuf::from(INTEGRITY_FILE),
+            summary_file: PathBuf::from(SUMMARY_FILE),
+            bundles_dir: PathBuf::from(BUNDLES_DIR),
+        }
+    }
+}
+
+impl Paths {
+    /// The default layout rebased under `root`, e.g. a fake device tree.
+    pub fn under(root: &Path) -> Self {
+        let rebase = |p: PathBuf| root.join(p.strip_prefix("/").unwrap_or(&p));
+        let defaults = Self::default();
+
+        Self {
+            run_dir: rebase(defaults.run_dir),
+            state_file: rebase(defaults.state_file),
+            journal_file: rebase(defaults.journal_file),
+            selinux_report_file: rebase(defaults.selinux_report_file),
+            rules_dir: rebase(defaults.rules_dir),
+            staging_dir: rebase(defaults.staging_dir),
+            system_rw_dir: rebase(defaults.system_rw_dir),
+            module_prop_file: rebase(defaults.module_prop_file),
+            last_good_plan_file: rebase(defaults.last_good_plan_file),
+            mount_ns_dir: rebase(defaults.mount_ns_dir),
+            trace_file: rebase(defaults.trace_file),
+            deferred_file: rebase(defaults.deferred_file),
+            integrity_file: rebase(defaults.integrity_file),
+            summary_file: rebase(defaults.summary_file),
+            bundles_dir: rebase(defaults.bundles_dir),
+        }
+    }
+}
+
 pub const REPLACE_DIR_FILE_NAME: &str = ".replace";
+pub const RW_MARKER_SUFFIX: &str = ".rw";
 pub const REPLACE_DIR_XATTR: &str = "trusted.overlay.opaque";
@@ -0,0 +1,29 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Hybrid Mount engine.
+//!
+//! The `meta-hybrid` binary is a thin CLI over this crate. The stable surface
+//! for other tools is:
+//!
+//! - [`Config`] and [`Paths`] to describe a run,
+//! - [`MountController`] and its typestates in [`core::manager`] to drive it,
+//! - [`core::inventory::scan`], [`core::ops::planner::generate`] and
+//!   [`core::ops::planner::MountPlan::analyze`] for dry runs and diagnostics,
+//!   with `_with_paths`/`_with_probe` variants that run against a fake root
+//!   through [`Paths::under`] and [`core::ops::probe::RootedSystem`],
+//! - [`RuntimeState`], [`core::storage::status`] and
+//!   [`core::inventory::model::list`] for reporting.
+
+pub mod conf;
+pub mod core;
+pub mod defs;
+pub mod mount;
+pub mod sys;
+pub mod utils;
+
+pub use crate::{
+    conf::config::Config,
+    core::{MountController, state::RuntimeState},
+    defs::Paths,
+};
@@ -1,22 +1,30 @@
 // Copyright 2026 Hybrid Mount Developers
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-mod conf;
-mod core;
-mod defs;
-mod mount;
-mod sys;
-mod utils;
+use std::path::{Path, PathBuf};
 
-use core::MountController;
-use std::path::PathBuf;
-
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, ensure};
 use clap::Parser;
-use conf::{
-    cli::{Cli, Commands},
-    cli_handlers,
-    config::Config,
+use meta_hybrid::{
+    conf::{
+        cli::{Cli, Commands},
+        cli_handlers,
+        config::{Config, LogFormat, LogLevel, NamespaceMode},
+        profile,
+    },
+    core::{
+        self, MountController,
+        failure::{self, Classify, FailureClass, RunError},
+        state::RuntimeState,
+        summary,
+    },
+    defs::{self, Paths},
+    sys::{
+        denylist,
+        namespace::{self, NamespaceReport},
+        root_backend,
+    },
+    utils::{self, pool},
 };
 use mimalloc::MiMalloc;
 
@@ -51,68 +59,238 @@ fn load_config(cli: &Cli) -> Result<Config> {
 
 fn load_final_config(cli: &Cli) -> Result<Config> {
     let mut config = load_config(cli)?;
+    let config_path = cli
+        .config
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(defs::CONFIG_FILE));
+    match profile::resolve(&mut config, &config_path, cli.profile.as_deref()) {
+        Ok(Some(applied)) => log::info!(">> Applied config profile {}", applied.name),
+        Ok(None) => {}
+        Err(e) => {
+            log::warn!("!! Ignoring config profile: {:#}", e);
+            config.profile = None;
+        }
+    }
     config.merge_with_cli(
         cli.moduledir.clone(),
         cli.mountsource.clone(),
         cli.verbose,
+        cli.debug_modules.clone(),
         cli.partitions.clone(),
+        cli.force_rebuild_image,
+        cli.daemon,
+        cli.use_last_good,
     );
     Ok(config)
 }
 
-fn main() -> Result<()> {
-    // [Change] Create RUN_DIR immediately as it now hosts critical state files (boot_counter)
-    utils::ensure_dir_exists(defs::RUN_DIR)
-        .with_context(|| format!("Failed to create run directory: {}", defs::RUN_DIR))?;
+fn build_thread_pool(threads: Option<usize>) {
+    if let Err(e) = pool::init(threads) {
+        eprintln!("{:#}", e);
+    }
+}
 
-    let threads = std::thread::available_parallelism()
-        .map(|n| n.get())
-        .unwrap_or(4);
+/// One boot-time mount sequence, with its failure classed by phase.
+fn run(
+    config: Config,
+    namespace: NamespaceReport,
+    mnt_base: &Path,
+    img_path: &Path,
+) -> Result<(), RunError> {
+    MountController::new(config)
+        .in_namespace(namespace)
+        .init_storage(mnt_base, img_path)
+        .classify(FailureClass::Storage, "Failed to initialize storage")?
+        .scan_and_sync()
+        .classify(FailureClass::Inventory, "Failed to scan and sync modules")?
+        .generate_plan()
+        .classify(FailureClass::Plan, "Failed to generate mount plan")?
+        .execute()
+        .classify(FailureClass::Execution, "Failed to execute mount plan")?
+        .finalize()
+        .classify(FailureClass::Execution, "Failed to finalize boot sequence")
+}
 
-    let _ = rayon::ThreadPoolBuilder::new()
-        .num_threads(threads)
-        .build_global();
+/// Records a failed run in the state file and the boot summary, and exits
+/// with its code. A cancelled run has already written both.
+fn fail(error: &RunError, summary_text: bool) -> ! {
+    if error.cancelled().is_none() {
+        if let Err(e) = failure::record(Path::new(defs::STATE_FILE), error) {
+            eprintln!("Failed to record the failure: {:#}", e);
+        }
+        let state = RuntimeState::load_from(Path::new(defs::STATE_FILE)).unwrap_or_default();
+        summary::record(&state, 0, &Paths::default().summary_file, summary_text);
+    }
+    std::process::exit(error.exit_code());
+}
 
+fn main() -> Result<()> {
     let cli = Cli::parse();
 
+    // Rescue must work when /data/adb is in any state, so it runs before
+    // anything else touches it.
+    if let Some(Commands::Rescue {
+        disable_all,
+        disable,
+        remove_images,
+        clear_state,
+        yes,
+    }) = &cli.command
+    {
+        return cli_handlers::handle_rescue(
+            *disable_all,
+            disable,
+            *remove_images,
+            *clear_state,
+            *yes,
+        );
+    }
+
+    // Nor may cleaning up after the module recreate what it deletes.
+    if let Some(Commands::SelfUninstall { keep_config }) = &cli.command {
+        return cli_handlers::handle_self_uninstall(*keep_config);
+    }
+
+    // A sysroot is a device tree dumped elsewhere, often on a PC: only
+    // planning runs, and nothing is written under /data.
+    if cli.sysroot.is_some() {
+        ensure!(
+            match &cli.command {
+                Some(command) => command.allows_sysroot(),
+                None => cli.dry_run,
+            },
+            "--sysroot only plans; use --dry-run, plan, plan-diff, conflicts or diagnostics"
+        );
+    } else {
+        // [Change] Create RUN_DIR immediately as it now hosts critical state files (boot_counter)
+        utils::ensure_dir_exists(defs::RUN_DIR)
+            .with_context(|| format!("Failed to create run directory: {}", defs::RUN_DIR))?;
+    }
+
     if let Some(command) = &cli.command {
+        // Like the boot path, this has to happen while the process still
+        // has a single thread.
+        if let Commands::Bench { mount: true, .. } = command {
+            namespace::enter_private()
+                .context("Refusing to benchmark mounts outside a private mount namespace")?;
+        }
+        build_thread_pool(load_config(&cli).ok().and_then(|c| c.threads));
+
         match command {
             Commands::GenConfig { output } => cli_handlers::handle_gen_config(output)?,
-            Commands::ShowConfig => cli_handlers::handle_show_config(&cli)?,
+            Commands::ShowConfig { resolved } => cli_handlers::handle_show_config(&cli, *resolved)?,
             Commands::SaveConfig { payload } => cli_handlers::handle_save_config(payload)?,
             Commands::SaveModuleRules { module, payload } => {
                 cli_handlers::handle_save_module_rules(module, payload)?
             }
-            Commands::Storage => cli_handlers::handle_storage()?,
+            Commands::Api { request } => cli_handlers::handle_api(&cli, request.as_deref())?,
+            Commands::Storage => cli_handlers::handle_storage(&cli)?,
             Commands::Modules => cli_handlers::handle_modules(&cli)?,
-            Commands::Conflicts => cli_handlers::handle_conflicts(&cli)?,
-            Commands::Diagnostics => cli_handlers::handle_diagnostics(&cli)?,
+            Commands::Conflicts { path } => cli_handlers::handle_conflicts(&cli, path.as_deref())?,
+            Commands::Diagnostics { preview_updates } => {
+                cli_handlers::handle_diagnostics(&cli, *preview_updates)?
+            }
+            Commands::Plan => cli_handlers::handle_plan(&cli)?,
+            Commands::PlanDiff { baseline } => cli_handlers::handle_plan_diff(&cli, baseline)?,
+            Commands::Check { config } => cli_handlers::handle_check(&cli, config.as_deref())?,
+            Commands::Status => cli_handlers::handle_status(&cli)?,
+            Commands::Snapshot { label } => cli_handlers::handle_snapshot(&cli, label)?,
+            Commands::Snapshots => cli_handlers::handle_snapshots(&cli)?,
+            Commands::Restore { id } => cli_handlers::handle_restore(&cli, id)?,
+            Commands::WinnowSet {
+                path,
+                module,
+                force,
+            } => cli_handlers::handle_winnow_set(&cli, path, module, *force)?,
+            Commands::WinnowUnset { path } => cli_handlers::handle_winnow_unset(&cli, path)?,
+            Commands::WinnowList => cli_handlers::handle_winnow_list(&cli)?,
+            Commands::WinnowSuggest { apply } => cli_handlers::handle_winnow_suggest(&cli, *apply)?,
+            Commands::Exclude { id } => cli_handlers::handle_exclude(&cli, id)?,
+            Commands::Include { id } => cli_handlers::handle_include(&cli, id)?,
+            Commands::Stage { zip } => cli_handlers::handle_stage(zip)?,
+            Commands::Unstage { id } => cli_handlers::handle_unstage(id)?,
+            Commands::Rescue { .. } | Commands::SelfUninstall { .. } => {
+                unreachable!("handled before startup")
+            }
+            Commands::CacheClear => cli_handlers::handle_cache_clear()?,
+            Commands::Summary => cli_handlers::handle_summary(&cli)?,
+            Commands::Trace { previous } => cli_handlers::handle_trace(*previous)?,
+            Commands::Bench { iterations, mount } => {
+                cli_handlers::handle_bench(&cli, *iterations, *mount)?
+            }
+            Commands::MountDeferred => {
+                cli_handlers::handle_mount_deferred(load_final_config(&cli)?)?
+            }
+            Commands::Daemon => cli_handlers::handle_daemon(load_final_config(&cli)?)?,
             Commands::Poaceae { target, action } => cli_handlers::handle_poaceae(target, action)?,
         }
 
         return Ok(());
     }
 
-    let mut config = load_final_config(&cli)?;
+    if cli.dry_run {
+        build_thread_pool(load_config(&cli).ok().and_then(|c| c.threads));
+        return cli_handlers::handle_plan(&cli);
+    }
+
+    let mut config = match load_final_config(&cli) {
+        Ok(config) => config,
+        Err(e) => {
+            let error = RunError::new(FailureClass::Config, e);
+            eprintln!("!! {}", error);
+            fail(&error, false);
+        }
+    };
+
+    // Before any second thread exists, which would make unshare fail.
+    let namespace = match config.namespace_mode {
+        NamespaceMode::Global => Ok(NamespaceReport::default()),
+        NamespaceMode::Clone => namespace::enter_clone(Path::new(defs::MOUNT_NS_DIR)),
+    };
+
+    build_thread_pool(config.threads);
+
+    if let Err(e) = utils::cancel::install() {
+        eprintln!("{:#}", e);
+    }
 
-    if utils::check_zygisksu_enforce_status() {
+    let denylist = denylist::status(config.denylist_provider);
+    if denylist.enforcing {
         if config.allow_umount_coexistence {
-            if config.verbose {
+            if config.effective_log_level() >= LogLevel::Debug {
                 println!(
-                    ">> ZygiskSU Enforce!=0 detected, but Umount Coexistence enabled. Respecting \
-                        user config."
+                    ">> {:?} denylist enforcing, but Umount Coexistence enabled. Respecting user \
+                        config.",
+                    denylist.provider
                 );
             }
         } else {
-            if config.verbose {
-                println!(">> ZygiskSU Enforce!=0 detected. Forcing DISABLE_UMOUNT to TRUE.");
+            if config.effective_log_level() >= LogLevel::Debug {
+                println!(
+                    ">> {:?} denylist enforcing. Forcing DISABLE_UMOUNT to TRUE.",
+                    denylist.provider
+                );
             }
 
             config.disable_umount = true;
         }
     }
 
-    utils::init_logging(config.verbose).context("Failed to initialize logging")?;
+    if let Err(e) = utils::rotate_log(
+        defs::DAEMON_LOG_FILE,
+        config.log_max_size,
+        config.log_max_files,
+    ) {
+        eprintln!("Failed to rotate {}: {:#}", defs::DAEMON_LOG_FILE, e);
+    }
+
+    let log_guard = utils::init_logging(
+        config.effective_log_level().filter(),
+        &config.debug_modules,
+        config.log_format == LogFormat::Json,
+        config.log_buffer_kb,
+    )
+    .context("Failed to initialize logging")?;
 
     let camouflage_name = utils::random_kworker_name();
 
@@ -123,17 +301,52 @@ fn main() -> Result<()> {
     log::info!(">> Initializing Hybrid Mount Daemon...");
 
     log::debug!("Process camouflaged as: {}", camouflage_name);
+    log::info!(">> Worker threads: {}", pool::size());
 
     if let Ok(version) = std::fs::read_to_string("/proc/sys/kernel/osrelease") {
         log::debug!("Kernel Version: {}", version.trim());
     }
 
-    utils::check_ksu();
+    let backend = root_backend::detect();
+    log::info!(">> Root backend: {:?}", backend);
+    root_backend::install(backend);
+
+    let namespace = match namespace {
+        Ok(namespace) => {
+            match (&namespace.ns_path, namespace.mode) {
+                (Some(pin), _) => {
+                    log::info!(
+                        ">> Mounting in a cloned mount namespace pinned at {}",
+                        pin.display()
+                    )
+                }
+                (None, NamespaceMode::Clone) => log::warn!(
+                    "!! Mounting in a cloned mount namespace that could not be pinned; it ends with this process"
+                ),
+                (None, NamespaceMode::Global) => {}
+            }
+            namespace
+        }
+        Err(e) => {
+            log::warn!(
+                "!! Cannot clone the mount namespace, mounting globally: {:#}",
+                e
+            );
+            NamespaceReport::default()
+        }
+    };
 
     if config.disable_umount {
         log::warn!("!! Umount is DISABLED via config.");
     }
 
+    config.apply_stealth();
+    log::debug!(
+        "Mount source: {}, mount base: {}",
+        config.mountsource,
+        config.hybrid_mnt_dir
+    );
+
     let mnt_base = PathBuf::from(&config.hybrid_mnt_dir);
     let img_path = PathBuf::from(defs::MODULES_IMG_FILE);
 
@@ -141,17 +354,32 @@ fn main() -> Result<()> {
         log::warn!("Backup: Failed to create boot snapshot: {}", e);
     }*/
 
-    MountController::new(config)
-        .init_storage(&mnt_base, &img_path)
-        .context("Failed to initialize storage")?
-        .scan_and_sync()
-        .context("Failed to scan and sync modules")?
-        .generate_plan()
-        .context("Failed to generate mount plan")?
-        .execute()
-        .context("Failed to execute mount plan")?
-        .finalize()
-        .context("Failed to finalize boot sequence")?;
+    let daemon = config.daemon;
+    let summary_text = config.summary_text;
+
+    let progress = cli.progress_socket.as_ref().and_then(|path| {
+        utils::progress::open(path)
+            .map_err(|e| log::warn!("Progress reporting disabled: {:#}", e))
+            .ok()
+    });
+
+    // Recorded from the start so a failed run still has phase times.
+    utils::timing::start_recording();
+    if let Err(e) = run(config, namespace, &mnt_base, &img_path) {
+        match e.cancelled() {
+            Some(cancelled) => log::warn!(">> Mount sequence {}", cancelled),
+            None => log::error!("!! Mount sequence failed: {}", e),
+        }
+        // `fail` exits without running destructors: unlink the socket and
+        // flush the log first.
+        drop(progress);
+        drop(log_guard);
+        fail(&e, summary_text);
+    }
+
+    if daemon && let Err(e) = core::daemon::spawn() {
+        log::warn!("Failed to start daemon mode: {:#}", e);
+    }
 
     Ok(())
 }
@@ -0,0 +1,36 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use rustix::mount::{MountFlags, UnmountFlags, mount_bind, mount_remount, unmount};
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::mount::umount_mgr::send_umountable;
+use crate::utils::trace::{self, TraceOp};
+
+/// Bind-mounts the module file `source` over the stock file `target` and
+/// makes the mount read-only. With `umount` the target is handed to the
+/// root solution for unmounting in denylisted processes.
+pub fn bind_file(source: &Path, target: &Path, umount: bool) -> Result<()> {
+    let event = trace::begin(TraceOp::Bind, target);
+    let bound = mount_bind(source, target);
+    event.finish(&bound);
+    bound.with_context(|| format!("bind mount {} -> {}", source.display(), target.display()))?;
+
+    let event = trace::begin(TraceOp::Remount, target);
+    let remounted = mount_remount(target, MountFlags::RDONLY | MountFlags::BIND, "");
+    event.finish(&remounted);
+    if let Err(e) = remounted {
+        let _ = unmount(target, UnmountFlags::DETACH);
+        return Err(e).with_context(|| format!("make {} read-only", target.display()));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if umount && let Err(e) = send_umountable(target) {
+        log::warn!("Failed to schedule unmount for {}: {}", target.display(), e);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,87 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use procfs::process::{MMapPath, Process, all_processes};
+
+use crate::{
+    conf::config::BusyFilePolicy,
+    mount::node::{Node, NodeFileType},
+};
+
+/// Stock files some process is executing or has mapped (binaries and
+/// libraries), gathered from `/proc/*/exe` and `/proc/*/maps`.
+fn busy_paths() -> HashSet<PathBuf> {
+    let Ok(processes) = all_processes() else {
+        return HashSet::new();
+    };
+
+    let mut busy = HashSet::new();
+    for process in processes.flatten() {
+        collect_process(&process, &mut busy);
+    }
+    busy
+}
+
+fn collect_process(process: &Process, busy: &mut HashSet<PathBuf>) {
+    if let Ok(exe) = process.exe() {
+        busy.insert(exe);
+    }
+    if let Ok(maps) = process.maps() {
+        busy.extend(maps.into_iter().filter_map(|map| match map.pathname {
+            MMapPath::Path(path) => Some(path),
+            _ => None,
+        }));
+    }
+}
+
+/// Applies `policy` to module files that would replace a stock file in use.
+/// With `Skip` the node is left out of the mount, so the stock file stays
+/// visible; files inside a replaced directory cannot be kept apart from it
+/// and are only warned about. Returns the skipped paths.
+pub fn preflight(root: &mut Node, policy: BusyFilePolicy) -> Vec<PathBuf> {
+    if policy == BusyFilePolicy::Force {
+        return Vec::new();
+    }
+
+    let busy = busy_paths();
+    let mut skipped = Vec::new();
+    if !busy.is_empty() {
+        walk(root, Path::new("/"), false, policy, &busy, &mut skipped);
+    }
+    skipped
+}
+
+fn walk(
+    node: &mut Node,
+    parent: &Path,
+    in_replace: bool,
+    policy: BusyFilePolicy,
+    busy: &HashSet<PathBuf>,
+    skipped: &mut Vec<PathBuf>,
+) {
+    for (name, child) in &mut node.children {
+        let path = parent.join(name);
+
+        match child.file_type {
+            NodeFileType::Directory => {
+                let in_replace = in_replace || child.replace;
+                walk(child, &path, in_replace, policy, busy, skipped);
+            }
+            NodeFileType::RegularFile if child.module_path.is_some() && busy.contains(&path) => {
+                if policy == BusyFilePolicy::Skip && !in_replace {
+                    log::warn!("{} is in use, keeping the stock file", path.display());
+                    child.skip = true;
+                    skipped.push(path);
+                } else {
+                    log::warn!("{} is in use, replacing it anyway", path.display());
+                }
+            }
+            _ => {}
+        }
+    }
+}
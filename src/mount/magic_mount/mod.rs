@@ -1,13 +1,16 @@
 // Copyright 2026 Hybrid Mount Developers
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+mod busy;
 mod utils;
 
+pub use utils::collect_module_files;
+
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fs,
-    path::{Path, PathBuf},
-    sync::atomic::AtomicU32,
+    path::{Component, Path, PathBuf},
+    sync::atomic::{AtomicU32, Ordering},
 };
 
 use anyhow::{Context, Result, bail};
@@ -19,15 +22,76 @@ use rustix::mount::{
 #[cfg(any(target_os = "linux", target_os = "android"))]
 use crate::mount::umount_mgr::{self, send_umountable};
 use crate::{
+    conf::config::BusyFilePolicy,
+    core::ops::{partition_map::PartitionMap, probe::LiveSystem},
     mount::{
-        magic_mount::utils::{clone_symlink, collect_module_files, mount_mirror},
+        magic_mount::utils::{clone_symlink, mount_mirror_all},
         node::{Node, NodeFileType},
     },
-    utils::ensure_dir_exists,
+    utils::{
+        ensure_dir_exists,
+        trace::{self, TraceOp},
+    },
 };
 
-static MOUNTED_FILES: AtomicU32 = AtomicU32::new(0);
-static MOUNTED_SYMBOLS_FILES: AtomicU32 = AtomicU32::new(0);
+/// What a single magic mount run did under one top-level partition.
+#[derive(Debug, Default, Clone)]
+pub struct MountCounters {
+    pub files: u32,
+    pub symlinks: u32,
+    pub tmpfs_dirs: u32,
+    /// Stock entries recreated around module files inside tmpfs dirs.
+    pub mirrored: u32,
+    /// Module files left out because the stock file was in use.
+    pub busy_skipped: Vec<PathBuf>,
+}
+
+/// Per-entry debug lines logged in each directory; the rest of the
+/// directory is summarised in one line once it is done.
+pub(crate) const LOG_SAMPLE_PER_DIR: u32 = 8;
+
+/// State shared by every node of one magic mount run.
+#[derive(Default)]
+struct MountContext {
+    per_partition: HashMap<String, MountCounters>,
+    // first path mounted for each hard-linked (dev, ino)
+    linked: HashMap<(u64, u64), PathBuf>,
+    // module files that skip the read-only remount
+    writable: HashSet<PathBuf>,
+    // entries mounted so far in each directory, for log sampling
+    logged: HashMap<PathBuf, u32>,
+}
+
+impl MountContext {
+    fn counters(&mut self, partition: String) -> &mut MountCounters {
+        self.per_partition.entry(partition).or_default()
+    }
+
+    /// Counts an entry of `module` mounted into `dir` and tells whether it
+    /// gets its own debug line.
+    fn sample(&mut self, dir: &Path, module: Option<&str>) -> bool {
+        if !crate::utils::module_debug_enabled(module) {
+            return false;
+        }
+        let seen = self.logged.entry(dir.to_path_buf()).or_default();
+        *seen += 1;
+        *seen <= LOG_SAMPLE_PER_DIR
+    }
+
+    /// Logs how many entries of `dir` were not logged one by one.
+    fn summarize(&mut self, dir: &Path) {
+        if let Some(seen) = self.logged.remove(dir)
+            && seen > LOG_SAMPLE_PER_DIR
+        {
+            log::debug!(
+                "{}: {} entries mounted, {} not logged individually",
+                dir.display(),
+                seen,
+                seen - LOG_SAMPLE_PER_DIR
+            );
+        }
+    }
+}
 
 struct MagicMount {
     node: Node,
@@ -59,13 +123,23 @@ impl MagicMount {
         }
     }
 
-    fn do_mount(&mut self) -> Result<()> {
+    fn partition(&self) -> String {
+        self.path
+            .components()
+            .find_map(|c| match c {
+                Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+                _ => None,
+            })
+            .unwrap_or_else(|| "/".to_string())
+    }
+
+    fn do_mount(&mut self, ctx: &mut MountContext) -> Result<()> {
         match self.node.file_type {
-            NodeFileType::Symlink => self.symlink(),
-            NodeFileType::RegularFile => self.regular_file(),
-            NodeFileType::Directory => self.directory(),
+            NodeFileType::Symlink => self.symlink(ctx),
+            NodeFileType::RegularFile => self.regular_file(ctx),
+            NodeFileType::Directory => self.directory(ctx),
             NodeFileType::Whiteout => {
-                log::debug!("file {} is removed", self.path.display());
+                crate::module_debug!(self.module(), "file {} is removed", self.path.display());
                 Ok(())
             }
         }
@@ -73,13 +147,24 @@ impl MagicMount {
 }
 
 impl MagicMount {
-    fn symlink(&self) -> Result<()> {
+    fn parent(&self) -> &Path {
+        self.path.parent().unwrap_or(&self.path)
+    }
+
+    fn module(&self) -> Option<&str> {
+        self.node.module_id.as_deref()
+    }
+
+    fn symlink(&self, ctx: &mut MountContext) -> Result<()> {
         if let Some(module_path) = &self.node.module_path {
-            log::debug!(
-                "create module symlink {} -> {}",
-                module_path.display(),
-                self.work_dir_path.display()
-            );
+            if ctx.sample(self.parent(), self.module()) {
+                crate::module_debug!(
+                    self.module(),
+                    "create module symlink {} -> {}",
+                    module_path.display(),
+                    self.work_dir_path.display()
+                );
+            }
             clone_symlink(module_path, &self.work_dir_path).with_context(|| {
                 format!(
                     "create module symlink {} -> {}",
@@ -87,15 +172,14 @@ impl MagicMount {
                     self.work_dir_path.display(),
                 )
             })?;
-            let mounted = MOUNTED_SYMBOLS_FILES.load(std::sync::atomic::Ordering::Relaxed) + 1;
-            MOUNTED_SYMBOLS_FILES.store(mounted, std::sync::atomic::Ordering::Relaxed);
+            ctx.counters(self.partition()).symlinks += 1;
             Ok(())
         } else {
             bail!("cannot mount root symlink {}!", self.path.display());
         }
     }
 
-    fn regular_file(&self) -> Result<()> {
+    fn regular_file(&self, ctx: &mut MountContext) -> Result<()> {
         let target = if self.has_tmpfs {
             fs::File::create(&self.work_dir_path)?;
             &self.work_dir_path
@@ -107,15 +191,43 @@ impl MagicMount {
             bail!("cannot mount root file {}!", self.path.display());
         }
 
-        let module_path = &self.node.module_path.clone().unwrap();
+        let module_path = self.node.module_path.as_ref().unwrap();
+        let writable = ctx.writable.contains(module_path);
+
+        // Each name of a hard-linked file is bound from a copy that keeps
+        // the links, so stat() through any of them reports the shared ino
+        // and link count; a hard link in the tmpfs workdir could not point
+        // at a bind mount anyway.
+        if let Some(key) = self.node.inode {
+            match ctx.linked.get(&key) {
+                Some(first) => {
+                    crate::module_debug!(
+                        self.module(),
+                        "hard link {} shares inode with {}",
+                        self.path.display(),
+                        first.display()
+                    );
+                }
+                None => {
+                    ctx.linked.insert(key, self.path.clone());
+                }
+            }
+        }
+        let verbose = ctx.sample(self.parent(), self.module());
 
-        log::debug!(
-            "mount module file {} -> {}",
-            module_path.display(),
-            self.work_dir_path.display()
-        );
+        if verbose {
+            crate::module_debug!(
+                self.module(),
+                "mount module file {} -> {}",
+                module_path.display(),
+                self.work_dir_path.display()
+            );
+        }
 
-        mount_bind(module_path, target).with_context(|| {
+        let event = trace::begin(TraceOp::Bind, target);
+        let bound = mount_bind(module_path, target);
+        event.finish(&bound);
+        bound.with_context(|| {
             #[cfg(any(target_os = "linux", target_os = "android"))]
             if self.umount {
                 let _ = send_umountable(target);
@@ -127,17 +239,25 @@ impl MagicMount {
             )
         })?;
 
-        if let Err(e) = mount_remount(target, MountFlags::RDONLY | MountFlags::BIND, "") {
-            log::warn!("make file {} ro: {e:#?}", target.display());
+        if writable {
+            if verbose {
+                crate::module_debug!(self.module(), "keep file {} writable", target.display());
+            }
+        } else {
+            let event = trace::begin(TraceOp::Remount, target);
+            let remounted = mount_remount(target, MountFlags::RDONLY | MountFlags::BIND, "");
+            event.finish(&remounted);
+            if let Err(e) = remounted {
+                log::warn!("make file {} ro: {e:#?}", target.display());
+            }
         }
 
-        let mounted = MOUNTED_FILES.load(std::sync::atomic::Ordering::Relaxed) + 1;
-        MOUNTED_FILES.store(mounted, std::sync::atomic::Ordering::Relaxed);
+        ctx.counters(self.partition()).files += 1;
         Ok(())
     }
 
     #[allow(clippy::too_many_lines)]
-    fn directory(&mut self) -> Result<()> {
+    fn directory(&mut self, ctx: &mut MountContext) -> Result<()> {
         let mut tmpfs = !self.has_tmpfs && self.node.replace && self.node.module_path.is_some();
 
         if !self.has_tmpfs && !tmpfs {
@@ -159,8 +279,9 @@ impl MagicMount {
                 if need {
                     if self.node.module_path.is_none() {
                         log::error!(
-                            "cannot create tmpfs on {}, ignore: {name}",
-                            self.path.display()
+                            "cannot create tmpfs on {}, ignore: {}",
+                            self.path.display(),
+                            name.display()
                         );
                         node.skip = true;
                         continue;
@@ -177,17 +298,22 @@ impl MagicMount {
         }
 
         if tmpfs {
-            mount_bind(&self.work_dir_path, &self.work_dir_path).with_context(|| {
+            let event = trace::begin(TraceOp::Tmpfs, &self.work_dir_path);
+            let bound = mount_bind(&self.work_dir_path, &self.work_dir_path);
+            event.finish(&bound);
+            bound.with_context(|| {
                 format!(
                     "creating tmpfs for {} at {}",
                     self.path.display(),
                     self.work_dir_path.display(),
                 )
             })?;
+            ctx.counters(self.partition()).tmpfs_dirs += 1;
         }
 
+        // A replaced dir shows only module content, so there is nothing to mirror.
         if self.path.exists() && !self.node.replace {
-            self.mount_path(has_tmpfs)?;
+            self.mount_path(has_tmpfs, ctx)?;
         }
 
         if self.node.replace {
@@ -198,7 +324,7 @@ impl MagicMount {
                 );
             }
 
-            log::debug!("dir {} is replaced", self.path.display());
+            crate::module_debug!(self.module(), "dir {} is replaced", self.path.display());
         }
 
         for (name, node) in &self.node.children {
@@ -215,17 +341,21 @@ impl MagicMount {
                     #[cfg(any(target_os = "linux", target_os = "android"))]
                     self.umount,
                 )
-                .do_mount()
+                .do_mount(ctx)
             }
-            .with_context(|| format!("magic mount {}/{name}", self.path.display()))
+            .with_context(|| format!("magic mount {}", self.path.join(name).display()))
             {
                 if has_tmpfs {
                     return Err(e);
                 }
 
-                log::error!("mount child {}/{name} failed: {e:#?}", self.path.display());
+                log::error!(
+                    "mount child {} failed: {e:#?}",
+                    self.path.join(name).display()
+                );
             }
         }
+        ctx.summarize(&self.path);
 
         if tmpfs {
             log::debug!(
@@ -234,14 +364,20 @@ impl MagicMount {
                 self.path.display()
             );
 
-            if let Err(e) = mount_remount(
+            let event = trace::begin(TraceOp::Remount, &self.work_dir_path);
+            let remounted = mount_remount(
                 &self.work_dir_path,
                 MountFlags::RDONLY | MountFlags::BIND,
                 "",
-            ) {
+            );
+            event.finish(&remounted);
+            if let Err(e) = remounted {
                 log::warn!("make dir {} ro: {e:#?}", self.path.display());
             }
-            mount_move(&self.work_dir_path, &self.path).with_context(|| {
+            let event = trace::begin(TraceOp::Move, &self.path);
+            let moved = mount_move(&self.work_dir_path, &self.path);
+            event.finish(&moved);
+            moved.with_context(|| {
                 format!(
                     "moving tmpfs {} -> {}",
                     self.work_dir_path.display(),
@@ -262,64 +398,112 @@ impl MagicMount {
 }
 
 impl MagicMount {
-    fn mount_path(&mut self, has_tmpfs: bool) -> Result<()> {
-        for entry in self.path.read_dir()?.flatten() {
-            let name = entry.file_name().to_string_lossy().to_string();
-            let result = {
-                if let Some(node) = self.node.children.remove(&name) {
-                    if node.skip {
-                        continue;
-                    }
+    fn mount_path(&mut self, has_tmpfs: bool, ctx: &mut MountContext) -> Result<()> {
+        let mut mirror = Vec::new();
 
-                    Self::new(
-                        &node,
-                        &self.path,
-                        &self.work_dir_path,
-                        has_tmpfs,
-                        #[cfg(any(target_os = "linux", target_os = "android"))]
-                        self.umount,
-                    )
-                    .do_mount()
-                    .with_context(|| format!("magic mount {}/{name}", self.path.display()))
-                } else if has_tmpfs {
-                    mount_mirror(&self.path, &self.work_dir_path, &entry)
-                        .with_context(|| format!("mount mirror {}/{name}", self.path.display()))
-                } else {
-                    Ok(())
+        for entry in self.path.read_dir()?.flatten() {
+            let name = entry.file_name();
+            let Some(node) = self.node.children.remove(&name) else {
+                if has_tmpfs {
+                    mirror.push(entry);
                 }
+                continue;
             };
+            if node.skip {
+                // Keep the stock entry visible in place of the skipped node.
+                if has_tmpfs {
+                    mirror.push(entry);
+                }
+                continue;
+            }
+
+            let result = Self::new(
+                &node,
+                &self.path,
+                &self.work_dir_path,
+                has_tmpfs,
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                self.umount,
+            )
+            .do_mount(ctx)
+            .with_context(|| format!("magic mount {}", self.path.join(&name).display()));
 
             if let Err(e) = result {
                 if has_tmpfs {
                     return Err(e);
                 }
-                log::error!("mount child {}/{name} failed: {e:#?}", self.path.display());
+                log::error!(
+                    "mount child {} failed: {e:#?}",
+                    self.path.join(&name).display()
+                );
             }
         }
 
+        if !mirror.is_empty() {
+            let mirrored = AtomicU32::new(0);
+            let result = mount_mirror_all(&self.path, &self.work_dir_path, &mirror, &mirrored)
+                .with_context(|| format!("mount mirror {}", self.path.display()));
+            ctx.counters(self.partition()).mirrored += mirrored.load(Ordering::Relaxed);
+            result?;
+        }
+
         Ok(())
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn magic_mount<P>(
     tmp_path: P,
     module_dir: &Path,
     mount_source: &str,
     extra_partitions: &[String],
-    need_id: HashSet<String>,
+    need_id: &[String],
+    exclusions: &HashMap<String, HashSet<String>>,
+    blacklisted: &HashMap<String, HashSet<PathBuf>>,
+    deny_paths: &[String],
+    writable: HashSet<PathBuf>,
+    busy_policy: BusyFilePolicy,
     #[cfg(any(target_os = "linux", target_os = "android"))] umount: bool,
     #[cfg(not(any(target_os = "linux", target_os = "android")))] _umount: bool,
-) -> Result<()>
+) -> Result<HashMap<String, MountCounters>>
 where
     P: AsRef<Path>,
 {
-    if let Some(root) = collect_module_files(module_dir, extra_partitions, need_id)? {
+    let mut ctx = MountContext {
+        writable,
+        ..Default::default()
+    };
+
+    if let Some(mut root) = collect_module_files(
+        module_dir,
+        extra_partitions,
+        need_id,
+        exclusions,
+        blacklisted,
+        deny_paths,
+        &PartitionMap::resolve(extra_partitions, &LiveSystem),
+    )? {
         log::debug!("collected: {root:?}");
+
+        for path in busy::preflight(&mut root, busy_policy) {
+            let partition = path
+                .components()
+                .find_map(|c| match c {
+                    Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+                    _ => None,
+                })
+                .unwrap_or_else(|| "/".to_string());
+            ctx.counters(partition).busy_skipped.push(path);
+        }
+
         let tmp_root = tmp_path.as_ref();
         let tmp_dir = tmp_root.join("workdir");
         ensure_dir_exists(&tmp_dir)?;
 
-        mount(mount_source, &tmp_dir, "tmpfs", MountFlags::empty(), None).context("mount tmp")?;
+        let event = trace::begin(TraceOp::Tmpfs, &tmp_dir);
+        let mounted = mount(mount_source, &tmp_dir, "tmpfs", MountFlags::empty(), None);
+        event.finish(&mounted);
+        mounted.context("mount tmp")?;
         mount_change(&tmp_dir, MountPropagationFlags::PRIVATE).context("make tmp private")?;
 
         let ret = MagicMount::new(
@@ -330,7 +514,7 @@ where
             #[cfg(any(target_os = "linux", target_os = "android"))]
             umount,
         )
-        .do_mount();
+        .do_mount(&mut ctx);
 
         if let Err(e) = unmount(&tmp_dir, UnmountFlags::DETACH) {
             log::error!("failed to unmount tmp {e}");
@@ -339,12 +523,12 @@ where
         umount_mgr::commit()?;
         fs::remove_dir(tmp_dir).ok();
 
-        let mounted_symbols = MOUNTED_SYMBOLS_FILES.load(std::sync::atomic::Ordering::Relaxed);
-        let mounted_files = MOUNTED_FILES.load(std::sync::atomic::Ordering::Relaxed);
+        let mounted_files: u32 = ctx.per_partition.values().map(|c| c.files).sum();
+        let mounted_symbols: u32 = ctx.per_partition.values().map(|c| c.symlinks).sum();
         log::info!("mounted files: {mounted_files}, mounted symlinks: {mounted_symbols}");
-        ret
+        ret.map(|_| ctx.per_partition)
     } else {
         log::info!("no modules to mount, skipping!");
-        Ok(())
+        Ok(ctx.per_partition)
     }
 }
@@ -2,22 +2,30 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
     fs::{self, DirEntry, Metadata, create_dir, create_dir_all, read_link},
     os::unix::fs::{MetadataExt, symlink},
     path::{Path, PathBuf},
+    sync::atomic::{AtomicU32, Ordering},
 };
 
 use anyhow::{Result, bail};
+use rayon::prelude::*;
 use rustix::{
     fs::{Gid, Mode, Uid, chmod, chown},
     mount::mount_bind,
 };
 
 use crate::{
+    core::ops::partition_map::{self, PartitionMap},
     defs::{DISABLE_FILE_NAME, REMOVE_FILE_NAME, SKIP_MOUNT_FILE_NAME},
-    mount::node::Node,
-    utils::{lgetfilecon, lsetfilecon, validate_module_id},
+    mount::{magic_mount::LOG_SAMPLE_PER_DIR, node::Node},
+    utils::{
+        denied_target, lgetfilecon, lsetfilecon, pool,
+        trace::{self, TraceOp},
+        validate_module_id,
+    },
 };
 
 fn metadata_path<P>(path: P, node: &Node) -> Result<(Metadata, PathBuf)>
@@ -60,56 +68,128 @@ where
     Ok(())
 }
 
-pub fn mount_mirror<P>(path: P, work_dir_path: P, entry: &DirEntry) -> Result<()>
-where
-    P: AsRef<Path>,
-{
-    let path = path.as_ref().join(entry.file_name());
-    let work_dir_path = work_dir_path.as_ref().join(entry.file_name());
-    let file_type = entry.file_type()?;
-
-    if file_type.is_file() {
+/// Mirrors the stock entries `entries` of `path` into `work_dir_path` on the
+/// worker pool. `mirrored` counts every entry recreated by this run. Only the
+/// first [`LOG_SAMPLE_PER_DIR`] entries are logged one by one.
+pub fn mount_mirror_all(
+    path: &Path,
+    work_dir_path: &Path,
+    entries: &[DirEntry],
+    mirrored: &AtomicU32,
+) -> Result<()> {
+    pool::install(|| {
+        entries
+            .par_iter()
+            .enumerate()
+            .try_for_each(|(index, entry)| {
+                let verbose = index < LOG_SAMPLE_PER_DIR as usize;
+                mount_mirror(path, work_dir_path, entry, mirrored, verbose)
+            })
+    })?;
+
+    if entries.len() > LOG_SAMPLE_PER_DIR as usize {
         log::debug!(
-            "mount mirror file {} -> {}",
+            "{}: {} entries mirrored, {} not logged individually",
             path.display(),
-            work_dir_path.display()
+            entries.len(),
+            entries.len() - LOG_SAMPLE_PER_DIR as usize
         );
-        fs::File::create(&work_dir_path)?;
-        mount_bind(&path, &work_dir_path)?;
+    }
+    Ok(())
+}
+
+/// Mirrors one stock entry. An entry that disappears while it is being
+/// mirrored (some vendor daemons rotate files during boot) is skipped
+/// instead of failing the whole mount.
+fn mount_mirror(
+    path: &Path,
+    work_dir_path: &Path,
+    entry: &DirEntry,
+    mirrored: &AtomicU32,
+    verbose: bool,
+) -> Result<()> {
+    let src = path.join(entry.file_name());
+    let dst = work_dir_path.join(entry.file_name());
+
+    match mirror_entry(&src, &dst, entry, mirrored, verbose) {
+        Err(e) if fs::symlink_metadata(&src).is_err() => {
+            log::debug!("mirror source {} vanished: {e:#}", src.display());
+            let _ = fs::remove_dir(&dst).or_else(|_| fs::remove_file(&dst));
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+fn mirror_entry(
+    path: &Path,
+    work_dir_path: &Path,
+    entry: &DirEntry,
+    mirrored: &AtomicU32,
+    verbose: bool,
+) -> Result<()> {
+    let file_type = entry.file_type()?;
+
+    if file_type.is_file() {
+        if verbose {
+            log::debug!(
+                "mount mirror file {} -> {}",
+                path.display(),
+                work_dir_path.display()
+            );
+        }
+        fs::File::create(work_dir_path)?;
+        let event = trace::begin(TraceOp::Mirror, work_dir_path);
+        let bound = mount_bind(path, work_dir_path);
+        event.finish(&bound);
+        bound?;
     } else if file_type.is_dir() {
-        log::debug!(
-            "mount mirror dir {} -> {}",
-            path.display(),
-            work_dir_path.display()
-        );
-        create_dir(&work_dir_path)?;
+        if verbose {
+            log::debug!(
+                "mount mirror dir {} -> {}",
+                path.display(),
+                work_dir_path.display()
+            );
+        }
+        create_dir(work_dir_path)?;
         let metadata = entry.metadata()?;
-        chmod(&work_dir_path, Mode::from_raw_mode(metadata.mode()))?;
+        chmod(work_dir_path, Mode::from_raw_mode(metadata.mode()))?;
         chown(
-            &work_dir_path,
+            work_dir_path,
             Some(Uid::from_raw(metadata.uid())),
             Some(Gid::from_raw(metadata.gid())),
         )?;
-        lsetfilecon(&work_dir_path, lgetfilecon(&path)?.as_str())?;
-        for entry in path.read_dir()?.flatten() {
-            mount_mirror(&path, &work_dir_path, &entry)?;
-        }
+        lsetfilecon(work_dir_path, lgetfilecon(path)?.as_str())?;
+        let entries: Vec<DirEntry> = path.read_dir()?.flatten().collect();
+        mount_mirror_all(path, work_dir_path, &entries, mirrored)?;
     } else if file_type.is_symlink() {
-        log::debug!(
-            "create mirror symlink {} -> {}",
-            path.display(),
-            work_dir_path.display()
-        );
-        clone_symlink(&path, &work_dir_path)?;
+        if verbose {
+            log::debug!(
+                "create mirror symlink {} -> {}",
+                path.display(),
+                work_dir_path.display()
+            );
+        }
+        clone_symlink(path, work_dir_path)?;
     }
 
+    mirrored.fetch_add(1, Ordering::Relaxed);
     Ok(())
 }
 
+/// Builds the tree of the modules in `need_id` below `module_dir`.
+/// Partitions resolve through `partitions`: a partition mounted on its own
+/// hangs off the root with the content of both its spellings, anything else
+/// stays below `system`. Extra partitions at a denied target, built in or in
+/// `deny_paths`, are dropped.
 pub fn collect_module_files(
     module_dir: &Path,
     extra_partitions: &[String],
-    need_id: HashSet<String>,
+    need_id: &[String],
+    exclusions: &HashMap<String, HashSet<String>>,
+    blacklisted: &HashMap<String, HashSet<PathBuf>>,
+    deny_paths: &[String],
+    partitions: &PartitionMap,
 ) -> Result<Option<Node>> {
     let mut root = Node::new_root("");
     let mut system = Node::new_root("system");
@@ -118,22 +198,31 @@ pub fn collect_module_files(
 
     log::debug!("begin collect module files: {}", module_root.display());
 
-    for entry in module_root.read_dir()?.flatten() {
+    // The first module to claim a path wins, so walk them in layer order.
+    let mut entries: Vec<DirEntry> = module_root.read_dir()?.flatten().collect();
+    entries.sort_by_key(|e| {
+        need_id
+            .iter()
+            .position(|id| e.file_name() == id.as_str())
+            .unwrap_or(usize::MAX)
+    });
+
+    for entry in entries {
         if !entry.file_type()?.is_dir() {
             continue;
         }
 
         let id = entry.file_name().to_str().unwrap().to_string();
-        log::debug!("processing new module: {id}");
+        crate::module_debug!(Some(&id), "processing new module");
 
         if !need_id.contains(&id) {
-            log::debug!("module {id} was blocked.");
+            crate::module_debug!(Some(&id), "module was blocked");
             continue;
         }
 
         let prop = entry.path().join("module.prop");
         if !prop.exists() {
-            log::debug!("skipped module {id}, because not found module.prop");
+            crate::module_debug!(Some(&id), "skipped, because not found module.prop");
             continue;
         }
         let string = fs::read_to_string(prop)?;
@@ -149,79 +238,69 @@ pub fn collect_module_files(
             || entry.path().join(REMOVE_FILE_NAME).exists()
             || entry.path().join(SKIP_MOUNT_FILE_NAME).exists()
         {
-            log::debug!("skipped module {id}, due to disable/remove/skip_mount");
+            crate::module_debug!(Some(&id), "skipped, due to disable/remove/skip_mount");
             continue;
         }
 
-        let mut modified = false;
-        let mut partitions = HashSet::new();
-        partitions.insert("system".to_string());
-        partitions.extend(extra_partitions.iter().cloned());
-
-        for p in &partitions {
-            if entry.path().join(p).is_dir() {
-                modified = true;
-                break;
-            }
-            log::debug!("{id} due not modify {p}");
-        }
-
-        if !modified {
+        let module_root = entry.path();
+        let excluded = exclusions.get(&id);
+        let sources = partition_map::module_sources(&module_root, partitions, &|name| {
+            let known = name == "system"
+                || partition_map::ALIASED_PARTITIONS.contains(&name)
+                || extra_partitions.iter().any(|p| p == name);
+            known && !excluded.is_some_and(|excluded| excluded.contains(name))
+        });
+
+        if sources.is_empty() {
+            crate::module_debug!(Some(&id), "does not modify any partition");
             continue;
         }
 
-        log::debug!("collecting {}", entry.path().display());
-
-        for p in partitions {
-            if !entry.path().join(&p).exists() {
-                continue;
-            }
-
-            has_file.insert(system.collect_module_files(entry.path().join(&p))?);
+        crate::module_debug!(Some(&id), "collecting {}", module_root.display());
+
+        let nested = partitions.nested_sources(&module_root);
+        let skipped = blacklisted.get(&id);
+        let skip = |path: &Path| {
+            skipped.is_some_and(|skipped| {
+                path.strip_prefix(&module_root)
+                    .is_ok_and(|relative| skipped.contains(relative))
+            })
+        };
+        let skip_in_system = |path: &Path| nested.iter().any(|n| n == path) || skip(path);
+
+        for (partition, source) in sources {
+            let collected = if partition == "system" {
+                system.collect_module_files(&source, &id, &skip_in_system)?
+            } else if partitions.is_standalone(&partition) {
+                root.collect_module_dir(OsStr::new(&partition), &source, &id, &skip)?
+            } else {
+                system.collect_module_dir(OsStr::new(&partition), &source, &id, &skip)?
+            };
+            has_file.insert(collected);
         }
     }
 
     if has_file.contains(&true) {
-        const BUILTIN_PARTITIONS: [(&str, bool); 4] = [
-            ("vendor", true),
-            ("system_ext", true),
-            ("product", true),
-            ("odm", false),
-        ];
-
-        for (partition, require_symlink) in BUILTIN_PARTITIONS {
-            let path_of_root = Path::new("/").join(partition);
-            let path_of_system = Path::new("/system").join(partition);
-            if path_of_root.is_dir() && (!require_symlink || path_of_system.is_symlink()) {
-                let name = partition.to_string();
-                if let Some(node) = system.children.remove(&name) {
-                    root.children.insert(name, node);
-                }
-            }
-        }
-
         for partition in extra_partitions {
-            if BUILTIN_PARTITIONS.iter().any(|(p, _)| p == partition) {
-                continue;
-            }
             if partition == "system" {
                 continue;
             }
 
-            let path_of_root = Path::new("/").join(partition);
-            let path_of_system = Path::new("/system").join(partition);
-            let require_symlink = false;
-
-            if path_of_root.is_dir() && (!require_symlink || path_of_system.is_symlink()) {
-                let name = partition.clone();
-                if let Some(node) = system.children.remove(&name) {
-                    log::debug!("attach extra partition '{name}' to root");
-                    root.children.insert(name, node);
-                }
+            let target = partitions
+                .target(partition)
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| Path::new("/").join(partition));
+            if let Some(denied) = denied_target(&target, deny_paths) {
+                log::error!(
+                    "refusing to mount extra partition '{partition}': {} is below {denied}",
+                    target.display()
+                );
+                root.children.remove(OsStr::new(partition));
+                system.children.remove(OsStr::new(partition));
             }
         }
 
-        root.children.insert("system".to_string(), system);
+        root.children.insert("system".into(), system);
         Ok(Some(root))
     } else {
         Ok(None)
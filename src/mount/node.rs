@@ -3,6 +3,7 @@
 
 use std::{
     collections::{HashMap, hash_map::Entry},
+    ffi::{OsStr, OsString},
     fmt,
     fs::{DirEntry, FileType},
     os::unix::fs::{FileTypeExt, MetadataExt},
@@ -10,9 +11,8 @@ use std::{
 };
 
 use anyhow::Result;
-use extattr::lgetxattr;
 
-use crate::defs::{REPLACE_DIR_FILE_NAME, REPLACE_DIR_XATTR};
+use crate::utils;
 
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub enum NodeFileType {
@@ -38,13 +38,18 @@ impl From<FileType> for NodeFileType {
 
 #[derive(Debug, Clone)]
 pub struct Node {
-    pub name: String,
+    /// File name exactly as on disk, which need not be UTF-8.
+    pub name: OsString,
     pub file_type: NodeFileType,
-    pub children: HashMap<String, Self>,
+    pub children: HashMap<OsString, Self>,
     // the module that owned this node
     pub module_path: Option<PathBuf>,
+    /// Id of that module, for its per-file log lines.
+    pub module_id: Option<String>,
     pub replace: bool,
     pub skip: bool,
+    // (dev, ino) of hard-linked regular files
+    pub inode: Option<(u64, u64)>,
 }
 
 impl fmt::Display for Node {
@@ -57,23 +62,37 @@ impl fmt::Display for Node {
 }
 
 impl Node {
-    pub fn collect_module_files<P>(&mut self, module_dir: P) -> Result<bool>
+    /// Adds the entries below `module_dir` of module `module_id`, leaving
+    /// out the paths `skip` returns true for.
+    pub fn collect_module_files<P>(
+        &mut self,
+        module_dir: P,
+        module_id: &str,
+        skip: &dyn Fn(&Path) -> bool,
+    ) -> Result<bool>
     where
         P: AsRef<Path>,
     {
         let dir = module_dir.as_ref();
         let mut has_file = false;
         for entry in dir.read_dir()?.flatten() {
-            let name = entry.file_name().to_string_lossy().to_string();
+            // The marker is carried by `replace`, it is not module content.
+            if skip(&entry.path()) || utils::is_replace_marker(&entry.path()) {
+                continue;
+            }
+            let name = entry.file_name();
 
             let node = match self.children.entry(name.clone()) {
                 Entry::Occupied(o) => Some(o.into_mut()),
-                Entry::Vacant(v) => Self::new_module(&name, &entry).map(|it| v.insert(it)),
+                Entry::Vacant(v) => {
+                    Self::new_module(&name, &entry, module_id).map(|it| v.insert(it))
+                }
             };
 
             if let Some(node) = node {
                 has_file |= if node.file_type == NodeFileType::Directory {
-                    node.collect_module_files(dir.join(&node.name))? || node.replace
+                    node.collect_module_files(dir.join(&node.name), module_id, skip)?
+                        || node.replace
                 } else {
                     true
                 }
@@ -83,37 +102,62 @@ impl Node {
         Ok(has_file)
     }
 
-    fn dir_is_replace<P>(path: P) -> bool
+    /// Adds `module_dir` as the child directory `name`, merged below what
+    /// earlier directories already put there.
+    pub fn collect_module_dir<P>(
+        &mut self,
+        name: &OsStr,
+        module_dir: P,
+        module_id: &str,
+        skip: &dyn Fn(&Path) -> bool,
+    ) -> Result<bool>
     where
         P: AsRef<Path>,
     {
-        if let Ok(v) = lgetxattr(&path, REPLACE_DIR_XATTR)
-            && String::from_utf8_lossy(&v) == "y"
-        {
-            return true;
+        let dir = module_dir.as_ref();
+        let node = self
+            .children
+            .entry(name.to_os_string())
+            .or_insert_with(|| Self {
+                name: name.to_os_string(),
+                file_type: NodeFileType::Directory,
+                children: HashMap::default(),
+                module_path: Some(dir.to_path_buf()),
+                module_id: Some(module_id.to_string()),
+                replace: Self::dir_is_replace(dir),
+                skip: false,
+                inode: None,
+            });
+        if node.file_type != NodeFileType::Directory {
+            return Ok(false);
         }
+        Ok(node.collect_module_files(dir, module_id, skip)? || node.replace)
+    }
 
-        path.as_ref().join(REPLACE_DIR_FILE_NAME).exists()
+    fn dir_is_replace<P>(path: P) -> bool
+    where
+        P: AsRef<Path>,
+    {
+        utils::is_replace_dir(path.as_ref())
     }
 
     pub fn new_root<S>(name: S) -> Self
     where
-        S: AsRef<str> + Into<String>,
+        S: Into<OsString>,
     {
         Self {
             name: name.into(),
             file_type: NodeFileType::Directory,
             children: HashMap::default(),
             module_path: None,
+            module_id: None,
             replace: false,
             skip: false,
+            inode: None,
         }
     }
 
-    pub fn new_module<S>(name: &S, entry: &DirEntry) -> Option<Self>
-    where
-        S: ToString,
-    {
+    pub fn new_module(name: &OsStr, entry: &DirEntry, module_id: &str) -> Option<Self> {
         if let Ok(metadata) = entry.metadata() {
             let path = entry.path();
             let file_type = if metadata.file_type().is_char_device() && metadata.rdev() == 0 {
@@ -124,15 +168,19 @@ impl Node {
             if let Some(file_type) = file_type {
                 let replace = file_type == NodeFileType::Directory && Self::dir_is_replace(&path);
                 if replace {
-                    log::debug!("{} need replace", path.display());
+                    crate::module_debug!(Some(module_id), "{} need replace", path.display());
                 }
+                let inode = (file_type == NodeFileType::RegularFile && metadata.nlink() > 1)
+                    .then(|| (metadata.dev(), metadata.ino()));
                 return Some(Self {
-                    name: name.to_string(),
+                    name: name.to_os_string(),
                     file_type,
                     children: HashMap::default(),
                     module_path: Some(path),
+                    module_id: Some(module_id.to_string()),
                     replace,
                     skip: false,
+                    inode,
                 });
             }
         }
@@ -12,12 +12,13 @@ use std::{
 
 use anyhow::{Result, bail};
 
-use crate::defs;
+use crate::{conf::config::OverlayOptions, defs, utils::denied_target};
 
 #[allow(dead_code)]
 pub fn mount_systemlessly(
     module_id: HashSet<String>,
     extra_partitions: &[String],
+    deny_paths: &[String],
     mount_source: &str,
 ) -> Result<()> {
     let module_dir = Path::new(defs::MODULES_DIR);
@@ -78,12 +79,12 @@ pub fn mount_systemlessly(
         }
     }
 
-    if let Err(e) = mount_partition("system", &system_lowerdir, mount_source) {
+    if let Err(e) = mount_partition("system", &system_lowerdir, deny_paths, mount_source) {
         log::warn!("mount system failed: {:#}", e);
     }
 
     for (k, v) in partition_lowerdir {
-        if let Err(e) = mount_partition(k.clone(), &v, mount_source) {
+        if let Err(e) = mount_partition(k.clone(), &v, deny_paths, mount_source) {
             log::warn!("mount {k} failed: {:#}", e);
         }
     }
@@ -92,7 +93,12 @@ pub fn mount_systemlessly(
 }
 
 #[allow(dead_code)]
-fn mount_partition<S>(partition_name: S, lowerdir: &Vec<String>, mount_source: &str) -> Result<()>
+fn mount_partition<S>(
+    partition_name: S,
+    lowerdir: &Vec<String>,
+    deny_paths: &[String],
+    mount_source: &str,
+) -> Result<()>
 where
     S: AsRef<str>,
 {
@@ -104,6 +110,16 @@ where
 
     let partition = format!("/{partition_name}");
 
+    let target = Path::new(&partition)
+        .canonicalize()
+        .unwrap_or_else(|_| partition.clone().into());
+    if let Some(denied) = denied_target(&target, deny_paths) {
+        bail!(
+            "refusing to mount over {}: below {denied}",
+            target.display()
+        );
+    }
+
     if Path::new(&partition).read_link().is_ok() {
         log::warn!("partition: {partition} is a symlink");
         return Ok(());
@@ -117,5 +133,13 @@ where
         upperdir = Some(system_rw_dir.join(partition_name).join("upperdir"));
     }
 
-    overlayfs::mount_overlay(&partition, lowerdir, workdir, upperdir, mount_source)
+    overlayfs::mount_overlay(
+        &partition,
+        lowerdir,
+        workdir,
+        upperdir,
+        mount_source,
+        &OverlayOptions::default(),
+    )
+    .map(|_| ())
 }
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::{
+    collections::BTreeMap,
     ffi::CString,
     os::fd::AsFd,
     path::{Path, PathBuf},
@@ -13,15 +14,50 @@ use rustix::{
     fs::CWD,
     mount::{
         FsMountFlags, FsOpenFlags, MountAttrFlags, MountFlags, MoveMountFlags, fsconfig_create,
-        fsconfig_set_string, fsmount, fsopen, mount, move_mount,
+        fsconfig_set_flag, fsconfig_set_string, fsmount, fsopen, mount, move_mount,
     },
 };
+use serde::{Deserialize, Serialize};
 
-use crate::mount::{overlayfs::utils::umount_dir, umount_mgr::send_umountable};
+use crate::{
+    conf::config::OverlayOptions,
+    mount::{overlayfs::utils::umount_dir, umount_mgr::send_umountable},
+    utils::trace::{self, TraceOp},
+};
 
-const MAX_LOWERDIR_COUNT: usize = 128;
+pub const MAX_LOWERDIR_COUNT: usize = 128;
+/// Mount options `overlay_options` can set, as named in the mount table.
+const TUNING_OPTIONS: &[&str] = &["xino", "metacopy", "redirect_dir", "userxattr"];
 const MAX_ARG_LENGTH: usize = 3000;
 
+/// Longest `lowerdir` value we pass to the kernel: mount data is limited to
+/// a single page, and we keep room for upperdir/workdir on top.
+pub fn max_lowerdir_len() -> usize {
+    MAX_ARG_LENGTH.min(rustix::param::page_size())
+}
+
+/// Escapes one layer for the `lowerdir` list, where `:` separates layers
+/// and `\` escapes the next character.
+pub fn escape_lowerdir(layer: &str) -> String {
+    let mut escaped = String::with_capacity(layer.len());
+    for c in layer.chars() {
+        if matches!(c, '\\' | ':') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MountMethod {
+    Fsmount,
+    Legacy,
+    /// A single module file bind-mounted over its stock counterpart.
+    Bind,
+}
+
 pub fn mount_overlayfs(
     lower_dirs: &[String],
     lowest: &str,
@@ -29,11 +65,13 @@ pub fn mount_overlayfs(
     workdir: Option<PathBuf>,
     dest: impl AsRef<Path>,
     mount_source: &str,
-) -> Result<()> {
-    let mut valid_lower_dirs: Vec<&str> = lower_dirs
+    options: &OverlayOptions,
+) -> Result<MountMethod> {
+    let mut valid_lower_dirs: Vec<String> = lower_dirs
         .iter()
-        .map(|s| s.as_str())
+        .map(String::as_str)
         .chain(std::iter::once(lowest))
+        .map(escape_lowerdir)
         .collect();
 
     if valid_lower_dirs.len() > MAX_LOWERDIR_COUNT {
@@ -47,24 +85,26 @@ pub fn mount_overlayfs(
 
     let mut lowerdir_config = valid_lower_dirs.join(":");
 
-    if lowerdir_config.len() > MAX_ARG_LENGTH {
+    let max_len = max_lowerdir_len();
+    if lowerdir_config.len() > max_len {
         log::warn!(
             "OverlayFS lowerdir argument too long ({} bytes). Truncating...",
             lowerdir_config.len()
         );
-        while lowerdir_config.len() > MAX_ARG_LENGTH && valid_lower_dirs.len() > 1 {
+        while lowerdir_config.len() > max_len && valid_lower_dirs.len() > 1 {
             valid_lower_dirs.pop();
             lowerdir_config = valid_lower_dirs.join(":");
         }
     }
 
     log::info!(
-        "mount overlayfs on {:?}, layers={}, upperdir={:?}, workdir={:?}, source={}",
+        "mount overlayfs on {:?}, layers={}, upperdir={:?}, workdir={:?}, source={}, options={:?}",
         dest.as_ref(),
         valid_lower_dirs.len(),
         upperdir,
         workdir,
-        mount_source
+        mount_source,
+        options
     );
 
     let upperdir_s = upperdir
@@ -75,15 +115,58 @@ pub fn mount_overlayfs(
         .as_ref()
         .filter(|wd| wd.exists())
         .map(|e| e.display().to_string());
+    let dirs = OverlayDirs {
+        lowerdir: &lowerdir_config,
+        upperdir: upperdir_s.as_deref(),
+        workdir: workdir_s.as_deref(),
+    };
 
+    let params = options.mount_params();
+    match mount_with_params(&dirs, dest.as_ref(), mount_source, &params) {
+        Err(e) if !params.is_empty() => {
+            // Kernels reject tuning options they do not know, or combinations
+            // such as metacopy without redirect_dir, with EINVAL.
+            log::warn!(
+                "overlayfs on {} rejected tuning options ({:#}), retrying without them",
+                dest.as_ref().display(),
+                e
+            );
+            mount_with_params(&dirs, dest.as_ref(), mount_source, &[])
+        }
+        result => result,
+    }
+}
+
+struct OverlayDirs<'a> {
+    lowerdir: &'a str,
+    upperdir: Option<&'a str>,
+    workdir: Option<&'a str>,
+}
+
+fn mount_with_params(
+    dirs: &OverlayDirs,
+    dest: &Path,
+    mount_source: &str,
+    params: &[(&str, Option<&str>)],
+) -> Result<MountMethod> {
+    let event = trace::begin(TraceOp::Overlay, dest);
     let result = (|| {
+        if !crate::utils::kernel_features().overlay_fsopen {
+            return Err(rustix::io::Errno::NOSYS);
+        }
         let fs = fsopen("overlay", FsOpenFlags::FSOPEN_CLOEXEC)?;
         let fs = fs.as_fd();
-        fsconfig_set_string(fs, "lowerdir", &lowerdir_config)?;
-        if let (Some(upperdir), Some(workdir)) = (&upperdir_s, &workdir_s) {
+        fsconfig_set_string(fs, "lowerdir", dirs.lowerdir)?;
+        if let (Some(upperdir), Some(workdir)) = (dirs.upperdir, dirs.workdir) {
             fsconfig_set_string(fs, "upperdir", upperdir)?;
             fsconfig_set_string(fs, "workdir", workdir)?;
         }
+        for (key, value) in params {
+            match value {
+                Some(value) => fsconfig_set_string(fs, *key, *value)?,
+                None => fsconfig_set_flag(fs, *key)?,
+            }
+        }
         fsconfig_set_string(fs, "source", mount_source)?;
         fsconfig_create(fs)?;
         let mount = fsmount(fs, FsMountFlags::FSMOUNT_CLOEXEC, MountAttrFlags::empty())?;
@@ -91,32 +174,65 @@ pub fn mount_overlayfs(
             mount.as_fd(),
             "",
             CWD,
-            dest.as_ref(),
+            dest,
             MoveMountFlags::MOVE_MOUNT_F_EMPTY_PATH,
         )
     })();
+    event.finish(&result);
 
     if let Err(e) = result {
         log::warn!("fsopen mount failed: {:#}, fallback to mount", e);
-        let safe_lower = lowerdir_config.replace(',', "\\,");
+        let safe_lower = dirs.lowerdir.replace(',', "\\,");
         let mut data = format!("lowerdir={safe_lower}");
 
-        if let (Some(upperdir), Some(workdir)) = (upperdir_s, workdir_s) {
+        if let (Some(upperdir), Some(workdir)) = (dirs.upperdir, dirs.workdir) {
             data = format!(
                 "{data},upperdir={},workdir={}",
                 upperdir.replace(',', "\\,"),
                 workdir.replace(',', "\\,")
             );
         }
-        mount(
+        for (key, value) in params {
+            match value {
+                Some(value) => data.push_str(&format!(",{key}={value}")),
+                None => data.push_str(&format!(",{key}")),
+            }
+        }
+        let data = CString::new(data)?;
+        let event = trace::begin(TraceOp::Overlay, dest);
+        let mounted = mount(
             mount_source,
-            dest.as_ref(),
+            dest,
             "overlay",
             MountFlags::empty(),
-            Some(CString::new(data)?.as_c_str()),
-        )?;
+            Some(data.as_c_str()),
+        );
+        event.finish(&mounted);
+        mounted?;
+        return Ok(MountMethod::Legacy);
     }
-    Ok(())
+    Ok(MountMethod::Fsmount)
+}
+
+/// Tuning options of the topmost overlay mount on `dest`, as the kernel
+/// reports them in the mount table. Flags without a value read as `on`.
+pub fn effective_options(dest: &Path) -> BTreeMap<String, String> {
+    let Ok(mounts) = Process::myself().and_then(|p| p.mountinfo()) else {
+        return BTreeMap::new();
+    };
+    mounts
+        .0
+        .into_iter()
+        .rev()
+        .find(|m| m.fs_type == "overlay" && m.mount_point == dest)
+        .map(|m| {
+            m.super_options
+                .into_iter()
+                .filter(|(key, _)| TUNING_OPTIONS.contains(&key.as_str()))
+                .map(|(key, value)| (key, value.unwrap_or_else(|| "on".to_string())))
+                .collect()
+        })
+        .unwrap_or_default()
 }
 
 pub fn bind_mount(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
@@ -126,32 +242,31 @@ pub fn bind_mount(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
         to.as_ref().display()
     );
     use rustix::mount::{OpenTreeFlags, open_tree};
-    match open_tree(
+    let event = trace::begin(TraceOp::Bind, to.as_ref());
+    let mounted = match open_tree(
         CWD,
         from.as_ref(),
         OpenTreeFlags::OPEN_TREE_CLOEXEC
             | OpenTreeFlags::OPEN_TREE_CLONE
             | OpenTreeFlags::AT_RECURSIVE,
     ) {
-        Result::Ok(tree) => {
-            move_mount(
-                tree.as_fd(),
-                "",
-                CWD,
-                to.as_ref(),
-                MoveMountFlags::MOVE_MOUNT_F_EMPTY_PATH,
-            )?;
-        }
-        _ => {
-            mount(
-                from.as_ref(),
-                to.as_ref(),
-                "",
-                MountFlags::BIND | MountFlags::REC,
-                None,
-            )?;
-        }
-    }
+        Result::Ok(tree) => move_mount(
+            tree.as_fd(),
+            "",
+            CWD,
+            to.as_ref(),
+            MoveMountFlags::MOVE_MOUNT_F_EMPTY_PATH,
+        ),
+        _ => mount(
+            from.as_ref(),
+            to.as_ref(),
+            "",
+            MountFlags::BIND | MountFlags::REC,
+            None,
+        ),
+    };
+    event.finish(&mounted);
+    mounted?;
     Ok(())
 }
 
@@ -161,6 +276,7 @@ fn mount_overlay_child(
     module_roots: &Vec<String>,
     stock_root: &String,
     mount_source: &str,
+    options: &OverlayOptions,
 ) -> Result<()> {
     if !module_roots
         .iter()
@@ -191,6 +307,7 @@ fn mount_overlay_child(
         None,
         mount_point,
         mount_source,
+        options,
     ) {
         log::warn!("failed: {:#}, fallback to bind mount", e);
         bind_mount(stock_root, mount_point)?;
@@ -205,7 +322,8 @@ pub fn mount_overlay(
     workdir: Option<PathBuf>,
     upperdir: Option<PathBuf>,
     mount_source: &str,
-) -> Result<()> {
+    options: &OverlayOptions,
+) -> Result<MountMethod> {
     log::info!("mount overlay for {}", root);
     std::env::set_current_dir(root).with_context(|| format!("failed to chdir to {root}"))?;
     let stock_root = ".";
@@ -224,8 +342,16 @@ pub fn mount_overlay(
     mount_seq.sort();
     mount_seq.dedup();
 
-    mount_overlayfs(module_roots, root, upperdir, workdir, root, mount_source)
-        .with_context(|| "mount overlayfs for root failed")?;
+    let method = mount_overlayfs(
+        module_roots,
+        root,
+        upperdir,
+        workdir,
+        root,
+        mount_source,
+        options,
+    )
+    .with_context(|| "mount overlayfs for root failed")?;
     for mount_point in mount_seq.iter() {
         let Some(mount_point) = mount_point else {
             continue;
@@ -241,6 +367,7 @@ pub fn mount_overlay(
             module_roots,
             &stock_root,
             mount_source,
+            options,
         ) {
             log::warn!(
                 "failed to mount overlay for child {}: {:#}, revert",
@@ -251,5 +378,5 @@ pub fn mount_overlay(
             bail!(e);
         }
     }
-    Ok(())
+    Ok(method)
 }
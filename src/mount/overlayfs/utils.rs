@@ -4,12 +4,15 @@
 #[cfg(any(target_os = "linux", target_os = "android"))]
 use std::fs;
 #[cfg(any(target_os = "linux", target_os = "android"))]
-use std::{os::unix::fs::PermissionsExt, path::Path, process::Command};
+use std::{os::unix::fs::PermissionsExt, path::Path};
 
 #[cfg(any(target_os = "linux", target_os = "android"))]
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Context, Result};
 #[cfg(any(target_os = "linux", target_os = "android"))]
-use rustix::mount::{UnmountFlags, unmount};
+use rustix::mount::{MountFlags, UnmountFlags, mount, unmount};
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::sys::loopdev::{self, LoopOptions};
 
 #[allow(dead_code)]
 pub struct AutoMountExt4 {
@@ -39,7 +42,7 @@ impl AutoMountExt4 {
             }
         }
 
-        mount_ext4(source, target.as_ref())?;
+        mount_ext4(source, target.as_ref(), &LoopOptions::default())?;
         Ok(Self {
             target: target.as_ref().as_str()?.to_string(),
             auto_umount,
@@ -78,23 +81,42 @@ impl Drop for AutoMountExt4 {
 #[allow(dead_code)]
 #[cfg(any(target_os = "linux", target_os = "android"))]
 pub fn mount_image(src: &str, target: &str, _autodrop: bool) -> Result<()> {
-    mount_ext4(src, target)?;
+    mount_ext4(src, target, &LoopOptions::default())?;
     Ok(())
 }
 
+/// Attaches the image at `source` to a loop device and mounts it on
+/// `target`. Returns the device number; the device is detached again when
+/// the mount fails.
 #[cfg(any(target_os = "linux", target_os = "android"))]
-pub fn mount_ext4(source: impl AsRef<Path>, target: impl AsRef<Path>) -> Result<()> {
-    let status = Command::new("mount")
-        .args(["-t", "ext4", "-o", "loop,rw,noatime"])
-        .arg(source.as_ref())
-        .arg(target.as_ref())
-        .status()
-        .context("Failed to execute mount command")?;
+pub fn mount_ext4(
+    source: impl AsRef<Path>,
+    target: impl AsRef<Path>,
+    options: &LoopOptions,
+) -> Result<u32> {
+    let (source, target) = (source.as_ref(), target.as_ref());
+    let device = loopdev::loop_attach(source, options)?;
 
-    if !status.success() {
-        return Err(anyhow!("Mount command failed"));
+    let mounted = device
+        .node()
+        .with_context(|| format!("No device node for {}", device.name()))
+        .and_then(|node| {
+            mount(&node, target, c"ext4", MountFlags::NOATIME, None).with_context(|| {
+                format!(
+                    "Failed to mount {} ({}) on {}",
+                    node.display(),
+                    source.display(),
+                    target.display()
+                )
+            })
+        });
+    if let Err(e) = mounted {
+        if let Err(detach) = loopdev::detach_number(device.number) {
+            log::warn!("{:#}", detach);
+        }
+        return Err(e);
     }
-    Ok(())
+    Ok(device.number)
 }
 
 #[cfg(any(target_os = "linux", target_os = "android"))]
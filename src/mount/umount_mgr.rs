@@ -1,64 +1,138 @@
 // Copyright 2026 Hybrid Mount Developers
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+//! try_umount: the mounts hidden from apps the root solution denies.
+//!
+//! Paths are queued once each while mounting and handed over in [`commit`],
+//! first to the active root backend's driver. When that driver has no
+//! try_umount, as with KernelSU builds older than its ioctl interface, the
+//! paths go to SUSFS's own list instead if the kernel carries SUSFS.
+
 use std::{
     collections::HashSet,
-    path::Path,
+    path::{Path, PathBuf},
     sync::{LazyLock, Mutex, OnceLock},
 };
 
-use anyhow::Result;
-use ksu::TryUmount;
+use anyhow::{Result, anyhow};
+
+use crate::sys::{
+    root_backend::{self, Backend},
+    susfs::{self, SusfsError},
+};
 
 pub static TMPFS: OnceLock<String> = OnceLock::new();
-pub static LIST: LazyLock<Mutex<TryUmount>> = LazyLock::new(|| Mutex::new(TryUmount::new()));
-static HISTORY: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+static QUEUE: LazyLock<Mutex<UmountQueue>> = LazyLock::new(|| Mutex::new(UmountQueue::default()));
 
-pub fn send_umountable<P>(target: P) -> Result<()>
+/// umount(2) flags a try_umount driver is asked with, in order: a plain
+/// unmount, then `MNT_DETACH` for mounts still busy.
+pub const RETRY_FLAGS: [u32; 2] = [0, libc::MNT_DETACH as u32];
+
+/// Paths queued for try_umount since the last commit, each once, in the
+/// order they were mounted.
+#[derive(Debug, Default)]
+pub struct UmountQueue {
+    seen: HashSet<PathBuf>,
+    pending: Vec<PathBuf>,
+}
+
+impl UmountQueue {
+    /// Queues `path`. Returns `false` when it already is.
+    pub fn push(&mut self, path: &Path) -> bool {
+        if !self.seen.insert(path.to_path_buf()) {
+            return false;
+        }
+        self.pending.push(path.to_path_buf());
+        true
+    }
+
+    /// Hands out the queued paths and forgets them, so a later run may
+    /// queue them again.
+    pub fn take(&mut self) -> Vec<PathBuf> {
+        self.seen.clear();
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// Runs `attempt` with each of [`RETRY_FLAGS`] until one succeeds and
+/// returns the flags that did.
+pub fn with_flag_retry<F>(mut attempt: F) -> Result<u32>
 where
-    P: AsRef<Path>,
+    F: FnMut(u32) -> Result<()>,
 {
-    if !crate::utils::KSU.load(std::sync::atomic::Ordering::Relaxed) {
-        return Ok(());
+    let [plain, detach] = RETRY_FLAGS;
+    match attempt(plain) {
+        Ok(()) => return Ok(plain),
+        Err(e) => log::debug!(
+            "try_umount({}) failed: {:#}, retrying with flags({})",
+            plain,
+            e,
+            detach
+        ),
     }
+    attempt(detach).map_err(|e| anyhow!("try_umount({}) failed: {:#}", detach, e))?;
+    Ok(detach)
+}
 
-    let path_str = target.as_ref().to_string_lossy().to_string();
-    let mut history = HISTORY
+/// Queues `target` for try_umount.
+pub fn send_umountable<P>(target: P) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let target = target.as_ref();
+    let mut queue = QUEUE
         .lock()
-        .map_err(|_| anyhow::anyhow!("Failed to lock history mutex"))?;
+        .map_err(|_| anyhow!("Failed to lock umount queue"))?;
 
-    if history.contains(&path_str) {
-        log::debug!("Ignored duplicate umount request: {}", path_str);
+    if !queue.push(target) {
+        log::debug!("Ignored duplicate umount request: {}", target.display());
         return Ok(());
     }
 
-    history.insert(path_str);
-    LIST.lock()
-        .map_err(|_| anyhow::anyhow!("Failed to lock umount list"))?
-        .add(target);
-    Ok(())
+    root_backend::active().add_try_umount(target)
 }
 
-pub fn commit() -> Result<()> {
-    if !crate::utils::KSU.load(std::sync::atomic::Ordering::Relaxed) {
-        return Ok(());
+/// Adds `paths` to SUSFS's try_umount list. Returns how many it took, or
+/// `None` when the kernel has no SUSFS try_umount.
+fn commit_susfs(paths: &[PathBuf]) -> Option<usize> {
+    let mut added = 0;
+    for path in paths {
+        match susfs::add_try_umount(path) {
+            Ok(()) => added += 1,
+            Err(SusfsError::Unsupported) if added == 0 => return None,
+            Err(e) => log::warn!("SUSFS try_umount refused {}: {}", path.display(), e),
+        }
     }
-    let mut list = LIST
-        .lock()
-        .map_err(|_| anyhow::anyhow!("Failed to lock umount list"))?;
+    Some(added)
+}
 
-    list.flags(0);
-    if let Err(e0) = list.umount() {
-        log::debug!("try_umount(0) failed: {:#}, retrying with flags(2)", e0);
+pub fn commit() -> Result<()> {
+    let paths = match QUEUE.lock() {
+        Ok(mut queue) => queue.take(),
+        Err(_) => Vec::new(),
+    };
+
+    let mut backend = root_backend::active();
+    let failed = match backend.commit_umounts() {
+        Ok(()) if backend.backend() != Backend::Null => return Ok(()),
+        Ok(()) => None,
+        Err(e) => Some(e),
+    };
+    drop(backend);
 
-        list.flags(2);
-        if let Err(e2) = list.umount() {
-            log::warn!("try_umount(2) failed: {:#}", e2);
+    if paths.is_empty() {
+        if let Some(e) = failed {
+            log::warn!("{:#}", e);
         }
+        return Ok(());
     }
 
-    if let Ok(mut history) = HISTORY.lock() {
-        history.clear();
+    match commit_susfs(&paths) {
+        Some(added) => log::info!("SUSFS try_umount took {} of {} paths", added, paths.len()),
+        None => match failed {
+            Some(e) => log::warn!("{:#}", e),
+            None => log::debug!("No try_umount driver; {} mounts stay visible", paths.len()),
+        },
     }
 
     Ok(())
@@ -0,0 +1,119 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::ops::planner::{DiagnosticIssue, DiagnosticLevel},
+    defs,
+};
+
+/// Something that hides module mounts from denylisted apps on its own.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DenylistProvider {
+    #[serde(alias = "zygisk_su")]
+    ZygiskSU,
+    Shamiko,
+    NoHello,
+    /// KernelSU's own per-app umount. It works through the same try_umount
+    /// list we feed, so it never conflicts with our umount registration.
+    #[serde(alias = "ksu")]
+    KsuNative,
+    #[default]
+    None,
+}
+
+/// The provider in charge of the denylist and whether it unmounts module
+/// mounts itself, in which case our own umount registration must stay off.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DenylistStatus {
+    pub provider: DenylistProvider,
+    pub enforcing: bool,
+    /// Every provider found on the device, in priority order.
+    pub detected: Vec<DenylistProvider>,
+}
+
+/// A Zygisk module counts as present while it is installed and enabled.
+fn module_active(id: &str) -> bool {
+    let dir = Path::new(defs::MODULES_DIR).join(id);
+    dir.is_dir()
+        && !dir.join(defs::DISABLE_FILE_NAME).exists()
+        && !dir.join(defs::REMOVE_FILE_NAME).exists()
+}
+
+/// Whether `provider` is installed and, if so, whether it enforces.
+fn probe(provider: DenylistProvider) -> Option<bool> {
+    match provider {
+        DenylistProvider::ZygiskSU => fs::read_to_string(defs::ZYGISKSU_DENYLIST_FILE)
+            .ok()
+            .map(|s| s.trim() != "0"),
+        DenylistProvider::Shamiko => module_active("zygisk_shamiko").then_some(true),
+        DenylistProvider::NoHello => module_active("zygisk_nohello").then_some(true),
+        DenylistProvider::KsuNative => Path::new(defs::KSU_ALLOWLIST_FILE)
+            .exists()
+            .then_some(false),
+        DenylistProvider::None => None,
+    }
+}
+
+const PROBE_ORDER: [DenylistProvider; 4] = [
+    DenylistProvider::ZygiskSU,
+    DenylistProvider::Shamiko,
+    DenylistProvider::NoHello,
+    DenylistProvider::KsuNative,
+];
+
+/// Detects the denylist providers on the device. `preferred` (from the
+/// `denylist_provider` config) overrides which one is in charge; otherwise
+/// the first enforcing provider wins, then the first one found.
+pub fn status(preferred: Option<DenylistProvider>) -> DenylistStatus {
+    let found: Vec<(DenylistProvider, bool)> = PROBE_ORDER
+        .into_iter()
+        .filter_map(|p| probe(p).map(|enforcing| (p, enforcing)))
+        .collect();
+
+    let (provider, enforcing) = match preferred {
+        Some(p) => (p, probe(p).unwrap_or(false)),
+        None => found
+            .iter()
+            .find(|(_, enforcing)| *enforcing)
+            .or(found.first())
+            .copied()
+            .unwrap_or_default(),
+    };
+
+    DenylistStatus {
+        provider,
+        enforcing,
+        detected: found.into_iter().map(|(p, _)| p).collect(),
+    }
+}
+
+/// Warns about provider combinations that are known to fight over mounts.
+pub fn diagnose(preferred: Option<DenylistProvider>) -> Vec<DiagnosticIssue> {
+    let status = status(preferred);
+
+    let zygisk: Vec<String> = status
+        .detected
+        .iter()
+        .filter(|p| **p != DenylistProvider::KsuNative)
+        .map(|p| format!("{:?}", p))
+        .collect();
+
+    if zygisk.len() < 2 {
+        return Vec::new();
+    }
+
+    vec![DiagnosticIssue {
+        level: DiagnosticLevel::Warning,
+        context: "Denylist".to_string(),
+        message: format!(
+            "Multiple denylist providers are active ({}); they unmount independently and \
+             are known to misbehave together",
+            zygisk.join(", ")
+        ),
+    }]
+}
@@ -0,0 +1,460 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, anyhow, ensure};
+use nix::{errno::Errno, ioctl_none};
+use serde::Serialize;
+
+use crate::{conf::config::Config, utils};
+
+const SYS_BLOCK: &str = "/sys/block";
+const DELETED_SUFFIX: &str = " (deleted)";
+const LOOP_CONTROL: &str = "/dev/loop-control";
+/// `lo_file_name` of our devices unless stealth randomizes it.
+const LOOP_FILE_NAME: &str = "meta-hybrid";
+const LO_FLAGS_READ_ONLY: u32 = 1;
+const LO_FLAGS_AUTOCLEAR: u32 = 4;
+const LO_FLAGS_DIRECT_IO: u32 = 16;
+/// Pause before the second attempt; doubled for each later one.
+const ATTACH_BACKOFF: Duration = Duration::from_millis(10);
+const ATTACH_BACKOFF_MAX: Duration = Duration::from_millis(200);
+const ATTACH_ATTEMPTS: u32 = 8;
+
+ioctl_none!(loop_clr_fd, 0x4C, 0x01);
+
+mod raw {
+    use nix::{ioctl_none, ioctl_write_int_bad, ioctl_write_ptr_bad};
+
+    const LO_NAME_SIZE: usize = 64;
+
+    /// `struct loop_info64` of `<linux/loop.h>`.
+    #[repr(C)]
+    pub struct LoopInfo64 {
+        pub lo_device: u64,
+        pub lo_inode: u64,
+        pub lo_rdevice: u64,
+        pub lo_offset: u64,
+        pub lo_sizelimit: u64,
+        pub lo_number: u32,
+        pub lo_encrypt_type: u32,
+        pub lo_encrypt_key_size: u32,
+        pub lo_flags: u32,
+        pub lo_file_name: [u8; LO_NAME_SIZE],
+        pub lo_crypt_name: [u8; LO_NAME_SIZE],
+        pub lo_encrypt_key: [u8; 32],
+        pub lo_init: [u64; 2],
+    }
+
+    /// `struct loop_config` of `<linux/loop.h>`.
+    #[repr(C)]
+    pub struct LoopConfig {
+        pub fd: u32,
+        pub block_size: u32,
+        pub info: LoopInfo64,
+        pub reserved: [u64; 8],
+    }
+
+    impl LoopInfo64 {
+        pub fn new(flags: u32, file_name: &str) -> Self {
+            let mut name = [0; LO_NAME_SIZE];
+            let len = file_name.len().min(LO_NAME_SIZE - 1);
+            name[..len].copy_from_slice(&file_name.as_bytes()[..len]);
+            Self {
+                lo_device: 0,
+                lo_inode: 0,
+                lo_rdevice: 0,
+                lo_offset: 0,
+                lo_sizelimit: 0,
+                lo_number: 0,
+                lo_encrypt_type: 0,
+                lo_encrypt_key_size: 0,
+                lo_flags: flags,
+                lo_file_name: name,
+                lo_crypt_name: [0; LO_NAME_SIZE],
+                lo_encrypt_key: [0; 32],
+                lo_init: [0; 2],
+            }
+        }
+    }
+
+    ioctl_none!(loop_ctl_get_free, 0x4C, 0x82);
+    ioctl_write_int_bad!(loop_set_fd, 0x4C00);
+    ioctl_write_ptr_bad!(loop_set_status64, 0x4C04, LoopInfo64);
+    ioctl_write_int_bad!(loop_set_direct_io, 0x4C08);
+    ioctl_write_ptr_bad!(loop_configure, 0x4C0A, LoopConfig);
+}
+
+/// How [`loop_attach`] binds an image to a loop device.
+#[derive(Debug, Clone)]
+pub struct LoopOptions {
+    /// The kernel detaches the device once its last user, the mount, is gone.
+    pub autoclear: bool,
+    /// Reads and writes bypass the page cache of the backing file.
+    pub direct_io: bool,
+    /// The image is opened and bound read-only.
+    pub read_only: bool,
+    /// `lo_file_name` the device reports; at most 63 bytes are kept.
+    pub file_name: String,
+    /// Free devices tried before giving up.
+    pub attempts: u32,
+}
+
+impl Default for LoopOptions {
+    fn default() -> Self {
+        Self {
+            autoclear: true,
+            direct_io: false,
+            read_only: false,
+            file_name: LOOP_FILE_NAME.to_string(),
+            attempts: ATTACH_ATTEMPTS,
+        }
+    }
+}
+
+impl LoopOptions {
+    /// Flags from `storage`; the file name is random for the boot when
+    /// `stealth.randomize_mountsource` is set.
+    pub fn from_config(config: &Config) -> Self {
+        let file_name = if config.stealth.randomize_mountsource {
+            format!("{:08x}.img", utils::random_u32())
+        } else {
+            LOOP_FILE_NAME.to_string()
+        };
+        Self {
+            autoclear: config.storage.loop_autoclear,
+            direct_io: config.storage.loop_direct_io,
+            file_name,
+            ..Default::default()
+        }
+    }
+
+    fn flags(&self) -> u32 {
+        let mut flags = 0;
+        if self.read_only {
+            flags |= LO_FLAGS_READ_ONLY;
+        }
+        if self.autoclear {
+            flags |= LO_FLAGS_AUTOCLEAR;
+        }
+        if self.direct_io {
+            flags |= LO_FLAGS_DIRECT_IO;
+        }
+        flags
+    }
+}
+
+/// The calls [`attach_with`] makes, so the retry logic can be driven
+/// without a loop driver.
+pub trait LoopBackend {
+    /// Number of a free loop device, which the kernel creates if need be.
+    fn get_free(&self) -> nix::Result<u32>;
+    /// Binds `backing` to device `number` and returns the device, still
+    /// open.
+    fn configure(&self, number: u32, backing: &File, options: &LoopOptions) -> nix::Result<File>;
+}
+
+/// The kernel's loop driver, through `/dev/loop-control`.
+pub struct KernelLoops;
+
+fn errno_of(e: io::Error) -> Errno {
+    Errno::from_raw(e.raw_os_error().unwrap_or(libc::EIO))
+}
+
+impl LoopBackend for KernelLoops {
+    fn get_free(&self) -> nix::Result<u32> {
+        let control = File::open(LOOP_CONTROL).map_err(errno_of)?;
+        // SAFETY: LOOP_CTL_GET_FREE takes no argument and `control` is open
+        // for the duration of the call.
+        let number = unsafe { raw::loop_ctl_get_free(control.as_raw_fd()) }?;
+        Ok(number as u32)
+    }
+
+    fn configure(&self, number: u32, backing: &File, options: &LoopOptions) -> nix::Result<File> {
+        // ueventd may not have created the node of a new device yet.
+        let node = device_node(&format!("loop{}", number)).ok_or(Errno::ENOENT)?;
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&node)
+            .map_err(errno_of)?;
+
+        let config = raw::LoopConfig {
+            fd: backing.as_raw_fd() as u32,
+            block_size: 0,
+            info: raw::LoopInfo64::new(options.flags(), &options.file_name),
+            reserved: [0; 8],
+        };
+        // SAFETY: `config` is a `#[repr(C)]` `struct loop_config` the kernel
+        // only reads, and `device` and `backing` stay open across the call.
+        match unsafe { raw::loop_configure(device.as_raw_fd(), &config) } {
+            Ok(_) => {}
+            // Kernels before 5.8 have no LOOP_CONFIGURE.
+            Err(Errno::EINVAL | Errno::ENOTTY) => set_fd_and_status(&device, backing, options)?,
+            Err(e) => return Err(e),
+        }
+        Ok(device)
+    }
+}
+
+/// The pre-5.8 sequence: bind, then set the flags and name. The device is
+/// released again when the second step fails.
+fn set_fd_and_status(device: &File, backing: &File, options: &LoopOptions) -> nix::Result<()> {
+    let fd = device.as_raw_fd();
+    // SAFETY: both fds belong to files the caller keeps open.
+    unsafe { raw::loop_set_fd(fd, backing.as_raw_fd()) }?;
+
+    let info = raw::LoopInfo64::new(options.flags(), &options.file_name);
+    // SAFETY: `info` is a `#[repr(C)]` `struct loop_info64` the kernel only
+    // reads.
+    if let Err(e) = unsafe { raw::loop_set_status64(fd, &info) } {
+        // SAFETY: LOOP_CLR_FD takes no argument; `fd` is still open.
+        let _ = unsafe { loop_clr_fd(fd) };
+        return Err(e);
+    }
+    // SAFETY: LOOP_SET_DIRECT_IO takes its flag by value.
+    if options.direct_io
+        && let Err(e) = unsafe { raw::loop_set_direct_io(fd, 1) }
+    {
+        log::warn!("Loop device does not take direct I/O: {}", e);
+    }
+    Ok(())
+}
+
+/// Up to `pause` again on top of it, so racing callers spread out.
+fn jittered(pause: Duration) -> Duration {
+    let millis = pause.as_millis() as u64;
+    pause + Duration::from_millis(utils::random_u32() as u64 % (millis + 1))
+}
+
+/// A loop device [`loop_attach`] bound. It is held open until dropped, so
+/// an autoclear device is not detached before it is mounted.
+pub struct AttachedLoop {
+    pub number: u32,
+    pub backing_file: PathBuf,
+    _device: File,
+}
+
+impl AttachedLoop {
+    pub fn name(&self) -> String {
+        format!("loop{}", self.number)
+    }
+
+    pub fn node(&self) -> Option<PathBuf> {
+        device_node(&self.name())
+    }
+}
+
+/// Binds the image at `path` to a free loop device through `backend`.
+/// Between looking up a free device and binding it, vold or another
+/// process may claim it; on EBUSY or EEXIST, or a node that is not there
+/// yet, the next free device is requested after a jittered pause, up to
+/// `options.attempts` times in all.
+pub fn attach_with(
+    backend: &dyn LoopBackend,
+    path: &Path,
+    options: &LoopOptions,
+) -> Result<AttachedLoop> {
+    let backing = OpenOptions::new()
+        .read(true)
+        .write(!options.read_only)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+
+    let mut pause = ATTACH_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        let number = backend
+            .get_free()
+            .context("Failed to get a free loop device")?;
+
+        match backend.configure(number, &backing, options) {
+            Ok(device) => {
+                log::info!("Attached {} to loop{}", path.display(), number);
+                return Ok(AttachedLoop {
+                    number,
+                    backing_file: path.to_path_buf(),
+                    _device: device,
+                });
+            }
+            Err(e @ (Errno::EBUSY | Errno::EEXIST | Errno::ENOENT))
+                if attempt < options.attempts =>
+            {
+                log::warn!(
+                    "loop{} unavailable for {} ({}), attempt {} of {}",
+                    number,
+                    path.display(),
+                    e,
+                    attempt,
+                    options.attempts
+                );
+            }
+            Err(e) => {
+                return Err(anyhow!(e).context(format!(
+                    "Failed to attach {} to loop{} after {} attempt(s)",
+                    path.display(),
+                    number,
+                    attempt
+                )));
+            }
+        }
+
+        thread::sleep(jittered(pause));
+        pause = (pause * 2).min(ATTACH_BACKOFF_MAX);
+        attempt += 1;
+    }
+}
+
+/// [`attach_with`] the kernel's loop driver.
+pub fn loop_attach(path: &Path, options: &LoopOptions) -> Result<AttachedLoop> {
+    attach_with(&KernelLoops, path, options)
+}
+
+/// A loop device whose backing file lives under our base directory.
+#[derive(Debug, Clone, Serialize)]
+pub struct LoopDevice {
+    pub name: String,
+    pub backing_file: PathBuf,
+    /// The backing file was replaced or removed since it was attached.
+    pub deleted: bool,
+    /// The device is mounted somewhere.
+    pub active: bool,
+}
+
+/// Names (`loopN`) of loop devices that are the source of a mount.
+fn mounted_loop_names() -> HashSet<String> {
+    procfs::process::Process::myself()
+        .and_then(|p| p.mountinfo())
+        .map(|mounts| {
+            mounts
+                .into_iter()
+                .filter_map(|m| m.mount_source)
+                .filter_map(|source| source.rsplit('/').next().map(str::to_string))
+                .filter(|name| name.starts_with("loop"))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Attached loop devices backed by files under `base_dir`.
+pub fn list_owned(base_dir: &Path) -> Vec<LoopDevice> {
+    let Ok(entries) = fs::read_dir(SYS_BLOCK) else {
+        return Vec::new();
+    };
+
+    let mounted = mounted_loop_names();
+
+    let mut devices: Vec<LoopDevice> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with("loop") {
+                return None;
+            }
+
+            let raw = fs::read_to_string(entry.path().join("loop/backing_file")).ok()?;
+            let raw = raw.trim_end_matches('\n');
+            let (path, deleted) = match raw.strip_suffix(DELETED_SUFFIX) {
+                Some(path) => (path, true),
+                None => (raw, false),
+            };
+
+            let backing_file = PathBuf::from(path);
+            if !backing_file.starts_with(base_dir) {
+                return None;
+            }
+
+            Some(LoopDevice {
+                active: mounted.contains(&name),
+                name,
+                backing_file,
+                deleted,
+            })
+        })
+        .collect();
+
+    devices.sort_by(|a, b| a.name.cmp(&b.name));
+    devices
+}
+
+pub fn device_node(name: &str) -> Option<PathBuf> {
+    ["/dev/block", "/dev"]
+        .into_iter()
+        .map(|dir| Path::new(dir).join(name))
+        .find(|p| p.exists())
+}
+
+fn detach(device: &LoopDevice, base_dir: &Path) -> Result<()> {
+    ensure!(
+        device.backing_file.starts_with(base_dir),
+        "{} is not backed by a file under {}",
+        device.name,
+        base_dir.display()
+    );
+
+    clear(&device.name)
+}
+
+fn clear(name: &str) -> Result<()> {
+    let node = device_node(name).with_context(|| format!("No device node for {}", name))?;
+    let file = File::open(&node).with_context(|| format!("Failed to open {}", node.display()))?;
+
+    // SAFETY: LOOP_CLR_FD takes no argument and `file` is open for the
+    // duration of the call.
+    unsafe { loop_clr_fd(file.as_raw_fd()) }
+        .with_context(|| format!("Failed to detach {}", node.display()))?;
+
+    Ok(())
+}
+
+/// Detaches `loop<number>`, which this run attached. A device still in use
+/// is detached by the kernel once its last user is gone.
+pub fn detach_number(number: u32) -> Result<()> {
+    clear(&format!("loop{}", number))
+}
+
+/// Detaches loop devices backed by files under `base_dir` that are not
+/// mounted anywhere. Devices backed by other files are never touched.
+/// Returns the number of devices detached.
+pub fn cleanup_stale(base_dir: &Path) -> usize {
+    detach_idle(base_dir, &HashSet::new())
+}
+
+/// Names of the loop devices currently backed by files under `base_dir`.
+pub fn owned_names(base_dir: &Path) -> HashSet<String> {
+    list_owned(base_dir).into_iter().map(|d| d.name).collect()
+}
+
+/// Like [`cleanup_stale`], but leaves the devices named in `keep` alone,
+/// e.g. those that were attached before this run started.
+pub fn detach_idle(base_dir: &Path, keep: &HashSet<String>) -> usize {
+    let mut detached = 0;
+
+    for device in list_owned(base_dir)
+        .into_iter()
+        .filter(|d| !d.active && !keep.contains(&d.name))
+    {
+        match detach(&device, base_dir) {
+            Ok(()) => {
+                log::info!(
+                    "Detached stale loop device {} ({})",
+                    device.name,
+                    device.backing_file.display()
+                );
+                detached += 1;
+            }
+            Err(e) => log::warn!("{:#}", e),
+        }
+    }
+
+    detached
+}
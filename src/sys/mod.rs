@@ -1,5 +1,11 @@
 // Copyright 2026 Hybrid Mount Developers
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+pub mod denylist;
+pub mod loopdev;
 pub mod mount;
+pub mod namespace;
 pub mod poaceae;
+pub mod priority;
+pub mod root_backend;
+pub mod susfs;
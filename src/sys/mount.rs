@@ -7,13 +7,15 @@ use anyhow::{Context, Result, bail};
 use procfs::process::Process;
 use rustix::mount::{MountFlags, mount};
 
-use crate::utils::ensure_dir_exists;
+use crate::utils::{RootImpl, ensure_dir_exists};
 
 pub fn detect_mount_source() -> String {
-    if ksu::version().is_some() {
-        return "KSU".to_string();
+    match crate::utils::detect_root_impl() {
+        RootImpl::KernelSU => "KSU",
+        RootImpl::Magisk => "magisk",
+        RootImpl::APatch | RootImpl::Unknown => "APatch",
     }
-    "APatch".to_string()
+    .to_string()
 }
 
 pub fn is_mounted<P: AsRef<Path>>(path: P) -> bool {
@@ -52,9 +54,11 @@ pub fn mount_tmpfs(target: &Path, source: &str) -> Result<()> {
     Ok(())
 }
 
+/// Runs `e2fsck -p` on an unmounted image; fails when it left errors it
+/// could not fix safely.
 pub fn repair_image(image_path: &Path) -> Result<()> {
     let status = Command::new("e2fsck")
-        .args(["-y", "-f"])
+        .args(["-p", "-f"])
         .arg(image_path)
         .status()
         .context("Failed to execute e2fsck")?;
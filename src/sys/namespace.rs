@@ -0,0 +1,171 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! The mount namespace a mount run works in.
+//!
+//! With `namespace_mode = "global"` everything is mounted in the init
+//! namespace, as it always was. With `"clone"` the run first moves into a
+//! new mount namespace whose tree is a slave of the global one: mounts made
+//! globally later still show up in it, while ours stay inside. The new
+//! namespace is pinned by bind mounting its nsfs file on `run/ns/mnt`, so
+//! it outlives the process and can be entered again with `setns`.
+//!
+//! `unshare` and `setns` fail with `EINVAL` once the process has a second
+//! thread, so [`enter_clone`] runs before the rayon pool and the log writer
+//! are started.
+
+use std::{
+    fs::{self, File},
+    io,
+    os::{
+        fd::AsRawFd,
+        unix::{fs::MetadataExt, process::CommandExt},
+    },
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Context, Result, bail};
+use procfs::{FromBufRead, process::MountInfo};
+use rustix::mount::{MountPropagationFlags, UnmountFlags, mount_bind, mount_change, unmount};
+use serde::{Deserialize, Serialize};
+
+use crate::{conf::config::NamespaceMode, sys::mount::is_mounted, utils::ensure_dir_exists};
+
+const SELF_MNT_NS: &str = "/proc/self/ns/mnt";
+/// File name of the pinned namespace inside the namespace directory.
+const PIN_FILE_NAME: &str = "mnt";
+
+/// The namespace a run mounted in, kept in the runtime state.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamespaceReport {
+    pub mode: NamespaceMode,
+    /// nsfs bind mount that keeps a cloned namespace alive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ns_path: Option<PathBuf>,
+    /// The cloned namespace was pinned and outlives the run.
+    #[serde(default)]
+    pub propagated: bool,
+}
+
+fn unshare_mnt() -> io::Result<()> {
+    // SAFETY: unshare only changes the namespaces of the calling process.
+    if unsafe { libc::unshare(libc::CLONE_NEWNS) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn setns_mnt(ns: &File) -> io::Result<()> {
+    // SAFETY: the fd is an open nsfs file for as long as `ns` lives.
+    if unsafe { libc::setns(ns.as_raw_fd(), libc::CLONE_NEWNS) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Makes `ns_dir` a private mount of its own, so the nsfs bind mounted in it
+/// does not propagate into the namespace it refers to.
+fn prepare_pin_dir(ns_dir: &Path) -> Result<PathBuf> {
+    ensure_dir_exists(ns_dir)?;
+    if !is_mounted(ns_dir) {
+        mount_bind(ns_dir, ns_dir)
+            .with_context(|| format!("Failed to bind {} onto itself", ns_dir.display()))?;
+    }
+    mount_change(ns_dir, MountPropagationFlags::PRIVATE)
+        .with_context(|| format!("Failed to make {} private", ns_dir.display()))?;
+
+    let pin = ns_dir.join(PIN_FILE_NAME);
+    if is_mounted(&pin) {
+        // The namespace of an earlier run; it goes away with its last user.
+        unmount(&pin, UnmountFlags::DETACH)
+            .with_context(|| format!("Failed to release {}", pin.display()))?;
+    }
+    if !pin.exists() {
+        fs::write(&pin, b"").with_context(|| format!("Failed to create {}", pin.display()))?;
+    }
+    Ok(pin)
+}
+
+/// Moves the process into a new mount namespace and pins it under
+/// `ns_dir`. A namespace that could not be pinned is still used; it lasts
+/// as long as this process and its children. Fails, leaving the process in
+/// the global namespace, when no namespace could be set up.
+pub fn enter_clone(ns_dir: &Path) -> Result<NamespaceReport> {
+    let pin = prepare_pin_dir(ns_dir)?;
+    let global = File::open(SELF_MNT_NS).context("Failed to open the global mount namespace")?;
+
+    unshare_mnt().context("Failed to unshare the mount namespace")?;
+    if let Err(e) = mount_change(
+        "/",
+        MountPropagationFlags::DOWNSTREAM | MountPropagationFlags::REC,
+    ) {
+        setns_mnt(&global).context("Failed to return to the global mount namespace")?;
+        bail!("Failed to make the cloned mount tree a slave: {}", e);
+    }
+    let cloned = File::open(SELF_MNT_NS).context("Failed to open the cloned mount namespace")?;
+
+    // The nsfs file can only be bound from outside the namespace it names.
+    setns_mnt(&global).context("Failed to return to the global mount namespace")?;
+    let source = PathBuf::from(format!("/proc/self/fd/{}", cloned.as_raw_fd()));
+    let propagated = mount_bind(&source, &pin).is_ok();
+    setns_mnt(&cloned).context("Failed to enter the cloned mount namespace")?;
+
+    Ok(NamespaceReport {
+        mode: NamespaceMode::Clone,
+        ns_path: propagated.then_some(pin),
+        propagated,
+    })
+}
+
+/// Moves the process into a new mount namespace that shares nothing with
+/// the global one: mounts made from now on stay in it and vanish with the
+/// process. Fails unless the namespace really changed and its whole tree
+/// is private, so callers can rely on never touching real mounts.
+pub fn enter_private() -> Result<()> {
+    let before = fs::metadata(SELF_MNT_NS).context("Failed to stat the mount namespace")?;
+
+    unshare_mnt().context("Failed to unshare the mount namespace")?;
+    mount_change(
+        "/",
+        MountPropagationFlags::PRIVATE | MountPropagationFlags::REC,
+    )
+    .context("Failed to make the new mount tree private")?;
+
+    let after = fs::metadata(SELF_MNT_NS).context("Failed to stat the mount namespace")?;
+    if before.ino() == after.ino() {
+        bail!("Still in the original mount namespace after unshare");
+    }
+    Ok(())
+}
+
+/// The mount table of the namespace pinned at `ns_path`, read by a child
+/// that enters it, since a threaded process cannot `setns` itself.
+pub fn mountinfo_in(ns_path: &Path) -> Result<Vec<MountInfo>> {
+    let ns =
+        File::open(ns_path).with_context(|| format!("Failed to open {}", ns_path.display()))?;
+    let fd = ns.as_raw_fd();
+
+    let mut command = Command::new("cat");
+    command.arg("/proc/self/mountinfo");
+    // SAFETY: setns is async-signal-safe and `fd` stays open until the
+    // child has been spawned.
+    unsafe {
+        command.pre_exec(move || {
+            if libc::setns(fd, libc::CLONE_NEWNS) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let output = command
+        .output()
+        .with_context(|| format!("Failed to enter {}", ns_path.display()))?;
+    if !output.status.success() {
+        bail!("Reading the mount table in {} failed", ns_path.display());
+    }
+
+    let infos = procfs::process::MountInfos::from_buf_read(output.stdout.as_slice())
+        .context("Failed to parse the namespace mount table")?;
+    Ok(infos.0)
+}
@@ -0,0 +1,226 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Best-effort throttling of the storage and sync phases.
+//!
+//! With `boot_priority = "low"` the run drops to the idle I/O class, nices
+//! the main and worker pool threads, and moves into a transient cgroup v2
+//! group with a low `io.weight`, so image creation and module sync give way
+//! to the apps starting alongside. Everything is restored before the mount
+//! syscalls. Each step is skipped quietly when the kernel lacks it.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{conf::config::BootPriority, utils::pool};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const CGROUP_NAME: &str = "meta_hybrid_boot";
+const IO_WEIGHT: u32 = 10;
+const LOW_NICE: i32 = 10;
+
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+
+/// What was actually applied, kept in the runtime state.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PriorityReport {
+    pub priority: BootPriority,
+    /// Threads, main included, moved to idle I/O or niced.
+    #[serde(default)]
+    pub niced_threads: usize,
+    /// Transient cgroup the process ran in while throttled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cgroup: Option<PathBuf>,
+}
+
+/// A thread's scheduling state before it was lowered.
+struct SavedThread {
+    nice: i32,
+    ioprio: libc::c_int,
+}
+
+/// Lowered priority of the current run. Restored by `restore` or on drop.
+pub struct Throttle {
+    report: PriorityReport,
+    main: Option<SavedThread>,
+    workers: Vec<Option<SavedThread>>,
+    /// The cgroup to move back to, relative to the cgroup root.
+    original_cgroup: Option<String>,
+}
+
+fn gettid() -> libc::id_t {
+    // SAFETY: gettid has no preconditions.
+    unsafe { libc::syscall(libc::SYS_gettid) as libc::id_t }
+}
+
+fn get_ioprio() -> Option<libc::c_int> {
+    // SAFETY: ioprio_get only reads the priority of the calling thread.
+    let prio = unsafe { libc::syscall(libc::SYS_ioprio_get, IOPRIO_WHO_PROCESS, 0) };
+    (prio >= 0).then_some(prio as libc::c_int)
+}
+
+fn set_ioprio(prio: libc::c_int) -> bool {
+    // SAFETY: ioprio_set only changes the priority of the calling thread.
+    unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, prio) == 0 }
+}
+
+fn get_nice() -> i32 {
+    // SAFETY: getpriority on the calling thread. -1 is also a valid nice
+    // value, so a failure just reads as -1 and is restored as such.
+    unsafe { libc::getpriority(libc::PRIO_PROCESS, gettid()) }
+}
+
+fn set_nice(nice: i32) -> bool {
+    // SAFETY: setpriority on the calling thread.
+    unsafe { libc::setpriority(libc::PRIO_PROCESS, gettid(), nice) == 0 }
+}
+
+/// Lowers the calling thread. `None` when neither knob could be turned.
+fn lower_thread() -> Option<SavedThread> {
+    let saved = SavedThread {
+        nice: get_nice(),
+        ioprio: get_ioprio()?,
+    };
+    let idle = set_ioprio(IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT);
+    let niced = set_nice(LOW_NICE);
+    (idle || niced).then_some(saved)
+}
+
+fn restore_thread(saved: &SavedThread) {
+    set_ioprio(saved.ioprio);
+    set_nice(saved.nice);
+}
+
+/// The cgroup v2 path of the process from `/proc/self/cgroup` contents,
+/// e.g. `/` or `/system`. `None` on a pure cgroup v1 system.
+pub fn cgroup_v2_path(content: &str) -> Option<String> {
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(|path| path.trim().to_string())
+}
+
+/// Creates the transient group under the cgroup v2 root, enables the io
+/// controller for it and moves the whole process in. Returns the original
+/// path and the new group.
+fn enter_cgroup(root: &Path) -> Option<(String, PathBuf)> {
+    let original = cgroup_v2_path(&fs::read_to_string("/proc/self/cgroup").ok()?)?;
+    let controllers = fs::read_to_string(root.join("cgroup.controllers")).ok()?;
+    if !controllers.split_whitespace().any(|c| c == "io") {
+        return None;
+    }
+
+    // Fails harmlessly when already enabled or not permitted; io.weight
+    // then does not exist and the group is dropped below.
+    let _ = fs::write(root.join("cgroup.subtree_control"), "+io");
+
+    let group = root.join(CGROUP_NAME);
+    if let Err(e) = fs::create_dir(&group)
+        && e.kind() != std::io::ErrorKind::AlreadyExists
+    {
+        return None;
+    }
+
+    let joined = fs::write(group.join("io.weight"), format!("default {}", IO_WEIGHT)).is_ok()
+        && fs::write(group.join("cgroup.procs"), std::process::id().to_string()).is_ok();
+    if !joined {
+        let _ = fs::remove_dir(&group);
+        return None;
+    }
+    Some((original, group))
+}
+
+fn leave_cgroup(root: &Path, original: &str, group: &Path) {
+    let procs = root
+        .join(original.trim_start_matches('/'))
+        .join("cgroup.procs");
+    if let Err(e) = fs::write(&procs, std::process::id().to_string()) {
+        log::warn!("Failed to leave boot cgroup {}: {}", group.display(), e);
+        return;
+    }
+    let _ = fs::remove_dir(group);
+}
+
+impl Throttle {
+    /// Applies `priority`. `Normal` changes nothing.
+    pub fn apply(priority: BootPriority) -> Self {
+        let mut throttle = Self {
+            report: PriorityReport {
+                priority,
+                ..Default::default()
+            },
+            main: None,
+            workers: Vec::new(),
+            original_cgroup: None,
+        };
+        if priority == BootPriority::Normal {
+            return throttle;
+        }
+
+        throttle.main = lower_thread();
+        throttle.workers = pool::broadcast(|_| lower_thread());
+        if throttle.workers.iter().all(Option::is_none) {
+            throttle.workers.clear();
+        }
+
+        if let Some((original, group)) = enter_cgroup(Path::new(CGROUP_ROOT)) {
+            throttle.original_cgroup = Some(original);
+            throttle.report.cgroup = Some(group);
+        }
+
+        throttle.report.niced_threads = usize::from(throttle.main.is_some())
+            + throttle.workers.iter().filter(|w| w.is_some()).count();
+
+        log::info!(
+            ">> Boot priority low: {} thread(s) at idle I/O, cgroup {}",
+            throttle.report.niced_threads,
+            throttle
+                .report
+                .cgroup
+                .as_deref()
+                .map_or("none".into(), |p| p.display().to_string())
+        );
+        throttle
+    }
+
+    pub fn report(&self) -> &PriorityReport {
+        &self.report
+    }
+
+    /// Puts every thread and the cgroup back. Safe to call more than once.
+    pub fn restore(&mut self) {
+        if self.main.is_none() && self.workers.is_empty() && self.original_cgroup.is_none() {
+            return;
+        }
+        if let Some(saved) = self.main.take() {
+            restore_thread(&saved);
+        }
+        if !self.workers.is_empty() {
+            // Broadcast reaches the threads in the same index order.
+            let workers = std::mem::take(&mut self.workers);
+            pool::broadcast(|ctx| {
+                if let Some(Some(saved)) = workers.get(ctx.index()) {
+                    restore_thread(saved);
+                }
+            });
+        }
+        if let (Some(original), Some(group)) =
+            (self.original_cgroup.take(), self.report.cgroup.as_deref())
+        {
+            leave_cgroup(Path::new(CGROUP_ROOT), &original, group);
+        }
+        log::info!(">> Boot priority restored for mounting");
+    }
+}
+
+impl Drop for Throttle {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
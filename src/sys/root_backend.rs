@@ -0,0 +1,293 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! The kernel interface of the root solution we run under.
+//!
+//! try_umount (unmounting module mounts in processes the root solution
+//! hides from) and sysfs nuking are features of the root implementation,
+//! not of the kernel. Each one is reached through its own driver, so every
+//! such call goes through the [`RootBackend`] [`detect`] picked at startup:
+//! KernelSU, APatch, or [`NullBackend`], which accepts everything and does
+//! nothing so a run without a usable driver still mounts.
+
+use std::{
+    ffi::CString,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex, MutexGuard},
+};
+
+use anyhow::{Result, anyhow, bail};
+use ksu::TryUmount;
+use serde::{Deserialize, Serialize};
+
+use crate::mount::umount_mgr;
+
+/// Root implementation whose driver the run talks to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    KernelSU,
+    APatch,
+    /// No driver answered; root-specific features are skipped.
+    #[default]
+    Null,
+}
+
+/// A root implementation's try_umount and hiding interface.
+pub trait RootBackend: Send {
+    fn backend(&self) -> Backend;
+    /// Queues `path` to be unmounted in processes the root solution hides
+    /// from.
+    fn add_try_umount(&mut self, path: &Path) -> Result<()>;
+    /// Hands the queued paths to the driver.
+    fn commit_umounts(&mut self) -> Result<()>;
+    /// Removes the sysfs node the filesystem mounted at `path` registered,
+    /// e.g. `/sys/fs/ext4/loopN` of modules.img.
+    fn nuke_sysfs(&mut self, path: &Path) -> Result<()>;
+}
+
+/// KernelSU's data directory and daemon.
+const KSU_PATHS: [&str; 2] = ["/data/adb/ksu", "/data/adb/ksud"];
+/// APatch's data directory and daemon.
+const APATCH_PATHS: [&str; 2] = ["/data/adb/ap", "/data/adb/apd"];
+
+/// What [`detect`] looks at; [`Evidence::probe`] collects it on the device.
+#[derive(Debug, Clone)]
+pub struct Evidence {
+    /// The KernelSU driver answered its handshake.
+    pub ksu_driver: bool,
+    /// The KernelPatch supercall under APatch answered.
+    pub apatch_driver: bool,
+    /// Filesystem root the manager paths are looked up under.
+    pub root: PathBuf,
+}
+
+impl Evidence {
+    pub fn probe() -> Self {
+        Self {
+            ksu_driver: ksu::version().is_some(),
+            apatch_driver: apatch::hello(),
+            root: PathBuf::from("/"),
+        }
+    }
+
+    fn installed(&self, paths: &[&str]) -> bool {
+        paths
+            .iter()
+            .all(|p| self.root.join(p.trim_start_matches('/')).exists())
+    }
+}
+
+/// The backend for `evidence`. A driver that answers beats files left
+/// behind by a manager that is gone. When both drivers answer, the one
+/// whose manager is installed wins, KernelSU if both or neither are.
+/// Without a driver the run falls back to [`Backend::Null`], whatever
+/// directories exist.
+pub fn detect_from(evidence: &Evidence) -> Backend {
+    match (evidence.ksu_driver, evidence.apatch_driver) {
+        (true, true) => {
+            if evidence.installed(&APATCH_PATHS) && !evidence.installed(&KSU_PATHS) {
+                Backend::APatch
+            } else {
+                Backend::KernelSU
+            }
+        }
+        (true, false) => Backend::KernelSU,
+        (false, true) => Backend::APatch,
+        (false, false) => Backend::Null,
+    }
+}
+
+/// The backend of this device.
+pub fn detect() -> Backend {
+    detect_from(&Evidence::probe())
+}
+
+impl Backend {
+    pub fn open(self) -> Box<dyn RootBackend> {
+        match self {
+            Self::KernelSU => Box::new(KernelSuBackend::default()),
+            Self::APatch => Box::new(ApatchBackend::default()),
+            Self::Null => Box::new(NullBackend),
+        }
+    }
+}
+
+static ACTIVE: LazyLock<Mutex<Box<dyn RootBackend>>> =
+    LazyLock::new(|| Mutex::new(Backend::Null.open()));
+
+/// Makes `backend` the one every later call goes through.
+pub fn install(backend: Backend) {
+    *active() = backend.open();
+}
+
+/// The backend calls go through; [`NullBackend`] until [`install`].
+pub fn active() -> MutexGuard<'static, Box<dyn RootBackend>> {
+    ACTIVE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub struct KernelSuBackend {
+    list: TryUmount,
+}
+
+impl Default for KernelSuBackend {
+    fn default() -> Self {
+        Self {
+            list: TryUmount::new(),
+        }
+    }
+}
+
+impl RootBackend for KernelSuBackend {
+    fn backend(&self) -> Backend {
+        Backend::KernelSU
+    }
+
+    fn add_try_umount(&mut self, path: &Path) -> Result<()> {
+        self.list.add(path);
+        Ok(())
+    }
+
+    fn commit_umounts(&mut self) -> Result<()> {
+        let list = &mut self.list;
+        umount_mgr::with_flag_retry(|flags| {
+            list.flags(flags);
+            list.umount().map_err(|e| anyhow!("{:#}", e))
+        })?;
+        self.list = TryUmount::new();
+        Ok(())
+    }
+
+    fn nuke_sysfs(&mut self, path: &Path) -> Result<()> {
+        ksu_ioctl::nuke_ext4_sysfs(path)
+    }
+}
+
+/// APatch has no try_umount or sysfs interface in KernelPatch; its driver
+/// is only used to recognise it. Paths are still collected so the log
+/// says what stays visible.
+#[derive(Default)]
+pub struct ApatchBackend {
+    pending: Vec<PathBuf>,
+}
+
+impl RootBackend for ApatchBackend {
+    fn backend(&self) -> Backend {
+        Backend::APatch
+    }
+
+    fn add_try_umount(&mut self, path: &Path) -> Result<()> {
+        self.pending.push(path.to_path_buf());
+        Ok(())
+    }
+
+    fn commit_umounts(&mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            log::info!(
+                "APatch has no try_umount; {} mounts stay visible to hidden apps",
+                self.pending.len()
+            );
+        }
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn nuke_sysfs(&mut self, path: &Path) -> Result<()> {
+        log::debug!("APatch cannot nuke sysfs for {}", path.display());
+        Ok(())
+    }
+}
+
+pub struct NullBackend;
+
+impl RootBackend for NullBackend {
+    fn backend(&self) -> Backend {
+        Backend::Null
+    }
+
+    fn add_try_umount(&mut self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn commit_umounts(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn nuke_sysfs(&mut self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The KernelSU driver's ioctl interface, reached through the fd its
+/// reboot-syscall handshake installs.
+mod ksu_ioctl {
+    use super::*;
+
+    const INSTALL_MAGIC1: libc::c_int = 0xDEADBEEFu32 as libc::c_int;
+    const INSTALL_MAGIC2: libc::c_int = 0xCAFEBABEu32 as libc::c_int;
+    /// `_IOC(_IOC_WRITE, 'K', 17, 0)`
+    const IOCTL_NUKE_EXT4_SYSFS: libc::c_ulong = (1 << 30) | ((b'K' as libc::c_ulong) << 8) | 17;
+
+    #[repr(C)]
+    struct NukeExt4SysfsCmd {
+        arg: u64,
+    }
+
+    fn grab_fd() -> Result<libc::c_int> {
+        let mut fd: libc::c_int = -1;
+        // SAFETY: the driver only writes the fd through the pointer; the
+        // kernel rejects the call unchanged when no driver is listening.
+        unsafe {
+            libc::syscall(
+                libc::SYS_reboot,
+                INSTALL_MAGIC1,
+                INSTALL_MAGIC2,
+                0,
+                &mut fd as *mut libc::c_int,
+            );
+        }
+        if fd < 0 {
+            bail!("KernelSU driver did not hand out an fd");
+        }
+        Ok(fd)
+    }
+
+    pub fn nuke_ext4_sysfs(path: &Path) -> Result<()> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let fd = grab_fd()?;
+        let mut cmd = NukeExt4SysfsCmd {
+            arg: path.as_ptr() as u64,
+        };
+        // SAFETY: `cmd` and the path it points to outlive the call.
+        let ret = unsafe { libc::ioctl(fd, IOCTL_NUKE_EXT4_SYSFS as _, &mut cmd) };
+        let err = std::io::Error::last_os_error();
+        // SAFETY: `fd` was handed to us and is closed once.
+        unsafe { libc::close(fd) };
+        if ret < 0 {
+            bail!("nuke_ext4_sysfs failed: {}", err);
+        }
+        Ok(())
+    }
+}
+
+/// The KernelPatch supercall APatch is built on.
+mod apatch {
+    const NR_SUPERCALL: libc::c_long = 45;
+    const SUPERCALL_HELLO: libc::c_long = 0x1000;
+    const SUPERCALL_HELLO_MAGIC: libc::c_long = 0x11581158;
+
+    /// Whether KernelPatch answers the hello supercall. Root processes
+    /// APatch allowed may call it without the superkey. KernelPatch hooks
+    /// syscall 45, which is `truncate` on arm64; elsewhere it is unrelated.
+    pub fn hello() -> bool {
+        if !cfg!(target_arch = "aarch64") {
+            return false;
+        }
+        let ver_and_cmd = (0x1158 << 16) | SUPERCALL_HELLO;
+        // SAFETY: without KernelPatch this is `truncate("")`, which fails
+        // with ENOENT and has no effect.
+        let ret = unsafe { libc::syscall(NR_SUPERCALL, c"".as_ptr(), ver_and_cmd) };
+        ret == SUPERCALL_HELLO_MAGIC
+    }
+}
@@ -0,0 +1,141 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! SUSFS support on kernels patched with it: `sus_path` hides paths from
+//! unprivileged processes on top of try_umount, and SUSFS's own try_umount
+//! list serves KernelSU builds whose driver has none.
+
+use std::{
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+/// `prctl` option KernelSU answers on.
+const KERNEL_SU_OPTION: libc::c_int = 0xDEADBEEFu32 as libc::c_int;
+const CMD_SUSFS_ADD_SUS_PATH: libc::c_ulong = 0x55550;
+const CMD_SUSFS_ADD_TRY_UMOUNT: libc::c_ulong = 0x55580;
+/// `mnt_mode` asking SUSFS to unmount with `MNT_DETACH`.
+const TRY_UMOUNT_DETACH: libc::c_int = 1;
+/// What the kernel leaves in the error slot when it does not know the
+/// command, i.e. SUSFS is absent or built without it.
+const ERR_CMD_NOT_SUPPORTED: libc::c_int = 126;
+const SUSFS_MAX_LEN_PATHNAME: usize = 256;
+
+#[repr(C)]
+struct SusPath {
+    target_ino: libc::c_ulong,
+    target_pathname: [u8; SUSFS_MAX_LEN_PATHNAME],
+    i_uid: libc::c_uint,
+}
+
+#[repr(C)]
+struct TryUmount {
+    target_pathname: [u8; SUSFS_MAX_LEN_PATHNAME],
+    mnt_mode: libc::c_int,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SusfsError {
+    /// The kernel does not implement the command.
+    Unsupported,
+    /// The path is missing or too long to pass.
+    InvalidPath,
+    /// The kernel rejected the request with this error.
+    Failed(i32),
+}
+
+impl std::fmt::Display for SusfsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unsupported => write!(f, "SUSFS command is not supported by this kernel"),
+            Self::InvalidPath => write!(f, "path is missing or too long"),
+            Self::Failed(code) => write!(f, "SUSFS returned error {}", code),
+        }
+    }
+}
+
+impl std::error::Error for SusfsError {}
+
+/// `path` as the NUL-terminated buffer SUSFS commands take.
+fn pathname(path: &Path) -> Result<[u8; SUSFS_MAX_LEN_PATHNAME], SusfsError> {
+    let bytes = path.as_os_str().as_encoded_bytes();
+    if bytes.len() >= SUSFS_MAX_LEN_PATHNAME {
+        return Err(SusfsError::InvalidPath);
+    }
+    let mut buf = [0; SUSFS_MAX_LEN_PATHNAME];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    Ok(buf)
+}
+
+/// Sends `cmd` with `arg` through KernelSU's prctl hook.
+fn call<T>(cmd: libc::c_ulong, arg: &mut T) -> Result<(), SusfsError> {
+    let mut error: libc::c_int = ERR_CMD_NOT_SUPPORTED;
+    // SAFETY: `arg` and `error` outlive the call, and the kernel only
+    // writes the error slot. Without SUSFS the option is unknown and
+    // nothing is touched.
+    unsafe {
+        libc::prctl(
+            KERNEL_SU_OPTION,
+            cmd,
+            arg as *mut T as libc::c_ulong,
+            0 as libc::c_ulong,
+            &mut error as *mut libc::c_int as libc::c_ulong,
+        );
+    }
+
+    match error {
+        0 => Ok(()),
+        ERR_CMD_NOT_SUPPORTED => Err(SusfsError::Unsupported),
+        code => Err(SusfsError::Failed(code)),
+    }
+}
+
+/// Registers `path` as a SUSFS `sus_path`.
+pub fn add_sus_path(path: &Path) -> Result<(), SusfsError> {
+    let meta = std::fs::metadata(path).map_err(|_| SusfsError::InvalidPath)?;
+    let mut info = SusPath {
+        target_ino: meta.ino() as libc::c_ulong,
+        target_pathname: pathname(path)?,
+        i_uid: meta.uid(),
+    };
+    call(CMD_SUSFS_ADD_SUS_PATH, &mut info)
+}
+
+/// Adds `path` to SUSFS's try_umount list. SUSFS unmounts it in every app
+/// process it hides from, detached, since it does not retry on `EBUSY`.
+pub fn add_try_umount(path: &Path) -> Result<(), SusfsError> {
+    let mut info = TryUmount {
+        target_pathname: pathname(path)?,
+        mnt_mode: TRY_UMOUNT_DETACH,
+    };
+    call(CMD_SUSFS_ADD_TRY_UMOUNT, &mut info)
+}
+
+/// Hides each of `paths`, stopping at the first sign that the kernel lacks
+/// SUSFS. Returns whether SUSFS answered and the paths it accepted. Nothing
+/// here is fatal: failures are only logged at debug level.
+pub fn hide_paths(paths: &[PathBuf]) -> (bool, Vec<PathBuf>) {
+    let mut active = false;
+    let mut hidden = Vec::new();
+
+    for path in paths {
+        match add_sus_path(path) {
+            Ok(()) => {
+                active = true;
+                hidden.push(path.clone());
+            }
+            Err(SusfsError::Unsupported) => {
+                log::debug!("SUSFS sus_path unavailable, not hiding storage paths");
+                break;
+            }
+            Err(e) => {
+                if matches!(e, SusfsError::Failed(_)) {
+                    active = true;
+                }
+                log::debug!("SUSFS could not hide {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    (active, hidden)
+}
@@ -0,0 +1,73 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Cooperative cancellation on SIGTERM and SIGINT.
+//!
+//! The handler only records the signal. The mount sequence polls
+//! [`requested`] between stages and between mount operations, so work that
+//! already started (including rayon jobs) runs to completion and only queued
+//! work is skipped.
+
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use anyhow::{Context, Result};
+use nix::sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal, sigaction};
+
+static PENDING: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    PENDING.store(signal, Ordering::SeqCst);
+}
+
+/// Installs the SIGTERM and SIGINT handlers. `SA_RESTART` keeps an
+/// interrupted mount or copy going instead of failing it with `EINTR`.
+pub fn install() -> Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(on_signal),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+
+    for signal in [Signal::SIGTERM, Signal::SIGINT] {
+        unsafe { sigaction(signal, &action) }
+            .with_context(|| format!("Failed to install {} handler", signal))?;
+    }
+
+    Ok(())
+}
+
+/// The signal that asked us to stop, if any.
+pub fn requested() -> Option<i32> {
+    match PENDING.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+/// Asks the running sequence to stop as if `signal` had been received.
+pub fn request(signal: i32) {
+    PENDING.store(signal, Ordering::SeqCst);
+}
+
+/// Error returned by a mount sequence stopped by a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled(pub i32);
+
+impl Cancelled {
+    /// Shell-style status for death by signal: 130 for SIGINT, 143 for
+    /// SIGTERM.
+    pub fn exit_code(&self) -> i32 {
+        128 + self.0
+    }
+}
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match Signal::try_from(self.0) {
+            Ok(signal) => write!(f, "cancelled by {}", signal),
+            Err(_) => write!(f, "cancelled by signal {}", self.0),
+        }
+    }
+}
+
+impl std::error::Error for Cancelled {}
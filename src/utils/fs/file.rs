@@ -2,10 +2,9 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::{
-    collections::HashSet,
     ffi::CString,
-    fs::{self, File, OpenOptions},
-    io::Write,
+    fs::{self, File, Metadata, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
     os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt, symlink},
     path::Path,
     time::{SystemTime, UNIX_EPOCH},
@@ -16,6 +15,7 @@ use rustix::fs::ioctl_ficlone;
 use walkdir::WalkDir;
 
 use super::xattr::{internal_apply_system_context, internal_copy_extended_attributes};
+use crate::defs;
 
 pub fn atomic_write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, content: C) -> Result<()> {
     let path = path.as_ref();
@@ -35,6 +35,7 @@ pub fn atomic_write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, content: C) -> Resu
             .create_new(true)
             .open(&temp_file)?;
         file.write_all(content.as_ref())?;
+        file.sync_all()?;
     }
 
     if let Err(_e) = fs::rename(&temp_file, path) {
@@ -44,6 +45,11 @@ pub fn atomic_write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, content: C) -> Resu
         }
         let _ = fs::remove_file(&temp_file);
     }
+
+    // Persist the rename itself, or a power loss can bring back the old file.
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
     Ok(())
 }
 
@@ -54,13 +60,47 @@ pub fn ensure_dir_exists<T: AsRef<Path>>(dir: T) -> Result<()> {
     Ok(())
 }
 
+/// Bytes `meta` takes up on disk, less than its length when the file has
+/// holes.
+pub fn allocated_size(meta: &Metadata) -> u64 {
+    meta.blocks() * 512
+}
+
+/// Copies the data extents of `src` into the empty `dest`, leaving the
+/// holes between them unwritten, and extends `dest` to `len`.
+fn copy_sparse(mut src: &File, mut dest: &File, len: u64) -> io::Result<()> {
+    let mut offset = 0;
+    while offset < len {
+        let data = match rustix::fs::seek(src, rustix::fs::SeekFrom::Data(offset)) {
+            Ok(data) => data,
+            // Nothing but a hole left.
+            Err(rustix::io::Errno::NXIO) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let hole = rustix::fs::seek(src, rustix::fs::SeekFrom::Hole(data))?.min(len);
+        src.seek(SeekFrom::Start(data))?;
+        dest.seek(SeekFrom::Start(data))?;
+        io::copy(&mut src.take(hole - data), &mut dest)?;
+        offset = hole;
+    }
+    dest.set_len(len)
+}
+
+/// Copies `src` to `dest` as a reflink where the filesystem can share
+/// extents. Otherwise a file with holes is copied extent by extent so
+/// `dest` keeps them, and any other file through `copy_file_range`.
 pub fn reflink_or_copy(src: &Path, dest: &Path) -> Result<u64> {
     let src_file = File::open(src)?;
     let dest_file = File::create(dest)?;
+    let metadata = src_file.metadata()?;
+    let len = metadata.len();
 
     if ioctl_ficlone(&dest_file, &src_file).is_ok() {
-        let metadata = src_file.metadata()?;
-        let len = metadata.len();
+        dest_file.set_permissions(metadata.permissions())?;
+        return Ok(len);
+    }
+    // Filesystems without SEEK_DATA fall back to a plain copy below.
+    if allocated_size(&metadata) < len && copy_sparse(&src_file, &dest_file, len).is_ok() {
         dest_file.set_permissions(metadata.permissions())?;
         return Ok(len);
     }
@@ -78,87 +118,82 @@ fn make_device_node(path: &Path, mode: u32, rdev: u64) -> Result<()> {
             bail!("mknod failed for {}: {}", path.display(), err);
         }
     }
+    // mknod honours the umask; restore the exact permission bits.
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))?;
     Ok(())
 }
 
-fn native_cp_r(
-    src: &Path,
-    dst: &Path,
-    relative: &Path,
-    repair: bool,
-    visited: &mut HashSet<(u64, u64)>,
-) -> Result<()> {
-    if !dst.exists() {
-        if src.is_dir() {
-            fs::create_dir_all(dst)?;
-        }
-        if let Ok(src_meta) = src.metadata() {
-            let _ = fs::set_permissions(dst, src_meta.permissions());
-        }
+fn remove_path(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path)?,
+        Ok(_) => fs::remove_file(path)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
 
-        if repair && relative.as_os_str().is_empty() {
-            let _ = internal_apply_system_context(dst, relative);
-        } else if !repair {
-            let _ = internal_copy_extended_attributes(src, dst);
-        }
+/// Copies a single non-directory entry (file, symlink or device node) to
+/// `dst`, replacing whatever currently exists there.
+pub fn copy_entry(src: &Path, dst: &Path, relative: &Path, repair: bool) -> Result<()> {
+    let metadata = fs::symlink_metadata(src)?;
+    let ft = metadata.file_type();
+
+    // A hard-linked destination is unlinked first so rewriting it does not
+    // change the other names sharing its inode.
+    if let Ok(dst_meta) = fs::symlink_metadata(dst)
+        && (ft.is_symlink() || !dst_meta.is_file() || !ft.is_file() || dst_meta.nlink() > 1)
+    {
+        remove_path(dst)?;
     }
 
-    for entry in fs::read_dir(src)? {
-        let entry = entry?;
-        let src_path = entry.path();
-        let file_name = entry.file_name();
-        let dst_path = dst.join(&file_name);
-        let next_relative = relative.join(&file_name);
-
-        let metadata = entry.metadata()?;
-        let ft = metadata.file_type();
-        let dev = metadata.dev();
-        let ino = metadata.ino();
-
-        if ft.is_dir() {
-            if !visited.insert((dev, ino)) {
-                continue;
-            }
-            native_cp_r(&src_path, &dst_path, &next_relative, repair, visited)?;
-        } else if ft.is_symlink() {
-            if dst_path.exists() {
-                fs::remove_file(&dst_path)?;
-            }
-            let link_target = fs::read_link(&src_path)?;
-            symlink(&link_target, &dst_path)?;
-        } else if ft.is_char_device() || ft.is_block_device() || ft.is_fifo() {
-            if dst_path.exists() {
-                fs::remove_file(&dst_path)?;
-            }
-            let mode = metadata.permissions().mode();
-            let rdev = metadata.rdev();
-            make_device_node(&dst_path, mode, rdev)?;
-        } else {
-            reflink_or_copy(&src_path, &dst_path)?;
+    if ft.is_symlink() {
+        let link_target = fs::read_link(src)?;
+        symlink(&link_target, dst)?;
+    } else if ft.is_char_device() || ft.is_block_device() || ft.is_fifo() {
+        let mode = metadata.permissions().mode();
+        let rdev = metadata.rdev();
+        make_device_node(dst, mode, rdev)?;
+    } else {
+        reflink_or_copy(src, dst)?;
+        if let Ok(mtime) = metadata.modified() {
+            let _ = File::options()
+                .write(true)
+                .open(dst)
+                .and_then(|f| f.set_modified(mtime));
         }
+    }
 
-        let _ = internal_copy_extended_attributes(&src_path, &dst_path);
+    let _ = internal_copy_extended_attributes(src, dst);
 
-        if repair {
-            let _ = internal_apply_system_context(&dst_path, &next_relative);
-        }
+    if repair {
+        let _ = internal_apply_system_context(dst, relative);
     }
+
     Ok(())
 }
 
-pub fn sync_dir(src: &Path, dst: &Path, repair_context: bool) -> Result<()> {
-    if !src.exists() {
-        return Ok(());
+/// Ensures `dst` is a directory carrying the permissions and labels of `src`.
+/// Returns `true` when the directory had to be created.
+pub fn mirror_dir(src: &Path, dst: &Path, relative: &Path, repair: bool) -> Result<bool> {
+    if dst.symlink_metadata().is_ok_and(|m| m.is_dir()) {
+        return Ok(false);
+    }
+
+    remove_path(dst)?;
+    fs::create_dir_all(dst)?;
+
+    if let Ok(src_meta) = src.metadata() {
+        let _ = fs::set_permissions(dst, src_meta.permissions());
+    }
+
+    let _ = internal_copy_extended_attributes(src, dst);
+
+    if repair {
+        let _ = internal_apply_system_context(dst, relative);
     }
-    ensure_dir_exists(dst)?;
-    let mut visited = HashSet::new();
-    native_cp_r(src, dst, Path::new(""), repair_context, &mut visited).with_context(|| {
-        format!(
-            "Failed to natively sync {} to {}",
-            src.display(),
-            dst.display()
-        )
-    })
+
+    Ok(true)
 }
 
 pub fn prune_empty_dirs<P: AsRef<Path>>(root: P) -> Result<()> {
@@ -180,3 +215,35 @@ pub fn prune_empty_dirs<P: AsRef<Path>>(root: P) -> Result<()> {
     }
     Ok(())
 }
+
+/// Whether `path` has a `<name>.rw` sibling asking for it to stay writable.
+pub fn has_rw_marker(path: &Path) -> bool {
+    let mut marker = path.as_os_str().to_owned();
+    marker.push(defs::RW_MARKER_SUFFIX);
+    Path::new(&marker).is_file()
+}
+
+/// Whether `path` is a Magisk-style `.replace` marker file.
+pub fn is_replace_marker(path: &Path) -> bool {
+    path.file_name() == Some(defs::REPLACE_DIR_FILE_NAME.as_ref())
+        && fs::symlink_metadata(path).is_ok_and(|m| m.is_file())
+}
+
+/// Whether the directory `dir` replaces its stock counterpart wholesale,
+/// through a `.replace` marker or an opaque xattr.
+pub fn is_replace_dir(dir: &Path) -> bool {
+    is_replace_marker(&dir.join(defs::REPLACE_DIR_FILE_NAME)) || super::is_overlay_opaque(dir)
+}
+
+/// Whether `path` is itself a `.rw` marker next to a regular file.
+pub fn is_rw_marker(path: &Path) -> bool {
+    let Some(stem) = path
+        .as_os_str()
+        .to_str()
+        .and_then(|p| p.strip_suffix(defs::RW_MARKER_SUFFIX))
+    else {
+        return false;
+    };
+
+    fs::symlink_metadata(stem).is_ok_and(|m| m.is_file())
+}
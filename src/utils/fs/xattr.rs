@@ -1,15 +1,13 @@
 // Copyright 2026 Hybrid Mount Developers
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-#[cfg(any(target_os = "linux", target_os = "android"))]
-use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
 #[cfg(any(target_os = "linux", target_os = "android"))]
-use std::process::Command;
+use std::{fs, os::unix::ffi::OsStrExt};
 
 use anyhow::{Context, Result};
 #[cfg(any(target_os = "linux", target_os = "android"))]
-use extattr::{Flags as XattrFlags, lgetxattr, llistxattr, lsetxattr};
+use extattr::{Flags as XattrFlags, lgetxattr, llistxattr, lremovexattr, lsetxattr};
 
 const SELINUX_XATTR: &str = "security.selinux";
 const OVERLAY_OPAQUE_XATTR: &str = "trusted.overlay.opaque";
@@ -18,6 +16,23 @@ const CONTEXT_VENDOR: &str = "u:object_r:vendor_file:s0";
 const CONTEXT_HAL: &str = "u:object_r:same_process_hal_file:s0";
 const CONTEXT_VENDOR_EXEC: &str = "u:object_r:vendor_file:s0";
 const CONTEXT_ROOTFS: &str = "u:object_r:rootfs:s0";
+const CONTEXT_UNLABELED: &str = "u:object_r:unlabeled:s0";
+const SELINUXFS_ENFORCE: &str = "/sys/fs/selinux/enforce";
+const CONTEXT_SYSTEM_DLKM: &str = "u:object_r:system_dlkm_file:s0";
+const CONTEXT_VENDOR_DLKM: &str = "u:object_r:vendor_dlkm_file:s0";
+
+/// The fixed context of everything on a kernel module partition, for paths
+/// under `/system_dlkm` or `/vendor_dlkm`. The kernel refuses to load a .ko
+/// that carries any other label there.
+fn dlkm_context(path: &Path) -> Option<&'static str> {
+    if path.starts_with("/system_dlkm") {
+        Some(CONTEXT_SYSTEM_DLKM)
+    } else if path.starts_with("/vendor_dlkm") {
+        Some(CONTEXT_VENDOR_DLKM)
+    } else {
+        None
+    }
+}
 
 fn copy_extended_attributes(src: &Path, dst: &Path) -> Result<()> {
     #[cfg(any(target_os = "linux", target_os = "android"))]
@@ -50,6 +65,45 @@ fn copy_extended_attributes(src: &Path, dst: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Whether a synced entry may keep `ctx`: set, and neither the `rootfs`
+/// every unlabeled tmpfs file reports nor `unlabeled`.
+pub fn is_valid_context(ctx: &str) -> bool {
+    !ctx.is_empty() && ctx != CONTEXT_ROOTFS && ctx != CONTEXT_UNLABELED
+}
+
+/// Whether the kernel runs SELinux, so files can carry a context at all.
+pub fn selinux_enabled() -> bool {
+    Path::new(SELINUXFS_ENFORCE).exists()
+}
+
+/// Compares the `trusted.overlay.*` attributes that sync carries over verbatim.
+pub fn overlay_xattrs_equal<P: AsRef<Path>>(src: P, dst: P) -> bool {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let collect = |path: &Path| -> Vec<(Vec<u8>, Vec<u8>)> {
+            let mut attrs: Vec<(Vec<u8>, Vec<u8>)> = llistxattr(path)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|name| name.as_bytes().starts_with(b"trusted.overlay."))
+                .filter_map(|name| {
+                    lgetxattr(path, &name)
+                        .ok()
+                        .map(|val| (name.as_bytes().to_vec(), val))
+                })
+                .collect();
+            attrs.sort();
+            attrs
+        };
+
+        collect(src.as_ref()) == collect(dst.as_ref())
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = (src, dst);
+        true
+    }
+}
+
 pub fn set_overlay_opaque<P: AsRef<Path>>(path: P) -> Result<()> {
     #[cfg(any(target_os = "linux", target_os = "android"))]
     {
@@ -63,6 +117,26 @@ pub fn set_overlay_opaque<P: AsRef<Path>>(path: P) -> Result<()> {
     Ok(())
 }
 
+pub fn is_overlay_opaque<P: AsRef<Path>>(path: P) -> bool {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        lgetxattr(path.as_ref(), OVERLAY_OPAQUE_XATTR).is_ok_and(|v| v == b"y")
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = path;
+        false
+    }
+}
+
+pub fn clear_overlay_opaque<P: AsRef<Path>>(path: P) -> Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        lremovexattr(path.as_ref(), OVERLAY_OPAQUE_XATTR)?;
+    }
+    Ok(())
+}
+
 pub fn lsetfilecon<P: AsRef<Path>>(path: P, con: &str) -> Result<()> {
     #[cfg(any(target_os = "linux", target_os = "android"))]
     {
@@ -96,37 +170,45 @@ pub fn lgetfilecon<P: AsRef<Path>>(_path: P) -> Result<String> {
     unimplemented!();
 }
 
-pub fn is_overlay_xattr_supported() -> Result<bool> {
+/// Reports whether the filesystem backing `path` can carry the
+/// `trusted.overlay.*` xattrs that opaque directories depend on, by setting
+/// and removing `trusted.overlay.opaque` on a scratch directory below it.
+/// Where no directory can be created, only an erofs image, which keeps the
+/// xattrs it was built with, counts as supporting them.
+pub fn fs_supports_overlay_xattrs<P: AsRef<Path>>(path: P) -> bool {
     #[cfg(any(target_os = "linux", target_os = "android"))]
     {
-        let output = Command::new("zcat")
-            .arg("/proc/config.gz")
-            .output()
-            .context("Failed to read config.gz")
-            .unwrap();
-        let config = String::from_utf8_lossy(&output.stdout);
-
-        for i in config.lines() {
-            if i.starts_with("#") {
-                continue;
-            }
-
-            let Some((k, v)) = i.split_once('=') else {
-                continue;
-            };
+        const EROFS_SUPER_MAGIC: rustix::fs::FsWord = 0xE0F5_E1E2;
 
-            if k.trim() == "CONFIG_TMPFS_XATTR" && v.trim() == "y" {
-                return Ok(true);
-            }
+        let path = path.as_ref();
+        let scratch = path.join(format!(
+            ".overlay_xattr_probe.{}",
+            crate::utils::random_u32()
+        ));
+        if fs::create_dir(&scratch).is_err() {
+            return rustix::fs::statfs(path).is_ok_and(|st| st.f_type == EROFS_SUPER_MAGIC);
         }
 
-        Ok(false)
+        let supported = lsetxattr(&scratch, OVERLAY_OPAQUE_XATTR, b"y", XattrFlags::empty())
+            .is_ok()
+            && lremovexattr(&scratch, OVERLAY_OPAQUE_XATTR).is_ok();
+        if let Err(e) = fs::remove_dir(&scratch) {
+            log::warn!("Failed to remove {}: {}", scratch.display(), e);
+        }
+        supported
     }
     #[cfg(not(any(target_os = "linux", target_os = "android")))]
-    Ok(true)
+    {
+        let _ = path;
+        true
+    }
 }
 
 fn guess_context_by_path(path: &Path) -> &'static str {
+    if let Some(ctx) = dlkm_context(path) {
+        return ctx;
+    }
+
     let path_str = path.to_string_lossy();
 
     if path_str.starts_with("/vendor") || path_str.starts_with("/odm") {
@@ -148,33 +230,21 @@ fn guess_context_by_path(path: &Path) -> &'static str {
     CONTEXT_SYSTEM
 }
 
-fn apply_system_context(current: &Path, relative: &Path) -> Result<()> {
-    if let Some(name) = current.file_name().and_then(|n| n.to_str())
-        && (name == "upperdir" || name == "workdir")
-        && let Some(parent) = current.parent()
-        && let Ok(ctx) = lgetfilecon(parent)
-    {
-        return lsetfilecon(current, &ctx);
-    }
-
-    let current_ctx = lgetfilecon(current).ok();
-    if let Some(ctx) = &current_ctx
-        && !ctx.is_empty()
-        && ctx != CONTEXT_ROOTFS
-        && ctx != "u:object_r:unlabeled:s0"
-    {
-        return Ok(());
-    }
-
+/// Returns the context a synced entry for `relative` should carry, derived
+/// from the stock path, its parent, or a path-based guess. Kernel module
+/// partitions always get their fixed context.
+pub fn expected_system_context(relative: &Path) -> String {
     let system_path = Path::new("/").join(relative);
+    if let Some(ctx) = dlkm_context(&system_path) {
+        return ctx.to_string();
+    }
     if system_path.exists() {
         if let Ok(sys_ctx) = lgetfilecon(&system_path) {
-            let target_ctx = if sys_ctx == CONTEXT_ROOTFS {
-                CONTEXT_SYSTEM
+            return if sys_ctx == CONTEXT_ROOTFS {
+                CONTEXT_SYSTEM.to_string()
             } else {
-                &sys_ctx
+                sys_ctx
             };
-            return lsetfilecon(current, target_ctx);
         }
     } else if let Some(parent) = system_path.parent()
         && parent.exists()
@@ -183,13 +253,37 @@ fn apply_system_context(current: &Path, relative: &Path) -> Result<()> {
     {
         let guessed = guess_context_by_path(&system_path);
         if guessed == CONTEXT_HAL && parent_ctx == CONTEXT_VENDOR {
-            return lsetfilecon(current, CONTEXT_HAL);
+            return CONTEXT_HAL.to_string();
         }
-        return lsetfilecon(current, &parent_ctx);
+        return parent_ctx;
+    }
+
+    guess_context_by_path(&system_path).to_string()
+}
+
+fn apply_system_context(current: &Path, relative: &Path) -> Result<()> {
+    if let Some(name) = current.file_name().and_then(|n| n.to_str())
+        && (name == "upperdir" || name == "workdir")
+        && let Some(parent) = current.parent()
+        && let Ok(ctx) = lgetfilecon(parent)
+    {
+        return lsetfilecon(current, &ctx);
+    }
+
+    let current_ctx = lgetfilecon(current).ok();
+    if let Some(expected) = dlkm_context(&Path::new("/").join(relative)) {
+        if current_ctx.as_deref() == Some(expected) {
+            return Ok(());
+        }
+        return lsetfilecon(current, expected);
+    }
+    if let Some(ctx) = &current_ctx
+        && is_valid_context(ctx)
+    {
+        return Ok(());
     }
 
-    let target_context = guess_context_by_path(&system_path);
-    lsetfilecon(current, target_context)
+    lsetfilecon(current, &expected_system_context(relative))
 }
 
 pub(crate) fn internal_copy_extended_attributes(src: &Path, dst: &Path) -> Result<()> {
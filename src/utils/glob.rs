@@ -0,0 +1,107 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Shell-style globs over absolute paths, compiled to anchored regexes.
+//!
+//! `*` and `?` stay within one path segment, `**` spans any number of
+//! segments (`**/x` also matches `x` itself, `x/**` everything below `x`),
+//! `[...]` / `[!...]` are character classes and `{a,b}` alternatives.
+
+use anyhow::{Result, bail};
+use regex_lite::Regex;
+
+#[derive(Debug, Clone)]
+pub struct Glob {
+    pattern: String,
+    regex: Regex,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let regex = Regex::new(&translate(pattern)?)?;
+        Ok(Self {
+            pattern: pattern.to_string(),
+            regex,
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        self.regex.is_match(path)
+    }
+}
+
+fn translate(pattern: &str) -> Result<String> {
+    if pattern.is_empty() {
+        bail!("empty glob");
+    }
+
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut out = String::from("^");
+    let mut in_braces = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let at_start = i == 0 || chars[i - 1] == '/';
+                let at_end = i + 2 == chars.len();
+                let before_slash = chars.get(i + 2) == Some(&'/');
+                if !at_start || !(at_end || before_slash) {
+                    bail!("'**' must be a whole path segment in '{}'", pattern);
+                }
+                if at_end {
+                    out.push_str(".*");
+                    i += 2;
+                } else {
+                    // `**/` matches zero or more whole segments.
+                    out.push_str("(?:.*/)?");
+                    i += 3;
+                }
+                continue;
+            }
+            '*' => out.push_str("[^/]*"),
+            '?' => out.push_str("[^/]"),
+            '[' => {
+                let Some(len) = chars[i + 1..].iter().skip(1).position(|&c| c == ']') else {
+                    bail!("unclosed '[' in '{}'", pattern);
+                };
+                let class: String = chars[i + 1..i + 2 + len].iter().collect();
+                let class = match class.strip_prefix('!') {
+                    Some(rest) => format!("^{}", rest),
+                    None => class,
+                };
+                out.push('[');
+                out.push_str(&class.replace('\\', "\\\\").replace('[', "\\["));
+                out.push(']');
+                i += len + 3;
+                continue;
+            }
+            '{' if !in_braces => {
+                in_braces = true;
+                out.push_str("(?:");
+            }
+            ',' if in_braces => out.push('|'),
+            '}' if in_braces => {
+                in_braces = false;
+                out.push(')');
+            }
+            '{' => bail!("nested '{{' in '{}'", pattern),
+            _ => {
+                let mut buf = [0; 4];
+                out.push_str(&regex_lite::escape(c.encode_utf8(&mut buf)));
+            }
+        }
+        i += 1;
+    }
+
+    if in_braces {
+        bail!("unclosed '{{' in '{}'", pattern);
+    }
+    out.push('$');
+    Ok(out)
+}
@@ -0,0 +1,126 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{fs, path::Path, sync::OnceLock};
+
+use rustix::mount::{FsOpenFlags, UnmountFlags, fsopen, unmount};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    defs,
+    mount::overlayfs::overlayfs::{MAX_LOWERDIR_COUNT, max_lowerdir_len},
+};
+
+/// Root solution the daemon runs under.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RootImpl {
+    KernelSU,
+    APatch,
+    Magisk,
+    #[default]
+    Unknown,
+}
+
+/// What the running kernel and root environment support, probed once per
+/// process. See [`kernel_features`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KernelFeatures {
+    /// tmpfs accepts `trusted.overlay.*` xattrs (CONFIG_TMPFS_XATTR).
+    pub tmpfs_xattr: bool,
+    pub erofs: bool,
+    pub overlayfs: bool,
+    /// Overlays can be created through the new mount API (fsopen/fsmount).
+    pub overlay_fsopen: bool,
+    /// Lower layers we stack in one overlay mount.
+    pub max_lowerdir_count: usize,
+    /// Longest `lowerdir=` option the kernel accepts from us.
+    pub max_lowerdir_len: usize,
+    pub root_impl: RootImpl,
+}
+
+fn proc_filesystems() -> Vec<String> {
+    fs::read_to_string("/proc/filesystems")
+        .map(|content| {
+            content
+                .lines()
+                .filter_map(|line| line.split_whitespace().last())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Sets an overlay xattr on a file in a scratch tmpfs. This answers the
+/// question directly, where `/proc/config.gz` is often missing on OEM kernels.
+fn probe_tmpfs_xattr() -> bool {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let dir = Path::new(defs::XATTR_CHECK_DIR);
+        if crate::sys::mount::mount_tmpfs(dir, "mh_check").is_err() {
+            let _ = fs::remove_dir(dir);
+            return false;
+        }
+
+        let probe = dir.join("probe");
+        let supported = fs::write(&probe, b"").is_ok()
+            && extattr::lsetxattr(
+                &probe,
+                defs::REPLACE_DIR_XATTR,
+                b"y",
+                extattr::Flags::empty(),
+            )
+            .is_ok();
+
+        let _ = unmount(dir, UnmountFlags::DETACH);
+        let _ = fs::remove_dir(dir);
+        supported
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    true
+}
+
+fn probe_overlay_fsopen() -> bool {
+    fsopen("overlay", FsOpenFlags::FSOPEN_CLOEXEC).is_ok()
+}
+
+/// `ksu::version` performs the KernelSU reboot-syscall handshake; APatch and
+/// Magisk are recognised by their data directories.
+pub fn detect_root_impl() -> RootImpl {
+    if ksu::version().is_some() {
+        RootImpl::KernelSU
+    } else if Path::new("/data/adb/ap").is_dir() {
+        RootImpl::APatch
+    } else if Path::new("/data/adb/magisk").is_dir() {
+        RootImpl::Magisk
+    } else {
+        RootImpl::Unknown
+    }
+}
+
+impl KernelFeatures {
+    pub fn detect() -> Self {
+        let filesystems = proc_filesystems();
+        let overlayfs = filesystems.iter().any(|f| f == "overlay");
+
+        Self {
+            tmpfs_xattr: probe_tmpfs_xattr(),
+            erofs: filesystems.iter().any(|f| f == "erofs"),
+            overlayfs,
+            overlay_fsopen: overlayfs && probe_overlay_fsopen(),
+            max_lowerdir_count: MAX_LOWERDIR_COUNT,
+            max_lowerdir_len: max_lowerdir_len(),
+            root_impl: detect_root_impl(),
+        }
+    }
+}
+
+/// Features of the running kernel, detected on first use.
+pub fn kernel_features() -> &'static KernelFeatures {
+    static FEATURES: OnceLock<KernelFeatures> = OnceLock::new();
+    FEATURES.get_or_init(|| {
+        let features = KernelFeatures::detect();
+        log::debug!("Kernel features: {:?}", features);
+        features
+    })
+}
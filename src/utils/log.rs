@@ -1,27 +1,276 @@
 // Copyright 2026 Hybrid Mount Developers
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use anyhow::Result;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Write},
+    os::{fd::AsFd, unix::fs::MetadataExt},
+    path::{Path, PathBuf},
+    sync::{
+        Mutex, RwLock,
+        mpsc::{self, Receiver, SyncSender},
+    },
+    thread::JoinHandle,
+};
 
-pub fn init_logging(verbose: bool) -> Result<()> {
-    let level = if verbose {
-        log::LevelFilter::Debug
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+static CURRENT_PHASE: Mutex<Option<&'static str>> = Mutex::new(None);
+
+/// Target of the per-file lines logged through [`module_debug!`].
+pub const MODULE_TARGET: &str = "module";
+
+/// `debug_modules`: when not empty, only these modules get per-file lines.
+static DEBUG_MODULES: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Whether per-file lines about module `id` are logged: for every module
+/// at debug level and up, or for the `debug_modules` alone when set. Lines
+/// about no module in particular only go with the former.
+pub fn module_debug_enabled(id: Option<&str>) -> bool {
+    match DEBUG_MODULES.read() {
+        Ok(listed) if !listed.is_empty() => id.is_some_and(|id| listed.iter().any(|m| m == id)),
+        _ => log::log_enabled!(log::Level::Debug),
+    }
+}
+
+/// `log::debug!` for a per-file line about the module `$id`
+/// (`Option<&str>`), dropped unless [`module_debug_enabled`] lets it through.
+#[macro_export]
+macro_rules! module_debug {
+    ($id:expr, $($arg:tt)+) => {{
+        let id: Option<&str> = $id;
+        if $crate::utils::log::module_debug_enabled(id) {
+            ::log::debug!(
+                target: $crate::utils::log::MODULE_TARGET,
+                "[{}] {}",
+                id.unwrap_or("-"),
+                format_args!($($arg)+)
+            );
+        }
+    }};
+}
+
+/// Records queued for the writer thread before loggers start to block.
+const QUEUE_DEPTH: usize = 1024;
+
+static SINK: Mutex<Option<SyncSender<Vec<u8>>>> = Mutex::new(None);
+
+/// Hands `bytes` to the writer thread, or writes them to stderr directly
+/// when there is none (unbuffered, or after the guard was dropped).
+fn emit(bytes: &[u8]) {
+    if let Ok(sink) = SINK.lock()
+        && let Some(sender) = sink.as_ref()
+        && sender.send(bytes.to_vec()).is_ok()
+    {
+        return;
+    }
+    let _ = std::io::stderr().lock().write_all(bytes);
+}
+
+/// `Write` adapter that feeds [`emit`].
+#[cfg(not(target_os = "android"))]
+struct SinkWriter;
+
+#[cfg(not(target_os = "android"))]
+impl Write for SinkWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        emit(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes queued records through a buffer of `capacity` bytes. Records that
+/// arrive in a burst are written together; the buffer is flushed whenever
+/// the queue runs dry, so only the records of the last instant are at risk
+/// if the process is killed.
+fn run_writer(out: File, capacity: usize, queue: Receiver<Vec<u8>>) {
+    let mut out = BufWriter::with_capacity(capacity, out);
+    while let Ok(first) = queue.recv() {
+        let _ = out.write_all(&first);
+        while let Ok(next) = queue.try_recv() {
+            let _ = out.write_all(&next);
+        }
+        let _ = out.flush();
+    }
+    let _ = out.flush();
+    let _ = out.get_ref().sync_all();
+}
+
+/// Keeps the background log writer alive. Dropping it stops the writer
+/// after everything queued so far is written, flushed and fsynced, so keep
+/// it until the process is about to exit, including on error paths.
+#[must_use = "dropping the guard stops buffered logging"]
+pub struct LogGuard {
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Drop for LogGuard {
+    fn drop(&mut self) {
+        let Some(writer) = self.writer.take() else {
+            let _ = std::io::stderr().flush();
+            return;
+        };
+        if let Ok(mut sink) = SINK.lock() {
+            sink.take();
+        }
+        let _ = writer.join();
+    }
+}
+
+/// Starts the writer thread on a duplicate of stderr, which the boot
+/// scripts redirect into `daemon.log`.
+fn start_writer(buffer_kb: usize) -> Option<JoinHandle<()>> {
+    if buffer_kb == 0 {
+        return None;
+    }
+    let out = File::from(std::io::stderr().as_fd().try_clone_to_owned().ok()?);
+    let (sender, queue) = mpsc::sync_channel(QUEUE_DEPTH);
+    let writer = std::thread::Builder::new()
+        .name("log-writer".into())
+        .spawn(move || run_writer(out, buffer_kb * 1024, queue))
+        .ok()?;
+    *SINK.lock().ok()? = Some(sender);
+    Some(writer)
+}
+
+/// Restores the previous phase when dropped.
+pub struct PhaseGuard {
+    previous: Option<&'static str>,
+}
+
+impl Drop for PhaseGuard {
+    fn drop(&mut self) {
+        if let Ok(mut phase) = CURRENT_PHASE.lock() {
+            *phase = self.previous;
+        }
+    }
+}
+
+/// Tags every log record emitted until the guard is dropped with `phase`.
+pub fn enter_phase(phase: &'static str) -> PhaseGuard {
+    let previous = CURRENT_PHASE
+        .lock()
+        .map(|mut current| current.replace(phase))
+        .unwrap_or(None);
+
+    PhaseGuard { previous }
+}
+
+fn current_phase() -> Option<&'static str> {
+    CURRENT_PHASE.lock().ok().and_then(|phase| *phase)
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    timestamp: String,
+    level: &'a str,
+    target: &'a str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    phase: Option<&'static str>,
+}
+
+struct JsonLogger;
+
+impl log::Log for JsonLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        let entry = JsonRecord {
+            timestamp: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+            level: record.level().as_str(),
+            target: record.target(),
+            message: record.args().to_string(),
+            phase: current_phase(),
+        };
+
+        if let Ok(mut line) = serde_json::to_vec(&entry) {
+            line.push(b'\n');
+            emit(&line);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Passes records at `level` and up to `inner`, and with `debug_modules`
+/// set the debug records of [`MODULE_TARGET`] as well.
+struct Filtered<L> {
+    inner: L,
+    level: log::LevelFilter,
+    module_lines: bool,
+}
+
+impl<L: log::Log> log::Log for Filtered<L> {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level
+            || (self.module_lines
+                && metadata.target() == MODULE_TARGET
+                && metadata.level() <= log::Level::Debug)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Installs the logger at `level`; with `debug_modules` the per-file lines
+/// of those modules are logged at debug level whatever `level` is. With
+/// `buffer_kb > 0`, records are written by a background thread through a
+/// buffer of that size instead of by the caller; the returned guard must
+/// outlive all logging.
+pub fn init_logging(
+    level: log::LevelFilter,
+    debug_modules: &[String],
+    json: bool,
+    buffer_kb: usize,
+) -> Result<LogGuard> {
+    if let Ok(mut listed) = DEBUG_MODULES.write() {
+        *listed = debug_modules.to_vec();
+    }
+    let module_lines = !debug_modules.is_empty();
+    let max_level = if module_lines {
+        level.max(log::LevelFilter::Debug)
     } else {
-        log::LevelFilter::Info
+        level
     };
-    #[cfg(target_os = "android")]
-    {
-        android_logger::init_once(
-            android_logger::Config::default()
-                .with_max_level(level)
-                .with_tag("mhm"),
-        );
+
+    if json {
+        let logger = Filtered {
+            inner: JsonLogger,
+            level,
+            module_lines,
+        };
+        log::set_logger(Box::leak(Box::new(logger)))
+            .map_err(|e| anyhow::anyhow!("Failed to install JSON logger: {}", e))?;
+        log::set_max_level(max_level);
+        return Ok(LogGuard {
+            writer: start_writer(buffer_kb),
+        });
     }
 
-    #[cfg(not(target_os = "android"))]
-    {
-        use std::io::Write;
+    #[cfg(target_os = "android")]
+    let inner = android_logger::AndroidLogger::new(
+        android_logger::Config::default()
+            .with_max_level(max_level)
+            .with_tag("mhm"),
+    );
 
+    #[cfg(not(target_os = "android"))]
+    let inner = {
         let mut builder = env_logger::Builder::new();
 
         builder.format(|buf, record| {
@@ -33,7 +282,73 @@ pub fn init_logging(verbose: bool) -> Result<()> {
                 record.args()
             )
         });
-        builder.filter_level(level).init();
+        builder
+            .filter_level(max_level)
+            .target(env_logger::Target::Pipe(Box::new(SinkWriter)))
+            .build()
+    };
+
+    let logger = Filtered {
+        inner,
+        level,
+        module_lines,
+    };
+    log::set_logger(Box::leak(Box::new(logger)))
+        .map_err(|e| anyhow::anyhow!("Failed to install logger: {}", e))?;
+    log::set_max_level(max_level);
+
+    // Plain records go to logcat on Android, so there is nothing to buffer.
+    #[cfg(target_os = "android")]
+    let writer = None;
+    #[cfg(not(target_os = "android"))]
+    let writer = start_writer(buffer_kb);
+
+    Ok(LogGuard { writer })
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+/// Shifts `path` to `path.1` (and older files up by one) once it grows past
+/// `max_size` bytes, keeping at most `keep` rotated files. If stdout/stderr
+/// were redirected into the rotated file they are reopened on a fresh one.
+pub fn rotate_log<P: AsRef<Path>>(path: P, max_size: u64, keep: usize) -> Result<bool> {
+    let path = path.as_ref();
+
+    let Ok(meta) = fs::metadata(path) else {
+        return Ok(false);
+    };
+
+    if max_size == 0 || meta.len() <= max_size {
+        return Ok(false);
     }
-    Ok(())
+
+    let redirected = fs::metadata("/proc/self/fd/2")
+        .is_ok_and(|fd| fd.dev() == meta.dev() && fd.ino() == meta.ino());
+
+    if keep == 0 {
+        fs::remove_file(path)?;
+    } else {
+        let _ = fs::remove_file(rotated_path(path, keep));
+        for index in (1..keep).rev() {
+            let from = rotated_path(path, index);
+            if from.exists() {
+                fs::rename(&from, rotated_path(path, index + 1))?;
+            }
+        }
+        fs::rename(path, rotated_path(path, 1))?;
+    }
+
+    if redirected {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        rustix::stdio::dup2_stdout(&file)
+            .with_context(|| format!("Failed to redirect stdout to {}", path.display()))?;
+        rustix::stdio::dup2_stderr(&file)
+            .with_context(|| format!("Failed to redirect stderr to {}", path.display()))?;
+    }
+
+    Ok(true)
 }
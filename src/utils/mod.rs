@@ -1,9 +1,16 @@
 // Copyright 2026 Hybrid Mount Developers
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+pub mod cancel;
 pub mod fs;
+pub mod glob;
+pub mod kernel_features;
 pub mod log;
+pub mod pool;
 pub mod process;
+pub mod progress;
+pub mod timing;
+pub mod trace;
 pub mod validation;
 
-pub use self::{fs::*, log::*, process::*, validation::*};
+pub use self::{fs::*, kernel_features::*, log::*, process::*, validation::*};
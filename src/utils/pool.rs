@@ -0,0 +1,79 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! The worker pool parallel scanning, planning, sync and mirroring run on.
+//!
+//! Every parallel iterator is started through [`install`] so it runs here
+//! instead of rayon's global pool, which would size itself to every core
+//! and name its threads `rayon-worker-N` in `/proc/<pid>/task/*/comm`.
+//! Ours are named like the kernel workers the process poses as.
+
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+use rayon::{BroadcastContext, ThreadPool, ThreadPoolBuilder};
+
+use super::process::random_u32;
+
+/// Upper bound of the default size; more threads only contend for /data.
+pub const MAX_DEFAULT_THREADS: usize = 4;
+
+static POOL: OnceLock<ThreadPool> = OnceLock::new();
+
+/// The pool size for `threads` from the config on a device with `cores`
+/// cores. Unset or `0` picks the core count, capped at
+/// [`MAX_DEFAULT_THREADS`].
+pub fn resolve_threads(threads: Option<usize>, cores: usize) -> usize {
+    match threads {
+        Some(n) if n > 0 => n,
+        _ => cores.clamp(1, MAX_DEFAULT_THREADS),
+    }
+}
+
+/// Builds the pool with the size [`resolve_threads`] gives for this
+/// device and returns it. Only the first call builds anything.
+pub fn init(threads: Option<usize>) -> Result<usize> {
+    if let Some(pool) = POOL.get() {
+        return Ok(pool.current_num_threads());
+    }
+    let cores = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(MAX_DEFAULT_THREADS);
+    let threads = resolve_threads(threads, cores);
+
+    let unbound = random_u32() % 16;
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(move |i| format!("kworker/u{}:{}", unbound, i))
+        .build()
+        .context("Failed to build the worker pool")?;
+    Ok(POOL.get_or_init(|| pool).current_num_threads())
+}
+
+/// Number of worker threads, `0` before [`init`].
+pub fn size() -> usize {
+    POOL.get().map_or(0, ThreadPool::current_num_threads)
+}
+
+/// Runs `op` in the pool, so the parallel iterators inside it use our
+/// workers. Before [`init`] it runs on the calling thread.
+pub fn install<R, F>(op: F) -> R
+where
+    R: Send,
+    F: FnOnce() -> R + Send,
+{
+    match POOL.get() {
+        Some(pool) => pool.install(op),
+        None => op(),
+    }
+}
+
+/// Runs `op` once on every worker, in index order. Before [`init`] there
+/// are no workers and nothing runs.
+pub fn broadcast<R, F>(op: F) -> Vec<R>
+where
+    R: Send,
+    F: Fn(BroadcastContext<'_>) -> R + Sync,
+{
+    POOL.get().map_or_else(Vec::new, |pool| pool.broadcast(op))
+}
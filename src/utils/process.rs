@@ -3,7 +3,14 @@
 
 use std::{
     ffi::CString,
-    time::{SystemTime, UNIX_EPOCH},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::Result;
@@ -16,12 +23,86 @@ pub fn camouflage_process(name: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn random_kworker_name() -> String {
+/// Runs `work` on a worker thread named `name` and waits at most `timeout`
+/// for its result. Returns `None` when the work did not finish in time or
+/// the worker could not be started.
+///
+/// A timed-out worker is detached, not killed: Rust cannot cancel a thread,
+/// so it keeps running (often stuck in a kernel call) and its result is
+/// dropped when it eventually finishes. Detached threads do not keep the
+/// process alive, because returning from `main` exits every thread, but a
+/// thread in uninterruptible sleep can delay the kernel's final teardown of
+/// the process until that call returns.
+pub fn run_with_timeout<T, F>(name: &str, timeout: Duration, work: F) -> Option<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+
+    let spawned = thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move || {
+            let _ = tx.send(work());
+        });
+
+    if let Err(e) = spawned {
+        log::warn!("Failed to spawn watchdog worker {}: {}", name, e);
+        return None;
+    }
+
+    rx.recv_timeout(timeout).ok()
+}
+
+/// Reads a system property with `getprop`. `None` when it is unset or
+/// `getprop` is unavailable.
+pub fn getprop(name: &str) -> Option<String> {
+    let output = Command::new("getprop").arg(name).output().ok()?;
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !value.is_empty()).then_some(value)
+}
+
+/// Cheap non-cryptographic randomness seeded from the clock and pid. Good
+/// enough for names that only need to differ between boots.
+pub fn random_u32() -> u32 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
     let nanos = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap_or_default()
-        .subsec_nanos();
-    let x = nanos % 16;
-    let y = (nanos >> 4) % 10;
+        .as_nanos() as u64;
+
+    // splitmix64 finalizer, so consecutive calls do not look alike.
+    let mut z = nanos
+        ^ ((std::process::id() as u64) << 32)
+        ^ COUNTER
+            .fetch_add(1, Ordering::Relaxed)
+            .wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (z ^ (z >> 31)) as u32
+}
+
+pub fn random_kworker_name() -> String {
+    let n = random_u32();
+    let x = n % 16;
+    let y = (n >> 4) % 10;
     format!("kworker/u{}:{}", x, y)
 }
+
+/// A mount source shaped like a device-mapper block device.
+pub fn random_mount_source() -> String {
+    format!("/dev/block/dm-{}", random_u32() % 48)
+}
+
+/// A fresh directory path below the first existing parent that normally
+/// holds runtime mounts.
+pub fn random_mount_dir() -> PathBuf {
+    let parent = ["/mnt/vendor", "/mnt"]
+        .into_iter()
+        .map(Path::new)
+        .find(|p| p.is_dir())
+        .unwrap_or(Path::new("/dev"));
+
+    parent.join(format!("{:08x}", random_u32()))
+}
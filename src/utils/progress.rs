@@ -0,0 +1,168 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs,
+    io::{ErrorKind, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+static CHANNEL: Mutex<Option<Channel>> = Mutex::new(None);
+
+struct Client {
+    stream: UnixStream,
+    /// Tail of an event the socket buffer could not take in one write.
+    pending: Vec<u8>,
+}
+
+impl Client {
+    /// Returns false once the peer is gone.
+    fn send(&mut self, line: &[u8]) -> bool {
+        if !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(n) => {
+                    self.pending.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return true,
+                Err(_) => return false,
+            }
+            if !self.pending.is_empty() {
+                return true;
+            }
+        }
+
+        match self.stream.write(line) {
+            Ok(n) => {
+                self.pending.extend_from_slice(&line[n..]);
+                true
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => true,
+            Err(_) => false,
+        }
+    }
+}
+
+struct Channel {
+    listener: UnixListener,
+    clients: Vec<Client>,
+    path: PathBuf,
+}
+
+#[derive(Serialize)]
+struct ProgressEvent<'a> {
+    phase: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    module: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<&'a str>,
+    done: usize,
+    total: usize,
+}
+
+/// Closes the channel and unlinks the socket when dropped, so every exit
+/// path of the mount sequence cleans up after itself.
+pub struct ProgressGuard;
+
+impl Drop for ProgressGuard {
+    fn drop(&mut self) {
+        close();
+    }
+}
+
+/// Binds a nonblocking unix socket at `path`; connected clients receive
+/// newline-delimited JSON progress events.
+pub fn open<P: AsRef<Path>>(path: P) -> Result<ProgressGuard> {
+    let path = path.as_ref();
+
+    if fs::symlink_metadata(path).is_ok() {
+        fs::remove_file(path)
+            .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+    }
+
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind progress socket {}", path.display()))?;
+    listener.set_nonblocking(true)?;
+
+    if let Ok(mut channel) = CHANNEL.lock() {
+        *channel = Some(Channel {
+            listener,
+            clients: Vec::new(),
+            path: path.to_path_buf(),
+        });
+    }
+
+    Ok(ProgressGuard)
+}
+
+fn close() {
+    let channel = CHANNEL.lock().ok().and_then(|mut c| c.take());
+
+    if let Some(channel) = channel {
+        drop(channel.clients);
+        drop(channel.listener);
+        if let Err(e) = fs::remove_file(&channel.path) {
+            log::debug!(
+                "Failed to remove progress socket {}: {}",
+                channel.path.display(),
+                e
+            );
+        }
+    }
+}
+
+fn send(event: &ProgressEvent) {
+    let Ok(mut guard) = CHANNEL.lock() else {
+        return;
+    };
+    let Some(channel) = guard.as_mut() else {
+        return;
+    };
+
+    while let Ok((stream, _)) = channel.listener.accept() {
+        if stream.set_nonblocking(true).is_ok() {
+            channel.clients.push(Client {
+                stream,
+                pending: Vec::new(),
+            });
+        }
+    }
+
+    if channel.clients.is_empty() {
+        return;
+    }
+
+    let Ok(mut line) = serde_json::to_vec(event) else {
+        return;
+    };
+    line.push(b'\n');
+
+    channel.clients.retain_mut(|client| client.send(&line));
+}
+
+/// Reports `done` of `total` steps for `phase`. Never blocks; events are
+/// dropped for clients that are not keeping up.
+pub fn emit(phase: &str, module: Option<&str>, done: usize, total: usize) {
+    send(&ProgressEvent {
+        phase,
+        module,
+        target: None,
+        done,
+        total,
+    });
+}
+
+/// Same as [`emit`] for steps that act on a mount target.
+pub fn emit_target(phase: &str, target: &str, done: usize, total: usize) {
+    send(&ProgressEvent {
+        phase,
+        module: None,
+        target: Some(target),
+        done,
+        total,
+    });
+}
@@ -0,0 +1,151 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Wall-clock spans around the engine stages.
+//!
+//! Every finished [`Span`] is logged at debug level, so a boot log shows
+//! what each stage cost. While [`start_recording`] is in effect the spans
+//! are also kept, which is how `bench` collects its numbers and a boot
+//! summary its phase times.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+static SAMPLES: Mutex<Option<Vec<Sample>>> = Mutex::new(None);
+
+/// One finished span.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub stage: &'static str,
+    pub elapsed: Duration,
+    /// Units of work the stage handled, e.g. modules scanned.
+    pub items: usize,
+}
+
+/// Times `stage` until dropped.
+pub struct Span {
+    stage: &'static str,
+    started: Instant,
+    items: usize,
+}
+
+pub fn span(stage: &'static str) -> Span {
+    Span {
+        stage,
+        started: Instant::now(),
+        items: 0,
+    }
+}
+
+impl Span {
+    /// Sets how much work the stage handled.
+    pub fn items(&mut self, items: usize) {
+        self.items = items;
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed();
+        log::debug!(
+            "timing: {} took {:.2} ms ({} items)",
+            self.stage,
+            elapsed.as_secs_f64() * 1000.0,
+            self.items
+        );
+        if let Ok(mut samples) = SAMPLES.lock()
+            && let Some(samples) = samples.as_mut()
+        {
+            samples.push(Sample {
+                stage: self.stage,
+                elapsed,
+                items: self.items,
+            });
+        }
+    }
+}
+
+/// Keeps every span finished from now on, dropping any kept before.
+pub fn start_recording() {
+    if let Ok(mut samples) = SAMPLES.lock() {
+        *samples = Some(Vec::new());
+    }
+}
+
+/// The spans kept since [`start_recording`], which go on being kept.
+pub fn recorded() -> Vec<Sample> {
+    SAMPLES
+        .lock()
+        .ok()
+        .and_then(|samples| samples.clone())
+        .unwrap_or_default()
+}
+
+/// The spans kept since [`start_recording`]; stops keeping them.
+pub fn take_samples() -> Vec<Sample> {
+    SAMPLES
+        .lock()
+        .ok()
+        .and_then(|mut samples| samples.take())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageStats {
+    pub stage: &'static str,
+    pub runs: usize,
+    pub min_ms: f64,
+    pub median_ms: f64,
+    pub max_ms: f64,
+    /// Items per run, averaged.
+    pub items: usize,
+    /// Items over the total time of all runs.
+    pub items_per_sec: f64,
+}
+
+/// Statistics per stage, in the order the stages first finished.
+pub fn summarize(samples: &[Sample]) -> Vec<StageStats> {
+    let mut stages: Vec<&'static str> = Vec::new();
+    for sample in samples {
+        if !stages.contains(&sample.stage) {
+            stages.push(sample.stage);
+        }
+    }
+
+    stages
+        .into_iter()
+        .map(|stage| {
+            let runs: Vec<&Sample> = samples.iter().filter(|s| s.stage == stage).collect();
+            let mut ms: Vec<f64> = runs
+                .iter()
+                .map(|s| s.elapsed.as_secs_f64() * 1000.0)
+                .collect();
+            ms.sort_by(f64::total_cmp);
+            let median = if ms.len() % 2 == 1 {
+                ms[ms.len() / 2]
+            } else {
+                (ms[ms.len() / 2 - 1] + ms[ms.len() / 2]) / 2.0
+            };
+            let items: usize = runs.iter().map(|s| s.items).sum();
+            let total_secs: f64 = ms.iter().sum::<f64>() / 1000.0;
+
+            StageStats {
+                stage,
+                runs: runs.len(),
+                min_ms: ms[0],
+                median_ms: median,
+                max_ms: ms[ms.len() - 1],
+                items: items / runs.len(),
+                items_per_sec: if total_secs > 0.0 {
+                    items as f64 / total_secs
+                } else {
+                    0.0
+                },
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,449 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Mount event trace that survives the device dying mid-run.
+//!
+//! With `trace_mounts` set, every mount syscall of the executor and magic
+//! mount is recorded in `run/trace.bin` before it is attempted, and its
+//! result is filled in afterwards. Each record is synced to disk on its
+//! own, so after a bootloop the newest pending record names the mount that
+//! took the device down, even when the text log lost its tail.
+//!
+//! The file starts with a [`RING_LEN`] byte ring: a 64 byte header followed
+//! by 32 byte records. Records carry a hash of the path rather than the
+//! path itself; each distinct path is appended once after the ring as
+//! `[hash u64][len u16][bytes]`. Recording neither allocates nor formats.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io,
+    os::unix::{ffi::OsStrExt, fs::FileExt},
+    path::{Path, PathBuf},
+    ptr::NonNull,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+
+const MAGIC: &[u8; 8] = b"MHTRACE\0";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 64;
+const RECORD_LEN: usize = 32;
+/// Size of the header and record ring; the string table follows it.
+pub const RING_LEN: usize = 256 * 1024;
+/// Number of records the ring holds before the oldest are overwritten.
+pub const CAPACITY: usize = (RING_LEN - HEADER_LEN) / RECORD_LEN;
+/// Result of a record whose syscall never returned.
+const PENDING: i32 = i32::MIN;
+/// Result of a failed syscall whose errno is unknown.
+const UNKNOWN_ERRNO: i32 = -1;
+/// Slots of the set of path hashes already in the string table.
+const SEEN_SLOTS: usize = 4096;
+
+/// The kind of mount operation a record stands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(u16)]
+pub enum TraceOp {
+    Overlay = 1,
+    Bind = 2,
+    Remount = 3,
+    Tmpfs = 4,
+    Move = 5,
+    Umount = 6,
+    Mirror = 7,
+}
+
+impl TraceOp {
+    fn from_raw(raw: u16) -> Option<Self> {
+        Some(match raw {
+            1 => Self::Overlay,
+            2 => Self::Bind,
+            3 => Self::Remount,
+            4 => Self::Tmpfs,
+            5 => Self::Move,
+            6 => Self::Umount,
+            7 => Self::Mirror,
+            _ => return None,
+        })
+    }
+}
+
+struct Tracer {
+    file: File,
+    map: NonNull<u8>,
+    page: usize,
+    next_seq: u64,
+    strings_end: u64,
+    seen: Box<[u64; SEEN_SLOTS]>,
+    seen_len: usize,
+}
+
+// SAFETY: the mapping is only touched through the mutex around the tracer.
+unsafe impl Send for Tracer {}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        // SAFETY: `map` came from mmap with exactly RING_LEN bytes.
+        unsafe { libc::munmap(self.map.as_ptr().cast(), RING_LEN) };
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TRACER: Mutex<Option<Tracer>> = Mutex::new(None);
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in bytes {
+        hash ^= u64::from(*b);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    // Zero marks an empty slot in the seen set.
+    hash.max(1)
+}
+
+fn now_ns() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: clock_gettime only writes the timespec it is given.
+    unsafe { libc::clock_gettime(libc::CLOCK_REALTIME, &mut ts) };
+    (ts.tv_sec as u64)
+        .wrapping_mul(1_000_000_000)
+        .wrapping_add(ts.tv_nsec as u64)
+}
+
+/// Where the trace of the run before the current one is kept.
+pub fn previous_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".1");
+    PathBuf::from(name)
+}
+
+impl Tracer {
+    fn create(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        file.set_len(RING_LEN as u64)
+            .with_context(|| format!("Failed to size {}", path.display()))?;
+
+        let mut header = [0u8; HEADER_LEN];
+        header[0..8].copy_from_slice(MAGIC);
+        header[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        header[12..16].copy_from_slice(&(RECORD_LEN as u32).to_le_bytes());
+        header[16..20].copy_from_slice(&(CAPACITY as u32).to_le_bytes());
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        header[24..32].copy_from_slice(&started.to_le_bytes());
+        file.write_all_at(&header, 0)?;
+        file.sync_all()?;
+
+        // SAFETY: a fresh shared mapping of a file that is RING_LEN bytes long.
+        let map = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                RING_LEN,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                std::os::fd::AsRawFd::as_raw_fd(&file),
+                0,
+            )
+        };
+        if map == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error())
+                .with_context(|| format!("Failed to map {}", path.display()));
+        }
+        // SAFETY: sysconf has no preconditions.
+        let page = match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+            n if n > 0 => n as usize,
+            _ => 4096,
+        };
+
+        Ok(Self {
+            file,
+            map: NonNull::new(map.cast()).context("mmap returned null")?,
+            page,
+            next_seq: 1,
+            strings_end: RING_LEN as u64,
+            seen: Box::new([0; SEEN_SLOTS]),
+            seen_len: 0,
+        })
+    }
+
+    /// Appends `bytes` to the string table unless it is already there.
+    /// Returns whether the file grew.
+    fn intern(&mut self, hash: u64, bytes: &[u8]) -> bool {
+        let mut slot = hash as usize % SEEN_SLOTS;
+        loop {
+            match self.seen[slot] {
+                0 => break,
+                h if h == hash => return false,
+                _ => slot = (slot + 1) % SEEN_SLOTS,
+            }
+        }
+        // The set is kept at most half full so probing stays short; paths
+        // beyond that are written each time they come up.
+        if self.seen_len < SEEN_SLOTS / 2 {
+            self.seen[slot] = hash;
+            self.seen_len += 1;
+        }
+
+        let bytes = &bytes[..bytes.len().min(u16::MAX as usize)];
+        let mut entry = [0u8; 10];
+        entry[0..8].copy_from_slice(&hash.to_le_bytes());
+        entry[8..10].copy_from_slice(&(bytes.len() as u16).to_le_bytes());
+        if self.file.write_all_at(&entry, self.strings_end).is_err()
+            || self
+                .file
+                .write_all_at(bytes, self.strings_end + entry.len() as u64)
+                .is_err()
+        {
+            return false;
+        }
+        self.strings_end += (entry.len() + bytes.len()) as u64;
+        true
+    }
+
+    fn offset(seq: u64) -> usize {
+        HEADER_LEN + ((seq - 1) as usize % CAPACITY) * RECORD_LEN
+    }
+
+    fn sync(&self, offset: usize) {
+        let start = offset & !(self.page - 1);
+        // SAFETY: the page lies inside the mapping; records never straddle
+        // a page because pages are a multiple of the record size.
+        unsafe {
+            libc::msync(
+                self.map.as_ptr().add(start).cast(),
+                self.page.min(RING_LEN - start),
+                libc::MS_SYNC,
+            )
+        };
+    }
+
+    fn record(&mut self, op: TraceOp, path: &Path) -> u64 {
+        let bytes = path.as_os_str().as_bytes();
+        let hash = fnv1a(bytes);
+        let grew = self.intern(hash, bytes);
+
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let mut record = [0u8; RECORD_LEN];
+        record[0..8].copy_from_slice(&seq.to_le_bytes());
+        record[8..16].copy_from_slice(&now_ns().to_le_bytes());
+        record[16..24].copy_from_slice(&hash.to_le_bytes());
+        record[24..26].copy_from_slice(&(op as u16).to_le_bytes());
+        record[28..32].copy_from_slice(&PENDING.to_le_bytes());
+
+        let offset = Self::offset(seq);
+        // SAFETY: offset + RECORD_LEN <= RING_LEN by construction.
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                record.as_ptr(),
+                self.map.as_ptr().add(offset),
+                RECORD_LEN,
+            )
+        };
+        if grew {
+            // Flushes the new string together with the mapped record.
+            let _ = self.file.sync_data();
+        } else {
+            self.sync(offset);
+        }
+        seq
+    }
+
+    fn finish(&mut self, seq: u64, errno: i32) {
+        if self.next_seq - seq > CAPACITY as u64 {
+            return;
+        }
+        let offset = Self::offset(seq);
+        // SAFETY: the errno field lies inside the record at `offset`.
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                errno.to_le_bytes().as_ptr(),
+                self.map.as_ptr().add(offset + 28),
+                4,
+            )
+        };
+        self.sync(offset);
+    }
+}
+
+/// Starts a fresh trace at `path`, keeping the previous one as `path.1`.
+pub fn start(path: &Path) -> Result<()> {
+    let mut tracer = TRACER.lock().unwrap_or_else(|e| e.into_inner());
+    *tracer = None;
+    if path.exists() {
+        fs::rename(path, previous_path(path))
+            .with_context(|| format!("Failed to rotate {}", path.display()))?;
+    }
+    *tracer = Some(Tracer::create(path)?);
+    ENABLED.store(true, Ordering::Release);
+    Ok(())
+}
+
+/// Stops tracing and unmaps the trace file.
+pub fn stop() {
+    ENABLED.store(false, Ordering::Release);
+    *TRACER.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// A recorded operation waiting for its result.
+#[must_use]
+pub struct Event(Option<u64>);
+
+/// Records that `op` on `path` is about to run. The record is on disk when
+/// this returns. Does nothing while tracing is off.
+pub fn begin(op: TraceOp, path: &Path) -> Event {
+    if !ENABLED.load(Ordering::Acquire) {
+        return Event(None);
+    }
+    let mut tracer = TRACER.lock().unwrap_or_else(|e| e.into_inner());
+    Event(tracer.as_mut().map(|t| t.record(op, path)))
+}
+
+impl Event {
+    /// Fills in the result of the operation.
+    pub fn finish<T, E: TraceErrno>(self, result: &Result<T, E>) {
+        let Some(seq) = self.0 else {
+            return;
+        };
+        let errno = match result {
+            Ok(_) => 0,
+            Err(e) => e.errno().unwrap_or(UNKNOWN_ERRNO),
+        };
+        if let Some(t) = TRACER.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            t.finish(seq, errno);
+        }
+    }
+}
+
+/// Errors a trace record can take an errno from.
+pub trait TraceErrno {
+    fn errno(&self) -> Option<i32>;
+}
+
+impl TraceErrno for rustix::io::Errno {
+    fn errno(&self) -> Option<i32> {
+        Some(self.raw_os_error())
+    }
+}
+
+impl TraceErrno for io::Error {
+    fn errno(&self) -> Option<i32> {
+        self.raw_os_error()
+    }
+}
+
+impl TraceErrno for anyhow::Error {
+    fn errno(&self) -> Option<i32> {
+        self.chain().find_map(|cause| {
+            cause
+                .downcast_ref::<rustix::io::Errno>()
+                .map(|e| e.raw_os_error())
+                .or_else(|| cause.downcast_ref::<io::Error>()?.raw_os_error())
+        })
+    }
+}
+
+/// One decoded trace record.
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceEntry {
+    pub seq: u64,
+    pub time_ns: u64,
+    /// `None` for op codes this build does not know.
+    pub op: Option<TraceOp>,
+    pub path: Option<String>,
+    pub path_hash: String,
+    /// `None` while the syscall had not returned: the likely culprit when
+    /// it is the last entry.
+    pub errno: Option<i32>,
+}
+
+/// A decoded trace file.
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceDump {
+    pub started: u64,
+    pub entries: Vec<TraceEntry>,
+}
+
+impl TraceDump {
+    /// Entries whose syscall never returned.
+    pub fn pending(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.entries.iter().filter(|e| e.errno.is_none())
+    }
+}
+
+fn u16_at(buf: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(buf[at..at + 2].try_into().unwrap())
+}
+
+fn u32_at(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(buf[at..at + 8].try_into().unwrap())
+}
+
+/// Decodes the trace file at `path`, oldest record first.
+pub fn decode(path: &Path) -> Result<TraceDump> {
+    let buf = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    if buf.len() < RING_LEN || &buf[0..8] != MAGIC {
+        bail!("{} is not a mount trace", path.display());
+    }
+    if u32_at(&buf, 8) != VERSION || u32_at(&buf, 12) as usize != RECORD_LEN {
+        bail!("{} has an unsupported trace format", path.display());
+    }
+    let capacity = (u32_at(&buf, 16) as usize).min(CAPACITY);
+
+    let mut strings = HashMap::new();
+    let mut at = RING_LEN;
+    while at + 10 <= buf.len() {
+        let hash = u64_at(&buf, at);
+        let len = u16_at(&buf, at + 8) as usize;
+        let Some(bytes) = buf.get(at + 10..at + 10 + len) else {
+            break;
+        };
+        strings.insert(hash, String::from_utf8_lossy(bytes).into_owned());
+        at += 10 + len;
+    }
+
+    let mut entries: Vec<_> = (0..capacity)
+        .map(|i| HEADER_LEN + i * RECORD_LEN)
+        .filter(|&off| u64_at(&buf, off) != 0)
+        .map(|off| {
+            let hash = u64_at(&buf, off + 16);
+            let errno = u32_at(&buf, off + 28) as i32;
+            TraceEntry {
+                seq: u64_at(&buf, off),
+                time_ns: u64_at(&buf, off + 8),
+                op: TraceOp::from_raw(u16_at(&buf, off + 24)),
+                path: strings.get(&hash).cloned(),
+                path_hash: format!("{:016x}", hash),
+                errno: (errno != PENDING).then_some(errno),
+            }
+        })
+        .collect();
+    entries.sort_by_key(|e| e.seq);
+
+    Ok(TraceDump {
+        started: u64_at(&buf, 24),
+        entries,
+    })
+}
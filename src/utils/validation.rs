@@ -1,28 +1,15 @@
 // Copyright 2026 Hybrid Mount Developers
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::{
-    path::Path,
-    sync::{
-        OnceLock,
-        atomic::{AtomicBool, Ordering},
-    },
-};
+use std::{path::Path, sync::OnceLock};
 
 use anyhow::{Result, bail};
 use regex_lite::Regex;
 
-use crate::defs;
-
-pub static KSU: AtomicBool = AtomicBool::new(false);
+use crate::{core::bundles, defs};
 
 static MODULE_ID_REGEX: OnceLock<Regex> = OnceLock::new();
 
-pub fn check_ksu() {
-    let status = ksu::version().is_some();
-    KSU.store(status, Ordering::Relaxed);
-}
-
 pub fn validate_module_id(module_id: &str) -> Result<()> {
     let re = MODULE_ID_REGEX
         .get_or_init(|| Regex::new(r"^[a-zA-Z][a-zA-Z0-9._-]+$").expect("Invalid Regex pattern"));
@@ -37,7 +24,17 @@ pub fn extract_module_id(path: &Path) -> Option<String> {
     let mut current = path;
     loop {
         if current.join("module.prop").exists() {
-            return current.file_name().map(|s| s.to_string_lossy().to_string());
+            let id = current.file_name()?.to_string_lossy();
+            // Bundle modules sit at `bundles/<bundle>/<dir>` when planned
+            // against their source.
+            if let Some(bundle) = current.parent()
+                && bundle.parent().and_then(Path::file_name)
+                    == Path::new(defs::BUNDLES_DIR).file_name()
+                && let Some(name) = bundle.file_name()
+            {
+                return Some(bundles::module_id(&name.to_string_lossy(), &id));
+            }
+            return Some(id.to_string());
         }
         match current.parent() {
             Some(p) => current = p,
@@ -50,8 +47,33 @@ pub fn extract_module_id(path: &Path) -> Option<String> {
         .map(|s| s.to_string_lossy().to_string())
 }
 
-pub fn check_zygisksu_enforce_status() -> bool {
-    std::fs::read_to_string(defs::ZYGISKSU_DENYLIST_FILE)
-        .map(|s| s.trim() != "0")
-        .unwrap_or(false)
+/// The entry of `DENIED_MOUNT_TARGETS` or `user_deny` that forbids mounting
+/// on `target`, which is denied when it is the entry or lies below it.
+/// `target` must be canonical already, so that a symlink cannot pass a
+/// denied path off under another name.
+pub fn denied_target(target: &Path, user_deny: &[String]) -> Option<String> {
+    denied_target_except(target, user_deny, &[])
+}
+
+/// [`denied_target`] without the built-in entries in `exempt`, for mounts a
+/// setting allows below one of them. `user_deny` still applies in full.
+pub fn denied_target_except(
+    target: &Path,
+    user_deny: &[String],
+    exempt: &[&str],
+) -> Option<String> {
+    crate::defs::DENIED_MOUNT_TARGETS
+        .iter()
+        .copied()
+        .filter(|denied| !exempt.contains(denied))
+        .chain(user_deny.iter().map(String::as_str))
+        .find(|denied| {
+            let denied = Path::new(denied);
+            if denied == Path::new("/") {
+                target == denied
+            } else {
+                denied.is_absolute() && target.starts_with(denied)
+            }
+        })
+        .map(str::to_string)
 }
@@ -0,0 +1,101 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::path::Path;
+
+use clap::Parser;
+use common::TestEnv;
+use meta_hybrid::conf::{api, cli::Cli};
+use serde_json::{Value, json};
+
+fn cli_for(config: &Path) -> Cli {
+    Cli::parse_from(["meta-hybrid", "-c", &config.to_string_lossy(), "api"])
+}
+
+fn call(cli: &Cli, request: Value) -> Value {
+    let response = api::handle(cli, request.to_string().as_bytes());
+    serde_json::to_value(response).expect("serialize response")
+}
+
+#[test]
+fn malformed_requests_get_structured_errors() {
+    let env = TestEnv::new();
+    let config_file = env.root.join("config.toml");
+    env.config.save_to_file(&config_file).unwrap();
+    let cli = cli_for(&config_file);
+
+    let garbage = serde_json::to_value(api::handle(&cli, b"{not json")).unwrap();
+    assert_eq!(garbage["ok"], false);
+    assert_eq!(garbage["v"], api::API_VERSION);
+    assert_eq!(garbage["error"]["code"], "invalid_request");
+
+    let future = call(&cli, json!({ "v": 2, "op": "modules.list" }));
+    assert_eq!(future["ok"], false);
+    assert_eq!(future["error"]["code"], "unsupported_version");
+
+    let unknown = call(&cli, json!({ "v": 1, "op": "modules.frobnicate" }));
+    assert_eq!(unknown["error"]["code"], "unknown_op");
+    assert!(unknown.get("data").is_none());
+
+    let bad_params = call(
+        &cli,
+        json!({ "v": 1, "op": "winnow.set", "params": { "path": "/system/bin/sh" } }),
+    );
+    assert_eq!(bad_params["error"]["code"], "invalid_params");
+}
+
+#[test]
+fn config_show_returns_the_selected_config() {
+    let env = TestEnv::new();
+    let config_file = env.root.join("config.toml");
+    env.config.save_to_file(&config_file).unwrap();
+    let cli = cli_for(&config_file);
+
+    let response = call(&cli, json!({ "v": 1, "op": "config.show" }));
+
+    assert_eq!(response["ok"], true);
+    assert!(response.get("error").is_none());
+    assert_eq!(
+        response["data"]["moduledir"],
+        env.config.moduledir.to_string_lossy().as_ref()
+    );
+}
+
+#[test]
+fn winnow_rules_round_trip_through_the_api() {
+    let env = TestEnv::new();
+    let config_file = env.root.join("config.toml");
+    env.config.save_to_file(&config_file).unwrap();
+    let cli = cli_for(&config_file);
+
+    let unknown = call(
+        &cli,
+        json!({ "v": 1, "op": "winnow.set", "params": { "path": "system/bin/sh", "module": "ghost" } }),
+    );
+    assert_eq!(unknown["error"]["code"], "failed");
+
+    let set = call(
+        &cli,
+        json!({ "v": 1, "op": "winnow.set", "params": { "path": "system/bin/sh", "module": "ghost", "force": true } }),
+    );
+    assert_eq!(set["ok"], true);
+    let path = set["data"]["path"].clone();
+    assert_eq!(set["data"]["module"], "ghost");
+
+    let listed = call(&cli, json!({ "v": 1, "op": "winnow.list" }));
+    let rules = listed["data"].as_array().expect("rule list");
+    assert_eq!(rules.len(), 1);
+    assert_eq!(rules[0]["path"], path);
+    assert_eq!(rules[0]["stale"], true);
+
+    let unset = call(
+        &cli,
+        json!({ "v": 1, "op": "winnow.unset", "params": { "path": "system/bin/sh" } }),
+    );
+    assert_eq!(unset["data"]["module"], "ghost");
+
+    let listed = call(&cli, json!({ "v": 1, "op": "winnow.list" }));
+    assert_eq!(listed["data"], json!([]));
+}
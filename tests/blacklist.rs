@@ -0,0 +1,162 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use common::TestEnv;
+use meta_hybrid::{
+    conf::config::ModuleRules,
+    core::{
+        ops::{
+            blacklist::{self, Blacklist},
+            planner::DiagnosticLevel,
+            sync,
+        },
+        storage,
+    },
+    utils::glob::Glob,
+};
+
+#[test]
+fn globs_match_whole_segments() {
+    let hosts = Glob::new("**/hosts").unwrap();
+    assert!(hosts.is_match("/system/etc/hosts"));
+    assert!(hosts.is_match("hosts"));
+    assert!(!hosts.is_match("/system/etc/hosts.bak"));
+
+    let app = Glob::new("/system/priv-app/Foo/**").unwrap();
+    assert!(app.is_match("/system/priv-app/Foo/Foo.apk"));
+    assert!(app.is_match("/system/priv-app/Foo/lib/arm64/libfoo.so"));
+    assert!(!app.is_match("/system/priv-app/FooBar/FooBar.apk"));
+
+    let star = Glob::new("/system/etc/*.conf").unwrap();
+    assert!(star.is_match("/system/etc/a.conf"));
+    assert!(!star.is_match("/system/etc/init/a.conf"));
+
+    let alt = Glob::new("/vendor/{lib,lib64}/libfoo[0-9].so").unwrap();
+    assert!(alt.is_match("/vendor/lib64/libfoo2.so"));
+    assert!(!alt.is_match("/vendor/bin/libfoo2.so"));
+
+    assert!(Glob::new("/system/**x").is_err());
+    assert!(Glob::new("/system/[etc").is_err());
+}
+
+#[test]
+fn plan_lists_filtered_files_and_the_blacklist_beats_winnowing() {
+    let mut env = TestEnv::new();
+    env.module("alpha")
+        .file("system/etc/hosts", "alpha")
+        .file("system/vendor/etc/hosts", "alpha")
+        .file("system/etc/a.conf", "a");
+    env.module("beta")
+        .file("system/etc/hosts", "beta")
+        .file("system/priv-app/Foo/Foo.apk", "apk");
+
+    env.config.mount_blacklist = vec!["/vendor/**/hosts".to_string()];
+    env.config.rules.insert(
+        "alpha".to_string(),
+        ModuleRules {
+            blacklist: vec!["/system/etc/hosts".to_string(), "[".to_string()],
+            ..Default::default()
+        },
+    );
+    env.config.winnowing.set_rule("/system/etc/hosts", "alpha");
+
+    let plan = env.plan();
+    let filtered: Vec<_> = plan
+        .blacklisted
+        .iter()
+        .map(|f| (f.module_id.as_str(), f.target.as_str()))
+        .collect();
+    assert_eq!(
+        filtered,
+        [
+            ("alpha", "/system/etc/hosts"),
+            ("alpha", "/system/vendor/etc/hosts"),
+        ]
+    );
+
+    let report = env.analyze(&plan);
+    assert!(report.diagnostics.iter().any(|d| {
+        matches!(d.level, DiagnosticLevel::Info)
+            && d.context == "alpha"
+            && d.message
+                .contains("/system/etc/hosts ('/system/etc/hosts')")
+    }));
+    assert!(
+        report
+            .diagnostics
+            .iter()
+            .any(|d| matches!(d.level, DiagnosticLevel::Warning)
+                && d.context == "alpha"
+                && d.message.contains("the blacklist wins"))
+    );
+
+    let blacklist = Blacklist::new(&env.config, &env.scan());
+    let invalid = blacklist::diagnose(&blacklist);
+    assert_eq!(invalid.len(), 1);
+    assert!(invalid[0].message.contains("'['"));
+}
+
+#[test]
+fn sync_leaves_blacklisted_files_out_of_storage() {
+    let mut env = TestEnv::new();
+    env.module("alpha")
+        .file("system/etc/hosts", "alpha")
+        .file("system/etc/a.conf", "a");
+    let storage = env.root.join("storage");
+
+    sync::perform_sync(&env.scan(), &storage, 0).expect("sync");
+    assert!(storage.join("alpha/system/etc/hosts").exists());
+
+    env.config.mount_blacklist = vec!["**/hosts".to_string()];
+    let modules = env.scan();
+    let summary = sync::perform_sync_with_stock(
+        &modules,
+        &storage,
+        0,
+        None,
+        &Blacklist::new(&env.config, &modules),
+    )
+    .expect("resync");
+
+    assert_eq!(summary.skipped_blacklisted, 1);
+    assert!(!storage.join("alpha/system/etc/hosts").exists());
+    assert!(storage.join("alpha/system/etc/a.conf").exists());
+}
+
+#[test]
+fn changing_a_blacklist_changes_the_sync_fingerprint() {
+    let mut env = TestEnv::new();
+    env.module("alpha").file("system/etc/hosts", "alpha");
+    env.module("beta").file("system/etc/b.conf", "b");
+    let base = storage::sync_fingerprint(&env.config, &env.scan(), None);
+    assert_eq!(
+        storage::sync_fingerprint(&env.config, &env.scan(), None),
+        base
+    );
+
+    env.config.mount_blacklist = vec!["/system/etc/hosts".to_string()];
+    let global = storage::sync_fingerprint(&env.config, &env.scan(), None);
+    assert_ne!(global, base);
+
+    env.config.mount_blacklist.clear();
+    env.config.rules.insert(
+        "alpha".to_string(),
+        ModuleRules {
+            blacklist: vec!["/system/etc/hosts".to_string()],
+            ..Default::default()
+        },
+    );
+    let per_module = storage::sync_fingerprint(&env.config, &env.scan(), None);
+    assert_ne!(per_module, base);
+    assert_ne!(per_module, global);
+
+    // A module left out of the sync changes it too.
+    let mut modules = env.scan();
+    modules.retain(|m| m.id != "beta");
+    assert_ne!(
+        storage::sync_fingerprint(&env.config, &modules, None),
+        per_module
+    );
+}
@@ -0,0 +1,146 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Modules read from `image_modules` bundles. The mounted images are
+//! stood in for by plain directories under the bundles directory.
+
+mod common;
+
+use std::{fs, path::PathBuf};
+
+use common::{ModuleFixture, TestEnv};
+use meta_hybrid::{
+    core::{
+        bundles::{self, ImageKind},
+        inventory::model as modules,
+        ops::{planner::DiagnosticLevel, sync},
+    },
+    sys::loopdev::LoopOptions,
+};
+
+/// Lists `<name>.sqfs` in `image_modules`, without creating it.
+fn add_image(env: &mut TestEnv, name: &str) {
+    env.config
+        .image_modules
+        .push(env.root.join(format!("sdcard/{}.sqfs", name)));
+}
+
+/// Creates module `dir` in the mounted bundle `bundle`.
+fn bundled(env: &TestEnv, bundle: &str, dir: &str) -> ModuleFixture {
+    let dir_path = env.paths.bundles_dir.join(bundle).join(dir);
+    fs::create_dir_all(&dir_path).unwrap();
+    fs::write(
+        dir_path.join("module.prop"),
+        format!("id={dir}\nname={dir}\nversion=1.0\nversionCode=1\nauthor=test\n"),
+    )
+    .unwrap();
+    ModuleFixture { dir: dir_path }
+}
+
+#[test]
+fn bundle_modules_are_scanned_under_prefixed_ids() {
+    let mut env = TestEnv::new();
+    add_image(&mut env, "pack");
+    env.config.exclusions.push("pack.excluded".to_string());
+    env.module("alpha").file("system/etc/a.conf", "a");
+    bundled(&env, "pack", "fonts").file("system/fonts/x.ttf", "x");
+    bundled(&env, "pack", "off").disabled();
+    bundled(&env, "pack", "excluded").file("system/etc/e.conf", "e");
+    // Not listed in `image_modules`.
+    bundled(&env, "stray", "lost").file("system/etc/s.conf", "s");
+
+    let scanned = env.scan();
+    let ids: Vec<&str> = scanned.iter().map(|m| m.id.as_str()).collect();
+    assert_eq!(ids, ["pack.fonts", "alpha"]);
+    assert_eq!(scanned[0].bundle.as_deref(), Some("pack"));
+    assert_eq!(scanned[1].bundle, None);
+
+    let listed = modules::list_with_paths(&env.config, &env.paths).unwrap();
+    let off = listed.iter().find(|m| m.id == "pack.off").unwrap();
+    assert!(off.excluded_by.is_some());
+    assert_eq!(off.bundle.as_deref(), Some("pack"));
+}
+
+#[test]
+fn bundle_modules_sync_and_conflict_under_their_ids() {
+    let mut env = TestEnv::new();
+    add_image(&mut env, "pack");
+    env.module("alpha").file("system/etc/hosts", "alpha");
+    bundled(&env, "pack", "hosts").file("system/etc/hosts", "bundle");
+
+    let report = env.analyze(&env.plan());
+    let hosts = report
+        .conflicts
+        .iter()
+        .find(|c| c.relative_display == "hosts")
+        .expect("hosts is contested");
+    let mut contenders = hosts.contending_modules.clone();
+    contenders.sort();
+    assert_eq!(contenders, ["alpha", "pack.hosts"]);
+
+    let storage = env.root.join("storage");
+    sync::perform_sync(&env.scan(), &storage, 0).expect("sync");
+    assert_eq!(
+        fs::read_to_string(storage.join("pack.hosts/system/etc/hosts")).unwrap(),
+        "bundle"
+    );
+}
+
+#[test]
+fn unusable_images_are_warned_about_and_skipped() {
+    let env = TestEnv::new();
+    let images_dir = env.root.join("sdcard");
+    fs::create_dir_all(&images_dir).unwrap();
+
+    let squashfs = images_dir.join("pack.sqfs");
+    fs::write(&squashfs, b"hsqs and the rest of a superblock").unwrap();
+    let mut erofs = vec![0; 1028];
+    erofs[1024..].copy_from_slice(&0xE0F5_E1E2_u32.to_le_bytes());
+    let erofs_path = images_dir.join("other.erofs");
+    fs::write(&erofs_path, erofs).unwrap();
+    assert_eq!(
+        ImageKind::detect(&squashfs).unwrap(),
+        Some(ImageKind::Squashfs)
+    );
+    assert_eq!(
+        ImageKind::detect(&erofs_path).unwrap(),
+        Some(ImageKind::Erofs)
+    );
+
+    let garbage = images_dir.join("garbage.img");
+    fs::write(&garbage, vec![0xAA; 4096]).unwrap();
+    let duplicate = images_dir.join("dup/pack.erofs");
+    fs::create_dir_all(duplicate.parent().unwrap()).unwrap();
+    fs::copy(&squashfs, &duplicate).unwrap();
+    let images: Vec<PathBuf> = vec![
+        squashfs,
+        images_dir.join("missing.sqfs"),
+        garbage.clone(),
+        images_dir.join("9lives.sqfs"),
+        duplicate,
+    ];
+
+    let issues = bundles::diagnose(&images);
+    assert_eq!(issues.len(), 4, "{:?}", issues);
+    assert!(
+        issues
+            .iter()
+            .all(|i| matches!(i.level, DiagnosticLevel::Warning))
+    );
+    assert!(issues[0].message.contains("does not exist"));
+    assert!(
+        issues[1]
+            .message
+            .contains("neither a squashfs nor an erofs")
+    );
+
+    let mounted = bundles::mount_all(
+        &images[1..4],
+        &env.paths.bundles_dir,
+        &LoopOptions::default(),
+        false,
+    );
+    assert!(mounted.is_empty());
+    assert!(!env.paths.bundles_dir.exists());
+    assert!(garbage.exists());
+}
@@ -0,0 +1,31 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use common::TestEnv;
+use meta_hybrid::{
+    core::ops::sync,
+    utils::cancel::{self, Cancelled},
+};
+
+// The cancellation flag is process-wide, so this file holds a single test.
+#[test]
+fn cancelled_sync_skips_queued_modules() {
+    let env = TestEnv::new();
+    env.module("alpha").file("system/etc/a.conf", "a");
+    env.module("beta").file("system/etc/b.conf", "b");
+    let storage = env.root.join("storage");
+
+    cancel::request(libc::SIGTERM);
+    let summary = sync::perform_sync(&env.scan(), &storage, 0).expect("sync");
+
+    assert_eq!(cancel::requested(), Some(libc::SIGTERM));
+    assert_eq!(summary.copied, 0);
+    assert!(!storage.join("alpha").exists());
+    assert!(!storage.join("beta").exists());
+
+    assert_eq!(Cancelled(libc::SIGTERM).exit_code(), 143);
+    assert_eq!(Cancelled(libc::SIGINT).exit_code(), 130);
+    assert_eq!(Cancelled(libc::SIGTERM).to_string(), "cancelled by SIGTERM");
+}
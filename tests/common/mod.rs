@@ -0,0 +1,211 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! A fake device tree for driving the planner without touching the host.
+//!
+//! `TestEnv` lays out `<root>/system`, `<root>/vendor`, ... plus the module
+//! directory `<root>/data/adb/modules`, and exposes a [`RootedSystem`] probe
+//! so planning resolves `/system/vendor -> /vendor` inside the tree.
+
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+use meta_hybrid::{
+    Config, Paths,
+    core::{
+        inventory::{self, Module},
+        ops::{
+            planner::{self, AnalysisReport, MountPlan},
+            probe::RootedSystem,
+        },
+        winnow::{self, ChaffConflict},
+    },
+};
+use nix::sys::stat::{Mode, SFlag, makedev, mknod};
+use tempfile::TempDir;
+
+pub struct TestEnv {
+    _dir: TempDir,
+    pub root: PathBuf,
+    pub config: Config,
+    pub paths: Paths,
+    pub probe: RootedSystem,
+}
+
+impl TestEnv {
+    /// A device with `system`, `vendor`, `product` and `system_ext`, where
+    /// the last three are mount points linked from `/system` as on
+    /// system-as-root devices.
+    pub fn new() -> Self {
+        let dir = TempDir::new().expect("create temp dir");
+        let root = dir.path().to_path_buf();
+
+        for d in [
+            "system/bin",
+            "system/etc",
+            "system/framework",
+            "system/lib64",
+            "system/app",
+            "vendor/etc",
+            "vendor/lib64",
+            "product/etc",
+            "product/app",
+            "system_ext/etc",
+        ] {
+            fs::create_dir_all(root.join(d)).expect("create partition dir");
+        }
+
+        let mut probe = RootedSystem::new(&root);
+        for partition in ["vendor", "product", "system_ext"] {
+            symlink(
+                format!("/{}", partition),
+                root.join("system").join(partition),
+            )
+            .expect("link partition into /system");
+            probe.mount_points.insert(Path::new("/").join(partition));
+        }
+
+        let paths = Paths::under(&root);
+        let config = Config {
+            moduledir: root.join("data/adb/modules"),
+            ..Config::default()
+        };
+        fs::create_dir_all(&config.moduledir).expect("create module dir");
+
+        Self {
+            _dir: dir,
+            root,
+            config,
+            paths,
+            probe,
+        }
+    }
+
+    /// An older A-only device: vendor lives in `/system/vendor` and
+    /// `/vendor` links to it.
+    pub fn a_only() -> Self {
+        let mut env = Self::new();
+        fs::remove_file(env.root.join("system/vendor")).expect("unlink /system/vendor");
+        fs::rename(env.root.join("vendor"), env.root.join("system/vendor"))
+            .expect("move vendor into /system");
+        symlink("/system/vendor", env.root.join("vendor")).expect("link /vendor");
+        env.probe.mount_points.remove(Path::new("/vendor"));
+        env
+    }
+
+    /// Adds `/<name>` to the fake device, as a mount point when `mounted`.
+    pub fn partition(&mut self, name: &str, mounted: bool) -> &mut Self {
+        fs::create_dir_all(self.root.join(name)).expect("create partition dir");
+        if mounted {
+            self.probe.mount_points.insert(Path::new("/").join(name));
+        }
+        self
+    }
+
+    /// Creates `data/adb/modules/<id>` with a `module.prop`.
+    pub fn module(&self, id: &str) -> ModuleFixture {
+        let dir = self.config.moduledir.join(id);
+        fs::create_dir_all(&dir).expect("create module dir");
+        fs::write(
+            dir.join("module.prop"),
+            format!("id={id}\nname={id}\nversion=1.0\nversionCode=1\nauthor=test\n"),
+        )
+        .expect("write module.prop");
+        ModuleFixture { dir }
+    }
+
+    /// Stages `data/adb/modules_update/<id>` at `version`, as a module
+    /// update waiting for the reboot.
+    pub fn pending_update(&self, id: &str, version: &str) -> ModuleFixture {
+        let dir = self.root.join("data/adb/modules_update").join(id);
+        fs::create_dir_all(&dir).expect("create update dir");
+        fs::write(
+            dir.join("module.prop"),
+            format!("id={id}\nname={id}\nversion={version}\nversionCode=2\nauthor=test\n"),
+        )
+        .expect("write module.prop");
+        ModuleFixture { dir }
+    }
+
+    pub fn scan(&self) -> Vec<Module> {
+        inventory::scan_with_paths(&self.config.moduledir, &self.config, &self.paths)
+            .expect("scan modules")
+    }
+
+    /// Plans against the module directory itself, as a dry run does.
+    pub fn plan(&self) -> MountPlan {
+        let modules = self.scan();
+        planner::generate_with_probe(&self.config, &modules, &self.config.moduledir, &self.probe)
+            .expect("generate plan")
+    }
+
+    pub fn analyze(&self, plan: &MountPlan) -> AnalysisReport {
+        plan.analyze_with_probe(&self.probe)
+    }
+
+    pub fn sift(&self, plan: &MountPlan) -> Vec<ChaffConflict> {
+        winnow::sift_conflicts(&self.analyze(plan).conflicts, &self.config.winnowing)
+    }
+}
+
+/// Builder for the contents of one fixture module.
+pub struct ModuleFixture {
+    pub dir: PathBuf,
+}
+
+impl ModuleFixture {
+    pub fn file(self, rel: &str, contents: &str) -> Self {
+        let path = self.dir.join(rel);
+        fs::create_dir_all(path.parent().expect("file has a parent")).expect("create parent");
+        fs::write(path, contents).expect("write module file");
+        self
+    }
+
+    /// Marks `rel` as a Magisk-style `.replace` directory.
+    pub fn replace_dir(self, rel: &str) -> Self {
+        let dir = self.dir.join(rel);
+        fs::create_dir_all(&dir).expect("create replace dir");
+        fs::write(dir.join(".replace"), "").expect("write .replace");
+        self
+    }
+
+    /// Creates an overlayfs whiteout (a 0:0 character device) at `rel`.
+    /// Returns `None` when the host refuses `mknod`, e.g. without root.
+    pub fn whiteout(self, rel: &str) -> Option<Self> {
+        let path = self.dir.join(rel);
+        fs::create_dir_all(path.parent()?).ok()?;
+        mknod(
+            &path,
+            SFlag::S_IFCHR,
+            Mode::from_bits_truncate(0o644),
+            makedev(0, 0),
+        )
+        .ok()?;
+        Some(self)
+    }
+
+    pub fn mount_mode(self, mode: &str) -> Self {
+        fs::write(self.dir.join("mount_mode"), mode).expect("write mount_mode");
+        self
+    }
+
+    pub fn disabled(self) -> Self {
+        fs::write(self.dir.join("disable"), "").expect("write disable");
+        self
+    }
+
+    pub fn skip_mount(self) -> Self {
+        fs::write(self.dir.join("skip_mount"), "").expect("write skip_mount");
+        self
+    }
+
+    pub fn removed(self) -> Self {
+        fs::write(self.dir.join("remove"), "").expect("write remove");
+        self
+    }
+}
@@ -0,0 +1,318 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use meta_hybrid::{
+    Config,
+    conf::{
+        config::{BootPriority, LogLevel, NamespaceMode, OverlayMode, OverlayOptions, XinoMode},
+        migrate::{CURRENT_SCHEMA_VERSION, LoadReport},
+        profile::{self, ProfileSource},
+    },
+    sys::priority,
+};
+use tempfile::TempDir;
+
+fn parse(content: &str) -> (Config, LoadReport) {
+    Config::parse(content).expect("parse config")
+}
+
+/// Saves `config` and loads it back, returning the written text too.
+fn round_trip(config: &Config) -> (Config, String) {
+    let dir = TempDir::new().expect("create temp dir");
+    let path = dir.path().join("config.toml");
+    config.save_to_file(&path).expect("save config");
+    let text = std::fs::read_to_string(&path).expect("read saved config");
+    (Config::from_file(&path).expect("reload config"), text)
+}
+
+#[test]
+fn unversioned_config_is_treated_as_v1() {
+    let (config, report) = parse("verbose = true\n");
+
+    assert_eq!(report.from_version, 1);
+    assert!(report.unknown_keys.is_empty());
+    assert_eq!(config.schema_version, CURRENT_SCHEMA_VERSION);
+    assert!(config.verbose);
+}
+
+#[test]
+fn v1_storage_flags_become_storage_mode() {
+    let (config, report) = parse("verbose = false\nforce_ext4 = true\n");
+    assert!(report.unknown_keys.is_empty());
+    assert!(matches!(config.storage_mode, Some(OverlayMode::Ext4)));
+
+    let (config, _) = parse("verbose = false\nforce_ext4 = true\nuse_erofs = true\n");
+    assert!(matches!(config.storage_mode, Some(OverlayMode::Erofs)));
+
+    let (config, _) = parse("verbose = false\nforce_ext4 = false\nuse_erofs = false\n");
+    assert!(config.storage_mode.is_none());
+
+    let (config, _) = parse("verbose = false\nuse_erofs = true\nstorage_mode = \"zram\"\n");
+    assert!(matches!(config.storage_mode, Some(OverlayMode::Zram)));
+
+    let (reloaded, text) = round_trip(&parse("verbose = false\nforce_ext4 = true\n").0);
+    assert!(!text.contains("force_ext4"));
+    assert!(text.contains("storage_mode = \"ext4\""));
+    assert!(matches!(reloaded.storage_mode, Some(OverlayMode::Ext4)));
+}
+
+#[test]
+fn v1_flat_winnowing_keys_move_into_rules() {
+    let (config, report) = parse(
+        r#"
+verbose = false
+
+[winnowing]
+"/system/etc/hosts" = "alpha"
+"system/framework/services.jar/" = "beta"
+
+[winnowing.rules]
+"/system/etc/hosts" = "gamma"
+"#,
+    );
+
+    assert!(report.unknown_keys.is_empty(), "{:?}", report.unknown_keys);
+    assert_eq!(
+        config.winnowing.get_preferred_module("/system/etc/hosts"),
+        Some("gamma")
+    );
+    assert_eq!(
+        config
+            .winnowing
+            .get_preferred_module("/system/framework/services.jar"),
+        Some("beta")
+    );
+
+    let (reloaded, _) = round_trip(&config);
+    assert_eq!(reloaded.winnowing.rules, config.winnowing.rules);
+}
+
+#[test]
+fn v1_granary_table_becomes_backup() {
+    let (config, report) = parse("verbose = false\n\n[granary]\nmax_backups = 7\n");
+
+    assert!(report.unknown_keys.is_empty());
+    assert_eq!(config.backup.max_backups, 7);
+
+    let (reloaded, text) = round_trip(&config);
+    assert!(text.contains("[backup]"));
+    assert!(!text.contains("granary"));
+    assert_eq!(reloaded.backup.max_backups, 7);
+}
+
+#[test]
+fn current_configs_are_not_migrated() {
+    let (config, report) = parse(&format!(
+        "schema_version = {CURRENT_SCHEMA_VERSION}\nverbose = false\nforce_ext4 = true\n"
+    ));
+
+    assert_eq!(report.from_version, CURRENT_SCHEMA_VERSION);
+    assert_eq!(report.unknown_keys, ["force_ext4"]);
+    assert!(config.storage_mode.is_none());
+}
+
+#[test]
+fn unknown_keys_are_reported() {
+    let (_, report) = parse(
+        r#"
+verbose = false
+no_such_option = 1
+
+[stealth]
+randomize_mountsource = true
+cloak = true
+
+[rules.alpha]
+default_mode = "magic"
+"#,
+    );
+
+    assert_eq!(report.unknown_keys, ["no_such_option", "stealth.cloak"]);
+}
+
+#[test]
+fn save_always_writes_the_latest_schema() {
+    let config = Config {
+        schema_version: 1,
+        ..Config::default()
+    };
+
+    let (reloaded, text) = round_trip(&config);
+
+    assert!(text.contains(&format!("schema_version = {CURRENT_SCHEMA_VERSION}")));
+    assert_eq!(reloaded.schema_version, CURRENT_SCHEMA_VERSION);
+}
+
+#[test]
+fn profile_values_win_and_maps_merge_per_key() {
+    let (mut config, _) = parse(
+        "verbose = false\npartitions = [\"my_custom\"]\nexclusions = [\"a\"]\n\
+         [backup]\nmax_backups = 5\nretention_days = 7\n\
+         [winnowing.rules]\n\"/system/bin/sh\" = \"alpha\"\n\"/system/etc/hosts\" = \"beta\"\n",
+    );
+    let dir = TempDir::new().expect("create temp dir");
+    let profiles = dir.path().join(profile::PROFILES_DIR);
+    std::fs::create_dir_all(&profiles).unwrap();
+    std::fs::write(
+        profiles.join("banking.toml"),
+        "partitions = \"oem, odm\"\nexclusions = []\n[backup]\nmax_backups = 1\n\
+         [winnowing.rules]\n\"system/etc/hosts/\" = \"gamma\"\n",
+    )
+    .unwrap();
+
+    let config_path = dir.path().join("config.toml");
+    assert_eq!(profile::profiles_dir(&config_path), profiles);
+    let path = profile::apply(&mut config, &profiles, "banking").expect("apply profile");
+
+    assert_eq!(path, profiles.join("banking.toml"));
+    assert_eq!(config.profile.as_deref(), Some("banking"));
+    assert!(!config.verbose);
+    assert_eq!(config.partitions, ["oem", "odm"]);
+    assert!(config.exclusions.is_empty());
+    assert_eq!(config.backup.max_backups, 1);
+    assert_eq!(config.backup.retention_days, 7);
+    assert_eq!(config.winnowing.rules["/system/bin/sh"], "alpha");
+    assert_eq!(config.winnowing.rules["/system/etc/hosts"], "gamma");
+}
+
+#[test]
+fn profile_selection_precedence() {
+    let (config, _) = parse("verbose = false\nprofile = \"dev\"\n");
+    let pick = |cli, prop| profile::select(cli, prop, &config);
+
+    assert_eq!(
+        pick(Some("bank"), Some("work")),
+        Some(("bank".to_string(), ProfileSource::Cli))
+    );
+    assert_eq!(
+        pick(None, Some("work")),
+        Some(("work".to_string(), ProfileSource::Property))
+    );
+    assert_eq!(
+        pick(None, Some("")),
+        Some(("dev".to_string(), ProfileSource::Config))
+    );
+    assert_eq!(profile::select(None, None, &Config::default()), None);
+}
+
+#[test]
+fn bad_profiles_are_rejected_without_touching_the_config() {
+    let (mut config, _) = parse("verbose = true\n");
+    let dir = TempDir::new().expect("create temp dir");
+    std::fs::write(dir.path().join("typo.toml"), "verbos = false\n").unwrap();
+
+    assert!(profile::apply(&mut config, dir.path(), "typo").is_err());
+    assert!(profile::apply(&mut config, dir.path(), "missing").is_err());
+    assert!(profile::apply(&mut config, dir.path(), "../config").is_err());
+    assert!(config.verbose);
+    assert_eq!(config.profile, None);
+}
+
+#[test]
+fn boot_priority_defaults_to_normal() {
+    let (config, report) = parse("verbose = false\n");
+    assert_eq!(config.boot_priority, BootPriority::Normal);
+
+    let (config, report_low) = parse("verbose = false\nboot_priority = \"low\"\n");
+    assert_eq!(config.boot_priority, BootPriority::Low);
+    assert!(report.unknown_keys.is_empty() && report_low.unknown_keys.is_empty());
+}
+
+#[test]
+fn storage_table_is_known_and_partial() {
+    let (config, report) = parse("verbose = false\n[storage]\nimage_overhead_mb = 16\n");
+    assert_eq!(config.storage.image_overhead_mb, 16);
+    assert_eq!(config.storage.image_headroom_percent, 20);
+    assert!(report.unknown_keys.is_empty());
+
+    let (_, report) = parse("verbose = false\n[storage]\noverhead = 16\n");
+    assert_eq!(report.unknown_keys, ["storage.overhead"]);
+}
+
+#[test]
+fn granular_fallback_defaults_to_on() {
+    let (config, _) = parse("verbose = false\n");
+    assert!(config.granular_fallback);
+
+    let (config, report) = parse("verbose = false\ngranular_fallback = false\n");
+    assert!(!config.granular_fallback);
+    assert!(report.unknown_keys.is_empty());
+}
+
+#[test]
+fn namespace_mode_defaults_to_global() {
+    let (config, _) = parse("verbose = false\n");
+    assert_eq!(config.namespace_mode, NamespaceMode::Global);
+
+    let (config, report) = parse("verbose = false\nnamespace_mode = \"clone\"\n");
+    assert_eq!(config.namespace_mode, NamespaceMode::Clone);
+    assert!(report.unknown_keys.is_empty());
+}
+
+#[test]
+fn log_level_reads_verbose_as_debug() {
+    let (config, report) = parse("log_level = \"warn\"\n");
+    assert_eq!(config.effective_log_level(), LogLevel::Warn);
+    assert!(report.unknown_keys.is_empty());
+
+    let (config, _) = parse("verbose = true\nlog_level = \"trace\"\n");
+    assert_eq!(config.effective_log_level(), LogLevel::Trace);
+
+    let (mut config, _) = parse("verbose = true\ndebug_modules = [\"alpha\"]\n");
+    assert_eq!(config.effective_log_level(), LogLevel::Debug);
+    config.merge_with_cli(
+        None,
+        None,
+        false,
+        vec!["beta".into(), "alpha".into()],
+        Vec::new(),
+        false,
+        false,
+        false,
+    );
+    assert_eq!(config.debug_modules, ["alpha", "beta"]);
+}
+
+#[test]
+fn cgroup_v2_path_is_read_from_the_unified_line() {
+    assert_eq!(
+        priority::cgroup_v2_path("1:cpuset:/top-app\n0::/uid_0/pid_42\n").as_deref(),
+        Some("/uid_0/pid_42")
+    );
+    assert_eq!(priority::cgroup_v2_path("0::/\n").as_deref(), Some("/"));
+    assert_eq!(priority::cgroup_v2_path("2:memory:/\n1:cpu:/\n"), None);
+}
+
+#[test]
+fn overlay_options_merge_partition_entries_over_the_wildcard() {
+    let (config, report) = parse(
+        "verbose = false\n\
+         [overlay_options.\"*\"]\nxino = \"auto\"\nmetacopy = false\n\
+         [overlay_options.vendor]\nmetacopy = true\nredirect_dir = true\nuserxattr = true\n",
+    );
+    assert!(report.unknown_keys.is_empty());
+
+    let system = config.overlay_options_for("system");
+    assert_eq!(system.xino, Some(XinoMode::Auto));
+    assert_eq!(
+        system.mount_params(),
+        [("xino", Some("auto")), ("metacopy", Some("off"))]
+    );
+
+    assert_eq!(
+        config.overlay_options_for("vendor").mount_params(),
+        [
+            ("xino", Some("auto")),
+            ("metacopy", Some("on")),
+            ("redirect_dir", Some("on")),
+            ("userxattr", None),
+        ]
+    );
+
+    let (config, _) = parse("verbose = false\n");
+    assert_eq!(
+        config.overlay_options_for("system"),
+        OverlayOptions::default()
+    );
+    assert!(OverlayOptions::default().mount_params().is_empty());
+}
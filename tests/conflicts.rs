@@ -0,0 +1,155 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Conflict analysis against a pairwise reference and at scale.
+
+mod common;
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use common::TestEnv;
+use meta_hybrid::core::ops::planner::MountPlan;
+use walkdir::WalkDir;
+
+type Contested = BTreeMap<(String, PathBuf), Vec<String>>;
+
+fn files(layer: &Path) -> Vec<PathBuf> {
+    WalkDir::new(layer)
+        .min_depth(1)
+        .into_iter()
+        .flatten()
+        .filter(|e| !e.file_type().is_dir())
+        .map(|e| e.path().strip_prefix(layer).unwrap().to_path_buf())
+        .collect()
+}
+
+fn module_of(layer: &Path) -> String {
+    layer
+        .ancestors()
+        .find(|p| p.parent().is_some_and(|p| p.ends_with("modules")))
+        .and_then(|p| p.file_name())
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Compares every pair of layers of every target.
+fn naive(plan: &MountPlan) -> Contested {
+    let mut contested = Contested::new();
+    for op in &plan.overlay_ops {
+        let listed: Vec<(String, Vec<PathBuf>)> = op
+            .lowerdirs
+            .iter()
+            .map(|l| (module_of(l), files(l)))
+            .collect();
+        for (i, (a, a_files)) in listed.iter().enumerate() {
+            for (b, b_files) in &listed[i + 1..] {
+                for file in a_files.iter().filter(|f| b_files.contains(f)) {
+                    let ids = contested
+                        .entry((op.target.clone(), file.clone()))
+                        .or_default();
+                    for id in [a, b] {
+                        if !ids.contains(id) {
+                            ids.push(id.clone());
+                        }
+                    }
+                }
+            }
+        }
+    }
+    contested
+}
+
+fn analyzed(env: &TestEnv, plan: &MountPlan) -> Contested {
+    env.analyze(plan)
+        .conflicts
+        .into_iter()
+        .map(|c| ((c.target, c.relative_path), c.contending_modules))
+        .collect()
+}
+
+#[test]
+fn single_pass_matches_pairwise_comparison() {
+    let env = TestEnv::new();
+    env.module("alpha")
+        .file("system/etc/a.conf", "a")
+        .file("system/etc/shared.conf", "a")
+        .file("system/framework/x.jar", "a");
+    env.module("beta")
+        .file("system/etc/shared.conf", "b")
+        .file("system/etc/deep/one.xml", "b")
+        .file("system/framework/x.jar", "b");
+    env.module("gamma")
+        .file("system/etc/shared.conf", "g")
+        .file("system/etc/deep/one.xml", "g")
+        .file("vendor/etc/v.conf", "g");
+    env.module("delta").file("vendor/etc/v.conf", "d");
+
+    let plan = env.plan();
+    let expected = naive(&plan);
+    assert_eq!(expected.len(), 4);
+    assert_eq!(analyzed(&env, &plan), expected);
+
+    // Sorted by full path, so two runs diff cleanly.
+    let report = env.analyze(&plan);
+    let paths: Vec<PathBuf> = report
+        .conflicts
+        .iter()
+        .map(|c| Path::new(&c.target).join(&c.relative_path))
+        .collect();
+    let mut sorted = paths.clone();
+    sorted.sort();
+    assert_eq!(paths, sorted);
+
+    let framework = plan.analyze_under(Path::new("/system/framework"));
+    assert_eq!(framework.conflicts.len(), 1);
+    assert_eq!(framework.conflicts[0].relative_display, "x.jar");
+    let deep = plan.analyze_under(Path::new("/system/etc/deep"));
+    assert_eq!(deep.conflicts.len(), 1);
+    assert_eq!(deep.conflicts[0].relative_display, "deep/one.xml");
+    assert!(plan.analyze_under(Path::new("/odm")).conflicts.is_empty());
+}
+
+#[test]
+fn hundred_thousand_files_analyze_quickly() {
+    const MODULES: usize = 50;
+    const FILES: usize = 2000;
+
+    let env = TestEnv::new();
+    for m in 0..MODULES {
+        let module = env.module(&format!("mod{:02}", m));
+        for f in 0..FILES {
+            // Every module shares the first ten names with all the others.
+            let rel = if f < 10 {
+                format!("system/etc/shared/s{}.conf", f)
+            } else {
+                format!("system/etc/m{}/d{}/f{}.conf", m, f % 20, f)
+            };
+            let path = module.dir.join(rel);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, [m as u8]).unwrap();
+        }
+    }
+
+    let plan = env.plan();
+    let started = Instant::now();
+    let report = env.analyze(&plan);
+    let elapsed = started.elapsed();
+
+    assert_eq!(report.conflicts.len(), 10);
+    assert!(
+        report
+            .conflicts
+            .iter()
+            .all(|c| c.contending_modules.len() == MODULES)
+    );
+    assert!(
+        elapsed < Duration::from_secs(60),
+        "analysis took {:?}",
+        elapsed
+    );
+}
@@ -0,0 +1,113 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::{fs, os::unix::fs::symlink, path::PathBuf};
+
+use common::TestEnv;
+use meta_hybrid::core::{
+    deferred::{self, DeferredList},
+    inventory::encryption,
+    ops::planner::DiagnosticLevel,
+};
+
+fn link(env: &TestEnv, id: &str, rel: &str, target: &str) {
+    let path = env.config.moduledir.join(id).join(rel);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    symlink(target, path).unwrap();
+}
+
+#[test]
+fn symlinks_into_ce_storage_are_flagged() {
+    let env = TestEnv::new();
+    env.module("wallpaper").file("system/etc/a.conf", "a");
+    link(
+        &env,
+        "wallpaper",
+        "system/etc/theme",
+        "/data/data/com.theme/files",
+    );
+    link(
+        &env,
+        "wallpaper",
+        "system/media/boot.zip",
+        "../../data/media/0/boot.zip",
+    );
+    env.module("plain").file("system/etc/b.conf", "b");
+    link(&env, "plain", "system/etc/de", "/data/user_de/0/com.x");
+    link(&env, "plain", "system/etc/alias", "b.conf");
+
+    let modules = env.scan();
+    let wallpaper = modules.iter().find(|m| m.id == "wallpaper").unwrap();
+    assert_eq!(
+        wallpaper.ce_refs,
+        [
+            PathBuf::from("system/etc/theme"),
+            PathBuf::from("system/media/boot.zip")
+        ]
+    );
+    let plain = modules.iter().find(|m| m.id == "plain").unwrap();
+    assert!(plain.ce_refs.is_empty());
+
+    let issues = encryption::diagnose(&modules, false);
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].context, "wallpaper");
+    assert!(matches!(issues[0].level, DiagnosticLevel::Warning));
+    assert!(matches!(
+        encryption::diagnose(&modules, true)[0].level,
+        DiagnosticLevel::Info
+    ));
+}
+
+#[test]
+fn flagged_modules_are_held_back() {
+    let env = TestEnv::new();
+    env.module("wallpaper");
+    link(
+        &env,
+        "wallpaper",
+        "system/etc/theme",
+        "/data/user/0/com.theme",
+    );
+    env.module("plain").file("system/etc/b.conf", "b");
+
+    let mut modules = env.scan();
+    assert_eq!(deferred::hold_back(&mut modules), ["wallpaper"]);
+    assert_eq!(modules.len(), 1);
+    assert_eq!(modules[0].id, "plain");
+}
+
+#[test]
+fn the_list_round_trips_and_an_empty_one_is_removed() {
+    let env = TestEnv::new();
+    let path = &env.paths.deferred_file;
+
+    assert!(DeferredList::load_from(path).unwrap().modules.is_empty());
+
+    DeferredList::new(vec!["wallpaper".to_string()])
+        .save_to(path)
+        .unwrap();
+    assert_eq!(
+        DeferredList::load_from(path).unwrap().modules,
+        ["wallpaper"]
+    );
+
+    DeferredList::new(Vec::new()).save_to(path).unwrap();
+    assert!(!path.exists());
+}
+
+#[test]
+fn mounting_without_a_list_is_a_no_op() {
+    let env = TestEnv::new();
+    env.module("wallpaper");
+    link(
+        &env,
+        "wallpaper",
+        "system/etc/theme",
+        "/data/data/com.theme",
+    );
+
+    assert!(deferred::mount(&env.config, &env.paths).unwrap().is_empty());
+    assert!(!env.paths.state_file.exists());
+}
@@ -0,0 +1,139 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::{collections::HashMap, ffi::OsStr, os::unix::fs::symlink, path::Path};
+
+use common::TestEnv;
+use meta_hybrid::{
+    core::ops::{
+        partition_map::PartitionMap,
+        planner::{DiagnosticLevel, MountPlan},
+    },
+    mount::magic_mount,
+    utils,
+};
+
+fn targets(plan: &MountPlan) -> Vec<&str> {
+    plan.overlay_ops
+        .iter()
+        .map(|op| op.target.as_str())
+        .collect()
+}
+
+fn critical_for(env: &TestEnv, plan: &MountPlan, module_id: &str) -> Vec<String> {
+    env.analyze(plan)
+        .diagnostics
+        .into_iter()
+        .filter(|d| matches!(d.level, DiagnosticLevel::Critical) && d.context == module_id)
+        .map(|d| d.message)
+        .collect()
+}
+
+#[test]
+fn denied_targets_cover_their_subtrees_except_root() {
+    let none: &[String] = &[];
+    assert_eq!(
+        utils::denied_target(Path::new("/data"), none).as_deref(),
+        Some("/data")
+    );
+    assert_eq!(
+        utils::denied_target(Path::new("/data/app/x"), none).as_deref(),
+        Some("/data")
+    );
+    assert_eq!(
+        utils::denied_target(Path::new("/"), none).as_deref(),
+        Some("/")
+    );
+    assert_eq!(utils::denied_target(Path::new("/system/bin"), none), None);
+    assert_eq!(utils::denied_target(Path::new("/database"), none), None);
+
+    let user = vec!["/odm/etc/".to_string(), "relative".to_string()];
+    assert_eq!(
+        utils::denied_target(Path::new("/odm/etc/init"), &user).as_deref(),
+        Some("/odm/etc/")
+    );
+    assert_eq!(utils::denied_target(Path::new("/odm/lib"), &user), None);
+    assert_eq!(utils::denied_target(Path::new("/relative"), &user), None);
+}
+
+#[test]
+fn configured_data_partition_is_never_planned() {
+    let mut env = TestEnv::new();
+    env.partition("data", true);
+    env.config.partitions = vec!["data".to_string()];
+    env.module("rogue")
+        .file("data/local/tmp/x", "x")
+        .file("system/etc/ok.conf", "ok");
+
+    let plan = env.plan();
+    assert!(!targets(&plan).iter().any(|t| t.starts_with("/data")));
+    assert!(targets(&plan).contains(&"/system/etc"));
+    assert!(!plan.extra_partitions.contains(&"data".to_string()));
+
+    let critical = critical_for(&env, &plan, "rogue");
+    assert_eq!(critical.len(), 1);
+    assert!(critical[0].contains("/data"));
+}
+
+#[test]
+fn partition_symlinked_to_data_is_denied() {
+    let mut env = TestEnv::new();
+    env.partition("data", true);
+    symlink("/data", env.root.join("oem")).unwrap();
+    env.module("sneaky").file("oem/etc/x.conf", "x");
+
+    let plan = env.plan();
+    assert!(!targets(&plan).iter().any(|t| t.starts_with("/data")));
+    assert_eq!(plan.denied.len(), 1);
+    assert_eq!(plan.denied[0].module_id, "sneaky");
+    assert_eq!(plan.denied[0].target, "/data");
+    assert_eq!(critical_for(&env, &plan, "sneaky").len(), 1);
+}
+
+#[test]
+fn user_deny_paths_extend_the_list() {
+    let mut env = TestEnv::new();
+    env.partition("odm", true);
+    env.config.user_deny_paths = vec!["/odm".to_string()];
+    env.module("vendorish")
+        .file("odm/etc/x.conf", "x")
+        .file("system/etc/y.conf", "y");
+
+    let plan = env.plan();
+    assert!(!targets(&plan).iter().any(|t| t.starts_with("/odm")));
+    assert!(targets(&plan).contains(&"/system/etc"));
+    let critical = critical_for(&env, &plan, "vendorish");
+    assert_eq!(critical.len(), 1);
+    assert!(critical[0].contains("/odm"));
+}
+
+#[test]
+fn magic_mount_leaves_out_extra_partitions_the_user_denies() {
+    let mut env = TestEnv::new();
+    env.partition("mi_ext", true);
+    env.module("vendorish")
+        .file("mi_ext/etc/x.conf", "x")
+        .file("system/etc/y.conf", "y");
+
+    let extra = vec!["mi_ext".to_string()];
+    let tree = |deny: &[String]| {
+        magic_mount::collect_module_files(
+            &env.config.moduledir,
+            &extra,
+            &["vendorish".to_string()],
+            &HashMap::new(),
+            &HashMap::new(),
+            deny,
+            &PartitionMap::resolve(&extra, &env.probe),
+        )
+        .unwrap()
+        .expect("vendorish has files")
+    };
+
+    assert!(tree(&[]).children.contains_key(OsStr::new("mi_ext")));
+    let denied = tree(&["/mi_ext".to_string()]);
+    assert!(!denied.children.contains_key(OsStr::new("mi_ext")));
+    assert!(denied.children.contains_key(OsStr::new("system")));
+}
@@ -0,0 +1,84 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::fs;
+
+use anyhow::{Context, anyhow};
+use clap::CommandFactory;
+use common::TestEnv;
+use meta_hybrid::{
+    conf::cli::Cli,
+    core::{
+        failure::{self, Classify, DiagnosticsAbort, FailureClass, RunError},
+        state::{BootResult, RuntimeState},
+    },
+    utils::cancel::Cancelled,
+};
+
+#[test]
+fn phases_map_to_distinct_codes() {
+    let classes = [
+        (FailureClass::Config, 2),
+        (FailureClass::Storage, 3),
+        (FailureClass::Inventory, 4),
+        (FailureClass::Plan, 5),
+        (FailureClass::Execution, 6),
+        (FailureClass::DiagnosticsAbort, 7),
+    ];
+    for (class, code) in classes {
+        let error = Err::<(), _>(anyhow!("boom"))
+            .classify(class, "phase failed")
+            .unwrap_err();
+        assert_eq!(error.class(), class);
+        assert_eq!(error.exit_code(), code);
+        assert_eq!(error.to_string(), "phase failed: boom");
+    }
+}
+
+#[test]
+fn diagnostics_abort_and_cancellation_override_the_phase() {
+    let aborted = Err::<(), _>(anyhow::Error::new(DiagnosticsAbort(
+        "foreign mounts on vendor".to_string(),
+    )))
+    .context("Failed to run executor")
+    .classify(FailureClass::Execution, "Failed to execute mount plan")
+    .unwrap_err();
+    assert!(matches!(aborted, RunError::DiagnosticsAbort(_)));
+    assert_eq!(aborted.exit_code(), 7);
+
+    let cancelled = RunError::new(FailureClass::Plan, anyhow::Error::new(Cancelled(15)));
+    assert_eq!(cancelled.cancelled(), Some(Cancelled(15)));
+    assert_eq!(cancelled.exit_code(), 143);
+}
+
+#[test]
+fn failure_is_recorded_even_over_a_corrupt_state() {
+    let env = TestEnv::new();
+    let state_file = &env.paths.state_file;
+    fs::create_dir_all(state_file.parent().unwrap()).unwrap();
+    fs::write(state_file, "{ truncated").unwrap();
+
+    let error = RunError::new(FailureClass::Storage, anyhow!("no loop device"));
+    failure::record(state_file, &error).unwrap();
+
+    let state = RuntimeState::load_from(state_file).unwrap();
+    assert_eq!(state.last_result, BootResult::Failed);
+    assert_eq!(state.boot_count, 1);
+    let record = state.failure.unwrap();
+    assert_eq!(record.code, 3);
+    assert_eq!(record.phase, FailureClass::Storage);
+    assert_eq!(record.message, "no loop device");
+
+    failure::record(state_file, &error).unwrap();
+    assert_eq!(RuntimeState::load_from(state_file).unwrap().boot_count, 2);
+}
+
+#[test]
+fn help_lists_the_exit_codes() {
+    let help = Cli::command().render_help().to_string();
+    assert!(help.contains("Exit codes:"));
+    assert!(help.contains("3    storage backend failure"));
+    assert!(help.contains("7    aborted by diagnostics"));
+}
@@ -0,0 +1,52 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use meta_hybrid::core::ops::executor::bisect_layers;
+
+/// Bisects `count` layers where the overlay fails whenever a layer in `bad`
+/// is present, returning the result and the number of trial mounts.
+fn bisect(count: usize, bad: &[usize]) -> (Option<Vec<usize>>, usize) {
+    let mut trials = 0;
+    let found = bisect_layers(count, &mut |set| {
+        trials += 1;
+        !set.iter().any(|i| bad.contains(i))
+    });
+    (found, trials)
+}
+
+fn bound(count: usize) -> usize {
+    count.next_power_of_two().trailing_zeros() as usize + 1
+}
+
+#[test]
+fn a_single_bad_layer_is_found_anywhere() {
+    for count in [2, 3, 7, 8, 20] {
+        for culprit in 0..count {
+            let (found, trials) = bisect(count, &[culprit]);
+            assert_eq!(found, Some(vec![culprit]), "{culprit} of {count}");
+            assert!(trials <= bound(count), "{trials} trials for {count}");
+        }
+    }
+}
+
+#[test]
+fn several_bad_layers_are_cut_off_with_some_good_ones() {
+    let (found, trials) = bisect(8, &[1, 6]);
+    let found = found.expect("some subset mounts");
+    assert!(found.contains(&1) && found.contains(&6));
+    assert!(found.len() < 8);
+    assert!(trials <= bound(8));
+}
+
+#[test]
+fn no_culprit_when_nothing_mounts() {
+    let mut trials = 0;
+    let found = bisect_layers(6, &mut |_| {
+        trials += 1;
+        false
+    });
+    assert_eq!(found, None);
+    assert!(trials <= bound(6));
+
+    assert_eq!(bisect(1, &[0]).0, None);
+}
@@ -0,0 +1,86 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::{collections::HashMap, fs, os::unix::fs::MetadataExt, path::Path};
+
+use common::TestEnv;
+use meta_hybrid::{
+    core::ops::{partition_map::PartitionMap, sync},
+    mount::{magic_mount, node::Node},
+};
+
+/// A module shipping `blob.bin` under two names, plus an unrelated file.
+fn linked_module(env: &TestEnv) {
+    let alpha = env
+        .module("alpha")
+        .file("vendor/lib64/blob.bin", "blob")
+        .file("vendor/etc/plain.conf", "plain");
+    fs::create_dir_all(alpha.dir.join("vendor/lib/hw")).unwrap();
+    fs::hard_link(
+        alpha.dir.join("vendor/lib64/blob.bin"),
+        alpha.dir.join("vendor/lib/hw/blob.bin"),
+    )
+    .unwrap();
+}
+
+fn child<'a>(node: &'a Node, path: &str) -> &'a Node {
+    Path::new(path)
+        .iter()
+        .fold(node, |node, name| &node.children[name])
+}
+
+#[test]
+fn synced_copy_keeps_the_link_count() {
+    let env = TestEnv::new();
+    linked_module(&env);
+
+    let storage = env.root.join("storage");
+    sync::perform_sync(&env.scan(), &storage, 0).expect("sync");
+    let first = fs::metadata(storage.join("alpha/vendor/lib64/blob.bin")).unwrap();
+    let second = fs::metadata(storage.join("alpha/vendor/lib/hw/blob.bin")).unwrap();
+    assert_eq!(first.ino(), second.ino());
+    assert_eq!(first.nlink(), 2);
+    assert_eq!(
+        fs::metadata(storage.join("alpha/vendor/etc/plain.conf"))
+            .unwrap()
+            .nlink(),
+        1
+    );
+
+    // A resync finds the link in place and leaves it alone.
+    sync::perform_sync(&env.scan(), &storage, 0).expect("resync");
+    let second = fs::metadata(storage.join("alpha/vendor/lib/hw/blob.bin")).unwrap();
+    assert_eq!((second.ino(), second.nlink()), (first.ino(), 2));
+}
+
+#[test]
+fn magic_tree_binds_linked_names_to_one_inode() {
+    let env = TestEnv::new();
+    linked_module(&env);
+    let storage = env.root.join("storage");
+    sync::perform_sync(&env.scan(), &storage, 0).expect("sync");
+
+    let root = magic_mount::collect_module_files(
+        &storage,
+        &[],
+        &["alpha".to_string()],
+        &HashMap::new(),
+        &HashMap::new(),
+        &[],
+        &PartitionMap::resolve(&[], &env.probe),
+    )
+    .unwrap()
+    .expect("module has files");
+
+    let first = child(&root, "vendor/lib64/blob.bin");
+    let second = child(&root, "vendor/lib/hw/blob.bin");
+    assert!(first.inode.is_some());
+    assert_eq!(first.inode, second.inode);
+    assert!(child(&root, "vendor/etc/plain.conf").inode.is_none());
+
+    let source = fs::metadata(second.module_path.as_ref().unwrap()).unwrap();
+    assert_eq!(Some((source.dev(), source.ino())), first.inode);
+    assert_eq!(source.nlink(), 2);
+}
@@ -0,0 +1,85 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{fs, path::Path};
+
+use meta_hybrid::core::hashcache::HashCache;
+use sha2::{Digest, Sha256};
+use tempfile::TempDir;
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+fn write(path: &Path, data: &str) {
+    fs::write(path, data).expect("write file");
+}
+
+#[test]
+fn entries_survive_a_reload_and_follow_file_changes() {
+    let dir = TempDir::new().expect("create temp dir");
+    let store = dir.path().join("run/hash_cache.json");
+    let file = dir.path().join("a.txt");
+    write(&file, "one");
+
+    let cache = HashCache::load(&store, 16);
+    assert_eq!(cache.hash(&file).unwrap(), sha256(b"one"));
+    cache.save().expect("save cache");
+
+    let reloaded = HashCache::load(&store, 16);
+    assert_eq!(reloaded.len(), 1);
+    assert_eq!(reloaded.hash(&file).unwrap(), sha256(b"one"));
+
+    write(&file, "two, longer");
+    assert_eq!(reloaded.hash(&file).unwrap(), sha256(b"two, longer"));
+}
+
+#[test]
+fn least_recently_used_entries_are_evicted() {
+    let dir = TempDir::new().expect("create temp dir");
+    let store = dir.path().join("hash_cache.json");
+    let files: Vec<_> = (0..6)
+        .map(|i| {
+            let path = dir.path().join(format!("{i}.txt"));
+            write(&path, &i.to_string());
+            path
+        })
+        .collect();
+
+    let cache = HashCache::load(&store, 64);
+    for path in &files {
+        cache.hash(path).unwrap();
+    }
+    // Touch the oldest entry so it counts as recent again.
+    cache.hash(&files[0]).unwrap();
+    cache.save().expect("save cache");
+
+    let small = HashCache::load(&store, 3);
+    assert_eq!(small.len(), 3);
+    small.save().expect("save cache");
+    let text = fs::read_to_string(&store).expect("read cache");
+    for kept in [&files[0], &files[4], &files[5]] {
+        assert!(text.contains(&*kept.to_string_lossy()), "{kept:?} evicted");
+    }
+    for evicted in &files[1..4] {
+        assert!(
+            !text.contains(&*evicted.to_string_lossy()),
+            "{evicted:?} kept"
+        );
+    }
+}
+
+#[test]
+fn corrupt_cache_files_are_ignored() {
+    let dir = TempDir::new().expect("create temp dir");
+    let store = dir.path().join("hash_cache.json");
+    let file = dir.path().join("a.txt");
+    write(&file, "data");
+    write(&store, "{\"version\": 1, \"entries\": [trunc");
+
+    let cache = HashCache::load(&store, 16);
+    assert!(cache.is_empty());
+    assert_eq!(cache.hash(&file).unwrap(), sha256(b"data"));
+    cache.save().expect("save over corrupt cache");
+    assert_eq!(HashCache::load(&store, 16).len(), 1);
+}
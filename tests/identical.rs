@@ -0,0 +1,126 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::{fs, os::unix::fs::symlink};
+
+use common::TestEnv;
+use meta_hybrid::core::{
+    ops::{blacklist::Blacklist, identical, sync},
+    storage,
+};
+
+fn stock(env: &TestEnv, rel: &str, contents: &str) {
+    let path = env.root.join(rel);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+#[test]
+fn files_identical_to_stock_are_left_out() {
+    let env = TestEnv::new();
+    stock(&env, "system/etc/same.conf", "same");
+    stock(&env, "system/etc/other.conf", "stock");
+    stock(&env, "system/etc/real.conf", "linked");
+    symlink("real.conf", env.root.join("system/etc/link.conf")).unwrap();
+    stock(&env, "system/app/Foo/Foo.apk", "apk");
+
+    let module = env
+        .module("alpha")
+        .file("system/etc/same.conf", "same")
+        .file("system/etc/other.conf", "mine!")
+        .file("system/etc/link.conf", "linked")
+        .file("system/etc/new.conf", "new")
+        .replace_dir("system/app/Foo")
+        .file("system/app/Foo/Foo.apk", "apk");
+
+    let storage = env.root.join("storage");
+    let summary = sync::perform_sync_with_stock(
+        &env.scan(),
+        &storage,
+        0,
+        Some(&env.root),
+        &Blacklist::default(),
+    )
+    .expect("sync");
+
+    assert_eq!(summary.skipped_identical, 2);
+    assert_eq!(summary.module_usage["alpha"].skipped_identical, 2);
+    let synced = storage.join("alpha/system");
+    assert!(!synced.join("etc/same.conf").exists());
+    assert!(!synced.join("etc/link.conf").exists());
+    assert!(synced.join("etc/other.conf").exists());
+    assert!(synced.join("etc/new.conf").exists());
+    assert!(synced.join("app/Foo/Foo.apk").exists());
+
+    assert_eq!(identical::count(&module.dir, &env.root), 2);
+    let issues = identical::diagnose(&env.scan(), &env.root);
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].context, "alpha");
+    assert!(issues[0].message.starts_with("2 file(s)"));
+}
+
+#[test]
+fn earlier_copies_are_removed_once_skipped() {
+    let env = TestEnv::new();
+    stock(&env, "system/etc/same.conf", "same");
+    env.module("alpha").file("system/etc/same.conf", "same");
+
+    let storage = env.root.join("storage");
+    sync::perform_sync(&env.scan(), &storage, 0).expect("sync");
+    assert!(storage.join("alpha/system/etc/same.conf").exists());
+
+    let summary = sync::perform_sync_with_stock(
+        &env.scan(),
+        &storage,
+        0,
+        Some(&env.root),
+        &Blacklist::default(),
+    )
+    .expect("sync");
+    assert_eq!(summary.skipped_identical, 1);
+    assert!(!storage.join("alpha/system/etc/same.conf").exists());
+}
+
+#[test]
+fn nothing_is_skipped_without_a_stock_root() {
+    let env = TestEnv::new();
+    stock(&env, "system/etc/same.conf", "same");
+    env.module("alpha").file("system/etc/same.conf", "same");
+
+    let storage = env.root.join("storage");
+    let summary = sync::perform_sync(&env.scan(), &storage, 0).expect("sync");
+    assert_eq!(summary.skipped_identical, 0);
+    assert!(storage.join("alpha/system/etc/same.conf").exists());
+}
+
+#[test]
+fn stock_changes_and_the_option_change_the_sync_fingerprint() {
+    let env = TestEnv::new();
+    stock(&env, "system/etc/same.conf", "same");
+    env.module("alpha")
+        .file("system/etc/same.conf", "same")
+        .file("system/etc/new.conf", "new");
+    let modules = env.scan();
+
+    let off = storage::sync_fingerprint(&env.config, &modules, None);
+    let on = storage::sync_fingerprint(&env.config, &modules, Some(&env.root));
+    assert_ne!(off, on);
+    assert_eq!(
+        storage::sync_fingerprint(&env.config, &modules, Some(&env.root)),
+        on
+    );
+
+    // A stock file appearing for a module file, or changing, is seen.
+    stock(&env, "system/etc/new.conf", "new");
+    let added = storage::sync_fingerprint(&env.config, &modules, Some(&env.root));
+    assert_ne!(added, on);
+    stock(&env, "system/etc/same.conf", "changed");
+    assert_ne!(
+        storage::sync_fingerprint(&env.config, &modules, Some(&env.root)),
+        added
+    );
+    // Without the option stock files are not looked at.
+    assert_eq!(storage::sync_fingerprint(&env.config, &modules, None), off);
+}
@@ -0,0 +1,95 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::{fs, path::PathBuf};
+
+use common::TestEnv;
+use meta_hybrid::core::{
+    integrity::{self, IntegrityStatus, Manifest, VerifyLimits},
+    ops::{planner::DiagnosticLevel, sync},
+};
+
+const EVERY_FILE: VerifyLimits = VerifyLimits {
+    max_seconds: 0,
+    sample_percent: 100,
+    full_above_bytes: 0,
+};
+
+fn synced(env: &TestEnv) -> (PathBuf, Manifest) {
+    env.module("alpha")
+        .file("system/bin/tool", "original")
+        .file("system/etc/a.conf", "a");
+    env.module("beta").file("vendor/etc/b.conf", "b");
+
+    let storage = env.root.join("storage");
+    let summary = sync::perform_sync(&env.scan(), &storage, 0).expect("sync");
+    let mut manifest = Manifest::default();
+    let rehashed = manifest.update(&storage, &env.scan(), &summary.changed);
+    assert_eq!(rehashed, ["alpha", "beta"]);
+    (storage, manifest)
+}
+
+#[test]
+fn untouched_copies_verify() {
+    let env = TestEnv::new();
+    let (storage, manifest) = synced(&env);
+
+    let report = integrity::verify(&manifest, &storage, &env.scan(), EVERY_FILE);
+    assert_eq!(report.status, IntegrityStatus::Verified);
+    // Both copies carry their module.prop.
+    assert_eq!((report.hashed, report.total), (5, 5));
+
+    let sampled = VerifyLimits {
+        sample_percent: 0,
+        full_above_bytes: u64::MAX,
+        ..EVERY_FILE
+    };
+    let report = integrity::verify(&manifest, &storage, &env.scan(), sampled);
+    assert_eq!(report.status, IntegrityStatus::Sampled);
+    assert_eq!(report.hashed, 0);
+}
+
+#[test]
+fn tampered_and_added_files_fail_their_module() {
+    let env = TestEnv::new();
+    let (storage, manifest) = synced(&env);
+
+    // Same size, so only hashing catches it.
+    fs::write(storage.join("alpha/system/bin/tool"), "tampered").unwrap();
+    fs::write(storage.join("beta/vendor/etc/extra.so"), "x").unwrap();
+
+    let report = integrity::verify(&manifest, &storage, &env.scan(), EVERY_FILE);
+    assert_eq!(report.status, IntegrityStatus::Failed);
+    assert_eq!(report.failed["alpha"], ["system/bin/tool"]);
+    assert_eq!(report.failed["beta"], ["vendor/etc/extra.so"]);
+
+    let issues = integrity::diagnose(&report);
+    assert_eq!(issues.len(), 2);
+    assert!(
+        issues
+            .iter()
+            .all(|i| matches!(i.level, DiagnosticLevel::Critical))
+    );
+}
+
+#[test]
+fn only_changed_modules_are_rehashed() {
+    let env = TestEnv::new();
+    let (storage, mut manifest) = synced(&env);
+
+    fs::write(env.config.moduledir.join("beta/vendor/etc/b.conf"), "b2").unwrap();
+    let summary = sync::perform_sync(&env.scan(), &storage, 0).expect("resync");
+    assert_eq!(summary.changed, ["beta"]);
+    assert_eq!(
+        manifest.update(&storage, &env.scan(), &summary.changed),
+        ["beta"]
+    );
+    let report = integrity::verify(&manifest, &storage, &env.scan(), EVERY_FILE);
+    assert_eq!(report.status, IntegrityStatus::Verified);
+
+    let alpha_only: Vec<_> = env.scan().into_iter().filter(|m| m.id == "alpha").collect();
+    manifest.update(&storage, &alpha_only, &[]);
+    assert_eq!(manifest.modules.keys().collect::<Vec<_>>(), ["alpha"]);
+}
@@ -0,0 +1,78 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::fs;
+
+use common::TestEnv;
+use meta_hybrid::core::inventory::{
+    model::{self, ModuleInfo},
+    validate,
+};
+
+fn listed<'a>(infos: &'a [ModuleInfo], id: &str) -> &'a ModuleInfo {
+    infos
+        .iter()
+        .find(|info| info.id == id)
+        .unwrap_or_else(|| panic!("{id} is listed"))
+}
+
+#[test]
+fn vendor_only_module_is_listed_and_mounted() {
+    let env = TestEnv::new();
+    env.module("alpha").file("vendor/etc/a.conf", "a");
+
+    let infos = model::list_with_paths(&env.config, &env.paths).unwrap();
+    let alpha = listed(&infos, "alpha");
+    assert_eq!(alpha.partitions, ["vendor"]);
+    assert!(alpha.excluded_by.is_none());
+
+    let plan = env.plan();
+    assert_eq!(plan.overlay_module_ids, ["alpha"]);
+}
+
+#[test]
+fn script_only_module_is_listed_without_partitions() {
+    let env = TestEnv::new();
+    env.module("alpha")
+        .file("post-fs-data.sh", "#!/system/bin/sh\n");
+    env.module("beta").file("system/etc/b.conf", "b");
+
+    let infos = model::list_with_paths(&env.config, &env.paths).unwrap();
+    let ids: Vec<&str> = infos.iter().map(|info| info.id.as_str()).collect();
+    assert_eq!(ids, ["beta", "alpha"]);
+
+    let alpha = listed(&infos, "alpha");
+    assert!(alpha.partitions.is_empty());
+    assert!(alpha.excluded_by.is_none());
+    assert!(!alpha.is_mounted);
+    assert_eq!(listed(&infos, "beta").partitions, ["system"]);
+
+    let plan = env.plan();
+    assert!(!plan.overlay_module_ids.contains(&"alpha".to_string()));
+    assert!(!plan.magic_module_ids.contains(&"alpha".to_string()));
+}
+
+#[test]
+fn listing_checks_module_prop_but_not_file_names() {
+    let env = TestEnv::new();
+    let alpha = env.module("alpha").file("system/etc/a\nb.conf", "a");
+    fs::write(alpha.dir.join("module.prop"), "id=alpha\r\nversion=1.0\r\n").unwrap();
+
+    let infos = model::list_with_paths(&env.config, &env.paths).unwrap();
+    let messages: Vec<&str> = listed(&infos, "alpha")
+        .issues
+        .iter()
+        .map(|issue| issue.message.as_str())
+        .collect();
+    assert!(messages.contains(&"module.prop uses CRLF line endings"));
+    assert!(!messages.iter().any(|m| m.contains("newline")));
+
+    let diagnosed = validate::diagnose(&env.scan());
+    assert!(
+        diagnosed
+            .iter()
+            .any(|issue| issue.context == "alpha" && issue.message.contains("newline"))
+    );
+}
@@ -0,0 +1,74 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! The undo journal of a run. Mounting needs privileges, so the test only
+//! runs where a bind mount can be made.
+
+mod common;
+
+use std::{fs, path::Path};
+
+use common::TestEnv;
+use meta_hybrid::{
+    core::ops::{
+        executor,
+        planner::{BindOperation, MountPlan},
+    },
+    mount::overlayfs::overlayfs::MountMethod,
+};
+use rustix::mount::{UnmountFlags, mount_bind, unmount};
+
+fn can_bind_mount(dir: &Path) -> bool {
+    let (source, target) = (dir.join("probe.src"), dir.join("probe.dst"));
+    fs::write(&source, "").unwrap();
+    fs::write(&target, "").unwrap();
+    let ok = mount_bind(&source, &target).is_ok();
+    if ok {
+        unmount(&target, UnmountFlags::DETACH).unwrap();
+    }
+    ok
+}
+
+fn apex_op(source: &Path, target: &Path) -> BindOperation {
+    BindOperation {
+        module_id: "alpha".to_string(),
+        partition_name: "apex".to_string(),
+        source: source.to_path_buf(),
+        target: target.to_path_buf(),
+    }
+}
+
+#[test]
+fn mounts_kept_after_a_partial_failure_are_journaled() {
+    let mut env = TestEnv::new();
+    if !can_bind_mount(&env.root) {
+        return;
+    }
+    env.config.disable_umount = true;
+    env.config.hybrid_mnt_dir = env.root.join("mnt").to_string_lossy().to_string();
+
+    let (source, target) = (env.root.join("lib.so"), env.root.join("stock.so"));
+    fs::write(&source, "module").unwrap();
+    fs::write(&target, "stock").unwrap();
+    let plan = MountPlan {
+        apex_ops: vec![
+            apex_op(&source, &target),
+            apex_op(&env.root.join("missing.so"), &env.root.join("gone.so")),
+        ],
+        ..Default::default()
+    };
+
+    let result = executor::execute_with_paths(&plan, &env.config, &env.paths, None);
+    let mounted = fs::read_to_string(&target);
+    let _ = unmount(&target, UnmountFlags::DETACH);
+
+    let result = result.expect("a failed APEX bind does not fail the run");
+    assert_eq!(mounted.unwrap(), "module");
+    let entries: Vec<_> = result
+        .journal
+        .entries
+        .iter()
+        .map(|e| (e.target.as_path(), e.method))
+        .collect();
+    assert_eq!(entries, [(target.as_path(), MountMethod::Bind)]);
+}
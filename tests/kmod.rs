@@ -0,0 +1,155 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::{fs, path::Path};
+
+use common::TestEnv;
+use meta_hybrid::{
+    core::{inventory::kmod, ops::planner},
+    utils,
+};
+
+/// A minimal ELF relocatable with a `.shstrtab` and a `.modinfo` section.
+fn elf(elf64: bool, big_endian: bool, modinfo: &[u8]) -> Vec<u8> {
+    let put = |out: &mut Vec<u8>, value: u64, width: usize| {
+        let bytes = value.to_le_bytes();
+        let mut field = bytes[..width].to_vec();
+        if big_endian {
+            field.reverse();
+        }
+        out.extend(field);
+    };
+    let word = if elf64 { 8 } else { 4 };
+    let header_len = if elf64 { 64 } else { 52 };
+    let shentsize = if elf64 { 64 } else { 40 };
+
+    let names = b"\0.shstrtab\0.modinfo\0";
+    let names_at = header_len as u64;
+    let modinfo_at = names_at + names.len() as u64;
+    let shoff = modinfo_at + modinfo.len() as u64;
+
+    let mut out = b"\x7fELF".to_vec();
+    out.extend([if elf64 { 2 } else { 1 }, if big_endian { 2 } else { 1 }, 1]);
+    out.resize(16, 0);
+    put(&mut out, 1, 2); // e_type: relocatable
+    put(&mut out, 0, 2); // e_machine
+    put(&mut out, 1, 4); // e_version
+    put(&mut out, 0, word); // e_entry
+    put(&mut out, 0, word); // e_phoff
+    put(&mut out, shoff, word);
+    put(&mut out, 0, 4); // e_flags
+    put(&mut out, header_len as u64, 2);
+    put(&mut out, 0, 2); // e_phentsize
+    put(&mut out, 0, 2); // e_phnum
+    put(&mut out, shentsize as u64, 2);
+    put(&mut out, 3, 2); // e_shnum
+    put(&mut out, 1, 2); // e_shstrndx
+    assert_eq!(out.len(), header_len);
+
+    out.extend(names);
+    out.extend(modinfo);
+
+    for (name, offset, size) in [
+        (0, 0, 0),
+        (1, names_at, names.len() as u64),
+        (11, modinfo_at, modinfo.len() as u64),
+    ] {
+        let start = out.len();
+        put(&mut out, name, 4);
+        put(&mut out, 0, 4); // sh_type
+        put(&mut out, 0, word); // sh_flags
+        put(&mut out, 0, word); // sh_addr
+        put(&mut out, offset, word);
+        put(&mut out, size, word);
+        out.resize(start + shentsize, 0);
+    }
+    out
+}
+
+const MODINFO: &[u8] =
+    b"license=GPL\0vermagic=5.15.148-android14-11 SMP preempt mod_unload modversions aarch64\0name=wlan\0";
+
+#[test]
+fn vermagic_is_read_from_every_elf_flavour() {
+    let expected = "5.15.148-android14-11 SMP preempt mod_unload modversions aarch64";
+    for (elf64, big_endian) in [(true, false), (true, true), (false, false), (false, true)] {
+        assert_eq!(
+            kmod::vermagic(&elf(elf64, big_endian, MODINFO)).as_deref(),
+            Some(expected),
+            "elf64={} big_endian={}",
+            elf64,
+            big_endian
+        );
+    }
+
+    assert_eq!(kmod::vermagic(&elf(true, false, b"license=GPL\0")), None);
+    assert_eq!(kmod::vermagic(b"not an elf"), None);
+    let mut truncated = elf(true, false, MODINFO);
+    truncated.truncate(80);
+    assert_eq!(kmod::vermagic(&truncated), None);
+}
+
+#[test]
+fn only_modules_for_another_release_are_reported() {
+    let env = TestEnv::new();
+    let module = env.module("wifi");
+    let dir = module.dir.join("vendor_dlkm/lib/modules");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("wlan.ko"), elf(true, false, MODINFO)).unwrap();
+    fs::write(
+        dir.join("bt.ko"),
+        elf(true, false, b"vermagic=6.1.57-android14 SMP\0"),
+    )
+    .unwrap();
+    fs::write(dir.join("broken.ko"), b"garbage").unwrap();
+    fs::write(dir.join("modules.load"), "wlan.ko\n").unwrap();
+
+    let found = kmod::check(&module.dir, "6.1.57-android14");
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].relative, "vendor_dlkm/lib/modules/wlan.ko");
+    assert!(found[0].vermagic.starts_with("5.15.148-android14-11 "));
+
+    let found = kmod::check(&module.dir, "5.15.148-android14-11");
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].relative, "vendor_dlkm/lib/modules/bt.ko");
+}
+
+#[test]
+fn dlkm_partitions_are_planned_only_when_mounted() {
+    let mut env = TestEnv::new();
+    env.partition("system_dlkm", false);
+    env.partition("vendor_dlkm", true);
+    env.module("kmods")
+        .file("system_dlkm/lib/modules/a.ko", "a")
+        .file("vendor_dlkm/lib/modules/b.ko", "b");
+
+    assert_eq!(
+        planner::mounted_dlkm_partitions(&env.probe),
+        ["vendor_dlkm"]
+    );
+
+    let plan = env.plan();
+    assert!(plan.extra_partitions.contains(&"vendor_dlkm".to_string()));
+    assert!(!plan.extra_partitions.contains(&"system_dlkm".to_string()));
+    let targets: Vec<&str> = plan
+        .overlay_ops
+        .iter()
+        .map(|op| op.target.as_str())
+        .collect();
+    assert!(targets.contains(&"/vendor_dlkm"));
+    assert!(!targets.contains(&"/system_dlkm"));
+}
+
+#[test]
+fn dlkm_paths_get_their_own_context() {
+    assert_eq!(
+        utils::expected_system_context(Path::new("system_dlkm/lib/modules/a.ko")),
+        "u:object_r:system_dlkm_file:s0"
+    );
+    assert_eq!(
+        utils::expected_system_context(Path::new("vendor_dlkm/lib/modules/b.ko")),
+        "u:object_r:vendor_dlkm_file:s0"
+    );
+}
@@ -0,0 +1,76 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::fs;
+
+use common::TestEnv;
+use meta_hybrid::core::last_good::LastGoodPlan;
+
+fn ids(modules: &[meta_hybrid::core::inventory::Module]) -> Vec<String> {
+    let mut ids: Vec<String> = modules.iter().map(|m| m.id.clone()).collect();
+    ids.sort();
+    ids
+}
+
+#[test]
+fn replay_keeps_only_unchanged_recorded_modules() {
+    let env = TestEnv::new();
+    env.module("alpha").file("system/etc/a.conf", "a");
+    let beta = env.module("beta").file("system/etc/b.conf", "b");
+    let gone = env.module("gone").file("system/etc/g.conf", "g");
+
+    let modules = env.scan();
+    let plan = env.plan();
+    let overlay = plan.overlay_module_ids.clone();
+    let record = LastGoodPlan::record(&modules, [("overlay", &overlay[..])], &plan, &env.config);
+    assert_eq!(record.modules.len(), 3);
+    assert_eq!(record.targets, ["/system/etc"]);
+
+    let file = env.paths.last_good_plan_file.clone();
+    record.save_to(&file).unwrap();
+    let record = LastGoodPlan::load_from(&file).unwrap();
+    assert_eq!(record.modules["alpha"].mode, "overlay");
+
+    // Updated, newly installed and uninstalled modules.
+    let prop = beta.dir.join("module.prop");
+    let updated = fs::read_to_string(&prop)
+        .unwrap()
+        .replace("version=1.0", "version=2.0");
+    fs::write(&prop, updated).unwrap();
+    env.module("newcomer").file("system/etc/n.conf", "n");
+    fs::remove_dir_all(&gone.dir).unwrap();
+
+    let kept = record.restrict(env.scan());
+    assert_eq!(ids(&kept), ["alpha"]);
+}
+
+#[test]
+fn replay_drops_targets_the_record_never_mounted() {
+    let env = TestEnv::new();
+    env.module("alpha").file("system/etc/a.conf", "a");
+
+    let modules = env.scan();
+    let plan = env.plan();
+    let overlay = plan.overlay_module_ids.clone();
+    let record = LastGoodPlan::record(&modules, [("overlay", &overlay[..])], &plan, &env.config);
+
+    env.module("alpha").file("vendor/etc/v.conf", "v");
+    let mut plan = env.plan();
+    assert_eq!(plan.overlay_ops.len(), 2);
+
+    record.restrict_plan(&mut plan);
+    let targets: Vec<&str> = plan
+        .overlay_ops
+        .iter()
+        .map(|op| op.target.as_str())
+        .collect();
+    assert_eq!(targets, ["/system/etc"]);
+}
+
+#[test]
+fn missing_record_is_an_error() {
+    let env = TestEnv::new();
+    assert!(LastGoodPlan::load_from(&env.paths.last_good_plan_file).is_err());
+}
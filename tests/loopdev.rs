@@ -0,0 +1,90 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    fs::{self, File},
+};
+
+use meta_hybrid::sys::loopdev::{self, LoopBackend, LoopOptions};
+use nix::errno::Errno;
+use tempfile::TempDir;
+
+/// Hands out loop0, loop1, ... and fails `configure` with the queued
+/// errors before succeeding.
+#[derive(Default)]
+struct FlakyLoops {
+    next: Cell<u32>,
+    failures: RefCell<VecDeque<Errno>>,
+    configured: RefCell<Vec<u32>>,
+}
+
+impl FlakyLoops {
+    fn failing(errors: &[Errno]) -> Self {
+        Self {
+            failures: RefCell::new(errors.iter().copied().collect()),
+            ..Default::default()
+        }
+    }
+}
+
+impl LoopBackend for FlakyLoops {
+    fn get_free(&self) -> nix::Result<u32> {
+        let number = self.next.get();
+        self.next.set(number + 1);
+        Ok(number)
+    }
+
+    fn configure(&self, number: u32, backing: &File, _options: &LoopOptions) -> nix::Result<File> {
+        self.configured.borrow_mut().push(number);
+        match self.failures.borrow_mut().pop_front() {
+            Some(e) => Err(e),
+            None => Ok(backing.try_clone().expect("clone backing file")),
+        }
+    }
+}
+
+fn image(dir: &TempDir) -> std::path::PathBuf {
+    let path = dir.path().join("modules.img");
+    fs::write(&path, [0u8; 512]).unwrap();
+    path
+}
+
+#[test]
+fn a_device_taken_in_between_is_retried_on_the_next() {
+    let dir = TempDir::new().unwrap();
+    let path = image(&dir);
+    let loops = FlakyLoops::failing(&[Errno::EBUSY, Errno::EEXIST]);
+
+    let device = loopdev::attach_with(&loops, &path, &LoopOptions::default()).unwrap();
+    assert_eq!(device.number, 2);
+    assert_eq!(device.name(), "loop2");
+    assert_eq!(device.backing_file, path);
+    assert_eq!(*loops.configured.borrow(), [0, 1, 2]);
+}
+
+#[test]
+fn retries_stop_after_the_configured_attempts() {
+    let dir = TempDir::new().unwrap();
+    let path = image(&dir);
+    let loops = FlakyLoops::failing(&[Errno::EBUSY; 5]);
+    let options = LoopOptions {
+        attempts: 3,
+        ..Default::default()
+    };
+
+    let err = loopdev::attach_with(&loops, &path, &options).unwrap_err();
+    assert_eq!(err.downcast_ref::<Errno>(), Some(&Errno::EBUSY));
+    assert_eq!(loops.configured.borrow().len(), 3);
+}
+
+#[test]
+fn other_errors_are_not_retried() {
+    let dir = TempDir::new().unwrap();
+    let path = image(&dir);
+    let loops = FlakyLoops::failing(&[Errno::EACCES]);
+
+    assert!(loopdev::attach_with(&loops, &path, &LoopOptions::default()).is_err());
+    assert_eq!(*loops.configured.borrow(), [0]);
+}
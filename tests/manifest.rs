@@ -0,0 +1,115 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::{fs, path::PathBuf};
+
+use common::TestEnv;
+use meta_hybrid::core::inventory::{
+    manifest::{self, ManifestStatus},
+    validate,
+};
+
+fn manifest(dir: &std::path::Path, expects: &[&str]) {
+    let list: Vec<String> = expects.iter().map(|p| format!("\"{}\"", p)).collect();
+    fs::write(
+        dir.join("meta_hybrid_manifest.toml"),
+        format!("expects = [{}]\n", list.join(", ")),
+    )
+    .unwrap();
+}
+
+#[test]
+fn linked_partitions_match_either_layout() {
+    assert_eq!(
+        manifest::candidates("/vendor/lib64/libbar.so"),
+        [
+            PathBuf::from("vendor/lib64/libbar.so"),
+            PathBuf::from("system/vendor/lib64/libbar.so")
+        ]
+    );
+    assert_eq!(
+        manifest::candidates("/system/product/app/x.apk"),
+        [
+            PathBuf::from("system/product/app/x.apk"),
+            PathBuf::from("product/app/x.apk")
+        ]
+    );
+    assert_eq!(
+        manifest::candidates("/system/app/Foo/Foo.apk"),
+        [PathBuf::from("system/app/Foo/Foo.apk")]
+    );
+
+    let env = TestEnv::new();
+    let alpha = env
+        .module("alpha")
+        .file("system/app/Foo/Foo.apk", "a")
+        .file("system/vendor/lib64/libbar.so", "b");
+    manifest(
+        &alpha.dir,
+        &["/system/app/Foo/Foo.apk", "/vendor/lib64/libbar.so"],
+    );
+
+    let modules = env.scan();
+    assert_eq!(modules[0].manifest.status, ManifestStatus::Ok);
+    assert!(modules[0].manifest.issues.is_empty());
+}
+
+#[test]
+fn double_nesting_is_reported_with_a_fix() {
+    let env = TestEnv::new();
+    let alpha = env
+        .module("alpha")
+        .file("system/system/app/Foo/Foo.apk", "a")
+        .file("vendor/etc/extra.conf", "x");
+    manifest(&alpha.dir, &["/system/app/Foo/Foo.apk"]);
+    env.module("plain").file("system/etc/p.conf", "p");
+
+    let modules = env.scan();
+    let alpha = modules.iter().find(|m| m.id == "alpha").unwrap();
+    let plain = modules.iter().find(|m| m.id == "plain").unwrap();
+    assert_eq!(alpha.manifest.status, ManifestStatus::Mismatch);
+    assert_eq!(plain.manifest.status, ManifestStatus::Absent);
+
+    let issues = validate::diagnose(&modules);
+    let messages: Vec<&str> = issues
+        .iter()
+        .filter(|i| i.context == "alpha" && i.message.starts_with("Manifest: "))
+        .map(|i| i.message.as_str())
+        .collect();
+    assert!(messages.iter().any(|m| m.contains(
+        "is at system/system/app/Foo/Foo.apk; move the contents of system/system/ up into system/"
+    )));
+    assert!(
+        messages
+            .iter()
+            .any(|m| m.contains("system/system/ is nested twice"))
+    );
+    assert!(messages.iter().any(|m| m.contains("ships vendor/")));
+    assert_eq!(messages.len(), 3);
+    assert!(
+        !issues
+            .iter()
+            .any(|i| i.context == "plain" && i.message.starts_with("Manifest"))
+    );
+}
+
+#[test]
+fn missing_paths_and_bad_manifests_are_mismatches() {
+    let env = TestEnv::new();
+    let alpha = env.module("alpha").file("system/etc/a.conf", "a");
+    manifest(&alpha.dir, &["/system/etc/a.conf", "/system/etc/b.conf"]);
+    let beta = env.module("beta").file("system/etc/b.conf", "b");
+    fs::write(beta.dir.join("meta_hybrid_manifest.toml"), "expect = []\n").unwrap();
+
+    let modules = env.scan();
+    for module in &modules {
+        assert_eq!(module.manifest.status, ManifestStatus::Mismatch);
+        assert_eq!(module.manifest.issues.len(), 1);
+    }
+    let alpha = modules.iter().find(|m| m.id == "alpha").unwrap();
+    assert!(alpha.manifest.issues[0].contains("expected /system/etc/b.conf is missing"));
+    let beta = modules.iter().find(|m| m.id == "beta").unwrap();
+    assert!(beta.manifest.issues[0].contains("is invalid"));
+}
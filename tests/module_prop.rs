@@ -0,0 +1,85 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fs;
+
+use meta_hybrid::core::inventory::model::{self as modules, PropStatus, set_prop_values};
+use tempfile::TempDir;
+
+fn status() -> PropStatus {
+    PropStatus {
+        storage_mode: "tmpfs".to_string(),
+        overlay_count: 5,
+        magic_count: 2,
+        bind_count: 0,
+        usage_percent: 43,
+        degraded: false,
+        safe_mode: false,
+        headline: None,
+    }
+}
+
+#[test]
+fn comments_custom_keys_and_order_survive() {
+    let prop = b"# installed by the zip\nid=meta-hybrid\ndescription=old\nupdateJson=https://x/y.json\ncustom.key=v\n";
+    let out = set_prop_values(prop, &[("description", "new")]);
+    assert_eq!(
+        out,
+        b"# installed by the zip\nid=meta-hybrid\ndescription=new\nupdateJson=https://x/y.json\ncustom.key=v\n"
+    );
+}
+
+#[test]
+fn crlf_endings_are_kept() {
+    let prop = b"id=meta-hybrid\r\ndescription=old\r\nversion=1\r\n";
+    let out = set_prop_values(prop, &[("description", "new"), ("status", "ok")]);
+    assert_eq!(
+        out,
+        b"id=meta-hybrid\r\ndescription=new\r\nversion=1\r\nstatus=ok\r\n"
+    );
+}
+
+#[test]
+fn missing_description_is_appended() {
+    let out = set_prop_values(b"id=meta-hybrid\nversion=1", &[("description", "new")]);
+    assert_eq!(out, b"id=meta-hybrid\nversion=1\ndescription=new\n");
+
+    // Keys sharing a prefix are left alone, as are lines that are not UTF-8.
+    let out = set_prop_values(
+        b"descriptionX=keep\n\xff\xfe=raw\n",
+        &[("description", "d")],
+    );
+    assert_eq!(out, b"descriptionX=keep\n\xff\xfe=raw\ndescription=d\n");
+}
+
+#[test]
+fn status_prop_is_machine_readable() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("module.prop");
+    fs::write(&path, "id=meta-hybrid\ndescription=old\n").unwrap();
+
+    modules::update_status_prop(&path, &status());
+    let content = fs::read_to_string(&path).unwrap();
+    assert!(content.starts_with("id=meta-hybrid\ndescription="));
+    assert!(content.contains("| Storage: 43%"));
+    assert!(content.ends_with("status=ok,overlay=5,magic=2,bind=0,storage=tmpfs,usage=43%\n"));
+
+    let degraded = PropStatus {
+        degraded: true,
+        headline: Some("7 mounted, 1 fallback, 2 warnings".into()),
+        ..status()
+    };
+    modules::update_description(&path, &degraded);
+    let content = fs::read_to_string(&path).unwrap();
+    assert!(content.contains("⚠️ Degraded | 7 mounted, 1 fallback, 2 warnings\n"));
+    assert_eq!(content.lines().count(), 3);
+    assert!(degraded.status().starts_with("degraded,"));
+    assert!(
+        PropStatus {
+            safe_mode: true,
+            ..degraded
+        }
+        .status()
+        .starts_with("safe_mode,")
+    );
+}
@@ -0,0 +1,129 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::{
+    fs,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+use common::TestEnv;
+use meta_hybrid::core::ops::{
+    mount_alias::{MountIdentity, MountTable},
+    planner::{DiagnosticLevel, MountPlan},
+};
+
+/// `/odm` bind mounted from the `odm` directory of the vendor filesystem.
+const ODM_BIND: &str = "\
+20 0 253:0 / / ro,relatime - ext4 /dev/block/dm-0 ro
+21 20 253:1 / /vendor ro,relatime shared:2 - ext4 /dev/block/dm-1 ro
+22 20 253:1 /odm /odm ro,relatime shared:2 master:3 - ext4 /dev/block/dm-1 ro
+";
+
+/// alpha ships `odm/etc/a.conf`, beta `vendor/odm/etc/a.conf`.
+fn odm_modules(env: &TestEnv) {
+    fs::create_dir_all(env.root.join("vendor/odm/etc")).unwrap();
+    env.module("alpha").file("odm/etc/a.conf", "alpha");
+    env.module("beta").file("vendor/odm/etc/a.conf", "beta");
+}
+
+fn targets(plan: &MountPlan) -> Vec<&str> {
+    plan.overlay_ops
+        .iter()
+        .map(|op| op.target.as_str())
+        .collect()
+}
+
+fn identity(device: &str, path: &str) -> Option<MountIdentity> {
+    Some(MountIdentity {
+        device: device.to_string(),
+        path: PathBuf::from(path),
+    })
+}
+
+#[test]
+fn mountinfo_resolves_paths_to_their_subtree() {
+    let text = format!(
+        "{}30 21 0:40 / /vendor rw - overlay overlay rw,lowerdir=/a\n\
+         31 20 0:41 / /mnt/with\\040space rw - tmpfs tmpfs rw\n\
+         malformed line\n",
+        ODM_BIND
+    );
+    let table = MountTable::parse(&text);
+    assert_eq!(table.entries().len(), 5);
+    assert_eq!(table.entries()[4].mount_point, Path::new("/mnt/with space"));
+
+    // The overlay on /vendor does not hide the stock mount below it.
+    assert_eq!(
+        table.identity(Path::new("/vendor/odm/etc")),
+        identity("253:1", "/odm/etc")
+    );
+    assert_eq!(
+        table.identity(Path::new("/odm/etc")),
+        identity("253:1", "/odm/etc")
+    );
+    assert_eq!(
+        table.identity(Path::new("/system/etc")),
+        identity("253:0", "/system/etc")
+    );
+}
+
+#[test]
+fn bind_mounted_targets_collapse_into_one_stack() {
+    let mut env = TestEnv::new();
+    env.partition("odm", true);
+    fs::create_dir_all(env.root.join("odm/etc")).unwrap();
+    odm_modules(&env);
+
+    // Without mountinfo both spellings get an overlay of their own.
+    let plan = env.plan();
+    assert!(targets(&plan).contains(&"/odm/etc"));
+    assert!(env.analyze(&plan).conflicts.is_empty());
+
+    env.probe.mountinfo = Some(ODM_BIND.to_string());
+    let plan = env.plan();
+    assert!(!targets(&plan).contains(&"/odm/etc"));
+    let op = plan
+        .overlay_ops
+        .iter()
+        .find(|op| op.target == "/vendor/odm/etc")
+        .expect("canonical target");
+    let modules = &env.config.moduledir;
+    assert_eq!(
+        op.lowerdirs,
+        [
+            modules.join("beta/vendor/odm/etc"),
+            modules.join("alpha/odm/etc")
+        ]
+    );
+    assert_eq!(plan.target_aliases.len(), 1);
+    assert_eq!(plan.target_aliases[0].alias, "/odm/etc");
+    assert_eq!(plan.target_aliases[0].modules, ["alpha"]);
+
+    let report = env.analyze(&plan);
+    assert_eq!(report.conflicts.len(), 1);
+    assert_eq!(report.conflicts[0].contending_modules, ["beta", "alpha"]);
+    assert!(report.diagnostics.iter().any(|d| {
+        matches!(d.level, DiagnosticLevel::Warning)
+            && d.message
+                .starts_with("/odm/etc is a bind mount of /vendor/odm/etc")
+    }));
+}
+
+#[test]
+fn symlinked_targets_already_share_a_stack() {
+    let env = TestEnv::new();
+    symlink("/vendor/odm", env.root.join("odm")).unwrap();
+    odm_modules(&env);
+
+    let plan = env.plan();
+    let odm: Vec<_> = targets(&plan)
+        .into_iter()
+        .filter(|t| t.contains("odm"))
+        .collect();
+    assert_eq!(odm, ["/vendor/odm/etc"]);
+    assert!(plan.target_aliases.is_empty());
+    assert_eq!(env.analyze(&plan).conflicts.len(), 1);
+}
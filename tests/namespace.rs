@@ -0,0 +1,59 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::fs;
+
+use common::TestEnv;
+use meta_hybrid::{
+    conf::config::NamespaceMode,
+    core::state::RuntimeState,
+    sys::namespace::{self, NamespaceReport},
+};
+
+#[test]
+fn older_states_read_as_global() {
+    let env = TestEnv::new();
+    let state_file = &env.paths.state_file;
+    fs::create_dir_all(state_file.parent().unwrap()).unwrap();
+    fs::write(
+        state_file,
+        r#"{"timestamp":1,"pid":1,"storage_mode":"tmpfs","mount_point":"/mnt","overlay_modules":[],"magic_modules":[]}"#,
+    )
+    .unwrap();
+
+    let state = RuntimeState::load_from(state_file).unwrap();
+    assert_eq!(state.namespace, NamespaceReport::default());
+    assert_eq!(state.namespace.mode, NamespaceMode::Global);
+}
+
+#[test]
+fn clone_report_round_trips() {
+    let env = TestEnv::new();
+    let state_file = &env.paths.state_file;
+    let pin = env.paths.mount_ns_dir.join("mnt");
+    fs::create_dir_all(state_file.parent().unwrap()).unwrap();
+
+    let state = RuntimeState {
+        namespace: NamespaceReport {
+            mode: NamespaceMode::Clone,
+            ns_path: Some(pin.clone()),
+            propagated: true,
+        },
+        ..Default::default()
+    };
+    state.save_to(state_file).unwrap();
+
+    let loaded = RuntimeState::load_from(state_file).unwrap();
+    assert_eq!(loaded.namespace.mode, NamespaceMode::Clone);
+    assert_eq!(loaded.namespace.ns_path, Some(pin));
+    assert!(loaded.namespace.propagated);
+}
+
+#[test]
+fn a_missing_pin_cannot_be_entered() {
+    let env = TestEnv::new();
+    let err = namespace::mountinfo_in(&env.paths.mount_ns_dir.join("mnt")).unwrap_err();
+    assert!(format!("{:#}", err).contains("Failed to open"));
+}
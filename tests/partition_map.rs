@@ -0,0 +1,161 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+use common::TestEnv;
+use meta_hybrid::{
+    core::ops::{
+        partition_map::{self, PartitionMap},
+        planner::{DiagnosticLevel, MountPlan},
+    },
+    mount::{magic_mount, node::Node},
+};
+
+/// alpha ships `etc/a.conf` under both spellings of vendor.
+fn both_spellings(env: &TestEnv) {
+    env.module("alpha")
+        .file("vendor/etc/a.conf", "top")
+        .file("system/vendor/etc/a.conf", "nested")
+        .file("system/vendor/etc/b.conf", "nested")
+        .file("system/etc/c.conf", "c");
+}
+
+fn lowerdirs(plan: &MountPlan, target: &str) -> Vec<PathBuf> {
+    plan.overlay_ops
+        .iter()
+        .find(|op| op.target == target)
+        .map(|op| op.lowerdirs.clone())
+        .unwrap_or_default()
+}
+
+fn magic_tree(env: &TestEnv, map: &PartitionMap) -> Node {
+    magic_mount::collect_module_files(
+        &env.config.moduledir,
+        &[],
+        &["alpha".to_string()],
+        &HashMap::new(),
+        &HashMap::new(),
+        &[],
+        map,
+    )
+    .unwrap()
+    .expect("alpha has files")
+}
+
+fn child<'a>(node: &'a Node, path: &str) -> &'a Node {
+    Path::new(path)
+        .iter()
+        .fold(node, |node, name| &node.children[name])
+}
+
+#[test]
+fn real_vendor_merges_both_spellings_at_the_root() {
+    let env = TestEnv::new();
+    both_spellings(&env);
+    let alpha = env.config.moduledir.join("alpha");
+
+    let map = PartitionMap::resolve(&[], &env.probe);
+    assert_eq!(map.target("vendor"), Some(Path::new("/vendor")));
+    assert!(map.is_merged("vendor"));
+    assert!(map.is_standalone("vendor"));
+
+    let plan = env.plan();
+    assert_eq!(
+        lowerdirs(&plan, "/vendor/etc"),
+        [alpha.join("vendor/etc"), alpha.join("system/vendor/etc")]
+    );
+    assert!(lowerdirs(&plan, "/system/vendor/etc").is_empty());
+
+    let root = magic_tree(&env, &map);
+    assert!(
+        !root.children[OsStr::new("system")]
+            .children
+            .contains_key(OsStr::new("vendor"))
+    );
+    let etc = child(&root, "vendor/etc");
+    assert_eq!(
+        etc.children[OsStr::new("a.conf")].module_path,
+        Some(alpha.join("vendor/etc/a.conf"))
+    );
+    assert!(etc.children.contains_key(OsStr::new("b.conf")));
+}
+
+#[test]
+fn symlinked_vendor_merges_both_spellings_below_system() {
+    let env = TestEnv::a_only();
+    both_spellings(&env);
+    let alpha = env.config.moduledir.join("alpha");
+
+    let map = PartitionMap::resolve(&[], &env.probe);
+    assert_eq!(map.target("vendor"), Some(Path::new("/system/vendor")));
+    assert!(map.is_merged("vendor"));
+    assert!(!map.is_standalone("vendor"));
+
+    let plan = env.plan();
+    assert_eq!(
+        lowerdirs(&plan, "/system/vendor/etc"),
+        [alpha.join("vendor/etc"), alpha.join("system/vendor/etc")]
+    );
+    assert!(lowerdirs(&plan, "/vendor/etc").is_empty());
+
+    let root = magic_tree(&env, &map);
+    assert!(!root.children.contains_key(OsStr::new("vendor")));
+    let etc = child(&root, "system/vendor/etc");
+    assert_eq!(
+        etc.children[OsStr::new("a.conf")].module_path,
+        Some(alpha.join("vendor/etc/a.conf"))
+    );
+    assert!(etc.children.contains_key(OsStr::new("b.conf")));
+}
+
+#[test]
+fn conflicting_spellings_are_diagnosed_on_both_topologies() {
+    for env in [TestEnv::new(), TestEnv::a_only()] {
+        both_spellings(&env);
+        env.module("beta")
+            .file("vendor/etc/d.conf", "d")
+            .file("system/vendor/etc/e.conf", "e");
+
+        let map = PartitionMap::resolve(&[], &env.probe);
+        let alpha = env.config.moduledir.join("alpha");
+        assert_eq!(
+            partition_map::spelling_conflicts(&alpha, &map),
+            [PathBuf::from("vendor/etc/a.conf")]
+        );
+
+        let issues = partition_map::diagnose(&env.scan(), &env.probe);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].context, "alpha");
+        assert!(matches!(issues[0].level, DiagnosticLevel::Warning));
+    }
+}
+
+#[test]
+fn a_separate_system_vendor_is_not_an_alias() {
+    let env = TestEnv::new();
+    let root = &env.root;
+    std::fs::remove_file(root.join("system/vendor")).unwrap();
+    std::fs::create_dir_all(root.join("system/vendor/etc")).unwrap();
+    both_spellings(&env);
+
+    let map = PartitionMap::resolve(&[], &env.probe);
+    assert!(!map.is_merged("vendor"));
+    assert!(
+        partition_map::spelling_conflicts(&env.config.moduledir.join("alpha"), &map).is_empty()
+    );
+
+    let plan = env.plan();
+    let alpha = env.config.moduledir.join("alpha");
+    assert_eq!(lowerdirs(&plan, "/vendor/etc"), [alpha.join("vendor/etc")]);
+    assert_eq!(
+        lowerdirs(&plan, "/system/vendor/etc"),
+        [alpha.join("system/vendor/etc")]
+    );
+}
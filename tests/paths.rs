@@ -0,0 +1,124 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! File names that are not plain UTF-8 or that clash with mount option
+//! syntax.
+
+mod common;
+
+use std::{
+    ffi::OsStr,
+    fs,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use common::TestEnv;
+use meta_hybrid::{
+    core::ops::planner::DiagnosticLevel,
+    mount::{node::Node, overlayfs::overlayfs::escape_lowerdir},
+};
+
+fn raw(bytes: &[u8]) -> &Path {
+    Path::new(OsStr::from_bytes(bytes))
+}
+
+fn write(path: PathBuf, contents: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+#[test]
+fn lowerdir_escapes_separators() {
+    assert_eq!(escape_lowerdir("/system/fonts"), "/system/fonts");
+    assert_eq!(escape_lowerdir("/system/a:b"), "/system/a\\:b");
+    assert_eq!(escape_lowerdir("/system/a\\b"), "/system/a\\\\b");
+    // Commas only matter to the legacy mount data and are escaped there.
+    assert_eq!(escape_lowerdir("/system/a,b"), "/system/a,b");
+}
+
+#[test]
+fn targets_with_colons_and_commas_stay_on_overlay() {
+    let env = TestEnv::new();
+    fs::create_dir_all(env.root.join("system/odd:dir,v2")).unwrap();
+    env.module("alpha").file("system/odd:dir,v2/font.ttf", "a");
+
+    let plan = env.plan();
+
+    assert_eq!(plan.overlay_module_ids, ["alpha"]);
+    assert!(plan.demoted.is_empty());
+    let op = &plan.overlay_ops[0];
+    assert_eq!(op.target, "/system/odd:dir,v2");
+    assert_eq!(escape_lowerdir(&op.target), "/system/odd\\:dir,v2");
+}
+
+#[test]
+fn non_utf8_targets_fall_back_to_magic_with_a_diagnostic() {
+    let env = TestEnv::new();
+    fs::create_dir_all(env.root.join("system").join(raw(b"fonts\xe9"))).unwrap();
+    let alpha = env.module("alpha").file("system/etc/a.conf", "a");
+    write(
+        alpha
+            .dir
+            .join("system")
+            .join(raw(b"fonts\xe9"))
+            .join("x.ttf"),
+        "x",
+    );
+
+    let plan = env.plan();
+
+    assert!(plan.overlay_module_ids.is_empty());
+    assert_eq!(plan.magic_module_ids, ["alpha"]);
+    assert!(plan.overlay_ops.is_empty());
+
+    let report = env.analyze(&plan);
+    assert!(report.diagnostics.iter().any(|d| {
+        matches!(d.level, DiagnosticLevel::Warning)
+            && d.context == "alpha"
+            && d.message.contains("not valid UTF-8")
+    }));
+}
+
+#[test]
+fn names_that_lossy_decode_alike_do_not_collide() {
+    let env = TestEnv::new();
+    let alpha = env.module("alpha");
+    let beta = env.module("beta");
+    write(alpha.dir.join("system/etc").join(raw(b"\xe9.ttf")), "a");
+    write(beta.dir.join("system/etc").join(raw(b"\xfe.ttf")), "b");
+
+    let plan = env.plan();
+    assert!(env.analyze(&plan).conflicts.is_empty());
+
+    let mut root = Node::new_root("system");
+    assert!(
+        root.collect_module_files(alpha.dir.join("system"), "alpha", &|_| false)
+            .unwrap()
+    );
+    assert!(
+        root.collect_module_files(beta.dir.join("system"), "beta", &|_| false)
+            .unwrap()
+    );
+    let etc = &root.children[OsStr::new("etc")];
+    assert_eq!(etc.children.len(), 2);
+    assert!(etc.children.contains_key(OsStr::from_bytes(b"\xe9.ttf")));
+    assert!(etc.children.contains_key(OsStr::from_bytes(b"\xfe.ttf")));
+}
+
+#[test]
+fn conflicts_keep_the_exact_relative_path() {
+    let env = TestEnv::new();
+    let alpha = env.module("alpha");
+    let beta = env.module("beta");
+    write(alpha.dir.join("system/etc").join(raw(b"\xe9.ttf")), "a");
+    write(beta.dir.join("system/etc").join(raw(b"\xe9.ttf")), "b");
+
+    let plan = env.plan();
+    let report = env.analyze(&plan);
+
+    assert_eq!(report.conflicts.len(), 1);
+    let conflict = &report.conflicts[0];
+    assert_eq!(conflict.relative_path, raw(b"\xe9.ttf"));
+    assert_eq!(conflict.relative_display, "\u{fffd}.ttf");
+}
@@ -0,0 +1,139 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::fs;
+
+use common::TestEnv;
+use meta_hybrid::{
+    conf::config::ModuleRules,
+    core::ops::plan_export::{self, LayerChange, PLAN_SCHEMA_VERSION},
+};
+
+fn two_modules(env: &TestEnv) {
+    env.module("alpha")
+        .file("system/etc/a.conf", "a")
+        .file("vendor/etc/v.conf", "v");
+    env.module("beta").file("system/etc/b.conf", "b");
+}
+
+fn layer_modules(export: &plan_export::PlanExport, partition: &str, target: &str) -> Vec<String> {
+    export.partitions[partition]
+        .iter()
+        .find(|t| t.target == target)
+        .map(|t| t.layers.iter().map(|l| l.module.clone()).collect())
+        .unwrap_or_default()
+}
+
+#[test]
+fn export_lists_layers_top_first_and_is_stable() {
+    let env = TestEnv::new();
+    two_modules(&env);
+
+    let export = env.plan().to_json();
+    assert_eq!(export.schema_version, PLAN_SCHEMA_VERSION);
+    assert_eq!(export.module_order, ["beta", "alpha"]);
+    assert_eq!(
+        layer_modules(&export, "system", "/system/etc"),
+        ["beta", "alpha"]
+    );
+    assert_eq!(layer_modules(&export, "vendor", "/vendor/etc"), ["alpha"]);
+    assert_eq!(export.modes["alpha"], ["overlay"]);
+
+    let again = serde_json::to_string(&env.plan().to_json()).unwrap();
+    assert_eq!(serde_json::to_string(&export).unwrap(), again);
+}
+
+#[test]
+fn export_counts_magic_files_per_partition() {
+    let env = TestEnv::new();
+    env.module("alpha")
+        .file("system/etc/a.conf", "a")
+        .file("system/bin/tool", "t")
+        .file("vendor/etc/v.conf", "v")
+        .mount_mode("magic");
+
+    let export = env.plan().to_json();
+    assert_eq!(export.modes["alpha"], ["magic"]);
+    let files = &export.magic["alpha"];
+    assert_eq!(files["system"], 2);
+    assert_eq!(files["vendor"], 1);
+}
+
+#[test]
+fn saved_plan_round_trips_and_matches_itself() {
+    let env = TestEnv::new();
+    two_modules(&env);
+    let export = env.plan().to_json();
+
+    let saved = env.root.join("plan.json");
+    fs::write(&saved, serde_json::to_string(&export).unwrap()).unwrap();
+    let loaded = plan_export::load(&saved).unwrap();
+    assert_eq!(loaded, export);
+    assert!(plan_export::diff(&loaded, &export).is_empty());
+
+    let mut future = serde_json::to_value(&export).unwrap();
+    future["schema_version"] = (PLAN_SCHEMA_VERSION + 1).into();
+    fs::write(&saved, future.to_string()).unwrap();
+    assert!(plan_export::load(&saved).is_err());
+}
+
+#[test]
+fn diff_reports_layer_and_mode_changes() {
+    let env = TestEnv::new();
+    two_modules(&env);
+    let baseline = env.plan().to_json();
+
+    env.module("gamma").file("system/etc/c.conf", "c");
+    fs::write(env.config.moduledir.join("beta/mount_mode"), "magic").unwrap();
+    let current = env.plan().to_json();
+
+    let diff = plan_export::diff(&baseline, &current);
+    assert_eq!(
+        diff.added_layers,
+        [LayerChange {
+            target: "/system/etc".to_string(),
+            module: "gamma".to_string(),
+        }]
+    );
+    assert_eq!(
+        diff.removed_layers,
+        [LayerChange {
+            target: "/system/etc".to_string(),
+            module: "beta".to_string(),
+        }]
+    );
+    assert!(diff.reordered.is_empty());
+
+    let beta = diff
+        .mode_changes
+        .iter()
+        .find(|c| c.module == "beta")
+        .expect("beta switched to magic");
+    assert_eq!(beta.before, ["overlay"]);
+    assert_eq!(beta.after, ["magic"]);
+    assert!(diff.mode_changes.iter().any(|c| c.module == "gamma"));
+}
+
+#[test]
+fn diff_reports_a_reordered_stack() {
+    let mut env = TestEnv::new();
+    two_modules(&env);
+    let baseline = env.plan().to_json();
+
+    env.config.rules.insert(
+        "alpha".to_string(),
+        ModuleRules {
+            order: Some(1),
+            ..Default::default()
+        },
+    );
+    let diff = plan_export::diff(&baseline, &env.plan().to_json());
+
+    assert!(diff.added_layers.is_empty() && diff.removed_layers.is_empty());
+    assert_eq!(diff.reordered.len(), 1);
+    assert_eq!(diff.reordered[0].target, "/system/etc");
+    assert_eq!(diff.reordered[0].before, ["beta", "alpha"]);
+    assert_eq!(diff.reordered[0].after, ["alpha", "beta"]);
+}
@@ -0,0 +1,715 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::path::PathBuf;
+
+use common::TestEnv;
+use meta_hybrid::{
+    conf::config::ModuleRules,
+    core::{
+        inventory::{self, Exclusion},
+        ops::{
+            conflict::ConflictSeverity,
+            foreign::{self, ForeignKind},
+            planner::{self, ConflictEntry, DiagnosticLevel, MountPlan, OverlayOperation},
+            simulate::{self, PredictedOutcome},
+        },
+    },
+};
+
+fn op_targets(plan: &MountPlan) -> Vec<&str> {
+    plan.overlay_ops
+        .iter()
+        .map(|op| op.target.as_str())
+        .collect()
+}
+
+fn lowerdir_modules(plan: &MountPlan, target: &str) -> Vec<String> {
+    plan.overlay_ops
+        .iter()
+        .find(|op| op.target == target)
+        .unwrap_or_else(|| panic!("no overlay op for {target}"))
+        .lowerdirs
+        .iter()
+        .map(|p| {
+            p.strip_prefix(env_moduledir(p))
+                .ok()
+                .and_then(|rel| rel.iter().next())
+                .map(|id| id.to_string_lossy().to_string())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// The module directory a lowerdir lives in (`.../data/adb/modules`).
+fn env_moduledir(path: &std::path::Path) -> PathBuf {
+    path.ancestors()
+        .find(|p| p.ends_with("data/adb/modules"))
+        .expect("lowerdir inside module dir")
+        .to_path_buf()
+}
+
+fn conflict<'a>(conflicts: &'a [ConflictEntry], rel: &str) -> &'a ConflictEntry {
+    conflicts
+        .iter()
+        .find(|c| c.relative_display == rel)
+        .unwrap_or_else(|| panic!("no conflict on {rel}"))
+}
+
+#[test]
+fn scan_skips_disabled_removed_and_skip_mount_modules() {
+    let env = TestEnv::new();
+    env.module("alpha").file("system/etc/a.conf", "a");
+    env.module("beta").file("system/etc/b.conf", "b");
+    env.module("sleeper")
+        .file("system/etc/c.conf", "c")
+        .disabled();
+    env.module("ghost").file("system/etc/d.conf", "d").removed();
+    env.module("static")
+        .file("system/etc/e.conf", "e")
+        .skip_mount();
+
+    let mut ids: Vec<String> = env.scan().into_iter().map(|m| m.id).collect();
+    ids.sort();
+
+    assert_eq!(ids, ["alpha", "beta"]);
+
+    let plan = env.plan();
+    assert_eq!(plan.overlay_module_ids, ["alpha", "beta"]);
+    assert_eq!(op_targets(&plan), ["/system/etc"]);
+}
+
+#[test]
+fn layers_of_modules_no_longer_enabled_are_dropped() {
+    let env = TestEnv::new();
+    env.module("alpha").file("system/etc/a.conf", "a");
+    env.module("static")
+        .file("system/etc/e.conf", "e")
+        .file("vendor/etc/v.conf", "v")
+        .skip_mount();
+    let storage = &env.config.moduledir;
+
+    // A plan made before `static` was given skip_mount.
+    let mut plan = env.plan();
+    plan.overlay_ops[0]
+        .lowerdirs
+        .push(storage.join("static/system/etc"));
+    plan.overlay_ops.push(OverlayOperation {
+        partition_name: "vendor".to_string(),
+        target: "/vendor/etc".to_string(),
+        lowerdirs: vec![storage.join("static/vendor/etc")],
+    });
+
+    planner::drop_stale_layers(&mut plan, storage, &env.scan());
+
+    assert_eq!(op_targets(&plan), ["/system/etc"]);
+    assert_eq!(lowerdir_modules(&plan, "/system/etc"), ["alpha"]);
+    assert_eq!(plan.stale_layers.len(), 2);
+    assert!(plan.stale_layers.iter().all(|s| s.module_id == "static"));
+
+    let report = env.analyze(&plan);
+    assert!(report.diagnostics.iter().any(|d| d.context == "static"
+        && matches!(d.level, DiagnosticLevel::Warning)
+        && d.message.contains("/vendor/etc")));
+}
+
+#[test]
+fn excluded_modules_leave_the_plan_and_conflicts() {
+    let mut env = TestEnv::new();
+    env.module("alpha").file("system/etc/hosts", "alpha");
+    env.module("beta").file("system/etc/hosts", "beta");
+    env.module("sleeper")
+        .file("system/etc/c.conf", "c")
+        .disabled();
+    env.module("static")
+        .file("system/etc/e.conf", "e")
+        .skip_mount();
+
+    assert_eq!(env.sift(&env.plan()).len(), 1);
+
+    env.config.exclusions.push("beta".to_string());
+
+    let plan = env.plan();
+    assert_eq!(plan.overlay_module_ids, ["alpha"]);
+    assert!(env.sift(&plan).is_empty());
+
+    let mut reasons: Vec<(String, Option<Exclusion>)> =
+        inventory::scan_all_with_paths(&env.config.moduledir, &env.config, &env.paths)
+            .unwrap()
+            .into_iter()
+            .map(|(m, excluded)| (m.id, excluded))
+            .collect();
+    reasons.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        reasons,
+        [
+            ("alpha".to_string(), None),
+            ("beta".to_string(), Some(Exclusion::Config)),
+            ("sleeper".to_string(), Some(Exclusion::DisableFile)),
+            ("static".to_string(), Some(Exclusion::SkipMount)),
+        ]
+    );
+}
+
+#[test]
+fn system_is_split_per_directory() {
+    let env = TestEnv::new();
+    env.module("alpha")
+        .file("system/etc/hosts", "a")
+        .file("system/framework/services.jar", "a");
+
+    let plan = env.plan();
+
+    assert_eq!(op_targets(&plan), ["/system/etc", "/system/framework"]);
+    assert!(
+        plan.overlay_ops
+            .iter()
+            .all(|op| op.partition_name == "system")
+    );
+}
+
+#[test]
+fn partitions_linked_from_system_resolve_to_their_mount_point() {
+    let env = TestEnv::new();
+    env.module("alpha").file("system/vendor/etc/audio.xml", "a");
+    env.module("beta").file("vendor/etc/media.xml", "b");
+
+    let plan = env.plan();
+
+    assert_eq!(op_targets(&plan), ["/vendor/etc"]);
+    assert_eq!(plan.overlay_ops[0].partition_name, "vendor");
+
+    let mut layers = lowerdir_modules(&plan, "/vendor/etc");
+    layers.sort();
+    assert_eq!(layers, ["alpha", "beta"]);
+}
+
+#[test]
+fn partitions_missing_on_the_device_are_skipped() {
+    let env = TestEnv::new();
+    env.module("alpha")
+        .file("odm/etc/odm.conf", "a")
+        .file("system/etc/a.conf", "a");
+
+    let plan = env.plan();
+
+    assert_eq!(op_targets(&plan), ["/system/etc"]);
+}
+
+#[test]
+fn overlapping_files_are_classified_by_content_and_path() {
+    let env = TestEnv::new();
+    env.module("alpha")
+        .file("system/etc/hosts", "127.0.0.1 alpha")
+        .file("system/etc/same.conf", "shared")
+        .file("system/lib64/libfoo.so", "alpha build");
+    env.module("beta")
+        .file("system/etc/hosts", "127.0.0.1 beta")
+        .file("system/etc/same.conf", "shared")
+        .file("system/lib64/libfoo.so", "beta build");
+
+    let plan = env.plan();
+    let report = env.analyze(&plan);
+
+    assert!(
+        report
+            .diagnostics
+            .iter()
+            .all(|d| !d.message.contains("does not exist")),
+        "fake root targets must be found: {:?}",
+        report.diagnostics
+    );
+
+    let hosts = conflict(&report.conflicts, "hosts");
+    assert_eq!(hosts.target, "/system/etc");
+    assert!(!hosts.identical);
+    assert_eq!(hosts.severity, ConflictSeverity::Notice);
+
+    let same = conflict(&report.conflicts, "same.conf");
+    assert!(same.identical);
+    assert_eq!(same.severity, ConflictSeverity::Benign);
+
+    let lib = conflict(&report.conflicts, "libfoo.so");
+    assert_eq!(lib.severity, ConflictSeverity::Severe);
+}
+
+#[test]
+fn winnowing_picks_the_forced_module() {
+    let mut env = TestEnv::new();
+    env.module("alpha").file("system/etc/hosts", "alpha");
+    env.module("beta").file("system/etc/hosts", "beta");
+
+    let plan = env.plan();
+    let unforced = env.sift(&plan);
+    assert_eq!(unforced.len(), 1);
+    assert!(!unforced[0].is_forced);
+    let top = lowerdir_modules(&plan, "/system/etc")[0].clone();
+    assert_eq!(unforced[0].selected, top);
+
+    let loser = if top == "alpha" { "beta" } else { "alpha" };
+    env.config.winnowing.set_rule("/system/etc/hosts", loser);
+
+    let plan = env.plan();
+    let forced = env.sift(&plan);
+    assert_eq!(forced[0].path, "/system/etc/hosts");
+    assert_eq!(forced[0].selected, loser);
+    assert!(forced[0].is_forced);
+    assert_eq!(lowerdir_modules(&plan, "/system/etc")[0], loser);
+}
+
+#[test]
+fn winnowing_rule_reorders_layers_for_the_forced_module() {
+    let mut env = TestEnv::new();
+    env.module("moduleA")
+        .file("system/framework/services.jar", "a")
+        .file("hybrid_rules.json", r#"{"order": 10}"#);
+    env.module("moduleB")
+        .file("system/framework/services.jar", "b");
+
+    let plan = env.plan();
+    assert_eq!(
+        lowerdir_modules(&plan, "/system/framework"),
+        ["moduleA", "moduleB"]
+    );
+
+    env.config
+        .winnowing
+        .set_rule("/system/framework/services.jar", "moduleB");
+    let plan = env.plan();
+    assert_eq!(
+        lowerdir_modules(&plan, "/system/framework"),
+        ["moduleB", "moduleA"]
+    );
+    let sifted = env.sift(&plan);
+    assert_eq!(sifted[0].path, "/system/framework/services.jar");
+    assert_eq!(sifted[0].selected, "moduleB");
+    assert!(sifted[0].is_forced);
+}
+
+#[test]
+fn winnowing_reports_the_layer_that_won_the_target() {
+    let mut env = TestEnv::new();
+    env.module("alpha")
+        .file("system/etc/a.conf", "alpha")
+        .file("system/etc/b.conf", "alpha");
+    env.module("beta")
+        .file("system/etc/a.conf", "beta")
+        .file("system/etc/b.conf", "beta");
+    env.config.winnowing.set_rule("/system/etc/a.conf", "beta");
+    env.config.winnowing.set_rule("/system/etc/b.conf", "alpha");
+
+    // Both rules promote for /system/etc; the later path puts alpha on top.
+    let plan = env.plan();
+    assert_eq!(lowerdir_modules(&plan, "/system/etc")[0], "alpha");
+
+    let sifted = env.sift(&plan);
+    let by_path = |path: &str| sifted.iter().find(|c| c.path == path).expect("contested");
+    let a = by_path("/system/etc/a.conf");
+    assert_eq!(a.selected, "alpha");
+    assert!(!a.is_forced);
+    assert_eq!(a.unmet_rule.as_deref(), Some("beta"));
+    let b = by_path("/system/etc/b.conf");
+    assert_eq!(b.selected, "alpha");
+    assert!(b.is_forced);
+    assert!(b.unmet_rule.is_none());
+}
+
+#[test]
+fn replace_dirs_need_magic_mount_without_overlay_xattrs() {
+    let mut env = TestEnv::new();
+    env.module("alpha").replace_dir("system/app/Browser");
+    env.module("beta").file("system/app/Other/Other.apk", "b");
+
+    let plan = env.plan();
+    assert!(plan.overlay_module_ids.contains(&"alpha".to_string()));
+
+    env.probe.overlay_xattrs = false;
+    let plan = env.plan();
+
+    assert_eq!(plan.magic_module_ids, ["alpha"]);
+    assert_eq!(plan.overlay_module_ids, ["beta"]);
+    assert_eq!(lowerdir_modules(&plan, "/system/app"), ["beta"]);
+}
+
+#[test]
+fn whiteouts_take_part_in_conflicts() {
+    let env = TestEnv::new();
+    env.module("alpha")
+        .file("system/etc/gone.conf", "still here");
+    let Some(_) = env.module("beta").whiteout("system/etc/gone.conf") else {
+        eprintln!("skipping: mknod is not permitted on this host");
+        return;
+    };
+
+    let plan = env.plan();
+    let report = env.analyze(&plan);
+
+    let gone = conflict(&report.conflicts, "gone.conf");
+    assert!(!gone.identical);
+    assert_eq!(gone.contenders.len(), 2);
+}
+
+#[test]
+fn mount_mode_file_forces_magic_mount() {
+    let env = TestEnv::new();
+    env.module("alpha")
+        .file("system/etc/a.conf", "a")
+        .mount_mode("magic");
+    env.module("beta").file("system/etc/b.conf", "b");
+
+    let plan = env.plan();
+
+    assert_eq!(plan.magic_module_ids, ["alpha"]);
+    assert_eq!(plan.overlay_module_ids, ["beta"]);
+}
+
+#[test]
+fn auto_partitions_only_accepts_real_partitions() {
+    let mut env = TestEnv::new();
+    env.partition("my_custom", true);
+    env.module("alpha")
+        .file("my_custom/etc/custom.conf", "a")
+        .file("META-INF/com/google/android/updater-script", "#MAGISK")
+        .file("not_a_partition/file", "a");
+
+    let plan = env.plan();
+    assert!(plan.extra_partitions.is_empty());
+    assert!(op_targets(&plan).is_empty());
+
+    env.config.auto_partitions = true;
+    let plan = env.plan();
+
+    assert_eq!(plan.extra_partitions, ["my_custom"]);
+    assert_eq!(op_targets(&plan), ["/my_custom"]);
+}
+
+#[test]
+fn small_modules_replacing_stock_files_are_bind_mounted() {
+    let mut env = TestEnv::new();
+    for stock in ["system/bin/sh", "vendor/etc/audio.xml"] {
+        std::fs::write(env.root.join(stock), "stock").unwrap();
+    }
+    env.module("alpha")
+        .file("system/bin/sh", "a")
+        .file("system/vendor/etc/audio.xml", "a");
+    env.module("beta")
+        .file("system/bin/sh", "b")
+        .file("system/bin/new_tool", "b");
+
+    let plan = env.plan();
+    assert!(plan.bind_ops.is_empty());
+
+    env.config.prefer_bind_for_small_modules = true;
+    let plan = env.plan();
+
+    assert!(plan.bind_module_ids.is_empty());
+    assert_eq!(plan.bind_rejected.len(), 1);
+    assert_eq!(plan.bind_rejected[0].module_id, "alpha");
+    assert_eq!(plan.bind_rejected[0].target, "/system/bin/sh");
+    assert!(
+        env.analyze(&plan)
+            .diagnostics
+            .iter()
+            .any(|d| d.context == "alpha" && d.message.contains("Bind mount optimization"))
+    );
+
+    env.module("beta").removed();
+    let plan = env.plan();
+
+    assert_eq!(plan.bind_module_ids, ["alpha"]);
+    assert!(plan.overlay_module_ids.is_empty());
+    assert!(op_targets(&plan).is_empty());
+
+    let mut targets: Vec<_> = plan
+        .bind_ops
+        .iter()
+        .map(|op| (op.partition_name.as_str(), op.target.to_string_lossy()))
+        .collect();
+    targets.sort();
+    assert_eq!(
+        targets,
+        [
+            ("system", "/system/bin/sh".into()),
+            ("vendor", "/vendor/etc/audio.xml".into())
+        ]
+    );
+}
+
+#[test]
+fn modules_adding_files_are_not_bind_mounted() {
+    let mut env = TestEnv::new();
+    env.config.prefer_bind_for_small_modules = true;
+    std::fs::write(env.root.join("system/bin/sh"), "stock").unwrap();
+    env.module("alpha")
+        .file("system/bin/sh", "a")
+        .file("system/bin/extra", "a");
+    env.module("beta")
+        .file("system/etc/1", "b")
+        .file("system/etc/2", "b")
+        .file("system/etc/3", "b")
+        .file("system/etc/4", "b")
+        .file("system/etc/5", "b")
+        .file("system/etc/6", "b");
+    for n in 1..=6 {
+        std::fs::write(env.root.join(format!("system/etc/{n}")), "stock").unwrap();
+    }
+
+    let plan = env.plan();
+
+    assert!(plan.bind_ops.is_empty());
+    assert_eq!(plan.overlay_module_ids, ["alpha", "beta"]);
+}
+
+#[test]
+fn simulation_predicts_overlay_outcomes() {
+    let mut env = TestEnv::new();
+    env.module("alpha")
+        .file("system/etc/hosts", "a")
+        .replace_dir("system/app/Browser");
+    env.module("beta").file("vendor/etc/beta.conf", "b");
+    env.module("gamma").file("product/etc/gamma.conf", "c");
+
+    let plan = env.plan();
+    let predict = |plan: &MountPlan, probe: &_| {
+        simulate::simulate_with_probe(plan, &env.config.mountsource, probe)
+            .into_iter()
+            .map(|p| (p.target.clone(), p))
+            .collect::<std::collections::HashMap<_, _>>()
+    };
+
+    let clean = predict(&plan, &env.probe);
+    assert_eq!(clean.len(), plan.overlay_ops.len());
+    assert!(
+        clean
+            .values()
+            .all(|p| p.outcome == PredictedOutcome::Overlay && p.reasons.is_empty()),
+        "{clean:?}"
+    );
+
+    env.probe.overlay_xattrs = false;
+    env.probe
+        .overlays
+        .insert(PathBuf::from("/vendor/etc"), "other-manager".to_string());
+    env.probe.overlays.insert(
+        PathBuf::from("/product/etc"),
+        env.config.mountsource.clone(),
+    );
+    std::fs::remove_dir_all(env.root.join("system/etc")).expect("remove /system/etc");
+
+    let predicted = predict(&plan, &env.probe);
+    let outcome = |target: &str| predicted[target].outcome;
+
+    assert_eq!(outcome("/system/etc"), PredictedOutcome::Skip);
+    assert_eq!(outcome("/system/app"), PredictedOutcome::FallbackMagic);
+    assert_eq!(predicted["/system/app"].modules, ["alpha"]);
+    assert_eq!(outcome("/vendor/etc"), PredictedOutcome::FallbackMagic);
+    assert!(predicted["/vendor/etc"].reasons[0].contains("other-manager"));
+    assert_eq!(outcome("/product/etc"), PredictedOutcome::Overlay);
+}
+
+#[test]
+fn foreign_overlays_and_tmpfs_on_planned_partitions_are_detected() {
+    let env = TestEnv::new();
+    env.module("alpha").file("system/etc/hosts", "a");
+    env.module("beta").file("vendor/etc/beta.conf", "b");
+    let plan = env.plan();
+
+    let mount = |point: &str, fs: &str, source: &str| {
+        (PathBuf::from(point), fs.to_string(), source.to_string())
+    };
+    let found = foreign::detect(
+        [
+            mount("/system/bin", "tmpfs", "magisk"),
+            mount("/vendor/etc", "overlay", "worker"),
+            mount("/system/etc", "overlay", "KSU"),
+            mount("/vendor/lib64", "ext4", "/dev/block/dm-1"),
+            mount("/odm/etc", "overlay", "magisk"),
+            mount("/data", "tmpfs", "tmpfs"),
+        ],
+        &foreign::plan_partitions(&plan),
+        &["KSU"],
+    );
+
+    let summary: Vec<(&str, ForeignKind)> = found
+        .iter()
+        .map(|m| (m.partition.as_str(), m.kind))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("system", ForeignKind::Magisk),
+            ("vendor", ForeignKind::Worker)
+        ]
+    );
+}
+
+#[test]
+fn skipping_a_partition_drops_its_operations() {
+    let env = TestEnv::new();
+    env.module("alpha").file("system/etc/hosts", "a");
+    env.module("beta").file("vendor/etc/beta.conf", "b");
+    env.module("gamma")
+        .file("system/bin/tool", "c")
+        .mount_mode("magic");
+    let mut plan = env.plan();
+
+    let affected = plan.drop_partitions(&["vendor".to_string()].into());
+
+    assert_eq!(affected.into_iter().collect::<Vec<_>>(), ["beta"]);
+    assert!(
+        plan.overlay_ops
+            .iter()
+            .all(|op| op.partition_name != "vendor")
+    );
+    assert_eq!(lowerdir_modules(&plan, "/system/etc"), ["alpha"]);
+    assert!(plan.exclusions["gamma"].contains("vendor"));
+}
+
+#[test]
+fn excluded_partitions_never_reach_an_operation() {
+    let mut env = TestEnv::new();
+    env.module("alpha")
+        .file("system/etc/hosts", "a")
+        .file("vendor/etc/alpha.conf", "a")
+        .file("hybrid_rules.json", r#"{"exclude_partitions": ["vendor"]}"#);
+    env.module("beta").file("vendor/etc/beta.conf", "b");
+    env.module("gamma")
+        .file("vendor/bin/tool", "c")
+        .mount_mode("magic");
+    env.config.rules.insert(
+        "gamma".to_string(),
+        ModuleRules {
+            exclude_partitions: vec!["vendor".to_string()],
+            ..Default::default()
+        },
+    );
+
+    let plan = env.plan();
+
+    for id in ["alpha", "gamma"] {
+        assert!(plan.exclusions[id].contains("vendor"));
+        let vendor = env.config.moduledir.join(id).join("vendor");
+        assert!(
+            plan.overlay_ops
+                .iter()
+                .flat_map(|op| &op.lowerdirs)
+                .all(|dir| !dir.starts_with(&vendor))
+        );
+        assert!(
+            plan.bind_ops
+                .iter()
+                .all(|op| op.module_id != id || op.partition_name != "vendor")
+        );
+    }
+    assert_eq!(lowerdir_modules(&plan, "/system/etc"), ["alpha"]);
+    assert_eq!(lowerdir_modules(&plan, "/vendor/etc"), ["beta"]);
+    assert!(!plan.magic_module_ids.contains(&"gamma".to_string()));
+}
+
+#[test]
+fn apex_files_are_left_alone_without_opt_in() {
+    let mut env = TestEnv::new();
+    env.partition("apex/com.android.art/lib64", false);
+    env.probe
+        .mount_points
+        .insert(PathBuf::from("/apex/com.android.art"));
+    std::fs::write(
+        env.root.join("apex/com.android.art/lib64/libart.so"),
+        "stock",
+    )
+    .unwrap();
+    env.module("alpha")
+        .file("apex/com.android.art/lib64/libart.so", "patched");
+
+    let plan = env.plan();
+
+    assert!(plan.apex_ops.is_empty());
+    assert!(plan.denied.is_empty());
+    assert_eq!(plan.apex_skipped.len(), 1);
+    assert_eq!(plan.apex_skipped[0].reason, "allow_apex is off");
+}
+
+#[test]
+fn apex_files_bind_over_stock_files_of_mounted_apexes() {
+    let mut env = TestEnv::new();
+    env.config.allow_apex = true;
+    env.partition("apex/com.android.art/lib64", false);
+    env.partition("apex/com.android.conscrypt", false);
+    env.probe
+        .mount_points
+        .insert(PathBuf::from("/apex/com.android.art"));
+    std::fs::write(
+        env.root.join("apex/com.android.art/lib64/libart.so"),
+        "stock",
+    )
+    .unwrap();
+    env.module("alpha")
+        .file("system/etc/hosts", "a")
+        .file("apex/com.android.art/lib64/libart.so", "patched")
+        .file("apex/com.android.art/lib64/libnew.so", "new")
+        .file("apex/com.android.conscrypt/lib64/libssl.so", "ssl");
+
+    let plan = env.plan();
+
+    let targets: Vec<_> = plan.apex_ops.iter().map(|op| op.target.clone()).collect();
+    assert_eq!(
+        targets,
+        [PathBuf::from("/apex/com.android.art/lib64/libart.so")]
+    );
+    assert_eq!(plan.apex_names, ["com.android.art"]);
+    assert!(op_targets(&plan).iter().all(|t| !t.starts_with("/apex")));
+    assert_eq!(plan.overlay_module_ids, ["alpha"]);
+
+    let mut skipped: Vec<_> = plan
+        .apex_skipped
+        .iter()
+        .map(|s| (s.target.as_str(), s.reason.as_str()))
+        .collect();
+    skipped.sort();
+    assert_eq!(
+        skipped,
+        [
+            (
+                "/apex/com.android.art/lib64/libnew.so",
+                "no stock file to bind over; APEXes cannot gain files"
+            ),
+            ("/apex/com.android.conscrypt", "not an active APEX mount"),
+        ]
+    );
+
+    let report = env.analyze(&plan);
+    assert!(
+        report
+            .diagnostics
+            .iter()
+            .any(|d| d.context == "alpha" && d.message.contains("can bootloop")),
+        "{:?}",
+        report.diagnostics
+    );
+}
+
+#[test]
+fn allow_apex_still_honours_user_deny_paths() {
+    let mut env = TestEnv::new();
+    env.config.allow_apex = true;
+    env.partition("apex/com.android.art/lib64", false);
+    env.probe
+        .mount_points
+        .insert(PathBuf::from("/apex/com.android.art"));
+    std::fs::write(
+        env.root.join("apex/com.android.art/lib64/libart.so"),
+        "stock",
+    )
+    .unwrap();
+    env.module("alpha")
+        .file("apex/com.android.art/lib64/libart.so", "patched");
+
+    for deny in ["/apex/com.android.art/lib64", "/apex"] {
+        env.config.user_deny_paths = vec![deny.to_string()];
+        let plan = env.plan();
+        assert!(plan.apex_ops.is_empty(), "{}", deny);
+        assert_eq!(plan.apex_skipped[0].reason, "listed in user_deny_paths");
+    }
+}
@@ -0,0 +1,31 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use meta_hybrid::utils::pool::{self, MAX_DEFAULT_THREADS};
+
+#[test]
+fn default_size_follows_cores_up_to_the_cap() {
+    assert_eq!(pool::resolve_threads(None, 2), 2);
+    assert_eq!(pool::resolve_threads(None, 8), MAX_DEFAULT_THREADS);
+    assert_eq!(pool::resolve_threads(Some(0), 1), 1);
+    assert_eq!(pool::resolve_threads(Some(6), 8), 6);
+}
+
+// The pool is process-wide, so this file holds a single pool test.
+#[test]
+fn pool_runs_work_on_camouflaged_threads() {
+    assert_eq!(pool::size(), 0);
+    assert!(pool::broadcast(|_| ()).is_empty());
+
+    assert_eq!(pool::init(Some(2)).expect("build pool"), 2);
+    assert_eq!(pool::size(), 2);
+    // Later calls keep the first pool.
+    assert_eq!(pool::init(Some(3)).unwrap(), 2);
+
+    let names = pool::broadcast(|_| std::thread::current().name().map(str::to_string));
+    assert_eq!(names.len(), 2);
+    for name in names.iter().flatten() {
+        assert!(name.starts_with("kworker/u"), "{name}");
+    }
+    assert!(pool::install(|| rayon::current_thread_index().is_some()));
+}
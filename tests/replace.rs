@@ -0,0 +1,121 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use common::TestEnv;
+use meta_hybrid::{
+    core::ops::{partition_map::PartitionMap, replace, sync},
+    mount::{magic_mount, node::Node},
+    utils,
+};
+
+fn magic_tree(env: &TestEnv, moduledir: &Path, ids: &[&str]) -> Node {
+    let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+    magic_mount::collect_module_files(
+        moduledir,
+        &[],
+        &ids,
+        &HashMap::new(),
+        &HashMap::new(),
+        &[],
+        &PartitionMap::resolve(&[], &env.probe),
+    )
+    .unwrap()
+    .expect("modules have files")
+}
+
+fn child<'a>(node: &'a Node, path: &str) -> &'a Node {
+    Path::new(path)
+        .iter()
+        .fold(node, |node, name| &node.children[name])
+}
+
+#[test]
+fn magic_mount_honours_marker_and_xattr_alike() {
+    let env = TestEnv::new();
+    env.module("alpha")
+        .replace_dir("system/app/Browser")
+        .file("system/app/Browser/Browser.apk", "a");
+    env.module("beta")
+        .file("system/app/Gallery/Gallery.apk", "b");
+    let gallery = env.config.moduledir.join("beta/system/app/Gallery");
+    let xattr_form = utils::set_overlay_opaque(&gallery).is_ok();
+
+    let root = magic_tree(&env, &env.config.moduledir, &["alpha", "beta"]);
+
+    let browser = child(&root, "system/app/Browser");
+    assert!(browser.replace);
+    assert!(!browser.children.contains_key(OsStr::new(".replace")));
+    assert!(browser.children.contains_key(OsStr::new("Browser.apk")));
+    if xattr_form {
+        assert!(child(&root, "system/app/Gallery").replace);
+    }
+}
+
+#[test]
+fn sync_turns_the_marker_into_the_opaque_xattr() {
+    let env = TestEnv::new();
+    env.module("alpha")
+        .replace_dir("system/app/Browser")
+        .file("system/app/Browser/Browser.apk", "a");
+
+    let storage = env.root.join("storage");
+    let summary = sync::perform_sync(&env.scan(), &storage, 0).expect("sync");
+    let synced = storage.join("alpha/system/app/Browser");
+    let marker = synced.join(".replace");
+
+    // Storage that refuses trusted xattrs keeps the marker for magic mount.
+    if !utils::is_overlay_opaque(&synced) {
+        assert!(marker.exists());
+        return;
+    }
+    assert!(!marker.exists());
+    let usage = &summary.module_usage["alpha"];
+    assert_eq!(usage.synced_files, usage.source_files - 1);
+
+    let summary = sync::perform_sync(&env.scan(), &storage, 0).expect("resync");
+    assert_eq!(summary.copied, 0);
+    assert!(!marker.exists());
+    assert!(utils::is_overlay_opaque(&synced));
+
+    let root = magic_tree(&env, &storage, &["alpha"]);
+    assert!(child(&root, "system/app/Browser").replace);
+}
+
+#[test]
+fn replacing_a_directory_the_device_lacks_is_diagnosed() {
+    let env = TestEnv::new();
+    env.module("alpha")
+        .replace_dir("system/app/Browser")
+        .replace_dir("system/app/Browser/lib")
+        .replace_dir("system/etc/init")
+        .file("META-INF/.replace", "");
+    let alpha = env.config.moduledir.join("alpha");
+
+    assert_eq!(
+        replace::missing_stock(&alpha, &env.probe),
+        [
+            PathBuf::from("system/app/Browser"),
+            PathBuf::from("system/etc/init")
+        ]
+    );
+
+    fs::create_dir_all(env.root.join("system/app/Browser")).unwrap();
+    assert_eq!(
+        replace::missing_stock(&alpha, &env.probe),
+        [PathBuf::from("system/etc/init")]
+    );
+
+    let issues = replace::diagnose(&env.scan(), &env.probe);
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].context, "alpha");
+    assert!(issues[0].message.contains("/system/etc/init"));
+}
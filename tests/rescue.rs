@@ -0,0 +1,84 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::fs;
+
+use common::TestEnv;
+use meta_hybrid::core::rescue::{self, RescueAction, RescueTargets};
+
+fn targets(env: &TestEnv) -> RescueTargets {
+    let base = env.root.join("data/adb/meta-hybrid");
+    fs::create_dir_all(&base).unwrap();
+    RescueTargets {
+        modules_dir: env.config.moduledir.clone(),
+        image: base.join("modules.img"),
+        run_dir: env.paths.run_dir.clone(),
+    }
+}
+
+#[test]
+fn actions_work_with_corrupt_config_and_state() {
+    let env = TestEnv::new();
+    env.module("alpha");
+    env.module("beta").disabled();
+    let targets = targets(&env);
+
+    fs::create_dir_all(&targets.run_dir).unwrap();
+    fs::write(&env.paths.state_file, "{ not json").unwrap();
+    fs::create_dir_all(targets.run_dir.join("erofs_staging/x")).unwrap();
+    fs::write(env.root.join("data/adb/meta-hybrid/config.toml"), "= [").unwrap();
+    fs::write(&targets.image, "ext4").unwrap();
+    fs::write(targets.image.with_extension("erofs"), "erofs").unwrap();
+
+    let mut asked = Vec::new();
+    let summary = rescue::run(
+        &[
+            RescueAction::DisableAll,
+            RescueAction::RemoveImages,
+            RescueAction::ClearState,
+        ],
+        &targets,
+        &mut |prompt| {
+            asked.push(prompt.to_string());
+            true
+        },
+    );
+
+    assert_eq!(asked.len(), 3);
+    assert!(env.config.moduledir.join("alpha/disable").exists());
+    assert!(summary.contains(&"disabled alpha".to_string()));
+    assert!(summary.contains(&"beta was already disabled".to_string()));
+    assert!(!targets.image.exists());
+    assert!(!targets.image.with_extension("erofs").exists());
+    assert!(targets.run_dir.is_dir());
+    assert_eq!(fs::read_dir(&targets.run_dir).unwrap().count(), 0);
+    assert!(summary.iter().all(|line| !line.starts_with("FAILED")));
+}
+
+#[test]
+fn declined_and_failed_actions_are_reported() {
+    let env = TestEnv::new();
+    env.module("alpha");
+    let targets = targets(&env);
+    fs::write(&targets.image, "ext4").unwrap();
+
+    let summary = rescue::run(
+        &[
+            RescueAction::RemoveImages,
+            RescueAction::Disable("missing".to_string()),
+            RescueAction::Disable("../alpha".to_string()),
+            RescueAction::Disable("alpha".to_string()),
+        ],
+        &targets,
+        &mut |prompt| !prompt.starts_with("Delete"),
+    );
+
+    assert!(targets.image.exists());
+    assert!(summary[0].starts_with("skipped: Delete"));
+    assert!(summary[1].starts_with("FAILED: Disable module missing?"));
+    assert!(summary[2].starts_with("FAILED: Disable module ../alpha?"));
+    assert_eq!(summary[3], "disabled alpha");
+    assert!(env.config.moduledir.join("alpha/disable").exists());
+}
@@ -0,0 +1,77 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::{fs, path::Path};
+
+use common::TestEnv;
+use meta_hybrid::sys::root_backend::{self, Backend, Evidence};
+
+fn remnants(root: &Path, paths: &[&str]) {
+    for p in paths {
+        fs::create_dir_all(root.join(p)).unwrap();
+    }
+}
+
+fn evidence(env: &TestEnv, ksu_driver: bool, apatch_driver: bool) -> Evidence {
+    Evidence {
+        ksu_driver,
+        apatch_driver,
+        root: env.root.clone(),
+    }
+}
+
+#[test]
+fn live_driver_beats_leftover_manager_files() {
+    let env = TestEnv::new();
+    remnants(
+        &env.root,
+        &[
+            "data/adb/ksu",
+            "data/adb/ksud",
+            "data/adb/ap",
+            "data/adb/apd",
+            "data/adb/magisk",
+        ],
+    );
+
+    let detect = |ksu, apatch| root_backend::detect_from(&evidence(&env, ksu, apatch));
+    assert_eq!(detect(true, false), Backend::KernelSU);
+    assert_eq!(detect(false, true), Backend::APatch);
+    assert_eq!(detect(false, false), Backend::Null);
+}
+
+#[test]
+fn both_drivers_prefer_the_installed_manager() {
+    let env = TestEnv::new();
+    let detect = || root_backend::detect_from(&evidence(&env, true, true));
+
+    // Neither installed: KernelSU.
+    assert_eq!(detect(), Backend::KernelSU);
+
+    // Only APatch installed, KernelSU leaving just its data dir behind.
+    remnants(&env.root, &["data/adb/ap", "data/adb/apd", "data/adb/ksu"]);
+    assert_eq!(detect(), Backend::APatch);
+
+    // Both installed: KernelSU.
+    remnants(&env.root, &["data/adb/ksud"]);
+    assert_eq!(detect(), Backend::KernelSU);
+}
+
+#[test]
+fn null_backend_accepts_everything() {
+    let mut backend = Backend::Null.open();
+    assert_eq!(backend.backend(), Backend::Null);
+    backend.add_try_umount(Path::new("/system/etc")).unwrap();
+    backend.commit_umounts().unwrap();
+    backend.nuke_sysfs(Path::new("/mnt/meta")).unwrap();
+
+    let mut apatch = Backend::APatch.open();
+    apatch.add_try_umount(Path::new("/system/etc")).unwrap();
+    apatch.commit_umounts().unwrap();
+    assert_eq!(
+        serde_json::to_string(&apatch.backend()).unwrap(),
+        "\"apatch\""
+    );
+}
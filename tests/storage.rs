@@ -0,0 +1,163 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{fs, path::Path, time::Duration};
+
+use meta_hybrid::{
+    conf::config::{StorageConfig, TmpfsOverflow},
+    core::{
+        state::RuntimeState,
+        storage::{self, DataWait, ImageRecord},
+    },
+};
+
+const MIB: u64 = 1024 * 1024;
+
+fn sizing(overhead_mb: u64, headroom_percent: u64) -> StorageConfig {
+    StorageConfig {
+        image_overhead_mb: overhead_mb,
+        image_headroom_percent: headroom_percent,
+    }
+}
+
+#[test]
+fn small_sets_get_the_overhead_and_large_ones_the_headroom() {
+    let defaults = StorageConfig::default();
+    assert_eq!(storage::image_size(10 * MIB, &defaults), 74 * MIB);
+    assert_eq!(storage::image_size(1000 * MIB, &defaults), 1200 * MIB);
+
+    // A smaller overhead keeps small images small, down to the floor.
+    assert_eq!(storage::image_size(10 * MIB, &sizing(8, 20)), 18 * MIB);
+    assert_eq!(storage::image_size(0, &sizing(0, 0)), 16 * MIB);
+
+    // Sizes are whole MiB, so small content changes give the same image.
+    assert_eq!(
+        storage::image_size(10 * MIB + 1, &defaults),
+        storage::image_size(10 * MIB + 4096, &defaults)
+    );
+}
+
+#[test]
+fn grow_triggers_below_half_the_free_space() {
+    let defaults = StorageConfig::default();
+    let required = 100 * MIB;
+    let fresh = storage::image_size(required, &defaults);
+
+    assert!(!storage::needs_grow(fresh, required, &defaults));
+    assert!(!storage::needs_grow(
+        required + 33 * MIB,
+        required,
+        &defaults
+    ));
+    assert!(storage::needs_grow(
+        required + 31 * MIB,
+        required,
+        &defaults
+    ));
+    assert!(storage::needs_grow(required, required + MIB, &defaults));
+}
+
+#[test]
+fn last_resize_carries_over_until_the_next_resize() {
+    let untouched = ImageRecord::new(64 * MIB, MIB, false, None);
+    assert_eq!(untouched.last_resize, None);
+
+    let resized = ImageRecord::new(80 * MIB, MIB, true, Some(&untouched));
+    let at = resized.last_resize.expect("resize time recorded");
+
+    let later = ImageRecord::new(80 * MIB, 2 * MIB, false, Some(&resized));
+    assert_eq!(later.last_resize, Some(at));
+    assert_eq!(later.used_bytes, 2 * MIB);
+}
+
+#[test]
+fn unrepairable_images_are_kept_next_to_the_original() {
+    assert_eq!(
+        storage::image_backup_path(Path::new("/data/adb/meta-hybrid/modules.img")),
+        Path::new("/data/adb/meta-hybrid/modules.img.bak")
+    );
+}
+
+#[test]
+fn writable_data_is_not_waited_for() {
+    let dir = tempfile::tempdir().unwrap();
+    assert!(storage::data_writable(dir.path()));
+    assert_eq!(
+        storage::wait_for_writable(dir.path(), Duration::from_secs(10)),
+        None
+    );
+    // The probe file is not left behind.
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn data_that_stays_read_only_degrades_the_boot() {
+    let mut state = RuntimeState::default();
+    state.record_data_wait(Some(DataWait {
+        waited_ms: 1200,
+        writable: true,
+    }));
+    assert_eq!(state.degraded_reason, None);
+
+    state.record_data_wait(Some(DataWait {
+        waited_ms: 10_000,
+        writable: false,
+    }));
+    assert_eq!(
+        state.degraded_reason.as_deref(),
+        Some(storage::DATA_RO_REASON)
+    );
+}
+
+fn sizes(modules: &[(&str, u64)]) -> Vec<(String, u64)> {
+    modules
+        .iter()
+        .map(|(id, size)| (id.to_string(), *size))
+        .collect()
+}
+
+#[test]
+fn trimming_drops_the_largest_modules_until_the_rest_fit() {
+    let modules = sizes(&[
+        ("alpha", 300),
+        ("beta", 900),
+        ("gamma", 500),
+        ("delta", 100),
+    ]);
+
+    assert!(storage::trim_to_cap(&modules, 1800).is_empty());
+    assert_eq!(storage::trim_to_cap(&modules, 1000), ["beta"]);
+    assert_eq!(storage::trim_to_cap(&modules, 400), ["beta", "gamma"]);
+    assert_eq!(
+        storage::trim_to_cap(&modules, 0),
+        ["beta", "gamma", "alpha", "delta"]
+    );
+}
+
+#[test]
+fn modules_over_the_tmpfs_cap_apply_the_overflow_policy() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("alpha/system/etc")).unwrap();
+    fs::write(
+        dir.path().join("alpha/system/etc/big.bin"),
+        vec![0; 2 * MIB as usize],
+    )
+    .unwrap();
+
+    let report = storage::check_tmpfs_cap(dir.path(), 0, TmpfsOverflow::Fail).unwrap();
+    assert_eq!(report.cap_bytes, 0);
+    assert_eq!(report.overflow, None);
+
+    let report = storage::check_tmpfs_cap(dir.path(), 4, TmpfsOverflow::Fail).unwrap();
+    assert_eq!(report.estimated_bytes, 2 * MIB);
+    assert_eq!(report.overflow, None);
+
+    let report = storage::check_tmpfs_cap(dir.path(), 1, TmpfsOverflow::Trim).unwrap();
+    assert_eq!(report.cap_bytes, MIB);
+    assert_eq!(report.overflow, Some(TmpfsOverflow::Trim));
+
+    let report = storage::check_tmpfs_cap(dir.path(), 1, TmpfsOverflow::Ext4).unwrap();
+    assert_eq!(report.overflow, Some(TmpfsOverflow::Ext4));
+
+    assert!(storage::check_tmpfs_cap(dir.path(), 1, TmpfsOverflow::Fail).is_err());
+}
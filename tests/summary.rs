@@ -0,0 +1,88 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{fs, time::Duration};
+
+use meta_hybrid::{
+    core::{
+        failure::{FailureClass, FailureRecord},
+        state::{BootResult, MountPhase, RuntimeState},
+        summary::BootSummary,
+    },
+    utils::timing::Sample,
+};
+
+fn sample(stage: &'static str, ms: u64) -> Sample {
+    Sample {
+        stage,
+        elapsed: Duration::from_millis(ms),
+        items: 0,
+    }
+}
+
+fn mounted_state() -> RuntimeState {
+    let mut state = RuntimeState {
+        storage_mode: "tmpfs".to_string(),
+        overlay_modules: vec!["alpha".into(), "beta".into()],
+        magic_modules: vec!["gamma".into()],
+        last_result: BootResult::Mounted,
+        ..Default::default()
+    };
+    state
+        .mount_phase
+        .insert("delta".into(), MountPhase::Pending);
+    for id in ["m1", "m2", "m3", "m4", "m5", "m6"] {
+        state
+            .fallback_reasons
+            .insert(id.into(), "overlay failed".into());
+    }
+    state
+}
+
+#[test]
+fn counts_phases_and_warnings_are_summarized() {
+    let samples = [sample("scan", 10), sample("sync", 5), sample("scan", 4)];
+    let summary = BootSummary::new(&mounted_state(), &samples);
+
+    assert_eq!(summary.counts.mounted, 3);
+    assert_eq!(summary.counts.deferred, 1);
+    assert_eq!(summary.counts.fallbacks, 6);
+    assert_eq!(summary.counts.warnings, 6);
+    assert_eq!(summary.warnings.len(), 5);
+    assert_eq!(summary.headline, "3 mounted, 6 fallbacks, 6 warnings");
+    let phases: Vec<_> = summary
+        .phases
+        .iter()
+        .map(|p| (p.stage.as_str(), p.ms))
+        .collect();
+    assert_eq!(phases, [("scan", 14), ("sync", 5)]);
+    assert!(summary.render().contains("! ... and 1 more"));
+
+    let failed = RuntimeState {
+        last_result: BootResult::Failed,
+        failure: Some(FailureRecord {
+            code: 3,
+            phase: FailureClass::Storage,
+            message: "no space".into(),
+        }),
+        ..Default::default()
+    };
+    let summary = BootSummary::new(&failed, &samples[..1]);
+    assert_eq!(summary.headline, "Failed in Storage (exit 3)");
+    assert!(summary.warnings[0].contains("no space"));
+}
+
+#[test]
+fn text_rendering_follows_the_setting() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("run/last_summary.json");
+    let summary = BootSummary::new(&mounted_state(), &[]);
+
+    summary.save(&file, true).unwrap();
+    let text = fs::read_to_string(file.with_extension("txt")).unwrap();
+    assert!(text.starts_with(&summary.headline));
+    assert_eq!(BootSummary::load(&file).unwrap(), summary);
+
+    summary.save(&file, false).unwrap();
+    assert!(!file.with_extension("txt").exists());
+}
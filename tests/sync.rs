@@ -0,0 +1,211 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::{
+    fs::{self, File},
+    os::unix::fs::{FileExt, MetadataExt},
+};
+
+use common::TestEnv;
+use meta_hybrid::{
+    core::{
+        ops::{
+            sync,
+            sync_journal::{self, JournalOp},
+        },
+        storage,
+    },
+    utils,
+};
+
+#[test]
+fn orphans_survive_when_storage_is_not_mounted() {
+    let env = TestEnv::new();
+    env.module("alpha").file("system/etc/a.conf", "a");
+
+    let storage = env.root.join("storage");
+    fs::create_dir_all(storage.join("removed_module/system/etc")).unwrap();
+    fs::write(storage.join("removed_module/system/etc/old.conf"), "old").unwrap();
+
+    let summary = sync::perform_sync(&env.scan(), &storage, 0).expect("sync");
+
+    assert_eq!(summary.pruned, 0);
+    assert_eq!(summary.reclaimed_bytes, 0);
+    assert!(storage.join("removed_module/system/etc/old.conf").exists());
+    assert!(storage.join("alpha/system/etc/a.conf").exists());
+}
+
+#[test]
+fn module_usage_splits_deduplicated_files() {
+    let env = TestEnv::new();
+    let shared = "x".repeat(4096);
+    env.module("alpha")
+        .file("system/etc/shared.bin", &shared)
+        .file("system/etc/a.conf", "alpha");
+    env.module("beta").file("system/etc/shared.bin", &shared);
+    env.module("scripts_only").file("service.sh", "#!/bin/sh\n");
+
+    let storage = env.root.join("storage");
+    let summary = sync::perform_sync(&env.scan(), &storage, 1024).expect("sync");
+    let usage = &summary.module_usage;
+
+    assert_eq!(summary.dedup_linked, 1);
+
+    let alpha = &usage["alpha"];
+    assert!(alpha.in_storage);
+    assert!(alpha.source_bytes >= 4096 + 5);
+    assert_eq!(alpha.synced_files, alpha.source_files);
+    assert_eq!(alpha.synced_bytes, alpha.source_bytes - 2048);
+
+    let beta = &usage["beta"];
+    assert_eq!(beta.synced_bytes, beta.source_bytes - 2048);
+    assert_eq!(beta.size_bytes(), beta.synced_bytes);
+
+    let scripts = &usage["scripts_only"];
+    assert!(!scripts.in_storage);
+    assert_eq!(scripts.synced_bytes, 0);
+    assert_eq!(scripts.size_bytes(), scripts.source_bytes);
+}
+
+#[test]
+fn sparse_files_keep_their_holes() {
+    const LEN: u64 = 64 * 1024 * 1024;
+    let env = TestEnv::new();
+    env.module("alpha").file("system/etc/cache.bin", "head");
+    let source = env.config.moduledir.join("alpha/system/etc/cache.bin");
+    let file = File::options().write(true).open(&source).unwrap();
+    file.set_len(LEN).unwrap();
+    file.write_at(b"tail", LEN - 4).unwrap();
+    drop(file);
+    let allocated = utils::allocated_size(&fs::metadata(&source).unwrap());
+    assert!(allocated < 1024 * 1024, "fixture is not sparse");
+    assert!(storage::required_bytes(&env.scan()) < 1024 * 1024);
+
+    let storage = env.root.join("storage");
+    let summary = sync::perform_sync(&env.scan(), &storage, 0).expect("sync");
+    let copy = storage.join("alpha/system/etc/cache.bin");
+    let meta = fs::metadata(&copy).unwrap();
+    assert_eq!(meta.len(), LEN);
+    assert!(meta.blocks() * 512 < 1024 * 1024);
+    let content = fs::read(&copy).unwrap();
+    assert_eq!(
+        (&content[..4], &content[content.len() - 4..]),
+        (&b"head"[..], &b"tail"[..])
+    );
+    assert!(content[4..content.len() - 4].iter().all(|b| *b == 0));
+
+    let alpha = &summary.module_usage["alpha"];
+    assert!(alpha.source_bytes >= LEN);
+    assert!(alpha.source_allocated_bytes < 1024 * 1024);
+    assert!(alpha.synced_allocated_bytes < 1024 * 1024);
+}
+
+#[test]
+fn synced_entries_get_a_valid_selinux_context() {
+    let env = TestEnv::new();
+    env.module("alpha")
+        .file("vendor/lib64/hw/camera.so", "hal")
+        .file("system/etc/a.conf", "a");
+    let modules = env.scan();
+    let src = &modules[0].source_path;
+    utils::lsetfilecon(
+        src.join("vendor/lib64/hw/camera.so"),
+        "u:object_r:unlabeled:s0",
+    )
+    .unwrap();
+    utils::lsetfilecon(src.join("system/etc/a.conf"), "u:object_r:vendor_file:s0").unwrap();
+
+    let storage = env.root.join("storage");
+    let summary = sync::perform_sync(&modules, &storage, 0).expect("sync");
+    let context = |rel: &str| utils::lgetfilecon(storage.join("alpha").join(rel)).unwrap();
+
+    assert!(utils::is_valid_context(&context(
+        "vendor/lib64/hw/camera.so"
+    )));
+    assert_eq!(context("system/etc/a.conf"), "u:object_r:vendor_file:s0");
+    assert!(utils::is_valid_context(&context("vendor/lib64")));
+    if !utils::selinux_enabled() {
+        assert_eq!(summary.unlabeled, 0);
+    }
+
+    // An unchanged copy that lost its label is repaired on the next sync.
+    let stale = storage.join("alpha/system/etc/a.conf");
+    utils::lsetfilecon(&stale, "u:object_r:rootfs:s0").unwrap();
+    let summary = sync::perform_sync(&modules, &storage, 0).expect("resync");
+    assert_eq!(summary.copied, 0);
+    assert!(utils::is_valid_context(&context("system/etc/a.conf")));
+
+    assert!(!utils::is_valid_context(""));
+    assert!(!utils::is_valid_context("u:object_r:unlabeled:s0"));
+    assert!(!utils::is_valid_context("u:object_r:rootfs:s0"));
+}
+
+#[test]
+fn interrupted_sync_rebuilds_the_copy() {
+    let env = TestEnv::new();
+    env.module("alpha")
+        .file("system/lib64/liba.so", "full library")
+        .file("system/lib64/libdep.so", "dependency");
+    let storage = env.root.join("storage");
+    let modules = env.scan();
+    sync::perform_sync(&modules, &storage, 0).expect("sync");
+    assert!(sync_journal::pending(&storage).is_empty());
+
+    // A power loss left a truncated library with its metadata intact and a
+    // stray file behind.
+    let lib = storage.join("alpha/system/lib64/liba.so");
+    let mtime = fs::metadata(&lib).unwrap().modified().unwrap();
+    fs::write(&lib, "full libr\0\0\0").unwrap();
+    File::options()
+        .write(true)
+        .open(&lib)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+    fs::write(storage.join("alpha/system/lib64/.tmp"), "").unwrap();
+    sync_journal::begin(&storage, JournalOp::Sync, "alpha", None).unwrap();
+
+    let summary = sync::perform_sync(&modules, &storage, 0).expect("resync");
+
+    assert_eq!(summary.recovered, ["alpha"]);
+    assert!(summary.dirty.is_empty());
+    assert_eq!(fs::read_to_string(&lib).unwrap(), "full library");
+    assert!(!storage.join("alpha/system/lib64/.tmp").exists());
+    assert!(sync_journal::pending(&storage).is_empty());
+    assert_eq!(sync::diagnose(&summary).len(), 1);
+}
+
+#[test]
+fn interrupted_prune_is_finished() {
+    let env = TestEnv::new();
+    env.module("alpha").file("system/etc/a.conf", "a");
+    let storage = env.root.join("storage");
+    fs::create_dir_all(&storage).unwrap();
+    sync_journal::begin(&storage, JournalOp::Prune, "gone", None).unwrap();
+
+    let summary = sync::perform_sync(&env.scan(), &storage, 0).expect("sync");
+
+    assert_eq!(summary.recovered, ["gone"]);
+    assert!(sync_journal::pending(&storage).is_empty());
+}
+
+#[test]
+fn module_without_a_journal_marker_is_dirty() {
+    let env = TestEnv::new();
+    env.module("alpha").file("system/etc/a.conf", "a");
+    env.module("beta").file("system/etc/b.conf", "b");
+    let storage = env.root.join("storage");
+    fs::create_dir_all(&storage).unwrap();
+    // The journal cannot be written, so no copy may be trusted.
+    fs::write(sync_journal::journal_dir(&storage), "").unwrap();
+
+    let summary = sync::perform_sync(&env.scan(), &storage, 0).expect("sync");
+
+    assert_eq!(summary.dirty, ["alpha", "beta"]);
+    assert!(!storage.join("alpha/system/etc/a.conf").exists());
+    let issues = sync::diagnose(&summary);
+    assert_eq!(issues.len(), 2);
+    assert!(issues[0].message.starts_with("Not mounted"));
+}
@@ -0,0 +1,50 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::{fs, path::Path};
+
+use common::TestEnv;
+use meta_hybrid::core::ops::{
+    planner,
+    probe::{RootedSystem, SystemProbe},
+};
+
+#[test]
+fn dumped_partitions_are_mount_points() {
+    let env = TestEnv::new();
+    fs::create_dir_all(env.root.join("apex/com.android.art/lib64")).unwrap();
+    let sysroot = RootedSystem::sysroot(&env.root);
+
+    for mounted in [
+        "/vendor",
+        "/product",
+        "/system_ext",
+        "/apex/com.android.art",
+    ] {
+        assert!(sysroot.is_mount_point(Path::new(mounted)), "{mounted}");
+    }
+    assert!(!sysroot.is_mount_point(Path::new("/system")));
+    assert!(!sysroot.is_mount_point(Path::new("/data")));
+
+    let a_only = RootedSystem::sysroot(&TestEnv::a_only().root);
+    assert!(!a_only.is_mount_point(Path::new("/vendor")));
+}
+
+#[test]
+fn sysroot_plans_like_the_device() {
+    let env = TestEnv::new();
+    env.module("alpha")
+        .file("system/etc/a.conf", "a")
+        .file("vendor/etc/v.conf", "v")
+        .file("system/product/app/A.apk", "p");
+    env.module("beta").file("system_ext/etc/b.conf", "b");
+
+    let modules = env.scan();
+    let sysroot = RootedSystem::sysroot(&env.root);
+    let plan = planner::generate_with_probe(&env.config, &modules, &env.config.moduledir, &sysroot)
+        .expect("plan against the sysroot");
+
+    assert_eq!(plan.to_json(), env.plan().to_json());
+}
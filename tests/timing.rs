@@ -0,0 +1,59 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::time::Duration;
+
+use meta_hybrid::utils::timing::{self, Sample};
+
+fn sample(stage: &'static str, ms: u64, items: usize) -> Sample {
+    Sample {
+        stage,
+        elapsed: Duration::from_millis(ms),
+        items,
+    }
+}
+
+#[test]
+fn summary_keeps_stage_order_and_takes_the_median() {
+    let samples = [
+        sample("inventory", 30, 10),
+        sample("plan", 4, 10),
+        sample("inventory", 10, 10),
+        sample("inventory", 20, 10),
+        sample("plan", 8, 10),
+    ];
+
+    let stats = timing::summarize(&samples);
+    let stages: Vec<_> = stats.iter().map(|s| s.stage).collect();
+    assert_eq!(stages, ["inventory", "plan"]);
+
+    let inventory = &stats[0];
+    assert_eq!(inventory.runs, 3);
+    assert_eq!(inventory.min_ms, 10.0);
+    assert_eq!(inventory.median_ms, 20.0);
+    assert_eq!(inventory.max_ms, 30.0);
+    assert_eq!(inventory.items, 10);
+    // 30 items in 60 ms.
+    assert!((inventory.items_per_sec - 500.0).abs() < 1e-6);
+
+    assert_eq!(stats[1].median_ms, 6.0);
+}
+
+// The recorder is process-wide, so this file holds a single test using it.
+#[test]
+fn spans_are_kept_only_while_recording() {
+    drop(timing::span("ignored"));
+
+    timing::start_recording();
+    {
+        let mut span = timing::span("scan");
+        span.items(3);
+    }
+    let samples = timing::take_samples();
+    assert_eq!(samples.len(), 1);
+    assert_eq!(samples[0].stage, "scan");
+    assert_eq!(samples[0].items, 3);
+
+    drop(timing::span("after"));
+    assert!(timing::take_samples().is_empty());
+}
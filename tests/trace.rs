@@ -0,0 +1,83 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::{fs, io, path::Path};
+
+use common::TestEnv;
+use meta_hybrid::utils::trace::{self, CAPACITY, TraceOp};
+
+// The tracer is process-wide, so this file holds a single tracing test.
+#[test]
+fn trace_records_rotate_and_wrap() {
+    let env = TestEnv::new();
+    let path = env.paths.trace_file.clone();
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+    trace::start(&path).expect("start");
+    trace::begin(TraceOp::Bind, Path::new("/system/etc/a.conf")).finish(&Ok::<(), io::Error>(()));
+    trace::begin(TraceOp::Overlay, Path::new("/vendor"))
+        .finish(&Err::<(), _>(io::Error::from_raw_os_error(libc::EBUSY)));
+    trace::begin(TraceOp::Bind, Path::new("/system/etc/a.conf"))
+        .finish(&Err::<(), _>(anyhow::anyhow!("no errno here")));
+    let _killed = trace::begin(TraceOp::Move, Path::new("/product/app"));
+
+    let dump = trace::decode(&path).expect("decode");
+    let seqs: Vec<_> = dump.entries.iter().map(|e| e.seq).collect();
+    assert_eq!(seqs, [1, 2, 3, 4]);
+    let ops: Vec<_> = dump.entries.iter().filter_map(|e| e.op).collect();
+    assert_eq!(
+        ops,
+        [
+            TraceOp::Bind,
+            TraceOp::Overlay,
+            TraceOp::Bind,
+            TraceOp::Move
+        ]
+    );
+    let paths: Vec<_> = dump.entries.iter().map(|e| e.path.as_deref()).collect();
+    assert_eq!(
+        paths,
+        [
+            Some("/system/etc/a.conf"),
+            Some("/vendor"),
+            Some("/system/etc/a.conf"),
+            Some("/product/app")
+        ]
+    );
+    let errnos: Vec<_> = dump.entries.iter().map(|e| e.errno).collect();
+    assert_eq!(errnos, [Some(0), Some(libc::EBUSY), Some(-1), None]);
+    let pending: Vec<_> = dump.pending().map(|e| e.seq).collect();
+    assert_eq!(pending, [4]);
+
+    // A new run keeps the last trace as `.1` and starts empty.
+    trace::start(&path).expect("restart");
+    assert!(trace::decode(&path).unwrap().entries.is_empty());
+    let previous = trace::decode(&trace::previous_path(&path)).unwrap();
+    assert_eq!(previous.entries.len(), 4);
+
+    // Past capacity the oldest records are overwritten.
+    for _ in 0..CAPACITY + 3 {
+        trace::begin(TraceOp::Mirror, Path::new("/system/lib")).finish(&Ok::<(), io::Error>(()));
+    }
+    let dump = trace::decode(&path).unwrap();
+    assert_eq!(dump.entries.len(), CAPACITY);
+    assert_eq!(dump.entries.first().unwrap().seq, 4);
+    assert_eq!(dump.entries.last().unwrap().seq, CAPACITY as u64 + 3);
+
+    // Stopped, nothing more is written.
+    trace::stop();
+    trace::begin(TraceOp::Umount, Path::new("/system/lib")).finish(&Ok::<(), io::Error>(()));
+    assert_eq!(trace::decode(&path).unwrap().entries.len(), CAPACITY);
+}
+
+#[test]
+fn decode_rejects_other_files() {
+    let env = TestEnv::new();
+    let path = env.root.join("not-a-trace.bin");
+    fs::write(&path, vec![0u8; 300 * 1024]).unwrap();
+
+    let err = trace::decode(&path).unwrap_err();
+    assert!(err.to_string().contains("is not a mount trace"), "{err:#}");
+}
@@ -0,0 +1,57 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use meta_hybrid::mount::umount_mgr::{self, RETRY_FLAGS, UmountQueue};
+
+#[test]
+fn queue_keeps_each_path_once_until_taken() {
+    let mut queue = UmountQueue::default();
+    assert!(queue.push(Path::new("/system/etc")));
+    assert!(queue.push(Path::new("/vendor/lib64")));
+    assert!(!queue.push(Path::new("/system/etc")));
+
+    assert_eq!(
+        queue.take(),
+        [PathBuf::from("/system/etc"), PathBuf::from("/vendor/lib64")]
+    );
+    assert!(queue.take().is_empty());
+
+    // A commit forgets what it handed over, so the next run queues it again.
+    assert!(queue.push(Path::new("/system/etc")));
+    assert_eq!(queue.take(), [PathBuf::from("/system/etc")]);
+}
+
+#[test]
+fn flags_are_retried_with_detach() {
+    let mut tried = Vec::new();
+    let used = umount_mgr::with_flag_retry(|flags| {
+        tried.push(flags);
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!((used, tried), (0, vec![0]));
+
+    let mut tried = Vec::new();
+    let used = umount_mgr::with_flag_retry(|flags| {
+        tried.push(flags);
+        if flags == 0 {
+            bail!("busy");
+        }
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(used, libc::MNT_DETACH as u32);
+    assert_eq!(tried, RETRY_FLAGS);
+
+    let mut tried = Vec::new();
+    let err = umount_mgr::with_flag_retry(|flags| {
+        tried.push(flags);
+        bail!("no driver")
+    })
+    .unwrap_err();
+    assert_eq!(tried, RETRY_FLAGS);
+    assert_eq!(format!("{:#}", err), "try_umount(2) failed: no driver");
+}
@@ -0,0 +1,89 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{fs, os::unix::fs::symlink, path::Path};
+
+use meta_hybrid::core::uninstall::{self, UninstallTargets};
+
+fn targets(base: &Path) -> UninstallTargets {
+    UninstallTargets {
+        base_dir: base.to_path_buf(),
+        state_file: base.join("run/daemon_state.json"),
+        journal_file: base.join("run/mount_journal.json"),
+    }
+}
+
+/// A base directory as a few boots leave it.
+fn populate(base: &Path) {
+    for dir in ["run/ns", "rules", "granary", "profiles", "mnt"] {
+        fs::create_dir_all(base.join(dir)).unwrap();
+    }
+    fs::write(base.join("config.toml"), "verbose = false\n").unwrap();
+    fs::write(base.join("modules.img"), vec![1; 8192]).unwrap();
+    fs::write(base.join("modules.erofs"), "erofs").unwrap();
+    fs::write(base.join("run/daemon_state.json"), "{ not json").unwrap();
+    fs::write(base.join("run/trace.bin"), "trace").unwrap();
+    fs::hard_link(base.join("run/trace.bin"), base.join("run/trace.bin.1")).unwrap();
+    fs::write(base.join("rules/alpha.toml"), "mode = \"magic\"\n").unwrap();
+    fs::write(base.join("granary/1.tar.zst"), "snapshot").unwrap();
+}
+
+#[test]
+fn everything_under_the_base_dir_goes() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().join("meta-hybrid");
+    populate(&base);
+    let outside = dir.path().join("outside");
+    fs::create_dir_all(&outside).unwrap();
+    fs::write(outside.join("keep.txt"), "not ours").unwrap();
+    symlink(&outside, base.join("run/escape")).unwrap();
+
+    let report = uninstall::run(&targets(&base), false).unwrap();
+    assert!(report.errors.is_empty(), "{:?}", report.errors);
+    assert!(!base.exists());
+    assert!(report.removed.contains(&base.join("modules.img")));
+    assert!(report.removed.contains(&base));
+    assert!(report.kept.is_empty());
+    // The hard-linked trace counts once; the escape link is a file of its own.
+    assert_eq!(report.removed_files, 8);
+    assert!(report.reclaimed_bytes >= 8192);
+    assert!(outside.join("keep.txt").exists());
+
+    // Nothing left to do is not an error.
+    let report = uninstall::run(&targets(&base), false).unwrap();
+    assert!(report.removed.is_empty());
+}
+
+#[test]
+fn keep_config_leaves_what_a_reinstall_reads() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().join("meta-hybrid");
+    populate(&base);
+
+    let report = uninstall::run(&targets(&base), true).unwrap();
+    assert!(report.errors.is_empty(), "{:?}", report.errors);
+    let mut left: Vec<_> = fs::read_dir(&base)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    left.sort();
+    assert_eq!(left, ["config.toml", "granary", "profiles", "rules"]);
+    assert_eq!(report.kept.len(), 4);
+    assert!(base.join("rules/alpha.toml").exists());
+    assert!(!base.join("modules.img").exists());
+    assert!(!base.join("run").exists());
+}
+
+#[test]
+fn symlinked_base_dir_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let real = dir.path().join("real");
+    populate(&real);
+    let base = dir.path().join("meta-hybrid");
+    symlink(&real, &base).unwrap();
+
+    let err = uninstall::run(&targets(&base), false).unwrap_err();
+    assert!(format!("{:#}", err).contains("symlink"));
+    assert!(real.join("modules.img").exists());
+    assert!(base.exists());
+}
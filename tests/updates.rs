@@ -0,0 +1,118 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use common::TestEnv;
+use meta_hybrid::core::{
+    inventory::updates::{self, UpdateKind},
+    ops::update_preview::{self, PlannedMount},
+};
+
+fn mount(target: &str, method: &str) -> PlannedMount {
+    PlannedMount {
+        target: target.to_string(),
+        method: method.to_string(),
+    }
+}
+
+#[test]
+fn pending_updates_carry_both_versions() {
+    let env = TestEnv::new();
+    env.module("alpha").file("system/etc/a.conf", "old");
+    env.module("beta").file("system/etc/b.conf", "b");
+    env.pending_update("alpha", "2.0");
+    env.pending_update("gamma", "0.1");
+
+    let pending = updates::pending(&env.config.moduledir);
+    assert_eq!(pending.keys().collect::<Vec<_>>(), ["alpha", "gamma"]);
+
+    let alpha = &pending["alpha"];
+    assert_eq!(alpha.kind, UpdateKind::Update);
+    assert_eq!(alpha.current_version.as_deref(), Some("1.0"));
+    assert_eq!(alpha.new_version, "2.0");
+
+    let gamma = &pending["gamma"];
+    assert_eq!(gamma.kind, UpdateKind::Install);
+    assert_eq!(gamma.current_version, None);
+    assert_eq!(gamma.new_version, "0.1");
+}
+
+#[test]
+fn updated_scan_replaces_installs_and_removes() {
+    let env = TestEnv::new();
+    env.module("alpha").file("system/etc/a.conf", "old");
+    env.module("beta").file("system/etc/b.conf", "b");
+    env.pending_update("alpha", "2.0")
+        .file("system/etc/a.conf", "new");
+    env.pending_update("beta", "1.1").removed();
+    env.pending_update("gamma", "0.1")
+        .file("vendor/etc/g.conf", "g");
+
+    let updated = updates::scan_updated_with_paths(&env.config.moduledir, &env.config, &env.paths)
+        .expect("scan updated");
+    let mut ids: Vec<_> = updated.iter().map(|m| m.id.as_str()).collect();
+    ids.sort();
+    assert_eq!(ids, ["alpha", "gamma"]);
+    let update_dir = updates::update_dir(&env.config.moduledir);
+    assert!(
+        updated
+            .iter()
+            .all(|m| m.source_path.starts_with(&update_dir))
+    );
+
+    // The live scan is untouched.
+    let mut live: Vec<_> = env.scan().into_iter().map(|m| m.id).collect();
+    live.sort();
+    assert_eq!(live, ["alpha", "beta"]);
+}
+
+#[test]
+fn preview_reports_mount_changes_per_module() {
+    let env = TestEnv::new();
+    env.module("alpha")
+        .file("system/etc/a.conf", "a")
+        .file("vendor/etc/a.conf", "a");
+    env.module("beta").file("product/etc/b.conf", "b");
+    env.module("delta").file("system/etc/d.conf", "d");
+    // alpha drops vendor and takes on product; beta switches to magic
+    // mount; gamma is new; delta keeps its mounts.
+    env.pending_update("alpha", "2.0")
+        .file("system/etc/a.conf", "a")
+        .file("product/etc/a.conf", "a");
+    env.pending_update("beta", "1.1")
+        .file("product/etc/b.conf", "b")
+        .mount_mode("magic");
+    env.pending_update("gamma", "0.1")
+        .file("system_ext/etc/g.conf", "g");
+    env.pending_update("delta", "1.0.1")
+        .file("system/etc/d.conf", "d2");
+
+    let diffs =
+        update_preview::preview_with_probe(&env.config, &env.paths, &env.probe).expect("preview");
+    let by_id = |id: &str| diffs.iter().find(|d| d.id == id).expect(id);
+
+    let alpha = by_id("alpha");
+    assert!(alpha.added.iter().any(|m| m.target.starts_with("/product")));
+    assert!(
+        alpha
+            .removed
+            .iter()
+            .any(|m| m.target.starts_with("/vendor"))
+    );
+
+    let beta = by_id("beta");
+    assert!(beta.added.contains(&mount("/product", "magic")), "{beta:?}");
+    assert!(
+        beta.removed.iter().all(|m| m.method == "overlay") && !beta.removed.is_empty(),
+        "{beta:?}"
+    );
+
+    let gamma = by_id("gamma");
+    assert_eq!(gamma.update.as_ref().unwrap().kind, UpdateKind::Install);
+    assert!(gamma.removed.is_empty() && !gamma.added.is_empty());
+
+    let delta = by_id("delta");
+    assert!(delta.is_empty(), "{delta:?}");
+    assert_eq!(delta.update.as_ref().unwrap().new_version, "1.0.1");
+}
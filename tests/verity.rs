@@ -0,0 +1,108 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use common::TestEnv;
+use meta_hybrid::core::ops::{
+    planner::DiagnosticLevel,
+    verity::{self, VerityCheck, VerityState},
+};
+
+fn mount(point: &str, fs_type: &str, source: &str) -> (PathBuf, String, String) {
+    (
+        PathBuf::from(point),
+        fs_type.to_string(),
+        source.to_string(),
+    )
+}
+
+fn dm(env: &TestEnv, node: &str, name: &str, uuid: &str) -> PathBuf {
+    let sys_block = env.root.join("sys/block");
+    let dir = sys_block.join(node).join("dm");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("name"), format!("{}\n", name)).unwrap();
+    fs::write(dir.join("uuid"), format!("{}\n", uuid)).unwrap();
+    sys_block
+}
+
+#[test]
+fn getprop_output_is_parsed() {
+    let props = verity::parse_getprop(
+        "[ro.boot.veritymode]: [enforcing]\n[partition.system.verified]: [2]\ngarbage\n[empty]: []\n",
+    );
+    assert_eq!(props["ro.boot.veritymode"], "enforcing");
+    assert_eq!(props["partition.system.verified"], "2");
+    assert_eq!(props["empty"], "");
+    assert_eq!(props.len(), 3);
+}
+
+#[test]
+fn verity_target_under_an_overlay_is_found() {
+    let env = TestEnv::new();
+    let sys_block = dm(&env, "dm-3", "system-verity", "CRYPT-VERITY-abc");
+    let mounts = [
+        mount("/", "erofs", "/dev/block/dm-3"),
+        mount("/system", "overlay", "KSU"),
+        mount("/vendor", "ext4", "/dev/block/sda12"),
+    ];
+    let props = BTreeMap::from([
+        ("ro.boot.veritymode".to_string(), "enforcing".to_string()),
+        ("ro.product.model".to_string(), "Pixel".to_string()),
+    ]);
+
+    let system = verity::gather("system", &mounts, &sys_block, &props);
+    assert_eq!(system.mount_point, Some(PathBuf::from("/")));
+    assert_eq!(system.dm_name.as_deref(), Some("system-verity"));
+    assert_eq!(system.fs_type.as_deref(), Some("erofs"));
+    assert_eq!(system.properties.len(), 1);
+    assert_eq!(verity::assess(&system), VerityState::Enforcing);
+
+    let overlaid = VerityCheck {
+        state: verity::assess(&system),
+        overlaid: true,
+        evidence: system.clone(),
+    };
+    assert!(matches!(overlaid.level(), DiagnosticLevel::Critical));
+    let magic_only = VerityCheck {
+        overlaid: false,
+        ..overlaid
+    };
+    assert!(matches!(magic_only.level(), DiagnosticLevel::Info));
+
+    let vendor = verity::gather("vendor", &mounts, &sys_block, &props);
+    assert_eq!(verity::assess(&vendor), VerityState::NotEnforcing);
+}
+
+#[test]
+fn logging_mode_and_missing_evidence_are_not_critical() {
+    let env = TestEnv::new();
+    let sys_block = dm(&env, "dm-1", "system-verity", "");
+    let mounts = [mount("/", "erofs", "/dev/block/dm-1")];
+    let props = BTreeMap::from([("ro.boot.veritymode".to_string(), "logging".to_string())]);
+
+    let logging = verity::gather("system", &mounts, &sys_block, &props);
+    assert_eq!(verity::assess(&logging), VerityState::NotEnforcing);
+
+    // A dm node whose sysfs entry cannot be read.
+    let unreadable = verity::gather(
+        "product",
+        &[mount("/product", "erofs", "/dev/block/dm-7")],
+        &sys_block,
+        &BTreeMap::new(),
+    );
+    assert_eq!(verity::assess(&unreadable), VerityState::Unknown);
+
+    let nothing = verity::gather("odm", &[], &sys_block, &BTreeMap::new());
+    assert_eq!(nothing.device, None);
+    let check = VerityCheck {
+        state: verity::assess(&nothing),
+        overlaid: true,
+        evidence: nothing,
+    };
+    assert_eq!(check.state, VerityState::Unknown);
+    assert!(matches!(check.level(), DiagnosticLevel::Info));
+    assert!(check.message().contains("could not be determined"));
+}
@@ -0,0 +1,83 @@
+// Copyright 2026 Hybrid Mount Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod common;
+
+use common::TestEnv;
+use meta_hybrid::core::winnow::{self, SuggestionBasis, WinnowSuggestion};
+
+fn suggest(env: &TestEnv) -> Vec<WinnowSuggestion> {
+    let plan = env.plan();
+    let report = env.analyze(&plan);
+    winnow::suggest_rules(&plan, &report.conflicts, &env.scan(), &env.config.winnowing)
+}
+
+fn version_code(id: &str, code: u64) -> String {
+    format!("id={id}\nname={id}\nversion=1.0\nversionCode={code}\nauthor=test\n")
+}
+
+#[test]
+fn newest_app_wins_by_version_code() {
+    let env = TestEnv::new();
+    env.module("alpha")
+        .file("module.prop", &version_code("alpha", 7))
+        .file("system/app/Camera/Camera.apk", "alpha");
+    env.module("beta")
+        .file("module.prop", &version_code("beta", 3))
+        .file("system/app/Camera/Camera.apk", "beta");
+
+    let suggestions = suggest(&env);
+    assert_eq!(suggestions.len(), 1);
+    assert_eq!(suggestions[0].path, "/system/app/Camera/Camera.apk");
+    assert_eq!(suggestions[0].module, "alpha");
+    assert_eq!(suggestions[0].basis, SuggestionBasis::VersionCode);
+}
+
+#[test]
+fn package_name_then_current_order_decide() {
+    let env = TestEnv::new();
+    env.module("hosts")
+        .file("system/etc/hosts", "blocklist")
+        .file("system/etc/a.conf", "hosts");
+    env.module("beta")
+        .file("system/etc/hosts", "stock")
+        .file("system/etc/a.conf", "beta");
+
+    let suggestions = suggest(&env);
+    let by_path = |path: &str| {
+        suggestions
+            .iter()
+            .find(|s| s.path == path)
+            .expect("suggested")
+    };
+
+    let hosts = by_path("/system/etc/hosts");
+    assert_eq!(hosts.module, "hosts");
+    assert_eq!(hosts.basis, SuggestionBasis::PackageName);
+
+    let conf = by_path("/system/etc/a.conf");
+    assert_eq!(conf.basis, SuggestionBasis::CurrentOrder);
+    assert_eq!(conf.module, conf.contenders[0]);
+}
+
+#[test]
+fn covered_identical_and_hidden_files_are_not_proposed() {
+    let mut env = TestEnv::new();
+    env.module("alpha")
+        .file("system/etc/hosts", "alpha")
+        .file("system/etc/same.conf", "same");
+    env.module("beta")
+        .file("system/etc/hosts", "beta")
+        .file("system/etc/same.conf", "same");
+    env.config.winnowing.set_rule("/system/etc/hosts", "beta");
+    assert!(suggest(&env).is_empty());
+
+    let Some(_) = env.module("gamma").whiteout("system/etc/gone.conf") else {
+        return;
+    };
+    env.module("delta").file("system/etc/gone.conf", "delta");
+    let suggestions = suggest(&env);
+    assert_eq!(suggestions.len(), 1);
+    assert_eq!(suggestions[0].path, "/system/etc/gone.conf");
+    assert_eq!(suggestions[0].module, "delta");
+}
@@ -204,9 +204,15 @@ const RealAPI: AppAPI = {
           const state = JSON.parse(outState);
           info.mountBase = state.mount_point || "Unknown";
           info.activeMounts = state.active_mounts || [];
-          if (state.zygisksu_enforce !== undefined) {
-            info.zygisksuEnforce = state.zygisksu_enforce ? "1" : "0";
+          const enforce = state.denylist_enforce ?? state.zygisksu_enforce;
+          if (enforce !== undefined) {
+            info.zygisksuEnforce = enforce ? "1" : "0";
           }
+          if (state.denylist_provider !== undefined) {
+            info.denylistProvider = state.denylist_provider;
+          }
+          info.pendingChanges = state.pending_changes ?? 0;
+          info.safeMode = state.safe_mode ?? false;
         } catch {
           // ignore
         }
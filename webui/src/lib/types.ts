@@ -6,23 +6,93 @@
 export interface ModuleRules {
   default_mode: MountMode;
   paths: Record<string, string>;
+  order?: number;
+  after?: string[];
+  exclude_partitions?: string[];
+  allow_rw_files?: boolean;
+  blacklist?: string[];
 }
 
-export type OverlayMode = "tmpfs" | "ext4" | "erofs";
+export type OverlayMode = "tmpfs" | "ext4" | "erofs" | "zram";
+
+export interface OverlayOptions {
+  xino?: "auto" | "on" | "off";
+  metacopy?: boolean;
+  redirect_dir?: boolean;
+  userxattr?: boolean;
+}
 
 export interface AppConfig {
+  schema_version?: number;
   moduledir: string;
+  image_modules?: string[];
   mountsource: string;
+  /** Deprecated: the same as `log_level: "debug"`. */
   verbose: boolean;
   hybrid_mnt_dir: string;
   partitions: string[];
   overlay_mode: OverlayMode;
+  overlay_options?: Record<string, OverlayOptions>;
+  granular_fallback?: boolean;
+  storage_mode?: OverlayMode;
   disable_umount: boolean;
   allow_umount_coexistence: boolean;
+  susfs_hide_paths?: boolean;
+  daemon?: boolean;
+  ext4_reserved_blocks_percent?: number;
+  tmpfs_max_mb?: number;
+  tmpfs_overflow?: "ext4" | "trim" | "fail";
+  selinux_audit?: boolean;
+  dedup_min_size?: number;
+  verify_storage?: boolean;
+  verify_max_seconds?: number;
+  verify_sample_percent?: number;
+  verify_full_above_kb?: number;
+  summary_text?: boolean;
+  skip_identical_files?: boolean;
+  auto_shrink?: boolean;
+  rw_partitions?: string[];
+  safe_mode_threshold?: number;
+  safe_modules?: string[];
+  exclusions?: string[];
+  user_deny_paths?: string[];
+  mount_blacklist?: string[];
+  use_last_good?: "never" | "on-boot-loop" | "always";
+  profile?: string;
+  boot_priority?: "low" | "normal";
+  allow_apex?: boolean;
+  defer_ce_modules?: boolean;
+  threads?: number;
+  namespace_mode?: "global" | "clone";
+  trace_mounts?: boolean;
+  mount_timeout_secs?: number;
+  mount_deadline_secs?: number;
+  data_rw_wait_secs?: number;
+  denylist_provider?: DenylistProvider;
+  auto_partitions?: boolean;
+  busy_file_policy?: "skip" | "warn" | "force";
+  foreign_mount_policy?: "abort" | "stack" | "skip-partition";
+  prefer_bind_for_small_modules?: boolean;
+  stealth?: {
+    randomize_mountsource?: boolean;
+    randomize_tempdir?: boolean;
+  };
+  storage?: {
+    image_overhead_mb?: number;
+    image_headroom_percent?: number;
+    loop_autoclear?: boolean;
+    loop_direct_io?: boolean;
+  };
+  log_level?: "error" | "warn" | "info" | "debug" | "trace";
+  debug_modules?: string[];
+  log_format?: "plain" | "json";
+  log_max_size?: number;
+  log_max_files?: number;
+  log_buffer_kb?: number;
   logfile?: string;
 }
 
-export type MountMode = "overlay" | "magic" | "ignore";
+export type MountMode = "overlay" | "magic" | "ignore" | "hymo";
 
 export interface Module {
   id: string;
@@ -31,27 +101,98 @@ export interface Module {
   author: string;
   description: string;
   mode: string;
+  resolved_mode?: MountMode;
+  mode_source?: "mount_mode" | "rules";
+  partitions?: string[];
   is_mounted: boolean;
+  effective_mode?: "overlay" | "magic" | "hymo" | "bind";
+  fallback_reason?: string;
+  mount_phase?: "boot" | "pending" | "deferred";
+  size_bytes?: number;
+  in_storage?: boolean;
+  staged?: boolean;
+  bundle?: string;
+  update?: PendingUpdate;
+  excluded_by?: "meta-hybrid" | "disable file" | "skip_mount";
+  manifest?: "ok" | "mismatch" | "absent";
   enabled?: boolean;
   source_path?: string;
   rules: ModuleRules;
+  issues?: ModuleIssue[];
+}
+
+export interface PendingUpdate {
+  kind: "update" | "install";
+  current_version?: string;
+  new_version: string;
+}
+
+export interface ModuleIssue {
+  severity: "warning" | "critical";
+  message: string;
 }
 
 export interface StorageStatus {
   size: string;
   used: string;
   percent: string;
-  type: "tmpfs" | "ext4" | "erofs" | "unknown" | null;
+  type: "tmpfs" | "ext4" | "erofs" | "zram" | "unknown" | null;
   error?: string;
+  zram?: {
+    device: number;
+    uncompressed_size: number;
+    compressed_size: number;
+    mem_used: number;
+  };
   hymofs_available?: boolean;
+  dedup_saved_bytes?: number;
+  tmpfs?: {
+    cap_bytes: number;
+    estimated_bytes: number;
+    used_bytes: number;
+    overflow?: "ext4" | "trim" | "fail";
+    trimmed?: string[];
+  };
+  loop_devices?: LoopDevice[];
+  modules?: ModuleUsage[];
+}
+
+export interface ModuleUsage {
+  id: string;
+  size_bytes: number;
+  source_bytes: number;
+  source_allocated_bytes?: number;
+  source_files: number;
+  synced_bytes: number;
+  synced_allocated_bytes?: number;
+  synced_files: number;
+  in_storage: boolean;
+  skipped_identical?: number;
 }
 
+export interface LoopDevice {
+  name: string;
+  backing_file: string;
+  deleted: boolean;
+  active: boolean;
+}
+
+export type DenylistProvider =
+  | "zygisksu"
+  | "shamiko"
+  | "nohello"
+  | "ksunative"
+  | "none";
+
 export interface SystemInfo {
   kernel: string;
   selinux: string;
   mountBase: string;
   activeMounts: string[];
   zygisksuEnforce?: string;
+  denylistProvider?: DenylistProvider;
+  pendingChanges?: number;
+  safeMode?: boolean;
   supported_overlay_modes?: OverlayMode[];
 }
 
@@ -81,13 +222,141 @@ export interface ModeStats {
 }
 
 export interface ConflictEntry {
-  partition: string;
-  relative_path: string;
-  contending_modules: string[];
+  path: string;
+  contenders: string[];
+  selected: string;
+  is_forced: boolean;
+  unmet_rule?: string;
+  identical?: boolean;
+  severity?: "Benign" | "Notice" | "Severe";
+}
+
+export interface WinnowRule {
+  path: string;
+  module: string;
+  contenders: string[];
+  stale: boolean;
+}
+
+export interface WinnowSuggestion {
+  path: string;
+  module: string;
+  contenders: string[];
+  basis: "version_code" | "package_name" | "current_order";
+  rationale: string;
 }
 
 export interface DiagnosticIssue {
   level: "Info" | "Warning" | "Critical";
   context: string;
   message: string;
+  /** Present on "Predicted Mounts" entries. */
+  prediction?: MountPrediction;
+  /** Present on "Verity" entries. */
+  verity?: VerityCheck;
+}
+
+export interface VerityCheck {
+  state: "enforcing" | "not_enforcing" | "unknown";
+  overlaid: boolean;
+  evidence: {
+    partition: string;
+    mount_point?: string | null;
+    device?: string | null;
+    fs_type?: string | null;
+    dm_name?: string | null;
+    dm_uuid?: string | null;
+    properties: Record<string, string>;
+  };
+}
+
+export interface MountPrediction {
+  partition: string;
+  target: string;
+  modules: string[];
+  outcome: "overlay" | "fallback-magic" | "skip";
+  reasons: string[];
+}
+
+export interface SnapshotInfo {
+  id: string;
+  label: string;
+  timestamp: number;
+  size: number;
+  module_count: number;
+}
+
+/** `plan.show`: the mount plan as data. */
+export interface PlanExport {
+  schema_version: 1;
+  module_order: string[];
+  partitions: Record<
+    string,
+    { target: string; layers: { module: string; source: string }[] }[]
+  >;
+  magic: Record<string, Record<string, number>>;
+  hymo?: { module: string; partition: string; source: string; target: string }[];
+  bind?: Record<string, string[]>;
+  modes: Record<string, string[]>;
+}
+
+/** `plan.diff`: the current plan against a saved one. */
+export interface PlanDiff {
+  added_layers: { target: string; module: string }[];
+  removed_layers: { target: string; module: string }[];
+  reordered: { target: string; before: string[]; after: string[] }[];
+  mode_changes: { module: string; before: string[]; after: string[] }[];
+}
+
+/** `run/last_summary.json`, served by the `summary.get` op. */
+export interface BootSummary {
+  schema_version: number;
+  timestamp: number;
+  result: "unknown" | "mounted" | "safe_mode" | "cancelled" | "failed";
+  headline: string;
+  counts: {
+    mounted: number;
+    overlay: number;
+    magic: number;
+    hymo: number;
+    bind: number;
+    deferred: number;
+    fallbacks: number;
+    conflicts_resolved: number;
+    warnings: number;
+  };
+  storage: {
+    mode: string;
+    used_bytes: number;
+    total_bytes: number;
+    usage_percent: number;
+  };
+  degraded: boolean;
+  degraded_reason?: string;
+  /** At most five, most severe first. */
+  warnings: string[];
+  phases: { stage: string; ms: number }[];
+  failure?: { code: number; phase: string; message: string };
+}
+
+/** Request envelope for `meta-hybrid api`. */
+export interface ApiRequest {
+  v: 1;
+  op: string;
+  params?: Record<string, unknown>;
+}
+
+export interface ApiResponse<T = unknown> {
+  v: number;
+  ok: boolean;
+  data?: T;
+  error?: {
+    code:
+      | "invalid_request"
+      | "unsupported_version"
+      | "unknown_op"
+      | "invalid_params"
+      | "failed";
+    message: string;
+  };
 }
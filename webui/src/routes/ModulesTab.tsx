@@ -106,13 +106,13 @@ export default function ModulesTab() {
   function getModeLabel(mod: Module) {
     const m = store.L.modules?.modes;
     if (!mod.is_mounted) return m?.none ?? "Unmounted";
-    if (mod.mode === "magic") return m?.magic ?? "Magic";
+    if ((mod.effective_mode ?? mod.mode) === "magic") return m?.magic ?? "Magic";
     return m?.auto ?? "Overlay";
   }
 
   function getModeClass(mod: Module) {
     if (!mod.is_mounted) return "mode-ignore";
-    if (mod.mode === "magic") return "mode-magic";
+    if ((mod.effective_mode ?? mod.mode) === "magic") return "mode-magic";
     return "mode-auto";
   }
 
@@ -231,7 +231,15 @@ export default function ModulesTab() {
                           <span class="version-badge">{mod.version}</span>
                         </div>
                       </div>
-                      <div class={`mode-indicator ${getModeClass(mod)}`}>
+                      <div
+                        class={`mode-indicator ${getModeClass(mod)}`}
+                        title={mod.fallback_reason}
+                      >
+                        <Show when={mod.fallback_reason}>
+                          <svg class="fallback-icon" viewBox="0 0 24 24">
+                            <path d={ICONS.warning} />
+                          </svg>
+                        </Show>
                         {getModeLabel(mod)}
                       </div>
                     </div>
@@ -7,7 +7,7 @@ use std::{
     process::{Command, Stdio},
 };
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use clap::{Parser, Subcommand, ValueEnum};
 use fs_extra::{
     dir::{self},
@@ -167,6 +167,9 @@ fn build_full(
     let module_src = Path::new("module");
     let options = dir::CopyOptions::new().overwrite(true).content_only(true);
     dir::copy(module_src, &stage_dir, &options)?;
+    if !stage_dir.join("tools/mkfs.erofs").exists() {
+        bail!("module/tools/mkfs.erofs is missing; EROFS storage needs the bundled binary");
+    }
     let gitignore = stage_dir.join(".gitignore");
     if gitignore.exists() {
         fs::remove_file(gitignore)?;